@@ -0,0 +1,32 @@
+//! Runs one prompt against a scripted client and prints each event the agent
+//! emits as a line of JSON, the way a frontend would forward them.
+//!
+//! cargo run --example events
+
+use sentinel::llm::mock::MockLlmClient;
+use sentinel::Agent;
+use serde_json::json;
+use tokio::sync::broadcast;
+
+#[tokio::main]
+async fn main() {
+    let client = MockLlmClient::new()
+        .with_tools(&["bash"])
+        .with_tool_call("bash", json!({ "command": "echo hello" }))
+        .with_reply("The command printed hello");
+    let mut agent = Agent::with_client(Box::new(client), "mock");
+
+    let (mut sender, mut receiver) = broadcast::channel(64);
+    let run = async move {
+        agent
+            .handle_input("say hello from the shell", &mut sender)
+            .await;
+        // Dropping the sender ends the stream
+    };
+    let print = async {
+        while let Ok(event) = receiver.recv().await {
+            println!("{}", serde_json::to_string(&event).unwrap());
+        }
+    };
+    tokio::join!(run, print);
+}
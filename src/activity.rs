@@ -0,0 +1,326 @@
+//! Showing that the model is still at work.
+//!
+//! An [`Activity`] records when a generation started and what it is doing,
+//! such as running a tool. The TUI shows it in the title of the input box and
+//! the CLI as a [`Spinner`] on stderr. Both count from the instant the reply's
+//! duration is measured from, so the time shown matches the one recorded.
+
+use std::future::Future;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::llm::ollama::LlmClient;
+
+/// The frames of the spinner, one per tick
+pub const FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// How often the spinner moves on a frame
+pub const TICK: Duration = Duration::from_millis(100);
+
+/// Moves to the start of the line and clears it
+pub const ERASE: &str = "\r\x1b[K";
+
+/// The phase of a generation waiting for the server to load the model
+pub const LOADING_MODEL: &str = "loading model";
+
+/// How often to ask the server whether the model finished loading
+const LOAD_POLL: Duration = Duration::from_millis(500);
+
+/// The status of a generation running for `elapsed`, e.g. "⠹ running bash… 12s"
+pub fn status_line(elapsed: Duration, phase: Option<&str>) -> String {
+    let frame = (elapsed.as_millis() / TICK.as_millis()) as usize % FRAMES.len();
+    format!(
+        "{} {}… {}s",
+        FRAMES[frame],
+        phase.unwrap_or("generating"),
+        elapsed.as_secs()
+    )
+}
+
+/// Whether the CLI shows a spinner: only to a person at a terminal, and
+/// never around machine-readable output or with color turned off
+pub fn spinner_enabled(stderr_is_tty: bool, color: bool, json: bool) -> bool {
+    stderr_is_tty && color && !json
+}
+
+#[derive(Debug, Default)]
+struct ActivityState {
+    started: Option<Instant>,
+    phase: Option<String>,
+}
+
+/// What a generation is doing, shared between whoever runs it and whoever shows it
+#[derive(Debug, Default)]
+pub struct Activity {
+    state: Mutex<ActivityState>,
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A generation started at `started`
+    pub fn start(&self, started: Instant) {
+        *self.state.lock().unwrap() = ActivityState {
+            started: Some(started),
+            phase: None,
+        };
+    }
+
+    /// The generation is over
+    pub fn finish(&self) {
+        *self.state.lock().unwrap() = ActivityState::default();
+    }
+
+    /// Say what the generation is doing, e.g. "running bash", or None for
+    /// generating
+    pub fn set_phase(&self, phase: Option<String>) {
+        let mut state = self.state.lock().unwrap();
+        if state.started.is_some() {
+            state.phase = phase;
+        }
+    }
+
+    /// Go back to generating if the generation is still in `phase`
+    pub fn end_phase(&self, phase: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state.phase.as_deref() == Some(phase) {
+            state.phase = None;
+        }
+    }
+
+    /// The status line of the generation under way, if there is one
+    pub fn status(&self) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        let started = state.started?;
+        Some(status_line(started.elapsed(), state.phase.as_deref()))
+    }
+}
+
+/// Run `generation`, saying "loading model" in `activity` for as long as
+/// `client` reports the model isn't loaded yet.
+///
+/// Replies aren't streamed, so whether the model is loaded is the only sign
+/// of generation having begun; clients that can't tell show "generating".
+pub async fn while_loading<T>(
+    activity: &Activity,
+    client: &dyn LlmClient,
+    generation: impl Future<Output = T>,
+) -> T {
+    if client.model_loaded().await != Some(false) {
+        return generation.await;
+    }
+    activity.set_phase(Some(LOADING_MODEL.to_string()));
+    let loaded = async {
+        loop {
+            tokio::time::sleep(LOAD_POLL).await;
+            if client.model_loaded().await != Some(false) {
+                break;
+            }
+        }
+    };
+    tokio::pin!(generation);
+    tokio::select! {
+        output = &mut generation => {
+            activity.end_phase(LOADING_MODEL);
+            return output;
+        }
+        () = loaded => activity.end_phase(LOADING_MODEL),
+    }
+    generation.await
+}
+
+struct SpinnerOutput {
+    writer: Box<dyn Write + Send>,
+    // Whether a frame is on the line, and how many holds keep it off
+    shown: bool,
+    held: usize,
+}
+
+/// Draws the [`Activity`] on one line of a terminal, redrawing it in place.
+///
+/// Anything printed while a generation is under way goes through
+/// [`hold`](Spinner::hold), which erases the spinner first and keeps it off
+/// until the printing is done.
+pub struct Spinner {
+    activity: Arc<Activity>,
+    output: Mutex<SpinnerOutput>,
+}
+
+impl Spinner {
+    pub fn new(activity: Arc<Activity>, writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            activity,
+            output: Mutex::new(SpinnerOutput {
+                writer,
+                shown: false,
+                held: 0,
+            }),
+        }
+    }
+
+    /// The activity this spinner shows
+    pub fn activity(&self) -> &Arc<Activity> {
+        &self.activity
+    }
+
+    /// Draw the current frame over the last one, or erase the last one if
+    /// the generation is over
+    pub fn tick(&self) {
+        let mut output = self.output.lock().unwrap();
+        if output.held > 0 {
+            return;
+        }
+        match self.activity.status() {
+            Some(status) => {
+                let _ = write!(output.writer, "{}{}", ERASE, status);
+                let _ = output.writer.flush();
+                output.shown = true;
+            }
+            None => erase(&mut output),
+        }
+    }
+
+    /// Erase the spinner and keep it off while the returned guard lives
+    pub fn hold(&self) -> SpinnerHold<'_> {
+        let mut output = self.output.lock().unwrap();
+        erase(&mut output);
+        output.held += 1;
+        SpinnerHold { spinner: self }
+    }
+
+    /// Tick `spinner` in the background for as long as it is alive
+    pub fn spawn(spinner: &Arc<Self>) {
+        let spinner = Arc::downgrade(spinner);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(TICK).await;
+                match spinner.upgrade() {
+                    Some(spinner) => spinner.tick(),
+                    None => break,
+                }
+            }
+        });
+    }
+}
+
+// Clear the line of a frame drawn before
+fn erase(output: &mut SpinnerOutput) {
+    if output.shown {
+        let _ = write!(output.writer, "{}", ERASE);
+        let _ = output.writer.flush();
+        output.shown = false;
+    }
+}
+
+/// Keeps a [`Spinner`] off the screen until dropped
+pub struct SpinnerHold<'a> {
+    spinner: &'a Spinner,
+}
+
+impl Drop for SpinnerHold<'_> {
+    fn drop(&mut self) {
+        self.spinner.output.lock().unwrap().held -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::mock::MockLlmClient;
+
+    // A writer whose output a test can read back
+    #[derive(Clone, Default)]
+    struct Recorded(Arc<Mutex<Vec<u8>>>);
+
+    impl Recorded {
+        fn take(&self) -> String {
+            String::from_utf8(std::mem::take(&mut *self.0.lock().unwrap())).unwrap()
+        }
+    }
+
+    impl Write for Recorded {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_status_line() {
+        assert_eq!(status_line(Duration::ZERO, None), "⠋ generating… 0s");
+        assert_eq!(
+            status_line(Duration::from_millis(12_250), Some("running bash")),
+            "⠹ running bash… 12s"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_loading_model_is_shown_until_the_model_is_loaded() {
+        let activity = Activity::new();
+        activity.start(Instant::now());
+        let cold = MockLlmClient::new().with_model_loaded(false);
+        let phase = while_loading(&activity, &cold, async { activity.status().unwrap() }).await;
+        assert_eq!(phase, "⠋ loading model… 0s");
+        assert_eq!(activity.status().unwrap(), "⠋ generating… 0s");
+
+        let warm = MockLlmClient::new().with_model_loaded(true);
+        let phase = while_loading(&activity, &warm, async { activity.status().unwrap() }).await;
+        assert_eq!(phase, "⠋ generating… 0s");
+
+        // A tool that started running is left alone
+        activity.set_phase(Some("running bash".to_string()));
+        activity.end_phase(LOADING_MODEL);
+        assert_eq!(activity.status().unwrap(), "⠋ running bash… 0s");
+    }
+
+    #[test]
+    fn test_spinner_only_shows_at_a_colored_terminal() {
+        assert!(spinner_enabled(true, true, false));
+        assert!(!spinner_enabled(false, true, false));
+        assert!(!spinner_enabled(true, false, false));
+        assert!(!spinner_enabled(true, true, true));
+    }
+
+    #[test]
+    fn test_spinner_is_erased_before_printing() {
+        let recorded = Recorded::default();
+        let activity = Arc::new(Activity::new());
+        let spinner = Spinner::new(Arc::clone(&activity), Box::new(recorded.clone()));
+
+        // Nothing is drawn until a generation starts
+        spinner.tick();
+        assert_eq!(recorded.take(), "");
+
+        activity.start(Instant::now());
+        spinner.tick();
+        assert_eq!(recorded.take(), "\r\x1b[K⠋ generating… 0s");
+        activity.set_phase(Some("running bash".to_string()));
+        spinner.tick();
+        assert_eq!(recorded.take(), "\r\x1b[K⠋ running bash… 0s");
+
+        // Holding erases the line, and ticks leave it alone until released
+        {
+            let _hold = spinner.hold();
+            assert_eq!(recorded.take(), ERASE);
+            spinner.tick();
+            assert_eq!(recorded.take(), "");
+        }
+        spinner.tick();
+        assert!(recorded.take().starts_with(ERASE));
+
+        // The last frame is erased once the generation is over, and only once
+        activity.finish();
+        spinner.tick();
+        assert_eq!(recorded.take(), ERASE);
+        spinner.tick();
+        let _hold = spinner.hold();
+        assert_eq!(recorded.take(), "");
+    }
+}
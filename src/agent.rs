@@ -0,0 +1,1866 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use crate::activity::{self, Activity};
+use crate::apply::{self, Plan};
+use crate::attachments::{self, Attachment};
+use crate::candidates::{self, Candidate};
+use crate::commands::{self, CommandError, Parsed, COMMANDS};
+use crate::compare::{self, Comparison};
+use crate::config::Config;
+use crate::context::{self, ContextWindows, Window};
+use crate::failure::Failure;
+use crate::images::{self, ImageAttachment};
+use crate::interrupt::Interrupter;
+use crate::last_code::{self, ScratchFiles};
+use crate::llm::limits::ResponseLimits;
+use crate::llm::ollama::{LlmClient, OllamaClient, Tool};
+use crate::llm::reasoning;
+use crate::llm::timings::ServerTimings;
+use crate::llm::tool_support::{self, ToolSupport};
+use crate::memory::{self, Memory, MemoryError};
+use crate::notify::Notifier;
+use crate::prime::{self, Primer};
+use crate::privacy;
+use crate::session::{self, BranchSummary, Sessions};
+use crate::stats::{PriceTable, SessionStats, ToolTiming};
+use crate::status::PromptState;
+use crate::suggest;
+use crate::templates::{self, Template};
+use crate::tool_loop;
+use crate::tools::bash::{Bash, BashParams};
+use crate::tools::changes::SessionDiff;
+use crate::tools::journal::Journal;
+use crate::tools::memory::MemoryTool;
+use crate::tools::policy::ExecutionPolicy;
+use crate::tools::progress::{Progress, ToolEvent};
+use crate::tools::registry::{self, ToolSet};
+use crate::tools::result::ToolError;
+use crate::tools::review::Reviewer;
+use crate::tools::schema;
+use crate::{Message, Role};
+
+pub use crate::commands::CommandHelp;
+
+/// Something that happened while the agent handled input.
+///
+/// Frontends decide how to present events; the agent never prints. Events
+/// serialize as `{"type": "tool_started", "data": {...}}`, so they can be
+/// forwarded as they are, e.g. over a websocket.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum AgentEvent {
+    /// A message the user sent to the model, as they typed it
+    UserMessage(String),
+    /// Whether the agent is waiting for input or generating a reply
+    StateChanged(AgentState),
+    /// A message is on its way to the model, which is offered tools or not
+    Sending { with_tools: bool },
+    /// A tool the model called is about to run
+    ToolStarted {
+        name: String,
+        args: serde_json::Value,
+    },
+    /// What a tool the model called returned, and how long it ran
+    ToolFinished {
+        name: String,
+        output: String,
+        duration: Duration,
+    },
+    /// A piece of the reply, in order; clients that don't stream give the
+    /// whole reply as one piece
+    AssistantDelta(String),
+    /// The reply as it was added to the conversation, after the
+    /// [`AgentEvent::Response`] or [`AgentEvent::Interrupted`] shown for it
+    AssistantComplete(Message),
+    /// General information, e.g. "Conversation cleared"
+    Info(String),
+    /// Something went wrong handling the input
+    Error(String),
+    /// Something the user should know before going on, e.g. that the model
+    /// answers without tools
+    Warning(String),
+    /// The commands the agent understands
+    Help(Vec<CommandHelp>),
+    /// How to use one command, in reply to /help <command>
+    Usage(CommandHelp),
+    /// The tools available to the model
+    Tools(Vec<String>),
+    /// The tools offered to the model, in reply to /schema
+    Schemas(Vec<Tool>),
+    /// One tool as the model is given it, in reply to /schema <tool>
+    Schema(Tool),
+    /// The tools used while producing the next response
+    ToolsUsed(Vec<String>),
+    /// How long each tool call made for the next response took, in order
+    ToolTimes(Vec<ToolTiming>),
+    /// What a reasoning model thought before the next response; kept out of
+    /// the conversation, so frontends only show it on request
+    Reasoning(String),
+    /// The model's reply to a message
+    Response {
+        text: String,
+        /// Tokens of the prompt, without the tool output in it
+        input_tokens: usize,
+        /// Tokens of tool output sent back to the model on the way to the reply
+        tool_output_tokens: usize,
+        output_tokens: usize,
+        /// The model that produced the reply
+        model: String,
+        /// Who served `model`, e.g. "ollama"
+        provider: String,
+        /// Whether the reply was cut off by the `max_tokens` limit
+        truncated: bool,
+        /// How long the server spent loading the model, reading the prompt
+        /// and generating, if it said
+        server_timings: Option<ServerTimings>,
+    },
+    /// What the model had said when the user cut its reply short, ending
+    /// in "(interrupted)"
+    Interrupted(String),
+    /// Cumulative usage, in reply to /stats
+    Stats(SessionStats),
+    /// Messages matching a /search
+    SearchResults(Vec<SearchHit>),
+    /// Files staged for the next message
+    Staged(Vec<Attachment>),
+    /// The messages of the active branch, in reply to /history
+    History(Vec<HistoryEntry>),
+    /// Every branch of the session, in reply to /sessions
+    Branches(Vec<BranchSummary>),
+    /// Candidate replies generated by /best, waiting for /pick
+    Candidates(Vec<Candidate>),
+    /// Replies of other models side by side, from /compare, waiting for /promote
+    Comparison(Vec<Comparison>),
+    /// The prompt templates available to /use, in reply to /templates
+    Templates(Vec<Template>),
+    /// What the tools changed in files this session, in reply to /diff
+    Diff(SessionDiff),
+}
+
+/// What the agent is doing, for frontends that show it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentState {
+    /// Waiting for input
+    Idle,
+    /// Waiting for the model's reply, and running the tools it calls
+    Generating,
+}
+
+/// A message matching a search, with its index in the conversation
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[non_exhaustive]
+pub struct SearchHit {
+    pub index: usize,
+    pub role: Role,
+    pub snippet: String,
+}
+
+/// A message in a /history listing
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[non_exhaustive]
+pub struct HistoryEntry {
+    pub index: usize,
+    pub role: Role,
+    pub preview: String,
+    /// Whether the message is kept when the conversation is trimmed
+    pub pinned: bool,
+}
+
+impl From<ToolEvent> for AgentEvent {
+    fn from(event: ToolEvent) -> Self {
+        match event {
+            ToolEvent::Started { name, args } => AgentEvent::ToolStarted { name, args },
+            ToolEvent::Finished {
+                name,
+                output,
+                duration,
+            } => AgentEvent::ToolFinished {
+                name,
+                output,
+                duration,
+            },
+        }
+    }
+}
+
+/// Receives the events emitted by an [`Agent`]
+pub trait AgentOutput: Send {
+    fn emit(&mut self, event: AgentEvent);
+}
+
+impl AgentOutput for Vec<AgentEvent> {
+    fn emit(&mut self, event: AgentEvent) {
+        self.push(event);
+    }
+}
+
+impl AgentOutput for UnboundedSender<AgentEvent> {
+    fn emit(&mut self, event: AgentEvent) {
+        // A dropped receiver just means nobody is listening anymore
+        let _ = self.send(event);
+    }
+}
+
+impl AgentOutput for broadcast::Sender<AgentEvent> {
+    fn emit(&mut self, event: AgentEvent) {
+        // Sending fails only while there are no subscribers
+        let _ = self.send(event);
+    }
+}
+
+/// Whether the frontend should keep reading input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Exit,
+}
+
+// Candidates waiting for /pick, and how picking one changes the conversation
+struct PendingCandidates {
+    // The new user message given to /best, if any
+    prompt: Option<Message>,
+    // Whether the candidates regenerate the last reply
+    replaces_reply: bool,
+    candidates: Vec<Candidate>,
+}
+
+/// Manages a conversation with an LLM
+pub struct Agent {
+    client: Box<dyn LlmClient>,
+    model: String,
+    sessions: Sessions<Message>,
+    staged_attachments: Vec<Attachment>,
+    staged_images: Vec<ImageAttachment>,
+    pending: Option<PendingCandidates>,
+    // The replies of the last /compare, until one is promoted or a message is sent
+    comparison: Vec<Comparison>,
+    // Chosen with /use, waiting for the frontend to supply its variables
+    pending_template: Option<Template>,
+    primer: Option<Primer>,
+    // From the config, sent at the start of every conversation
+    system_prompt: Option<String>,
+    // Response length and stop sequences, changed with /set
+    limits: ResponseLimits,
+    // Facts remembered across sessions, given to the model at the start of a conversation
+    memory: Option<Arc<Memory>>,
+    // Tools run by the agent for clients that hand tool calls back
+    tools: ToolSet,
+    // Which models turned out to call tools, and how much context they take
+    tool_support: ToolSupport,
+    context: ContextWindows,
+    policy: Arc<ExecutionPolicy>,
+    stats: SessionStats,
+    prices: PriceTable,
+    notifier: Notifier,
+    interrupter: Interrupter,
+    activity: Arc<Activity>,
+    // Where /run-last wrote the code blocks of the last reply
+    scratch: ScratchFiles,
+    // The code blocks /apply listed, waiting for the user to pick some
+    apply_plan: Option<Plan>,
+}
+
+impl Agent {
+    /// Create an agent talking to Ollama with the specified model, set up by
+    /// the global config and the project's `.sentinel.toml`. A config that
+    /// cannot be loaded is ignored; use `with_config` to handle its errors.
+    pub fn new(model: &str) -> Self {
+        let config = Config::load().unwrap_or_default();
+        Self::with_client(Box::new(OllamaClient::new().with_model(model)), model)
+            .with_config(&config)
+    }
+
+    /// Create an agent on top of any LLM client
+    pub fn with_client(client: Box<dyn LlmClient>, model: &str) -> Self {
+        let policy = ExecutionPolicy::shared(client.dry_run());
+        client.set_journal(policy.journal());
+        client.set_remote_gate(policy.remote_gate());
+        let mut tools = ToolSet::with_defaults(Arc::clone(&policy));
+        tools.set_tape(client.tape());
+        Self {
+            tools,
+            tool_support: ToolSupport::new(),
+            context: ContextWindows::new(),
+            policy,
+            client,
+            model: model.to_string(),
+            sessions: Sessions::new(Vec::new()),
+            staged_attachments: Vec::new(),
+            staged_images: Vec::new(),
+            pending: None,
+            comparison: Vec::new(),
+            pending_template: None,
+            primer: None,
+            system_prompt: None,
+            limits: ResponseLimits::default(),
+            memory: None,
+            stats: SessionStats::new(),
+            prices: PriceTable::from_env(),
+            notifier: Notifier::default(),
+            interrupter: Interrupter::new(),
+            activity: Arc::new(Activity::new()),
+            scratch: ScratchFiles::new(),
+            apply_plan: None,
+        }
+    }
+
+    /// Apply a config: its system prompt, response limits, the tools it
+    /// enables, its sandbox root and protected paths
+    pub fn with_config(mut self, config: &Config) -> Self {
+        for tool in &config.tools.custom {
+            self.tools.register_custom(tool);
+        }
+        self.tools.retain(|name| config.tools.allows(name));
+        self.tools
+            .set_max_malformed(config.tools.max_malformed_calls());
+        self.tools.set_auto_retry(config.tools.bash.auto_retry);
+        self.tools.set_summarize(config.tools.summarize.clone());
+        self.policy.configure(config);
+        self.client.apply_config(config);
+        self.tool_support.set_forced(config.force_tools);
+        self.context.configure(&config.model);
+        self.limits = ResponseLimits::from_config(&config.model);
+        self.client.set_limits(self.limits.clone());
+
+        match &config.system_prompt {
+            Some(prompt) => self.with_system_prompt(prompt),
+            None => self,
+        }
+    }
+
+    /// Start conversations with `prompt`, in place of the system prompt given so far
+    pub fn with_system_prompt(mut self, prompt: &str) -> Self {
+        let old = self.system_prompt.replace(prompt.to_string());
+        let messages = self.sessions.messages_mut();
+        let position = messages
+            .iter()
+            .position(|m| m.role == Role::System && Some(&m.content) == old.as_ref());
+        match position {
+            Some(position) => messages[position].content = prompt.to_string(),
+            None => messages.insert(0, Message::system(prompt)),
+        }
+        self
+    }
+
+    /// Continue a conversation, e.g. the one the TUI saved last, in place of
+    /// the system prompt given so far. Remembered facts are brought up to date
+    pub fn with_messages(self, messages: Vec<Message>) -> Self {
+        self.with_sessions(Sessions::new(messages))
+    }
+
+    /// Continue a conversation and the branches forked from it, in the active one
+    pub fn with_sessions(mut self, sessions: Sessions<Message>) -> Self {
+        self.sessions = sessions;
+        self.refresh_memory();
+        self
+    }
+
+    /// Remember facts in `memory` across sessions: the model gets the memory
+    /// tool and the facts at the start of each conversation
+    pub fn with_memory(mut self, memory: Arc<Memory>) -> Self {
+        self.tools.register(MemoryTool::new(Arc::clone(&memory)));
+        self.client.set_memory(Arc::clone(&memory));
+        self.memory = Some(memory);
+        self.refresh_memory();
+        self
+    }
+
+    /// Run these tools when the client hands back tool calls, instead of Sentinel's own
+    pub fn with_tools(mut self, mut tools: ToolSet) -> Self {
+        tools.set_tape(self.client.tape());
+        self.tools = tools;
+        self
+    }
+
+    /// Use a price table for cost estimates instead of `SENTINEL_PRICES`
+    pub fn with_prices(mut self, prices: PriceTable) -> Self {
+        self.prices = prices;
+        self
+    }
+
+    /// Notify about slow responses as configured instead of not at all
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
+    /// Record in `activity` when replies start and what they are doing, for
+    /// a frontend to show while they are generated
+    pub fn with_activity(mut self, activity: Arc<Activity>) -> Self {
+        self.activity = activity;
+        self
+    }
+
+    /// Have `reviewer` approve file changes before tools make them
+    pub fn with_reviewer(self, reviewer: Arc<dyn Reviewer>) -> Self {
+        self.policy.set_reviewer(Arc::clone(&reviewer));
+        self.client.set_reviewer(reviewer);
+        self
+    }
+
+    /// Send the output of running tools to `progress` as it is printed
+    pub fn with_progress(self, progress: Arc<dyn Progress>) -> Self {
+        self.policy.set_progress(Arc::clone(&progress));
+        self.client.set_progress(progress);
+        self
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// What the tools may do, shared by the agent's tools and the client's
+    pub fn policy(&self) -> &Arc<ExecutionPolicy> {
+        &self.policy
+    }
+
+    /// When the reply being generated started and what it is doing
+    pub fn activity(&self) -> Arc<Activity> {
+        Arc::clone(&self.activity)
+    }
+
+    pub fn notifier(&self) -> &Notifier {
+        &self.notifier
+    }
+
+    /// The file changes the tools made this session, for /undo and /diff
+    pub fn journal(&self) -> Arc<Journal> {
+        self.policy.journal()
+    }
+
+    /// A handle that cuts the reply being generated short, for frontends to
+    /// call from another task when the user presses Esc or Ctrl+C
+    pub fn interrupter(&self) -> Interrupter {
+        self.interrupter.clone()
+    }
+
+    pub fn client(&self) -> &dyn LlmClient {
+        self.client.as_ref()
+    }
+
+    /// A warning to show before the conversation starts if the model is known
+    /// not to call tools
+    pub async fn tool_warning(&self) -> Option<String> {
+        let supported = self
+            .tool_support
+            .check(self.client.as_ref(), &self.model)
+            .await;
+        (!supported).then(|| tool_support::warning(&self.model))
+    }
+
+    /// Size the context window of the model before the first message, returning
+    /// a warning if the configured one is more than the model supports
+    pub async fn check_context_window(&self) -> Option<String> {
+        let (window, warning) = self.context.window(self.client.as_ref(), &self.model).await;
+        self.client.set_context_window(window.tokens);
+        warning
+    }
+
+    /// The context window of the model, once it was looked up
+    pub fn context_window(&self) -> Option<Window> {
+        self.context.known(&self.model)
+    }
+
+    /// Whether the model is known to answer without tools
+    pub fn tools_unsupported(&self) -> bool {
+        !self.tool_support.is_forced() && self.tool_support.known(&self.model) == Some(false)
+    }
+
+    /// Messages of the active branch
+    pub fn conversation(&self) -> &[Message] {
+        self.sessions.messages()
+    }
+
+    /// Put `messages` in place of the active branch, for a frontend that keeps
+    /// the conversation it shows itself, e.g. with notes between the messages
+    pub fn replace_conversation(&mut self, messages: Vec<Message>) {
+        *self.sessions.messages_mut() = messages;
+    }
+
+    pub fn sessions(&self) -> &Sessions<Message> {
+        &self.sessions
+    }
+
+    pub fn stats(&self) -> &SessionStats {
+        &self.stats
+    }
+
+    /// Carry on counting usage from `stats`, e.g. those of a continued session
+    pub fn with_stats(mut self, stats: SessionStats) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// What the REPL prompt can show: the model, and how much context the
+    /// last request took. Nothing queues, as the REPL sends each message as it is typed
+    pub fn prompt_state(&self) -> PromptState {
+        let context = self
+            .sessions
+            .messages()
+            .iter()
+            .rev()
+            .find(|message| message.role == Role::Assistant)
+            .map_or(0, |message| {
+                message.input_tokens + message.tool_output_tokens
+            });
+        PromptState {
+            model: self.model.clone(),
+            provider: self.client.provider().to_string(),
+            context,
+            window: self.context.known(&self.model).map(|window| window.tokens),
+            queue: 0,
+        }
+    }
+
+    pub fn staged_attachments(&self) -> &[Attachment] {
+        &self.staged_attachments
+    }
+
+    pub fn staged_images(&self) -> &[ImageAttachment] {
+        &self.staged_images
+    }
+
+    /// The project context given to the model, if any
+    pub fn primer(&self) -> Option<&Primer> {
+        self.primer.as_ref()
+    }
+
+    /// Put project context at the start of the conversation, replacing any earlier primer
+    pub fn set_primer(&mut self, primer: Primer) {
+        let message = primer.message();
+        let messages = self.sessions.messages_mut();
+        match messages.first_mut() {
+            Some(first)
+                if first.role == Role::System && first.content.starts_with(prime::HEADER) =>
+            {
+                *first = message;
+            }
+            _ => messages.insert(0, message),
+        }
+        self.primer = Some(primer);
+    }
+
+    /// Candidates generated by /best that have not been picked yet
+    pub fn pending_candidates(&self) -> &[Candidate] {
+        self.pending
+            .as_ref()
+            .map(|pending| pending.candidates.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Add the candidate labelled `label` to the conversation, optionally
+    /// keeping the other candidates as its alternatives, and run the tool
+    /// calls it only simulated. Returns the tools run.
+    pub async fn choose_candidate(
+        &mut self,
+        label: &str,
+        keep_alternatives: bool,
+    ) -> Result<Vec<String>> {
+        let pending = self
+            .pending
+            .as_ref()
+            .ok_or_else(|| anyhow!("No candidates to pick from; use /best <n> first"))?;
+        let index = candidates::index_of(label)
+            .filter(|index| *index < pending.candidates.len())
+            .ok_or_else(|| anyhow!("No candidate '{}'", label))?;
+
+        let pending = self
+            .pending
+            .take()
+            .expect("pending candidates were checked");
+        let mut candidates = pending.candidates;
+        let (model, provider) = self.source();
+        let chosen = candidates.remove(index);
+        let mut message = chosen.clone().into_message().with_source(model, provider);
+        if keep_alternatives {
+            message.alternatives = candidates.into_iter().map(|c| c.text).collect();
+        }
+
+        let messages = self.sessions.messages_mut();
+        if pending.replaces_reply && matches!(messages.last(), Some(m) if m.role == Role::Assistant)
+        {
+            messages.pop();
+        }
+        messages.extend(pending.prompt);
+        messages.push(message);
+
+        candidates::run_simulated(&mut self.tools, &chosen)
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "Error running the tool calls of candidate {}: {}",
+                    chosen.label,
+                    e
+                )
+            })
+    }
+
+    /// Ask the model for a single shell command that performs `task`.
+    ///
+    /// The exchange is recorded in the conversation. If the reply is not a
+    /// usable command the model is asked once more, more strictly.
+    pub async fn suggest_command(&mut self, task: &str) -> Result<String> {
+        let messages = self.sessions.messages_mut();
+        if messages.is_empty() {
+            messages.push(Message::system(suggest::SYSTEM_PROMPT));
+        }
+        messages.push(Message::user(task));
+
+        if let (_, Some(command)) = self.request_command().await? {
+            return Ok(command);
+        }
+
+        self.sessions
+            .messages_mut()
+            .push(Message::user(suggest::STRICT_PROMPT));
+        match self.request_command().await? {
+            (_, Some(command)) => Ok(command),
+            (reply, None) => Err(anyhow!(
+                "The model did not suggest a single command: {}",
+                reply.trim()
+            )),
+        }
+    }
+
+    /// Record that `command` was run and what it printed
+    pub fn record_command_output(&mut self, command: &str, output: &str) {
+        self.sessions.messages_mut().push(
+            Message::user(last_code::report(command, output))
+                .with_local_sources(vec![privacy::command_output(command)]),
+        );
+    }
+
+    // Ask before the local content of `messages` goes to a server on another
+    // machine, once for each server
+    fn check_remote(&self, messages: &[Message]) -> Result<(), ToolError> {
+        self.policy.set_remote_endpoint(self.client.endpoint());
+        self.policy.check_remote(&privacy::local_sources(messages))
+    }
+
+    /// Drop candidates generated by /best without picking one
+    pub fn discard_candidates(&mut self) {
+        self.pending = None;
+    }
+
+    /// The replies of the last /compare that have not been promoted
+    pub fn pending_comparison(&self) -> &[Comparison] {
+        &self.comparison
+    }
+
+    /// Drop the replies of the last /compare without promoting one
+    pub fn discard_comparison(&mut self) {
+        self.comparison.clear();
+    }
+
+    /// Put the reply labelled `label` of the last /compare in place of the
+    /// last reply, returning what was kept
+    pub fn promote(&mut self, label: &str) -> Result<String> {
+        if self.comparison.is_empty() {
+            return Err(anyhow!(
+                "No comparison to promote from; use /compare <model> first"
+            ));
+        }
+        let comparison = compare::find(&self.comparison, label)
+            .ok_or_else(|| anyhow!("No reply '{}' in the comparison", label))?;
+        if comparison.original {
+            self.comparison.clear();
+            return Ok("Kept the original reply".to_string());
+        }
+        let reply = comparison.reply.clone().map_err(|e| {
+            anyhow!(
+                "{} failed to reply, so there is nothing to promote: {}",
+                comparison.model,
+                e
+            )
+        })?;
+        let kept = format!("Replaced the last reply with {}'s", comparison.model);
+        let message = reply
+            .into_message()
+            .with_source(comparison.model.clone(), comparison.provider.clone());
+
+        let messages = self.sessions.messages_mut();
+        if matches!(messages.last(), Some(m) if m.role == Role::Assistant) {
+            messages.pop();
+        }
+        messages.push(message);
+        self.comparison.clear();
+        Ok(kept)
+    }
+
+    /// Take the template chosen with /use, whose variables the frontend should ask for
+    pub fn take_pending_template(&mut self) -> Option<Template> {
+        self.pending_template.take()
+    }
+
+    /// Render `template` with `values` and send the result as a message
+    pub async fn send_template(
+        &mut self,
+        template: &Template,
+        values: &BTreeMap<String, String>,
+        output: &mut dyn AgentOutput,
+    ) {
+        match template.render(values) {
+            Ok(rendered) => {
+                for warning in rendered.warnings {
+                    output.emit(AgentEvent::Info(format!("Warning: {}", warning)));
+                }
+                self.send_message(&rendered.text, output).await;
+            }
+            Err(e) => output.emit(AgentEvent::Error(e.to_string())),
+        }
+    }
+
+    /// The commands understood by [`Agent::handle_input`]
+    pub fn commands() -> &'static [CommandHelp] {
+        &COMMANDS
+    }
+
+    /// Handle one line of input: a /command or a message for the model
+    pub async fn handle_input(&mut self, input: &str, output: &mut dyn AgentOutput) -> Flow {
+        let input = input.trim();
+        if input.is_empty() {
+            return Flow::Continue;
+        }
+
+        let result = match commands::parse(input) {
+            Parsed::NotACommand => {
+                self.send_message(input, output).await;
+                return Flow::Continue;
+            }
+            Parsed::Unknown(name) => Err(commands::unknown(name)),
+            Parsed::Command { command, args } => self.process_command(command, args, output).await,
+        };
+
+        result.unwrap_or_else(|e| {
+            output.emit(AgentEvent::Error(e.to_string()));
+            if let CommandError::Usage(command) = e {
+                output.emit(AgentEvent::Usage(*command));
+            }
+            Flow::Continue
+        })
+    }
+
+    /// Send a message to the model, including any staged attachments
+    pub async fn send_message(&mut self, input: &str, output: &mut dyn AgentOutput) {
+        if self.pending.take().is_some() {
+            output.emit(AgentEvent::Info(
+                "Discarded the unpicked candidates".to_string(),
+            ));
+        }
+        if !std::mem::take(&mut self.comparison).is_empty() {
+            output.emit(AgentEvent::Info("Discarded the comparison".to_string()));
+        }
+
+        // Refuse before anything is sent, leaving the images staged
+        if !self.staged_images.is_empty() {
+            if let Err(e) = images::ensure_vision(self.client.as_ref(), &self.model).await {
+                output.emit(AgentEvent::Error(e.to_string()));
+                return;
+            }
+        }
+
+        let content = attachments::with_attachments(input, &self.staged_attachments);
+        let message =
+            Message::user(content).with_local_sources(attachments::paths(&self.staged_attachments));
+        // Local content waits for the user before going to a remote server,
+        // leaving the attachments staged
+        if let Err(e) =
+            self.check_remote(&[self.sessions.messages(), std::slice::from_ref(&message)].concat())
+        {
+            output.emit(AgentEvent::Error(e.to_string()));
+            return;
+        }
+        self.staged_attachments.clear();
+
+        self.sessions
+            .messages_mut()
+            .push(message.with_images(std::mem::take(&mut self.staged_images)));
+        output.emit(AgentEvent::UserMessage(input.to_string()));
+
+        let with_tools = self
+            .tool_support
+            .check(self.client.as_ref(), &self.model)
+            .await;
+        output.emit(AgentEvent::Sending { with_tools });
+
+        let (window, warning) = self.context.window(self.client.as_ref(), &self.model).await;
+        if let Some(warning) = warning {
+            output.emit(AgentEvent::Warning(warning));
+        }
+        self.client.set_context_window(window.tokens);
+        let fitted = context::fit(self.sessions.messages(), window.budget());
+        if fitted.trimmed > 0 {
+            output.emit(AgentEvent::Info(format!(
+                "Left the {} oldest messages out to fit the context window; pinned messages are still sent",
+                fitted.trimmed
+            )));
+        }
+
+        let started = Instant::now();
+        self.activity.start(started);
+        output.emit(AgentEvent::StateChanged(AgentState::Generating));
+        let interrupt = self.interrupter.start();
+        // Tool calls are reported as they start and end, whichever runs them
+        let (events, mut tool_events) = mpsc::unbounded_channel();
+        self.tools.policy().set_tool_events(Some(events.clone()));
+        self.client.set_tool_events(Some(events));
+        let outcome = {
+            let turn = activity::while_loading(
+                &self.activity,
+                self.client.as_ref(),
+                tool_loop::respond_checked(
+                    self.client.as_ref(),
+                    &mut self.tools,
+                    &fitted.messages,
+                    &interrupt,
+                    &self.tool_support,
+                    &self.model,
+                ),
+            );
+            tokio::pin!(turn);
+            loop {
+                tokio::select! {
+                    outcome = &mut turn => break outcome,
+                    Some(event) = tool_events.recv() => output.emit(event.into()),
+                }
+            }
+        };
+        self.tools.policy().set_tool_events(None);
+        self.client.set_tool_events(None);
+        while let Ok(event) = tool_events.try_recv() {
+            output.emit(event.into());
+        }
+        self.interrupter.finish();
+        self.activity.finish();
+        for notice in self.client.take_notices() {
+            output.emit(AgentEvent::Info(notice));
+        }
+        self.stats
+            .record_rate_limited(self.client.take_rate_limited());
+        self.stats
+            .record_recovered(self.client.take_recovered_calls() + self.tools.take_recovered());
+        self.stats
+            .record_prompt_usage(self.client.take_prompt_usage());
+        let server_timings = Some(self.client.take_server_timings()).filter(|t| !t.is_empty());
+        if let Some(server_timings) = server_timings {
+            self.stats.record_server_timings(server_timings);
+        }
+
+        let timings = outcome
+            .as_ref()
+            .map(tool_loop::TurnOutcome::timings)
+            .unwrap_or_default();
+        if let Ok(outcome) = &outcome {
+            match outcome.retry_report() {
+                Some(report) if outcome.retries_exhausted => {
+                    output.emit(AgentEvent::Warning(report))
+                }
+                Some(report) => output.emit(AgentEvent::Info(report)),
+                None => {}
+            }
+        }
+        match outcome {
+            Ok(tool_loop::TurnOutcome {
+                text,
+                input_tokens,
+                tool_output_tokens,
+                output_tokens,
+                used_tools,
+                reasoning,
+                reasoning_tokens,
+                summary_tokens,
+                interrupted,
+                tools_refused,
+                truncated,
+                ..
+            }) => {
+                if tools_refused {
+                    output.emit(AgentEvent::Warning(tool_support::warning(&self.model)));
+                }
+                let (model, provider) = self.source();
+                self.stats.record(
+                    input_tokens,
+                    output_tokens,
+                    &used_tools,
+                    started.elapsed(),
+                    self.prices.price_for(&self.model),
+                );
+                self.stats
+                    .record_tool_output(tool_output_tokens, self.prices.price_for(&self.model));
+                self.stats.record_reasoning(reasoning_tokens);
+                self.stats.record_summaries(summary_tokens);
+                self.stats.record_model(&model, input_tokens, output_tokens);
+                self.stats.record_tool_timings(&timings);
+
+                if !used_tools.is_empty() {
+                    output.emit(AgentEvent::ToolsUsed(used_tools.clone()));
+                }
+                if !timings.is_empty() {
+                    output.emit(AgentEvent::ToolTimes(timings.clone()));
+                }
+                if let Some(reasoning) = &reasoning {
+                    output.emit(AgentEvent::Reasoning(reasoning.clone()));
+                }
+
+                if !text.is_empty() {
+                    output.emit(AgentEvent::AssistantDelta(text.clone()));
+                }
+                // The partial reply stays in the conversation, so the model knows it was cut off
+                if interrupted {
+                    output.emit(AgentEvent::Interrupted(text.clone()));
+                } else {
+                    self.notifier.finished(started.elapsed(), &text);
+                    output.emit(AgentEvent::Response {
+                        text: text.clone(),
+                        input_tokens,
+                        tool_output_tokens,
+                        output_tokens,
+                        model: model.clone(),
+                        provider: provider.clone(),
+                        truncated,
+                        server_timings,
+                    });
+                }
+
+                let mut reply = Message::assistant(text, input_tokens, output_tokens, used_tools)
+                    .with_tool_output_tokens(tool_output_tokens)
+                    .with_tool_timings(timings)
+                    .with_reasoning(reasoning)
+                    .with_source(model, provider)
+                    .with_truncated(truncated)
+                    .with_latency(started.elapsed());
+                if let Some(server_timings) = server_timings {
+                    reply = reply.with_server_timings(server_timings);
+                }
+                self.sessions.messages_mut().push(reply.clone());
+                output.emit(AgentEvent::AssistantComplete(reply));
+            }
+            Err(e) => {
+                output.emit(AgentEvent::Error(Failure::from(e).to_string()));
+            }
+        }
+        output.emit(AgentEvent::StateChanged(AgentState::Idle));
+    }
+
+    // Ask for a command with the conversation so far, returning the reply and the command in it
+    async fn request_command(&mut self) -> Result<(String, Option<String>)> {
+        let started = Instant::now();
+        let (reply, input_tokens, output_tokens) = self
+            .client
+            .generate_response(self.sessions.messages())
+            .await?;
+        self.stats.record(
+            input_tokens,
+            output_tokens,
+            &[],
+            started.elapsed(),
+            self.prices.price_for(&self.model),
+        );
+        let (model, provider) = self.source();
+        self.stats.record_model(&model, input_tokens, output_tokens);
+
+        // A command quoted while thinking is not the one suggested
+        let reply = reasoning::split(&reply);
+        self.stats
+            .record_reasoning(reply.reasoning_tokens(output_tokens));
+        let command = suggest::extract_command(&reply.content);
+        self.sessions.messages_mut().push(
+            Message::assistant(
+                reply.content.clone(),
+                input_tokens,
+                output_tokens,
+                Vec::new(),
+            )
+            .with_reasoning(reply.reasoning)
+            .with_source(model, provider),
+        );
+        Ok((reply.content, command))
+    }
+
+    // The model and provider the client's last reply is attributed to
+    fn source(&self) -> (String, String) {
+        (
+            self.client
+                .model_name()
+                .unwrap_or_else(|| self.model.clone()),
+            self.client.provider().to_string(),
+        )
+    }
+
+    // Run one of the commands, with the rest of its line
+    async fn process_command(
+        &mut self,
+        command: &'static CommandHelp,
+        args: &str,
+        output: &mut dyn AgentOutput,
+    ) -> Result<Flow, CommandError> {
+        match command.name() {
+            "/exit" => {
+                commands::no_args(command, args)?;
+                output.emit(AgentEvent::Info(format!(
+                    "Session: {}",
+                    self.stats.summary()
+                )));
+                output.emit(AgentEvent::Info("Goodbye!".to_string()));
+                return Ok(Flow::Exit);
+            }
+            "/help" if args.is_empty() => output.emit(AgentEvent::Help(COMMANDS.to_vec())),
+            "/help" => match commands::find(args) {
+                Some(command) => output.emit(AgentEvent::Usage(*command)),
+                None => return Err(commands::unknown(args)),
+            },
+            "/clear" => {
+                commands::no_args(command, args)?;
+                self.clear_conversation(output);
+            }
+            "/tools" if !args.is_empty() => {
+                let (name, enabled) = commands::tool_switch(command, args)?;
+                match registry::switch_tool(self.client.as_ref(), &mut self.tools, name, enabled) {
+                    Ok(note) => output.emit(AgentEvent::Info(note)),
+                    Err(e) => output.emit(AgentEvent::Error(e)),
+                }
+            }
+            "/tools" => {
+                let mut tools = self.client.available_tools();
+                tools.extend(
+                    self.tools
+                        .custom_names()
+                        .into_iter()
+                        .map(|name| format!("{} (custom)", name)),
+                );
+                tools.extend(self.tools.failed_names());
+                output.emit(AgentEvent::Tools(tools));
+            }
+            "/schema" => {
+                let tools = registry::offered(&self.client.available_tools(), &self.tools);
+                if args.is_empty() {
+                    output.emit(AgentEvent::Schemas(tools));
+                } else {
+                    match schema::find(&tools, args) {
+                        Some(tool) => output.emit(AgentEvent::Schema(tool.clone())),
+                        None => output.emit(AgentEvent::Error(schema::not_offered(args))),
+                    }
+                }
+            }
+            "/stats" => output.emit(AgentEvent::Stats(self.stats.clone())),
+            "/search" => self.search(args, output),
+            "/attach" => self.attach(args, output),
+            "/image" => self.stage_image(args, output),
+            "/dryrun" => self.set_dry_run(args, output),
+            "/notify" => self.set_notify(args, output),
+            "/set" => self.set_limits(command, args, output)?,
+            "/history" => self.history(output),
+            "/fork" => self.fork(args, output),
+            "/pin" => self.set_pinned(command, args, true, output)?,
+            "/unpin" => self.set_pinned(command, args, false, output)?,
+            "/sessions" => self.list_sessions(args, output),
+            // Only the TUI accepts messages while a reply is generated
+            "/queue" => output.emit(AgentEvent::Info(
+                "Nothing is queued: the REPL sends each message as it is typed".to_string(),
+            )),
+            // Only the TUI draws its own colors
+            "/theme" => output.emit(AgentEvent::Info(
+                "Themes color the TUI; the REPL uses the terminal's colors".to_string(),
+            )),
+            // Only the TUI saves its sessions
+            "/title" => output.emit(AgentEvent::Info(
+                "Titles name saved sessions, and the REPL doesn't save its sessions".to_string(),
+            )),
+            "/best" => self.best(args, output).await,
+            "/pick" => self.pick(args, output).await,
+            "/compare" => self.compare(command, args, output).await?,
+            "/promote" => {
+                if args.is_empty() {
+                    return Err(CommandError::Usage(command));
+                }
+                match self.promote(args) {
+                    Ok(kept) => output.emit(AgentEvent::Info(kept)),
+                    Err(e) => output.emit(AgentEvent::Error(e.to_string())),
+                }
+            }
+            "/prime" => self.prime(output).await,
+            "/templates" => match templates::list() {
+                Ok(templates) => output.emit(AgentEvent::Templates(templates)),
+                Err(e) => output.emit(AgentEvent::Error(format!("Error listing templates: {}", e))),
+            },
+            "/use" => self.use_template(args, output),
+            "/run-last" => self.run_last(command, args, output).await?,
+            "/apply" => self.apply(command, args, output).await?,
+            "/undo" => self.undo(args, output),
+            "/diff" => {
+                commands::no_args(command, args)?;
+                output.emit(AgentEvent::Diff(SessionDiff::of_journal(&self.journal())));
+            }
+            "/protect" => self.protect(args, output),
+            "/memory" => self.memory(command, args, output)?,
+            "/unload" => match self.client.unload().await {
+                Ok(()) => output.emit(AgentEvent::Info(format!(
+                    "Unloaded {}; it loads again with the next message",
+                    self.model
+                ))),
+                Err(e) => output.emit(AgentEvent::Error(format!(
+                    "Error unloading the model: {}",
+                    e
+                ))),
+            },
+            // In the table, but handled by the frontends
+            name => return Err(commands::unknown(name)),
+        }
+
+        Ok(Flow::Continue)
+    }
+
+    // Run a command over the code blocks of the last reply, then send the
+    // model what it printed so it can fix what failed
+    async fn run_last(
+        &mut self,
+        command: &'static CommandHelp,
+        args: &str,
+        output: &mut dyn AgentOutput,
+    ) -> Result<(), CommandError> {
+        if args.is_empty() {
+            return Err(CommandError::Usage(command));
+        }
+        let reply = self
+            .sessions
+            .messages()
+            .iter()
+            .rev()
+            .find(|message| message.role == Role::Assistant)
+            .map(|message| message.content.clone());
+        let Some(reply) = reply else {
+            output.emit(AgentEvent::Error(
+                "There is no reply to run a command over yet".to_string(),
+            ));
+            return Ok(());
+        };
+        let line = match last_code::prepare(&reply, args, &mut self.scratch) {
+            Ok(line) => line,
+            Err(e) => {
+                output.emit(AgentEvent::Error(e));
+                return Ok(());
+            }
+        };
+
+        output.emit(AgentEvent::Info(format!("Running: {}", line)));
+        let mut bash = Bash::new().with_policy(Arc::clone(&self.policy));
+        let result = bash.run_to_text(BashParams::new(line.clone())).await;
+        output.emit(AgentEvent::Info(result.clone()));
+        self.send_message(&last_code::report(&line, &result), output)
+            .await;
+        Ok(())
+    }
+
+    // List the code blocks of the last reply with the files they go to, or
+    // write the ones the user picks from that list
+    async fn apply(
+        &mut self,
+        command: &'static CommandHelp,
+        args: &str,
+        output: &mut dyn AgentOutput,
+    ) -> Result<(), CommandError> {
+        let request = apply::parse(args).ok_or(CommandError::Usage(command))?;
+        let messages = self.sessions.messages();
+        let reply = messages
+            .iter()
+            .rposition(|message| message.role == Role::Assistant);
+        let question = reply
+            .and_then(|index| {
+                messages[..index]
+                    .iter()
+                    .rev()
+                    .find(|message| message.role == Role::User)
+            })
+            .map(|message| message.content.clone())
+            .unwrap_or_default();
+        let reply = reply.map(|index| messages[index].content.clone());
+
+        if request == apply::Request::Propose {
+            let Some(reply) = reply else {
+                output.emit(AgentEvent::Error(
+                    "There is no reply to apply yet".to_string(),
+                ));
+                return Ok(());
+            };
+            match Plan::propose(&reply) {
+                Ok(mut plan) => {
+                    apply::suggest(self.client.as_ref(), &question, &mut plan).await;
+                    output.emit(AgentEvent::Info(plan.describe()));
+                    self.apply_plan = Some(plan);
+                }
+                Err(e) => output.emit(AgentEvent::Error(e)),
+            }
+            return Ok(());
+        }
+        if request == apply::Request::Cancel {
+            self.apply_plan = None;
+            output.emit(AgentEvent::Info("Nothing will be applied".to_string()));
+            return Ok(());
+        }
+
+        let Some(plan) = &mut self.apply_plan else {
+            output.emit(AgentEvent::Error(apply::NOTHING_LISTED.to_string()));
+            return Ok(());
+        };
+        if !reply.is_some_and(|reply| plan.is_for(&reply)) {
+            output.emit(AgentEvent::Error(apply::REPLY_CHANGED.to_string()));
+            return Ok(());
+        }
+        match request {
+            apply::Request::Set(number, path) => match plan.set(number, &path) {
+                Ok(()) => output.emit(AgentEvent::Info(plan.describe())),
+                Err(e) => output.emit(AgentEvent::Error(e)),
+            },
+            apply::Request::Write(chosen) => {
+                match apply::write(plan, chosen.as_deref(), Arc::clone(&self.policy)).await {
+                    Ok(report) => {
+                        output.emit(AgentEvent::Info(report));
+                        self.apply_plan = None;
+                    }
+                    Err(e) => output.emit(AgentEvent::Error(e)),
+                }
+            }
+            apply::Request::Propose | apply::Request::Cancel => {}
+        }
+        Ok(())
+    }
+
+    // Generate several candidate replies to a new message, or to the last one
+    async fn best(&mut self, args: &str, output: &mut dyn AgentOutput) {
+        let (count, message) = match args.split_once(' ') {
+            Some((count, message)) => (count, message.trim()),
+            None => (args, ""),
+        };
+        let Ok(count) = count.parse::<usize>() else {
+            output.emit(AgentEvent::Error("Usage: /best <n> [message]".to_string()));
+            return;
+        };
+
+        let mut messages = self.sessions.messages().to_vec();
+        let (prompt, replaces_reply) = if message.is_empty() {
+            let replaces_reply = matches!(messages.last(), Some(m) if m.role == Role::Assistant);
+            if replaces_reply {
+                messages.pop();
+            }
+            if !matches!(messages.last(), Some(m) if m.role == Role::User) {
+                output.emit(AgentEvent::Error(
+                    "Nothing to regenerate: send a message first, or use /best <n> <message>"
+                        .to_string(),
+                ));
+                return;
+            }
+            (None, replaces_reply)
+        } else {
+            let content = attachments::with_attachments(message, &self.staged_attachments);
+            let prompt = Message::user(content)
+                .with_local_sources(attachments::paths(&self.staged_attachments));
+            messages.push(prompt.clone());
+            (Some(prompt), false)
+        };
+        if let Err(e) = self.check_remote(&messages) {
+            output.emit(AgentEvent::Error(e.to_string()));
+            return;
+        }
+        self.staged_attachments.clear();
+
+        output.emit(AgentEvent::Info(format!(
+            "Generating {} candidates...",
+            count
+        )));
+
+        let interrupt = self.interrupter.start();
+        let generated = candidates::generate_with_tools(
+            self.client.as_ref(),
+            &mut self.tools,
+            &messages,
+            count,
+            &interrupt,
+            &self.tool_support,
+            &self.model,
+        )
+        .await;
+        self.interrupter.finish();
+        match generated {
+            Ok(candidates) => {
+                // Each candidate was a request of its own
+                let (model, _) = self.source();
+                for candidate in &candidates {
+                    self.stats.record(
+                        candidate.input_tokens,
+                        candidate.output_tokens,
+                        &candidate.used_tools,
+                        candidate.latency,
+                        self.prices.price_for(&self.model),
+                    );
+                    self.stats.record_reasoning(candidate.reasoning_tokens);
+                    self.stats.record_model(
+                        &model,
+                        candidate.input_tokens,
+                        candidate.output_tokens,
+                    );
+                }
+
+                output.emit(AgentEvent::Candidates(candidates.clone()));
+                self.pending = Some(PendingCandidates {
+                    prompt,
+                    replaces_reply,
+                    candidates,
+                });
+            }
+            Err(e) => output.emit(AgentEvent::Error(format!(
+                "Error generating candidates: {}",
+                e
+            ))),
+        }
+    }
+
+    // Ask other models for the last reply, or two models afresh, and show
+    // their replies beside each other without adding them to the conversation
+    async fn compare(
+        &mut self,
+        command: &'static CommandHelp,
+        args: &str,
+        output: &mut dyn AgentOutput,
+    ) -> Result<(), CommandError> {
+        let models = compare::models(args).ok_or(CommandError::Usage(command))?;
+        let mut messages = self.sessions.messages().to_vec();
+        let last_reply = match messages.last() {
+            Some(message) if message.role == Role::Assistant => messages.pop(),
+            _ => None,
+        };
+        if !matches!(messages.last(), Some(m) if m.role == Role::User) {
+            output.emit(AgentEvent::Error(
+                "Nothing to compare: send a message first".to_string(),
+            ));
+            return Ok(());
+        }
+        if let Err(e) = self.check_remote(&messages) {
+            output.emit(AgentEvent::Error(e.to_string()));
+            return Ok(());
+        }
+
+        // One model is set beside the reply it would replace
+        let mut before = Vec::new();
+        if let (1, Some(reply)) = (models.len(), last_reply) {
+            let (model, provider) = self.source();
+            let model = if reply.model.is_empty() {
+                model
+            } else {
+                reply.model.clone()
+            };
+            let provider = if reply.provider.is_empty() {
+                provider
+            } else {
+                reply.provider.clone()
+            };
+            before.push(Comparison::original(
+                &model,
+                &provider,
+                compare::Reply {
+                    text: reply.content,
+                    input_tokens: reply.input_tokens,
+                    output_tokens: reply.output_tokens,
+                    latency: reply.latency,
+                    reasoning: reply.reasoning,
+                    reasoning_tokens: 0,
+                },
+            ));
+        }
+
+        output.emit(AgentEvent::Info(format!(
+            "Asking {}...",
+            models.join(" and ")
+        )));
+        let started = Instant::now();
+        self.activity.start(started);
+        let comparison = compare::run(self.client.as_ref(), &models, &messages, before).await;
+        self.activity.finish();
+
+        for side in &comparison {
+            if let (false, Ok(reply)) = (side.original, &side.reply) {
+                self.stats.record(
+                    reply.input_tokens,
+                    reply.output_tokens,
+                    &[],
+                    reply.latency.unwrap_or_default(),
+                    self.prices.price_for(&side.model),
+                );
+                self.stats.record_reasoning(reply.reasoning_tokens);
+                self.stats
+                    .record_model(&side.model, reply.input_tokens, reply.output_tokens);
+            }
+        }
+        output.emit(AgentEvent::Comparison(comparison.clone()));
+        self.comparison = comparison;
+        Ok(())
+    }
+
+    // Revert a file change made by the tools, or list the ones that can be reverted
+    fn undo(&mut self, args: &str, output: &mut dyn AgentOutput) {
+        let journal = self.policy.journal();
+        if args == "list" {
+            let entries = journal.entries();
+            if entries.is_empty() {
+                output.emit(AgentEvent::Info("No file changes to undo".to_string()));
+            }
+            for (index, entry) in entries.iter().enumerate() {
+                output.emit(AgentEvent::Info(format!(
+                    "{}. {}",
+                    index + 1,
+                    entry.operation.describe()
+                )));
+            }
+            return;
+        }
+
+        let number = match args {
+            "" => None,
+            args => match args.parse::<usize>() {
+                Ok(number) => Some(number),
+                Err(_) => {
+                    output.emit(AgentEvent::Error("Usage: /undo [list|n]".to_string()));
+                    return;
+                }
+            },
+        };
+        match journal.undo(number) {
+            Ok(operation) => output.emit(AgentEvent::Info(format!(
+                "Undone: {}",
+                operation.describe()
+            ))),
+            Err(e) => output.emit(AgentEvent::Error(format!("Cannot undo: {}", e))),
+        }
+    }
+
+    // Give the model a snapshot of the current project
+    async fn prime(&mut self, output: &mut dyn AgentOutput) {
+        let directory = std::env::current_dir().unwrap_or_else(|_| ".".into());
+        match prime::prime(&directory).await {
+            Ok(primer) => {
+                output.emit(AgentEvent::Info(format!(
+                    "Primed with project context (~{} tokens{})",
+                    primer.tokens,
+                    if primer.cached { ", cached" } else { "" }
+                )));
+                self.set_primer(primer);
+            }
+            Err(e) => output.emit(AgentEvent::Error(format!("Error priming context: {}", e))),
+        }
+    }
+
+    // Choose a template for the frontend to fill in
+    fn use_template(&mut self, args: &str, output: &mut dyn AgentOutput) {
+        if args.is_empty() {
+            output.emit(AgentEvent::Error("Usage: /use <template>".to_string()));
+            return;
+        }
+
+        match templates::find(args) {
+            Ok(template) => {
+                let variables = template.variables();
+                if !variables.is_empty() {
+                    output.emit(AgentEvent::Info(format!(
+                        "Template '{}' needs: {} (use @path to read a value from a file)",
+                        template.name,
+                        variables.join(", ")
+                    )));
+                }
+                self.pending_template = Some(template);
+            }
+            Err(e) => output.emit(AgentEvent::Error(e.to_string())),
+        }
+    }
+
+    // Keep one of the candidates generated by /best
+    async fn pick(&mut self, args: &str, output: &mut dyn AgentOutput) {
+        let (label, keep) = match args.split_once(' ') {
+            Some((label, "keep")) => (label, true),
+            None if !args.is_empty() => (args, false),
+            _ => {
+                output.emit(AgentEvent::Error("Usage: /pick <label> [keep]".to_string()));
+                return;
+            }
+        };
+
+        match self.choose_candidate(label, keep).await {
+            Ok(ran) if ran.is_empty() => output.emit(AgentEvent::Info(format!(
+                "Kept candidate {}",
+                label.to_uppercase()
+            ))),
+            Ok(ran) => output.emit(AgentEvent::Info(format!(
+                "Kept candidate {} and ran its tool calls: {}",
+                label.to_uppercase(),
+                ran.join(", ")
+            ))),
+            Err(e) => output.emit(AgentEvent::Error(e.to_string())),
+        }
+    }
+
+    // Turn completion notifications on or off, or show whether they are on
+    fn set_notify(&mut self, args: &str, output: &mut dyn AgentOutput) {
+        match args.to_lowercase().as_str() {
+            "on" => self.notifier.set_enabled(true),
+            "off" => self.notifier.set_enabled(false),
+            "" => {}
+            _ => {
+                output.emit(AgentEvent::Error("Usage: /notify on|off".to_string()));
+                return;
+            }
+        }
+
+        let status = if self.notifier.enabled() {
+            "Notifications are on for slow responses"
+        } else {
+            "Notifications are off"
+        };
+        output.emit(AgentEvent::Info(status.to_string()));
+    }
+
+    // Change a response limit, or show them
+    fn set_limits(
+        &mut self,
+        command: &'static CommandHelp,
+        args: &str,
+        output: &mut dyn AgentOutput,
+    ) -> Result<(), CommandError> {
+        let words = commands::split_args(args)?;
+        if !words.is_empty() {
+            if !self.limits.set(&words) {
+                return Err(CommandError::Usage(command));
+            }
+            self.client.set_limits(self.limits.clone());
+        }
+        output.emit(AgentEvent::Info(format!(
+            "Response limits: {}",
+            self.limits.describe()
+        )));
+        Ok(())
+    }
+
+    // Turn dry-run mode on or off, or show whether it is on
+    fn set_dry_run(&mut self, args: &str, output: &mut dyn AgentOutput) {
+        match args.to_lowercase().as_str() {
+            "on" | "off" => {
+                let dry_run = args.eq_ignore_ascii_case("on");
+                self.client.set_dry_run(dry_run);
+                self.policy.set_dry_run(dry_run);
+            }
+            "" => {}
+            _ => {
+                output.emit(AgentEvent::Error("Usage: /dryrun on|off".to_string()));
+                return;
+            }
+        }
+
+        let status = if self.client.dry_run() {
+            "Dry-run mode is on: mutating tool actions are previewed, not executed"
+        } else {
+            "Dry-run mode is off"
+        };
+        output.emit(AgentEvent::Info(status.to_string()));
+    }
+
+    // Find the messages containing a term
+    fn search(&self, term: &str, output: &mut dyn AgentOutput) {
+        if term.is_empty() {
+            output.emit(AgentEvent::Error("Usage: /search <term>".to_string()));
+            return;
+        }
+
+        let term = term.to_lowercase();
+        let hits: Vec<SearchHit> = self
+            .sessions
+            .messages()
+            .iter()
+            .enumerate()
+            .filter_map(|(index, message)| {
+                let content = message.content.to_lowercase();
+                let position = content.find(&term)?;
+                Some(SearchHit {
+                    index,
+                    role: message.role.clone(),
+                    snippet: snippet(&content, &message.content, position, term.len()),
+                })
+            })
+            .collect();
+
+        if hits.is_empty() {
+            output.emit(AgentEvent::Info(format!("No messages match '{}'", term)));
+        } else {
+            output.emit(AgentEvent::SearchResults(hits));
+        }
+    }
+
+    // Stage files for the next message, or show what is staged
+    fn attach(&mut self, args: &str, output: &mut dyn AgentOutput) {
+        if args == "clear" {
+            self.staged_attachments.clear();
+            output.emit(AgentEvent::Info("Cleared staged attachments".to_string()));
+            return;
+        }
+
+        if !args.is_empty() {
+            let patterns: Vec<String> = args.split_whitespace().map(String::from).collect();
+            match attachments::load(&patterns) {
+                Ok(loaded) => {
+                    for warning in loaded.warnings {
+                        output.emit(AgentEvent::Error(warning));
+                    }
+                    self.staged_attachments.extend(loaded.attachments);
+                }
+                Err(e) => {
+                    output.emit(AgentEvent::Error(e.to_string()));
+                    return;
+                }
+            }
+        }
+
+        if self.staged_attachments.is_empty() {
+            output.emit(AgentEvent::Info("No files staged".to_string()));
+        } else {
+            output.emit(AgentEvent::Staged(self.staged_attachments.clone()));
+        }
+    }
+
+    // Stage an image for the next message, or show what is staged
+    fn stage_image(&mut self, args: &str, output: &mut dyn AgentOutput) {
+        match args {
+            "clear" => {
+                self.staged_images.clear();
+                output.emit(AgentEvent::Info("Cleared staged images".to_string()));
+                return;
+            }
+            "" => {}
+            path => match ImageAttachment::load(path) {
+                Ok(image) => self.staged_images.push(image),
+                Err(e) => {
+                    output.emit(AgentEvent::Error(e.to_string()));
+                    return;
+                }
+            },
+        }
+
+        if self.staged_images.is_empty() {
+            output.emit(AgentEvent::Info("No images staged".to_string()));
+        } else {
+            let staged: Vec<String> = self
+                .staged_images
+                .iter()
+                .map(|image| format!("{} ({} KB)", image.path, image.size.div_ceil(1024)))
+                .collect();
+            output.emit(AgentEvent::Info(format!(
+                "Images staged for the next message: {}",
+                staged.join(", ")
+            )));
+        }
+    }
+
+    // Write-protect paths matching a glob for the rest of the session, or list the rules
+    fn protect(&mut self, args: &str, output: &mut dyn AgentOutput) {
+        if !args.is_empty() {
+            self.policy.protect(args);
+            self.client.protect(args);
+            output.emit(AgentEvent::Info(format!(
+                "Paths matching '{}' are now write-protected",
+                args
+            )));
+            return;
+        }
+
+        let rules = self.policy.protected_paths();
+        if rules.is_empty() {
+            output.emit(AgentEvent::Info("No paths are write-protected".to_string()));
+        } else {
+            output.emit(AgentEvent::Info(format!(
+                "Write-protected: {}",
+                rules.join(", ")
+            )));
+        }
+    }
+
+    // List the messages of the active branch
+    fn history(&self, output: &mut dyn AgentOutput) {
+        let entries = self
+            .sessions
+            .messages()
+            .iter()
+            .enumerate()
+            .map(|(index, message)| HistoryEntry {
+                index,
+                role: message.role.clone(),
+                preview: session::preview(&message.content),
+                pinned: message.pinned,
+            })
+            .collect();
+        output.emit(AgentEvent::History(entries));
+    }
+
+    // Pin or unpin a message of the active branch
+    fn set_pinned(
+        &mut self,
+        command: &'static CommandHelp,
+        args: &str,
+        pinned: bool,
+        output: &mut dyn AgentOutput,
+    ) -> Result<(), CommandError> {
+        let index = args
+            .parse::<usize>()
+            .map_err(|_| CommandError::Usage(command))?;
+        let messages = self.sessions.messages_mut();
+        let count = messages.len();
+        let Some(message) = messages.get_mut(index) else {
+            output.emit(AgentEvent::Error(format!(
+                "No message {}; /history lists the {} there are",
+                index, count
+            )));
+            return Ok(());
+        };
+
+        message.pinned = pinned;
+        output.emit(AgentEvent::Info(if pinned {
+            format!(
+                "Pinned message {}; it is kept when older messages are trimmed",
+                index
+            )
+        } else {
+            format!("Unpinned message {}", index)
+        }));
+        Ok(())
+    }
+
+    // Continue the conversation in a new branch from an earlier message
+    fn fork(&mut self, args: &str, output: &mut dyn AgentOutput) {
+        let Ok(index) = args.parse::<usize>() else {
+            output.emit(AgentEvent::Error(
+                "Usage: /fork <message index>".to_string(),
+            ));
+            return;
+        };
+
+        let parent = self.sessions.active_name().to_string();
+        self.pending = None;
+        match self.sessions.fork(index) {
+            Ok(name) => output.emit(AgentEvent::Info(format!(
+                "Forked '{}' at message {} into '{}'",
+                parent, index, name
+            ))),
+            Err(e) => output.emit(AgentEvent::Error(e.to_string())),
+        }
+    }
+
+    // List the branches of this session, or switch to one
+    fn list_sessions(&mut self, args: &str, output: &mut dyn AgentOutput) {
+        if !args.is_empty() {
+            self.pending = None;
+            // Files read in this branch were not necessarily read in the other
+            self.tools.reset();
+            match self.sessions.switch(args) {
+                Ok(()) => output.emit(AgentEvent::Info(format!("Switched to '{}'", args))),
+                Err(e) => {
+                    output.emit(AgentEvent::Error(e.to_string()));
+                    return;
+                }
+            }
+        }
+
+        output.emit(AgentEvent::Branches(self.sessions.summaries()));
+    }
+
+    // Clear conversation history
+    fn clear_conversation(&mut self, output: &mut dyn AgentOutput) {
+        self.sessions.messages_mut().clear();
+        self.pending = None;
+        self.comparison.clear();
+        self.client.reset_tools();
+        self.tools.reset();
+
+        // The system prompt and project context outlive the conversation
+        if let Some(prompt) = &self.system_prompt {
+            self.sessions
+                .messages_mut()
+                .push(Message::system(prompt.clone()));
+        }
+        if let Some(primer) = self.primer.take() {
+            self.set_primer(primer);
+        }
+        self.refresh_memory();
+        output.emit(AgentEvent::Info("Conversation cleared".to_string()));
+    }
+
+    // Show or change the remembered facts
+    fn memory(
+        &mut self,
+        command: &'static CommandHelp,
+        args: &str,
+        output: &mut dyn AgentOutput,
+    ) -> Result<(), CommandError> {
+        let Some(memory) = &self.memory else {
+            output.emit(AgentEvent::Info(
+                "Memory is off: turn it on with enabled = true in the [memory] config section"
+                    .to_string(),
+            ));
+            return Ok(());
+        };
+        let words = commands::split_args(args)?;
+        match memory::command(memory, &words).ok_or(CommandError::Usage(command))? {
+            Ok(text) => output.emit(AgentEvent::Info(text)),
+            Err(e @ MemoryError::Secret) => output.emit(AgentEvent::Warning(e.to_string())),
+            Err(e) => output.emit(AgentEvent::Error(e.to_string())),
+        }
+        Ok(())
+    }
+
+    // Give the model the remembered facts after the other system messages at
+    // the start of the conversation, replacing the ones given before
+    fn refresh_memory(&mut self) {
+        let messages = self.sessions.messages_mut();
+        messages.retain(|m| !(m.role == Role::System && m.content.starts_with(memory::HEADER)));
+        let Some(prompt) = self.memory.as_ref().and_then(|memory| memory.prompt()) else {
+            return;
+        };
+        let position = messages
+            .iter()
+            .position(|m| m.role != Role::System)
+            .unwrap_or(messages.len());
+        messages.insert(position, Message::system(prompt));
+    }
+}
+
+// A single-line excerpt of `original` around a match found at `position` in its lowercased form
+fn snippet(lowered: &str, original: &str, position: usize, length: usize) -> String {
+    const CONTEXT: usize = 40;
+
+    // Lowercasing can change byte lengths, so fall back to the lowercased text if offsets differ
+    let text = if lowered.len() == original.len() {
+        original
+    } else {
+        lowered
+    };
+
+    let mut start = position.saturating_sub(CONTEXT);
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (position + length + CONTEXT).min(text.len());
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+
+    let mut excerpt = text[start..end].replace('\n', " ");
+    if start > 0 {
+        excerpt.insert_str(0, "...");
+    }
+    if end < text.len() {
+        excerpt.push_str("...");
+    }
+    excerpt
+}
@@ -0,0 +1,598 @@
+//! Writing the code blocks of the last reply to the files they are meant
+//! for, for `/apply`.
+//!
+//! Each fenced block gets a target path from a `file:` comment on its first
+//! line (`// file: src/main.rs`, `# file: setup.py`), which is left out of
+//! what is written, or else from a path in its fence's info string
+//! (```` ```rust src/main.rs ````, ```` ```rust title="src/main.rs" ````).
+//! The model is asked in a short request of its own about blocks left
+//! without one, and the user gives paths to the rest.
+//!
+//! `/apply` only proposes the mapping. Nothing is written until the user
+//! picks the blocks to apply, and then through the file tool, so the
+//! sandbox, protected paths, change review, dry-run mode and /undo all
+//! apply to the writes.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::last_code::{self, CodeBlock};
+use crate::llm::ollama::LlmClient;
+use crate::llm::reasoning;
+use crate::tools::file::File;
+use crate::tools::policy::ExecutionPolicy;
+use crate::Message;
+
+/// How long the request for paths may take before it is given up
+pub const SUGGEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Lines of each block quoted in the request for paths
+const QUOTED_LINES: usize = 12;
+
+// Most characters of the question quoted in the request for paths
+const QUOTED_CHARS: usize = 1_000;
+
+/// What `/apply` with arguments says when no blocks were listed
+pub const NOTHING_LISTED: &str =
+    "Nothing to apply yet: /apply without arguments lists the code blocks of the last reply first";
+
+/// What `/apply` says when a reply came after the one it listed
+pub const REPLY_CHANGED: &str =
+    "There is a newer reply than the one /apply listed the blocks of; run /apply again";
+
+// The labels of a first-line comment naming the file
+const LABELS: [&str; 3] = ["file", "filename", "path"];
+
+const INSTRUCTION: &str = "Which file is each of these code blocks meant for? \
+                           Reply with one line per block, `<number>: <path>`, using the paths \
+                           the request names. Reply `<number>: none` for a block that is not \
+                           meant for a file, or when unsure.";
+
+/// Where a block's target path came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// A `file:` comment on the block's first line
+    Comment,
+    /// The fence's info string
+    Fence,
+    /// The model's answer to the request for paths
+    Model,
+    /// `/apply <n> <path>`
+    User,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Comment => "from its file comment",
+            Self::Fence => "from its fence",
+            Self::Model => "guessed by the model",
+            Self::User => "given by you",
+        })
+    }
+}
+
+/// A path a block is written to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub path: String,
+    pub source: Source,
+}
+
+/// A code block and where it goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Planned {
+    pub language: Option<String>,
+    /// What is written: the block without a `file:` comment
+    pub code: String,
+    pub target: Option<Target>,
+}
+
+/// The proposed mapping of a reply's code blocks to files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+    // The reply the blocks are from, so a newer one isn't applied by mistake
+    reply: String,
+    pub blocks: Vec<Planned>,
+}
+
+impl Plan {
+    /// The blocks of `reply`, with the paths they name themselves
+    pub fn of(reply: &str) -> Self {
+        let blocks = last_code::code_blocks(reply)
+            .into_iter()
+            .map(|block| plan_block(&block))
+            .collect();
+        Self {
+            reply: reply.to_string(),
+            blocks,
+        }
+    }
+
+    /// The blocks of `reply`, or why there is nothing to apply
+    pub fn propose(reply: &str) -> Result<Self, String> {
+        let plan = Self::of(reply);
+        if plan.blocks.is_empty() {
+            return Err("The last reply has no code blocks to apply".to_string());
+        }
+        Ok(plan)
+    }
+
+    /// Whether the plan is for `reply`
+    pub fn is_for(&self, reply: &str) -> bool {
+        self.reply == reply
+    }
+
+    /// The numbers of the blocks without a target, counting from 1
+    pub fn unmapped(&self) -> Vec<usize> {
+        (1..=self.blocks.len())
+            .filter(|number| self.blocks[number - 1].target.is_none())
+            .collect()
+    }
+
+    /// Write block `number` to `path`, as the user said
+    pub fn set(&mut self, number: usize, path: &str) -> Result<(), String> {
+        let count = self.blocks.len();
+        let block = number
+            .checked_sub(1)
+            .and_then(|index| self.blocks.get_mut(index))
+            .ok_or_else(|| no_block(number, count))?;
+        block.target = Some(Target {
+            path: path.to_string(),
+            source: Source::User,
+        });
+        Ok(())
+    }
+
+    /// The numbers, paths and code of the blocks `chosen`, or all if None,
+    /// refused if any of them has no path or shares one with another
+    pub fn targets(&self, chosen: Option<&[usize]>) -> Result<Vec<(usize, &str, &str)>, String> {
+        let all: Vec<usize> = (1..=self.blocks.len()).collect();
+        let mut targets: Vec<(usize, &str, &str)> = Vec::new();
+        for &number in chosen.unwrap_or(&all) {
+            if targets.iter().any(|(seen, _, _)| *seen == number) {
+                continue;
+            }
+            let block = number
+                .checked_sub(1)
+                .and_then(|index| self.blocks.get(index))
+                .ok_or_else(|| no_block(number, self.blocks.len()))?;
+            let Some(target) = &block.target else {
+                return Err(format!(
+                    "Block {} has no target path; give it one with /apply {} <path>, or pick the \
+                     blocks to write, e.g. /apply 1,2",
+                    number, number
+                ));
+            };
+            if let Some((other, _, _)) = targets.iter().find(|(_, path, _)| *path == target.path) {
+                return Err(format!(
+                    "Blocks {} and {} both target {}; give one of them another path with /apply <n> <path>",
+                    other, number, target.path
+                ));
+            }
+            targets.push((number, &target.path, &block.code));
+        }
+        Ok(targets)
+    }
+
+    /// The proposed mapping, and how to go on from it
+    pub fn describe(&self) -> String {
+        let mut lines = vec![format!(
+            "The last reply has {}:",
+            last_code::blocks(self.blocks.len())
+        )];
+        for (index, block) in self.blocks.iter().enumerate() {
+            let target = match &block.target {
+                Some(target) => format!("{} ({})", target.path, target.source),
+                None => "no target path".to_string(),
+            };
+            let count = block.code.lines().count();
+            lines.push(format!(
+                "  {}. {}, {} line{}",
+                index + 1,
+                target,
+                count,
+                if count == 1 { "" } else { "s" }
+            ));
+        }
+        lines.push(
+            "/apply all writes them, /apply 1,2 only those, /apply <n> <path> sets a path and \
+             /apply cancel drops this"
+                .to_string(),
+        );
+        lines.join("\n")
+    }
+}
+
+// The error for a block number the plan doesn't have
+fn no_block(number: usize, count: usize) -> String {
+    format!(
+        "The last reply has {}; there is no block {}",
+        last_code::blocks(count),
+        number
+    )
+}
+
+// A block with the path its first line or its fence names
+fn plan_block(block: &CodeBlock) -> Planned {
+    let (first, rest) = block.code.split_once('\n').unwrap_or((&block.code, ""));
+    if let Some(path) = comment_path(first) {
+        return Planned {
+            language: block.language.clone(),
+            code: rest.to_string(),
+            target: Some(Target {
+                path,
+                source: Source::Comment,
+            }),
+        };
+    }
+    Planned {
+        language: block.language.clone(),
+        code: block.code.clone(),
+        target: fence_path(&block.info).map(|path| Target {
+            path,
+            source: Source::Fence,
+        }),
+    }
+}
+
+/// The path a comment line such as `// file: src/main.rs` or
+/// `<!-- file: index.html -->` names
+pub fn comment_path(line: &str) -> Option<String> {
+    let line = line.trim();
+    let inner = ["<!--", "/*", "//", "--", "#", ";"]
+        .iter()
+        .find_map(|marker| line.strip_prefix(marker))?;
+    let inner = inner
+        .trim_end()
+        .trim_end_matches("-->")
+        .trim_end_matches("*/");
+    let (label, path) = inner.split_once(':')?;
+    let label = label.trim().to_lowercase();
+    if !LABELS.contains(&label.as_str()) {
+        return None;
+    }
+    let path = unquote(path);
+    looks_like_path(path).then(|| path.to_string())
+}
+
+/// The path a fence's info string names: `title="src/main.rs"` or
+/// `file=src/main.rs`, `rust:src/main.rs`, or a word that looks like a path
+pub fn fence_path(info: &str) -> Option<String> {
+    let words: Vec<&str> = info.split_whitespace().collect();
+    let labelled = words.iter().find_map(|word| {
+        let (key, value) = word.split_once('=')?;
+        matches!(key, "title" | "file" | "filename" | "path").then(|| unquote(value))
+    });
+    let after_language = words
+        .first()
+        .and_then(|word| word.split_once(':'))
+        .map(|(_, path)| path);
+    labelled
+        .into_iter()
+        .chain(after_language)
+        .chain(words.iter().copied())
+        .find(|word| looks_like_path(word))
+        .map(str::to_string)
+}
+
+// `text` trimmed of spaces, quotes and backticks
+fn unquote(text: &str) -> &str {
+    text.trim()
+        .trim_matches(|c: char| matches!(c, '"' | '\'' | '`' | '*'))
+}
+
+/// Whether `word` reads as a file path: one word with a directory or an
+/// extension, and not a URL or a flag
+pub fn looks_like_path(word: &str) -> bool {
+    if word.is_empty()
+        || word.contains(char::is_whitespace)
+        || word.contains("://")
+        || word.starts_with('-')
+        || word.ends_with('/')
+    {
+        return false;
+    }
+    let name = word.rsplit('/').next().unwrap_or(word);
+    let extension = name.rsplit_once('.').is_some_and(|(stem, extension)| {
+        !stem.is_empty()
+            && (1..=5).contains(&extension.len())
+            && extension.chars().all(|c| c.is_ascii_alphanumeric())
+    });
+    extension || (word.contains('/') && !name.contains('.'))
+}
+
+/// The request asking the model where the blocks `numbers` of `plan` go,
+/// for the question the reply answered
+pub fn request(question: &str, plan: &Plan, numbers: &[usize]) -> Vec<Message> {
+    let mut text = format!(
+        "{}\n\nRequest: {}",
+        INSTRUCTION,
+        question.chars().take(QUOTED_CHARS).collect::<String>()
+    );
+    for &number in numbers {
+        let block = &plan.blocks[number - 1];
+        let quoted: Vec<&str> = block.code.lines().take(QUOTED_LINES).collect();
+        text.push_str(&format!(
+            "\n\nBlock {} ({}):\n{}",
+            number,
+            block.language.as_deref().unwrap_or("no language"),
+            quoted.join("\n")
+        ));
+    }
+    vec![Message::user(text)]
+}
+
+/// The paths a model's answer gives blocks, as (number, path) pairs;
+/// lines that don't name a block and a path are skipped
+pub fn parse_suggestions(text: &str) -> Vec<(usize, String)> {
+    text.lines()
+        .filter_map(|line| {
+            let line = line.trim().trim_start_matches(['-', '*', ' ']);
+            let (number, path) = line.split_once(':')?;
+            let number = number
+                .trim()
+                .trim_start_matches(|c: char| !c.is_ascii_digit())
+                .parse()
+                .ok()?;
+            let path = unquote(path);
+            looks_like_path(path).then(|| (number, path.to_string()))
+        })
+        .collect()
+}
+
+/// Ask `client` where the blocks of `plan` without a target go, filling in
+/// what it answers; a failed or slow request leaves them as they are
+pub async fn suggest(client: &dyn LlmClient, question: &str, plan: &mut Plan) {
+    let numbers = plan.unmapped();
+    if numbers.is_empty() {
+        return;
+    }
+    let messages = request(question, plan, &numbers);
+    let Ok(Ok((text, _, _))) =
+        tokio::time::timeout(SUGGEST_TIMEOUT, client.generate_response(&messages)).await
+    else {
+        return;
+    };
+    for (number, path) in parse_suggestions(&reasoning::split(&text).content) {
+        if numbers.contains(&number) {
+            plan.blocks[number - 1].target = Some(Target {
+                path,
+                source: Source::Model,
+            });
+        }
+    }
+}
+
+/// Write the blocks `chosen` of `plan`, or all if None, with the file tool
+/// under `policy`, saying what happened to each; refused as a whole if
+/// [`Plan::targets`] refuses them
+pub async fn write(
+    plan: &Plan,
+    chosen: Option<&[usize]>,
+    policy: Arc<ExecutionPolicy>,
+) -> Result<String, String> {
+    let targets = plan.targets(chosen)?;
+    let mut file = File::with_policy(policy);
+    let mut lines = Vec::new();
+    for (number, path, code) in targets {
+        let mut code = code.to_string();
+        if !code.is_empty() && !code.ends_with('\n') {
+            code.push('\n');
+        }
+        let outcome = match file.write(path, &code, false).await {
+            // A declined review says more to the model than the user needs
+            Ok(output) => output
+                .content
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            Err(e) => format!("Error: {}", e),
+        };
+        lines.push(format!("Block {}: {}", number, outcome));
+    }
+    Ok(lines.join("\n"))
+}
+
+/// What `/apply` was asked to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// Propose paths for the blocks of the last reply
+    Propose,
+    /// Give block n a path
+    Set(usize, String),
+    /// Write the blocks listed, or all of them
+    Write(Option<Vec<usize>>),
+    /// Drop the proposal
+    Cancel,
+}
+
+/// The arguments of `/apply`, or None if they make no sense
+pub fn parse(args: &str) -> Option<Request> {
+    match args {
+        "" => return Some(Request::Propose),
+        "all" => return Some(Request::Write(None)),
+        "cancel" => return Some(Request::Cancel),
+        _ => {}
+    }
+    let numbers: Option<Vec<usize>> = args
+        .split(',')
+        .map(|number| number.trim().parse().ok().filter(|number| *number > 0))
+        .collect();
+    if let Some(numbers) = numbers {
+        return Some(Request::Write(Some(numbers)));
+    }
+    let (number, path) = args.split_once(char::is_whitespace)?;
+    let number = number.parse().ok().filter(|number| *number > 0)?;
+    Some(Request::Set(number, unquote(path).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::mock::MockLlmClient;
+    use tempfile::TempDir;
+
+    const REPLY: &str = "Change these:\n\
+        ```rust\n\
+        // file: src/main.rs\n\
+        fn main() {}\n\
+        ```\n\
+        ```toml title=\"Cargo.toml\"\n\
+        [package]\n\
+        ```\n\
+        ```python\n\
+        print(1)\n\
+        ```\n";
+
+    fn paths(plan: &Plan) -> Vec<Option<(&str, Source)>> {
+        plan.blocks
+            .iter()
+            .map(|block| {
+                block
+                    .target
+                    .as_ref()
+                    .map(|target| (target.path.as_str(), target.source))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_paths_come_from_file_comments_and_fences() {
+        let plan = Plan::of(REPLY);
+        assert_eq!(
+            paths(&plan),
+            [
+                Some(("src/main.rs", Source::Comment)),
+                Some(("Cargo.toml", Source::Fence)),
+                None
+            ]
+        );
+        // The comment naming the file is not written to it
+        assert_eq!(plan.blocks[0].code, "fn main() {}");
+        assert_eq!(plan.unmapped(), [3]);
+
+        assert_eq!(
+            comment_path("# file: setup.py").as_deref(),
+            Some("setup.py")
+        );
+        assert_eq!(
+            comment_path("<!-- Filename: `web/index.html` -->").as_deref(),
+            Some("web/index.html")
+        );
+        assert_eq!(
+            comment_path("/* path: src/app.css */").as_deref(),
+            Some("src/app.css")
+        );
+        assert_eq!(comment_path("// This file: does things"), None);
+        assert_eq!(comment_path("file: src/main.rs"), None);
+        assert_eq!(comment_path("// see: src/main.rs"), None);
+
+        assert_eq!(fence_path("rust src/lib.rs").as_deref(), Some("src/lib.rs"));
+        assert_eq!(fence_path("rust:src/lib.rs").as_deref(), Some("src/lib.rs"));
+        assert_eq!(
+            fence_path("Makefile.toml").as_deref(),
+            Some("Makefile.toml")
+        );
+        assert_eq!(
+            fence_path("sh file=scripts/run").as_deref(),
+            Some("scripts/run")
+        );
+        assert_eq!(fence_path("rust"), None);
+        assert_eq!(fence_path("c++ -O2"), None);
+        assert_eq!(fence_path("text https://example.com/a.html"), None);
+    }
+
+    #[test]
+    fn test_ambiguous_or_missing_targets_are_refused() {
+        let mut plan = Plan::of(REPLY);
+        assert_eq!(
+            plan.targets(None).unwrap_err(),
+            "Block 3 has no target path; give it one with /apply 3 <path>, or pick the blocks \
+             to write, e.g. /apply 1,2"
+        );
+        let chosen = plan.targets(Some(&[2, 1])).unwrap();
+        assert_eq!(
+            chosen,
+            [
+                (2, "Cargo.toml", "[package]"),
+                (1, "src/main.rs", "fn main() {}")
+            ]
+        );
+
+        // Two blocks for one file can't both be written
+        plan.set(3, "src/main.rs").unwrap();
+        assert_eq!(
+            plan.targets(None).unwrap_err(),
+            "Blocks 1 and 3 both target src/main.rs; give one of them another path with /apply <n> <path>"
+        );
+        plan.set(3, "scripts/hello.py").unwrap();
+        assert_eq!(plan.targets(None).unwrap().len(), 3);
+        assert!(plan
+            .describe()
+            .contains("  3. scripts/hello.py (given by you), 1 line"));
+
+        assert_eq!(
+            plan.set(4, "a.rs").unwrap_err(),
+            "The last reply has 3 code blocks; there is no block 4"
+        );
+        assert!(plan.targets(Some(&[5])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_the_model_is_asked_about_unmapped_blocks() {
+        let client = MockLlmClient::new().with_reply("3: `scripts/hello.py`\n1: src/other.rs");
+        let mut plan = Plan::of(REPLY);
+        suggest(&client, "Print one", &mut plan).await;
+        // Only the block without a path takes the suggestion
+        assert_eq!(paths(&plan)[0], Some(("src/main.rs", Source::Comment)));
+        assert_eq!(paths(&plan)[2], Some(("scripts/hello.py", Source::Model)));
+        let asked = &client.received()[0][0].content;
+        assert!(asked.contains("Request: Print one"));
+        assert!(asked.contains("Block 3 (python):\nprint(1)") && !asked.contains("Block 1"));
+
+        assert_eq!(
+            parse_suggestions("- 1: none\n2. `src/a.rs`\nBlock 3: lib/b.rs"),
+            [(3, "lib/b.rs".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_blocks_are_written_with_the_file_tool() -> anyhow::Result<()> {
+        let dir = TempDir::new()?;
+        let policy = ExecutionPolicy::shared(false);
+        policy.set_sandbox_root(dir.path().to_path_buf());
+        let mut plan = Plan::of(REPLY);
+        plan.set(3, "../outside.py").unwrap();
+
+        let report = write(&plan, None, Arc::clone(&policy)).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("src/main.rs"))?,
+            "fn main() {}\n"
+        );
+        assert!(report.starts_with("Block 1: Successfully wrote file: "));
+        assert!(report
+            .lines()
+            .nth(2)
+            .unwrap()
+            .starts_with("Block 3: Error: "));
+        assert_eq!(policy.journal().entries().len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_arguments() {
+        assert_eq!(parse(""), Some(Request::Propose));
+        assert_eq!(parse("all"), Some(Request::Write(None)));
+        assert_eq!(parse("1, 3"), Some(Request::Write(Some(vec![1, 3]))));
+        assert_eq!(
+            parse("2 src/my file.rs"),
+            Some(Request::Set(2, "src/my file.rs".to_string()))
+        );
+        assert_eq!(parse("cancel"), Some(Request::Cancel));
+        assert_eq!(parse("0"), None);
+        assert_eq!(parse("yes please"), None);
+    }
+}
@@ -0,0 +1,383 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+use crate::truncate::{self, Strategy};
+
+// Maximum number of bytes included from a single attached file
+const MAX_ATTACHMENT_LENGTH: usize = 20000;
+// Maximum combined size of all files matched by the patterns of one request
+const MAX_TOTAL_ATTACHMENT_BYTES: u64 = 200 * 1024;
+// Maximum number of files a single request may attach
+const MAX_ATTACHMENT_FILES: usize = 50;
+
+// Directories never descended into when expanding globs
+const IGNORED_DIRS: [&str; 8] = [
+    ".git",
+    "target",
+    "node_modules",
+    "__pycache__",
+    "dist",
+    "build",
+    ".idea",
+    ".vscode",
+];
+
+/// A file staged to be included with a prompt
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Attachment {
+    pub path: String,
+    pub content: String,
+    pub truncated: bool,
+}
+
+impl Attachment {
+    /// Render the attachment as a fenced code block labeled with its path
+    pub fn render(&self) -> String {
+        // Use a fence longer than any backtick run in the content so it can't be closed early
+        let longest_run = self
+            .content
+            .split(|c| c != '`')
+            .map(|run| run.len())
+            .max()
+            .unwrap_or(0);
+        let fence = "`".repeat(longest_run.max(2) + 1);
+
+        let mut block = format!(
+            "File: {}\n{}{}\n{}\n{}",
+            self.path,
+            fence,
+            language_for(&self.path),
+            self.content.trim_end_matches('\n'),
+            fence
+        );
+
+        if self.truncated {
+            block.push_str(&format!(
+                "\n(File truncated to {} bytes)",
+                MAX_ATTACHMENT_LENGTH
+            ));
+        }
+
+        block
+    }
+}
+
+/// Attachments loaded for a request along with warnings for skipped or truncated files
+#[derive(Debug, Default)]
+pub struct LoadedAttachments {
+    pub attachments: Vec<Attachment>,
+    pub warnings: Vec<String>,
+}
+
+/// Expand the given paths and glob patterns and read every matched file
+pub fn load(patterns: &[String]) -> Result<LoadedAttachments> {
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        let matched = expand_pattern(pattern)?;
+        if matched.is_empty() {
+            return Err(anyhow!("No files match '{}'", pattern));
+        }
+        for path in matched {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+
+    if paths.len() > MAX_ATTACHMENT_FILES {
+        return Err(anyhow!(
+            "Refusing to attach {} files (limit is {}). Use a more specific path or pattern",
+            paths.len(),
+            MAX_ATTACHMENT_FILES
+        ));
+    }
+
+    let total_size: u64 = paths
+        .iter()
+        .filter_map(|path| fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    if total_size > MAX_TOTAL_ATTACHMENT_BYTES {
+        return Err(anyhow!(
+            "Refusing to attach {} KB across {} files (limit is {} KB). Use a more specific path or pattern",
+            total_size / 1024,
+            paths.len(),
+            MAX_TOTAL_ATTACHMENT_BYTES / 1024
+        ));
+    }
+
+    let mut loaded = LoadedAttachments::default();
+    for path in paths {
+        let display = path.to_string_lossy().to_string();
+
+        if is_secret_file(&path) {
+            loaded.warnings.push(format!(
+                "Skipped '{}': it looks like a secrets file",
+                display
+            ));
+            continue;
+        }
+
+        let bytes = fs::read(&path).map_err(|e| anyhow!("Failed to read '{}': {}", display, e))?;
+        let Ok(content) = String::from_utf8(bytes) else {
+            loaded
+                .warnings
+                .push(format!("Skipped '{}': not a UTF-8 text file", display));
+            continue;
+        };
+
+        let truncated = content.len() > MAX_ATTACHMENT_LENGTH;
+        if truncated {
+            loaded.warnings.push(format!(
+                "'{}' was truncated to {} bytes",
+                display, MAX_ATTACHMENT_LENGTH
+            ));
+        }
+
+        loaded.attachments.push(Attachment {
+            path: display,
+            content: truncate::truncate(&content, MAX_ATTACHMENT_LENGTH, Strategy::Middle),
+            truncated,
+        });
+    }
+
+    Ok(loaded)
+}
+
+/// Append rendered attachments to a message
+pub fn with_attachments(message: &str, attachments: &[Attachment]) -> String {
+    if attachments.is_empty() {
+        return message.to_string();
+    }
+
+    let blocks: Vec<String> = attachments.iter().map(Attachment::render).collect();
+    format!("{}\n\n{}", message, blocks.join("\n\n"))
+}
+
+/// The paths of `attachments`, to remember where a message's content came from
+pub fn paths(attachments: &[Attachment]) -> Vec<String> {
+    attachments
+        .iter()
+        .map(|attachment| attachment.path.clone())
+        .collect()
+}
+
+// Whether the pattern contains glob syntax rather than being a plain path
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '[', '{'])
+}
+
+// Resolve a path or glob pattern to the list of files it names
+fn expand_pattern(pattern: &str) -> Result<Vec<PathBuf>> {
+    if !is_glob(pattern) {
+        let path = PathBuf::from(pattern);
+        if !path.exists() {
+            return Err(anyhow!("File '{}' does not exist", pattern));
+        }
+        if !path.is_file() {
+            return Err(anyhow!(
+                "'{}' is not a file. Use a glob like '{}/**/*' to attach a directory",
+                pattern,
+                pattern.trim_end_matches('/')
+            ));
+        }
+        return Ok(vec![path]);
+    }
+
+    // Walk from the longest literal directory prefix of the pattern
+    let root: PathBuf = Path::new(pattern)
+        .components()
+        .take_while(|component| !is_glob(&component.as_os_str().to_string_lossy()))
+        .collect();
+    let walk_root = if root.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        root
+    };
+
+    let mut files = Vec::new();
+    walk_files(&walk_root, &mut files)?;
+
+    let pattern = pattern.trim_start_matches("./");
+    let mut matched: Vec<PathBuf> = files
+        .into_iter()
+        .filter(|path| {
+            let relative = path.to_string_lossy();
+            glob_match::glob_match(pattern, relative.trim_start_matches("./"))
+        })
+        .collect();
+    matched.sort();
+
+    Ok(matched)
+}
+
+// Collect all regular files below a directory, skipping hidden and build directories
+fn walk_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            if name.starts_with('.') || IGNORED_DIRS.contains(&name.as_str()) {
+                continue;
+            }
+            walk_files(&path, files)?;
+        } else if file_type.is_file() {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a file name looks like it holds credentials
+pub fn is_secret_file(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    name == ".env"
+        || name.starts_with(".env.")
+        || name.starts_with("id_rsa")
+        || name.starts_with("id_ed25519")
+        || name.starts_with("credentials")
+        || [".pem", ".key", ".p12", ".pfx", ".keystore"]
+            .iter()
+            .any(|ext| name.ends_with(ext))
+}
+
+// Markdown language tag for a file path
+fn language_for(path: &str) -> &'static str {
+    let extension = Path::new(path)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "rs" => "rust",
+        "py" => "python",
+        "js" => "javascript",
+        "ts" => "typescript",
+        "go" => "go",
+        "toml" => "toml",
+        "json" => "json",
+        "yaml" | "yml" => "yaml",
+        "md" => "markdown",
+        "sh" => "bash",
+        "c" | "h" => "c",
+        "cpp" | "hpp" | "cc" => "cpp",
+        "java" => "java",
+        "html" => "html",
+        "css" => "css",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_load_single_file_renders_fenced_block() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("main.rs");
+        fs::write(&file_path, "fn main() {}\n")?;
+
+        let loaded = load(&[file_path.to_string_lossy().to_string()])?;
+        assert_eq!(loaded.attachments.len(), 1);
+        assert!(loaded.warnings.is_empty());
+
+        let rendered = loaded.attachments[0].render();
+        assert!(rendered.starts_with(&format!("File: {}", file_path.display())));
+        assert!(rendered.contains("```rust\nfn main() {}\n```"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_glob_expansion() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join("src/tools"))?;
+        fs::write(dir.path().join("src/main.rs"), "fn main() {}")?;
+        fs::write(dir.path().join("src/tools/bash.rs"), "pub mod bash;")?;
+        fs::write(dir.path().join("src/notes.txt"), "notes")?;
+
+        let pattern = format!("{}/src/**/*.rs", dir.path().display());
+        let loaded = load(&[pattern])?;
+
+        let paths: Vec<&str> = loaded.attachments.iter().map(|a| a.path.as_str()).collect();
+        assert_eq!(paths.len(), 2);
+        assert!(paths.iter().any(|p| p.ends_with("src/main.rs")));
+        assert!(paths.iter().any(|p| p.ends_with("src/tools/bash.rs")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_total_size_limit() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        for i in 0..3 {
+            fs::write(
+                dir.path().join(format!("big{}.txt", i)),
+                "x".repeat(100 * 1024),
+            )?;
+        }
+
+        let pattern = format!("{}/*.txt", dir.path().display());
+        let error = load(&[pattern]).unwrap_err().to_string();
+        assert!(error.contains("Refusing to attach"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_file_truncated_with_warning() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join("log.txt");
+        fs::write(&file_path, "line\n".repeat(MAX_ATTACHMENT_LENGTH))?;
+
+        let loaded = load(&[file_path.to_string_lossy().to_string()])?;
+        assert!(loaded.attachments[0].truncated);
+        assert!(loaded.attachments[0].content.contains("lines truncated"));
+        assert_eq!(loaded.warnings.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_secret_files_skipped() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let file_path = dir.path().join(".env");
+        fs::write(&file_path, "API_KEY=hunter2")?;
+
+        let loaded = load(&[file_path.to_string_lossy().to_string()])?;
+        assert!(loaded.attachments.is_empty());
+        assert!(loaded.warnings[0].contains("secrets"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_file_errors() {
+        assert!(load(&["/path/that/does/not/exist.rs".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_fence_longer_than_content_backticks() {
+        let attachment = Attachment {
+            path: "README.md".to_string(),
+            content: "```bash\ncargo run\n```".to_string(),
+            truncated: false,
+        };
+
+        let rendered = attachment.render();
+        assert!(rendered.contains("````markdown\n"));
+        assert!(rendered.ends_with("\n````"));
+    }
+}
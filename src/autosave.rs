@@ -0,0 +1,525 @@
+//! Saving the TUI conversation as it goes, so a crash doesn't lose it.
+//!
+//! While the TUI runs, a marker file holds its process id and the
+//! conversation, with every branch forked from it, is written to the
+//! last-session file every few seconds and after every reply. A clean exit removes the marker; finding it on startup, left by a
+//! process that is no longer running, means the last session can be restored.
+//! The session's undo journal is kept next to it whenever it changes, for
+//! `sentinel diff` and so a continued session can still undo its changes.
+//!
+//! The file is written and read through [`session_file`](crate::session_file),
+//! so sessions saved by older versions still load. Once it would grow past
+//! `[sessions] max_size_kb`, long contents move to blob files next to it, see
+//! [`session_blobs`](crate::session_blobs).
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use chrono::Local;
+use tokio::task::JoinHandle;
+
+use crate::history;
+use crate::session::{SessionMeta, Sessions};
+use crate::session_blobs::{self, BlobStore, SessionsConfig};
+use crate::session_file::{SessionFile, StoredMessage};
+use crate::tools::journal::Journal;
+
+/// How often the conversation is saved while nothing else prompts a save
+pub const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+const SESSION_FILE: &str = "last-session.json";
+const MARKER_FILE: &str = "last-session.pid";
+const JOURNAL_FILE: &str = "last-session-journal.json";
+
+/// What the previous run left behind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Previous {
+    /// It exited cleanly, or there was none
+    Finished,
+    /// Another process, with this id, is still running and saving
+    Running(u32),
+    /// It ended without exiting cleanly, so its last save can be restored
+    Unfinished,
+}
+
+/// The last-session file and its marker, and what was last written to them
+#[derive(Debug)]
+pub struct Autosave {
+    dir: PathBuf,
+    limits: SessionsConfig,
+    saved: Option<u64>,
+    saved_at: Instant,
+    pending: Option<JoinHandle<Result<()>>>,
+    // The revision of the journal last written
+    journal_saved: usize,
+}
+
+impl Autosave {
+    /// Save to `dir`
+    pub fn in_dir(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            limits: SessionsConfig::default(),
+            saved: None,
+            saved_at: Instant::now(),
+            pending: None,
+            journal_saved: 0,
+        }
+    }
+
+    /// Save next to the input history, if there is a place for it
+    pub fn open_default() -> Option<Self> {
+        history::data_dir().map(Self::in_dir)
+    }
+
+    /// Move long contents to blobs once the file would be larger than `limits` allow
+    pub fn with_limits(mut self, limits: SessionsConfig) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(SESSION_FILE)
+    }
+
+    /// Where the contents moved out of the file are kept
+    pub fn blobs(&self) -> BlobStore {
+        BlobStore::next_to(&self.path())
+    }
+
+    /// A warning if the file as last written is over the limit even with
+    /// its long contents moved to blobs, as every save of it is slow
+    pub fn size_warning(&self) -> Option<String> {
+        let size = fs::metadata(self.path()).ok()?.len();
+        (size > self.limits.max_size()).then(|| {
+            format!(
+                "The saved session is {}, over the {} of sessions.max_size_kb even with long contents moved to blob files, so saving it is slow; /clear starts a new one",
+                session_blobs::size(size),
+                session_blobs::size(self.limits.max_size())
+            )
+        })
+    }
+
+    /// Where the last session's file changes are kept
+    pub fn journal_path(&self) -> PathBuf {
+        self.dir.join(JOURNAL_FILE)
+    }
+
+    /// Keep the session's file changes, replacing the last session's
+    pub fn save_journal(&self, journal: &Journal) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        journal.save(&self.journal_path())
+    }
+
+    /// Keep the session's file changes if they changed since they were last
+    /// kept; a session that changed nothing leaves the last one's alone, and
+    /// a failed save is tried again once they change again
+    pub fn save_journal_if_changed(&mut self, journal: &Journal) -> Result<()> {
+        let revision = journal.revision();
+        if revision == self.journal_saved {
+            return Ok(());
+        }
+        self.journal_saved = revision;
+        self.save_journal(journal)
+    }
+
+    /// The file changes of the last session, if it kept any
+    pub fn load_journal(&self) -> Result<Option<Journal>> {
+        let path = self.journal_path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        Journal::load(&path).map(Some)
+    }
+
+    fn marker(&self) -> PathBuf {
+        self.dir.join(MARKER_FILE)
+    }
+
+    /// Check how the previous run ended, from its marker
+    pub fn previous(&self) -> Previous {
+        let Ok(contents) = fs::read_to_string(self.marker()) else {
+            return Previous::Finished;
+        };
+        match contents.trim().parse() {
+            Ok(pid) if pid != std::process::id() && is_running(pid) => Previous::Running(pid),
+            _ if self.path().exists() => Previous::Unfinished,
+            _ => Previous::Finished,
+        }
+    }
+
+    /// Read the last save, upgraded to the current format
+    pub fn load(&self) -> Result<SessionFile> {
+        let path = self.path();
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        SessionFile::parse(&contents).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Mark this process as running, until `finish`
+    pub fn start(&self) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        write_atomically(&self.marker(), std::process::id().to_string().as_bytes())
+            .with_context(|| format!("Failed to write {}", self.marker().display()))
+    }
+
+    /// Whether the interval since the last save has passed
+    pub fn is_due(&self) -> bool {
+        self.saved_at.elapsed() >= AUTOSAVE_INTERVAL
+    }
+
+    /// Write every branch of `sessions` on a blocking task, unless they and
+    /// `meta` are what was last written or the last write is still going;
+    /// true if a write started
+    pub fn save_if_changed<M>(&mut self, meta: &SessionMeta, sessions: &Sessions<M>) -> bool
+    where
+        M: Clone,
+        for<'a> StoredMessage: From<&'a M>,
+    {
+        if self
+            .pending
+            .as_ref()
+            .is_some_and(|write| !write.is_finished())
+        {
+            return false;
+        }
+        self.saved_at = Instant::now();
+        let Some((file, fingerprint)) = self.changed(meta, sessions) else {
+            return false;
+        };
+
+        let (path, limits) = (self.path(), self.limits);
+        self.saved = Some(fingerprint);
+        self.pending = Some(tokio::task::spawn_blocking(move || {
+            write_session(&path, file, &limits)
+        }));
+        true
+    }
+
+    /// Whether `meta` and `sessions` are what was last written
+    pub fn is_saved<M>(&self, meta: &SessionMeta, sessions: &Sessions<M>) -> bool
+    where
+        M: Clone,
+        for<'a> StoredMessage: From<&'a M>,
+    {
+        self.changed(meta, sessions).is_none()
+    }
+
+    /// Write `sessions` now, e.g. on the way out
+    pub fn save_now<M>(&mut self, meta: &SessionMeta, sessions: &Sessions<M>) -> Result<()>
+    where
+        M: Clone,
+        for<'a> StoredMessage: From<&'a M>,
+    {
+        let Some((file, fingerprint)) = self.changed(meta, sessions) else {
+            return Ok(());
+        };
+        write_session(&self.path(), file, &self.limits)?;
+        self.saved = Some(fingerprint);
+        Ok(())
+    }
+
+    /// Mark the run as having exited cleanly
+    pub fn finish(&self) -> Result<()> {
+        match fs::remove_file(self.marker()) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove {}", self.marker().display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // The session file to write and its fingerprint, if not what was last
+    // saved. The fingerprint leaves out the time of the save, which is now
+    fn changed<M>(&self, meta: &SessionMeta, sessions: &Sessions<M>) -> Option<(SessionFile, u64)>
+    where
+        M: Clone,
+        for<'a> StoredMessage: From<&'a M>,
+    {
+        let mut file = SessionFile::with_branches(
+            &SessionMeta {
+                updated: None,
+                ..meta.clone()
+            },
+            sessions,
+        );
+        let unchanged = file.to_json().ok()?;
+        let mut hasher = DefaultHasher::new();
+        unchanged.hash(&mut hasher);
+        let fingerprint = hasher.finish();
+        if self.saved == Some(fingerprint) {
+            return None;
+        }
+
+        file.metadata.updated = Some(Local::now());
+        Some((file, fingerprint))
+    }
+}
+
+// Write `file` to `path`, moving long contents to blobs first if it is too large
+fn write_session(path: &Path, mut file: SessionFile, limits: &SessionsConfig) -> Result<()> {
+    session_blobs::extract(&mut file, &BlobStore::next_to(path), limits)?;
+    write_atomically(path, file.to_json()?.as_bytes())
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Write `contents` to a temporary file next to `path` and rename it into
+/// place, so `path` holds either the old contents or the new, never half
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let temp = path.with_file_name(format!(".{}.tmp", name));
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
+
+// Whether a process with this id exists
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 checks the process without signalling it; EPERM means it
+    // exists but belongs to someone else
+    let signalled = unsafe { libc::kill(pid, 0) == 0 };
+    signalled || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::journal;
+    use crate::Message;
+    use tempfile::TempDir;
+
+    fn contents(file: SessionFile) -> Vec<String> {
+        file.messages
+            .into_iter()
+            .map(|message| message.content)
+            .collect()
+    }
+
+    #[test]
+    fn test_marker_tells_how_the_last_run_ended() -> Result<()> {
+        let dir = TempDir::new()?;
+        let autosave = Autosave::in_dir(dir.path());
+        assert_eq!(autosave.previous(), Previous::Finished);
+
+        // A run that saved but never finished
+        autosave.start()?;
+        write_atomically(
+            &autosave.path(),
+            br#"[{"role": "user", "content": "hello"}]"#,
+        )?;
+        assert_eq!(autosave.previous(), Previous::Unfinished);
+        assert_eq!(contents(autosave.load()?), ["hello"]);
+
+        autosave.finish()?;
+        assert_eq!(autosave.previous(), Previous::Finished);
+        autosave.finish()?;
+
+        // A marker left by a process that is still running
+        let mut child = std::process::Command::new("sleep").arg("5").spawn()?;
+        fs::write(autosave.marker(), child.id().to_string())?;
+        assert_eq!(autosave.previous(), Previous::Running(child.id()));
+        child.kill()?;
+        child.wait()?;
+        assert_eq!(autosave.previous(), Previous::Unfinished);
+        Ok(())
+    }
+
+    #[test]
+    fn test_the_journal_is_kept_as_it_changes() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut autosave = Autosave::in_dir(dir.path().join("data"));
+        let journal = Journal::new();
+        autosave.save_journal_if_changed(&journal)?;
+        assert!(autosave.load_journal()?.is_none());
+
+        let notes = dir.path().join("notes.txt");
+        fs::write(&notes, "milk\n")?;
+        let before = journal::backup(&notes);
+        fs::write(&notes, "eggs\n")?;
+        journal.record_write(&notes, before);
+        autosave.save_journal_if_changed(&journal)?;
+
+        // The run restoring a crashed session can undo its changes
+        let restored = Journal::new();
+        restored.restore(autosave.load_journal()?.unwrap());
+        restored.undo(None).unwrap();
+        assert_eq!(fs::read_to_string(&notes)?, "milk\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_saves_skip_unchanged_messages() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut autosave = Autosave::in_dir(dir.path());
+        let mut meta = SessionMeta::new("llama3.2");
+        let mut sessions = Sessions::new(vec![Message::system("system"), Message::user("hi")]);
+
+        assert!(autosave.save_if_changed(&meta, &sessions));
+        autosave.pending.take().unwrap().await??;
+        assert!(!autosave.save_if_changed(&meta, &sessions));
+        assert!(!autosave.is_due());
+
+        sessions
+            .messages_mut()
+            .push(Message::assistant("hello", 10, 2, Vec::new()));
+        assert!(autosave.save_if_changed(&meta, &sessions));
+        autosave.pending.take().unwrap().await??;
+        assert_eq!(
+            autosave.load()?.into_messages::<Message>(),
+            sessions.messages()
+        );
+
+        // A new title is a change too
+        meta.title = Some("Greetings".to_string());
+        assert!(autosave.save_if_changed(&meta, &sessions));
+        autosave.pending.take().unwrap().await??;
+
+        // Saving on the way out skips unchanged sessions too
+        fs::remove_file(autosave.path())?;
+        autosave.save_now(&meta, &sessions)?;
+        assert!(!autosave.path().exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_files_carry_a_header() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut autosave = Autosave::in_dir(dir.path());
+        let meta = SessionMeta {
+            title: Some("Naming a cat".to_string()),
+            ..SessionMeta::new("llama3.2")
+        };
+        autosave.save_now(
+            &meta,
+            &Sessions::new(vec![
+                Message::system("system"),
+                Message::user("name my cat"),
+            ]),
+        )?;
+
+        let saved = autosave.load()?;
+        assert_eq!(saved.metadata.title.as_deref(), Some("Naming a cat"));
+        assert_eq!(saved.metadata.model.as_deref(), Some("llama3.2"));
+        assert_eq!(saved.metadata.message_count, 2);
+        assert_eq!(saved.metadata.created, meta.created);
+        assert!(saved.metadata.updated >= meta.created);
+
+        // Files from before the header load with an empty one
+        write_atomically(
+            &autosave.path(),
+            br#"[{"role": "system", "content": "system"}, {"role": "user", "content": "hi"}]"#,
+        )?;
+        let old = autosave.load()?;
+        assert_eq!(old.metadata.title, None);
+        assert_eq!(old.metadata.message_count, 2);
+        assert_eq!(contents(old), ["system", "hi"]);
+
+        write_atomically(&autosave.path(), br#"{"messages": 3}"#)?;
+        assert!(autosave.load().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_forked_sessions_keep_their_branches() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut autosave = Autosave::in_dir(dir.path());
+        let mut sessions = Sessions::new(vec![
+            Message::system("system"),
+            Message::user("name my cat"),
+            Message::assistant("Miso", 10, 2, Vec::new()),
+        ]);
+        sessions.fork(1)?;
+        sessions
+            .messages_mut()
+            .push(Message::assistant("Tofu", 10, 2, Vec::new()));
+        sessions.switch("main")?;
+        sessions.fork(2)?;
+        sessions.messages_mut().push(Message::user("another"));
+        autosave.save_now(&SessionMeta::new("llama3.2"), &sessions)?;
+
+        let saved = autosave.load()?;
+        assert_eq!(
+            contents(saved.clone()),
+            ["system", "name my cat", "Miso", "another"]
+        );
+        let mut restored: Sessions<Message> = saved.into_sessions();
+        assert_eq!(restored.active_name(), "fork-2");
+        assert_eq!(restored.summaries(), sessions.summaries());
+        for name in ["main", "fork-1", "fork-2"] {
+            sessions.switch(name)?;
+            restored.switch(name)?;
+            assert_eq!(restored.messages(), sessions.messages());
+        }
+        // Forks go on being numbered from the last
+        assert_eq!(restored.fork(0)?, "fork-3");
+
+        // Sessions that were never forked load with only the main branch
+        autosave.save_now(
+            &SessionMeta::default(),
+            &Sessions::new(vec![Message::user("hi")]),
+        )?;
+        let saved = autosave.load()?;
+        assert!(saved.branches.is_empty());
+        assert_eq!(saved.into_sessions::<Message>().summaries().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_sessions_move_long_contents_to_blobs() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut autosave = Autosave::in_dir(dir.path()).with_limits(SessionsConfig {
+            max_size_kb: 8,
+            blob_min_kb: 4,
+        });
+        let meta = SessionMeta::new("llama3.2");
+        let report = "x".repeat(6 * 1024);
+        let mut sessions = Sessions::new(vec![Message::user("run it"), Message::user(&report)]);
+        autosave.save_now(&meta, &sessions)?;
+        assert_eq!(autosave.size_warning(), None);
+
+        // Past the limit the older report moves out, and loads back unchanged
+        sessions.messages_mut().push(Message::user(&report));
+        autosave.save_now(&meta, &sessions)?;
+        let saved = autosave.load()?;
+        let blob = saved.messages[1].blob.clone().unwrap();
+        assert!(saved.messages[1]
+            .content
+            .contains("moved out of the session file"));
+        assert_eq!(saved.messages[2].blob, None);
+        assert_eq!(autosave.blobs().get(&blob)?, report);
+        assert_eq!(autosave.size_warning(), None);
+
+        // Short messages alone can still outgrow it
+        sessions
+            .messages_mut()
+            .extend((0..100).map(|n| Message::user(format!("message {} {}", n, "y".repeat(100)))));
+        autosave.save_now(&meta, &sessions)?;
+        let warning = autosave.size_warning().unwrap();
+        assert!(warning.starts_with("The saved session is "), "{}", warning);
+        assert!(warning.contains("over the 8.0 KB of sessions.max_size_kb"));
+        Ok(())
+    }
+}
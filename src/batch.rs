@@ -0,0 +1,370 @@
+//! Running a file of prompts as independent requests, for `sentinel batch`.
+//!
+//! Each prompt is sent on its own, without tools or history, a few at a time.
+//! Every outcome is appended to the output file as soon as it is in, as one
+//! [`BatchRecord`] a line: the prompt with the same fields `ask --json`
+//! prints. A failed prompt is recorded with its error and the batch carries
+//! on, and a resumed batch skips the prompts the output file already has.
+
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::llm::ollama::LlmClient;
+use crate::report::{self, AskReport};
+use crate::Message;
+
+/// One prompt of a batch
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum BatchEntry {
+    Prompt(String),
+    Item {
+        prompt: String,
+        /// Replaces the configured system prompt for this prompt only
+        #[serde(default)]
+        system: Option<String>,
+    },
+}
+
+/// A prompt to send, with where it was in the file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchItem {
+    pub index: usize,
+    pub prompt: String,
+    pub system: Option<String>,
+}
+
+/// One line of the output file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchRecord {
+    /// Where the prompt was in the prompts file, counting from 0
+    pub index: usize,
+    pub prompt: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    #[serde(flatten)]
+    pub report: AskReport,
+}
+
+/// How to run a batch
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    /// How many prompts are sent at once
+    pub jobs: usize,
+    /// Skip the prompts the output file has already, and append to it
+    pub resume: bool,
+    /// The system prompt of prompts without their own
+    pub system: Option<String>,
+}
+
+/// What a batch did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchSummary {
+    pub ran: usize,
+    pub failed: usize,
+    /// Already in the output file of a resumed batch
+    pub skipped: usize,
+}
+
+/// Read the prompts of a batch: a JSON array or JSON Lines of prompts or
+/// `{"prompt", "system"}` objects for .json and .jsonl files, otherwise each
+/// non-empty line is a prompt
+pub fn load(path: &Path) -> Result<Vec<BatchItem>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read the prompts in {}", path.display()))?;
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    let entries = parse(&text, extension)
+        .with_context(|| format!("Failed to parse the prompts in {}", path.display()))?;
+    Ok(entries
+        .into_iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let (prompt, system) = match entry {
+                BatchEntry::Prompt(prompt) => (prompt, None),
+                BatchEntry::Item { prompt, system } => (prompt, system),
+            };
+            BatchItem {
+                index,
+                prompt,
+                system,
+            }
+        })
+        .collect())
+}
+
+/// The prompts in `text`, read as the format of a file with `extension`
+pub fn parse(text: &str, extension: Option<&str>) -> Result<Vec<BatchEntry>> {
+    let lines = text.lines().filter(|line| !line.trim().is_empty());
+    match extension {
+        Some("json") => Ok(serde_json::from_str(text)?),
+        Some("jsonl") => lines
+            .enumerate()
+            .map(|(number, line)| {
+                serde_json::from_str(line)
+                    .with_context(|| format!("Entry {} is not a prompt", number + 1))
+            })
+            .collect(),
+        Some("yaml" | "yml") => bail!("YAML is not supported; use JSON, JSON Lines or plain text"),
+        _ => Ok(lines
+            .map(|line| BatchEntry::Prompt(line.trim().to_string()))
+            .collect()),
+    }
+}
+
+/// The prompts in the output file `text` that were already sent, by index
+/// and text, so a changed prompts file is not mistaken for them
+pub fn completed(text: &str) -> HashSet<(usize, String)> {
+    // A line cut short by an interrupted batch is sent again
+    text.lines()
+        .filter_map(|line| serde_json::from_str::<BatchRecord>(line).ok())
+        .map(|record| (record.index, record.prompt))
+        .collect()
+}
+
+/// Send `items` to the model, `options.jobs` at a time, appending each
+/// outcome to `output` and passing it to `progress` as it comes in
+pub async fn run(
+    client: &dyn LlmClient,
+    model: &str,
+    items: Vec<BatchItem>,
+    output: &Path,
+    options: &BatchOptions,
+    mut progress: impl FnMut(&BatchRecord),
+) -> Result<BatchSummary> {
+    let existing = match fs::read_to_string(output) {
+        Ok(text) if options.resume => text,
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("Failed to read {}", output.display()));
+        }
+        _ => String::new(),
+    };
+    let done = completed(&existing);
+    let total = items.len();
+    let items: Vec<BatchItem> = items
+        .into_iter()
+        .filter(|item| !done.contains(&(item.index, item.prompt.clone())))
+        .collect();
+    let mut summary = BatchSummary {
+        skipped: total - items.len(),
+        ..BatchSummary::default()
+    };
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(options.resume)
+        .truncate(!options.resume)
+        .open(output)
+        .with_context(|| format!("Failed to open {}", output.display()))?;
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        writeln!(file)?;
+    }
+
+    let mut records = stream::iter(items)
+        .map(|item| ask(client, model, item, options.system.as_deref()))
+        .buffer_unordered(options.jobs.max(1));
+    while let Some(record) = records.next().await {
+        writeln!(file, "{}", serde_json::to_string(&record)?)
+            .with_context(|| format!("Failed to write {}", output.display()))?;
+        summary.ran += 1;
+        if record.report.is_error() {
+            summary.failed += 1;
+        }
+        progress(&record);
+    }
+    Ok(summary)
+}
+
+// Send one prompt on its own
+async fn ask(
+    client: &dyn LlmClient,
+    model: &str,
+    item: BatchItem,
+    system: Option<&str>,
+) -> BatchRecord {
+    let mut messages = Vec::new();
+    if let Some(system) = item.system.as_deref().or(system) {
+        messages.push(Message::system(system));
+    }
+    messages.push(Message::user(item.prompt.clone()));
+    let report = report::ask(client, None, &messages, model, client.provider()).await;
+    BatchRecord {
+        index: item.index,
+        prompt: item.prompt,
+        system: item.system,
+        report,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::mock::MockLlmClient;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn items(prompts: &[&str]) -> Vec<BatchItem> {
+        prompts
+            .iter()
+            .enumerate()
+            .map(|(index, prompt)| BatchItem {
+                index,
+                prompt: prompt.to_string(),
+                system: None,
+            })
+            .collect()
+    }
+
+    // The complete records of the output file
+    fn records(path: &Path) -> Vec<BatchRecord> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()
+    }
+
+    fn options(jobs: usize, resume: bool) -> BatchOptions {
+        BatchOptions {
+            jobs,
+            resume,
+            system: Some("Be brief.".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_parse_prompts() -> Result<()> {
+        assert_eq!(
+            parse("What is Rust?\n\n  Name a crab  \n", Some("txt"))?,
+            [
+                BatchEntry::Prompt("What is Rust?".to_string()),
+                BatchEntry::Prompt("Name a crab".to_string()),
+            ]
+        );
+        let entries = parse(
+            "\"What is Rust?\"\n{\"prompt\": \"Bonjour\", \"system\": \"Answer in French\"}\n",
+            Some("jsonl"),
+        )?;
+        assert_eq!(
+            entries[1],
+            BatchEntry::Item {
+                prompt: "Bonjour".to_string(),
+                system: Some("Answer in French".to_string()),
+            }
+        );
+        assert_eq!(
+            parse("[\"a\", {\"prompt\": \"b\"}]", Some("json"))?.len(),
+            2
+        );
+        assert!(parse("{\"system\": \"no prompt\"}", Some("jsonl")).is_err());
+        assert!(parse("- a", Some("yaml")).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batches_run_a_few_prompts_at_a_time() -> Result<()> {
+        let dir = TempDir::new()?;
+        let output = dir.path().join("results.jsonl");
+        let mut client = MockLlmClient::new();
+        for _ in 0..6 {
+            client = client.with_slow_reply("ok", Duration::from_millis(20));
+        }
+
+        let mut seen = 0;
+        let prompts = ["a", "b", "c", "d", "e", "f"];
+        let summary = run(
+            &client,
+            "mock",
+            items(&prompts),
+            &output,
+            &options(2, false),
+            |_| seen += 1,
+        )
+        .await?;
+        assert_eq!(summary.ran, 6);
+        assert_eq!(seen, 6);
+        assert_eq!(client.max_in_flight(), 2);
+
+        let mut sent: Vec<String> = records(&output).into_iter().map(|r| r.prompt).collect();
+        sent.sort();
+        assert_eq!(sent, prompts);
+        assert!(client
+            .received()
+            .iter()
+            .all(|request| request[0].content == "Be brief."));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_prompts_do_not_stop_the_batch() -> Result<()> {
+        let dir = TempDir::new()?;
+        let output = dir.path().join("results.jsonl");
+        let client = MockLlmClient::new()
+            .with_reply("one")
+            .with_error("model crashed")
+            .with_reply("three");
+
+        let mut batch = items(&["1", "2", "3"]);
+        batch[2].system = Some("Count".to_string());
+        let summary = run(&client, "mock", batch, &output, &options(1, false), |_| {}).await?;
+        assert_eq!(
+            summary,
+            BatchSummary {
+                ran: 3,
+                failed: 1,
+                skipped: 0
+            }
+        );
+
+        let records = records(&output);
+        assert_eq!(records[0].report.response, "one");
+        assert_eq!(records[1].report.error.as_deref(), Some("model crashed"));
+        assert_eq!(records[2].report.response, "three");
+        assert_eq!(records[2].system.as_deref(), Some("Count"));
+        assert_eq!(client.received()[2][0].content, "Count");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resumed_batches_skip_finished_prompts() -> Result<()> {
+        let dir = TempDir::new()?;
+        let output = dir.path().join("results.jsonl");
+        let client = MockLlmClient::new().echoing();
+        run(
+            &client,
+            "mock",
+            items(&["a", "b"]),
+            &output,
+            &options(1, false),
+            |_| {},
+        )
+        .await?;
+        // An interrupted batch may leave half a line behind
+        let mut file = OpenOptions::new().append(true).open(&output)?;
+        write!(file, "{{\"index\": 2, \"prom")?;
+
+        let summary = run(
+            &client,
+            "mock",
+            items(&["a", "b", "c"]),
+            &output,
+            &options(2, true),
+            |_| {},
+        )
+        .await?;
+        assert_eq!(summary.ran, 1);
+        assert_eq!(summary.skipped, 2);
+        assert_eq!(client.received().len(), 3);
+        assert_eq!(client.received()[2][1].content, "c");
+        let records = records(&output);
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2].report.response, "You said: c");
+        Ok(())
+    }
+}
@@ -4,18 +4,10 @@ use ollama_rs::{
     generation::chat::ChatMessage,
     generation::tools::implementations::{Calculator, DDGSearcher, Scraper, StockScraper},
     models::ModelOptions,
-    Ollama,
 };
-use std::env;
-
-/// Get the weather for a given city.
-#[ollama_rs::function]
-async fn get_weather(city: String) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
-    Ok(reqwest::get(format!("https://wttr.in/{city}?format=%C+%t"))
-        .await?
-        .text()
-        .await?)
-}
+use sentinel::config::Config;
+use sentinel::llm::ollama::OllamaClient;
+use sentinel::tools::weather::Weather;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -27,21 +19,15 @@ async fn main() -> Result<()> {
         .nth(1)
         .unwrap_or_else(|| "llama3.2:latest".to_string());
 
-    // Create Ollama client
-    let host = env::var("OLLAMA_HOST").unwrap_or_else(|_| "http://localhost".to_string());
-    let port = env::var("OLLAMA_PORT")
-        .unwrap_or_else(|_| "11434".to_string())
-        .parse::<u16>()
-        .unwrap_or(11434);
-
-    let ollama_client = Ollama::new(host, port);
+    // Create Ollama client, honoring OLLAMA_HOST and OLLAMA_PORT
+    let ollama_client = OllamaClient::new().ollama().clone();
 
     // Create coordinator with tools
     println!("Using model: {}", model);
     let history = vec![];
     let mut coordinator = Coordinator::new(ollama_client, model, history)
         .options(ModelOptions::default().num_ctx(16384))
-        .add_tool(get_weather)
+        .add_tool(Weather::new(Config::load()?.tools.weather))
         .add_tool(Calculator {})
         .add_tool(DDGSearcher::new())
         .add_tool(Scraper {})
@@ -0,0 +1,391 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::llm::ollama::{GenerationOptions, LlmClient, ToolInvocation};
+use crate::llm::reasoning;
+use crate::llm::tool_support::ToolSupport;
+use crate::tool_loop::{self, TurnOutcome};
+use crate::tools::policy;
+use crate::tools::registry::ToolSet;
+use crate::tools::result::ToolError;
+use crate::Message;
+
+/// Most candidates generated at once, one per letter
+pub const MAX_CANDIDATES: usize = 26;
+
+// Generations run at the same time when no tools are involved
+const MAX_CONCURRENT: usize = 3;
+
+/// One of several responses generated for the same prompt
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Candidate {
+    /// "A", "B", "C", ... in generation order
+    pub label: char,
+    pub text: String,
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+    /// Tools used while producing this candidate only
+    pub used_tools: Vec<String>,
+    pub latency: Duration,
+    pub seed: i32,
+    /// The think blocks of a reasoning model, removed from `text`
+    pub reasoning: Option<String>,
+    pub reasoning_tokens: usize,
+    /// The tool calls made for this candidate, the ones with side effects
+    /// only simulated
+    #[serde(skip)]
+    pub tool_calls: Vec<ToolInvocation>,
+}
+
+impl Candidate {
+    // The candidate at `index` from the model's answer to a message
+    fn of_outcome(index: usize, seed: i32, outcome: TurnOutcome, latency: Duration) -> Self {
+        Self {
+            label: label(index),
+            text: outcome.text,
+            input_tokens: outcome.input_tokens + outcome.tool_output_tokens,
+            output_tokens: outcome.output_tokens,
+            used_tools: outcome.used_tools,
+            latency,
+            seed,
+            reasoning: outcome.reasoning,
+            reasoning_tokens: outcome.reasoning_tokens,
+            tool_calls: outcome.tool_calls,
+        }
+    }
+
+    /// Whether any of the candidate's tool calls were only simulated
+    pub fn has_simulated_calls(&self) -> bool {
+        self.tool_calls
+            .iter()
+            .any(|call| policy::is_simulated(&call.output))
+    }
+
+    /// Turn the candidate into an assistant message
+    pub fn into_message(self) -> Message {
+        Message::assistant(
+            self.text,
+            self.input_tokens,
+            self.output_tokens,
+            self.used_tools,
+        )
+        .with_reasoning(self.reasoning)
+    }
+}
+
+/// Label of the candidate at `index`
+pub fn label(index: usize) -> char {
+    (b'A' + index as u8) as char
+}
+
+/// Position of the candidate labelled `label`, in either case
+pub fn index_of(label: &str) -> Option<usize> {
+    let mut chars = label.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphabetic() => {
+            Some((c.to_ascii_uppercase() as u8 - b'A') as usize)
+        }
+        _ => None,
+    }
+}
+
+/// Generate `count` responses to `messages`, each with its own seed.
+///
+/// Without tools the generations run concurrently, a few at a time. With
+/// the client's own tools they run one after another in dry-run mode, so
+/// tools with side effects are only simulated.
+pub async fn generate(
+    client: &dyn LlmClient,
+    messages: &[Message],
+    count: usize,
+    with_tools: bool,
+) -> Result<Vec<Candidate>> {
+    generate_with_seed(client, messages, count, with_tools, random_seed()).await
+}
+
+/// Like [`generate`], with seeds counting up from `base_seed`
+pub async fn generate_with_seed(
+    client: &dyn LlmClient,
+    messages: &[Message],
+    count: usize,
+    with_tools: bool,
+    base_seed: i32,
+) -> Result<Vec<Candidate>> {
+    check_count(count)?;
+
+    let seeds = (0..count).map(|index| (index, base_seed.wrapping_add(index as i32)));
+    let generations = stream::iter(seeds).map(|(index, seed)| async move {
+        generate_one(client, messages, index, seed, with_tools).await
+    });
+
+    if !with_tools {
+        return generations.buffered(MAX_CONCURRENT).try_collect().await;
+    }
+    let dry_run = client.dry_run();
+    client.set_dry_run(true);
+    let candidates = generations.buffered(1).try_collect().await;
+    client.set_dry_run(dry_run);
+    candidates
+}
+
+/// Generate `count` responses to `messages` one after another, each with its
+/// own seed, running the tools the model calls from `tools` as the answer to
+/// a message does.
+///
+/// The candidates are generated in dry-run mode, so tools with side effects
+/// are only simulated; [`run_simulated`] runs them for the one picked.
+/// Cancelling `interrupt` keeps the candidates finished so far.
+pub async fn generate_with_tools(
+    client: &dyn LlmClient,
+    tools: &mut ToolSet,
+    messages: &[Message],
+    count: usize,
+    interrupt: &CancellationToken,
+    support: &ToolSupport,
+    model: &str,
+) -> Result<Vec<Candidate>> {
+    check_count(count)?;
+
+    let policy = Arc::clone(tools.policy());
+    let dry_run = (client.dry_run(), policy.dry_run());
+    client.set_dry_run(true);
+    policy.set_dry_run(true);
+
+    let base_seed = random_seed();
+    let mut candidates = Vec::new();
+    let mut failure = None;
+    for index in 0..count {
+        let seed = base_seed.wrapping_add(index as i32);
+        let options = GenerationOptions::with_seed(seed);
+        let started = Instant::now();
+        let outcome = tool_loop::respond_checked_with_options(
+            client, tools, messages, interrupt, support, model, &options,
+        )
+        .await;
+        match outcome {
+            Ok(outcome) if outcome.interrupted => break,
+            Ok(outcome) => candidates.push(Candidate::of_outcome(
+                index,
+                seed,
+                outcome,
+                started.elapsed(),
+            )),
+            Err(e) => {
+                failure = Some(e);
+                break;
+            }
+        }
+    }
+
+    client.set_dry_run(dry_run.0);
+    policy.set_dry_run(dry_run.1);
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(candidates),
+    }
+}
+
+/// Run the tool calls that were only simulated for `candidate`, in the order
+/// it made them, now that it was picked. Returns the tools run, and stops at
+/// the first call that fails; in a dry-run session nothing is run.
+pub async fn run_simulated(
+    tools: &mut ToolSet,
+    candidate: &Candidate,
+) -> Result<Vec<String>, ToolError> {
+    if tools.policy().dry_run() {
+        return Ok(Vec::new());
+    }
+    tools.start_turn();
+    let mut ran = Vec::new();
+    for call in &candidate.tool_calls {
+        if policy::is_simulated(&call.output) {
+            tools.call(&call.name, call.arguments.clone()).await?;
+            ran.push(call.name.clone());
+        }
+    }
+    Ok(ran)
+}
+
+// Refuse to generate no candidates, or more than there are labels for
+fn check_count(count: usize) -> Result<()> {
+    if count == 0 || count > MAX_CANDIDATES {
+        return Err(anyhow!(
+            "The number of candidates must be between 1 and {}",
+            MAX_CANDIDATES
+        ));
+    }
+    Ok(())
+}
+
+// Generate the candidate at `index` with the given seed
+async fn generate_one(
+    client: &dyn LlmClient,
+    messages: &[Message],
+    index: usize,
+    seed: i32,
+    with_tools: bool,
+) -> Result<Candidate> {
+    let options = GenerationOptions::with_seed(seed);
+    let started = Instant::now();
+
+    let (text, input_tokens, output_tokens, used_tools) = if with_tools {
+        client
+            .generate_response_with_tools_and_options(messages, &[], &options)
+            .await?
+    } else {
+        let (text, input_tokens, output_tokens) = client
+            .generate_response_with_options(messages, &options)
+            .await?;
+        (text, input_tokens, output_tokens, Vec::new())
+    };
+
+    let reply = reasoning::split(&text);
+    Ok(Candidate {
+        label: label(index),
+        reasoning_tokens: reply.reasoning_tokens(output_tokens),
+        text: reply.content,
+        reasoning: reply.reasoning,
+        input_tokens,
+        output_tokens,
+        used_tools,
+        latency: started.elapsed(),
+        seed,
+        tool_calls: if with_tools {
+            client.last_tool_invocations()
+        } else {
+            Vec::new()
+        },
+    })
+}
+
+// A non-negative seed that differs between runs
+fn random_seed() -> i32 {
+    (uuid::Uuid::new_v4().as_u128() as u32 & i32::MAX as u32) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::ollama::Tool;
+    use async_trait::async_trait;
+
+    // Echoes the seed it was given and "uses" a tool named after it
+    struct SeedEcho;
+
+    #[async_trait]
+    impl LlmClient for SeedEcho {
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+
+        async fn generate_response(&self, _messages: &[Message]) -> Result<(String, usize, usize)> {
+            Ok(("no seed".to_string(), 1, 1))
+        }
+
+        async fn generate_response_with_tools(
+            &self,
+            _messages: &[Message],
+            _tools: &[Tool],
+        ) -> Result<(String, usize, usize, Vec<String>)> {
+            Ok(("no seed".to_string(), 1, 1, Vec::new()))
+        }
+
+        async fn generate_response_with_options(
+            &self,
+            _messages: &[Message],
+            options: &GenerationOptions,
+        ) -> Result<(String, usize, usize)> {
+            Ok((format!("seed {}", options.seed.unwrap_or(-1)), 1, 2))
+        }
+
+        async fn generate_response_with_tools_and_options(
+            &self,
+            _messages: &[Message],
+            _tools: &[Tool],
+            options: &GenerationOptions,
+        ) -> Result<(String, usize, usize, Vec<String>)> {
+            let seed = options.seed.unwrap_or(-1);
+            Ok((
+                format!("seed {}", seed),
+                1,
+                2,
+                vec![format!("tool-{}", seed)],
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_candidates_use_distinct_seeds_in_order() -> Result<()> {
+        let candidates = generate_with_seed(&SeedEcho, &[], 4, false, 100).await?;
+
+        let summary: Vec<(char, &str, i32)> = candidates
+            .iter()
+            .map(|c| (c.label, c.text.as_str(), c.seed))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ('A', "seed 100", 100),
+                ('B', "seed 101", 101),
+                ('C', "seed 102", 102),
+                ('D', "seed 103", 103),
+            ]
+        );
+        assert!(candidates.iter().all(|c| c.used_tools.is_empty()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tool_usage_is_kept_per_candidate() -> Result<()> {
+        let candidates = generate_with_seed(&SeedEcho, &[], 2, true, 7).await?;
+
+        assert_eq!(candidates[0].used_tools, vec!["tool-7".to_string()]);
+        assert_eq!(candidates[1].used_tools, vec!["tool-8".to_string()]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_candidates_with_the_tool_set_get_their_own_seeds() -> Result<()> {
+        let mut tools = ToolSet::new();
+        let candidates = generate_with_tools(
+            &SeedEcho,
+            &mut tools,
+            &[],
+            2,
+            &CancellationToken::new(),
+            &ToolSupport::new(),
+            "echo",
+        )
+        .await?;
+
+        let seed = candidates[1].seed;
+        assert_eq!(seed, candidates[0].seed.wrapping_add(1));
+        assert_eq!(candidates[1].text, format!("seed {}", seed));
+        assert_eq!(candidates[1].used_tools, vec![format!("tool-{}", seed)]);
+        assert!(!tools.policy().dry_run());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_candidate_count_is_bounded() {
+        assert!(generate(&SeedEcho, &[], 0, false).await.is_err());
+        assert!(generate(&SeedEcho, &[], MAX_CANDIDATES + 1, false)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_labels() {
+        assert_eq!(label(0), 'A');
+        assert_eq!(label(25), 'Z');
+        assert_eq!(index_of("b"), Some(1));
+        assert_eq!(index_of("C"), Some(2));
+        assert_eq!(index_of("AB"), None);
+        assert_eq!(index_of("1"), None);
+    }
+}
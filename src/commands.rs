@@ -0,0 +1,471 @@
+//! The /commands understood by the CLI and the TUI, and how a line of input is
+//! split into one.
+//!
+//! The command word is matched without regard to case; the rest of the line
+//! is handed to the command as it was typed, trimmed at both ends only, so
+//! `/system You are  a Pirate` keeps its case and spacing.
+
+use std::fmt;
+
+use serde::Serialize;
+
+/// A command and what it does, for help output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CommandHelp {
+    /// The command with its arguments, e.g. "/fork <n>"
+    pub command: &'static str,
+    pub description: &'static str,
+    /// What the arguments mean, for `/help <command>`
+    pub details: &'static str,
+}
+
+impl CommandHelp {
+    /// The command word, e.g. "/fork"
+    pub fn name(&self) -> &'static str {
+        self.command.split(' ').next().unwrap_or(self.command)
+    }
+
+    /// The full usage text shown by `/help <command>`
+    pub fn usage(&self) -> String {
+        let mut usage = format!("Usage: {}\n{}", self.command, self.description);
+        if !self.details.is_empty() {
+            usage.push_str("\n\n");
+            usage.push_str(self.details);
+        }
+        usage
+    }
+}
+
+/// Every command, in the order /help lists them
+pub const COMMANDS: [CommandHelp; 34] = [
+    CommandHelp {
+        command: "/exit",
+        description: "Quit the application",
+        details: "",
+    },
+    CommandHelp {
+        command: "/clear",
+        description: "Clear the conversation history",
+        details: "Also forgets the candidates of /best and which files the tools have read.",
+    },
+    CommandHelp {
+        command: "/tools [<tool> on|off]",
+        description: "List available tools, or switch one on or off",
+        details: "Example: /tools bash off stops offering bash to the model for the rest of the \
+                  session, and /tools bash on offers it again. The enabled list of the [tools] \
+                  config section picks the tools a session starts with.",
+    },
+    CommandHelp {
+        command: "/schema [tool]",
+        description: "Show a tool's description and parameter schema as the model is given them",
+        details: "Without a tool, lists the tools offered to the model. `sentinel tool <name> --schema` \
+                  shows the same, and `sentinel tools --export <path>` writes every schema to a JSON file.\n\
+                  Example: /schema bash",
+    },
+    CommandHelp {
+        command: "/stats",
+        description: "Show token and tool usage for this session",
+        details: "",
+    },
+    CommandHelp {
+        command: "/search <term>",
+        description: "List messages containing a term",
+        details: "The term is matched without regard to case; spaces are part of it.",
+    },
+    CommandHelp {
+        command: "/attach <path|glob>",
+        description: "Stage files for the next message (/attach clear to unstage)",
+        details: "Staged files are sent with the next message, then unstaged.\n\
+                  Examples: /attach src/main.rs, /attach src/**/*.rs, /attach clear",
+    },
+    CommandHelp {
+        command: "/image <path>",
+        description:
+            "Stage a PNG, JPEG or WebP image for the next message (/image clear to unstage)",
+        details: "The model must be able to read images.",
+    },
+    CommandHelp {
+        command: "/history",
+        description: "List the messages of the current branch",
+        details: "The numbers are the ones /fork takes.",
+    },
+    CommandHelp {
+        command: "/fork <n>",
+        description: "Continue in a new branch from message n",
+        details: "Message n and those before it are copied; the branch you leave is kept.",
+    },
+    CommandHelp {
+        command: "/pin <n>",
+        description: "Keep message n word for word when older messages are trimmed",
+        details: "When the conversation outgrows the context window, the oldest messages are \
+                  left out of what is sent and summarized in a note; pinned messages are always \
+                  sent. The numbers are the ones /history shows.",
+    },
+    CommandHelp {
+        command: "/unpin <n>",
+        description: "Let message n be trimmed again",
+        details: "",
+    },
+    CommandHelp {
+        command: "/sessions [name]",
+        description: "List branches, or switch to one",
+        details: "",
+    },
+    CommandHelp {
+        command: "/title [text]",
+        description: "Show the title of this session, or set it",
+        details: "The TUI asks the model for a title after the first reply, unless auto_title \
+                  is turned off in the [model] config section; a title you set replaces it. \
+                  The REPL doesn't save its sessions, so they have no title.\n\
+                  Example: /title Parser rewrite",
+    },
+    CommandHelp {
+        command: "/best <n> [message]",
+        description: "Generate n candidate replies to a message, or to the last one",
+        details: "Without a message, the last reply is generated again n times.\n\
+                  Example: /best 3 Suggest a name for this function",
+    },
+    CommandHelp {
+        command: "/pick <label> [keep]",
+        description: "Keep one candidate; 'keep' saves the others as alternatives",
+        details: "Example: /pick B keep",
+    },
+    CommandHelp {
+        command: "/compare <model> [model]",
+        description: "Show another model's reply to the last message beside the last reply",
+        details: "With two models, both are asked afresh. The models are asked at the same \
+                  time, without tools, and their replies are not saved until one is promoted.\n\
+                  Examples: /compare qwen2.5, /compare llama3.2 mistral",
+    },
+    CommandHelp {
+        command: "/promote <label>",
+        description: "Put a reply from the last /compare in place of the last reply",
+        details: "In the TUI, press enter on the reply in the comparison instead.\n\
+                  Example: /promote B",
+    },
+    CommandHelp {
+        command: "/queue [clear|remove <n>]",
+        description: "List the messages waiting for the reply being generated, or cancel them",
+        details: "In the TUI, messages sent while a reply is generated wait in a queue and are \
+                  sent one at a time. The REPL sends each message as it is typed.\n\
+                  Examples: /queue, /queue remove 2, /queue clear",
+    },
+    CommandHelp {
+        command: "/templates",
+        description: "List the prompt templates",
+        details: "",
+    },
+    CommandHelp {
+        command: "/use <template>",
+        description: "Fill in a prompt template and send it",
+        details: "You are asked for each of the template's variables; answer @path to read one from a file.",
+    },
+    CommandHelp {
+        command: "/prime",
+        description: "Give the model a snapshot of the current project",
+        details: "",
+    },
+    CommandHelp {
+        command: "/dryrun on|off",
+        description: "Preview mutating tool actions instead of executing them",
+        details: "Without on or off, shows whether dry-run mode is on.",
+    },
+    CommandHelp {
+        command: "/notify on|off",
+        description: "Ring the bell and notify the desktop when a slow response finishes",
+        details: "Without on or off, shows whether notifications are on.",
+    },
+    CommandHelp {
+        command: "/theme [dark|light]",
+        description: "Switch the colors of the TUI",
+        details: "Without a name, shows the theme in use. The config's `theme` sets the one to start with, \
+                  or a [theme] table of colors over either.",
+    },
+    CommandHelp {
+        command: "/set [max_tokens <n|off> | stop [text ...] | timeout <duration|off> [fixed|progress]]",
+        description: "Cap the length of responses, set where they stop or how long they may take",
+        details: "Without arguments, shows the limits; /set stop alone clears the stop sequences.\n\
+                  A progress timeout restarts whenever the model finishes a step or a tool returns; \
+                  a fixed one covers the whole response.\n\
+                  Examples: /set stop \"```\" END, /set timeout 2m fixed",
+    },
+    CommandHelp {
+        command: "/unload",
+        description: "Release the model's memory until the next message",
+        details: "",
+    },
+    CommandHelp {
+        command: "/run-last <command>",
+        description: "Run a command over the code blocks of the last reply and show the model its output",
+        details: "The blocks are written to temp files such as sentinel-last-1.rs, removed when the \
+                  session ends. {} in the command stands for all of them and {2} for the second; \
+                  without either, the files are added at the end. The command runs through the \
+                  bash tool, so its rules apply.\n\
+                  Examples: /run-last rustc --edition 2021 {1}, /run-last shellcheck",
+    },
+    CommandHelp {
+        command: "/apply [all | n,n... | <n> <path> | cancel]",
+        description: "Write the code blocks of the last reply to the files they are meant for",
+        details: "Without arguments, lists the blocks with the paths they name in a `// file: <path>` \
+                  first line or in their fence, e.g. ```rust src/main.rs, and asks the model about \
+                  the rest. Nothing is written until you pick the blocks; blocks without a path \
+                  or sharing one are refused. The writes go through the file tool, so review, \
+                  protected paths and /undo apply.\n\
+                  Examples: /apply, /apply 2 src/lib.rs, /apply 1,3, /apply all",
+    },
+    CommandHelp {
+        command: "/undo [list|n]",
+        description: "Revert the last file change made by a tool, or change n",
+        details: "A change is not undone if the file was modified outside Sentinel since.\n\
+                  Examples: /undo, /undo list, /undo 3",
+    },
+    CommandHelp {
+        command: "/diff",
+        description: "Show what the tools changed in files this session, as one diff",
+        details: "Each file is compared as it was before the session first changed it with what \
+                  it holds now. `sentinel diff` shows the same for the last session.",
+    },
+    CommandHelp {
+        command: "/protect [glob]",
+        description: "Write-protect matching paths for this session, or list the rules",
+        details: "Example: /protect migrations/",
+    },
+    CommandHelp {
+        command: "/memory [list | add <fact> | edit <id> <fact> | forget <id> | priority <id> low|normal|high | clear]",
+        description: "Show or change the facts remembered across sessions",
+        details: "The facts are given to the model at the start of each conversation, and it can \
+                  change them with the memory tool. Facts that look like passwords, keys or tokens \
+                  are refused.\n\
+                  Examples: /memory add The project uses sqlx, /memory forget 3",
+    },
+    CommandHelp {
+        command: "/help [command]",
+        description: "Show this help message, or the usage of one command",
+        details: "Example: /help fork",
+    },
+];
+
+/// A line of input sorted out by [`parse`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parsed<'a> {
+    /// A message for the model
+    NotACommand,
+    /// A known command, with the rest of the line
+    Command {
+        command: &'static CommandHelp,
+        args: &'a str,
+    },
+    /// A /word that is no command
+    Unknown(&'a str),
+}
+
+/// Why a command could not run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    /// No command has this name; the closest one, if any is close
+    Unknown {
+        name: String,
+        suggestion: Option<&'static str>,
+    },
+    /// The arguments don't fit the command
+    Usage(&'static CommandHelp),
+    /// A quote was opened and never closed
+    UnclosedQuote(char),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown { name, suggestion } => {
+                write!(f, "Unknown command: {}", name)?;
+                match suggestion {
+                    Some(suggestion) => write!(f, " (did you mean {}?)", suggestion),
+                    None => write!(f, "; type /help for available commands"),
+                }
+            }
+            Self::Usage(command) => write!(f, "Usage: {}", command.command),
+            Self::UnclosedQuote(quote) => write!(f, "Missing closing {} in the arguments", quote),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Split a line of input into a command and its arguments
+pub fn parse(input: &str) -> Parsed<'_> {
+    let input = input.trim();
+    if !input.starts_with('/') {
+        return Parsed::NotACommand;
+    }
+
+    let (name, args) = match input.split_once(char::is_whitespace) {
+        Some((name, args)) => (name, args.trim()),
+        None => (input, ""),
+    };
+    match find(name) {
+        Some(command) => Parsed::Command { command, args },
+        None => Parsed::Unknown(name),
+    }
+}
+
+/// The command named `name`, with or without its slash, in any case
+pub fn find(name: &str) -> Option<&'static CommandHelp> {
+    let name = name.strip_prefix('/').unwrap_or(name);
+    COMMANDS
+        .iter()
+        .find(|command| command.name()[1..].eq_ignore_ascii_case(name))
+}
+
+/// The error for an unknown command, suggesting one that starts the same way
+pub fn unknown(name: &str) -> CommandError {
+    let word = name.trim_start_matches('/').to_lowercase();
+    let suggestion = (!word.is_empty())
+        .then(|| {
+            COMMANDS
+                .iter()
+                .map(CommandHelp::name)
+                .find(|known| known[1..].starts_with(&word) || word.starts_with(&known[1..]))
+        })
+        .flatten();
+    CommandError::Unknown {
+        name: name.to_string(),
+        suggestion,
+    }
+}
+
+/// Refuse arguments to a command that takes none
+pub fn no_args(command: &'static CommandHelp, args: &str) -> Result<(), CommandError> {
+    if args.is_empty() {
+        Ok(())
+    } else {
+        Err(CommandError::Usage(command))
+    }
+}
+
+/// The tool and whether to switch it on, from the `<tool> on|off` of `/tools`
+pub fn tool_switch<'a>(
+    command: &'static CommandHelp,
+    args: &'a str,
+) -> Result<(&'a str, bool), CommandError> {
+    let (name, state) = args
+        .split_once(char::is_whitespace)
+        .ok_or(CommandError::Usage(command))?;
+    match state.trim().to_lowercase().as_str() {
+        "on" => Ok((name, true)),
+        "off" => Ok((name, false)),
+        _ => Err(CommandError::Usage(command)),
+    }
+}
+
+/// Split arguments at whitespace, keeping quoted words together: `"a b"` and
+/// `'a b'` are one argument, and a backslash escapes the next character
+/// outside single quotes
+pub fn split_args(args: &str) -> Result<Vec<String>, CommandError> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = args.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some(close) if close == c => break,
+                        Some('\\') if c == '"' => word.extend(chars.next()),
+                        Some(next) => word.push(next),
+                        None => return Err(CommandError::UnclosedQuote(c)),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            c if c.is_whitespace() => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(input: &str) -> (&'static str, &str) {
+        match parse(input) {
+            Parsed::Command { command, args } => (command.name(), args),
+            other => panic!("{:?} is not a command: {:?}", input, other),
+        }
+    }
+
+    #[test]
+    fn test_parse_keeps_the_arguments_as_typed() {
+        assert_eq!(command("/fork 3"), ("/fork", "3"));
+        assert_eq!(
+            command("  /SEARCH   Hello  World  "),
+            ("/search", "Hello  World")
+        );
+        assert_eq!(command("/attach\tsrc/Main.rs"), ("/attach", "src/Main.rs"));
+        assert_eq!(command("/exit"), ("/exit", ""));
+
+        assert_eq!(parse("hello /exit"), Parsed::NotACommand);
+        assert_eq!(parse("   "), Parsed::NotACommand);
+    }
+
+    #[test]
+    fn test_unknown_commands() {
+        assert_eq!(parse("/load mysession"), Parsed::Unknown("/load"));
+        assert_eq!(parse("/"), Parsed::Unknown("/"));
+
+        assert_eq!(
+            unknown("/his").to_string(),
+            "Unknown command: /his (did you mean /history?)"
+        );
+        assert_eq!(
+            unknown("/helpme").to_string(),
+            "Unknown command: /helpme (did you mean /help?)"
+        );
+        assert_eq!(
+            unknown("/load").to_string(),
+            "Unknown command: /load; type /help for available commands"
+        );
+        assert_eq!(
+            unknown("/").to_string(),
+            "Unknown command: /; type /help for available commands"
+        );
+    }
+
+    #[test]
+    fn test_find_and_usage() {
+        assert_eq!(find("fork").map(CommandHelp::name), Some("/fork"));
+        assert_eq!(find("/FORK").map(CommandHelp::name), Some("/fork"));
+        assert_eq!(find("/for"), None);
+
+        let best = find("best").unwrap();
+        assert_eq!(
+            best.usage().lines().next(),
+            Some("Usage: /best <n> [message]")
+        );
+        assert!(no_args(find("exit").unwrap(), "now").is_err());
+        assert!(no_args(find("exit").unwrap(), "").is_ok());
+    }
+
+    #[test]
+    fn test_split_args_quoting() {
+        assert_eq!(
+            split_args(r#"one "two three" 'four "five"' six\ seven"#).unwrap(),
+            ["one", "two three", "four \"five\"", "six seven"]
+        );
+        assert_eq!(split_args("  a   b  ").unwrap(), ["a", "b"]);
+        assert_eq!(
+            split_args(r#""say \"hi\"" '' x"#).unwrap(),
+            ["say \"hi\"", "", "x"]
+        );
+        assert_eq!(split_args("key=\"a b\"").unwrap(), ["key=a b"]);
+        assert!(split_args("").unwrap().is_empty());
+        assert_eq!(
+            split_args("\"open").unwrap_err(),
+            CommandError::UnclosedQuote('"')
+        );
+    }
+}
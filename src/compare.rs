@@ -0,0 +1,230 @@
+//! Replaying the conversation against other models, for `/compare`.
+//!
+//! `/compare <model>` sends the prompt of the last reply to another model and
+//! sets its answer beside that reply; `/compare <a> <b>` asks two models
+//! afresh. The models are asked at the same time. Their replies stay out of
+//! the conversation, and so out of the history and autosave, until one is
+//! promoted in place of the last reply.
+
+use std::time::{Duration, Instant};
+
+use futures::future;
+use serde::Serialize;
+
+use crate::candidates;
+use crate::llm::ollama::LlmClient;
+use crate::llm::reasoning;
+use crate::Message;
+
+/// Most models asked by one /compare
+pub const MAX_MODELS: usize = 2;
+
+/// A reply shown in a comparison
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Reply {
+    pub text: String,
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+    /// How long the reply took, unless it was generated before this session
+    pub latency: Option<Duration>,
+    /// The think blocks of a reasoning model, removed from `text`
+    pub reasoning: Option<String>,
+    pub reasoning_tokens: usize,
+}
+
+impl Reply {
+    /// Turn the reply into an assistant message
+    pub fn into_message(self) -> Message {
+        let message =
+            Message::assistant(self.text, self.input_tokens, self.output_tokens, Vec::new())
+                .with_reasoning(self.reasoning);
+        match self.latency {
+            Some(latency) => message.with_latency(latency),
+            None => message,
+        }
+    }
+}
+
+/// One model's side of a comparison
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Comparison {
+    /// "A", "B", ... from left to right
+    pub label: char,
+    pub model: String,
+    /// Who served `model`, e.g. "ollama"
+    pub provider: String,
+    /// Whether this is the reply already in the conversation
+    pub original: bool,
+    /// The reply, or why the model failed to give one
+    pub reply: Result<Reply, String>,
+}
+
+impl Comparison {
+    /// The reply already in the conversation, produced by `model`
+    pub fn original(model: &str, provider: &str, reply: Reply) -> Self {
+        Self {
+            label: candidates::label(0),
+            model: model.to_string(),
+            provider: provider.to_string(),
+            original: true,
+            reply: Ok(reply),
+        }
+    }
+
+    /// How the reply is told apart, e.g. "B qwen2.5 (not saved)"
+    pub fn heading(&self) -> String {
+        let kind = if self.original {
+            "original"
+        } else {
+            "not saved"
+        };
+        format!("{} {} ({})", self.label, self.model, kind)
+    }
+
+    /// The tokens and latency of the reply, e.g. "12 in / 48 out tokens, 2.1s"
+    pub fn usage(&self) -> String {
+        match &self.reply {
+            Ok(reply) => {
+                let latency = match reply.latency {
+                    Some(latency) => format!("{:.1}s", latency.as_secs_f64()),
+                    None => "time not recorded".to_string(),
+                };
+                format!(
+                    "{} in / {} out tokens, {}",
+                    reply.input_tokens, reply.output_tokens, latency
+                )
+            }
+            Err(_) => "failed".to_string(),
+        }
+    }
+
+    /// The text shown for the reply: its content, or the error
+    pub fn text(&self) -> String {
+        match &self.reply {
+            Ok(reply) => reply.text.clone(),
+            Err(error) => format!("Error: {}", error),
+        }
+    }
+}
+
+/// The models named in the arguments of /compare, if there are one or two
+pub fn models(args: &str) -> Option<Vec<String>> {
+    let models: Vec<String> = args.split_whitespace().map(str::to_string).collect();
+    (1..=MAX_MODELS).contains(&models.len()).then_some(models)
+}
+
+/// Ask each of `models` for a reply to `messages`, all at the same time,
+/// labelling the replies after those in `before`
+pub async fn run(
+    client: &dyn LlmClient,
+    models: &[String],
+    messages: &[Message],
+    before: Vec<Comparison>,
+) -> Vec<Comparison> {
+    let requests = models
+        .iter()
+        .map(|model| async move { ask(client, model, messages).await });
+    let replies = future::join_all(requests).await;
+
+    let mut comparisons = before;
+    for (model, (provider, reply)) in models.iter().zip(replies) {
+        comparisons.push(Comparison {
+            label: candidates::label(comparisons.len()),
+            model: model.clone(),
+            provider,
+            original: false,
+            reply,
+        });
+    }
+    comparisons
+}
+
+// One model's reply to `messages`, with the provider that served it
+async fn ask(
+    client: &dyn LlmClient,
+    model: &str,
+    messages: &[Message],
+) -> (String, Result<Reply, String>) {
+    let client = match client.for_model(model) {
+        Ok(client) => client,
+        Err(e) => return (String::new(), Err(format!("{:#}", e))),
+    };
+    let provider = client.provider().to_string();
+    let started = Instant::now();
+    let reply = match client.generate_response(messages).await {
+        Ok((text, input_tokens, output_tokens)) => {
+            let split = reasoning::split(&text);
+            Ok(Reply {
+                reasoning_tokens: split.reasoning_tokens(output_tokens),
+                text: split.content,
+                reasoning: split.reasoning,
+                input_tokens,
+                output_tokens,
+                latency: Some(started.elapsed()),
+            })
+        }
+        Err(e) => Err(format!("{:#}", e)),
+    };
+    (provider, reply)
+}
+
+/// The comparison labelled `label`, in either case
+pub fn find<'a>(comparisons: &'a [Comparison], label: &str) -> Option<&'a Comparison> {
+    let index = candidates::index_of(label)?;
+    comparisons.get(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::mock::MockLlmClient;
+
+    #[test]
+    fn test_models_are_parsed() {
+        assert_eq!(models("qwen2.5"), Some(vec!["qwen2.5".to_string()]));
+        assert_eq!(
+            models(" llama3.2  mistral "),
+            Some(vec!["llama3.2".to_string(), "mistral".to_string()])
+        );
+        assert_eq!(models(""), None);
+        assert_eq!(models("a b c"), None);
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_kept_per_model() {
+        let client = MockLlmClient::new()
+            .with_model_client("qwen", MockLlmClient::new().with_reply("From qwen"));
+        let original = Comparison::original(
+            "llama",
+            "mock",
+            Reply {
+                text: "From llama".to_string(),
+                input_tokens: 3,
+                output_tokens: 5,
+                latency: None,
+                reasoning: None,
+                reasoning_tokens: 0,
+            },
+        );
+        let models = ["qwen".to_string(), "missing".to_string()];
+        let comparisons = run(&client, &models, &[Message::user("hi")], vec![original]).await;
+
+        let labels: Vec<char> = comparisons.iter().map(|c| c.label).collect();
+        assert_eq!(labels, ['A', 'B', 'C']);
+        assert_eq!(comparisons[0].heading(), "A llama (original)");
+        assert_eq!(
+            comparisons[0].usage(),
+            "3 in / 5 out tokens, time not recorded"
+        );
+        assert_eq!(comparisons[1].heading(), "B qwen (not saved)");
+        assert_eq!(comparisons[1].text(), "From qwen");
+        assert!(comparisons[2]
+            .text()
+            .starts_with("Error: MockLlmClient has no client"));
+        assert_eq!(
+            find(&comparisons, "b").map(|c| c.model.as_str()),
+            Some("qwen")
+        );
+        assert!(find(&comparisons, "d").is_none());
+    }
+}
@@ -0,0 +1,615 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use ollama_rs::generation::parameters::KeepAlive;
+use serde::Deserialize;
+use toml::{Table, Value};
+
+use crate::llm::endpoint::Endpoint;
+use crate::llm::keep_alive;
+use crate::llm::limits::TimeoutMode;
+use crate::memory::MemoryConfig;
+use crate::notify::NotifyConfig;
+use crate::privacy::PrivacyConfig;
+use crate::session_blobs::SessionsConfig;
+use crate::stats;
+use crate::tools::command_rules::BashConfig;
+use crate::tools::custom::CustomToolConfig;
+use crate::tools::file::DeleteConfig;
+use crate::tools::rate_limit::RateLimit;
+use crate::tools::recovery;
+use crate::tools::summarize::SummarizeConfig;
+use crate::tools::untrusted::WebContentConfig;
+use crate::tools::walk::WalkConfig;
+use crate::tools::weather::WeatherConfig;
+
+/// The per-project config file, found in the current directory or one of its parents
+pub const PROJECT_FILE: &str = ".sentinel.toml";
+
+// Keys holding paths, which are relative to the directory of the file that sets them
+const PATH_KEYS: [&str; 1] = ["sandbox_root"];
+
+/// Settings read from the user's config file, with any project config merged over it
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// TUI key binding overrides, e.g. `quit = "ctrl-q"`
+    pub keys: BTreeMap<String, String>,
+    /// The colors of the TUI
+    pub theme: ThemeConfig,
+    /// When to tell the user a slow generation finished
+    pub notify: NotifyConfig,
+    /// Which model to use, how long it stays loaded, and whether to load it on startup
+    pub model: ModelConfig,
+    /// Sent at the start of every conversation, e.g. to point at the project's conventions
+    pub system_prompt: Option<String>,
+    /// The REPL prompt, with {model}, {provider}, {context}, {window} and
+    /// {queue} standing for the session's state
+    pub prompt_format: Option<String>,
+    /// Which tools the model may use
+    pub tools: ToolsConfig,
+    /// The directory the file tool is confined to; relative paths are resolved against it
+    pub sandbox_root: Option<PathBuf>,
+    /// Glob patterns of paths the tools may read but never change, e.g.
+    /// "migrations/**" or "Cargo.lock"
+    pub protected_paths: Vec<String>,
+    /// Refuse every change to files and every command that isn't read-only,
+    /// for the whole session; also set by `--read-only`
+    pub read_only: bool,
+    /// Set by `--force-protected` to let the tools change protected paths anyway
+    #[serde(skip)]
+    pub force_protected: bool,
+    /// Set by `--force-tools` to send tools even to models that can't call them
+    #[serde(skip)]
+    pub force_tools: bool,
+    /// Ollama servers to try in order, from `[[endpoints]]` tables
+    pub endpoints: Vec<Endpoint>,
+    /// Whether and where facts are remembered across sessions
+    pub memory: MemoryConfig,
+    /// Whether local content goes to remote servers without asking
+    pub privacy: PrivacyConfig,
+    /// Show the words, characters and reading time of the last reply in the
+    /// TUI's stats panel
+    pub word_counts: bool,
+    /// How the TUI is laid out; `z` switches it and saves the choice here
+    pub layout: LayoutMode,
+    /// How large the saved session may grow before long contents move to blob files
+    pub sessions: SessionsConfig,
+    /// Problems that did not stop the config from loading, such as unknown keys
+    #[serde(skip)]
+    pub warnings: Vec<String>,
+}
+
+/// Model used when neither the command line nor a config file names one
+pub const DEFAULT_MODEL: &str = "llama3.2:latest";
+
+/// Seconds a response may take unless the config says otherwise
+pub const DEFAULT_TIMEOUT_SECS: u64 = 600;
+
+/// The `[model]` config table
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct ModelConfig {
+    /// The model to use unless the command line names one
+    pub name: Option<String>,
+    /// How long the model stays loaded after a request, e.g. "30m", "-1" (forever) or "0"
+    pub keep_alive: Option<String>,
+    /// Load the model in the background on startup so the first prompt is fast
+    pub warm_up: bool,
+    /// Ask the model for a title for the conversation after its first reply
+    pub auto_title: bool,
+    /// Most tokens a response may have (Ollama's num_predict); unlimited if unset
+    pub max_tokens: Option<u32>,
+    /// Text that ends a response when the model writes it
+    pub stop: Vec<String>,
+    /// Seconds a response may take before it is given up; 0 for no limit
+    pub timeout_secs: u64,
+    /// Whether the timeout restarts whenever the model makes progress ("progress")
+    /// or covers the whole response ("fixed")
+    pub timeout_mode: TimeoutMode,
+    /// The context window to request (Ollama's num_ctx); as large as the
+    /// model supports, within `max_num_ctx`, if unset
+    pub num_ctx: Option<usize>,
+    /// The largest context window requested for a model that supports more,
+    /// to keep its memory in check; 32768 if unset
+    pub max_num_ctx: Option<usize>,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            name: None,
+            keep_alive: None,
+            warm_up: true,
+            auto_title: true,
+            max_tokens: None,
+            stop: Vec::new(),
+            timeout_secs: DEFAULT_TIMEOUT_SECS,
+            timeout_mode: TimeoutMode::default(),
+            num_ctx: None,
+            max_num_ctx: None,
+        }
+    }
+}
+
+impl ModelConfig {
+    /// The model to use, `flag` taking precedence over the config file
+    pub fn name(&self, flag: Option<&str>) -> String {
+        flag.or(self.name.as_deref())
+            .unwrap_or(DEFAULT_MODEL)
+            .to_string()
+    }
+
+    /// The keep-alive setting, `flag` taking precedence over the config file
+    pub fn keep_alive(&self, flag: Option<&str>) -> Result<Option<KeepAlive>> {
+        flag.or(self.keep_alive.as_deref())
+            .map(keep_alive::parse)
+            .transpose()
+    }
+}
+
+/// The colors of the TUI: `theme = "dark"` or `"light"`, or a `[theme]`
+/// table of colors over one of them, e.g. `base = "light"` and `user = "#005f87"`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum ThemeConfig {
+    Named(String),
+    Custom(BTreeMap<String, String>),
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        Self::Named("dark".to_string())
+    }
+}
+
+/// How the TUI is laid out: `layout = "full"` for the status bar, stats
+/// panel and input box, or `"zen"` for the conversation alone over a line of input
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayoutMode {
+    #[default]
+    Full,
+    Zen,
+}
+
+impl LayoutMode {
+    /// The name the config gives it
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Full => "full",
+            Self::Zen => "zen",
+        }
+    }
+
+    /// The other layout
+    pub fn toggled(&self) -> Self {
+        match self {
+            Self::Full => Self::Zen,
+            Self::Zen => Self::Full,
+        }
+    }
+}
+
+/// The `[tools]` config table
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ToolsConfig {
+    /// Names of the tools offered to the model; all of them if unset
+    pub enabled: Option<Vec<String>>,
+    /// How often the network tools (weather, search, scraper) may be called
+    pub rate_limit: RateLimit,
+    /// Which service the weather tool asks, and in which units
+    pub weather: WeatherConfig,
+    /// Which commands the bash tool may run
+    pub bash: BashConfig,
+    /// Malformed tool calls in a row after which a turn fails; 3 if unset
+    pub max_malformed_calls: Option<usize>,
+    /// How the output of the web tools is guarded against prompt injection
+    pub web_content: WebContentConfig,
+    /// Which entries the ls and find_file tools leave out
+    pub walk: WalkConfig,
+    /// How large a directory the file tool deletes without being forced
+    pub delete: DeleteConfig,
+    /// Seconds after which a tool call is highlighted as slow; 10 if unset
+    pub slow_call_secs: Option<f64>,
+    /// Tell the model how long each tool took, as part of the tool's output
+    pub verbose_tool_output: bool,
+    /// Which tool output is summarized before it goes back to the model
+    pub summarize: SummarizeConfig,
+    /// Project-specific tools that run shell commands, from `[[tools.custom]]` tables
+    pub custom: Vec<CustomToolConfig>,
+}
+
+impl ToolsConfig {
+    /// Whether the tool called `name` may be offered to the model
+    pub fn allows(&self, name: &str) -> bool {
+        self.enabled
+            .as_ref()
+            .is_none_or(|enabled| enabled.iter().any(|tool| tool.eq_ignore_ascii_case(name)))
+    }
+
+    /// Offer the tool called `name` to the model, or stop offering it. `known`
+    /// are all the tools there are, which stand for an unset enabled list
+    pub fn set_enabled(&mut self, name: &str, enabled: bool, known: &[String]) {
+        let mut names = self.enabled.clone().unwrap_or_else(|| known.to_vec());
+        names.retain(|tool| !tool.eq_ignore_ascii_case(name));
+        if enabled {
+            names.push(name.to_string());
+        }
+        self.enabled = Some(names);
+    }
+
+    /// Malformed tool calls in a row after which a turn fails
+    pub fn max_malformed_calls(&self) -> usize {
+        self.max_malformed_calls
+            .unwrap_or(recovery::DEFAULT_MAX_MALFORMED)
+    }
+
+    /// How long a tool call takes before it is highlighted as slow
+    pub fn slow_call(&self) -> Duration {
+        self.slow_call_secs
+            .filter(|secs| secs.is_finite() && *secs >= 0.0)
+            .map_or(stats::DEFAULT_SLOW_CALL, Duration::from_secs_f64)
+    }
+}
+
+/// The merged settings of every config file that was read, and where each came from
+#[derive(Debug, Default)]
+pub struct Layered {
+    pub config: Config,
+    pub table: Table,
+    /// The file that set each dotted key, e.g. "notify.threshold"
+    pub sources: BTreeMap<String, PathBuf>,
+}
+
+impl Config {
+    /// The Ollama servers to use: the configured ones, or else the one from the environment
+    pub fn endpoints(&self) -> Result<Vec<Endpoint>> {
+        if self.endpoints.is_empty() {
+            Ok(vec![Endpoint::from_env()?])
+        } else {
+            Ok(self.endpoints.clone())
+        }
+    }
+
+    /// Load the global config file and the project's `.sentinel.toml`, or
+    /// the defaults if there are none
+    pub fn load() -> Result<Self> {
+        Ok(Self::load_layered()?.config)
+    }
+
+    /// Like `load`, keeping track of which file set each key
+    pub fn load_layered() -> Result<Layered> {
+        let cwd = std::env::current_dir().context("Failed to get current directory")?;
+        let mut files: Vec<PathBuf> = default_path()
+            .into_iter()
+            .filter(|path| path.exists())
+            .collect();
+        files.extend(find_project_file(&cwd));
+        Layered::from_files(&files)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self> {
+        Ok(Layered::from_files(&[path.to_path_buf()])?.config)
+    }
+}
+
+impl Layered {
+    /// Merge config files in order, later files winning key by key
+    pub fn from_files(files: &[PathBuf]) -> Result<Self> {
+        let mut layered = Self::default();
+        let mut warnings = Vec::new();
+
+        for path in files {
+            let text = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let mut table: Table = toml::from_str(&text)
+                .with_context(|| format!("Invalid config in {}", path.display()))?;
+            if let Some(dir) = path.parent() {
+                resolve_paths(&mut table, dir);
+            }
+
+            // Check each file on its own so errors and warnings can name it
+            let mut unknown = Vec::new();
+            let checked: Config = serde_ignored::deserialize(Value::Table(table.clone()), |key| {
+                unknown.push(key.to_string())
+            })
+            .with_context(|| format!("Invalid config in {}", path.display()))?;
+            // A custom tool the model couldn't call is left out, not the session
+            for tool in &checked.tools.custom {
+                if let Err(e) = tool.check() {
+                    warnings.push(format!(
+                        "Invalid custom tool '{}' in {} is left out: {}",
+                        tool.name,
+                        path.display(),
+                        e
+                    ));
+                }
+            }
+            warnings.extend(
+                unknown
+                    .into_iter()
+                    .map(|key| format!("Unknown key '{}' in {}", key, path.display())),
+            );
+
+            merge(&mut layered.table, table, path, "", &mut layered.sources);
+        }
+
+        layered.config =
+            Config::deserialize(Value::Table(layered.table.clone())).context("Invalid config")?;
+        layered.config.warnings = warnings;
+        Ok(layered)
+    }
+
+    /// Every setting as `key = value`, with the file it came from
+    pub fn describe(&self) -> Vec<(String, String, &Path)> {
+        self.sources
+            .iter()
+            .filter_map(|(key, path)| {
+                let value = lookup(&self.table, key)?;
+                Some((key.clone(), value.to_string(), path.as_path()))
+            })
+            .collect()
+    }
+}
+
+/// The closest `.sentinel.toml` in `start` or one of its parents
+pub fn find_project_file(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+// Make relative paths in `table` relative to `dir` instead
+fn resolve_paths(table: &mut Table, dir: &Path) {
+    for key in PATH_KEYS {
+        if let Some(Value::String(path)) = table.get_mut(key) {
+            if Path::new(path.as_str()).is_relative() {
+                *path = dir.join(path.as_str()).to_string_lossy().to_string();
+            }
+        }
+    }
+}
+
+// Merge `source` (read from `path`) into `target`, recording where each leaf came from
+fn merge(
+    target: &mut Table,
+    source: Table,
+    path: &Path,
+    prefix: &str,
+    sources: &mut BTreeMap<String, PathBuf>,
+) {
+    for (key, value) in source {
+        let dotted = format!("{}{}", prefix, key);
+        match (target.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(table)) => {
+                merge(existing, table, path, &format!("{}.", dotted), sources);
+            }
+            (_, value) => {
+                sources
+                    .retain(|key, _| key != &dotted && !key.starts_with(&format!("{}.", dotted)));
+                record_leaves(&value, &dotted, path, sources);
+                target.insert(key, value);
+            }
+        }
+    }
+}
+
+fn record_leaves(
+    value: &Value,
+    dotted: &str,
+    path: &Path,
+    sources: &mut BTreeMap<String, PathBuf>,
+) {
+    match value {
+        Value::Table(table) => {
+            for (key, value) in table {
+                record_leaves(value, &format!("{}.{}", dotted, key), path, sources);
+            }
+        }
+        _ => {
+            sources.insert(dotted.to_string(), path.to_path_buf());
+        }
+    }
+}
+
+// The value at a dotted key
+fn lookup<'a>(table: &'a Table, dotted: &str) -> Option<&'a Value> {
+    let (first, rest) = match dotted.split_once('.') {
+        Some((first, rest)) => (first, Some(rest)),
+        None => (dotted, None),
+    };
+    match (table.get(first)?, rest) {
+        (Value::Table(nested), Some(rest)) => lookup(nested, rest),
+        (value, None) => Some(value),
+        _ => None,
+    }
+}
+
+/// The config file a setting changed at runtime is saved to: the one that
+/// set `key` last, or else the global config file
+pub fn file_for(key: &str) -> Option<PathBuf> {
+    Config::load_layered()
+        .ok()
+        .and_then(|layered| layered.sources.get(key).cloned())
+        .or_else(default_path)
+}
+
+/// Set the top-level `key` to the string `value` in the config file at
+/// `path`, creating the file if need be and keeping the rest of it as written
+pub fn save_setting(path: &Path, key: &str, value: &str) -> Result<()> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let mut document: toml_edit::DocumentMut = text
+        .parse()
+        .with_context(|| format!("Invalid config in {}", path.display()))?;
+    document[key] = toml_edit::value(value);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(path, document.to_string())
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// `$XDG_CONFIG_HOME/sentinel/config.toml`, or `~/.config/sentinel/config.toml`
+pub fn default_path() -> Option<PathBuf> {
+    Some(directory()?.join("config.toml"))
+}
+
+/// `$XDG_CONFIG_HOME/sentinel`, or `~/.config/sentinel`
+pub fn directory() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_home.join("sentinel"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    // How a keep-alive setting is sent to Ollama
+    fn json(keep_alive: KeepAlive) -> serde_json::Value {
+        serde_json::to_value(keep_alive).unwrap()
+    }
+
+    #[test]
+    fn test_load_keys() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "[keys]\nquit = \"ctrl-q\"\n")?;
+
+        let config = Config::load_from(&path)?;
+        assert_eq!(config.keys["quit"], "ctrl-q");
+
+        fs::write(&path, "[keys\n")?;
+        let error = Config::load_from(&path).unwrap_err();
+        assert!(error.to_string().starts_with("Invalid config in"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_flags_override_config() -> Result<()> {
+        let config: ModelConfig = toml::from_str("name = \"qwen2.5\"\nkeep_alive = \"10m\"")?;
+        assert_eq!(config.name(None), "qwen2.5");
+        assert_eq!(config.name(Some("phi3")), "phi3");
+        assert_eq!(ModelConfig::default().name(None), DEFAULT_MODEL);
+        assert!(config.warm_up);
+        assert!(config.auto_title);
+        let quiet: ModelConfig = toml::from_str("auto_title = false")?;
+        assert!(!quiet.auto_title);
+        let capped: ModelConfig = toml::from_str("max_tokens = 200\nstop = [\"```\", \"END\"]")?;
+        assert_eq!(capped.max_tokens, Some(200));
+        assert_eq!(capped.stop, ["```", "END"]);
+        assert_eq!(json(config.keep_alive(None)?.unwrap()), "10m");
+        assert_eq!(json(config.keep_alive(Some("-1"))?.unwrap()), -1);
+        assert!(ModelConfig::default().keep_alive(None)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_file_is_found_in_parents() -> Result<()> {
+        let dir = tempdir()?;
+        let nested = dir.path().join("crates/core/src");
+        fs::create_dir_all(&nested)?;
+        assert_eq!(find_project_file(&nested), None);
+
+        fs::write(dir.path().join(PROJECT_FILE), "")?;
+        assert_eq!(
+            find_project_file(&nested),
+            Some(dir.path().join(PROJECT_FILE))
+        );
+
+        // The closest file wins
+        fs::write(dir.path().join("crates").join(PROJECT_FILE), "")?;
+        assert_eq!(
+            find_project_file(&nested),
+            Some(dir.path().join("crates").join(PROJECT_FILE))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_project_values_win() -> Result<()> {
+        let dir = tempdir()?;
+        let global = dir.path().join("config.toml");
+        let project = dir.path().join(PROJECT_FILE);
+        fs::write(
+            &global,
+            "system_prompt = \"Be brief\"\n[notify]\nenabled = true\nthreshold = 5\n[model]\nname = \"llama3.2\"\n",
+        )?;
+        fs::write(
+            &project,
+            "[notify]\nthreshold = 30\n[model]\nname = \"qwen2.5-coder\"\n[tools]\nenabled = [\"file\", \"ls\"]\n",
+        )?;
+
+        let layered = Layered::from_files(&[global.clone(), project.clone()])?;
+        let config = &layered.config;
+        assert_eq!(config.model.name.as_deref(), Some("qwen2.5-coder"));
+        assert_eq!(config.system_prompt.as_deref(), Some("Be brief"));
+        assert!(config.notify.enabled);
+        assert_eq!(config.notify.threshold, 30);
+        assert!(config.tools.allows("file") && !config.tools.allows("bash"));
+
+        assert_eq!(layered.sources["notify.enabled"], global);
+        assert_eq!(layered.sources["notify.threshold"], project);
+        let described = layered.describe();
+        assert!(described.contains(&(
+            "model.name".to_string(),
+            "\"qwen2.5-coder\"".to_string(),
+            project.as_path()
+        )));
+        Ok(())
+    }
+
+    #[test]
+    fn test_relative_paths_and_unknown_keys() -> Result<()> {
+        let dir = tempdir()?;
+        let project = dir.path().join(PROJECT_FILE);
+        fs::write(&project, "sandbox_root = \"src\"\nmodle = \"typo\"\n")?;
+
+        let config = Config::load_from(&project)?;
+        assert_eq!(config.sandbox_root, Some(dir.path().join("src")));
+        assert_eq!(
+            config.warnings,
+            [format!("Unknown key 'modle' in {}", project.display())]
+        );
+
+        fs::write(&project, "sandbox_root = \"/srv/app\"\n")?;
+        let config = Config::load_from(&project)?;
+        assert_eq!(config.sandbox_root, Some(PathBuf::from("/srv/app")));
+        Ok(())
+    }
+    #[test]
+    fn test_saved_settings_keep_the_file_as_written() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("sentinel/config.toml");
+        save_setting(&path, "layout", "zen")?;
+        assert_eq!(Config::load_from(&path)?.layout, LayoutMode::Zen);
+
+        let text = "# My settings\nword_counts = true # for prose\n\n[model]\nname = \"qwen2.5\"\n";
+        fs::write(&path, text)?;
+        save_setting(&path, "layout", "zen")?;
+        save_setting(&path, "layout", "full")?;
+        let saved = fs::read_to_string(&path)?;
+        assert!(
+            saved.starts_with("# My settings\nword_counts = true # for prose\nlayout = \"full\"\n")
+        );
+        assert!(saved.ends_with("[model]\nname = \"qwen2.5\"\n"));
+        let config = Config::load_from(&path)?;
+        assert_eq!(config.layout, LayoutMode::Full);
+        assert_eq!(config.model.name.as_deref(), Some("qwen2.5"));
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+//! The terminal as the console of library code that needs the user.
+//!
+//! Library modules never print: the setup wizard and the check that the
+//! model is installed take a console instead, and the frontends hand them
+//! [`Terminal`] before anything else is on the screen. An embedder passes
+//! its own, or one that stays quiet.
+
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use crate::llm::availability::{PullConsole, PullProgress};
+use crate::style;
+use crate::wizard::Console;
+
+/// Questions and answers on stdin, lines for people where [`style`] sends them
+#[derive(Debug, Default)]
+pub struct Terminal {
+    // What a download showed last as a plain line, when it can't be redrawn
+    printed: Option<String>,
+}
+
+impl Terminal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// The line to print for `progress` when it can't be redrawn in place: one
+/// per status, and one every ten percent, or None if `printed` covers it
+pub fn plain_progress(printed: &Option<String>, progress: &PullProgress) -> Option<String> {
+    let line = match progress.percent() {
+        Some(percent) => format!("{}: {}%", progress.status, (percent / 10.0).floor() * 10.0),
+        None => progress.status.clone(),
+    };
+    (printed.as_ref() != Some(&line)).then_some(line)
+}
+
+impl Console for Terminal {
+    fn say(&mut self, text: &str) {
+        println!("{}", text);
+    }
+
+    fn ask(&mut self, question: &str) -> Option<String> {
+        print!("\n{}", style::paint(style::BRIGHT_GREEN, question));
+        io::stdout().flush().ok()?;
+        let mut answer = String::new();
+        match io::stdin().lock().read_line(&mut answer) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(answer.trim().to_string()),
+        }
+    }
+}
+
+impl PullConsole for Terminal {
+    fn warn(&mut self, text: &str) {
+        style::print_line(style::YELLOW, text);
+    }
+
+    // Only asked at a terminal; anything else can't answer
+    fn confirm_pull(&mut self, model: &str) -> bool {
+        if !io::stdin().is_terminal() {
+            return false;
+        }
+        let mut output = style::human_output();
+        let _ = write!(output, "Pull '{}' now? [y/N] ", model);
+        let _ = output.flush();
+
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer).is_err() {
+            return false;
+        }
+        matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+    }
+
+    // Redraw a single progress line on a colored terminal; elsewhere, e.g.
+    // piped, print a plain line now and then
+    fn progress(&mut self, progress: &PullProgress) {
+        let mut output = style::human_output();
+        if style::enabled() && style::human_output_is_terminal() {
+            let _ = write!(output, "\r\x1b[2K{}", progress);
+            let _ = output.flush();
+        } else if let Some(line) = plain_progress(&self.printed, progress) {
+            let _ = writeln!(output, "{}", line);
+            self.printed = Some(line);
+        }
+    }
+
+    fn pulled(&mut self, model: &str) {
+        // End the redrawn line
+        if self.printed.take().is_none() {
+            let _ = writeln!(style::human_output());
+        }
+        style::print_line(style::BRIGHT_GREEN, &format!("Pulled '{}'", model));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(status: &str, completed: u64) -> PullProgress {
+        PullProgress {
+            status: status.to_string(),
+            completed: Some(completed),
+            total: Some(200),
+        }
+    }
+
+    #[test]
+    fn test_plain_progress_prints_each_status_and_tenth_once() {
+        let mut printed = None;
+        let mut lines = Vec::new();
+        for update in [
+            progress("pulling manifest", 0),
+            progress("pulling abc", 5),
+            progress("pulling abc", 15),
+            progress("pulling abc", 25),
+            progress("pulling abc", 200),
+            progress("verifying", 200),
+        ] {
+            if let Some(line) = plain_progress(&printed, &update) {
+                lines.push(line.clone());
+                printed = Some(line);
+            }
+        }
+        assert_eq!(
+            lines,
+            [
+                "pulling manifest: 0%",
+                "pulling abc: 0%",
+                "pulling abc: 10%",
+                "pulling abc: 100%",
+                "verifying: 100%",
+            ]
+        );
+        for line in &lines {
+            assert!(!line.contains('\x1b'));
+        }
+    }
+}
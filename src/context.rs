@@ -0,0 +1,454 @@
+//! Fitting a conversation into the model's context window.
+//!
+//! The window is as large as the model supports, as its metadata tells,
+//! within `model.max_num_ctx`; a `model.num_ctx` from the config is used
+//! instead unless the model can't take it. When the messages would not fit,
+//! the oldest are left out of the request; the conversation itself keeps
+//! them. System messages, pinned messages and the message being answered are
+//! always sent word for word, and each run of messages left out is replaced by
+//! a note saying what they were about. Messages are left out several at a
+//! time, so that most turns send the start of the request exactly as before.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde_json::Value;
+
+use crate::config::ModelConfig;
+use crate::llm::ollama::LlmClient;
+use crate::session;
+use crate::stats::estimate_tokens;
+use crate::{Message, Role};
+
+/// The context window requests are sent with when the model doesn't say
+/// what it supports, in tokens
+pub const CONTEXT_TOKENS: usize = 16384;
+
+/// The largest window requested unless the config sets `model.max_num_ctx`;
+/// a larger one takes more memory than most machines want to give
+pub const DEFAULT_MAX_CONTEXT: usize = 32768;
+
+/// Room kept in the window for the reply
+pub const REPLY_TOKENS: usize = 2048;
+
+/// The tokens the messages of a request may take
+pub const DEFAULT_BUDGET: usize = CONTEXT_TOKENS - REPLY_TOKENS;
+
+// Room kept for each note standing in for messages left out
+const NOTE_TOKENS: usize = 80;
+
+// How many of the questions left out a note repeats, the latest ones
+const NOTE_QUESTIONS: usize = 3;
+
+// Messages are left out this many at a time, so the cut stays put for a few
+// turns and Ollama can reuse the start of the request it evaluated before
+const TRIM_STEP: usize = 8;
+
+/// The context window of a model, in tokens, and the budget of its messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    pub tokens: usize,
+}
+
+impl Window {
+    /// The tokens the messages of a request may take, keeping room for the reply
+    pub fn budget(&self) -> usize {
+        self.tokens - REPLY_TOKENS.min(self.tokens / 4)
+    }
+}
+
+impl Default for Window {
+    fn default() -> Self {
+        Self {
+            tokens: CONTEXT_TOKENS,
+        }
+    }
+}
+
+/// The context window a model is sent with: the configured `num_ctx`, or
+/// what the model `supports` within `max`. A configured window the model
+/// can't take is cut down to what it supports, with a warning
+pub fn choose(
+    model: &str,
+    supported: Option<usize>,
+    configured: Option<usize>,
+    max: usize,
+) -> (Window, Option<String>) {
+    match (configured, supported) {
+        (Some(configured), Some(supported)) if configured > supported => (
+            Window { tokens: supported },
+            Some(format!(
+                "model.num_ctx is {} but {} supports at most {} tokens of context; using {}",
+                configured, model, supported, supported
+            )),
+        ),
+        (Some(configured), _) => (Window { tokens: configured }, None),
+        (None, Some(supported)) => (
+            Window {
+                tokens: supported.min(max),
+            },
+            None,
+        ),
+        (None, None) => (
+            Window {
+                tokens: CONTEXT_TOKENS.min(max),
+            },
+            None,
+        ),
+    }
+}
+
+/// The context length the /api/show metadata of a model gives, or None if
+/// it doesn't tell
+pub fn length_from_metadata(show: &Value) -> Option<usize> {
+    let info = show.get("model_info")?.as_object()?;
+    let key = match info.get("general.architecture").and_then(Value::as_str) {
+        Some(architecture) => format!("{}.context_length", architecture),
+        None => info
+            .keys()
+            .find(|key| key.ends_with(".context_length"))?
+            .clone(),
+    };
+    info.get(&key)?.as_u64().map(|tokens| tokens as usize)
+}
+
+/// The context windows of the models used in one session, each looked up
+/// the first time the model is used
+#[derive(Debug)]
+pub struct ContextWindows {
+    known: Mutex<HashMap<String, Window>>,
+    configured: Option<usize>,
+    max: usize,
+}
+
+impl Default for ContextWindows {
+    fn default() -> Self {
+        Self {
+            known: Mutex::default(),
+            configured: None,
+            max: DEFAULT_MAX_CONTEXT,
+        }
+    }
+}
+
+impl ContextWindows {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use the `num_ctx` and `max_num_ctx` of a config, looking the models up again
+    pub fn configure(&mut self, config: &ModelConfig) {
+        self.configured = config.num_ctx;
+        self.max = config.max_num_ctx.unwrap_or(DEFAULT_MAX_CONTEXT);
+        self.known.lock().unwrap().clear();
+    }
+
+    /// The window of `model`, asking `client` what it supports the first time.
+    /// Whatever is worth a warning is only returned that first time
+    pub async fn window(&self, client: &dyn LlmClient, model: &str) -> (Window, Option<String>) {
+        if let Some(window) = self.known(model) {
+            return (window, None);
+        }
+        let supported = client.context_length().await;
+        let (window, warning) = choose(model, supported, self.configured, self.max);
+        self.known.lock().unwrap().insert(model.to_string(), window);
+        (window, warning)
+    }
+
+    /// The window `model` was found to have, if it was used yet
+    pub fn known(&self, model: &str) -> Option<Window> {
+        self.known.lock().unwrap().get(model).copied()
+    }
+}
+
+/// The messages to send, and how many were left out
+#[derive(Debug, Clone)]
+pub struct Fitted {
+    pub messages: Vec<Message>,
+    pub trimmed: usize,
+}
+
+/// The messages that fit in `budget` tokens: the ones that must be kept,
+/// then as many of the most recent others as fit
+pub fn fit(messages: &[Message], budget: usize) -> Fitted {
+    let total: usize = messages.iter().map(tokens).sum();
+    if total <= budget {
+        return Fitted {
+            messages: messages.to_vec(),
+            trimmed: 0,
+        };
+    }
+
+    let last = messages.len().saturating_sub(1);
+    let mut keep: Vec<bool> = messages
+        .iter()
+        .enumerate()
+        .map(|(index, message)| is_kept(message) || index == last)
+        .collect();
+    let mut used: usize = messages
+        .iter()
+        .zip(&keep)
+        .filter(|(_, kept)| **kept)
+        .map(|(message, _)| tokens(message))
+        .sum();
+    // There are at most as many gaps to note as messages that must be kept
+    used += NOTE_TOKENS * keep.iter().filter(|kept| **kept).count();
+
+    // Newest first, stopping at the first that doesn't fit so nothing older
+    // is sent without what came after it
+    for (index, message) in messages.iter().enumerate().rev() {
+        if keep[index] {
+            continue;
+        }
+        if used + tokens(message) > budget {
+            break;
+        }
+        used += tokens(message);
+        keep[index] = true;
+    }
+    let mut trimmed = keep.iter().filter(|kept| !**kept).count();
+    for (index, message) in messages.iter().enumerate() {
+        if trimmed % TRIM_STEP == 0 {
+            break;
+        }
+        if keep[index] && !is_kept(message) && index != last {
+            keep[index] = false;
+            trimmed += 1;
+        }
+    }
+
+    let mut fitted = Vec::new();
+    let mut left_out: Vec<&Message> = Vec::new();
+    for (message, kept) in messages.iter().zip(&keep) {
+        if !kept {
+            left_out.push(message);
+            continue;
+        }
+        if !left_out.is_empty() {
+            fitted.push(Message::system(note(&left_out)));
+            left_out.clear();
+        }
+        fitted.push(message.clone());
+    }
+
+    Fitted {
+        trimmed,
+        messages: fitted,
+    }
+}
+
+// Messages sent whatever the budget
+fn is_kept(message: &Message) -> bool {
+    message.pinned || message.role == Role::System
+}
+
+fn tokens(message: &Message) -> usize {
+    estimate_tokens(&message.content)
+}
+
+// What stands in for a run of messages left out: how many, and the last
+// things the user asked in them
+fn note(left_out: &[&Message]) -> String {
+    let questions: Vec<&Message> = left_out
+        .iter()
+        .copied()
+        .filter(|message| message.role == Role::User)
+        .collect();
+    let asked: Vec<String> = questions[questions.len().saturating_sub(NOTE_QUESTIONS)..]
+        .iter()
+        .map(|message| format!("\"{}\"", session::preview(&message.content)))
+        .collect();
+    let count = match left_out.len() {
+        1 => "1 earlier message".to_string(),
+        count => format!("{} earlier messages", count),
+    };
+    if asked.is_empty() {
+        format!("[{} left out to fit the context window]", count)
+    } else {
+        format!(
+            "[{} left out to fit the context window. The user had asked: {}]",
+            count,
+            asked.join("; ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::mock::MockLlmClient;
+
+    // A scripted conversation: a system prompt, then `turns` questions and
+    // answers of about 100 tokens each
+    fn conversation(turns: usize) -> Vec<Message> {
+        let mut messages = vec![Message::system("You are a helpful assistant.")];
+        for turn in 0..turns {
+            messages.push(Message::user(format!(
+                "Question {}: {}",
+                turn,
+                "q".repeat(400)
+            )));
+            messages.push(Message::assistant(
+                format!("Answer {}: {}", turn, "a".repeat(400)),
+                0,
+                0,
+                Vec::new(),
+            ));
+        }
+        messages
+    }
+
+    #[test]
+    fn test_conversations_that_fit_are_sent_whole() {
+        let messages = conversation(3);
+        let fitted = fit(&messages, DEFAULT_BUDGET);
+        assert_eq!(fitted.trimmed, 0);
+        assert_eq!(fitted.messages.len(), messages.len());
+    }
+
+    #[test]
+    fn test_pinned_messages_survive_trimming_verbatim() {
+        let mut messages = conversation(40);
+        let decision = "We agreed to keep the public API in src/lib.rs stable until 2.0.";
+        messages[3].content = decision.to_string();
+        messages[3].pinned = true;
+        messages.push(Message::user("What did we agree about the API?"));
+
+        let fitted = fit(&messages, 1000);
+        assert!(fitted.trimmed > 0);
+        let total: usize = fitted.messages.iter().map(tokens).sum();
+        assert!(total <= 1000, "{} tokens sent", total);
+        assert!(fitted
+            .messages
+            .iter()
+            .all(|message| tokens(message) <= NOTE_TOKENS || !message.content.starts_with('[')));
+
+        let contents: Vec<&str> = fitted.messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents[0], "You are a helpful assistant.");
+        assert!(contents[1].starts_with("[2 earlier messages left out"));
+        assert!(contents[1].contains("\"Question 0: qqq"));
+        assert_eq!(contents[2], decision);
+        assert!(fitted.messages[2].pinned);
+        assert!(contents[3].starts_with("[") && contents[3].contains("left out"));
+        assert_eq!(contents.last(), Some(&"What did we agree about the API?"));
+
+        // The most recent messages are the ones kept
+        assert!(contents[contents.len() - 2].starts_with("Answer 39: "));
+        assert_eq!(fitted.trimmed + fitted.messages.len() - 2, messages.len());
+    }
+
+    #[test]
+    fn test_the_cut_stays_put_for_a_few_turns() {
+        let mut messages = conversation(30);
+        let mut previous: Option<Vec<String>> = None;
+        let mut moved = 0;
+        for turn in 30..46 {
+            messages.push(Message::user(format!(
+                "Question {}: {}",
+                turn,
+                "q".repeat(400)
+            )));
+            let fitted = fit(&messages, 2000);
+            assert_eq!(fitted.trimmed % TRIM_STEP, 0);
+            let sent: Vec<String> = fitted.messages.iter().map(|m| m.content.clone()).collect();
+            if let Some(previous) = &previous {
+                // Everything sent before is sent again, then the answer and the next question
+                if !sent.starts_with(previous) {
+                    moved += 1;
+                }
+            }
+            previous = Some(sent);
+            messages.push(Message::assistant(
+                format!("Answer {}: {}", turn, "a".repeat(400)),
+                0,
+                0,
+                Vec::new(),
+            ));
+        }
+        assert!(moved <= 4, "the cut moved {} times in 15 turns", moved);
+    }
+
+    #[test]
+    fn test_kept_messages_are_sent_even_over_budget() {
+        let mut messages = conversation(2);
+        messages[1].pinned = true;
+        let fitted = fit(&messages, 10);
+        let contents: Vec<&str> = fitted.messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents.len(), 4);
+        assert!(contents[1].starts_with("Question 0: "));
+        assert_eq!(
+            contents[2],
+            format!(
+                "[2 earlier messages left out to fit the context window. The user had asked: \"Question 1: {}...\"]",
+                "q".repeat(48)
+            )
+        );
+        assert!(contents[3].starts_with("Answer 1: "));
+    }
+
+    #[test]
+    fn test_window_follows_the_model_within_the_limits() {
+        // As large as the model supports, up to the maximum
+        assert_eq!(
+            choose("a", Some(8192), None, 32768),
+            (Window { tokens: 8192 }, None)
+        );
+        assert_eq!(
+            choose("a", Some(131072), None, 32768).0,
+            Window { tokens: 32768 }
+        );
+        assert_eq!(choose("a", None, None, 32768).0, Window::default());
+
+        // A configured window wins unless the model can't take it
+        assert_eq!(
+            choose("a", Some(131072), Some(65536), 32768),
+            (Window { tokens: 65536 }, None)
+        );
+        let (window, warning) = choose("phi3", Some(4096), Some(16384), 32768);
+        assert_eq!(window.tokens, 4096);
+        assert_eq!(
+            warning.as_deref(),
+            Some("model.num_ctx is 16384 but phi3 supports at most 4096 tokens of context; using 4096")
+        );
+
+        assert_eq!(Window::default().budget(), DEFAULT_BUDGET);
+        assert_eq!(Window { tokens: 2048 }.budget(), 1536);
+    }
+
+    #[test]
+    fn test_length_from_metadata() {
+        let show = serde_json::json!({
+            "model_info": {
+                "general.architecture": "llama",
+                "llama.context_length": 131072,
+                "llama.embedding_length": 3072
+            }
+        });
+        assert_eq!(length_from_metadata(&show), Some(131072));
+        let unnamed = serde_json::json!({ "model_info": { "qwen2.context_length": 32768 } });
+        assert_eq!(length_from_metadata(&unnamed), Some(32768));
+        assert_eq!(length_from_metadata(&serde_json::json!({})), None);
+    }
+
+    #[tokio::test]
+    async fn test_windows_are_looked_up_once_per_model() {
+        let client = MockLlmClient::new().with_context_length(4096);
+        let mut windows = ContextWindows::new();
+        windows.configure(&ModelConfig {
+            num_ctx: Some(8192),
+            ..ModelConfig::default()
+        });
+
+        let (window, warning) = windows.window(&client, "phi3").await;
+        assert_eq!(window.tokens, 4096);
+        assert!(warning.is_some());
+        // The warning is given once, and the model isn't asked again
+        assert_eq!(windows.window(&client, "phi3").await, (window, None));
+        assert_eq!(client.context_lookups(), 1);
+
+        // Another model is asked about in turn
+        windows.window(&client, "llama3.2").await;
+        assert_eq!(client.context_lookups(), 2);
+        assert_eq!(windows.known("llama3.2"), Some(window));
+    }
+}
@@ -0,0 +1,179 @@
+//! Why a request to the model failed, and how `sentinel ask` exits because of it.
+//!
+//! Scripts tell the failures apart by the exit status of `ask`, or by the
+//! `error_kind` and `exit_code` of `ask --json`. The REPL and TUI show the
+//! same wording, so an error reads alike wherever it happens.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::llm::availability;
+use crate::llm::deadline;
+use crate::llm::failover;
+
+/// What kind of failure a request ran into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// Bad arguments, configuration or attachments; nothing was sent
+    Usage,
+    /// No Ollama server could be reached
+    Unreachable,
+    /// The model is not installed on the server
+    ModelNotFound,
+    /// The response ran out of time
+    Timeout,
+    /// The server was reached but failed to answer
+    Generation,
+}
+
+impl FailureKind {
+    /// The kind of `error`, found from the errors in its chain
+    pub fn of(error: &anyhow::Error) -> Self {
+        if let Some(failure) = error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<Failure>())
+        {
+            return failure.kind;
+        }
+        if deadline::is_timeout(error) {
+            Self::Timeout
+        } else if failover::is_connection_error(error)
+            || error
+                .chain()
+                .any(|cause| cause.is::<failover::NoneReachable>())
+        {
+            Self::Unreachable
+        } else if is_missing_model(error) {
+            Self::ModelNotFound
+        } else {
+            Self::Generation
+        }
+    }
+
+    /// The exit status of `sentinel ask` for this kind of failure
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Usage => 2,
+            Self::Unreachable => 3,
+            Self::ModelNotFound => 4,
+            Self::Timeout => 5,
+            Self::Generation => 6,
+        }
+    }
+}
+
+// Whether `error` says the model isn't there, either from the installed
+// models or from the server answering a request for it
+fn is_missing_model(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if cause.is::<availability::NotInstalled>() {
+            return true;
+        }
+        let text = cause.to_string().to_lowercase();
+        text.contains("model") && text.contains("not found")
+    })
+}
+
+/// A failed request, with the error that caused it
+#[derive(Debug)]
+pub struct Failure {
+    pub kind: FailureKind,
+    error: anyhow::Error,
+}
+
+impl Failure {
+    /// A failure of the arguments or configuration, whatever the error says
+    pub fn usage(error: impl Into<anyhow::Error>) -> Self {
+        Self {
+            kind: FailureKind::Usage,
+            error: error.into(),
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        self.kind.exit_code()
+    }
+}
+
+impl From<anyhow::Error> for Failure {
+    fn from(error: anyhow::Error) -> Self {
+        Self {
+            kind: FailureKind::of(&error),
+            error,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            FailureKind::Unreachable => write!(
+                f,
+                "Could not reach Ollama: {:#}. Start it with `ollama serve`, or point OLLAMA_HOST at another server",
+                self.error
+            ),
+            FailureKind::Generation => write!(f, "The model failed to respond: {:#}", self.error),
+            FailureKind::Usage | FailureKind::ModelNotFound | FailureKind::Timeout => {
+                write!(f, "{:#}", self.error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::deadline::TimedOut;
+    use anyhow::{anyhow, Context};
+    use std::io;
+    use std::time::Duration;
+
+    #[test]
+    fn test_errors_are_classified() {
+        let refused = anyhow::Error::new(io::Error::from(io::ErrorKind::ConnectionRefused))
+            .context("Failed to generate a response");
+        assert_eq!(FailureKind::of(&refused), FailureKind::Unreachable);
+
+        let timed_out = anyhow::Error::new(TimedOut {
+            after: Duration::from_secs(1),
+        });
+        assert_eq!(FailureKind::of(&timed_out), FailureKind::Timeout);
+
+        let missing = anyhow!("model \"llama9\" not found, try pulling it first");
+        assert_eq!(FailureKind::of(&missing), FailureKind::ModelNotFound);
+
+        let failed = anyhow!("the server had an error");
+        assert_eq!(FailureKind::of(&failed), FailureKind::Generation);
+
+        // A failure wrapped in context keeps its kind
+        let usage = Err::<(), _>(Failure::usage(anyhow!("No such file: notes.md")))
+            .context("Could not attach the files")
+            .unwrap_err();
+        assert_eq!(FailureKind::of(&usage), FailureKind::Usage);
+    }
+
+    #[test]
+    fn test_failures_are_worded_by_kind() {
+        let failure = Failure::from(anyhow!("the server had an error"));
+        assert_eq!(failure.exit_code(), 6);
+        assert_eq!(
+            failure.to_string(),
+            "The model failed to respond: the server had an error"
+        );
+
+        let failure = Failure::from(anyhow::Error::new(TimedOut {
+            after: Duration::from_secs(1),
+        }));
+        assert_eq!(failure.exit_code(), 5);
+        assert_eq!(failure.to_string(), "Generation timed out after 1s");
+
+        let refused = anyhow::Error::new(io::Error::from(io::ErrorKind::ConnectionRefused));
+        assert!(Failure::from(refused).to_string().starts_with(
+            "Could not reach Ollama: connection refused. Start it with `ollama serve`"
+        ));
+    }
+}
@@ -0,0 +1,349 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+// Entries kept when SENTINEL_HISTORY_SIZE is not set
+const DEFAULT_CAPACITY: usize = 1000;
+
+/// Prompts entered in previous and current sessions, oldest first.
+///
+/// The history file holds one entry per line; backslashes and newlines inside
+/// an entry are escaped so multi-line prompts survive the round trip.
+#[derive(Debug, Clone, Default)]
+pub struct InputHistory {
+    entries: Vec<String>,
+    capacity: usize,
+    path: Option<PathBuf>,
+}
+
+impl InputHistory {
+    /// An empty history that is never written to disk
+    pub fn in_memory(capacity: usize) -> Self {
+        Self {
+            entries: Vec::new(),
+            capacity,
+            path: None,
+        }
+    }
+
+    /// Load the history from `path`, starting empty if the file does not exist
+    pub fn load(path: impl Into<PathBuf>, capacity: usize) -> Result<Self> {
+        let path = path.into();
+        let mut history = Self {
+            path: Some(path.clone()),
+            ..Self::in_memory(capacity)
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                for line in contents.lines() {
+                    history.push(unescape(line));
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+
+        Ok(history)
+    }
+
+    /// Load the history from its default location, falling back to an
+    /// in-memory history if it can't be read
+    pub fn load_default() -> Self {
+        let capacity = std::env::var("SENTINEL_HISTORY_SIZE")
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(DEFAULT_CAPACITY);
+
+        default_path()
+            .and_then(|path| Self::load(path, capacity).ok())
+            .unwrap_or_else(|| Self::in_memory(capacity))
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(String::as_str)
+    }
+
+    /// Add an entry, skipping blanks and repeats of the previous entry
+    pub fn push(&mut self, entry: impl Into<String>) {
+        let entry = entry.into();
+        if entry.trim().is_empty() || self.entries.last() == Some(&entry) {
+            return;
+        }
+
+        self.entries.push(entry);
+        if self.entries.len() > self.capacity {
+            let excess = self.entries.len() - self.capacity;
+            self.entries.drain(..excess);
+        }
+    }
+
+    /// Index of the newest entry before `before` that contains `query`
+    pub fn find_before(&self, query: &str, before: usize) -> Option<usize> {
+        self.entries[..before.min(self.entries.len())]
+            .iter()
+            .rposition(|entry| entry.contains(query))
+    }
+
+    /// Write the history back to its file, if it has one
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        let mut contents = String::new();
+        for entry in &self.entries {
+            contents.push_str(&escape(entry));
+            contents.push('\n');
+        }
+
+        fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+}
+
+/// Where Up and Down have moved to in an [`InputHistory`].
+///
+/// Browsing starts at the newest entry and keeps what was being typed, so
+/// moving back down past the newest entry brings the draft back. Neither end
+/// wraps, and Down without browsing leaves the input alone.
+#[derive(Debug, Clone, Default)]
+pub struct HistoryCursor {
+    index: Option<usize>,
+    draft: String,
+}
+
+impl HistoryCursor {
+    /// The entry before the current one, starting from the newest; None when
+    /// there is nothing older
+    pub fn previous(&mut self, history: &InputHistory, input: &str) -> Option<String> {
+        let index = match self.index {
+            None if history.is_empty() => return None,
+            None => {
+                self.draft = input.to_string();
+                history.len() - 1
+            }
+            Some(0) => return None,
+            Some(index) => index.min(history.len()) - 1,
+        };
+        self.index = Some(index);
+        history.get(index).map(str::to_string)
+    }
+
+    /// The entry after the current one, or the draft after the newest; None
+    /// when not browsing
+    pub fn next(&mut self, history: &InputHistory) -> Option<String> {
+        let index = self.index? + 1;
+        if index < history.len() {
+            self.index = Some(index);
+            return history.get(index).map(str::to_string);
+        }
+        self.index = None;
+        Some(std::mem::take(&mut self.draft))
+    }
+
+    pub fn is_browsing(&self) -> bool {
+        self.index.is_some()
+    }
+
+    /// Stop browsing, e.g. when the input is submitted
+    pub fn reset(&mut self) {
+        self.index = None;
+        self.draft.clear();
+    }
+}
+
+/// `$XDG_DATA_HOME/sentinel/history`, or `~/.local/share/sentinel/history`
+pub fn default_path() -> Option<PathBuf> {
+    Some(data_dir()?.join("history"))
+}
+
+/// `$XDG_DATA_HOME/sentinel`, or `~/.local/share/sentinel`, where Sentinel
+/// keeps what it remembers between sessions
+pub fn data_dir() -> Option<PathBuf> {
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share"))
+        })?;
+
+    Some(data_home.join("sentinel"))
+}
+
+fn escape(entry: &str) -> String {
+    entry
+        .replace('\\', "\\\\")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+fn unescape(line: &str) -> String {
+    let mut entry = String::with_capacity(line.len());
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            entry.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => entry.push('\n'),
+            Some('r') => entry.push('\r'),
+            Some(other) => entry.push(other),
+            None => entry.push('\\'),
+        }
+    }
+
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_round_trip_through_file() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("nested").join("history");
+
+        let mut history = InputHistory::load(&path, 10)?;
+        history.push("list files");
+        history.push("write a poem\nabout \\n escapes");
+        history.save()?;
+
+        let loaded = InputHistory::load(&path, 10)?;
+        assert_eq!(loaded.entries(), history.entries());
+        assert_eq!(loaded.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_push_dedupes_and_caps() {
+        let mut history = InputHistory::in_memory(3);
+        for entry in ["a", "b", "b", "  ", "c", "d", "d"] {
+            history.push(entry);
+        }
+        assert_eq!(history.entries(), ["b", "c", "d"]);
+
+        // Non-consecutive duplicates are kept
+        history.push("c");
+        assert_eq!(history.entries(), ["c", "d", "c"]);
+    }
+
+    #[test]
+    fn test_find_before_searches_backwards() {
+        let mut history = InputHistory::in_memory(10);
+        for entry in ["git status", "ls", "git log", "pwd"] {
+            history.push(entry);
+        }
+
+        assert_eq!(history.find_before("git", history.len()), Some(2));
+        assert_eq!(history.find_before("git", 2), Some(0));
+        assert_eq!(history.find_before("git", 0), None);
+        assert_eq!(history.find_before("cargo", 4), None);
+    }
+
+    fn history(entries: &[&str]) -> InputHistory {
+        let mut history = InputHistory::in_memory(10);
+        for entry in entries {
+            history.push(*entry);
+        }
+        history
+    }
+
+    #[test]
+    fn test_cursor_on_an_empty_history() {
+        let history = history(&[]);
+        let mut cursor = HistoryCursor::default();
+
+        assert_eq!(cursor.previous(&history, "draft"), None);
+        assert_eq!(cursor.next(&history), None);
+        assert!(!cursor.is_browsing());
+    }
+
+    #[test]
+    fn test_cursor_stops_at_both_ends_and_keeps_the_draft() {
+        let history = history(&["first", "second"]);
+        let mut cursor = HistoryCursor::default();
+
+        // Down before browsing leaves the input alone
+        assert_eq!(cursor.next(&history), None);
+
+        assert_eq!(
+            cursor.previous(&history, "half typed").as_deref(),
+            Some("second")
+        );
+        assert_eq!(
+            cursor.previous(&history, "second").as_deref(),
+            Some("first")
+        );
+        assert_eq!(cursor.previous(&history, "first"), None);
+        assert!(cursor.is_browsing());
+
+        // Past the newest entry the draft comes back, once
+        assert_eq!(cursor.next(&history).as_deref(), Some("second"));
+        assert_eq!(cursor.next(&history).as_deref(), Some("half typed"));
+        assert!(!cursor.is_browsing());
+        assert_eq!(cursor.next(&history), None);
+    }
+
+    #[test]
+    fn test_cursor_after_submitting_while_browsing() {
+        let mut history = history(&["first", "second"]);
+        let mut cursor = HistoryCursor::default();
+        cursor.previous(&history, "");
+        cursor.previous(&history, "second");
+
+        // Submitting an older entry starts the next browse from the newest
+        history.push("first");
+        cursor.reset();
+        assert_eq!(cursor.previous(&history, "").as_deref(), Some("first"));
+        assert_eq!(
+            cursor.previous(&history, "first").as_deref(),
+            Some("second")
+        );
+        assert_eq!(
+            cursor.previous(&history, "second").as_deref(),
+            Some("first")
+        );
+        assert_eq!(cursor.previous(&history, "first"), None);
+    }
+
+    #[test]
+    fn test_cursor_survives_the_history_shrinking() {
+        let mut history = history(&["a", "b", "c"]);
+        let mut cursor = HistoryCursor::default();
+        cursor.previous(&history, "");
+        cursor.next(&history);
+        cursor.previous(&history, "");
+
+        // The oldest entries are dropped once the history is over capacity
+        history = InputHistory::in_memory(1);
+        history.push("z");
+        assert_eq!(cursor.previous(&history, "c").as_deref(), Some("z"));
+    }
+}
@@ -0,0 +1,194 @@
+//! Images sent with a prompt to multimodal models such as llava.
+
+use std::fmt;
+use std::fs;
+
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::llm::ollama::LlmClient;
+
+/// Largest image that may be attached, in bytes
+pub const MAX_IMAGE_BYTES: u64 = 10 * 1024 * 1024;
+
+/// The image formats multimodal models accept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Webp,
+}
+
+impl ImageFormat {
+    /// Recognize the format from the leading bytes of a file
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Self::Png)
+        } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some(Self::Jpeg)
+        } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+            Some(Self::Webp)
+        } else {
+            None
+        }
+    }
+}
+
+/// An image attached to a message.
+///
+/// Only the path, format and size are serialized, so saved conversations note the
+/// image instead of carrying its base64 data.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageAttachment {
+    pub path: String,
+    pub format: ImageFormat,
+    /// Size of the image file in bytes
+    pub size: u64,
+    /// The image, base64-encoded; empty for images loaded from a saved conversation
+    #[serde(skip)]
+    pub data: String,
+}
+
+impl ImageAttachment {
+    /// Read, validate and encode the image at `path`
+    pub fn load(path: &str) -> Result<Self> {
+        let size = fs::metadata(path)
+            .map_err(|e| anyhow!("Failed to read '{}': {}", path, e))?
+            .len();
+        check_size(path, size)?;
+        let bytes = fs::read(path).map_err(|e| anyhow!("Failed to read '{}': {}", path, e))?;
+        Self::from_bytes(path, &bytes)
+    }
+
+    /// Validate and encode an image read from `path`
+    pub fn from_bytes(path: &str, bytes: &[u8]) -> Result<Self> {
+        let size = bytes.len() as u64;
+        check_size(path, size)?;
+        let format = ImageFormat::detect(bytes)
+            .ok_or_else(|| anyhow!("'{}' is not a PNG, JPEG or WebP image", path))?;
+
+        Ok(Self {
+            path: path.to_string(),
+            format,
+            size,
+            data: STANDARD.encode(bytes),
+        })
+    }
+}
+
+impl fmt::Display for ImageAttachment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[image: {}]", self.path)
+    }
+}
+
+fn check_size(path: &str, size: u64) -> Result<()> {
+    if size > MAX_IMAGE_BYTES {
+        return Err(anyhow!(
+            "'{}' is {:.1} MB; images are limited to {} MB",
+            path,
+            size as f64 / (1024.0 * 1024.0),
+            MAX_IMAGE_BYTES / (1024 * 1024)
+        ));
+    }
+    Ok(())
+}
+
+/// Whether a model can read images, judged from the metadata Ollama's
+/// `/api/show` returns for it, or `None` when the metadata doesn't say
+pub fn vision_from_metadata(show: &Value) -> Option<bool> {
+    // Recent servers list capabilities outright
+    if let Some(capabilities) = show.get("capabilities").and_then(Value::as_array) {
+        return Some(capabilities.iter().any(|capability| capability == "vision"));
+    }
+    // llava-style models carry a separate vision projector
+    if show.get("projector_info").is_some() {
+        return Some(true);
+    }
+    let info = show.get("model_info")?.as_object()?;
+    Some(info.keys().any(|key| key.contains(".vision.")))
+}
+
+/// Fail with a clear message when `client` is known not to read images
+pub async fn ensure_vision(client: &dyn LlmClient, model: &str) -> Result<()> {
+    match client.supports_images().await {
+        Some(false) => Err(anyhow!(
+            "{} cannot read images; use a multimodal model such as llava or llama3.2-vision",
+            model
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    #[test]
+    fn test_images_are_validated_and_encoded() -> Result<()> {
+        let image = ImageAttachment::from_bytes("cat.png", PNG)?;
+        assert_eq!(image.format, ImageFormat::Png);
+        assert_eq!(image.size, PNG.len() as u64);
+        assert_eq!(STANDARD.decode(&image.data)?, PNG);
+        assert_eq!(image.to_string(), "[image: cat.png]");
+
+        assert_eq!(
+            ImageFormat::detect(b"RIFF\x10\0\0\0WEBPVP8 "),
+            Some(ImageFormat::Webp)
+        );
+        assert_eq!(
+            ImageFormat::detect(&[0xff, 0xd8, 0xff, 0xe0]),
+            Some(ImageFormat::Jpeg)
+        );
+        let error = ImageAttachment::from_bytes("notes.txt", b"hello").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "'notes.txt' is not a PNG, JPEG or WebP image"
+        );
+
+        // Saved conversations keep the path, not the data
+        let saved = serde_json::to_value(&image)?;
+        assert_eq!(
+            saved,
+            json!({ "path": "cat.png", "format": "png", "size": 16 })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_images_are_refused() {
+        let mut bytes = PNG.to_vec();
+        bytes.resize(MAX_IMAGE_BYTES as usize + 1, 0);
+
+        let error = ImageAttachment::from_bytes("huge.png", &bytes).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "'huge.png' is 10.0 MB; images are limited to 10 MB"
+        );
+    }
+
+    #[test]
+    fn test_vision_from_metadata() {
+        let capable = json!({ "capabilities": ["completion", "vision"] });
+        assert_eq!(vision_from_metadata(&capable), Some(true));
+        let text_only = json!({ "capabilities": ["completion", "tools"] });
+        assert_eq!(vision_from_metadata(&text_only), Some(false));
+
+        let llava =
+            json!({ "model_info": {}, "projector_info": { "clip.has_vision_encoder": true } });
+        assert_eq!(vision_from_metadata(&llava), Some(true));
+        let mllama = json!({ "model_info": { "mllama.vision.block_count": 32 } });
+        assert_eq!(vision_from_metadata(&mllama), Some(true));
+        let llama = json!({ "model_info": { "llama.block_count": 28 } });
+        assert_eq!(vision_from_metadata(&llama), Some(false));
+
+        assert_eq!(vision_from_metadata(&json!({})), None);
+    }
+}
@@ -0,0 +1,183 @@
+use std::io::{IsTerminal, Read};
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+
+// Maximum number of bytes of piped stdin included in a prompt
+const MAX_STDIN_LENGTH: usize = 30000;
+
+/// How piped stdin is combined with the prompt of `sentinel ask`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StdinMode {
+    /// Append stdin to the message as a delimited context block
+    Context,
+    /// Use stdin as the message, replacing the positional message
+    Message,
+    /// Do not read stdin at all
+    Ignore,
+}
+
+/// Read stdin if it is piped (not a terminal), returning `None` for a terminal or empty input
+pub fn read_piped_stdin() -> Result<Option<String>> {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        return Ok(None);
+    }
+
+    read_input(stdin.lock())
+}
+
+/// Read `reader` as UTF-8 text up to the cap. Longer input is not read past
+/// it, and is cut at the last line end before it with a note saying so
+pub fn read_input<R: Read>(reader: R) -> Result<Option<String>> {
+    // The byte past the cap only tells that there was more
+    let mut bytes = Vec::new();
+    reader
+        .take(MAX_STDIN_LENGTH as u64 + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|e| anyhow!("Failed to read stdin: {}", e))?;
+    let cut_off = bytes.len() > MAX_STDIN_LENGTH;
+    if cut_off {
+        bytes.truncate(MAX_STDIN_LENGTH);
+        // The cap may fall inside a character
+        if let Err(e) = std::str::from_utf8(&bytes) {
+            if e.error_len().is_none() {
+                bytes.truncate(e.valid_up_to());
+            }
+        }
+    }
+
+    let mut text = String::from_utf8(bytes)
+        .map_err(|_| anyhow!("stdin is not valid UTF-8 text; binary input is not supported"))?;
+
+    if text.trim().is_empty() {
+        return Ok(None);
+    }
+
+    if cut_off {
+        if let Some(end) = text.rfind('\n') {
+            text.truncate(end);
+        }
+        text.push_str(&format!(
+            "\n... [stdin cut off after {} bytes] ...",
+            MAX_STDIN_LENGTH
+        ));
+    }
+    Ok(Some(text))
+}
+
+/// Build the final prompt from the positional message and optional piped stdin
+pub fn build_prompt(message: &str, stdin: Option<&str>, mode: StdinMode) -> Result<String> {
+    let message = message.trim();
+
+    let prompt = match (mode, stdin) {
+        (StdinMode::Context, Some(stdin)) => {
+            if message.is_empty() {
+                format!("{}\n\n", context_block("stdin", stdin))
+            } else {
+                format!("{}\n\n{}", message, context_block("stdin", stdin))
+            }
+        }
+        (StdinMode::Message, Some(stdin)) => stdin.trim().to_string(),
+        _ => message.to_string(),
+    };
+
+    if prompt.trim().is_empty() {
+        return Err(anyhow!(
+            "No message provided. Pass a message or pipe input into stdin"
+        ));
+    }
+
+    Ok(prompt.trim_end().to_string())
+}
+
+/// Wrap content in clearly delimited markers labeled with its source
+pub fn context_block(label: &str, content: &str) -> String {
+    format!(
+        "--- begin {} ---\n{}\n--- end {} ---",
+        label,
+        content.trim_end_matches('\n'),
+        label
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_read_input_rejects_non_utf8() {
+        let result = read_input(Cursor::new(vec![0xff, 0xfe, 0x00, 0x80]));
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("not valid UTF-8"));
+    }
+
+    #[test]
+    fn test_read_input_empty_is_none() {
+        assert!(read_input(Cursor::new(Vec::new())).unwrap().is_none());
+        assert!(read_input(Cursor::new(b"  \n\n".to_vec()))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_read_input_truncates_large_input() {
+        let line = "error: something went wrong ✗\n";
+        let input = line.repeat(MAX_STDIN_LENGTH / 10);
+
+        let text = read_input(Cursor::new(input.into_bytes()))
+            .unwrap()
+            .unwrap();
+
+        assert!(text.ends_with("✗\n... [stdin cut off after 30000 bytes] ..."));
+        assert!(text.starts_with("error: something went wrong"));
+        assert!(text.len() < MAX_STDIN_LENGTH + 100);
+
+        // Input of exactly the cap is whole
+        let input = "x".repeat(MAX_STDIN_LENGTH);
+        assert_eq!(read_input(Cursor::new(input.clone())).unwrap(), Some(input));
+    }
+
+    #[test]
+    fn test_read_input_stops_reading_at_the_cap() {
+        // Endless input would never finish a read to the end
+        let endless = std::io::repeat(b'y');
+        let text = read_input(endless).unwrap().unwrap();
+        assert!(text.starts_with("yyy"));
+        assert!(text.ends_with("... [stdin cut off after 30000 bytes] ..."));
+    }
+
+    #[test]
+    fn test_build_prompt_context() {
+        let prompt = build_prompt(
+            "why is this failing?",
+            Some("panic at line 3\n"),
+            StdinMode::Context,
+        )
+        .unwrap();
+
+        assert_eq!(
+            prompt,
+            "why is this failing?\n\n--- begin stdin ---\npanic at line 3\n--- end stdin ---"
+        );
+    }
+
+    #[test]
+    fn test_build_prompt_message_replaces_positional() {
+        let prompt = build_prompt("ignored", Some("explain this\n"), StdinMode::Message).unwrap();
+        assert_eq!(prompt, "explain this");
+    }
+
+    #[test]
+    fn test_build_prompt_ignore() {
+        let prompt = build_prompt("hello", Some("piped"), StdinMode::Ignore).unwrap();
+        assert_eq!(prompt, "hello");
+    }
+
+    #[test]
+    fn test_build_prompt_requires_some_input() {
+        assert!(build_prompt("  ", None, StdinMode::Context).is_err());
+        assert!(build_prompt("", Some("data"), StdinMode::Ignore).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+//! Cutting a reply short.
+//!
+//! A frontend keeps an [`Interrupter`] for its agent and calls
+//! [`Interrupter::interrupt`] when the user presses Esc or Ctrl+C. The turn in
+//! flight sees its token cancelled, drops the request to the model and
+//! returns what it has so far.
+
+use std::sync::{Arc, Mutex};
+
+use tokio_util::sync::CancellationToken;
+
+/// Appended to a reply that was cut short
+pub const INTERRUPTED: &str = "(interrupted)";
+
+/// Lets one task interrupt the turn another is running
+#[derive(Debug, Clone, Default)]
+pub struct Interrupter {
+    // The token of the turn in flight, if any
+    current: Arc<Mutex<Option<CancellationToken>>>,
+}
+
+impl Interrupter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a turn, returning the token it should watch
+    pub fn start(&self) -> CancellationToken {
+        let token = CancellationToken::new();
+        *self.current.lock().unwrap() = Some(token.clone());
+        token
+    }
+
+    /// Finish the turn, so later interrupts have nothing to stop
+    pub fn finish(&self) {
+        *self.current.lock().unwrap() = None;
+    }
+
+    /// Whether a turn is in flight
+    pub fn is_busy(&self) -> bool {
+        self.current.lock().unwrap().is_some()
+    }
+
+    /// Interrupt the turn in flight. False when there is none, or it was
+    /// already interrupted, so a second Ctrl+C can mean something else
+    pub fn interrupt(&self) -> bool {
+        match &*self.current.lock().unwrap() {
+            Some(token) if !token.is_cancelled() => {
+                token.cancel();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// What the model had said so far, marked as cut short
+pub fn mark_interrupted(partial: &str) -> String {
+    let partial = partial.trim_end();
+    if partial.is_empty() {
+        INTERRUPTED.to_string()
+    } else {
+        format!("{}\n\n{}", partial, INTERRUPTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interrupts_reach_the_turn_in_flight_once() {
+        let interrupter = Interrupter::new();
+        assert!(!interrupter.interrupt());
+
+        let token = interrupter.start();
+        assert!(interrupter.is_busy());
+        assert!(interrupter.clone().interrupt());
+        assert!(token.is_cancelled());
+        // A second interrupt finds nothing left to stop
+        assert!(!interrupter.interrupt());
+
+        interrupter.finish();
+        assert!(!interrupter.is_busy());
+        assert!(!interrupter.start().is_cancelled());
+    }
+
+    #[test]
+    fn test_mark_interrupted() {
+        assert_eq!(mark_interrupted(""), "(interrupted)");
+        assert_eq!(
+            mark_interrupted("Let me look at the files.\n"),
+            "Let me look at the files.\n\n(interrupted)"
+        );
+    }
+}
@@ -0,0 +1,374 @@
+//! Running a command over the code of the last reply, for `/run-last`.
+//!
+//! The fenced code blocks of the reply are written to files in a directory of
+//! the system's temp directory, named by their place and language, e.g.
+//! `sentinel-last-1.rs`. `{}` in the command stands for all of them and `{2}`
+//! for the second; a command without placeholders gets them all at the end.
+//! The files are removed when the next `/run-last` writes its own, and when
+//! the session ends.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Tells apart the scratch directories of one process
+static SCRATCH_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+/// A fenced code block of a reply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock {
+    /// The first word after the opening fence, e.g. "rust", if there is one
+    pub language: Option<String>,
+    /// Everything after the opening fence, e.g. `rust title="main.rs"`
+    pub info: String,
+    pub code: String,
+}
+
+impl CodeBlock {
+    fn new(info: &str, lines: &[&str]) -> Self {
+        Self {
+            language: info.split_whitespace().next().map(str::to_string),
+            info: info.to_string(),
+            code: lines.join("\n"),
+        }
+    }
+
+    /// The file extension of the block's language, "txt" for an unknown one
+    pub fn extension(&self) -> &'static str {
+        let language = self.language.as_deref().unwrap_or_default();
+        match language.to_lowercase().as_str() {
+            "rust" | "rs" => "rs",
+            "python" | "py" | "python3" => "py",
+            "javascript" | "js" | "node" => "js",
+            "typescript" | "ts" => "ts",
+            "jsx" => "jsx",
+            "tsx" => "tsx",
+            "go" | "golang" => "go",
+            "bash" | "sh" | "shell" | "zsh" => "sh",
+            "toml" => "toml",
+            "json" => "json",
+            "yaml" | "yml" => "yaml",
+            "markdown" | "md" => "md",
+            "c" => "c",
+            "cpp" | "c++" => "cpp",
+            "java" => "java",
+            "kotlin" | "kt" => "kt",
+            "ruby" | "rb" => "rb",
+            "html" => "html",
+            "css" => "css",
+            "sql" => "sql",
+            _ => "txt",
+        }
+    }
+}
+
+/// The fenced code blocks of `text`, in order. A block left open runs to the
+/// end, as it does when a reply is cut short
+pub fn code_blocks(text: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    // The fence of the open block, its info string and its lines
+    let mut open: Option<(String, &str, Vec<&str>)> = None;
+
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        match &mut open {
+            Some((fence, _, lines)) => {
+                if !is_closing(trimmed.trim_end(), fence) {
+                    lines.push(line);
+                } else if let Some((_, info, lines)) = open.take() {
+                    blocks.push(CodeBlock::new(info, &lines));
+                }
+            }
+            None => {
+                if let Some(fence) = opening_fence(trimmed) {
+                    let info = trimmed[fence.len()..].trim();
+                    open = Some((fence, info, Vec::new()));
+                }
+            }
+        }
+    }
+    if let Some((_, info, lines)) = open {
+        blocks.push(CodeBlock::new(info, &lines));
+    }
+    blocks
+}
+
+// The run of three or more backticks or tildes that opens a block on `line`
+fn opening_fence(line: &str) -> Option<String> {
+    let marker = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let fence: String = line.chars().take_while(|c| *c == marker).collect();
+    (fence.len() >= 3).then_some(fence)
+}
+
+// Whether `line` closes a block opened by `fence`: the same marker, at least
+// as many of them, and nothing else
+fn is_closing(line: &str, fence: &str) -> bool {
+    let marker = fence.chars().next().unwrap_or('`');
+    line.len() >= fence.len() && line.chars().all(|c| c == marker)
+}
+
+/// `command` with `{}` replaced by every path and `{n}` by the n-th, or with
+/// every path added at the end if it has neither. A placeholder for a block
+/// that doesn't exist is an error
+pub fn substitute(command: &str, paths: &[PathBuf]) -> Result<String, String> {
+    let all = paths
+        .iter()
+        .map(|path| quote(path))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut substituted = String::new();
+    let mut placeholders = 0;
+    let mut rest = command;
+
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}').map(|end| start + end) else {
+            break;
+        };
+        let inside = &rest[start + 1..end];
+        let replacement = if inside.is_empty() {
+            all.clone()
+        } else if let Ok(number) = inside.parse::<usize>() {
+            let path = number
+                .checked_sub(1)
+                .and_then(|index| paths.get(index))
+                .ok_or_else(|| {
+                    format!(
+                        "{{{}}} names code block {}, but the last reply has {}",
+                        number,
+                        number,
+                        blocks(paths.len())
+                    )
+                })?;
+            quote(path)
+        } else {
+            // Braces of the command's own, e.g. of awk or find -exec
+            substituted.push_str(&rest[..=start]);
+            rest = &rest[start + 1..];
+            continue;
+        };
+        substituted.push_str(&rest[..start]);
+        substituted.push_str(&replacement);
+        placeholders += 1;
+        rest = &rest[end + 1..];
+    }
+    substituted.push_str(rest);
+
+    if placeholders == 0 {
+        substituted.push(' ');
+        substituted.push_str(&all);
+    }
+    Ok(substituted)
+}
+
+/// "1 code block" or "3 code blocks"
+pub fn blocks(count: usize) -> String {
+    format!("{} code block{}", count, if count == 1 { "" } else { "s" })
+}
+
+// A path as one shell word, quoted if it needs to be
+fn quote(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    let plain = path
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "/._-+:".contains(c));
+    if plain {
+        path.to_string()
+    } else {
+        format!("'{}'", path.replace('\'', r"'\''"))
+    }
+}
+
+/// The files the code blocks of a reply were last written to, removed when
+/// dropped
+#[derive(Debug)]
+pub struct ScratchFiles {
+    dir: PathBuf,
+    written: Vec<PathBuf>,
+}
+
+impl Default for ScratchFiles {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScratchFiles {
+    /// Files in a directory of the temp directory of their own
+    pub fn new() -> Self {
+        let number = SCRATCH_DIRS.fetch_add(1, Ordering::Relaxed);
+        Self::in_dir(std::env::temp_dir().join(format!(
+            "sentinel-{}-{}",
+            std::process::id(),
+            number
+        )))
+    }
+
+    /// Files in `dir`, which is created when they are first written
+    pub fn in_dir(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            written: Vec::new(),
+        }
+    }
+
+    /// Write `blocks` in place of the files written before, returning their paths
+    pub fn write(&mut self, blocks: &[CodeBlock]) -> io::Result<Vec<PathBuf>> {
+        self.remove();
+        fs::create_dir_all(&self.dir)?;
+        for (index, block) in blocks.iter().enumerate() {
+            let path = self
+                .dir
+                .join(format!("sentinel-last-{}.{}", index + 1, block.extension()));
+            let mut code = block.code.clone();
+            if !code.is_empty() && !code.ends_with('\n') {
+                code.push('\n');
+            }
+            self.written.push(path.clone());
+            fs::write(&path, code)?;
+        }
+        Ok(self.written.clone())
+    }
+
+    /// The files written last
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.written
+    }
+
+    /// Remove the files written last, and the directory once it is empty
+    pub fn remove(&mut self) {
+        for path in self.written.drain(..) {
+            let _ = fs::remove_file(path);
+        }
+        let _ = fs::remove_dir(&self.dir);
+    }
+}
+
+impl Drop for ScratchFiles {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+/// Write the code blocks of `reply` to `scratch` and fill their paths into
+/// `command`, or say why it can't run
+pub fn prepare(reply: &str, command: &str, scratch: &mut ScratchFiles) -> Result<String, String> {
+    let blocks = code_blocks(reply);
+    if blocks.is_empty() {
+        return Err("The last reply has no code blocks to run a command over".to_string());
+    }
+    let paths = scratch
+        .write(&blocks)
+        .map_err(|e| format!("Could not write the code blocks to temp files: {}", e))?;
+    substitute(command, &paths)
+}
+
+/// The message that tells the model what a command printed
+pub fn report(command: &str, output: &str) -> String {
+    format!("I ran `{}`. Output:\n{}", command, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const REPLY: &str = "Here is the fix:\n\
+        ```rust\n\
+        fn main() {\n    println!(\"hi\");\n}\n\
+        ```\n\
+        And a test script:\n\
+        ~~~ sh title=\"run\"\n\
+        ```not a fence\n\
+        cargo test\n\
+        ~~~~\n\
+        Then a plain one:\n\
+          ```\n\
+        notes\n\
+        ";
+
+    #[test]
+    fn test_code_blocks_are_extracted() {
+        let blocks = code_blocks(REPLY);
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[0].language.as_deref(), Some("rust"));
+        assert_eq!(blocks[0].code, "fn main() {\n    println!(\"hi\");\n}");
+        // A longer fence of the same marker closes it; the other marker doesn't
+        assert_eq!(blocks[1].language.as_deref(), Some("sh"));
+        assert_eq!(blocks[1].info, "sh title=\"run\"");
+        assert_eq!(blocks[1].code, "```not a fence\ncargo test");
+        // The last one is never closed
+        assert_eq!(blocks[2].language, None);
+        assert_eq!(blocks[2].code, "notes");
+
+        let extensions: Vec<&str> = blocks.iter().map(CodeBlock::extension).collect();
+        assert_eq!(extensions, ["rs", "sh", "txt"]);
+        assert!(code_blocks("No code here, just `inline` code").is_empty());
+    }
+
+    #[test]
+    fn test_placeholders_are_substituted() {
+        let paths = [
+            PathBuf::from("/tmp/s/sentinel-last-1.rs"),
+            PathBuf::from("/tmp/my dir/sentinel-last-2.sh"),
+        ];
+        assert_eq!(
+            substitute("rustfmt --check {}", &paths).unwrap(),
+            "rustfmt --check /tmp/s/sentinel-last-1.rs '/tmp/my dir/sentinel-last-2.sh'"
+        );
+        assert_eq!(
+            substitute("diff {1} {2}", &paths).unwrap(),
+            "diff /tmp/s/sentinel-last-1.rs '/tmp/my dir/sentinel-last-2.sh'"
+        );
+        // Without placeholders the paths go at the end
+        assert_eq!(
+            substitute("wc -l", &paths[..1]).unwrap(),
+            "wc -l /tmp/s/sentinel-last-1.rs"
+        );
+        // Braces that aren't placeholders are left alone
+        assert_eq!(
+            substitute("awk '{print $1}' {1}", &paths).unwrap(),
+            "awk '{print $1}' /tmp/s/sentinel-last-1.rs"
+        );
+        assert_eq!(
+            substitute("cat {3}", &paths).unwrap_err(),
+            "{3} names code block 3, but the last reply has 2 code blocks"
+        );
+    }
+
+    #[test]
+    fn test_scratch_files_are_replaced_and_removed() -> anyhow::Result<()> {
+        let temp = TempDir::new()?;
+        let dir = temp.path().join("scratch");
+        let mut scratch = ScratchFiles::in_dir(dir.clone());
+
+        let command = prepare(REPLY, "cat {1}", &mut scratch).map_err(anyhow::Error::msg)?;
+        assert_eq!(
+            command,
+            format!("cat {}", dir.join("sentinel-last-1.rs").display())
+        );
+        assert_eq!(scratch.paths().len(), 3);
+        assert_eq!(
+            fs::read_to_string(dir.join("sentinel-last-2.sh"))?,
+            "```not a fence\ncargo test\n"
+        );
+
+        // The next reply's blocks replace them
+        prepare("```python\nprint(1)\n```", "python3 {}", &mut scratch)
+            .map_err(anyhow::Error::msg)?;
+        assert!(dir.join("sentinel-last-1.py").exists());
+        assert!(!dir.join("sentinel-last-1.rs").exists());
+        assert!(!dir.join("sentinel-last-3.txt").exists());
+
+        let error = prepare("No code", "cat {}", &mut scratch).unwrap_err();
+        assert_eq!(
+            error,
+            "The last reply has no code blocks to run a command over"
+        );
+
+        // Ending the session removes them, and the directory
+        drop(scratch);
+        assert!(!dir.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+//! Sentinel: an LLM based terminal agent.
+//!
+//! The [`Agent`] runs the conversation loop without touching stdin or stdout:
+//! frontends feed it input and receive [`AgentEvent`]s through an
+//! [`AgentOutput`]. The `sentinel` binary is one such frontend.
+
+pub mod activity;
+pub mod agent;
+pub mod apply;
+pub mod attachments;
+pub mod autosave;
+pub mod batch;
+pub mod candidates;
+pub mod commands;
+pub mod compare;
+pub mod config;
+pub mod console;
+pub mod context;
+pub mod failure;
+pub mod history;
+pub mod images;
+pub mod input;
+pub mod interrupt;
+pub mod last_code;
+pub mod line_editor;
+pub mod links;
+pub mod llm;
+pub mod memory;
+pub mod notify;
+pub mod prime;
+pub mod privacy;
+pub mod recording;
+pub mod report;
+pub mod session;
+pub mod session_blobs;
+pub mod session_file;
+pub mod stats;
+pub mod status;
+pub mod style;
+pub mod suggest;
+pub mod templates;
+pub mod title;
+pub mod tool_loop;
+pub mod tools;
+pub mod truncate;
+pub mod tui;
+pub mod wizard;
+pub mod words;
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use images::ImageAttachment;
+use llm::timings::ServerTimings;
+use stats::ToolTiming;
+
+pub use agent::{
+    Agent, AgentEvent, AgentOutput, AgentState, CommandHelp, Flow, HistoryEntry, SearchHit,
+};
+
+// Message and Role definitions used by both the CLI and TUI
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum Role {
+    #[serde(rename = "user")]
+    User,
+    #[serde(rename = "assistant")]
+    Assistant,
+    #[serde(rename = "system")]
+    System,
+    /// The output of a tool the model called
+    #[serde(rename = "tool")]
+    Tool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[non_exhaustive]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+    #[serde(skip, default)]
+    pub input_tokens: usize,
+    /// Tokens of tool output sent to the model while producing this reply
+    #[serde(skip, default)]
+    pub tool_output_tokens: usize,
+    #[serde(skip, default)]
+    pub output_tokens: usize,
+    #[serde(skip, default)]
+    pub used_tools: Vec<String>,
+    /// How long each tool call made while producing this reply took
+    #[serde(skip, default)]
+    pub tool_timings: Vec<ToolTiming>,
+    /// Other candidate replies kept when this one was picked
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternatives: Vec<String>,
+    /// The think blocks of a reasoning model, kept out of `content` so they
+    /// are never sent back to the model
+    #[serde(skip, default)]
+    pub reasoning: Option<String>,
+    /// The model that produced an assistant message; empty for other roles
+    /// and for messages saved before it was recorded
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub model: String,
+    /// Who served `model`, e.g. "ollama"
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub provider: String,
+    /// Images sent with the message to a multimodal model
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<ImageAttachment>,
+    /// Sent word for word however much of the conversation is trimmed to
+    /// fit the context window
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    /// Cut off by the `max_tokens` limit, so most likely incomplete
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// Where content read from this machine came from, e.g. the paths of
+    /// attached files, to ask before it goes to a remote server
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub local_sources: Vec<String>,
+    /// How long a reply of this session took to generate
+    #[serde(skip, default)]
+    pub latency: Option<Duration>,
+    /// Where the server's time went generating a reply of this session
+    #[serde(skip, default)]
+    pub server_timings: Option<ServerTimings>,
+}
+
+impl Message {
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            input_tokens: 0,
+            tool_output_tokens: 0,
+            output_tokens: 0,
+            used_tools: Vec::new(),
+            tool_timings: Vec::new(),
+            alternatives: Vec::new(),
+            reasoning: None,
+            model: String::new(),
+            provider: String::new(),
+            images: Vec::new(),
+            pinned: false,
+            truncated: false,
+            local_sources: Vec::new(),
+            latency: None,
+            server_timings: None,
+        }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new(Role::User, content)
+    }
+
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new(Role::System, content)
+    }
+
+    pub fn tool(content: impl Into<String>) -> Self {
+        Self::new(Role::Tool, content)
+    }
+
+    /// An assistant reply with its token counts and the tools used to produce it
+    pub fn assistant(
+        content: impl Into<String>,
+        input_tokens: usize,
+        output_tokens: usize,
+        used_tools: Vec<String>,
+    ) -> Self {
+        Self {
+            input_tokens,
+            output_tokens,
+            used_tools,
+            ..Self::new(Role::Assistant, content)
+        }
+    }
+
+    /// Record the tool output sent to the model while producing this reply
+    pub fn with_tool_output_tokens(mut self, tokens: usize) -> Self {
+        self.tool_output_tokens = tokens;
+        self
+    }
+
+    /// Record how long the tool calls made while producing this reply took
+    pub fn with_tool_timings(mut self, timings: Vec<ToolTiming>) -> Self {
+        self.tool_timings = timings;
+        self
+    }
+
+    /// Keep the reasoning that led to this message
+    pub fn with_reasoning(mut self, reasoning: Option<String>) -> Self {
+        self.reasoning = reasoning;
+        self
+    }
+
+    /// Record the model and provider that produced this message
+    pub fn with_source(mut self, model: impl Into<String>, provider: impl Into<String>) -> Self {
+        self.model = model.into();
+        self.provider = provider.into();
+        self
+    }
+
+    /// Send images along with this message
+    pub fn with_images(mut self, images: Vec<ImageAttachment>) -> Self {
+        self.images = images;
+        self
+    }
+
+    /// Mark a reply cut off by the `max_tokens` limit
+    pub fn with_truncated(mut self, truncated: bool) -> Self {
+        self.truncated = truncated;
+        self
+    }
+
+    /// Record where the local content of the message came from
+    pub fn with_local_sources(mut self, sources: Vec<String>) -> Self {
+        self.local_sources = sources;
+        self
+    }
+
+    /// Record how long the reply took to generate
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    /// Record the load, prompt and generation times the server reported
+    pub fn with_server_timings(mut self, timings: ServerTimings) -> Self {
+        self.server_timings = Some(timings);
+        self
+    }
+
+    /// "model via provider" for an attributed message
+    pub fn source(&self) -> Option<String> {
+        source_label(&self.model, &self.provider)
+    }
+}
+
+/// How the model and provider of a message are shown, or `None` when the
+/// message has no attribution
+pub fn source_label(model: &str, provider: &str) -> Option<String> {
+    match (model.is_empty(), provider.is_empty()) {
+        (true, _) => None,
+        (false, true) => Some(model.to_string()),
+        (false, false) => Some(format!("{} via {}", model, provider)),
+    }
+}
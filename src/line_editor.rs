@@ -0,0 +1,560 @@
+//! Editing the line typed at the REPL prompt.
+//!
+//! [`LineBuffer`] holds the text and the cursor and knows the Emacs-style
+//! bindings; [`LineEditor`] reads keys into one with the terminal in raw mode
+//! and bracketed paste on, so pasted lines stay in one prompt until Enter.
+//! Input that isn't a terminal, or `chat --dumb-terminal`, is read a line at
+//! a time instead.
+
+use std::env;
+use std::io::{self, IsTerminal, Write};
+
+use crossterm::cursor::{MoveDown, MoveToColumn, MoveUp};
+use crossterm::event::{
+    self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEvent, KeyEventKind,
+    KeyModifiers,
+};
+use crossterm::terminal::{self, Clear, ClearType};
+use crossterm::{execute, queue};
+use unicode_width::UnicodeWidthStr;
+
+use crate::history::{HistoryCursor, InputHistory};
+use crate::style;
+
+/// How line breaks in the input are shown, keeping it on one line
+const LINE_BREAK: &str = "↵";
+
+/// What a key did to the line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    /// The text or the cursor may have changed
+    Edited,
+    /// Send the line
+    Submit,
+    /// Drop the line, with Ctrl+C
+    Cancel,
+    /// End the input, with Ctrl+D on an empty line
+    Eof,
+    /// Go to the previous entry of the history
+    Previous,
+    /// Go to the next entry of the history
+    Next,
+}
+
+/// The text of a line being edited and where the cursor is in it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineBuffer {
+    text: String,
+    // A byte offset, always on a character boundary
+    cursor: usize,
+}
+
+impl LineBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The cursor as a byte offset into the text
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Replace the text, e.g. with a history entry, with the cursor at its end
+    pub fn set(&mut self, text: impl Into<String>) {
+        self.text = text.into();
+        self.cursor = self.text.len();
+    }
+
+    /// Take the text out, leaving the line empty
+    pub fn take(&mut self) -> String {
+        self.cursor = 0;
+        std::mem::take(&mut self.text)
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    /// Insert pasted text at the cursor, with its line breaks made `\n`
+    pub fn insert_str(&mut self, text: &str) {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        self.text.insert_str(self.cursor, &text);
+        self.cursor += text.len();
+    }
+
+    pub fn left(&mut self) {
+        self.cursor = self.before(self.cursor);
+    }
+
+    pub fn right(&mut self) {
+        self.cursor = self.after(self.cursor);
+    }
+
+    pub fn start(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    /// Move to the start of the word before the cursor
+    pub fn word_left(&mut self) {
+        self.cursor = self.word_start(self.cursor, is_word);
+    }
+
+    /// Move past the end of the word after the cursor
+    pub fn word_right(&mut self) {
+        self.cursor = self.word_end(self.cursor);
+    }
+
+    /// Delete the character before the cursor
+    pub fn backspace(&mut self) {
+        let start = self.before(self.cursor);
+        self.delete_range(start, self.cursor);
+    }
+
+    /// Delete the character under the cursor
+    pub fn delete(&mut self) {
+        let end = self.after(self.cursor);
+        self.delete_range(self.cursor, end);
+    }
+
+    /// Delete back to the whitespace before the cursor, like Ctrl+W in a shell
+    pub fn delete_to_whitespace(&mut self) {
+        let start = self.word_start(self.cursor, |c| !c.is_whitespace());
+        self.delete_range(start, self.cursor);
+    }
+
+    /// Delete the word before the cursor
+    pub fn delete_word_before(&mut self) {
+        let start = self.word_start(self.cursor, is_word);
+        self.delete_range(start, self.cursor);
+    }
+
+    /// Delete the word after the cursor
+    pub fn delete_word_after(&mut self) {
+        let end = self.word_end(self.cursor);
+        self.delete_range(self.cursor, end);
+    }
+
+    /// Delete from the cursor to the end of the line
+    pub fn kill_to_end(&mut self) {
+        self.delete_range(self.cursor, self.text.len());
+    }
+
+    /// Delete from the start of the line to the cursor
+    pub fn kill_to_start(&mut self) {
+        self.delete_range(0, self.cursor);
+    }
+
+    /// Apply a key with the Emacs-style bindings
+    pub fn apply(&mut self, key: KeyEvent) -> Edit {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        match key.code {
+            KeyCode::Enter if alt => self.insert('\n'),
+            KeyCode::Enter => return Edit::Submit,
+            KeyCode::Char('c') if control => return Edit::Cancel,
+            KeyCode::Char('d') if control && self.is_empty() => return Edit::Eof,
+            KeyCode::Char('d') if control => self.delete(),
+            KeyCode::Char('a') if control => self.start(),
+            KeyCode::Char('e') if control => self.end(),
+            KeyCode::Char('b') if control => self.left(),
+            KeyCode::Char('f') if control => self.right(),
+            KeyCode::Char('h') if control => self.backspace(),
+            KeyCode::Char('w') if control => self.delete_to_whitespace(),
+            KeyCode::Char('k') if control => self.kill_to_end(),
+            KeyCode::Char('u') if control => self.kill_to_start(),
+            KeyCode::Char('p') if control => return Edit::Previous,
+            KeyCode::Char('n') if control => return Edit::Next,
+            KeyCode::Char('b') if alt => self.word_left(),
+            KeyCode::Char('f') if alt => self.word_right(),
+            KeyCode::Char('d') if alt => self.delete_word_after(),
+            KeyCode::Char(c) if !control && !alt => self.insert(c),
+            KeyCode::Backspace if alt || control => self.delete_word_before(),
+            KeyCode::Backspace => self.backspace(),
+            KeyCode::Delete => self.delete(),
+            KeyCode::Left if alt || control => self.word_left(),
+            KeyCode::Right if alt || control => self.word_right(),
+            KeyCode::Left => self.left(),
+            KeyCode::Right => self.right(),
+            KeyCode::Home => self.start(),
+            KeyCode::End => self.end(),
+            KeyCode::Up => return Edit::Previous,
+            KeyCode::Down => return Edit::Next,
+            _ => {}
+        }
+        Edit::Edited
+    }
+
+    fn delete_range(&mut self, start: usize, end: usize) {
+        self.text.replace_range(start..end, "");
+        self.cursor = start;
+    }
+
+    // The boundary of the character before `at`
+    fn before(&self, at: usize) -> usize {
+        self.text[..at]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(index, _)| index)
+    }
+
+    // The boundary of the character after `at`
+    fn after(&self, at: usize) -> usize {
+        self.text[at..]
+            .chars()
+            .next()
+            .map_or(at, |c| at + c.len_utf8())
+    }
+
+    // Where the word before `at` starts, skipping what isn't in a word first
+    fn word_start(&self, at: usize, in_word: impl Fn(char) -> bool) -> usize {
+        let mut chars = self.text[..at].char_indices().rev().peekable();
+        while chars.next_if(|&(_, c)| !in_word(c)).is_some() {}
+        let mut start = chars.peek().map_or(0, |&(index, _)| index);
+        for (index, c) in chars {
+            if !in_word(c) {
+                break;
+            }
+            start = index;
+        }
+        start.min(at)
+    }
+
+    // Where the word after `at` ends, skipping what isn't in a word first
+    fn word_end(&self, at: usize) -> usize {
+        let rest = &self.text[at..];
+        let mut chars = rest.char_indices().peekable();
+        while chars.next_if(|&(_, c)| !is_word(c)).is_some() {}
+        while chars.next_if(|&(_, c)| is_word(c)).is_some() {}
+        at + chars.peek().map_or(rest.len(), |&(index, _)| index)
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Where a line shows on a terminal `columns` wide, in rows and columns
+/// from the start of its prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub end_row: usize,
+    pub end_column: usize,
+    pub cursor_row: usize,
+    pub cursor_column: usize,
+}
+
+impl Layout {
+    pub fn of(prompt_width: usize, line: &LineBuffer, columns: usize) -> Self {
+        let columns = columns.max(1);
+        let end = prompt_width + shown(line.text()).width();
+        let cursor = prompt_width + shown(&line.text()[..line.cursor()]).width();
+        Self {
+            end_row: end / columns,
+            end_column: end % columns,
+            cursor_row: cursor / columns,
+            cursor_column: cursor % columns,
+        }
+    }
+}
+
+// The text as drawn, with its line breaks on the same line
+fn shown(text: &str) -> String {
+    text.replace('\n', LINE_BREAK)
+}
+
+/// How reading a line ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadLine {
+    Line(String),
+    /// Ctrl+C dropped what was typed
+    Interrupted,
+    /// Ctrl+D, or Ctrl+C on an empty line, ended the input
+    Eof,
+}
+
+/// Reads lines at a prompt with the keys of [`LineBuffer::apply`], and Up
+/// and Down going through the history
+#[derive(Debug)]
+pub struct LineEditor {
+    prompt: String,
+    prompt_width: usize,
+    color: String,
+    browsing: HistoryCursor,
+    // The row the cursor was drawn on, counted from the prompt's
+    cursor_row: usize,
+}
+
+impl LineEditor {
+    /// An editor showing `prompt` in `color`
+    pub fn new(prompt: &str, color: &str) -> Self {
+        Self {
+            prompt: style::paint(color, prompt),
+            prompt_width: prompt.width(),
+            color: color.to_string(),
+            browsing: HistoryCursor::default(),
+            cursor_row: 0,
+        }
+    }
+
+    /// Show `prompt` from the next line on
+    pub fn set_prompt(&mut self, prompt: &str) {
+        self.prompt = style::paint(&self.color, prompt);
+        self.prompt_width = prompt.width();
+    }
+
+    /// Whether lines can be edited: both ends are terminals, and not dumb ones
+    pub fn available() -> bool {
+        io::stdin().is_terminal()
+            && io::stdout().is_terminal()
+            && env::var("TERM").map_or(true, |term| term != "dumb")
+    }
+
+    /// Read a line, going through `history` with Up and Down
+    pub fn read(&mut self, history: &InputHistory) -> io::Result<ReadLine> {
+        let _raw = RawMode::enable()?;
+        let mut out = io::stdout();
+        let mut line = LineBuffer::new();
+        self.browsing.reset();
+        self.cursor_row = 0;
+        self.draw(&mut out, &line)?;
+
+        let read = loop {
+            let edit = match event::read()? {
+                Event::Key(key) if key.kind != KeyEventKind::Release => line.apply(key),
+                Event::Paste(text) => {
+                    line.insert_str(&text);
+                    Edit::Edited
+                }
+                Event::Resize(..) => Edit::Edited,
+                _ => continue,
+            };
+            match edit {
+                Edit::Submit => break ReadLine::Line(line.take()),
+                Edit::Cancel if line.is_empty() => break ReadLine::Eof,
+                Edit::Cancel => break ReadLine::Interrupted,
+                Edit::Eof => break ReadLine::Eof,
+                Edit::Previous => {
+                    if let Some(entry) = self.browsing.previous(history, line.text()) {
+                        line.set(entry);
+                    }
+                }
+                Edit::Next => {
+                    if let Some(entry) = self.browsing.next(history) {
+                        line.set(entry);
+                    }
+                }
+                Edit::Edited => {}
+            }
+            self.draw(&mut out, &line)?;
+        };
+
+        // Leave the cursor on the line after the input, whatever was typed
+        line.end();
+        self.draw(&mut out, &line)?;
+        write!(out, "\r\n")?;
+        out.flush()?;
+        Ok(read)
+    }
+
+    // Draw the prompt and the line over what was drawn before
+    fn draw(&mut self, out: &mut impl Write, line: &LineBuffer) -> io::Result<()> {
+        let columns = terminal::size().map_or(80, |(columns, _)| columns as usize);
+        let layout = Layout::of(self.prompt_width, line, columns);
+        if self.cursor_row > 0 {
+            queue!(out, MoveUp(self.cursor_row as u16))?;
+        }
+        queue!(out, MoveToColumn(0), Clear(ClearType::FromCursorDown))?;
+        write!(out, "{}{}", self.prompt, shown(line.text()))?;
+        // A line that fills its last row leaves the cursor there until
+        // something more is written
+        if layout.end_column == 0 && layout.end_row > 0 {
+            write!(out, " \r")?;
+        }
+        if layout.end_row > layout.cursor_row {
+            queue!(out, MoveUp((layout.end_row - layout.cursor_row) as u16))?;
+        } else if layout.cursor_row > layout.end_row {
+            queue!(out, MoveDown((layout.cursor_row - layout.end_row) as u16))?;
+        }
+        queue!(out, MoveToColumn(layout.cursor_column as u16))?;
+        out.flush()?;
+        self.cursor_row = layout.cursor_row;
+        Ok(())
+    }
+}
+
+// Raw mode and bracketed paste, for as long as a line is read
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        // Terminals without bracketed paste send pasted text as keys
+        let _ = execute!(io::stdout(), EnableBracketedPaste);
+        Ok(Self)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), DisableBracketedPaste);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn ctrl(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    fn alt(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::ALT)
+    }
+
+    fn typed(text: &str) -> LineBuffer {
+        let mut line = LineBuffer::new();
+        for c in text.chars() {
+            assert_eq!(line.apply(key(KeyCode::Char(c))), Edit::Edited);
+        }
+        line
+    }
+
+    #[test]
+    fn test_cursor_moves_by_character_and_word() {
+        let mut line = typed("héllo wörld");
+        assert_eq!(line.cursor(), line.text().len());
+
+        line.apply(key(KeyCode::Left));
+        line.apply(key(KeyCode::Left));
+        line.apply(key(KeyCode::Left));
+        line.apply(key(KeyCode::Char('X')));
+        assert_eq!(line.text(), "héllo wöXrld");
+
+        line.apply(ctrl('a'));
+        assert_eq!(line.cursor(), 0);
+        line.apply(key(KeyCode::Left));
+        assert_eq!(line.cursor(), 0);
+        line.apply(ctrl('f'));
+        line.apply(key(KeyCode::Right));
+        line.apply(key(KeyCode::Char('!')));
+        assert_eq!(line.text(), "hé!llo wöXrld");
+
+        line.apply(alt(KeyCode::Char('f')));
+        assert_eq!(&line.text()[line.cursor()..], " wöXrld");
+        line.apply(alt(KeyCode::Char('f')));
+        assert_eq!(line.cursor(), line.text().len());
+        line.apply(key(KeyCode::Right));
+        assert_eq!(line.cursor(), line.text().len());
+        line.apply(KeyEvent::new(KeyCode::Left, KeyModifiers::CONTROL));
+        assert_eq!(&line.text()[line.cursor()..], "wöXrld");
+        line.apply(alt(KeyCode::Char('b')));
+        assert_eq!(&line.text()[line.cursor()..], "llo wöXrld");
+        line.apply(alt(KeyCode::Char('b')));
+        assert_eq!(line.cursor(), 0);
+
+        line.apply(ctrl('e'));
+        assert_eq!(line.cursor(), line.text().len());
+        line.apply(key(KeyCode::Home));
+        line.apply(key(KeyCode::Delete));
+        line.apply(key(KeyCode::End));
+        line.apply(key(KeyCode::Backspace));
+        assert_eq!(line.text(), "é!llo wöXrl");
+    }
+
+    #[test]
+    fn test_words_and_ends_are_deleted() {
+        let mut line = typed("git commit -m 'fix it'");
+        line.apply(ctrl('w'));
+        assert_eq!(line.text(), "git commit -m 'fix ");
+        line.apply(alt(KeyCode::Backspace));
+        assert_eq!(line.text(), "git commit -m '");
+        line.apply(alt(KeyCode::Backspace));
+        assert_eq!(line.text(), "git commit -");
+        line.apply(ctrl('w'));
+        assert_eq!(line.text(), "git commit ");
+
+        line.apply(ctrl('a'));
+        line.apply(alt(KeyCode::Char('d')));
+        assert_eq!(line.text(), " commit ");
+        line.apply(ctrl('k'));
+        assert!(line.is_empty());
+
+        let mut line = typed("keep this, drop that");
+        for _ in 0.."drop that".len() {
+            line.apply(key(KeyCode::Left));
+        }
+        line.apply(ctrl('u'));
+        assert_eq!((line.text(), line.cursor()), ("drop that", 0));
+        line.apply(ctrl('d'));
+        assert_eq!(line.text(), "rop that");
+    }
+
+    #[test]
+    fn test_pastes_stay_in_one_line_until_enter() {
+        let mut line = typed("Explain  please");
+        for _ in 0.." please".len() {
+            line.apply(key(KeyCode::Left));
+        }
+        line.insert_str("this:\r\nfn main() {}\r\n");
+        assert_eq!(line.text(), "Explain this:\nfn main() {}\n please");
+        assert_eq!(&line.text()[line.cursor()..], " please");
+
+        line.apply(key(KeyCode::Backspace));
+        line.apply(alt(KeyCode::Enter));
+        assert_eq!(line.text(), "Explain this:\nfn main() {}\n please");
+        assert_eq!(line.apply(key(KeyCode::Enter)), Edit::Submit);
+        assert_eq!(line.take(), "Explain this:\nfn main() {}\n please");
+        assert!(line.is_empty());
+    }
+
+    #[test]
+    fn test_keys_that_end_the_line() {
+        let mut line = LineBuffer::new();
+        assert_eq!(line.apply(ctrl('d')), Edit::Eof);
+        assert_eq!(line.apply(ctrl('c')), Edit::Cancel);
+        assert_eq!(line.apply(key(KeyCode::Up)), Edit::Previous);
+        assert_eq!(line.apply(ctrl('n')), Edit::Next);
+        // Control keys without a binding insert nothing
+        assert_eq!(line.apply(ctrl('z')), Edit::Edited);
+        assert!(line.is_empty());
+    }
+
+    #[test]
+    fn test_layout_wraps_at_the_terminal_width() {
+        let line = typed("0123456789界");
+        assert_eq!(
+            Layout::of(6, &line, 10),
+            Layout {
+                end_row: 1,
+                end_column: 8,
+                cursor_row: 1,
+                cursor_column: 8,
+            }
+        );
+
+        let mut line = typed("one");
+        line.insert_str("\ntwo");
+        line.start();
+        let layout = Layout::of(6, &line, 80);
+        assert_eq!((layout.end_column, layout.cursor_column), (13, 6));
+    }
+}
@@ -0,0 +1,417 @@
+//! Clickable links to the files and web pages an answer mentions.
+//!
+//! In the CLI, http(s) URLs and the paths of files that exist are wrapped in
+//! OSC 8 hyperlinks, which terminals such as iTerm2, WezTerm, kitty and
+//! recent VTE terminals let you click. Terminals that don't understand them
+//! would show the escape sequences, so they are only written where support is
+//! detected, and never with colors off or when output isn't a terminal;
+//! `FORCE_HYPERLINK=1` or `0` overrides the detection. The TUI can't emit
+//! them in place, so it opens a link with the system opener instead.
+
+use std::env;
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::style;
+use crate::tools::policy::ExecutionPolicy;
+
+lazy_static! {
+    // Up to whitespace, quotes, angle brackets and closing brackets, which
+    // more often end a URL in prose or Markdown than belong to it
+    static ref URL: Regex = Regex::new(r#"https?://[^\s<>"'`()\[\]{}]+"#).unwrap();
+    // A run of the characters paths are usually made of
+    static ref PATH: Regex = Regex::new(r"[A-Za-z0-9_@%+=~./-]+").unwrap();
+    // Version numbers such as 1.2.3, v0.24.0 or 2.0.1-beta.2, which look like file names
+    static ref VERSION: Regex = Regex::new(r"^v?\d+(\.\d+)+([-+][0-9A-Za-z.-]+)?$").unwrap();
+    // A file name's extension, e.g. ".rs" or ".tar.gz", but not ".5"
+    static ref EXTENSION: Regex = Regex::new(r"\.[A-Za-z][A-Za-z0-9]*$").unwrap();
+}
+
+/// Where a link points
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Url(String),
+    /// A file or directory, made absolute
+    Path(PathBuf),
+}
+
+impl Target {
+    /// The target as a URI, `file://` for paths
+    pub fn uri(&self) -> String {
+        match self {
+            Self::Url(url) => url.clone(),
+            Self::Path(path) => file_uri(path),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Url(url) => write!(f, "{}", url),
+            Self::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// A link found in some text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    /// The bytes of the text that make up the link
+    pub range: Range<usize>,
+    pub target: Target,
+}
+
+/// The URLs in `text` and the paths of files that exist, relative ones
+/// resolved against `base`, in the order they appear
+pub fn find(text: &str, base: &Path) -> Vec<Link> {
+    let mut links: Vec<Link> = URL
+        .find_iter(text)
+        .map(|found| {
+            let url = trim_end(found.as_str());
+            Link {
+                range: found.start()..found.start() + url.len(),
+                target: Target::Url(url.to_string()),
+            }
+        })
+        .collect();
+
+    let urls: Vec<Range<usize>> = links.iter().map(|link| link.range.clone()).collect();
+    for found in PATH.find_iter(text) {
+        if urls
+            .iter()
+            .any(|url| found.start() < url.end && url.start < found.end())
+        {
+            continue;
+        }
+        let candidate = trim_end(found.as_str());
+        if let Some(path) = existing_path(candidate, base) {
+            links.push(Link {
+                range: found.start()..found.start() + candidate.len(),
+                target: Target::Path(path),
+            });
+        }
+    }
+    links.sort_by_key(|link| link.range.start);
+    links
+}
+
+// Drop punctuation that ends the sentence rather than the link
+fn trim_end(text: &str) -> &str {
+    text.trim_end_matches(['.', ',', ';', ':', '!', '?'])
+}
+
+// The file `candidate` names, if it looks like a path and exists
+fn existing_path(candidate: &str, base: &Path) -> Option<PathBuf> {
+    if !looks_like_path(candidate) {
+        return None;
+    }
+    let path = match candidate.strip_prefix("~/") {
+        Some(rest) => PathBuf::from(env::var_os("HOME")?).join(rest),
+        None => base.join(candidate),
+    };
+    path.exists().then_some(path)
+}
+
+/// Whether `candidate` reads as a path: it has a directory or an extension
+/// and isn't a version number. Bare words such as "src" are left alone, as
+/// they are far more often just words
+pub fn looks_like_path(candidate: &str) -> bool {
+    let has_directory = candidate.trim_matches('/').contains('/')
+        || candidate.starts_with("./")
+        || candidate.starts_with("../")
+        || candidate.starts_with("~/");
+    let has_extension = EXTENSION.is_match(candidate);
+    (has_directory || has_extension)
+        && !VERSION.is_match(candidate)
+        && !candidate.starts_with('-')
+        && !candidate.starts_with("//")
+}
+
+/// `text` with each link in it made clickable
+pub fn linkify(text: &str, links: &[Link]) -> String {
+    let mut linked = String::with_capacity(text.len());
+    let mut end = 0;
+    for link in links {
+        linked.push_str(&text[end..link.range.start]);
+        linked.push_str(&hyperlink(&link.target.uri(), &text[link.range.clone()]));
+        end = link.range.end;
+    }
+    linked.push_str(&text[end..]);
+    linked
+}
+
+/// The OSC 8 escape sequence showing `text` as a link to `uri`
+pub fn hyperlink(uri: &str, text: &str) -> String {
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", uri, text)
+}
+
+/// A `file://` URI for an absolute `path`, with anything but unreserved
+/// characters and slashes percent-encoded
+pub fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+/// Whether the terminal shows OSC 8 hyperlinks, judging by the environment
+/// variables `var` looks up
+pub fn supports_hyperlinks(var: impl Fn(&str) -> Option<String>) -> bool {
+    if let Some(force) = var("FORCE_HYPERLINK").filter(|force| !force.is_empty()) {
+        return force != "0";
+    }
+    let term = var("TERM").unwrap_or_default();
+    if term == "dumb" {
+        return false;
+    }
+    let program = var("TERM_PROGRAM").unwrap_or_default();
+    let vte = var("VTE_VERSION")
+        .and_then(|version| version.parse::<u32>().ok())
+        .unwrap_or(0);
+
+    [
+        "iTerm.app",
+        "WezTerm",
+        "vscode",
+        "Hyper",
+        "ghostty",
+        "Tabby",
+    ]
+    .contains(&program.as_str())
+        || ["kitty", "wezterm", "foot", "alacritty", "ghostty"]
+            .iter()
+            .any(|name| term.contains(name))
+        || [
+            "KITTY_WINDOW_ID",
+            "WEZTERM_EXECUTABLE",
+            "WT_SESSION",
+            "KONSOLE_VERSION",
+        ]
+        .iter()
+        .any(|name| var(name).is_some())
+        || vte >= 5000
+}
+
+/// Makes the links in output clickable, where the terminal can show them
+#[derive(Debug, Clone)]
+pub struct Linker {
+    enabled: bool,
+    // What relative paths are relative to
+    base: PathBuf,
+}
+
+impl Linker {
+    /// Links resolved against `base`, the current directory without one,
+    /// for the terminal output meant for people goes to
+    pub fn for_terminal(base: Option<&Path>) -> Self {
+        let base = base
+            .map(Path::to_path_buf)
+            .or_else(|| env::current_dir().ok())
+            .unwrap_or_default();
+        Self::new(
+            style::enabled()
+                && style::human_output_is_terminal()
+                && supports_hyperlinks(|name| env::var(name).ok()),
+            base,
+        )
+    }
+
+    pub fn new(enabled: bool, base: PathBuf) -> Self {
+        Self { enabled, base }
+    }
+
+    /// `text` with its links made clickable, or as it is when links are off
+    pub fn linkify(&self, text: &str) -> String {
+        if !self.enabled {
+            return text.to_string();
+        }
+        linkify(text, &find(text, &self.base))
+    }
+}
+
+/// Open `target` with the system opener, `open` on macOS and `xdg-open`
+/// elsewhere. Paths outside the sandbox root are refused
+pub fn open(target: &Target, policy: &ExecutionPolicy) -> Result<()> {
+    let argument = match target {
+        Target::Url(url) => url.clone(),
+        Target::Path(path) => {
+            policy.check_sandboxed(path)?;
+            path.to_string_lossy().into_owned()
+        }
+    };
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    let mut child = Command::new(opener)
+        .arg(&argument)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", opener))?;
+    // The opener hands off to the application and exits; reap it without waiting here
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+
+    fn targets(text: &str, base: &Path) -> Vec<(String, Target)> {
+        find(text, base)
+            .into_iter()
+            .map(|link| (text[link.range.clone()].to_string(), link.target))
+            .collect()
+    }
+
+    #[test]
+    fn test_urls_are_found_without_trailing_punctuation() {
+        let base = Path::new("/nonexistent");
+        let text = "See https://docs.rs/regex. Or [the book](https://doc.rust-lang.org/book/ch01-01.html), \
+                    and http://localhost:11434/api/tags?x=1&y=2!";
+        assert_eq!(
+            targets(text, base),
+            vec![
+                (
+                    "https://docs.rs/regex".to_string(),
+                    Target::Url("https://docs.rs/regex".to_string())
+                ),
+                (
+                    "https://doc.rust-lang.org/book/ch01-01.html".to_string(),
+                    Target::Url("https://doc.rust-lang.org/book/ch01-01.html".to_string())
+                ),
+                (
+                    "http://localhost:11434/api/tags?x=1&y=2".to_string(),
+                    Target::Url("http://localhost:11434/api/tags?x=1&y=2".to_string())
+                ),
+            ]
+        );
+        assert!(find("ftp://example.com and https:// alone", base).is_empty());
+    }
+
+    #[test]
+    fn test_only_existing_paths_are_links() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir_all(dir.path().join("src/tui"))?;
+        fs::write(dir.path().join("src/main.rs"), "")?;
+        fs::write(dir.path().join("Cargo.toml"), "")?;
+
+        let text = "Edit src/main.rs:42 and `Cargo.toml`, see src/tui/, not src/lib.rs.";
+        assert_eq!(
+            targets(text, dir.path()),
+            vec![
+                (
+                    "src/main.rs".to_string(),
+                    Target::Path(dir.path().join("src/main.rs"))
+                ),
+                (
+                    "Cargo.toml".to_string(),
+                    Target::Path(dir.path().join("Cargo.toml"))
+                ),
+                (
+                    "src/tui/".to_string(),
+                    Target::Path(dir.path().join("src/tui/"))
+                ),
+            ]
+        );
+
+        let absolute = dir.path().join("Cargo.toml");
+        let text = format!("Wrote {}.", absolute.display());
+        assert_eq!(
+            targets(&text, Path::new("/elsewhere")),
+            vec![(
+                absolute.display().to_string(),
+                Target::Path(absolute.clone())
+            )]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_versions_and_words_are_not_paths() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for name in ["1.2.3", "v0.24.0", "src"] {
+            fs::create_dir(dir.path().join(name))?;
+        }
+        let text = "Upgrade to 1.2.3 or v0.24.0 in src, e.g. with --features=x.y";
+        assert!(find(text, dir.path()).is_empty());
+
+        assert!(!looks_like_path("2.0.1-beta.2"));
+        assert!(!looks_like_path("3.14"));
+        assert!(!looks_like_path("//comment"));
+        assert!(looks_like_path("README.md"));
+        assert!(looks_like_path("./run"));
+        assert!(looks_like_path("docs/v1.2.3"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_links_are_wrapped_in_osc8() {
+        assert_eq!(
+            hyperlink("https://example.com", "example"),
+            "\x1b]8;;https://example.com\x1b\\example\x1b]8;;\x1b\\"
+        );
+        assert_eq!(
+            file_uri(Path::new("/home/me/My Notes/ü.md")),
+            "file:///home/me/My%20Notes/%C3%BC.md"
+        );
+
+        let text = "Read https://a.io now";
+        let links = find(text, Path::new("/nonexistent"));
+        assert_eq!(
+            linkify(text, &links),
+            "Read \x1b]8;;https://a.io\x1b\\https://a.io\x1b]8;;\x1b\\ now"
+        );
+        let off = Linker::new(false, PathBuf::from("/"));
+        assert_eq!(off.linkify(text), text);
+    }
+
+    #[test]
+    fn test_hyperlink_support_is_detected_from_the_environment() {
+        let detect = |vars: &[(&str, &str)]| {
+            let vars: HashMap<String, String> = vars
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            supports_hyperlinks(|name| vars.get(name).cloned())
+        };
+        assert!(detect(&[("TERM_PROGRAM", "iTerm.app")]));
+        assert!(detect(&[("TERM_PROGRAM", "WezTerm")]));
+        assert!(detect(&[("TERM", "xterm-kitty")]));
+        assert!(detect(&[("VTE_VERSION", "7200")]));
+        assert!(!detect(&[("VTE_VERSION", "4600")]));
+        assert!(!detect(&[("TERM", "xterm-256color")]));
+        assert!(!detect(&[("TERM", "dumb"), ("TERM_PROGRAM", "WezTerm")]));
+        assert!(detect(&[("TERM", "xterm"), ("FORCE_HYPERLINK", "1")]));
+        assert!(!detect(&[
+            ("TERM_PROGRAM", "iTerm.app"),
+            ("FORCE_HYPERLINK", "0")
+        ]));
+    }
+
+    #[test]
+    fn test_paths_outside_the_sandbox_are_not_opened() {
+        let policy = ExecutionPolicy::new();
+        policy.set_sandbox_root(PathBuf::from("/srv/app"));
+        let error = open(&Target::Path(PathBuf::from("/etc/passwd")), &policy).unwrap_err();
+        assert!(error.to_string().contains("outside the sandbox root"));
+    }
+}
@@ -0,0 +1,443 @@
+use std::fmt;
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use tokio_stream::StreamExt;
+
+use crate::llm::failover::FailoverClient;
+use crate::llm::mock::MockLlmClient;
+use crate::llm::ollama::OllamaClient;
+
+// Maximum number of installed models suggested in place of a missing one
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Progress of a model download
+#[derive(Debug, Clone, PartialEq)]
+pub struct PullProgress {
+    pub status: String,
+    pub completed: Option<u64>,
+    pub total: Option<u64>,
+}
+
+impl PullProgress {
+    /// Percentage downloaded of the current layer, if known
+    pub fn percent(&self) -> Option<f64> {
+        match (self.completed, self.total) {
+            (Some(completed), Some(total)) if total > 0 => {
+                Some(completed as f64 / total as f64 * 100.0)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PullProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.percent(), self.completed, self.total) {
+            (Some(percent), Some(completed), Some(total)) => write!(
+                f,
+                "{}: {:.0}% ({} / {} MB)",
+                self.status,
+                percent,
+                completed / 1_000_000,
+                total / 1_000_000
+            ),
+            _ => write!(f, "{}", self.status),
+        }
+    }
+}
+
+/// Where [`ensure_model`] tells the user about a missing model, asks to pull
+/// it and shows the download; each frontend decides how
+pub trait PullConsole: Send {
+    /// Warn about the model, e.g. that it isn't installed
+    fn warn(&mut self, text: &str);
+
+    /// Whether to pull `model`; false when no one can be asked
+    fn confirm_pull(&mut self, model: &str) -> bool;
+
+    /// Show how far the download got
+    fn progress(&mut self, progress: &PullProgress);
+
+    /// The download of `model` finished
+    fn pulled(&mut self, model: &str);
+}
+
+/// The model list and pull calls of a model server, so they can be mocked
+#[async_trait]
+pub trait ModelRegistry: Send + Sync {
+    /// Names of the models installed locally, e.g. "llama3.2:latest"
+    async fn installed_models(&self) -> Result<Vec<String>>;
+
+    /// Download a model, reporting progress as it goes
+    async fn pull(
+        &self,
+        model: &str,
+        on_progress: &mut (dyn FnMut(PullProgress) + Send),
+    ) -> Result<()>;
+}
+
+#[async_trait]
+impl ModelRegistry for OllamaClient {
+    async fn installed_models(&self) -> Result<Vec<String>> {
+        let models = self
+            .ollama()
+            .list_local_models()
+            .await
+            .context("Failed to list local Ollama models")?;
+        Ok(models.into_iter().map(|model| model.name).collect())
+    }
+
+    async fn pull(
+        &self,
+        model: &str,
+        on_progress: &mut (dyn FnMut(PullProgress) + Send),
+    ) -> Result<()> {
+        let mut stream = self
+            .ollama()
+            .pull_model_stream(model.to_string(), false)
+            .await
+            .with_context(|| format!("Failed to pull model '{}'", model))?;
+
+        while let Some(status) = stream.next().await {
+            let status = status.with_context(|| format!("Failed to pull model '{}'", model))?;
+            on_progress(PullProgress {
+                status: status.message,
+                completed: status.completed,
+                total: status.total,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+// Models are listed and pulled on whichever server is active
+#[async_trait]
+impl ModelRegistry for MockLlmClient {
+    async fn installed_models(&self) -> Result<Vec<String>> {
+        Ok(self.models()?.to_vec())
+    }
+
+    async fn pull(
+        &self,
+        model: &str,
+        _on_progress: &mut (dyn FnMut(PullProgress) + Send),
+    ) -> Result<()> {
+        Err(anyhow!("The mock client cannot pull '{}'", model))
+    }
+}
+
+#[async_trait]
+impl ModelRegistry for FailoverClient {
+    async fn installed_models(&self) -> Result<Vec<String>> {
+        active_registry(self)?.installed_models().await
+    }
+
+    async fn pull(
+        &self,
+        model: &str,
+        on_progress: &mut (dyn FnMut(PullProgress) + Send),
+    ) -> Result<()> {
+        active_registry(self)?.pull(model, on_progress).await
+    }
+}
+
+fn active_registry(client: &FailoverClient) -> Result<&dyn ModelRegistry> {
+    let active = client.active_client().as_any();
+    if let Some(ollama) = active.downcast_ref::<OllamaClient>() {
+        return Ok(ollama);
+    }
+    if let Some(mock) = active.downcast_ref::<MockLlmClient>() {
+        return Ok(mock);
+    }
+    Err(anyhow!(
+        "{} is not an Ollama server",
+        client.active().address()
+    ))
+}
+
+/// Whether a model is installed, with close matches when it is not
+#[derive(Debug, Clone, PartialEq)]
+pub enum Availability {
+    Installed,
+    Missing { suggestions: Vec<String> },
+}
+
+/// The error of `ensure_model` when the model is missing and isn't pulled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotInstalled {
+    pub model: String,
+}
+
+impl fmt::Display for NotInstalled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Model '{}' is not installed. Run `ollama pull {}` or pass --pull to download it",
+            self.model, self.model
+        )
+    }
+}
+
+impl std::error::Error for NotInstalled {}
+
+/// Check a model against the installed model list
+pub async fn check<R: ModelRegistry + ?Sized>(registry: &R, model: &str) -> Result<Availability> {
+    let installed = registry.installed_models().await?;
+
+    let wanted = normalize(model);
+    if installed.iter().any(|name| normalize(name) == wanted) {
+        return Ok(Availability::Installed);
+    }
+
+    Ok(Availability::Missing {
+        suggestions: suggestions(model, &installed),
+    })
+}
+
+/// Installed models that are close to the requested one: same family first, then by edit distance
+pub fn suggestions(model: &str, installed: &[String]) -> Vec<String> {
+    let wanted = normalize(model);
+    let family = family(&wanted);
+
+    let mut scored: Vec<(usize, &String)> = installed
+        .iter()
+        .filter_map(|name| {
+            let candidate = normalize(name);
+            if family == self::family(&candidate) {
+                return Some((0, name));
+            }
+
+            let distance = edit_distance(family, self::family(&candidate));
+            (distance <= family.len().max(3) / 3).then_some((distance, name))
+        })
+        .collect();
+
+    scored.sort();
+    scored
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, name)| name.clone())
+        .collect()
+}
+
+/// Make sure a model is installed before the first request, offering to pull it.
+///
+/// With `auto_pull` the model is pulled without asking; otherwise `console`
+/// asks the user. If the model server can't be reached the check is skipped
+/// so the request itself reports the connection error.
+pub async fn ensure_model<R: ModelRegistry + ?Sized>(
+    registry: &R,
+    model: &str,
+    auto_pull: bool,
+    console: &mut dyn PullConsole,
+) -> Result<()> {
+    let suggestions = match check(registry, model).await {
+        Ok(Availability::Installed) => return Ok(()),
+        Ok(Availability::Missing { suggestions }) => suggestions,
+        Err(e) => {
+            console.warn(&format!("Warning: could not check installed models: {}", e));
+            return Ok(());
+        }
+    };
+
+    console.warn(&format!("Model '{}' is not installed locally.", model));
+    if !suggestions.is_empty() {
+        console.warn(&format!(
+            "Installed models with a similar name: {}",
+            suggestions.join(", ")
+        ));
+    }
+
+    let pull = auto_pull || console.confirm_pull(model);
+    if !pull {
+        return Err(NotInstalled {
+            model: model.to_string(),
+        }
+        .into());
+    }
+
+    registry
+        .pull(model, &mut |progress| console.progress(&progress))
+        .await?;
+    console.pulled(model);
+
+    Ok(())
+}
+
+// Ollama treats a missing tag as ":latest"
+pub(crate) fn normalize(model: &str) -> String {
+    if model.contains(':') {
+        model.to_string()
+    } else {
+        format!("{}:latest", model)
+    }
+}
+
+// Model name without its tag
+fn family(model: &str) -> &str {
+    model.split(':').next().unwrap_or(model)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct MockRegistry {
+        installed: Vec<String>,
+        pulled: Mutex<Vec<String>>,
+    }
+
+    impl MockRegistry {
+        fn new(installed: &[&str]) -> Self {
+            Self {
+                installed: installed.iter().map(|s| s.to_string()).collect(),
+                pulled: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    // A console that writes down what it is told and answers `pull`
+    #[derive(Default)]
+    struct Noted {
+        pull: bool,
+        lines: Vec<String>,
+    }
+
+    impl PullConsole for Noted {
+        fn warn(&mut self, text: &str) {
+            self.lines.push(text.to_string());
+        }
+
+        fn confirm_pull(&mut self, model: &str) -> bool {
+            self.lines.push(format!("Pull '{}'?", model));
+            self.pull
+        }
+
+        fn progress(&mut self, progress: &PullProgress) {
+            self.lines.push(progress.to_string());
+        }
+
+        fn pulled(&mut self, model: &str) {
+            self.lines.push(format!("Pulled '{}'", model));
+        }
+    }
+
+    #[async_trait]
+    impl ModelRegistry for MockRegistry {
+        async fn installed_models(&self) -> Result<Vec<String>> {
+            Ok(self.installed.clone())
+        }
+
+        async fn pull(
+            &self,
+            model: &str,
+            on_progress: &mut (dyn FnMut(PullProgress) + Send),
+        ) -> Result<()> {
+            on_progress(PullProgress {
+                status: "downloading".to_string(),
+                completed: Some(50),
+                total: Some(100),
+            });
+            self.pulled.lock().unwrap().push(model.to_string());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_installed_model_matches_without_tag() -> Result<()> {
+        let registry = MockRegistry::new(&["llama3.2:latest"]);
+        assert_eq!(check(&registry, "llama3.2").await?, Availability::Installed);
+        assert_eq!(
+            check(&registry, "llama3.2:latest").await?,
+            Availability::Installed
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_missing_model_suggests_close_matches() -> Result<()> {
+        let registry = MockRegistry::new(&["llama3.2:3b", "llama3.1:8b", "qwen2.5:7b"]);
+
+        let availability = check(&registry, "llama3.2:latest").await?;
+        assert_eq!(
+            availability,
+            Availability::Missing {
+                suggestions: vec!["llama3.2:3b".to_string(), "llama3.1:8b".to_string()]
+            }
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_auto_pull_pulls_missing_model() -> Result<()> {
+        let registry = MockRegistry::new(&[]);
+        let mut console = Noted::default();
+        ensure_model(&registry, "mistral", true, &mut console).await?;
+        assert_eq!(
+            *registry.pulled.lock().unwrap(),
+            vec!["mistral".to_string()]
+        );
+        assert_eq!(
+            console.lines[0],
+            "Model 'mistral' is not installed locally."
+        );
+        assert_eq!(console.lines.last().unwrap(), "Pulled 'mistral'");
+
+        // Installed models are never pulled again
+        let registry = MockRegistry::new(&["mistral:latest"]);
+        let mut console = Noted::default();
+        ensure_model(&registry, "mistral", true, &mut console).await?;
+        assert!(registry.pulled.lock().unwrap().is_empty());
+        assert!(console.lines.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_a_declined_pull_leaves_the_model_missing() {
+        let registry = MockRegistry::new(&["mistral:7b"]);
+        let mut console = Noted::default();
+        let error = ensure_model(&registry, "mistral:latest", false, &mut console)
+            .await
+            .unwrap_err();
+        assert!(error.is::<NotInstalled>());
+        assert!(registry.pulled.lock().unwrap().is_empty());
+        assert_eq!(
+            console.lines,
+            [
+                "Model 'mistral:latest' is not installed locally.",
+                "Installed models with a similar name: mistral:7b",
+                "Pull 'mistral:latest'?",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pull_progress_percent() {
+        let progress = PullProgress {
+            status: "pulling".to_string(),
+            completed: Some(25),
+            total: Some(200),
+        };
+        assert_eq!(progress.percent(), Some(12.5));
+        assert_eq!(progress.to_string(), "pulling: 12% (0 / 0 MB)");
+    }
+}
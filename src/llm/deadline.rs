@@ -0,0 +1,145 @@
+//! Giving up on responses that take too long.
+//!
+//! A wedged server, such as one loading a model forever, would otherwise
+//! leave a request hanging with no end. The timeout of the
+//! [`ResponseLimits`] is kept around each request to Ollama and around a
+//! whole tool-calling turn. In [`TimeoutMode::Progress`] the clock of a turn
+//! restarts whenever a step finishes or a tool returns; a single request
+//! only counts as progress once it is answered.
+//!
+//! Running out of time is not a connection error, so the failover client
+//! doesn't try the request again on the next server and the wait is never
+//! multiplied.
+
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::time::Instant;
+
+use crate::llm::limits::{self, ResponseLimits, TimeoutMode};
+
+/// The error of a response that ran out of time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedOut {
+    pub after: Duration,
+}
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Generation timed out after {}",
+            limits::format_duration(self.after)
+        )
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Whether `error` is a response running out of time
+pub fn is_timeout(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.is::<TimedOut>())
+}
+
+/// When a response runs out of time
+#[derive(Debug, Clone)]
+pub struct Deadline {
+    timeout: Option<Duration>,
+    mode: TimeoutMode,
+    at: Option<Instant>,
+}
+
+impl Deadline {
+    /// Start the clock of a response under `limits`
+    pub fn start(limits: &ResponseLimits) -> Self {
+        Self {
+            timeout: limits.timeout,
+            mode: limits.timeout_mode,
+            at: limits.timeout.map(|timeout| Instant::now() + timeout),
+        }
+    }
+
+    /// Note that the response moved on, restarting the clock in progress mode
+    pub fn progress(&mut self) {
+        if self.mode == TimeoutMode::Progress {
+            self.at = self.timeout.map(|timeout| Instant::now() + timeout);
+        }
+    }
+
+    /// Wait until the time is up, which is never without a timeout
+    pub async fn expired(&self) -> TimedOut {
+        match (self.at, self.timeout) {
+            (Some(at), Some(after)) => {
+                tokio::time::sleep_until(at).await;
+                TimedOut { after }
+            }
+            _ => std::future::pending().await,
+        }
+    }
+
+    /// Run `request`, giving it up with [`TimedOut`] when the time is up
+    pub async fn run<T>(&self, request: impl Future<Output = Result<T>>) -> Result<T> {
+        tokio::select! {
+            result = request => result,
+            timed_out = self.expired() => Err(timed_out.into()),
+        }
+    }
+}
+
+/// Run a single request under the timeout of `limits`
+pub async fn within<T>(
+    limits: &ResponseLimits,
+    request: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    Deadline::start(limits).run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(millis: u64, mode: TimeoutMode) -> ResponseLimits {
+        ResponseLimits {
+            timeout: Some(Duration::from_millis(millis)),
+            timeout_mode: mode,
+            ..ResponseLimits::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_given_up_when_the_time_is_up() {
+        let error = within(
+            &limits(20, TimeoutMode::Fixed),
+            std::future::pending::<Result<()>>(),
+        )
+        .await
+        .unwrap_err();
+        assert!(is_timeout(&error));
+        assert_eq!(error.to_string(), "Generation timed out after 20ms");
+
+        let answer = within(&limits(1_000, TimeoutMode::Fixed), async { Ok(7) }).await;
+        assert_eq!(answer.unwrap(), 7);
+        let untimed = within(&ResponseLimits::default(), async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok(())
+        })
+        .await;
+        assert!(untimed.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_progress_restarts_the_clock_only_in_progress_mode() {
+        for (mode, survives) in [(TimeoutMode::Progress, true), (TimeoutMode::Fixed, false)] {
+            let mut deadline = Deadline::start(&limits(200, mode));
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            deadline.progress();
+            let step = deadline.run(async {
+                tokio::time::sleep(Duration::from_millis(150)).await;
+                Ok(())
+            });
+            assert_eq!(step.await.is_ok(), survives, "{:?}", mode);
+        }
+    }
+}
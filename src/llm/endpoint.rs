@@ -0,0 +1,304 @@
+//! Where an Ollama server is, from the environment or the config.
+//!
+//! Every way of naming a server goes through [`Endpoint::parse`]: a full URL
+//! such as `https://proxy.example.com/ollama`, a bare host with or without a
+//! port, an IPv6 literal in brackets, and the older form that gives the host
+//! and port apart, as `OLLAMA_HOST` and `OLLAMA_PORT` or the `host` and `port`
+//! of an `[[endpoints]]` table. A port written in the host wins over the one
+//! given apart, which wins over the default: 443 for `https://`, else 11434.
+
+use std::env;
+
+use anyhow::{anyhow, Result};
+use reqwest::Url;
+use serde::Deserialize;
+
+/// The port Ollama listens on unless told otherwise
+pub const DEFAULT_PORT: u16 = 11434;
+
+/// An Ollama server, e.g. from an `[[endpoints]]` config table
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "EndpointTable")]
+pub struct Endpoint {
+    /// The scheme and host, e.g. "http://192.168.1.20" or "http://[::1]"
+    pub host: String,
+    pub port: u16,
+    /// Where the API is served under the host, e.g. "/ollama" behind a
+    /// reverse proxy; empty for the root
+    pub path: String,
+    /// The model to use on this server instead of the session's
+    pub model: Option<String>,
+}
+
+// An `[[endpoints]]` table as written
+#[derive(Deserialize)]
+struct EndpointTable {
+    host: String,
+    port: Option<u16>,
+    model: Option<String>,
+}
+
+impl TryFrom<EndpointTable> for Endpoint {
+    type Error = anyhow::Error;
+
+    fn try_from(table: EndpointTable) -> Result<Self> {
+        Ok(Self {
+            model: table.model,
+            ..Self::parse(&table.host, table.port)?
+        })
+    }
+}
+
+impl Default for Endpoint {
+    fn default() -> Self {
+        Self::new("http://localhost", DEFAULT_PORT)
+    }
+}
+
+impl Endpoint {
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            path: String::new(),
+            model: None,
+        }
+    }
+
+    /// The server `text` names, on `port` unless `text` has a port of its own
+    pub fn parse(text: &str, port: Option<u16>) -> Result<Self> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(anyhow!("The Ollama host is empty"));
+        }
+        let (scheme, rest) = match text.split_once("://") {
+            Some((scheme, rest)) => (scheme.to_ascii_lowercase(), rest),
+            None => ("http".to_string(), text),
+        };
+        if scheme != "http" && scheme != "https" {
+            return Err(anyhow!(
+                "'{}' uses {}://, but Ollama is reached over http:// or https://",
+                text,
+                scheme
+            ));
+        }
+        if rest.contains(['?', '#', '@']) {
+            return Err(anyhow!(
+                "'{}' has a query, fragment or user name; give only the scheme, host, port and path",
+                text
+            ));
+        }
+        let (authority, path) = match rest.find('/') {
+            Some(at) => (&rest[..at], rest[at..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+
+        let (host, written_port) = split_port(text, authority)?;
+        check_host(text, &host)?;
+        let default = if scheme == "https" { 443 } else { DEFAULT_PORT };
+        Ok(Self {
+            host: format!("{}://{}", scheme, host),
+            port: written_port.or(port).unwrap_or(default),
+            path: path.to_string(),
+            model: None,
+        })
+    }
+
+    /// The server named by `OLLAMA_HOST`, and `OLLAMA_PORT` if its host has
+    /// no port, or localhost:11434
+    pub fn from_env() -> Result<Self> {
+        let port = match env::var("OLLAMA_PORT") {
+            Ok(port) => Some(port.trim().parse().map_err(|_| {
+                anyhow!(
+                    "OLLAMA_PORT is '{}', which is not a port from 0 to 65535",
+                    port
+                )
+            })?),
+            Err(_) => None,
+        };
+        match env::var("OLLAMA_HOST") {
+            Ok(host) if !host.trim().is_empty() => Self::parse(&host, port)
+                .map_err(|e| anyhow!("OLLAMA_HOST is not a server address: {:#}", e)),
+            _ => Ok(Self::new("http://localhost", port.unwrap_or(DEFAULT_PORT))),
+        }
+    }
+
+    /// "scheme://host:port/path", for the status bar and messages
+    pub fn address(&self) -> String {
+        format!("{}:{}{}", self.host, self.port, self.path)
+    }
+
+    /// The URL the API's paths are joined to, ending in a slash
+    pub fn url(&self) -> Result<Url> {
+        Url::parse(&format!("{}/", self.address()))
+            .map_err(|e| anyhow!("'{}' is not a server address: {}", self.address(), e))
+    }
+}
+
+// The host of `authority` and the port written after it, if any
+fn split_port(text: &str, authority: &str) -> Result<(String, Option<u16>)> {
+    let port = |port: &str| {
+        port.parse::<u16>().map(Some).map_err(|_| {
+            anyhow!(
+                "'{}' has the port '{}', which is not a number from 0 to 65535",
+                text,
+                port
+            )
+        })
+    };
+    if let Some(bracketed) = authority.strip_prefix('[') {
+        let (address, after) = bracketed.split_once(']').ok_or_else(|| {
+            anyhow!(
+                "'{}' opens an IPv6 address with '[' but doesn't close it",
+                text
+            )
+        })?;
+        let written = match after {
+            "" => None,
+            after => port(after.strip_prefix(':').ok_or_else(|| {
+                anyhow!(
+                    "'{}' has '{}' after its IPv6 address, where a port goes",
+                    text,
+                    after
+                )
+            })?)?,
+        };
+        return Ok((format!("[{}]", address), written));
+    }
+    match authority.matches(':').count() {
+        0 => Ok((authority.to_string(), None)),
+        1 => {
+            let (host, written) = authority.split_once(':').unwrap_or_default();
+            Ok((host.to_string(), port(written)?))
+        }
+        // An IPv6 address written without brackets, which can't have a port
+        _ => Ok((format!("[{}]", authority), None)),
+    }
+}
+
+// Refuse hosts no server could have
+fn check_host(text: &str, host: &str) -> Result<()> {
+    let valid = match host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+    {
+        Some(address) => address.parse::<std::net::Ipv6Addr>().is_ok(),
+        None => {
+            !host.is_empty()
+                && host
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || "-._".contains(c))
+        }
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!("'{}' is not a valid host name or address", text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(text: &str, port: Option<u16>) -> String {
+        Endpoint::parse(text, port).unwrap().address()
+    }
+
+    #[test]
+    fn test_bare_hosts_and_the_two_variable_form() {
+        assert_eq!(parsed("localhost", None), "http://localhost:11434");
+        assert_eq!(parsed("gpu-box", Some(11500)), "http://gpu-box:11500");
+        assert_eq!(parsed("http://localhost", Some(9)), "http://localhost:9");
+        assert_eq!(
+            parsed("192.168.1.20:11500", None),
+            "http://192.168.1.20:11500"
+        );
+    }
+
+    #[test]
+    fn test_full_urls_keep_their_port_and_path() {
+        // The port in the URL wins over the one given apart
+        assert_eq!(
+            parsed("http://gpu-box:11434", Some(11434)),
+            "http://gpu-box:11434"
+        );
+        assert_eq!(
+            parsed("http://gpu-box:8080/", Some(11434)),
+            "http://gpu-box:8080"
+        );
+        assert_eq!(parsed("HTTP://gpu-box", None), "http://gpu-box:11434");
+
+        let proxied = Endpoint::parse("https://proxy.example.com/ollama/", None).unwrap();
+        assert_eq!(proxied.host, "https://proxy.example.com");
+        assert_eq!(proxied.port, 443);
+        assert_eq!(proxied.path, "/ollama");
+        assert_eq!(
+            proxied.url().unwrap().join("api/tags").unwrap().as_str(),
+            "https://proxy.example.com/ollama/api/tags"
+        );
+        assert_eq!(
+            parsed("https://proxy.example.com:8443/a/b", None),
+            "https://proxy.example.com:8443/a/b"
+        );
+    }
+
+    #[test]
+    fn test_ipv6_literals() {
+        assert_eq!(parsed("[::1]", None), "http://[::1]:11434");
+        assert_eq!(
+            parsed("http://[fe80::1]:11500", None),
+            "http://[fe80::1]:11500"
+        );
+        assert_eq!(parsed("::1", Some(9)), "http://[::1]:9");
+        let url = Endpoint::parse("https://[2001:db8::2]/ollama", None)
+            .unwrap()
+            .url()
+            .unwrap();
+        assert_eq!(url.as_str(), "https://[2001:db8::2]/ollama/");
+    }
+
+    #[test]
+    fn test_invalid_addresses_say_what_is_wrong() {
+        let error = |text: &str| Endpoint::parse(text, None).unwrap_err().to_string();
+        assert_eq!(error("  "), "The Ollama host is empty");
+        assert!(error("ftp://host").contains("http:// or https://"));
+        assert!(error("host:port").contains("the port 'port'"));
+        assert!(error("host:70000").contains("not a number from 0 to 65535"));
+        assert!(error("gpu box").contains("not a valid host"));
+        assert!(error("[::1").contains("doesn't close it"));
+        assert!(error("[::1]x").contains("where a port goes"));
+        assert!(error("[not-v6]").contains("not a valid host"));
+        assert!(error("http://user@host").contains("user name"));
+        assert!(error("http://:11434").contains("not a valid host"));
+    }
+
+    #[test]
+    fn test_config_tables() {
+        #[derive(Deserialize)]
+        struct Tables {
+            endpoints: Vec<Endpoint>,
+        }
+        let tables: Tables = toml::from_str(
+            r#"
+            [[endpoints]]
+            host = "https://proxy.example.com/ollama"
+            model = "qwen2.5"
+
+            [[endpoints]]
+            host = "http://localhost"
+            port = 11500
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            tables.endpoints[0].address(),
+            "https://proxy.example.com:443/ollama"
+        );
+        assert_eq!(tables.endpoints[0].model.as_deref(), Some("qwen2.5"));
+        assert_eq!(tables.endpoints[1].address(), "http://localhost:11500");
+
+        let invalid = toml::from_str::<Tables>("[[endpoints]]\nhost = \"ftp://x\"");
+        assert!(invalid.is_err());
+    }
+}
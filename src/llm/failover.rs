@@ -0,0 +1,576 @@
+//! Falling back to other Ollama servers when one can't be reached.
+
+use std::any::Any;
+use std::env;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use ollama_rs::generation::parameters::KeepAlive;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::config::Config;
+use crate::llm::endpoint::Endpoint;
+use crate::llm::limits::ResponseLimits;
+use crate::llm::mock::MockLlmClient;
+use crate::llm::ollama::{
+    GenerationOptions, LlmClient, ModelTurn, OllamaClient, Tool, ToolInvocation,
+};
+use crate::llm::prefix::PromptUsage;
+use crate::llm::timings::ServerTimings;
+use crate::memory::Memory;
+use crate::privacy::RemoteGate;
+use crate::tools::journal::Journal;
+use crate::tools::progress::{Progress, ToolEvent};
+use crate::tools::review::Reviewer;
+use crate::Message;
+
+/// How long a server has to answer the health check before the next one is tried
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Set to make `connect` answer every request with a canned client instead
+/// of a server, so the binary can be tested without Ollama. The values
+/// `unreachable`, `missing-model`, `timeout` and `error` make it fail that
+/// way instead; any other value repeats each message back
+pub const FAKE_LLM_VAR: &str = "SENTINEL_FAKE_LLM";
+
+/// Whether a request failed because the server could not be reached, as
+/// opposed to the server answering with an error
+pub fn is_connection_error(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(error) = cause.downcast_ref::<reqwest::Error>() {
+            return error.is_connect();
+        }
+        matches!(
+            cause.downcast_ref::<std::io::Error>().map(|e| e.kind()),
+            Some(std::io::ErrorKind::ConnectionRefused | std::io::ErrorKind::ConnectionReset)
+        )
+    })
+}
+
+/// The error of `select` when none of the endpoints answers its health check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoneReachable {
+    /// Each endpoint's address and why its check failed
+    pub failures: Vec<String>,
+}
+
+impl fmt::Display for NoneReachable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "No Ollama endpoint is reachable ({})",
+            self.failures.join("; ")
+        )
+    }
+}
+
+impl std::error::Error for NoneReachable {}
+
+/// A client that talks to the first reachable of several servers.
+///
+/// It keeps using that server until a request can't reach it, then retries
+/// the request on the next ones in order and stays with the one that answers.
+pub struct FailoverClient {
+    clients: Vec<(Endpoint, Box<dyn LlmClient>)>,
+    active: AtomicUsize,
+    // Switches not reported yet, taken with `take_notices`
+    notices: Mutex<Vec<String>>,
+}
+
+impl FailoverClient {
+    /// Use a client per endpoint, tried in order. Sending local content to
+    /// a remote server is agreed to per server, whichever client sends it
+    pub fn new(clients: Vec<(Endpoint, Box<dyn LlmClient>)>) -> Result<Self> {
+        if clients.is_empty() {
+            return Err(anyhow!("At least one endpoint is needed"));
+        }
+        let gate = Arc::new(RemoteGate::default());
+        for (_, client) in &clients {
+            client.set_remote_gate(Arc::clone(&gate));
+        }
+        Ok(Self {
+            clients,
+            active: AtomicUsize::new(0),
+            notices: Mutex::default(),
+        })
+    }
+
+    /// An Ollama client for each endpoint, using `model` unless the endpoint names its own
+    pub fn ollama(
+        endpoints: &[Endpoint],
+        model: &str,
+        keep_alive: Option<KeepAlive>,
+    ) -> Result<Self> {
+        let clients = endpoints
+            .iter()
+            .map(|endpoint| {
+                let client = OllamaClient::new()
+                    .with_endpoint(endpoint)
+                    .with_model(endpoint.model.as_deref().unwrap_or(model))
+                    .with_keep_alive(keep_alive.clone());
+                (endpoint.clone(), Box::new(client) as Box<dyn LlmClient>)
+            })
+            .collect();
+        Self::new(clients)
+    }
+
+    /// A client for the endpoints of `config`, or the one from the environment
+    /// if it lists none. With several, the first reachable one is selected.
+    pub async fn connect(
+        config: &Config,
+        model: &str,
+        keep_alive: Option<KeepAlive>,
+    ) -> Result<Self> {
+        if let Some(mode) = env::var_os(FAKE_LLM_VAR).filter(|value| !value.is_empty()) {
+            return Self::fake(model, &mode.to_string_lossy());
+        }
+        let endpoints = config.endpoints()?;
+        let client = Self::ollama(&endpoints, model, keep_alive)?;
+        if endpoints.len() > 1 {
+            client.select().await?;
+        }
+        Ok(client)
+    }
+
+    /// A client with `model` installed that repeats each message back, or
+    /// fails the way `mode` names; used when `SENTINEL_FAKE_LLM` is set
+    pub fn fake(model: &str, mode: &str) -> Result<Self> {
+        let client = MockLlmClient::new()
+            .echoing()
+            .with_models(&[model])
+            .with_tool_support(true);
+        let client = match mode {
+            "unreachable" => client.unreachable(),
+            "missing-model" => client.with_models(&[]),
+            "timeout" => client.with_hang(),
+            "error" => client.with_error("the model crashed"),
+            _ => client,
+        };
+        Self::new(vec![(Endpoint::new("fake", 0), Box::new(client))])
+    }
+
+    pub fn endpoints(&self) -> Vec<&Endpoint> {
+        self.clients.iter().map(|(endpoint, _)| endpoint).collect()
+    }
+
+    /// The endpoint requests currently go to
+    pub fn active(&self) -> &Endpoint {
+        &self.clients[self.active.load(Ordering::Relaxed)].0
+    }
+
+    /// Make the first endpoint that passes its health check the active one
+    pub async fn select(&self) -> Result<&Endpoint> {
+        let mut failures = Vec::new();
+        for (index, (endpoint, client)) in self.clients.iter().enumerate() {
+            let checked = tokio::time::timeout(HEALTH_TIMEOUT, client.health_check()).await;
+            match checked {
+                Ok(Ok(())) => {
+                    self.active.store(index, Ordering::Relaxed);
+                    return Ok(endpoint);
+                }
+                Ok(Err(e)) => failures.push(format!("{}: {}", endpoint.address(), e)),
+                Err(_) => failures.push(format!("{}: no answer", endpoint.address())),
+            }
+        }
+        Err(NoneReachable { failures }.into())
+    }
+
+    // Run a request on the active client, moving on to the next ones while
+    // the server can't be reached
+    async fn with_failover<'a, T>(
+        &'a self,
+        request: impl Fn(&'a dyn LlmClient) -> BoxFuture<'a, Result<T>>,
+    ) -> Result<T> {
+        let first = self.active.load(Ordering::Relaxed);
+        let mut index = first;
+        loop {
+            let (endpoint, client) = &self.clients[index];
+            match request(client.as_ref()).await {
+                Err(e) if is_connection_error(&e) => {
+                    let next = (index + 1) % self.clients.len();
+                    if next == first {
+                        return Err(e);
+                    }
+                    self.notices.lock().unwrap().push(format!(
+                        "Could not reach {}; switched to {}",
+                        endpoint.address(),
+                        self.clients[next].0.address()
+                    ));
+                    self.active.store(next, Ordering::Relaxed);
+                    index = next;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // Apply a setting to every client, so it holds whichever one is active
+    fn for_each(&self, apply: impl Fn(&dyn LlmClient)) {
+        for (_, client) in &self.clients {
+            apply(client.as_ref());
+        }
+    }
+
+    /// The client of the active endpoint
+    pub fn active_client(&self) -> &dyn LlmClient {
+        self.clients[self.active.load(Ordering::Relaxed)].1.as_ref()
+    }
+}
+
+#[async_trait]
+impl LlmClient for FailoverClient {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn generate_response(&self, messages: &[Message]) -> Result<(String, usize, usize)> {
+        self.with_failover(|client| client.generate_response(messages))
+            .await
+    }
+
+    async fn generate_response_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(String, usize, usize, Vec<String>)> {
+        self.with_failover(|client| client.generate_response_with_tools(messages, tools))
+            .await
+    }
+
+    async fn generate_response_with_options(
+        &self,
+        messages: &[Message],
+        options: &GenerationOptions,
+    ) -> Result<(String, usize, usize)> {
+        self.with_failover(|client| client.generate_response_with_options(messages, options))
+            .await
+    }
+
+    async fn generate_response_with_tools_and_options(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+        options: &GenerationOptions,
+    ) -> Result<(String, usize, usize, Vec<String>)> {
+        self.with_failover(|client| {
+            client.generate_response_with_tools_and_options(messages, tools, options)
+        })
+        .await
+    }
+
+    async fn chat_step(&self, messages: &[Message], tools: &[Tool]) -> Result<ModelTurn> {
+        self.with_failover(|client| client.chat_step(messages, tools))
+            .await
+    }
+
+    async fn chat_step_with_options(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+        options: &GenerationOptions,
+    ) -> Result<ModelTurn> {
+        self.with_failover(|client| client.chat_step_with_options(messages, tools, options))
+            .await
+    }
+
+    fn supports_tool_calls(&self) -> bool {
+        self.active_client().supports_tool_calls()
+    }
+
+    fn available_tools(&self) -> Vec<String> {
+        self.active_client().available_tools()
+    }
+
+    fn last_tool_invocations(&self) -> Vec<ToolInvocation> {
+        self.active_client().last_tool_invocations()
+    }
+
+    fn reset_tools(&self) {
+        self.for_each(|client| client.reset_tools());
+    }
+
+    fn set_dry_run(&self, dry_run: bool) {
+        self.for_each(|client| client.set_dry_run(dry_run));
+    }
+
+    fn dry_run(&self) -> bool {
+        self.active_client().dry_run()
+    }
+
+    fn set_reviewer(&self, reviewer: Arc<dyn Reviewer>) {
+        self.for_each(|client| client.set_reviewer(Arc::clone(&reviewer)));
+    }
+
+    fn set_progress(&self, progress: Arc<dyn Progress>) {
+        self.for_each(|client| client.set_progress(Arc::clone(&progress)));
+    }
+
+    fn set_tool_events(&self, events: Option<UnboundedSender<ToolEvent>>) {
+        self.for_each(|client| client.set_tool_events(events.clone()));
+    }
+
+    fn set_journal(&self, journal: Arc<Journal>) {
+        self.for_each(|client| client.set_journal(Arc::clone(&journal)));
+    }
+
+    fn set_remote_gate(&self, gate: Arc<RemoteGate>) {
+        self.for_each(|client| client.set_remote_gate(Arc::clone(&gate)));
+    }
+
+    fn set_memory(&self, memory: Arc<Memory>) {
+        self.for_each(|client| client.set_memory(Arc::clone(&memory)));
+    }
+
+    fn protect(&self, rule: &str) {
+        self.for_each(|client| client.protect(rule));
+    }
+
+    fn apply_config(&self, config: &Config) {
+        self.for_each(|client| client.apply_config(config));
+    }
+
+    fn set_tool_enabled(&self, name: &str, enabled: bool) -> bool {
+        self.clients.iter().fold(false, |known, (_, client)| {
+            client.set_tool_enabled(name, enabled) || known
+        })
+    }
+
+    fn provider(&self) -> &str {
+        self.active_client().provider()
+    }
+
+    fn model_name(&self) -> Option<String> {
+        self.active_client().model_name()
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.select().await.map(|_| ())
+    }
+
+    async fn warm_up(&self) -> Result<ServerTimings> {
+        self.with_failover(|client| client.warm_up()).await
+    }
+
+    async fn model_loaded(&self) -> Option<bool> {
+        self.active_client().model_loaded().await
+    }
+
+    async fn unload(&self) -> Result<()> {
+        self.active_client().unload().await
+    }
+
+    fn for_model(&self, model: &str) -> Result<Box<dyn LlmClient>> {
+        self.active_client().for_model(model)
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        Some(self.active().address())
+    }
+
+    fn check_remote(&self, sources: &[String]) -> Result<()> {
+        self.active_client().check_remote(sources)
+    }
+
+    async fn supports_images(&self) -> Option<bool> {
+        self.active_client().supports_images().await
+    }
+
+    async fn supports_tools(&self) -> Option<bool> {
+        self.active_client().supports_tools().await
+    }
+
+    async fn context_length(&self) -> Option<usize> {
+        self.active_client().context_length().await
+    }
+
+    fn set_limits(&self, limits: ResponseLimits) {
+        self.for_each(|client| client.set_limits(limits.clone()));
+    }
+
+    fn set_context_window(&self, tokens: usize) {
+        self.for_each(|client| client.set_context_window(tokens));
+    }
+
+    fn limits(&self) -> ResponseLimits {
+        self.active_client().limits()
+    }
+
+    fn take_truncated(&self) -> bool {
+        // Every client is asked, so none keeps a stale flag
+        self.clients.iter().fold(false, |truncated, (_, client)| {
+            client.take_truncated() || truncated
+        })
+    }
+
+    fn take_rate_limited(&self) -> usize {
+        self.clients
+            .iter()
+            .map(|(_, client)| client.take_rate_limited())
+            .sum()
+    }
+
+    fn take_recovered_calls(&self) -> usize {
+        self.clients
+            .iter()
+            .map(|(_, client)| client.take_recovered_calls())
+            .sum()
+    }
+
+    fn take_prompt_usage(&self) -> PromptUsage {
+        let mut usage = PromptUsage::default();
+        for (_, client) in &self.clients {
+            usage.add(client.take_prompt_usage());
+        }
+        usage
+    }
+
+    fn take_server_timings(&self) -> ServerTimings {
+        let mut timings = ServerTimings::default();
+        for (_, client) in &self.clients {
+            timings.add(client.take_server_timings());
+        }
+        timings
+    }
+
+    fn take_notices(&self) -> Vec<String> {
+        std::mem::take(&mut *self.notices.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // A port nothing listens on, so connections are refused
+    async fn refusing_port() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    // An Ollama stand-in answering every chat with `reply`; returns its port
+    async fn serve(reply: &'static str) -> (u16, tokio::task::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 4096];
+                while let Ok(read) = stream.read(&mut buffer).await {
+                    request.extend_from_slice(&buffer[..read]);
+                    if read == 0 || complete(&request) {
+                        break;
+                    }
+                }
+
+                let body = if String::from_utf8_lossy(&request).starts_with("GET /api/tags") {
+                    r#"{"models":[]}"#.to_string()
+                } else {
+                    serde_json::json!({
+                        "model": "mock",
+                        "created_at": "2024-01-01T00:00:00Z",
+                        "message": { "role": "assistant", "content": reply },
+                        "done": true
+                    })
+                    .to_string()
+                };
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (port, server)
+    }
+
+    // Whether the headers and the body they announce have arrived
+    fn complete(request: &[u8]) -> bool {
+        let text = String::from_utf8_lossy(request);
+        let Some((headers, body)) = text.split_once("\r\n\r\n") else {
+            return false;
+        };
+        let length = headers
+            .lines()
+            .find_map(|line| {
+                line.to_lowercase()
+                    .strip_prefix("content-length:")
+                    .map(|n| n.trim().parse::<usize>().unwrap_or(0))
+            })
+            .unwrap_or(0);
+        body.len() >= length
+    }
+
+    fn conversation() -> Vec<Message> {
+        vec![Message::system("Be brief"), Message::user("Hi")]
+    }
+
+    #[tokio::test]
+    async fn test_selects_the_first_reachable_endpoint() {
+        let down = Endpoint::new("http://127.0.0.1", refusing_port().await);
+        let (port, _server) = serve("hello from the second").await;
+        let up = Endpoint::new("http://127.0.0.1", port);
+        let client = FailoverClient::ollama(&[down, up.clone()], "mock", None).unwrap();
+
+        assert_eq!(client.select().await.unwrap(), &up);
+        assert_eq!(client.endpoint(), Some(up.address()));
+
+        let (text, _, _) = client.generate_response(&conversation()).await.unwrap();
+        assert_eq!(text, "hello from the second");
+        assert!(client.take_notices().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fails_over_mid_session_and_sticks() {
+        let (first_port, first) = serve("from the first").await;
+        let (second_port, _second) = serve("from the second").await;
+        let endpoints = [
+            Endpoint::new("http://127.0.0.1", first_port),
+            Endpoint::new("http://127.0.0.1", second_port),
+        ];
+        let client = FailoverClient::ollama(&endpoints, "mock", None).unwrap();
+        client.select().await.unwrap();
+        let (text, _, _) = client.generate_response(&conversation()).await.unwrap();
+        assert_eq!(text, "from the first");
+
+        // The first server goes away between requests
+        first.abort();
+        let _ = first.await;
+
+        let (text, _, _) = client.generate_response(&conversation()).await.unwrap();
+        assert_eq!(text, "from the second");
+        assert_eq!(client.active(), &endpoints[1]);
+        assert_eq!(
+            client.take_notices(),
+            vec![format!(
+                "Could not reach {}; switched to {}",
+                endpoints[0].address(),
+                endpoints[1].address()
+            )]
+        );
+        assert!(client.take_notices().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_no_reachable_endpoint() {
+        let endpoints = [
+            Endpoint::new("http://127.0.0.1", refusing_port().await),
+            Endpoint::new("http://127.0.0.1", refusing_port().await),
+        ];
+        let client = FailoverClient::ollama(&endpoints, "mock", None).unwrap();
+        let error = client.select().await.unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("No Ollama endpoint is reachable"));
+
+        let error = client.generate_response(&conversation()).await.unwrap_err();
+        assert!(is_connection_error(&error));
+    }
+}
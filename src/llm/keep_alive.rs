@@ -0,0 +1,55 @@
+//! How long Ollama keeps a model loaded, and warming it up ahead of the first prompt.
+
+use anyhow::{anyhow, Result};
+use ollama_rs::generation::parameters::{KeepAlive, TimeUnit};
+
+/// Parse a duration the way Ollama accepts it: a number of seconds, or a
+/// number with an `s`, `m` or `h` suffix. Negative values keep the model
+/// loaded indefinitely and zero unloads it after every request.
+pub fn parse(value: &str) -> Result<KeepAlive> {
+    let value = value.trim();
+    let (number, unit) = match value.char_indices().last() {
+        Some((index, 's')) => (&value[..index], TimeUnit::Seconds),
+        Some((index, 'm')) => (&value[..index], TimeUnit::Minutes),
+        Some((index, 'h')) => (&value[..index], TimeUnit::Hours),
+        _ => (value, TimeUnit::Seconds),
+    };
+
+    let time: i64 = number.trim().parse().map_err(|_| {
+        anyhow!(
+            "Invalid keep-alive '{}': expected e.g. 30m, 1h, 300 (seconds), 0 or -1",
+            value
+        )
+    })?;
+
+    Ok(match time {
+        ..=-1 => KeepAlive::Indefinitely,
+        0 => KeepAlive::UnloadOnCompletion,
+        time => KeepAlive::Until {
+            time: time as u64,
+            unit,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // How the setting is sent to Ollama
+    fn json(keep_alive: KeepAlive) -> serde_json::Value {
+        serde_json::to_value(keep_alive).unwrap()
+    }
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        assert_eq!(json(parse("30m")?), "30m");
+        assert_eq!(json(parse("2h")?), "2h");
+        assert_eq!(json(parse(" 90 ")?), "90s");
+        assert_eq!(json(parse("-1")?), -1);
+        assert_eq!(json(parse("0")?), 0);
+        assert!(parse("soon").is_err());
+        assert!(parse("m").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+//! How long a response may get and where it stops.
+//!
+//! The limits come from the `[model]` config table, `sentinel ask --max-tokens`,
+//! `--stop` and `--timeout`, or `/set` in the interactive modes. The token
+//! limit and stop sequences go out with every request as Ollama's
+//! `num_predict` and `stop` options. A response that used up the token limit
+//! was most likely cut off, and is marked as such. The timeout is kept on our
+//! side, see [`crate::llm::deadline`].
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use ollama_rs::models::ModelOptions;
+use serde::Deserialize;
+
+use crate::config::ModelConfig;
+
+/// Limits sent with every request
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResponseLimits {
+    /// Most tokens a response may have; unlimited if None
+    pub max_tokens: Option<u32>,
+    /// Text that ends a response when the model writes it, left out of the response
+    pub stop: Vec<String>,
+    /// How long a response may take before it is given up; no limit if None
+    pub timeout: Option<Duration>,
+    /// What the timeout counts
+    pub timeout_mode: TimeoutMode,
+}
+
+/// What a response's timeout counts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeoutMode {
+    /// The time since the model last made progress: finished a step of a
+    /// tool-calling turn, or had a tool finish
+    #[default]
+    Progress,
+    /// The whole response, however many steps it takes
+    Fixed,
+}
+
+impl ResponseLimits {
+    /// The defaults set in the config
+    pub fn from_config(config: &ModelConfig) -> Self {
+        Self {
+            max_tokens: config.max_tokens,
+            stop: clean_stop(config.stop.clone()),
+            timeout: (config.timeout_secs > 0).then(|| Duration::from_secs(config.timeout_secs)),
+            timeout_mode: config.timeout_mode,
+        }
+    }
+
+    /// These limits with the `--max-tokens` and `--stop` flags over them.
+    /// Any `--stop` replaces the configured sequences; `--stop ""` clears them
+    pub fn with_flags(mut self, max_tokens: Option<u32>, stop: &[String]) -> Self {
+        if max_tokens.is_some() {
+            self.max_tokens = max_tokens;
+        }
+        if !stop.is_empty() {
+            self.stop = clean_stop(stop.to_vec());
+        }
+        self
+    }
+
+    /// These limits with the `--timeout` flag over them, e.g. "120s" or "off"
+    pub fn with_timeout_flag(mut self, timeout: Option<&str>) -> Result<Self> {
+        if let Some(timeout) = timeout {
+            self.timeout = parse_timeout(timeout)?;
+        }
+        Ok(self)
+    }
+
+    /// Change a limit as `/set` does, from its name and values: `max_tokens`
+    /// with a number or "off", `stop` with the sequences (none clears them), or
+    /// `timeout` with a duration or "off" and optionally "fixed" or "progress".
+    /// Returns false, changing nothing, if the words are not a limit
+    pub fn set(&mut self, words: &[String]) -> bool {
+        match words.split_first() {
+            Some((name, values)) if name.eq_ignore_ascii_case("max_tokens") => {
+                match values {
+                    [value] if value.eq_ignore_ascii_case("off") => self.max_tokens = None,
+                    [value] => match value.parse::<u32>() {
+                        Ok(max_tokens) if max_tokens > 0 => self.max_tokens = Some(max_tokens),
+                        _ => return false,
+                    },
+                    _ => return false,
+                }
+                true
+            }
+            Some((name, values)) if name.eq_ignore_ascii_case("stop") => {
+                self.stop = clean_stop(values.to_vec());
+                true
+            }
+            Some((name, values)) if name.eq_ignore_ascii_case("timeout") => {
+                let (timeout, mode) = match values {
+                    [timeout] => (timeout, self.timeout_mode),
+                    [timeout, mode] => match mode.to_lowercase().as_str() {
+                        "fixed" => (timeout, TimeoutMode::Fixed),
+                        "progress" => (timeout, TimeoutMode::Progress),
+                        _ => return false,
+                    },
+                    _ => return false,
+                };
+                let Ok(timeout) = parse_timeout(timeout) else {
+                    return false;
+                };
+                self.timeout = timeout;
+                self.timeout_mode = mode;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Add the limits to a request's model options
+    pub fn apply(&self, options: ModelOptions) -> ModelOptions {
+        let options = match self.max_tokens {
+            // Ollama takes an i32; a larger limit is as good as none
+            Some(max_tokens) => options.num_predict(i32::try_from(max_tokens).unwrap_or(-1)),
+            None => options,
+        };
+        if self.stop.is_empty() {
+            options
+        } else {
+            options.stop(self.stop.clone())
+        }
+    }
+
+    /// Whether a response of `output_tokens` tokens used up the limit
+    pub fn reached(&self, output_tokens: u64) -> bool {
+        self.max_tokens
+            .is_some_and(|max_tokens| output_tokens >= u64::from(max_tokens))
+    }
+
+    /// The limits as `/set` shows them, e.g. `max_tokens 200, stop "```", timeout 2m`
+    pub fn describe(&self) -> String {
+        let max_tokens = match self.max_tokens {
+            Some(max_tokens) => format!("max_tokens {}", max_tokens),
+            None => "max_tokens off".to_string(),
+        };
+        let stop = if self.stop.is_empty() {
+            "no stop sequences".to_string()
+        } else {
+            let quoted: Vec<String> = self.stop.iter().map(|stop| format!("{:?}", stop)).collect();
+            format!("stop {}", quoted.join(" "))
+        };
+        let timeout = match (self.timeout, self.timeout_mode) {
+            (None, _) => "no timeout".to_string(),
+            (Some(timeout), TimeoutMode::Progress) => {
+                format!("timeout {}", format_duration(timeout))
+            }
+            (Some(timeout), TimeoutMode::Fixed) => {
+                format!("timeout {} fixed", format_duration(timeout))
+            }
+        };
+        format!("{}, {}, {}", max_tokens, stop, timeout)
+    }
+}
+
+/// Parse a timeout: a number of seconds, or a number with an `s`, `m` or `h`
+/// suffix. "off" or zero means no timeout
+pub fn parse_timeout(value: &str) -> Result<Option<Duration>> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("off") {
+        return Ok(None);
+    }
+    let (number, unit) = match value.char_indices().last() {
+        Some((index, 's')) => (&value[..index], 1),
+        Some((index, 'm')) => (&value[..index], 60),
+        Some((index, 'h')) => (&value[..index], 60 * 60),
+        _ => (value, 1),
+    };
+    let number: u64 = number.trim().parse().map_err(|_| {
+        anyhow!(
+            "Invalid timeout '{}': expected e.g. 120s, 10m, 90 (seconds) or off",
+            value
+        )
+    })?;
+    Ok((number > 0).then(|| Duration::from_secs(number.saturating_mul(unit))))
+}
+
+/// A duration in the largest unit that shows it whole, e.g. "2m" or "90s"
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if duration.subsec_nanos() != 0 {
+        format!("{}ms", duration.as_millis())
+    } else if secs >= 60 * 60 && secs.is_multiple_of(60 * 60) {
+        format!("{}h", secs / (60 * 60))
+    } else if secs >= 60 && secs.is_multiple_of(60) {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Shown with a response cut off by the token limit
+pub const TRUNCATED_NOTE: &str = "(cut off by the max_tokens limit)";
+
+// Empty sequences would stop every response before it starts
+fn clean_stop(stop: Vec<String>) -> Vec<String> {
+    stop.into_iter().filter(|stop| !stop.is_empty()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_flags_override_the_config() {
+        let config = ModelConfig {
+            max_tokens: Some(500),
+            stop: vec!["END".to_string()],
+            ..ModelConfig::default()
+        };
+        let limits = ResponseLimits::from_config(&config);
+        assert_eq!(limits.clone().with_flags(None, &[]), limits);
+
+        let flagged = limits
+            .clone()
+            .with_flags(Some(20), &["```".to_string(), "\n\n".to_string()]);
+        assert_eq!(flagged.max_tokens, Some(20));
+        assert_eq!(flagged.stop, ["```", "\n\n"]);
+
+        // An empty --stop clears the configured sequences
+        let cleared = limits.with_flags(None, &[String::new()]);
+        assert_eq!(cleared.max_tokens, Some(500));
+        assert!(cleared.stop.is_empty());
+    }
+
+    #[test]
+    fn test_set_changes_one_limit() {
+        let mut limits = ResponseLimits::default();
+        assert!(limits.set(&words("max_tokens 64")));
+        assert!(limits.set(&words("STOP END ---")));
+        assert_eq!(
+            limits.describe(),
+            "max_tokens 64, stop \"END\" \"---\", no timeout"
+        );
+        assert!(limits.set(&words("timeout 90 fixed")));
+        assert_eq!(limits.timeout, Some(Duration::from_secs(90)));
+        assert!(limits.describe().ends_with(", timeout 90s fixed"));
+        assert!(limits.set(&words("timeout 2m progress")));
+        assert!(limits.describe().ends_with(", timeout 2m"));
+        assert!(limits.set(&words("timeout off")));
+
+        assert!(limits.set(&words("max_tokens off")));
+        assert!(limits.set(&words("stop")));
+        assert_eq!(limits, ResponseLimits::default());
+        assert_eq!(
+            limits.describe(),
+            "max_tokens off, no stop sequences, no timeout"
+        );
+
+        for wrong in [
+            "max_tokens",
+            "max_tokens 0",
+            "max_tokens lots",
+            "temperature 1",
+            "timeout soon",
+            "timeout 10s forever",
+            "",
+        ] {
+            assert!(!limits.set(&words(wrong)), "{}", wrong);
+        }
+        assert_eq!(limits, ResponseLimits::default());
+    }
+
+    #[test]
+    fn test_limits_go_into_the_options() {
+        let limits = ResponseLimits {
+            max_tokens: Some(32),
+            stop: vec!["```".to_string(), "END".to_string()],
+            ..ResponseLimits::default()
+        };
+        let options = serde_json::to_value(limits.apply(ModelOptions::default())).unwrap();
+        assert_eq!(options["num_predict"], 32);
+        assert_eq!(options["stop"], serde_json::json!(["```", "END"]));
+
+        let options =
+            serde_json::to_value(ResponseLimits::default().apply(ModelOptions::default())).unwrap();
+        assert!(options.get("num_predict").is_none());
+        assert!(options.get("stop").is_none());
+
+        assert!(limits.reached(32));
+        assert!(!limits.reached(31));
+        assert!(!ResponseLimits::default().reached(100_000));
+    }
+
+    #[test]
+    fn test_timeouts_parse_and_print() -> Result<()> {
+        assert_eq!(parse_timeout("120s")?, Some(Duration::from_secs(120)));
+        assert_eq!(parse_timeout(" 10m ")?, Some(Duration::from_secs(600)));
+        assert_eq!(parse_timeout("90")?, Some(Duration::from_secs(90)));
+        assert_eq!(parse_timeout("OFF")?, None);
+        assert_eq!(parse_timeout("0")?, None);
+        assert!(parse_timeout("soon").is_err());
+        assert!(parse_timeout("-5s").is_err());
+
+        assert_eq!(format_duration(Duration::from_secs(120)), "2m");
+        assert_eq!(format_duration(Duration::from_secs(90)), "90s");
+        assert_eq!(format_duration(Duration::from_secs(7200)), "2h");
+        assert_eq!(format_duration(Duration::from_millis(50)), "50ms");
+
+        let config = ModelConfig::default();
+        let limits = ResponseLimits::from_config(&config);
+        assert_eq!(limits.timeout, Some(Duration::from_secs(600)));
+        assert_eq!(limits.timeout_mode, TimeoutMode::Progress);
+        let flagged = limits.clone().with_timeout_flag(Some("30s"))?;
+        assert_eq!(flagged.timeout, Some(Duration::from_secs(30)));
+        assert_eq!(limits.clone().with_timeout_flag(Some("off"))?.timeout, None);
+        assert!(limits.with_timeout_flag(Some("later")).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,513 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde_json::Value;
+
+use crate::llm::limits::ResponseLimits;
+use crate::llm::ollama::{LlmClient, ModelTurn, Tool, ToolCall};
+use crate::llm::timings::ServerTimings;
+use crate::{Message, Role};
+
+/// A scripted step of a [`MockLlmClient`] conversation
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockReply {
+    /// Answer with this text
+    Text(String),
+    /// Ask for these tools to be called
+    ToolCalls(Vec<ToolCall>),
+    /// Say something, then ask for these tools to be called
+    Narrated(String, Vec<ToolCall>),
+    /// Take this long to generate a step, like a model writing a long answer
+    Slow(Duration, Box<MockReply>),
+    /// Fail the request with this message
+    Error(String),
+    /// Never answer, like a server stuck loading the model
+    Hang,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    replies: VecDeque<MockReply>,
+    received: Vec<Vec<Message>>,
+    resets: usize,
+    dry_run: bool,
+    limits: ResponseLimits,
+    // The limits in force at each request in `received`
+    received_limits: Vec<ResponseLimits>,
+    truncated: bool,
+    // Slow replies being generated now, and the most there ever were at once
+    in_flight: usize,
+    max_in_flight: usize,
+    // How often the model's context length was asked for, and the window set
+    context_lookups: usize,
+    context_window: Option<usize>,
+    // The server timings of the requests answered since last taken
+    timings: ServerTimings,
+}
+
+/// An LLM client that replies from a script, for tests.
+///
+/// Clones share their script and records, so a test can keep a clone to
+/// inspect after handing the client to an agent:
+///
+/// ```
+/// use sentinel::llm::mock::MockLlmClient;
+/// use serde_json::json;
+///
+/// let client = MockLlmClient::new()
+///     .with_tool_call("ls", json!({ "path": "." }))
+///     .with_reply("The directory has 3 files");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MockLlmClient {
+    state: Arc<Mutex<MockState>>,
+    tools: Vec<String>,
+    vision: Option<bool>,
+    tool_support: Option<bool>,
+    context_length: Option<usize>,
+    endpoint: Option<String>,
+    server_timings: Option<ServerTimings>,
+    loaded: Option<bool>,
+    rejects_tools: bool,
+    echoes: bool,
+    unreachable: bool,
+    models: Vec<String>,
+    // The clients `for_model` hands out, by model
+    others: Vec<(String, MockLlmClient)>,
+}
+
+impl MockLlmClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a text answer
+    pub fn with_reply(self, text: impl Into<String>) -> Self {
+        self.push(MockReply::Text(text.into()))
+    }
+
+    /// Queue a request for a single tool call
+    pub fn with_tool_call(self, name: impl Into<String>, arguments: Value) -> Self {
+        self.push(MockReply::ToolCalls(vec![ToolCall {
+            name: name.into(),
+            arguments,
+        }]))
+    }
+
+    /// Queue a request for a single tool call, preceded by some text
+    pub fn with_narrated_tool_call(
+        self,
+        text: impl Into<String>,
+        name: impl Into<String>,
+        arguments: Value,
+    ) -> Self {
+        self.push(MockReply::Narrated(
+            text.into(),
+            vec![ToolCall {
+                name: name.into(),
+                arguments,
+            }],
+        ))
+    }
+
+    /// Queue a text answer that takes `delay` to generate
+    pub fn with_slow_reply(self, text: impl Into<String>, delay: Duration) -> Self {
+        self.push(MockReply::Slow(
+            delay,
+            Box::new(MockReply::Text(text.into())),
+        ))
+    }
+
+    /// Queue a request that is never answered
+    pub fn with_hang(self) -> Self {
+        self.push(MockReply::Hang)
+    }
+
+    /// Queue a failed request
+    pub fn with_error(self, message: impl Into<String>) -> Self {
+        self.push(MockReply::Error(message.into()))
+    }
+
+    /// Names reported by `available_tools`
+    pub fn with_tools(mut self, tools: &[&str]) -> Self {
+        self.tools = tools.iter().map(|tool| tool.to_string()).collect();
+        self
+    }
+
+    /// Whether `supports_images` reports the model reads images
+    pub fn with_vision(mut self, vision: bool) -> Self {
+        self.vision = Some(vision);
+        self
+    }
+
+    /// Whether `supports_tools` reports the model calls tools
+    pub fn with_tool_support(mut self, supported: bool) -> Self {
+        self.tool_support = Some(supported);
+        self
+    }
+
+    /// The context length `context_length` reports, as Ollama's show API
+    /// would for the model
+    pub fn with_context_length(mut self, tokens: usize) -> Self {
+        self.context_length = Some(tokens);
+        self
+    }
+
+    /// Report `address` as the server requests go to
+    pub fn with_endpoint(mut self, address: impl Into<String>) -> Self {
+        self.endpoint = Some(address.into());
+        self
+    }
+
+    /// Report `timings` for every answered request and for warming up, as
+    /// Ollama's final responses would
+    pub fn with_server_timings(mut self, timings: ServerTimings) -> Self {
+        self.server_timings = Some(timings);
+        self
+    }
+
+    /// Whether `model_loaded` reports the model loaded on the server
+    pub fn with_model_loaded(mut self, loaded: bool) -> Self {
+        self.loaded = Some(loaded);
+        self
+    }
+
+    /// Refuse requests that carry tools, the way Ollama does for models that
+    /// can't call them, without using a scripted step
+    pub fn rejecting_tools(mut self) -> Self {
+        self.rejects_tools = true;
+        self
+    }
+
+    /// Answer requests left without a scripted step by repeating the last
+    /// user message, as "You said: ..."
+    pub fn echoing(mut self) -> Self {
+        self.echoes = true;
+        self
+    }
+
+    /// Fail every request and model listing with a refused connection, like
+    /// a server that isn't running
+    pub fn unreachable(mut self) -> Self {
+        self.unreachable = true;
+        self
+    }
+
+    /// Answer for `model` with `client` when asked for it with `for_model`
+    pub fn with_model_client(mut self, model: &str, client: MockLlmClient) -> Self {
+        self.others.push((model.to_string(), client));
+        self
+    }
+
+    /// Names reported as installed models
+    pub fn with_models(mut self, models: &[&str]) -> Self {
+        self.models = models.iter().map(|model| model.to_string()).collect();
+        self
+    }
+
+    /// The models reported as installed
+    pub fn models(&self) -> Result<&[String]> {
+        self.reach()?;
+        Ok(&self.models)
+    }
+
+    // Fail the way a server that isn't running does, if this one isn't
+    fn reach(&self) -> Result<()> {
+        if self.unreachable {
+            return Err(io::Error::from(io::ErrorKind::ConnectionRefused).into());
+        }
+        Ok(())
+    }
+
+    /// Queue any scripted step
+    pub fn push(self, reply: MockReply) -> Self {
+        self.state.lock().unwrap().replies.push_back(reply);
+        self
+    }
+
+    /// The messages of every request so far, oldest first
+    pub fn received(&self) -> Vec<Vec<Message>> {
+        self.state.lock().unwrap().received.clone()
+    }
+
+    /// The limits in force at every request so far, oldest first
+    pub fn received_limits(&self) -> Vec<ResponseLimits> {
+        self.state.lock().unwrap().received_limits.clone()
+    }
+
+    /// How often `reset_tools` was called
+    pub fn resets(&self) -> usize {
+        self.state.lock().unwrap().resets
+    }
+
+    /// Scripted steps not used yet
+    pub fn remaining(&self) -> usize {
+        self.state.lock().unwrap().replies.len()
+    }
+
+    /// The most slow replies that were being generated at the same time
+    pub fn max_in_flight(&self) -> usize {
+        self.state.lock().unwrap().max_in_flight
+    }
+
+    /// How often the model's context length was asked for
+    pub fn context_lookups(&self) -> usize {
+        self.state.lock().unwrap().context_lookups
+    }
+
+    /// The context window requests are sent with, once one was set
+    pub fn context_window(&self) -> Option<usize> {
+        self.state.lock().unwrap().context_window
+    }
+
+    // Record a request and take the next scripted step for it, waiting out slow ones
+    async fn next(&self, messages: &[Message]) -> Result<MockReply> {
+        self.reach()?;
+        let reply = {
+            let mut state = self.state.lock().unwrap();
+            state.received.push(messages.to_vec());
+            let limits = state.limits.clone();
+            state.received_limits.push(limits);
+            let reply = match state.replies.pop_front() {
+                Some(reply) => reply,
+                None if self.echoes => MockReply::Text(echo(messages)),
+                None => return Err(anyhow!("MockLlmClient has no scripted reply left")),
+            };
+            if let Some(timings) = self.server_timings {
+                state.timings.add(timings);
+            }
+            reply
+        };
+        match reply {
+            MockReply::Slow(delay, reply) => {
+                {
+                    let mut state = self.state.lock().unwrap();
+                    state.in_flight += 1;
+                    state.max_in_flight = state.max_in_flight.max(state.in_flight);
+                }
+                tokio::time::sleep(delay).await;
+                self.state.lock().unwrap().in_flight -= 1;
+                Ok(*reply)
+            }
+            MockReply::Hang => std::future::pending().await,
+            reply => Ok(reply),
+        }
+    }
+
+    // Record a request with tools and refuse it, if the model can't call them
+    fn refuse_tools(&self, messages: &[Message]) -> Result<()> {
+        if !self.rejects_tools {
+            return Ok(());
+        }
+        let mut state = self.state.lock().unwrap();
+        state.received.push(messages.to_vec());
+        let limits = state.limits.clone();
+        state.received_limits.push(limits);
+        Err(anyhow!(
+            "registry.ollama.ai/library/mock does not support tools"
+        ))
+    }
+
+    // Token counts like the Ollama client's estimate of about four characters a token
+    fn estimate(text: &str) -> usize {
+        text.len().div_ceil(4)
+    }
+
+    // Cut `text` where the limits would have stopped a model: before the
+    // first stop sequence, or after `max_tokens` estimated tokens
+    fn limit(&self, mut text: String) -> String {
+        let mut state = self.state.lock().unwrap();
+        if let Some(end) = state
+            .limits
+            .stop
+            .iter()
+            .filter_map(|stop| text.find(stop.as_str()))
+            .min()
+        {
+            text.truncate(end);
+        }
+        let max_chars = state
+            .limits
+            .max_tokens
+            .map(|max_tokens| max_tokens as usize * 4);
+        state.truncated = match max_chars {
+            Some(max_chars) if text.len() > max_chars => {
+                let end = (0..=max_chars)
+                    .rev()
+                    .find(|&i| text.is_char_boundary(i))
+                    .unwrap_or(0);
+                text.truncate(end);
+                true
+            }
+            _ => false,
+        };
+        text
+    }
+
+    async fn text_reply(&self, messages: &[Message]) -> Result<(String, usize, usize)> {
+        match self.next(messages).await? {
+            MockReply::Text(text) => {
+                let text = self.limit(text);
+                let input_tokens = messages.iter().map(|m| Self::estimate(&m.content)).sum();
+                let output_tokens = Self::estimate(&text);
+                Ok((text, input_tokens, output_tokens))
+            }
+            MockReply::ToolCalls(calls) | MockReply::Narrated(_, calls) => Err(anyhow!(
+                "Scripted tool calls to {:?} need a caller that runs tools",
+                calls.iter().map(|c| c.name.as_str()).collect::<Vec<_>>()
+            )),
+            MockReply::Error(message) => Err(anyhow!(message)),
+            MockReply::Slow(..) | MockReply::Hang => Err(anyhow!("Slow steps cannot be nested")),
+        }
+    }
+}
+
+// What an echoing client answers
+fn echo(messages: &[Message]) -> String {
+    let said = messages
+        .iter()
+        .rev()
+        .find(|message| message.role == Role::User)
+        .map_or("nothing", |message| message.content.as_str());
+    format!("You said: {}", said)
+}
+
+#[async_trait]
+impl LlmClient for MockLlmClient {
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    async fn generate_response(&self, messages: &[Message]) -> Result<(String, usize, usize)> {
+        self.text_reply(messages).await
+    }
+
+    async fn generate_response_with_tools(
+        &self,
+        messages: &[Message],
+        _tools: &[Tool],
+    ) -> Result<(String, usize, usize, Vec<String>)> {
+        self.refuse_tools(messages)?;
+        let (text, input_tokens, output_tokens) = self.text_reply(messages).await?;
+        Ok((text, input_tokens, output_tokens, Vec::new()))
+    }
+
+    fn available_tools(&self) -> Vec<String> {
+        self.tools.clone()
+    }
+
+    fn reset_tools(&self) {
+        self.state.lock().unwrap().resets += 1;
+    }
+
+    fn set_dry_run(&self, dry_run: bool) {
+        self.state.lock().unwrap().dry_run = dry_run;
+    }
+
+    fn dry_run(&self) -> bool {
+        self.state.lock().unwrap().dry_run
+    }
+
+    fn provider(&self) -> &str {
+        "mock"
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        self.endpoint.clone()
+    }
+
+    fn set_limits(&self, limits: ResponseLimits) {
+        self.state.lock().unwrap().limits = limits;
+    }
+
+    fn limits(&self) -> ResponseLimits {
+        self.state.lock().unwrap().limits.clone()
+    }
+
+    fn take_truncated(&self) -> bool {
+        std::mem::take(&mut self.state.lock().unwrap().truncated)
+    }
+
+    fn take_server_timings(&self) -> ServerTimings {
+        std::mem::take(&mut self.state.lock().unwrap().timings)
+    }
+
+    async fn warm_up(&self) -> Result<ServerTimings> {
+        self.reach()?;
+        Ok(self.server_timings.unwrap_or_default())
+    }
+
+    async fn model_loaded(&self) -> Option<bool> {
+        self.loaded
+    }
+
+    async fn supports_images(&self) -> Option<bool> {
+        self.vision
+    }
+
+    async fn supports_tools(&self) -> Option<bool> {
+        self.tool_support
+    }
+
+    async fn context_length(&self) -> Option<usize> {
+        self.state.lock().unwrap().context_lookups += 1;
+        self.context_length
+    }
+
+    fn set_context_window(&self, tokens: usize) {
+        self.state.lock().unwrap().context_window = Some(tokens);
+    }
+
+    fn supports_tool_calls(&self) -> bool {
+        true
+    }
+
+    fn for_model(&self, model: &str) -> Result<Box<dyn LlmClient>> {
+        let client = match self.others.iter().find(|(name, _)| name == model) {
+            Some((_, client)) => client.clone(),
+            None if self.echoes => MockLlmClient::new().echoing(),
+            None => return Err(anyhow!("MockLlmClient has no client for '{}'", model)),
+        };
+        client.set_limits(self.limits());
+        Ok(Box::new(client))
+    }
+
+    async fn chat_step(&self, messages: &[Message], tools: &[Tool]) -> Result<ModelTurn> {
+        if !tools.is_empty() {
+            self.refuse_tools(messages)?;
+        }
+        let input_tokens = messages.iter().map(|m| Self::estimate(&m.content)).sum();
+        match self.next(messages).await? {
+            MockReply::Text(content) => {
+                let content = self.limit(content);
+                Ok(ModelTurn {
+                    output_tokens: Self::estimate(&content),
+                    content,
+                    tool_calls: Vec::new(),
+                    input_tokens,
+                })
+            }
+            MockReply::ToolCalls(tool_calls) => Ok(ModelTurn {
+                content: String::new(),
+                tool_calls,
+                input_tokens,
+                output_tokens: 0,
+            }),
+            MockReply::Narrated(content, tool_calls) => {
+                let content = self.limit(content);
+                Ok(ModelTurn {
+                    output_tokens: Self::estimate(&content),
+                    content,
+                    tool_calls,
+                    input_tokens,
+                })
+            }
+            MockReply::Error(message) => Err(anyhow!(message)),
+            MockReply::Slow(..) | MockReply::Hang => Err(anyhow!("Slow steps cannot be nested")),
+        }
+    }
+}
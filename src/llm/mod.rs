@@ -1,2 +1,35 @@
+// Checking that a model is installed and pulling it
+pub mod availability;
+
+// Falling back to other Ollama servers
+pub mod failover;
+
+// Giving up on responses that take too long
+pub mod deadline;
+
+// Where an Ollama server is, however it is written
+pub mod endpoint;
+
+// A scripted client for tests
+pub mod mock;
+
+// How long models stay loaded, and warming them up
+pub mod keep_alive;
+
+// Capping the length of responses and where they stop
+pub mod limits;
+
 // The ollama implementation module
-pub mod ollama;
\ No newline at end of file
+pub mod ollama;
+
+// Sending requests so Ollama can reuse what it evaluated for the last one
+pub mod prefix;
+
+// Where the time of a request went: loading the model, the prompt and the reply
+pub mod timings;
+
+// Whether models can call tools, remembered for the session
+pub mod tool_support;
+
+// Separating the think blocks of reasoning models from their answers
+pub mod reasoning;
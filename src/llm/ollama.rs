@@ -1,57 +1,160 @@
+use crate::config::{Config, ToolsConfig};
+use crate::context;
+use crate::images;
+use crate::llm::availability;
+use crate::llm::deadline;
+use crate::llm::endpoint::Endpoint;
+use crate::llm::limits::ResponseLimits;
+use crate::llm::prefix::{PrefixCache, PromptUsage};
+use crate::llm::timings::ServerTimings;
+use crate::llm::tool_support;
+use crate::memory::Memory;
+use crate::privacy::RemoteGate;
+use crate::recording::Tape;
+use crate::stats::{self, ToolTiming};
 use crate::Message;
 use crate::Role;
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
-use ollama_rs::generation::chat::{request::ChatMessageRequest, ChatMessage};
+use ollama_rs::coordinator::Coordinator;
+use ollama_rs::generation::chat::{request::ChatMessageRequest, ChatMessage, MessageRole};
 use ollama_rs::generation::completion::request::GenerationRequest;
+use ollama_rs::generation::images::Image;
+use ollama_rs::generation::parameters::KeepAlive;
 use ollama_rs::generation::tools::implementations::{Calculator, DDGSearcher, Scraper};
+use ollama_rs::history::ChatHistory;
 use ollama_rs::models::ModelOptions;
 use ollama_rs::Ollama;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::env;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedSender;
 
 use crate::tools::bash::Bash;
+use crate::tools::custom::{self, CustomToolConfig, CustomTools};
 use crate::tools::file::FileTool;
 use crate::tools::find_file_tool::FindAndReadFileTool;
+use crate::tools::journal::Journal;
 use crate::tools::ls::Ls;
+use crate::tools::memory::MemoryTool;
+use crate::tools::patch::ApplyPatch;
+use crate::tools::policy::ExecutionPolicy;
+use crate::tools::progress::{Progress, ToolEvent};
+use crate::tools::rate_limit::{RateLimit, RateLimited, RateLimiter};
+use crate::tools::read_cache::ReadCache;
+use crate::tools::recovery::{Recovering, Recovery, DEFAULT_MAX_MALFORMED};
+use crate::tools::review::Reviewer;
+use crate::tools::shared::Shared;
+use crate::tools::untrusted::Guarded;
+use crate::tools::weather::Weather;
 
 pub struct OllamaClient {
     client: Ollama,
     model: String,
-    host: String,
-    port: u16,
+    endpoint: Endpoint,
     last_used_tools: Arc<Mutex<Vec<String>>>,
+    last_tool_invocations: Arc<Mutex<Vec<ToolInvocation>>>,
+    // Stateful tools kept for the whole conversation
+    bash: Shared<Bash>,
+    read_cache: Arc<ReadCache>,
+    policy: Arc<ExecutionPolicy>,
+    // How long Ollama keeps the model loaded after a request; its default if None
+    keep_alive: Option<KeepAlive>,
+    // Which tools the config lets the model use
+    tools_config: Mutex<ToolsConfig>,
+    // Limits calls to the network tools, and remembers those of the current turn
+    limiter: Arc<RateLimiter>,
+    // Answers malformed tool calls instead of failing the turn, up to a point
+    recovery: Arc<Recovery>,
+    // Response length and stop sequences sent with every request
+    limits: Mutex<ResponseLimits>,
+    // The context window sent with every request, in tokens
+    context_window: AtomicUsize,
+    // Whether the last response used up the token limit
+    truncated: AtomicBool,
+    // The facts the memory tool works on, once the conversation has a store
+    memory: Mutex<Option<Arc<Memory>>>,
+    // The coordinator and its tools, built by the first request with tools and
+    // reused by the next ones. A request takes it out while it runs, so
+    // requests at the same time build their own
+    coordinator: Mutex<Option<ToolCoordinator>>,
+    // Bumped whenever the tools change, so a coordinator built before isn't kept
+    tools_generation: AtomicUsize,
+    // The last request sent, to tell whether Ollama could reuse it, and what it evaluated
+    prefix: PrefixCache,
+    // Where the time of the responses since last asked went on the server
+    timings: Mutex<ServerTimings>,
 }
 
-/// Get the weather for a given city.
-///
-/// * city - City to get the weather for.
-#[ollama_rs::function]
-async fn get_weather(city: String) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
-    Ok(reqwest::get(format!("https://wttr.in/{city}?format=%C+%t"))
-        .await?
-        .text()
-        .await?)
+impl Default for OllamaClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// How long to wait for the server to say which models it has loaded; the
+// answer only picks what the spinner says, so it is not worth waiting for
+const RUNNING_TIMEOUT: Duration = Duration::from_secs(1);
+
+// The tools the client runs itself, as the model knows them
+const BUILTIN_TOOLS: [&str; 9] = [
+    "Calculator",
+    "weather",
+    "DDGSearcher",
+    "Scraper",
+    "bash",
+    "ls",
+    "file",
+    "apply_patch",
+    "memory",
+];
+
+// The API client for `endpoint`
+fn ollama_at(endpoint: &Endpoint) -> Ollama {
+    Ollama::from_url(
+        endpoint
+            .url()
+            .expect("endpoints are checked when they are parsed"),
+    )
 }
 
 impl OllamaClient {
     pub fn new() -> Self {
-        // Default to localhost:11434 if not specified
-        let host = env::var("OLLAMA_HOST").unwrap_or_else(|_| "http://localhost".to_string());
-        let port = env::var("OLLAMA_PORT")
-            .unwrap_or_else(|_| "11434".to_string())
-            .parse::<u16>()
-            .unwrap_or(11434);
+        // Default to localhost:11434 if not specified; an invalid OLLAMA_HOST
+        // is reported when the config's endpoints are read
+        let endpoint = Endpoint::from_env().unwrap_or_default();
 
         // Default model (use llama3.2 which is available)
         let model = env::var("OLLAMA_MODEL").unwrap_or_else(|_| "llama3.2:latest".to_string());
 
+        let policy = Arc::new(ExecutionPolicy::new());
+
         Self {
-            client: Ollama::new(host.clone(), port),
+            client: ollama_at(&endpoint),
             model,
-            host,
-            port,
+            endpoint,
             last_used_tools: Arc::new(Mutex::new(Vec::new())),
+            last_tool_invocations: Arc::new(Mutex::new(Vec::new())),
+            bash: Shared::new(Bash::new().with_policy(Arc::clone(&policy))),
+            read_cache: ReadCache::shared(),
+            policy,
+            keep_alive: None,
+            tools_config: Mutex::default(),
+            limiter: RateLimiter::shared(RateLimit::default()),
+            recovery: Recovery::shared(DEFAULT_MAX_MALFORMED),
+            limits: Mutex::default(),
+            context_window: AtomicUsize::new(context::CONTEXT_TOKENS),
+            truncated: AtomicBool::new(false),
+            memory: Mutex::default(),
+            coordinator: Mutex::default(),
+            tools_generation: AtomicUsize::new(0),
+            prefix: PrefixCache::new(),
+            timings: Mutex::default(),
         }
     }
 
@@ -61,18 +164,13 @@ impl OllamaClient {
         tools.clone()
     }
 
-    // Get a list of available tools
-    pub fn get_available_tools(&self) -> Vec<String> {
-        // Return the names of all tools that are available to the LLM
-        vec![
-            "Calculator".to_string(),
-            "weather".to_string(),
-            "DDGSearcher".to_string(),
-            "Scraper".to_string(),
-            "bash".to_string(),
-            "ls".to_string(),
-            "file".to_string(),
-        ]
+    // The underlying Ollama API client
+    pub fn ollama(&self) -> &Ollama {
+        &self.client
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
     }
 
     pub fn with_model(mut self, model: &str) -> Self {
@@ -80,11 +178,227 @@ impl OllamaClient {
         self
     }
 
+    /// Talk to the Ollama server at `endpoint` instead of the one from the environment
+    pub fn with_endpoint(mut self, endpoint: &Endpoint) -> Self {
+        self.client = ollama_at(endpoint);
+        self.endpoint = endpoint.clone();
+        self
+    }
+
+    /// Keep the model loaded for this long after each request instead of Ollama's default
+    pub fn with_keep_alive(mut self, keep_alive: Option<KeepAlive>) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    // The options of every request: `base` with the context window, the
+    // response limits and per-request settings
+    fn model_options(&self, base: ModelOptions, options: &GenerationOptions) -> ModelOptions {
+        let base = base.num_ctx(self.context_window.load(Ordering::Relaxed) as u64);
+        options.apply(self.limits.lock().unwrap().apply(base))
+    }
+
+    // Add the time the server reported spending on a response
+    fn record_timings(&self, timings: Option<ServerTimings>) {
+        if let Some(timings) = timings {
+            self.timings.lock().unwrap().add(timings);
+        }
+    }
+
+    // Remember whether a response of `output_tokens` used up the token limit
+    fn record_truncated(&self, output_tokens: Option<u64>) {
+        let truncated =
+            output_tokens.is_some_and(|tokens| self.limits.lock().unwrap().reached(tokens));
+        self.truncated.store(truncated, Ordering::SeqCst);
+    }
+
+    // The coordinator of the last request with tools, or a new one if it
+    // is in use or the tools changed since
+    fn take_coordinator(
+        &self,
+        tools_config: &ToolsConfig,
+        custom: &[CustomToolConfig],
+    ) -> ToolCoordinator {
+        let generation = self.tools_generation.load(Ordering::SeqCst);
+        match self.coordinator.lock().unwrap().take() {
+            Some(kept) if kept.generation == generation => kept,
+            _ => self.build_coordinator(tools_config, custom, generation),
+        }
+    }
+
+    // Keep `coordinator` for the next request, unless the tools changed meanwhile
+    fn keep_coordinator(&self, coordinator: ToolCoordinator) {
+        if coordinator.generation == self.tools_generation.load(Ordering::SeqCst) {
+            *self.coordinator.lock().unwrap() = Some(coordinator);
+        }
+    }
+
+    // Build the next coordinator afresh, with the tools as they are then
+    fn forget_coordinator(&self) {
+        self.tools_generation.fetch_add(1, Ordering::SeqCst);
+        *self.coordinator.lock().unwrap() = None;
+    }
+
+    // A coordinator with every tool the config allows. Network tools are rate
+    // limited, and repeated calls answered from the turn's memo
+    fn build_coordinator(
+        &self,
+        tools_config: &ToolsConfig,
+        custom: &[CustomToolConfig],
+        generation: usize,
+    ) -> ToolCoordinator {
+        let history = RecordedHistory::reporting_to(Arc::clone(&self.policy));
+        let coordinator =
+            Coordinator::new(self.client.clone(), self.model.clone(), history.clone());
+        let coordinator = add_allowed(
+            coordinator,
+            tools_config,
+            &self.recovery,
+            &self.policy,
+            "weather",
+            RateLimited::new(
+                Weather::new(tools_config.weather.clone()),
+                Arc::clone(&self.limiter),
+            ),
+        );
+        let coordinator = add_allowed(
+            coordinator,
+            tools_config,
+            &self.recovery,
+            &self.policy,
+            "Calculator",
+            Calculator {},
+        );
+        let coordinator = add_allowed(
+            coordinator,
+            tools_config,
+            &self.recovery,
+            &self.policy,
+            "DDGSearcher",
+            RateLimited::new(DDGSearcher::new(), Arc::clone(&self.limiter)),
+        );
+        let coordinator = add_allowed(
+            coordinator,
+            tools_config,
+            &self.recovery,
+            &self.policy,
+            "Scraper",
+            RateLimited::new(Scraper {}, Arc::clone(&self.limiter)),
+        );
+        let coordinator = add_allowed(
+            coordinator,
+            tools_config,
+            &self.recovery,
+            &self.policy,
+            "bash",
+            self.bash.clone(),
+        );
+        let coordinator = add_allowed(
+            coordinator,
+            tools_config,
+            &self.recovery,
+            &self.policy,
+            "ls",
+            Ls::new(),
+        );
+        let coordinator = add_allowed(
+            coordinator,
+            tools_config,
+            &self.recovery,
+            &self.policy,
+            "file",
+            FileTool::with_policy(Arc::clone(&self.policy))
+                .with_cache(Arc::clone(&self.read_cache)),
+        );
+        let coordinator = add_allowed(
+            coordinator,
+            tools_config,
+            &self.recovery,
+            &self.policy,
+            "find_file",
+            FindAndReadFileTool::new().with_cache(Arc::clone(&self.read_cache)),
+        );
+        let mut coordinator = add_allowed(
+            coordinator,
+            tools_config,
+            &self.recovery,
+            &self.policy,
+            "apply_patch",
+            ApplyPatch::with_policy(Arc::clone(&self.policy)),
+        );
+        if let Some(memory) = self.memory.lock().unwrap().clone() {
+            coordinator = add_allowed(
+                coordinator,
+                tools_config,
+                &self.recovery,
+                &self.policy,
+                "memory",
+                MemoryTool::new(memory),
+            );
+        }
+        if !custom.is_empty() {
+            coordinator = coordinator.add_tool(Recovering::new(
+                CustomTools::new(custom, &self.policy),
+                Arc::clone(&self.recovery),
+            ));
+        }
+        if let Some(keep_alive) = &self.keep_alive {
+            coordinator = coordinator.keep_alive(keep_alive.clone());
+        }
+        ToolCoordinator {
+            coordinator,
+            history,
+            generation,
+        }
+    }
+
+    // A completion request with the client's model, limits and keep-alive setting
+    fn generation_request<'a>(
+        &self,
+        prompt: String,
+        options: &GenerationOptions,
+    ) -> GenerationRequest<'a> {
+        let request = GenerationRequest::new(self.model.clone(), prompt)
+            .options(self.model_options(ModelOptions::default(), options));
+        match &self.keep_alive {
+            Some(keep_alive) => request.keep_alive(keep_alive.clone()),
+            None => request,
+        }
+    }
+
+    // A chat request with the client's model, limits and keep-alive setting
+    fn chat_request(
+        &self,
+        messages: Vec<ChatMessage>,
+        options: &GenerationOptions,
+    ) -> ChatMessageRequest {
+        let request = ChatMessageRequest::new(self.model.clone(), messages)
+            .options(self.model_options(ModelOptions::default(), options));
+        match &self.keep_alive {
+            Some(keep_alive) => request.keep_alive(keep_alive.clone()),
+            None => request,
+        }
+    }
+
     fn convert_message_to_chat_message(message: &Message) -> ChatMessage {
-        match message.role {
+        let chat_message = match message.role {
             Role::User => ChatMessage::user(message.content.clone()),
             Role::Assistant => ChatMessage::assistant(message.content.clone()),
             Role::System => ChatMessage::system(message.content.clone()),
+            Role::Tool => ChatMessage::tool(message.content.clone()),
+        };
+
+        // Images restored from a saved conversation have no data to send
+        let images: Vec<Image> = message
+            .images
+            .iter()
+            .filter(|image| !image.data.is_empty())
+            .map(|image| Image::from_base64(image.data.clone()))
+            .collect();
+        if images.is_empty() {
+            chat_message
+        } else {
+            chat_message.with_images(images)
         }
     }
 
@@ -93,6 +407,54 @@ impl OllamaClient {
     fn estimate_token_count(text: &str) -> usize {
         (text.len() as f32 / 4.0).ceil() as usize
     }
+
+    // The estimated tokens of a whole prompt
+    fn estimate_prompt_tokens(messages: &[Message]) -> usize {
+        Self::estimate_token_count(
+            &messages
+                .iter()
+                .fold(String::new(), |acc, m| acc + &m.content + "\n"),
+        )
+    }
+
+    // The model's /api/show metadata; ollama-rs drops the capabilities,
+    // template and projector info from it
+    async fn show(&self) -> Option<serde_json::Value> {
+        reqwest::Client::new()
+            .post(format!("{}api/show", self.client.url_str()))
+            .json(&serde_json::json!({ "model": self.model, "name": self.model }))
+            .send()
+            .await
+            .ok()?
+            .error_for_status()
+            .ok()?
+            .json()
+            .await
+            .ok()
+    }
+
+    // Whether the server has the model loaded, from /api/ps; None if it
+    // can't be asked in time
+    async fn running(&self) -> Option<bool> {
+        let running: serde_json::Value = reqwest::Client::new()
+            .get(format!("{}api/ps", self.client.url_str()))
+            .timeout(RUNNING_TIMEOUT)
+            .send()
+            .await
+            .ok()?
+            .error_for_status()
+            .ok()?
+            .json()
+            .await
+            .ok()?;
+        let model = availability::normalize(&self.model);
+        Some(running["models"].as_array()?.iter().any(|loaded| {
+            ["name", "model"]
+                .iter()
+                .filter_map(|key| loaded[key].as_str())
+                .any(|name| availability::normalize(name) == model)
+        }))
+    }
 }
 
 #[async_trait]
@@ -104,10 +466,353 @@ pub trait LlmClient: Send + Sync {
         messages: &[Message],
         tools: &[Tool],
     ) -> Result<(String, usize, usize, Vec<String>)>;
+
+    // Names of the tools the model can call
+    fn available_tools(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    // Tools run during the last generate_response_with_tools call, with their arguments and output
+    fn last_tool_invocations(&self) -> Vec<ToolInvocation> {
+        Vec::new()
+    }
+
+    // Reset conversation-scoped tool state, such as the Bash working directory
+    fn reset_tools(&self) {}
+
+    // Preview mutating tool actions instead of executing them
+    fn set_dry_run(&self, _dry_run: bool) {}
+
+    fn dry_run(&self) -> bool {
+        false
+    }
+
+    // Ask `reviewer` before the client's own tools change files
+    fn set_reviewer(&self, _reviewer: Arc<dyn Reviewer>) {}
+
+    // Show the output of the client's own tools while they run
+    fn set_progress(&self, _progress: Arc<dyn Progress>) {}
+
+    // Report the calls of the client's own tools as they start and end
+    fn set_tool_events(&self, _events: Option<UnboundedSender<ToolEvent>>) {}
+
+    // Record the file changes of the client's own tools in `journal`, for /undo
+    fn set_journal(&self, _journal: Arc<Journal>) {}
+
+    // Ask through `gate` before the output of the client's own tools goes to a remote server
+    fn set_remote_gate(&self, _gate: Arc<RemoteGate>) {}
+
+    // Let the model remember facts in `memory` with the client's own memory tool
+    fn set_memory(&self, _memory: Arc<Memory>) {}
+
+    // Write-protect paths matching `rule` from the client's own tools for the rest of the session
+    fn protect(&self, _rule: &str) {}
+
+    // Apply the tool settings of a config, such as the enabled tools, the sandbox root and protected paths
+    fn apply_config(&self, _config: &Config) {}
+
+    // Offer the client's own tool called `name` to the model, or stop offering
+    // it, from the next request; false if the client has no such tool
+    fn set_tool_enabled(&self, _name: &str, _enabled: bool) -> bool {
+        false
+    }
+
+    // Who serves the model, recorded on each reply
+    fn provider(&self) -> &str {
+        "unknown"
+    }
+
+    // The model replies come from, when the client knows better than its caller
+    fn model_name(&self) -> Option<String> {
+        None
+    }
+
+    // Check the server can be reached before sending it requests
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
+
+    // Whether the model can read images, or None when that can't be told
+    async fn supports_images(&self) -> Option<bool> {
+        None
+    }
+
+    // Whether the model can call tools, or None when that can't be told
+    async fn supports_tools(&self) -> Option<bool> {
+        None
+    }
+
+    // The most tokens of context the model supports, or None when that can't be told
+    async fn context_length(&self) -> Option<usize> {
+        None
+    }
+
+    // The server requests go to, e.g. "http://localhost:11434", for the status bar
+    fn endpoint(&self) -> Option<String> {
+        None
+    }
+
+    // Ask before content from `sources`, read on this machine, goes to a
+    // server on another machine; an error if the user doesn't agree
+    fn check_remote(&self, _sources: &[String]) -> Result<()> {
+        Ok(())
+    }
+
+    // Things the client did on its own that the user should hear about,
+    // such as switching servers; each is returned once
+    fn take_notices(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    // Tool calls the client refused for going over a rate limit since the last time this was asked
+    fn take_rate_limited(&self) -> usize {
+        0
+    }
+
+    // Malformed tool calls the client's own tools recovered from since the last time this was asked
+    fn take_recovered_calls(&self) -> usize {
+        0
+    }
+
+    // What the server evaluated of the prompts sent since the last time this was asked
+    fn take_prompt_usage(&self) -> PromptUsage {
+        PromptUsage::default()
+    }
+
+    // Where the server's time went on the responses since the last time this was asked
+    fn take_server_timings(&self) -> ServerTimings {
+        ServerTimings::default()
+    }
+
+    // Cap the length of responses and set where they stop, for every request from now on
+    fn set_limits(&self, _limits: ResponseLimits) {}
+
+    // The limits set with set_limits, whose timeout callers keep around whole turns
+    fn limits(&self) -> ResponseLimits {
+        ResponseLimits::default()
+    }
+
+    // Send every request from now on with a context window of `tokens`
+    fn set_context_window(&self, _tokens: usize) {}
+
+    // Whether the last response was cut off by the token limit
+    fn take_truncated(&self) -> bool {
+        false
+    }
+
+    // Load the model ahead of the first real request, and how long the server took
+    async fn warm_up(&self) -> Result<ServerTimings> {
+        Ok(ServerTimings::default())
+    }
+
+    // Whether the model is loaded on the server now, or None if the client can't tell
+    async fn model_loaded(&self) -> Option<bool> {
+        None
+    }
+
+    // Release the model's memory now instead of when it would time out
+    async fn unload(&self) -> Result<()> {
+        Err(anyhow!("This client cannot unload its model"))
+    }
+
+    // A client for `model` on the same server and with the same limits, for /compare
+    fn for_model(&self, model: &str) -> Result<Box<dyn LlmClient>> {
+        Err(anyhow!(
+            "This client cannot ask another model such as '{}'",
+            model
+        ))
+    }
+
+    // Generate with per-request settings such as a sampling seed.
+    // Clients that don't support the settings ignore them
+    async fn generate_response_with_options(
+        &self,
+        messages: &[Message],
+        _options: &GenerationOptions,
+    ) -> Result<(String, usize, usize)> {
+        self.generate_response(messages).await
+    }
+
+    async fn generate_response_with_tools_and_options(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+        _options: &GenerationOptions,
+    ) -> Result<(String, usize, usize, Vec<String>)> {
+        self.generate_response_with_tools(messages, tools).await
+    }
+
+    // Whether the client hands tool calls back to the caller through chat_step.
+    // Clients that run tools themselves (like OllamaClient, through the
+    // ollama-rs Coordinator) are used through generate_response_with_tools
+    fn supports_tool_calls(&self) -> bool {
+        false
+    }
+
+    // One model turn: either a final answer or a request to call tools
+    async fn chat_step(&self, _messages: &[Message], _tools: &[Tool]) -> Result<ModelTurn> {
+        Err(anyhow!("This client does not hand back tool calls"))
+    }
+
+    // One model turn with per-request settings such as a sampling seed
+    async fn chat_step_with_options(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+        _options: &GenerationOptions,
+    ) -> Result<ModelTurn> {
+        self.chat_step(messages, tools).await
+    }
+
+    // Where the tools run for the client's tool calls are recorded, or
+    // answered from, when the session is recorded or replayed
+    fn tape(&self) -> Option<Tape> {
+        None
+    }
+}
+
+// A tool the model asked to call, with its JSON arguments
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub name: String,
+    pub arguments: serde_json::Value,
+}
+
+// A tool that was run for the model, with what it was given and what it returned
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolInvocation {
+    pub name: String,
+    pub arguments: serde_json::Value,
+    pub output: String,
+    // What the model was sent instead of a long `output`, marked as a summary
+    pub summary: Option<String>,
+    // How long the tool ran
+    pub duration: Duration,
+}
+
+impl ToolInvocation {
+    // How long the call took, labelled with the operation of tools that have several
+    pub fn timing(&self) -> ToolTiming {
+        let label = match self.arguments.get("operation").and_then(|op| op.as_str()) {
+            Some(operation) => format!("{} {}", self.name, operation),
+            None => self.name.clone(),
+        };
+        ToolTiming {
+            label,
+            tool: self.name.clone(),
+            duration: self.duration,
+        }
+    }
+
+    // Estimated tokens of the whole output
+    pub fn output_tokens(&self) -> usize {
+        stats::estimate_tokens(&self.output)
+    }
+
+    // Estimated tokens sent back to the model: the summary's, if the output had one
+    pub fn sent_tokens(&self) -> usize {
+        stats::estimate_tokens(self.summary.as_ref().unwrap_or(&self.output))
+    }
+}
+
+// What the model produced in one step of a conversation
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModelTurn {
+    pub content: String,
+    pub tool_calls: Vec<ToolCall>,
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+}
+
+/// The error of a reply without any text to show, e.g. only images
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NoText {
+    /// Images sent instead of text, which can't be shown
+    pub images: usize,
+}
+
+impl fmt::Display for NoText {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.images {
+            0 => write!(f, "the reply had no text"),
+            1 => write!(f, "the reply had no text, only an image"),
+            n => write!(f, "the reply had no text, only {} images", n),
+        }
+    }
+}
+
+impl std::error::Error for NoText {}
+
+/// The text of a reply meant as the final answer. A reply that only asks for
+/// tools is a step towards an answer, and one without text has nothing to show.
+pub fn reply_text(message: ChatMessage) -> Result<String> {
+    if message.content.trim().is_empty() {
+        if !message.tool_calls.is_empty() {
+            let names: Vec<&str> = message
+                .tool_calls
+                .iter()
+                .map(|call| call.function.name.as_str())
+                .collect();
+            return Err(anyhow!(
+                "The model asked to call {} instead of answering",
+                names.join(", ")
+            ));
+        }
+        let images = message.images.map_or(0, |images| images.len());
+        return Err(NoText { images }.into());
+    }
+    Ok(message.content)
+}
+
+// Per-request generation settings
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GenerationOptions {
+    // Sampling seed; the same seed and prompt give the same answer
+    pub seed: Option<i32>,
+}
+
+impl GenerationOptions {
+    pub fn with_seed(seed: i32) -> Self {
+        Self { seed: Some(seed) }
+    }
+
+    // Apply these settings on top of a model's base options
+    fn apply(&self, options: ModelOptions) -> ModelOptions {
+        match self.seed {
+            Some(seed) => options.seed(seed),
+            None => options,
+        }
+    }
+}
+
+// Add `tool` to the coordinator unless the config leaves the tool called `name` out,
+// with its malformed calls going through `recovery` and any web content it
+// fetches guarded by `policy`
+fn add_allowed<C, T>(
+    coordinator: Coordinator<C>,
+    tools_config: &ToolsConfig,
+    recovery: &Arc<Recovery>,
+    policy: &Arc<ExecutionPolicy>,
+    name: &str,
+    tool: T,
+) -> Coordinator<C>
+where
+    C: ChatHistory,
+    T: ollama_rs::generation::tools::Tool + 'static,
+    T::Params: Send + Sync,
+{
+    if tools_config.allows(name) {
+        coordinator.add_tool(Recovering::new(
+            Guarded::new(tool, Arc::clone(policy)),
+            Arc::clone(recovery),
+        ))
+    } else {
+        coordinator
+    }
 }
 
 // Tool definition
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Tool {
     pub name: String,
     pub description: String,
@@ -120,24 +825,251 @@ impl LlmClient for OllamaClient {
         self
     }
 
+    fn available_tools(&self) -> Vec<String> {
+        // Return the names of all tools that are available to the LLM
+        let tools_config = self.tools_config.lock().unwrap();
+        BUILTIN_TOOLS
+            .iter()
+            .filter(|name| tools_config.allows(name))
+            .filter(|name| **name != "memory" || self.memory.lock().unwrap().is_some())
+            .map(|name| name.to_string())
+            .collect()
+    }
+
+    fn last_tool_invocations(&self) -> Vec<ToolInvocation> {
+        self.last_tool_invocations.lock().unwrap().clone()
+    }
+
+    fn reset_tools(&self) {
+        let policy = Arc::clone(&self.policy);
+        self.bash
+            .replace_with(move || Bash::new().with_policy(policy));
+        self.read_cache.clear();
+        self.forget_coordinator();
+    }
+
+    fn set_dry_run(&self, dry_run: bool) {
+        self.policy.set_dry_run(dry_run);
+    }
+
+    fn dry_run(&self) -> bool {
+        self.policy.dry_run()
+    }
+
+    fn set_reviewer(&self, reviewer: Arc<dyn Reviewer>) {
+        self.policy.set_reviewer(reviewer);
+    }
+
+    fn set_progress(&self, progress: Arc<dyn Progress>) {
+        self.policy.set_progress(progress);
+    }
+
+    fn set_tool_events(&self, events: Option<UnboundedSender<ToolEvent>>) {
+        self.policy.set_tool_events(events);
+    }
+
+    fn set_journal(&self, journal: Arc<Journal>) {
+        self.policy.set_journal(journal);
+    }
+
+    fn set_remote_gate(&self, gate: Arc<RemoteGate>) {
+        self.policy.set_remote_gate(gate);
+    }
+
+    fn set_memory(&self, memory: Arc<Memory>) {
+        *self.memory.lock().unwrap() = Some(memory);
+        self.forget_coordinator();
+    }
+
+    fn protect(&self, rule: &str) {
+        self.policy.protect(rule);
+    }
+
+    fn apply_config(&self, config: &Config) {
+        *self.tools_config.lock().unwrap() = config.tools.clone();
+        self.forget_coordinator();
+        self.limiter.set_limit(config.tools.rate_limit);
+        self.recovery
+            .set_max_malformed(config.tools.max_malformed_calls());
+        self.policy.configure(config);
+    }
+
+    fn set_tool_enabled(&self, name: &str, enabled: bool) -> bool {
+        let mut tools_config = self.tools_config.lock().unwrap();
+        let known: Vec<String> = BUILTIN_TOOLS
+            .iter()
+            .map(|tool| tool.to_string())
+            .chain(tools_config.custom.iter().map(|tool| tool.name.clone()))
+            .collect();
+        let Some(name) = known.iter().find(|tool| tool.eq_ignore_ascii_case(name)) else {
+            return false;
+        };
+        tools_config.set_enabled(name, enabled, &known);
+        drop(tools_config);
+        self.forget_coordinator();
+        true
+    }
+
+    fn provider(&self) -> &str {
+        "ollama"
+    }
+
+    fn model_name(&self) -> Option<String> {
+        Some(self.model.clone())
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.client
+            .list_local_models()
+            .await
+            .with_context(|| format!("Failed to reach Ollama at {}", self.endpoint.address()))?;
+        Ok(())
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        Some(self.endpoint.address())
+    }
+
+    fn check_remote(&self, sources: &[String]) -> Result<()> {
+        self.policy.set_remote_endpoint(self.endpoint());
+        Ok(self.policy.check_remote(sources)?)
+    }
+
+    async fn supports_images(&self) -> Option<bool> {
+        images::vision_from_metadata(&self.show().await?)
+    }
+
+    async fn supports_tools(&self) -> Option<bool> {
+        tool_support::tools_from_metadata(&self.show().await?)
+    }
+
+    async fn context_length(&self) -> Option<usize> {
+        context::length_from_metadata(&self.show().await?)
+    }
+
+    fn take_rate_limited(&self) -> usize {
+        self.limiter.take_suppressed()
+    }
+
+    fn take_recovered_calls(&self) -> usize {
+        self.recovery.take_recovered()
+    }
+
+    fn take_prompt_usage(&self) -> PromptUsage {
+        self.prefix.take_usage()
+    }
+
+    fn take_server_timings(&self) -> ServerTimings {
+        std::mem::take(&mut *self.timings.lock().unwrap())
+    }
+
+    async fn model_loaded(&self) -> Option<bool> {
+        self.running().await
+    }
+
+    fn set_limits(&self, limits: ResponseLimits) {
+        *self.limits.lock().unwrap() = limits;
+    }
+
+    fn limits(&self) -> ResponseLimits {
+        self.limits.lock().unwrap().clone()
+    }
+
+    fn set_context_window(&self, tokens: usize) {
+        self.context_window.store(tokens, Ordering::Relaxed);
+    }
+
+    fn take_truncated(&self) -> bool {
+        self.truncated.swap(false, Ordering::SeqCst)
+    }
+
+    async fn warm_up(&self) -> Result<ServerTimings> {
+        // An empty prompt only loads the model
+        let response = self
+            .client
+            .generate(self.generation_request(String::new(), &GenerationOptions::default()))
+            .await
+            .with_context(|| format!("Failed to load model '{}'", self.model))?;
+        Ok(ServerTimings::of_generation(&response).unwrap_or_default())
+    }
+
+    fn for_model(&self, model: &str) -> Result<Box<dyn LlmClient>> {
+        let client = OllamaClient::new()
+            .with_endpoint(&self.endpoint)
+            .with_model(model)
+            .with_keep_alive(self.keep_alive.clone());
+        client.set_limits(self.limits());
+        Ok(Box::new(client))
+    }
+
+    async fn unload(&self) -> Result<()> {
+        let request = GenerationRequest::new(self.model.clone(), "")
+            .keep_alive(KeepAlive::UnloadOnCompletion);
+        self.client
+            .generate(request)
+            .await
+            .with_context(|| format!("Failed to unload model '{}'", self.model))?;
+        Ok(())
+    }
+
     async fn generate_response(&self, messages: &[Message]) -> Result<(String, usize, usize)> {
+        self.generate_response_with_options(messages, &GenerationOptions::default())
+            .await
+    }
+
+    async fn generate_response_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(String, usize, usize, Vec<String>)> {
+        self.generate_response_with_tools_and_options(
+            messages,
+            tools,
+            &GenerationOptions::default(),
+        )
+        .await
+    }
+
+    async fn generate_response_with_options(
+        &self,
+        messages: &[Message],
+        options: &GenerationOptions,
+    ) -> Result<(String, usize, usize)> {
         if messages.is_empty() {
             return Err(anyhow::anyhow!("Empty messages"));
         }
 
-        // For a simple completion with just the last message
-        if messages.len() == 1 {
-            let prompt = messages[0].content.clone();
-            let request = GenerationRequest::new(self.model.clone(), prompt);
+        // A simple completion for a single message, or for the next prompt of
+        // an exchange begun that way; images need the chat interface
+        let continuation = self.prefix.continuation(messages);
+        if (messages.len() == 1 && messages[0].images.is_empty()) || continuation.is_some() {
+            let prompt = messages[messages.len() - 1].content.clone();
+            let mut request = self.generation_request(prompt, options);
+            if let Some(context) = &continuation {
+                request = request.context(context.clone());
+            }
 
-            let response = self
-                .client
-                .generate(request)
-                .await
-                .context("Failed to generate completion from Ollama")?;
+            let response = deadline::within(&self.limits(), async {
+                self.client
+                    .generate(request)
+                    .await
+                    .context("Failed to generate completion from Ollama")
+            })
+            .await?;
+            self.record_truncated(response.eval_count);
+            self.record_timings(ServerTimings::of_generation(&response));
+            if response.response.trim().is_empty() {
+                return Err(NoText::default().into());
+            }
+            let input_tokens = Self::estimate_prompt_tokens(messages);
+            if let Some(evaluated) = response.prompt_eval_count {
+                self.prefix
+                    .record(continuation.is_some(), input_tokens, evaluated);
+            }
+            self.prefix
+                .remember(messages, &response.response, response.context.clone());
 
-            // For single message completion, we don't get token counts, so estimate
-            let input_tokens = Self::estimate_token_count(&messages[0].content);
+            // For completions, we don't get token counts, so estimate
             let output_tokens = Self::estimate_token_count(&response.response);
 
             return Ok((response.response, input_tokens, output_tokens));
@@ -150,31 +1082,38 @@ impl LlmClient for OllamaClient {
             .collect();
 
         // Using the chat interface for multiple messages
-        let request = ChatMessageRequest::new(self.model.clone(), chat_messages);
+        let shared = self.prefix.observe(&chat_messages);
+        let request = self.chat_request(chat_messages, options);
 
-        let response = self
-            .client
-            .send_chat_messages(request)
-            .await
-            .context("Failed to generate chat response from Ollama")?;
+        let response = deadline::within(&self.limits(), async {
+            self.client
+                .send_chat_messages(request)
+                .await
+                .context("Failed to generate chat response from Ollama")
+        })
+        .await?;
+        self.record_truncated(response.final_data.as_ref().map(|data| data.eval_count));
+        self.record_timings(response.final_data.as_ref().map(ServerTimings::of_chat));
 
         // For chat messages, we get an eval count which somewhat correlates to token count
         // This is a rough estimate - done is a boolean in recent ollama-rs versions,
         // so we need to just estimate tokens
-        let input_tokens = Self::estimate_token_count(
-            &messages
-                .iter()
-                .fold(String::new(), |acc, m| acc + &m.content + "\n"),
-        );
-        let output_tokens = Self::estimate_token_count(&response.message.content);
+        let input_tokens = Self::estimate_prompt_tokens(messages);
+        if let Some(data) = &response.final_data {
+            self.prefix
+                .record(shared.extends, input_tokens, data.prompt_eval_count);
+        }
+        let text = reply_text(response.message)?;
+        let output_tokens = Self::estimate_token_count(&text);
 
-        Ok((response.message.content, input_tokens, output_tokens))
+        Ok((text, input_tokens, output_tokens))
     }
 
-    async fn generate_response_with_tools(
+    async fn generate_response_with_tools_and_options(
         &self,
         messages: &[Message],
         _tools: &[Tool],
+        options: &GenerationOptions,
     ) -> Result<(String, usize, usize, Vec<String>)> {
         if messages.is_empty() {
             return Err(anyhow::anyhow!("Empty messages"));
@@ -188,163 +1127,695 @@ impl LlmClient for OllamaClient {
             return Err(anyhow::anyhow!("Last message must be from user"));
         }
 
-        // Create a copy of the Ollama client
-        let ollama_client = Ollama::new(self.host.clone(), self.port);
-
         // Convert messages to ChatMessage format for history
-        let chat_history: Vec<ChatMessage> = messages
+        let mut chat_history: Vec<ChatMessage> = messages
             .iter()
             .take(messages.len() - 1) // All except the last message
             .map(Self::convert_message_to_chat_message)
             .collect();
 
-        // Clear the tracked tools list before this new response
-        {
-            let mut tools = self.last_used_tools.lock().unwrap();
-            tools.clear();
+        // Custom tools have no types of their own, so they are run through one
+        // tool, and the model learns of them after the system prompt
+        let tools_config = self.tools_config.lock().unwrap().clone();
+        let custom: Vec<CustomToolConfig> = tools_config
+            .custom
+            .iter()
+            .filter(|tool| tools_config.allows(&tool.name) && tool.check().is_ok())
+            .cloned()
+            .collect();
+        if !custom.is_empty() {
+            let at = chat_history
+                .iter()
+                .take_while(|message| message.role == MessageRole::System)
+                .count();
+            chat_history.insert(at, ChatMessage::system(custom::catalog(&custom)));
         }
 
-        // Create a coordinator with tools
-        let mut coordinator = ollama_rs::coordinator::Coordinator::new(
-            ollama_client,
-            self.model.clone(),
-            chat_history,
-        )
-        .options(ModelOptions::default().num_ctx(16384))
-        .add_tool(get_weather)
-        .add_tool(Calculator {})
-        .add_tool(DDGSearcher::new())
-        .add_tool(Scraper {})
-        .add_tool(Bash::new())
-        .add_tool(Ls::new())
-        .add_tool(FileTool::new())
-        .add_tool(FindAndReadFileTool {});
-
-        // Print that we're using tools in coordinator
-        println!("\x1b[1;34m[COORDINATOR] Starting conversation with tools enabled\x1b[0m");
-
-        // Send the last user message to the coordinator
-        let user_message = ChatMessage::user(last_message.content.clone());
-
-        let response = coordinator
-            .chat(vec![user_message])
-            .await
-            .context("Failed to generate response with tools")?;
+        // The tools are set up once; each request only brings its history and options
+        let ToolCoordinator {
+            coordinator,
+            history,
+            generation,
+        } = self.take_coordinator(&tools_config, &custom);
+        history.start(chat_history);
+        let mut coordinator =
+            coordinator.options(self.model_options(ModelOptions::default(), options));
+        self.limiter.start_turn();
+        self.recovery.start_turn();
+        self.policy.start_turn();
+        self.policy.set_remote_endpoint(self.endpoint());
 
-        // Track which tools were actually used in this response
-        // by examining the tool_calls in the final response message
-        {
-            let mut tools = self.last_used_tools.lock().unwrap();
-
-            // Check if there are any tool calls in the response message
-            if !response.message.tool_calls.is_empty() {
-                for tool_call in &response.message.tool_calls {
-                    // Add each unique tool name to our tracking list
-                    let tool_name = tool_call.function.name.clone();
-                    if !tools.contains(&tool_name) {
-                        println!(
-                            "\x1b[1;33m[TOOL USAGE] Tool '{}' was used in response\x1b[0m",
-                            tool_name
-                        );
-                        tools.push(tool_name);
-                    }
-                }
+        // Send the last user message to the coordinator, which sends it after the history
+        let user_message = Self::convert_message_to_chat_message(last_message);
+        let shared = self.prefix.observe(
+            &[
+                history.messages().as_ref(),
+                std::slice::from_ref(&user_message),
+            ]
+            .concat(),
+        );
+
+        // The coordinator runs the tools itself, so its turn is timed as a whole
+        let response = deadline::within(&self.limits(), async {
+            coordinator
+                .chat(vec![user_message])
+                .await
+                .context("Failed to generate response with tools")
+        })
+        .await;
+
+        // The tool calls the coordinator made along the way, paired with their output
+        let invocations = history.invocations();
+        self.keep_coordinator(ToolCoordinator {
+            coordinator,
+            history,
+            generation,
+        });
+        let response = response?;
+        self.record_truncated(response.final_data.as_ref().map(|data| data.eval_count));
+        self.record_timings(response.final_data.as_ref().map(ServerTimings::of_chat));
+        if let Some(data) = &response.final_data {
+            self.prefix.record(
+                shared.extends,
+                Self::estimate_prompt_tokens(messages),
+                data.prompt_eval_count,
+            );
+        }
+
+        // The tools actually called: those the coordinator ran along the way,
+        // then any left in the final message. Recorded per call so concurrent
+        // responses don't mix their tools
+        let mut used_tools: Vec<String> = Vec::new();
+        let names = invocations.iter().map(|invocation| &invocation.name).chain(
+            response
+                .message
+                .tool_calls
+                .iter()
+                .map(|call| &call.function.name),
+        );
+        for name in names {
+            if !used_tools.contains(name) {
+                used_tools.push(name.clone());
             }
+        }
 
-            // If we still don't have any tools recorded, this means the coordinator has already
-            // processed all tool calls internally and they're not in the final message
-            // In this case, we need to check which tools were registered with the coordinator
-            // and check if they were used via specific patterns in the response content
-            if tools.is_empty() {
-                let content = response.message.content.to_lowercase();
-
-                // Check for patterns indicating tool usage in the response text
-                if content.contains("weather")
-                    || content.contains("temperature")
-                    || content.contains("forecast")
-                {
-                    println!("\x1b[1;33m[TOOL USAGE] Weather tool was used in response\x1b[0m");
-                    tools.push("weather".to_string());
-                }
+        *self.last_used_tools.lock().unwrap() = used_tools.clone();
+        *self.last_tool_invocations.lock().unwrap() = invocations;
 
-                if content.contains("calculated")
-                    || content.contains("result is")
-                    || content.contains("math")
-                    || content.contains("computation")
-                {
-                    println!("\x1b[1;33m[TOOL USAGE] Calculator tool was used in response\x1b[0m");
-                    tools.push("Calculator".to_string());
-                }
+        // Estimate token usage
+        let text = reply_text(response.message)?;
+        let input_tokens = Self::estimate_token_count(&last_message.content);
+        let output_tokens = Self::estimate_token_count(&text);
 
-                if content.contains("search")
-                    || content.contains("found information")
-                    || content.contains("according to")
-                    || content.contains("search results")
-                {
-                    println!("\x1b[1;33m[TOOL USAGE] DDGSearcher tool was used in response\x1b[0m");
-                    tools.push("DDGSearcher".to_string());
-                }
+        Ok((text, input_tokens, output_tokens, used_tools))
+    }
+}
 
-                if content.contains("webpage")
-                    || content.contains("website")
-                    || content.contains("web page")
-                    || content.contains("url")
-                {
-                    println!("\x1b[1;33m[TOOL USAGE] Scraper tool was used in response\x1b[0m");
-                    tools.push("Scraper".to_string());
-                }
+// A coordinator with its tools, and the history it answers from
+struct ToolCoordinator {
+    coordinator: Coordinator<RecordedHistory>,
+    history: RecordedHistory,
+    // The tools it was built with
+    generation: usize,
+}
 
-                // Check for Bash tool usage
-                if content.contains("command")
-                    || content.contains("executed")
-                    || content.contains("terminal")
-                    || content.contains("shell")
-                    || content.contains("bash")
-                    || content.contains("output shows")
-                    || content.contains("running")
-                {
-                    println!("\x1b[1;33m[TOOL USAGE] Bash tool was used in response\x1b[0m");
-                    tools.push("bash".to_string());
-                }
+// Chat history handed to the Coordinator, kept readable after it takes ownership
+// so the tool calls made while answering can be reported
+#[derive(Clone, Default)]
+struct RecordedHistory {
+    recorded: Arc<Mutex<Recorded>>,
+    // Where the calls are reported as they start and end
+    policy: Arc<ExecutionPolicy>,
+}
 
-                // Check for LS tool usage
-                if content.contains("directory")
-                    || content.contains("file list")
-                    || content.contains("files in")
-                    || content.contains("folder")
-                    || content.contains("listing")
-                {
-                    println!("\x1b[1;33m[TOOL USAGE] LS tool was used in response\x1b[0m");
-                    tools.push("ls".to_string());
-                }
+#[derive(Default)]
+struct Recorded {
+    messages: Vec<ChatMessage>,
+    start: usize,
+    // When each message after `start` was added; a tool ran from the message
+    // before its output until the output
+    added: Vec<Instant>,
+    // The calls of the last assistant message whose output is not in yet
+    running: VecDeque<(String, serde_json::Value)>,
+}
+
+impl RecordedHistory {
+    fn reporting_to(policy: Arc<ExecutionPolicy>) -> Self {
+        Self {
+            recorded: Arc::default(),
+            policy,
+        }
+    }
+
+    // Answer the next request from `messages`, forgetting those of the last one
+    fn start(&self, messages: Vec<ChatMessage>) {
+        let mut recorded = self.recorded.lock().unwrap();
+        recorded.start = messages.len();
+        recorded.messages = messages;
+        recorded.added.clear();
+        recorded.running.clear();
+    }
+
+    // Tool calls added since the request started, each with the tool message that answered it
+    fn invocations(&self) -> Vec<ToolInvocation> {
+        let recorded = self.recorded.lock().unwrap();
+        let added = &recorded.messages[recorded.start..];
+        let mut invocations = Vec::new();
+        let mut outputs = added
+            .iter()
+            .skip(1)
+            .zip(recorded.added.windows(2))
+            .filter(|(message, _)| message.role == ollama_rs::generation::chat::MessageRole::Tool)
+            .map(|(message, window)| (message, window[1] - window[0]));
+
+        for message in added {
+            for call in &message.tool_calls {
+                invocations.push(ToolInvocation {
+                    name: call.function.name.clone(),
+                    arguments: call.function.arguments.clone(),
+                    output: String::new(),
+                    summary: None,
+                    duration: Duration::ZERO,
+                });
+            }
+        }
+        for invocation in invocations.iter_mut() {
+            if let Some((output, duration)) = outputs.next() {
+                invocation.output = output.content.clone();
+                invocation.duration = duration;
+            }
+        }
+        invocations
+    }
+}
+
+impl ChatHistory for RecordedHistory {
+    fn push(&mut self, message: ChatMessage) {
+        let mut recorded = self.recorded.lock().unwrap();
+        let now = Instant::now();
+        // The coordinator runs the calls of an assistant message one after
+        // another, adding the output of each as soon as it returns
+        if message.role == MessageRole::Tool {
+            if let Some((name, _)) = recorded.running.pop_front() {
+                let started = recorded.added.last().copied().unwrap_or(now);
+                self.policy.report_tool(ToolEvent::Finished {
+                    name,
+                    output: message.content.clone(),
+                    duration: now - started,
+                });
+            }
+        } else {
+            recorded.running = message
+                .tool_calls
+                .iter()
+                .map(|call| (call.function.name.clone(), call.function.arguments.clone()))
+                .collect();
+        }
+        if let Some((name, args)) = recorded.running.front() {
+            self.policy.report_tool(ToolEvent::Started {
+                name: name.clone(),
+                args: args.clone(),
+            });
+        }
+        recorded.messages.push(message);
+        recorded.added.push(now);
+    }
+
+    fn messages(&self) -> Cow<'_, [ChatMessage]> {
+        Cow::Owned(self.recorded.lock().unwrap().messages.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::keep_alive;
+
+    #[test]
+    fn test_keep_alive_is_attached_to_requests() -> Result<()> {
+        let options = GenerationOptions::with_seed(7);
+
+        // Without a setting, Ollama's default applies
+        let client = OllamaClient::new().with_model("llama3.2:latest");
+        let request = serde_json::to_value(client.chat_request(Vec::new(), &options))?;
+        assert!(request.get("keep_alive").is_none());
+
+        let client = client.with_keep_alive(Some(keep_alive::parse("30m")?));
+        let request = serde_json::to_value(client.chat_request(Vec::new(), &options))?;
+        assert_eq!(request["keep_alive"], "30m");
+        assert_eq!(request["options"]["seed"], 7);
+
+        let client = client.with_keep_alive(Some(keep_alive::parse("-1")?));
+        let request = serde_json::to_value(client.generation_request("hi".to_string(), &options))?;
+        assert_eq!(request["keep_alive"], -1);
+        assert_eq!(request["model"], "llama3.2:latest");
+        Ok(())
+    }
+
+    #[test]
+    fn test_limits_are_sent_with_every_request() -> Result<()> {
+        let client = OllamaClient::new();
+        client.set_limits(ResponseLimits {
+            max_tokens: Some(50),
+            stop: vec!["```".to_string()],
+            ..ResponseLimits::default()
+        });
+        let options = GenerationOptions::with_seed(3);
+        for request in [
+            serde_json::to_value(client.chat_request(Vec::new(), &options))?,
+            serde_json::to_value(client.generation_request("hi".to_string(), &options))?,
+        ] {
+            assert_eq!(request["options"]["num_predict"], 50);
+            assert_eq!(request["options"]["stop"], serde_json::json!(["```"]));
+            assert_eq!(request["options"]["seed"], 3);
+        }
+
+        client.record_truncated(Some(50));
+        assert!(client.take_truncated());
+        assert!(!client.take_truncated());
+        client.record_truncated(Some(12));
+        assert!(!client.take_truncated());
+        Ok(())
+    }
+
+    #[test]
+    fn test_replies_without_text_are_errors() -> Result<()> {
+        // Messages as Ollama's /api/chat sends them back
+        let reply =
+            |json: serde_json::Value| -> Result<ChatMessage> { Ok(serde_json::from_value(json)?) };
+
+        let text = reply(serde_json::json!({ "role": "assistant", "content": "Hello" }))?;
+        assert_eq!(reply_text(text)?, "Hello");
+
+        // Text next to an image is still an answer
+        let both = reply(
+            serde_json::json!({ "role": "assistant", "content": "A cat", "images": ["aGk="] }),
+        )?;
+        assert_eq!(reply_text(both)?, "A cat");
+
+        let tools_only = reply(serde_json::json!({
+            "role": "assistant",
+            "content": "",
+            "tool_calls": [
+                { "function": { "name": "ls", "arguments": { "path": "." } } },
+                { "function": { "name": "file", "arguments": {} } }
+            ]
+        }))?;
+        assert_eq!(
+            reply_text(tools_only).unwrap_err().to_string(),
+            "The model asked to call ls, file instead of answering"
+        );
+
+        let images_only = reply(
+            serde_json::json!({ "role": "assistant", "content": "", "images": ["aGk=", "aGk="] }),
+        )?;
+        let error = reply_text(images_only).unwrap_err();
+        assert_eq!(error.downcast_ref::<NoText>(), Some(&NoText { images: 2 }));
+        assert_eq!(error.to_string(), "the reply had no text, only 2 images");
+
+        let blank = reply(serde_json::json!({ "role": "assistant", "content": " \n" }))?;
+        assert_eq!(
+            reply_text(blank).unwrap_err().to_string(),
+            "the reply had no text"
+        );
+        Ok(())
+    }
 
-                // Check for File tool usage
-                if content.contains("read file")
-                    || content.contains("wrote file")
-                    || content.contains("deleted file")
-                    || content.contains("moved file")
-                    || content.contains("copied file")
-                    || content.contains("file exists")
-                    || content.contains("saved to file")
-                {
-                    println!("\x1b[1;33m[TOOL USAGE] File tool was used in response\x1b[0m");
-                    tools.push("file".to_string());
+    // The history a coordinator taken for a request answers from, put back after
+    fn history_of(client: &OllamaClient) -> RecordedHistory {
+        let tools = client.tools_config.lock().unwrap().clone();
+        let coordinator = client.take_coordinator(&tools, &[]);
+        let history = coordinator.history.clone();
+        client.keep_coordinator(coordinator);
+        history
+    }
+
+    #[test]
+    fn test_tools_are_registered_once_until_they_change() {
+        let client = OllamaClient::new();
+        let first = history_of(&client);
+        let same = |history: &RecordedHistory| Arc::ptr_eq(&first.recorded, &history.recorded);
+        assert!(same(&history_of(&client)));
+
+        // A request that starts while another runs gets a coordinator of its own
+        let tools = client.tools_config.lock().unwrap().clone();
+        let running = client.take_coordinator(&tools, &[]);
+        assert!(!same(&history_of(&client)));
+        client.keep_coordinator(running);
+
+        // /clear starts the tools afresh, and so does a config reload
+        client.reset_tools();
+        let cleared = history_of(&client);
+        assert!(!same(&cleared));
+        client.apply_config(&Config::default());
+        assert!(!Arc::ptr_eq(
+            &cleared.recorded,
+            &history_of(&client).recorded
+        ));
+    }
+
+    #[test]
+    fn test_switching_a_tool_off_rebuilds_the_tools_without_it() {
+        let client = OllamaClient::new();
+        let first = history_of(&client);
+        let offers = |name: &str| client.available_tools().iter().any(|tool| tool == name);
+        assert!(offers("bash"));
+
+        assert!(client.set_tool_enabled("BASH", false));
+        assert!(!offers("bash"));
+        assert!(offers("ls"));
+        assert!(!Arc::ptr_eq(&first.recorded, &history_of(&client).recorded));
+
+        assert!(client.set_tool_enabled("bash", true));
+        assert!(offers("bash"));
+        assert!(!client.set_tool_enabled("teleport", false));
+    }
+
+    #[test]
+    fn test_a_history_only_reports_the_calls_of_its_request() {
+        let policy = Arc::new(ExecutionPolicy::default());
+        let (events, mut reported) = tokio::sync::mpsc::unbounded_channel();
+        policy.set_tool_events(Some(events));
+        let history = RecordedHistory::reporting_to(policy);
+        let mut pushed = history.clone();
+        history.start(vec![ChatMessage::user("earlier".to_string())]);
+        pushed.push(ChatMessage::user("list files".to_string()));
+        let mut call = ChatMessage::assistant(String::new());
+        call.tool_calls = serde_json::from_value(serde_json::json!([
+            { "function": { "name": "ls", "arguments": { "path": "." } } }
+        ]))
+        .unwrap();
+        pushed.push(call);
+        // The call is reported as started before the tool runs
+        assert_eq!(
+            reported.try_recv().unwrap(),
+            ToolEvent::Started {
+                name: "ls".to_string(),
+                args: serde_json::json!({ "path": "." }),
+            }
+        );
+        assert!(reported.try_recv().is_err());
+        pushed.push(ChatMessage::tool("Cargo.toml".to_string()));
+        assert!(matches!(
+            reported.try_recv().unwrap(),
+            ToolEvent::Finished { name, output, .. } if name == "ls" && output == "Cargo.toml"
+        ));
+        let invocations = history.invocations();
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].name, "ls");
+        assert_eq!(invocations[0].output, "Cargo.toml");
+
+        history.start(vec![ChatMessage::user("thanks".to_string())]);
+        assert_eq!(history.messages().len(), 1);
+        assert!(history.invocations().is_empty());
+    }
+
+    // The path and body of each request an Ollama stand-in was sent
+    type Requests = Arc<Mutex<Vec<(String, serde_json::Value)>>>;
+
+    // An Ollama stand-in answering chats with `reply` and generations with
+    // `reply` and some context tokens, reporting 12 prompt tokens evaluated
+    async fn serve(reply: &'static str) -> (u16, Requests) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Requests::default();
+        let seen = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 4096];
+                let (path, body) = loop {
+                    let read = stream.read(&mut buffer).await.unwrap_or(0);
+                    request.extend_from_slice(&buffer[..read]);
+                    let text = String::from_utf8_lossy(&request).into_owned();
+                    let Some((headers, body)) = text.split_once("\r\n\r\n") else {
+                        continue;
+                    };
+                    let length = headers
+                        .lines()
+                        .find_map(|line| {
+                            line.to_lowercase()
+                                .strip_prefix("content-length:")
+                                .and_then(|n| n.trim().parse::<usize>().ok())
+                        })
+                        .unwrap_or(0);
+                    if read == 0 || body.len() >= length {
+                        let path = headers.split(' ').nth(1).unwrap_or_default().to_string();
+                        break (path, body.to_string());
+                    }
+                };
+                let body = serde_json::from_str(&body).unwrap_or_default();
+                seen.lock().unwrap().push((path.clone(), body));
+
+                let answer = if path == "/api/generate" {
+                    serde_json::json!({
+                        "model": "mock",
+                        "created_at": "2024-01-01T00:00:00Z",
+                        "response": reply,
+                        "done": true,
+                        "context": [1, 2, 3],
+                        "load_duration": 3_000_000_000u64,
+                        "prompt_eval_count": 12,
+                        "eval_duration": 500_000_000
+                    })
+                } else if path == "/api/ps" {
+                    serde_json::json!({ "models": [{ "name": "mock:latest", "model": "mock:latest" }] })
+                } else {
+                    serde_json::json!({
+                        "model": "mock",
+                        "created_at": "2024-01-01T00:00:00Z",
+                        "message": { "role": "assistant", "content": reply },
+                        "done": true,
+                        "total_duration": 1,
+                        "load_duration": 12_100_000_000u64,
+                        "prompt_eval_count": 12,
+                        "prompt_eval_duration": 800_000_000,
+                        "eval_count": 2,
+                        "eval_duration": 4_200_000_000u64
+                    })
                 }
+                .to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    answer.len(),
+                    answer
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
             }
+        });
+        (port, requests)
+    }
+
+    // The messages of each chat request, each as it was serialized
+    fn sent_messages(requests: &Requests) -> Vec<Vec<String>> {
+        requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(path, _)| path == "/api/chat")
+            .map(|(_, body)| {
+                body["messages"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|message| message.to_string())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_each_turn_resends_the_last_request_byte_for_byte() -> Result<()> {
+        let (port, requests) = serve("Sure").await;
+        let client = OllamaClient::new()
+            .with_endpoint(&Endpoint::new("http://127.0.0.1", port))
+            .with_model("mock");
+        let custom = CustomToolConfig {
+            name: "deploy".to_string(),
+            description: "Deploy the site".to_string(),
+            command: "true".to_string(),
+            params: Vec::new(),
+            timeout_secs: None,
+        };
+        client.apply_config(&Config {
+            tools: ToolsConfig {
+                custom: vec![custom],
+                ..ToolsConfig::default()
+            },
+            ..Config::default()
+        });
+
+        let mut conversation = vec![
+            Message::system("Be brief"),
+            Message::system("Remembered facts: the user prefers metric units"),
+        ];
+        for turn in 0..4 {
+            conversation.push(Message::user(format!("Question {}", turn)));
+            let (reply, _, _, _) = client
+                .generate_response_with_tools(&conversation, &[])
+                .await?;
+            conversation.push(Message::assistant(reply, 0, 0, Vec::new()));
         }
 
-        // Get tools from our tracked list
-        let used_tools = self.get_last_used_tools();
+        // The system prompt, facts and tool catalog come first every time, unchanged
+        let sent = sent_messages(&requests);
+        assert_eq!(sent.len(), 4);
+        for pair in sent.windows(2) {
+            assert!(pair[1].starts_with(&pair[0]), "{:#?}", pair);
+            assert_eq!(pair[1].len(), pair[0].len() + 2);
+        }
+        assert!(sent[0][2].contains("deploy"));
 
-        // Estimate token usage
-        let input_tokens = Self::estimate_token_count(&last_message.content);
-        let output_tokens = Self::estimate_token_count(&response.message.content);
+        let usage = client.take_prompt_usage();
+        assert_eq!(
+            (usage.requests, usage.extended, usage.evaluated),
+            (4, 3, 48)
+        );
+        assert_eq!(client.take_prompt_usage(), PromptUsage::default());
+        Ok(())
+    }
 
-        Ok((
-            response.message.content,
-            input_tokens,
-            output_tokens,
-            used_tools,
-        ))
+    #[tokio::test]
+    async fn test_a_single_prompt_continues_from_its_context_tokens() -> Result<()> {
+        let (port, requests) = serve("A language.").await;
+        let client = OllamaClient::new()
+            .with_endpoint(&Endpoint::new("http://127.0.0.1", port))
+            .with_model("mock");
+
+        let mut conversation = vec![Message::user("What is Rust?")];
+        let (reply, _, _) = client.generate_response(&conversation).await?;
+        conversation.push(Message::assistant(reply, 0, 0, Vec::new()));
+        conversation.push(Message::user("Who made it?"));
+        client.generate_response(&conversation).await?;
+
+        {
+            let requests = requests.lock().unwrap();
+            let (path, first) = &requests[0];
+            assert_eq!(path, "/api/generate");
+            assert!(first.get("context").is_none());
+            let (path, next) = &requests[1];
+            assert_eq!(path, "/api/generate");
+            assert_eq!(next["prompt"], "Who made it?");
+            assert_eq!(next["context"], serde_json::json!([1, 2, 3]));
+        }
+        let usage = client.take_prompt_usage();
+        assert_eq!((usage.requests, usage.extended), (2, 1));
+
+        // An exchange that changed on the way goes through the chat interface
+        conversation[1].content = "A systems language.".to_string();
+        client.generate_response(&conversation).await?;
+        assert_eq!(requests.lock().unwrap()[2].0, "/api/chat");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replies_that_only_talk_about_tools_used_none() -> Result<()> {
+        let (port, _) = serve("Running the command listed the directory at that url").await;
+        let client = OllamaClient::new()
+            .with_endpoint(&Endpoint::new("http://127.0.0.1", port))
+            .with_model("mock");
+
+        let (_, _, _, used_tools) = client
+            .generate_response_with_tools(&[Message::user("What is here?")], &[])
+            .await?;
+        assert!(used_tools.is_empty(), "{:?}", used_tools);
+        assert!(client.get_last_used_tools().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_server_timings_are_kept_until_taken() -> Result<()> {
+        let (port, _) = serve("Hi").await;
+        let client = OllamaClient::new()
+            .with_endpoint(&Endpoint::new("http://127.0.0.1", port))
+            .with_model("mock");
+        assert!(client.take_server_timings().is_empty());
+
+        let conversation = [Message::system("Be brief"), Message::user("Hello")];
+        client.generate_response(&conversation).await?;
+        let timings = client.take_server_timings();
+        assert_eq!(timings.to_string(), "load 12.1s, prompt 0.8s, gen 4.2s");
+        assert!(client.take_server_timings().is_empty());
+
+        // Completions report theirs too, and so does warming up
+        client.generate_response(&[Message::user("Hello")]).await?;
+        assert_eq!(client.take_server_timings().load, Duration::from_secs(3));
+        assert_eq!(client.warm_up().await?.load, Duration::from_secs(3));
+        assert!(client.take_server_timings().is_empty());
+
+        // Whether the model is loaded comes from /api/ps, tag or not
+        assert_eq!(client.model_loaded().await, Some(true));
+        let other = client.for_model("qwen2.5")?;
+        assert_eq!(other.model_loaded().await, Some(false));
+        Ok(())
+    }
+
+    // How much of a growing conversation Ollama evaluates each turn:
+    // `cargo test -- --ignored --nocapture prompt_evaluation` with `llama3.2` pulled
+    #[tokio::test]
+    #[ignore = "needs an Ollama server"]
+    async fn prompt_evaluation_of_a_growing_conversation() -> Result<()> {
+        let client = OllamaClient::new().with_model("llama3.2:latest");
+        let mut conversation = vec![Message::system(
+            "You are a terse assistant. Answer in one short sentence.",
+        )];
+        for turn in 0..5 {
+            conversation.push(Message::user(format!(
+                "Name a prime number larger than {}.",
+                turn * 100
+            )));
+            let started = Instant::now();
+            let (reply, _, _) = client.generate_response(&conversation).await?;
+            let usage = client.take_prompt_usage();
+            println!(
+                "turn {}: {} of ~{} prompt tokens evaluated in {:?}",
+                turn,
+                usage.evaluated,
+                usage.sent,
+                started.elapsed()
+            );
+            conversation.push(Message::assistant(reply, 0, 0, Vec::new()));
+        }
+        Ok(())
+    }
+
+    // Timing for the coordinator reuse: `cargo test -- --ignored --nocapture
+    // turn_latency` with `llama3.2` pulled
+    #[tokio::test]
+    #[ignore = "needs an Ollama server"]
+    async fn turn_latency_with_and_without_rebuilding_tools() -> Result<()> {
+        let client = OllamaClient::new().with_model("llama3.2:latest");
+        let tools = client.tools_config.lock().unwrap().clone();
+        let messages = [Message::user("Reply with the word ok.".to_string())];
+        let turn = |rebuild: bool| {
+            if rebuild {
+                client.forget_coordinator();
+            }
+            let started = Instant::now();
+            let built = client.take_coordinator(&tools, &[]);
+            let setup = started.elapsed();
+            client.keep_coordinator(built);
+            setup
+        };
+        println!("building the tools: {:?}", turn(true));
+        println!("reusing them:       {:?}", turn(false));
+
+        // Load the model first, so neither turn pays for it
+        client.generate_response(&messages).await?;
+        for rebuild in [true, false] {
+            let started = Instant::now();
+            if rebuild {
+                client.forget_coordinator();
+            }
+            client.generate_response_with_tools(&messages, &[]).await?;
+            println!(
+                "turn {} rebuilding: {:?}",
+                if rebuild { "with" } else { "without" },
+                started.elapsed()
+            );
+        }
+        Ok(())
     }
 }
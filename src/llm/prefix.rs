@@ -0,0 +1,260 @@
+//! Sending every request so Ollama can reuse what it evaluated for the last.
+//!
+//! Ollama keeps the prompt it evaluated last in the model's cache, and only
+//! evaluates what follows the part a new request shares with it. A long
+//! conversation then costs little more per turn than its newest message, as
+//! long as every earlier message is sent byte for byte as before. The client
+//! compares each chat request with the one it sent before and adds up the
+//! prompt tokens Ollama reports evaluating, so the stats can show what the
+//! cache saved. Single messages go through the generate API instead, which
+//! hands back its context tokens; a reply to that exchange continues from
+//! them rather than starting over.
+
+use std::sync::Mutex;
+
+use ollama_rs::generation::chat::ChatMessage;
+use ollama_rs::generation::completion::GenerationContext;
+use serde::{Deserialize, Serialize};
+
+use crate::{Message, Role};
+
+/// What Ollama evaluated of the prompts sent since this was last asked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PromptUsage {
+    /// Requests Ollama reported on
+    pub requests: usize,
+    /// Requests that began with every message of the request before
+    pub extended: usize,
+    /// Prompt tokens sent, estimated
+    pub sent: usize,
+    /// Prompt tokens Ollama evaluated, leaving out those it had cached
+    pub evaluated: usize,
+}
+
+impl PromptUsage {
+    /// Add the usage of another client, e.g. one a failover switched from
+    pub fn add(&mut self, other: PromptUsage) {
+        self.requests += other.requests;
+        self.extended += other.extended;
+        self.sent += other.sent;
+        self.evaluated += other.evaluated;
+    }
+
+    /// The share of the prompt tokens sent that Ollama had cached, in percent
+    pub fn reused_percent(&self) -> f64 {
+        if self.sent == 0 {
+            return 0.0;
+        }
+        (1.0 - self.evaluated as f64 / self.sent as f64).max(0.0) * 100.0
+    }
+}
+
+/// How a request compares with the one sent before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shared {
+    /// Leading messages serialized exactly as before
+    pub messages: usize,
+    /// Whether the request began with all of the one before
+    pub extends: bool,
+}
+
+/// The last request a client sent, and what Ollama evaluated since last asked
+#[derive(Debug, Default)]
+pub struct PrefixCache {
+    // The messages of the last chat request, as they were serialized
+    sent: Mutex<Vec<String>>,
+    usage: Mutex<PromptUsage>,
+    continuation: Mutex<Option<Continuation>>,
+}
+
+// A generate exchange that can be continued: its prompts and replies, and
+// the context tokens Ollama returned for them
+#[derive(Debug, Clone)]
+struct Continuation {
+    messages: Vec<(Role, String)>,
+    context: GenerationContext,
+}
+
+impl PrefixCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compare `messages` with the request sent before, and remember them
+    /// as the last request
+    pub fn observe(&self, messages: &[ChatMessage]) -> Shared {
+        let next = serialize(messages);
+        let mut sent = self.sent.lock().unwrap();
+        let shared = Shared {
+            messages: shared_prefix(&sent, &next),
+            extends: !sent.is_empty() && next.starts_with(&sent),
+        };
+        *sent = next;
+        shared
+    }
+
+    /// Count a request Ollama answered, which `extends` the one before or
+    /// not, with about `sent` prompt tokens of which it evaluated `evaluated`
+    pub fn record(&self, extends: bool, sent: usize, evaluated: u64) {
+        let mut usage = self.usage.lock().unwrap();
+        usage.requests += 1;
+        usage.extended += usize::from(extends);
+        usage.sent += sent;
+        usage.evaluated += evaluated as usize;
+    }
+
+    /// What Ollama evaluated since the last time this was asked
+    pub fn take_usage(&self) -> PromptUsage {
+        std::mem::take(&mut *self.usage.lock().unwrap())
+    }
+
+    /// The context tokens to continue from when `messages` are an exchange
+    /// sent through the generate API followed by one more prompt without images
+    pub fn continuation(&self, messages: &[Message]) -> Option<GenerationContext> {
+        let (last, earlier) = messages.split_last()?;
+        if last.role != Role::User || !last.images.is_empty() {
+            return None;
+        }
+        let continuation = self.continuation.lock().unwrap();
+        let continuation = continuation.as_ref()?;
+        let same = continuation.messages.len() == earlier.len()
+            && continuation
+                .messages
+                .iter()
+                .zip(earlier)
+                .all(|((role, content), message)| {
+                    *role == message.role && *content == message.content
+                });
+        same.then(|| continuation.context.clone())
+    }
+
+    /// Remember the context Ollama returned for `messages` answered with
+    /// `reply`, or forget the last one if it returned none
+    pub fn remember(&self, messages: &[Message], reply: &str, context: Option<GenerationContext>) {
+        *self.continuation.lock().unwrap() = context.map(|context| Continuation {
+            messages: messages
+                .iter()
+                .map(|message| (message.role.clone(), message.content.clone()))
+                .chain([(Role::Assistant, reply.to_string())])
+                .collect(),
+            context,
+        });
+    }
+}
+
+/// Each message as it goes over the wire
+pub fn serialize(messages: &[ChatMessage]) -> Vec<String> {
+    messages
+        .iter()
+        .map(|message| serde_json::to_string(message).unwrap_or_default())
+        .collect()
+}
+
+/// How many leading messages `next` sends exactly as `previous` did
+pub fn shared_prefix(previous: &[String], next: &[String]) -> usize {
+    previous
+        .iter()
+        .zip(next)
+        .take_while(|(previous, next)| previous == next)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chat(messages: &[(&str, &str)]) -> Vec<ChatMessage> {
+        messages
+            .iter()
+            .map(|(role, content)| match *role {
+                "system" => ChatMessage::system(content.to_string()),
+                "user" => ChatMessage::user(content.to_string()),
+                _ => ChatMessage::assistant(content.to_string()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_requests_are_compared_with_the_one_before() {
+        let cache = PrefixCache::new();
+        let first = chat(&[("system", "Be brief"), ("user", "hi")]);
+        assert_eq!(
+            cache.observe(&first),
+            Shared {
+                messages: 0,
+                extends: false
+            }
+        );
+
+        let second = chat(&[
+            ("system", "Be brief"),
+            ("user", "hi"),
+            ("assistant", "Hello"),
+            ("user", "and you?"),
+        ]);
+        assert_eq!(
+            cache.observe(&second),
+            Shared {
+                messages: 2,
+                extends: true
+            }
+        );
+
+        // A changed system prompt breaks the prefix from the first message
+        let changed = chat(&[("system", "Be very brief"), ("user", "hi")]);
+        assert_eq!(
+            cache.observe(&changed),
+            Shared {
+                messages: 0,
+                extends: false
+            }
+        );
+    }
+
+    #[test]
+    fn test_usage_is_added_up_until_taken() {
+        let cache = PrefixCache::new();
+        cache.record(false, 120, 118);
+        cache.record(true, 160, 30);
+        let usage = cache.take_usage();
+        assert_eq!(
+            usage,
+            PromptUsage {
+                requests: 2,
+                extended: 1,
+                sent: 280,
+                evaluated: 148
+            }
+        );
+        assert_eq!(cache.take_usage(), PromptUsage::default());
+    }
+
+    #[test]
+    fn test_a_generate_exchange_continues_only_from_its_own_messages() {
+        let cache = PrefixCache::new();
+        let prompt = [Message::user("What is Rust?")];
+        cache.remember(
+            &prompt,
+            "A language.",
+            Some(GenerationContext(vec![1, 2, 3])),
+        );
+
+        let next = [
+            Message::user("What is Rust?"),
+            Message::assistant("A language.", 0, 0, Vec::new()),
+            Message::user("Who made it?"),
+        ];
+        assert_eq!(cache.continuation(&next).map(|c| c.0), Some(vec![1, 2, 3]));
+
+        // Another reply, a system prompt or a last message that isn't a prompt start over
+        let mut edited = next.clone();
+        edited[1].content = "A systems language.".to_string();
+        assert!(cache.continuation(&edited).is_none());
+        let with_system = [vec![Message::system("Be brief")], next.to_vec()].concat();
+        assert!(cache.continuation(&with_system).is_none());
+        assert!(cache.continuation(&next[..2]).is_none());
+
+        cache.remember(&prompt, "A language.", None);
+        assert!(cache.continuation(&next).is_none());
+    }
+}
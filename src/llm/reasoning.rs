@@ -0,0 +1,132 @@
+//! `<think>...</think>` blocks emitted by reasoning models such as deepseek-r1.
+//!
+//! The reasoning is kept apart from the answer so replaying the conversation
+//! does not spend context on it, while frontends can still show it on request.
+
+const OPEN: &str = "<think>";
+const CLOSE: &str = "</think>";
+
+/// A model reply with its reasoning separated from the answer
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reply {
+    pub content: String,
+    pub reasoning: Option<String>,
+}
+
+impl Reply {
+    /// Estimate how many of a reply's `output_tokens` went to reasoning, by length
+    pub fn reasoning_tokens(&self, output_tokens: usize) -> usize {
+        let Some(reasoning) = &self.reasoning else {
+            return 0;
+        };
+
+        let reasoning_chars = reasoning.chars().count();
+        let total_chars = reasoning_chars + self.content.chars().count();
+        if total_chars == 0 {
+            return 0;
+        }
+        output_tokens * reasoning_chars / total_chars
+    }
+}
+
+/// Separate the think blocks in `text` from the answer.
+///
+/// Text without think blocks is returned unchanged. A block that is never
+/// closed, because generation stopped while the model was thinking, runs to
+/// the end of the text, and a leading `</think>` without an opening tag, as
+/// left by chat templates that open the block in the prompt, closes one that
+/// started at the beginning.
+pub fn split(text: &str) -> Reply {
+    let mut blocks = Vec::new();
+    let mut content = String::new();
+    let mut rest = text;
+
+    if let Some(end) = rest.find(CLOSE) {
+        if !rest[..end].contains(OPEN) {
+            blocks.push(&rest[..end]);
+            rest = &rest[end + CLOSE.len()..];
+        }
+    }
+
+    while let Some(start) = rest.find(OPEN) {
+        content.push_str(&rest[..start]);
+        let inner = &rest[start + OPEN.len()..];
+        match inner.find(CLOSE) {
+            Some(end) => {
+                blocks.push(&inner[..end]);
+                rest = &inner[end + CLOSE.len()..];
+            }
+            None => {
+                blocks.push(inner);
+                rest = "";
+            }
+        }
+    }
+
+    if blocks.is_empty() {
+        return Reply {
+            content: text.to_string(),
+            reasoning: None,
+        };
+    }
+    content.push_str(rest);
+
+    let reasoning: Vec<&str> = blocks
+        .into_iter()
+        .map(str::trim)
+        .filter(|block| !block.is_empty())
+        .collect();
+    Reply {
+        content: content.trim().to_string(),
+        reasoning: (!reasoning.is_empty()).then(|| reasoning.join("\n\n")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let reply = split("<think>\nThe user wants a greeting.\n</think>\n\nHello!");
+        assert_eq!(reply.content, "Hello!");
+        assert_eq!(
+            reply.reasoning.as_deref(),
+            Some("The user wants a greeting.")
+        );
+
+        let reply = split("First <think>a</think>then<think> b </think> done");
+        assert_eq!(reply.content, "First then done");
+        assert_eq!(reply.reasoning.as_deref(), Some("a\n\nb"));
+
+        // Empty blocks are dropped along with their tags
+        let reply = split("<think>\n\n</think>\n\n42");
+        assert_eq!(reply.content, "42");
+        assert!(reply.reasoning.is_none());
+
+        let reply = split("  No thinking here\n");
+        assert_eq!(reply.content, "  No thinking here\n");
+        assert!(reply.reasoning.is_none());
+    }
+
+    #[test]
+    fn test_unbalanced_tags() {
+        let reply = split("Answer first <think>but then it ran out of tok");
+        assert_eq!(reply.content, "Answer first");
+        assert_eq!(
+            reply.reasoning.as_deref(),
+            Some("but then it ran out of tok")
+        );
+
+        let reply = split("Opened by the template</think>The answer");
+        assert_eq!(reply.content, "The answer");
+        assert_eq!(reply.reasoning.as_deref(), Some("Opened by the template"));
+    }
+
+    #[test]
+    fn test_reasoning_tokens() {
+        let reply = split("<think>xxxxxxxxxxxxxxx</think>yyyyy");
+        assert_eq!(reply.reasoning_tokens(100), 75);
+        assert_eq!(split("plain").reasoning_tokens(100), 0);
+    }
+}
@@ -0,0 +1,173 @@
+//! Where the time of a request went on the server.
+//!
+//! Ollama reports with every final response how long it spent loading the
+//! model, evaluating the prompt and generating the reply. The first request
+//! after a model was unloaded can spend most of its time loading it, which
+//! otherwise looks like a slow model; these timings tell the two apart.
+
+use std::fmt;
+use std::time::Duration;
+
+use ollama_rs::generation::chat::ChatMessageFinalResponseData;
+use ollama_rs::generation::completion::GenerationResponse;
+use serde::{Deserialize, Serialize};
+
+/// Loading a model for shorter than this is not worth pointing out; a loaded
+/// model still reports a few milliseconds
+pub const NOTABLE_LOAD: Duration = Duration::from_millis(500);
+
+/// How long the server spent on the requests since this was last asked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerTimings {
+    /// Loading the model into memory
+    pub load: Duration,
+    /// Evaluating the prompt
+    pub prompt: Duration,
+    /// Generating the reply
+    pub generation: Duration,
+}
+
+impl ServerTimings {
+    /// Timings from the nanoseconds Ollama reports them in
+    pub fn from_nanos(load: u64, prompt: u64, generation: u64) -> Self {
+        Self {
+            load: Duration::from_nanos(load),
+            prompt: Duration::from_nanos(prompt),
+            generation: Duration::from_nanos(generation),
+        }
+    }
+
+    /// The timings of a chat response
+    pub fn of_chat(data: &ChatMessageFinalResponseData) -> Self {
+        Self::from_nanos(
+            data.load_duration,
+            data.prompt_eval_duration,
+            data.eval_duration,
+        )
+    }
+
+    /// The timings of a completion, if the server reported any
+    pub fn of_generation(response: &GenerationResponse) -> Option<Self> {
+        if response.load_duration.is_none()
+            && response.prompt_eval_duration.is_none()
+            && response.eval_duration.is_none()
+        {
+            return None;
+        }
+        Some(Self::from_nanos(
+            response.load_duration.unwrap_or(0),
+            response.prompt_eval_duration.unwrap_or(0),
+            response.eval_duration.unwrap_or(0),
+        ))
+    }
+
+    /// Add the timings of another request, or of another client
+    pub fn add(&mut self, other: ServerTimings) {
+        self.load += other.load;
+        self.prompt += other.prompt;
+        self.generation += other.generation;
+    }
+
+    /// Whether the server reported nothing
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether loading the model took long enough to point out
+    pub fn loaded(&self) -> bool {
+        self.load >= NOTABLE_LOAD
+    }
+
+    /// "(load 12.1s, prompt 0.8s, gen 4.2s)" to follow the token counts of a
+    /// reply, when loading the model took long enough to point out
+    pub fn load_note(&self) -> Option<String> {
+        self.loaded().then(|| format!("({})", self))
+    }
+}
+
+impl fmt::Display for ServerTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "load {}, prompt {}, gen {}",
+            seconds(self.load),
+            seconds(self.prompt),
+            seconds(self.generation)
+        )
+    }
+}
+
+/// What warming up `model` came to: how long loading it took, or that it
+/// was loaded already
+pub fn warm_up_note(model: &str, timings: &ServerTimings) -> String {
+    if timings.loaded() {
+        format!("Loaded {} in {}", model, seconds(timings.load))
+    } else {
+        format!("{} was already loaded", model)
+    }
+}
+
+/// A duration in seconds to a tenth, as the timings are shown
+pub fn seconds(duration: Duration) -> String {
+    format!("{:.1}s", duration.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timings_are_read_from_the_response_metadata() {
+        let data: ChatMessageFinalResponseData = serde_json::from_value(serde_json::json!({
+            "total_duration": 17_200_000_000u64,
+            "load_duration": 12_100_000_000u64,
+            "prompt_eval_count": 40,
+            "prompt_eval_duration": 800_000_000u64,
+            "eval_count": 90,
+            "eval_duration": 4_200_000_000u64
+        }))
+        .unwrap();
+        let timings = ServerTimings::of_chat(&data);
+        assert_eq!(timings.load, Duration::from_millis(12_100));
+        assert_eq!(timings.to_string(), "load 12.1s, prompt 0.8s, gen 4.2s");
+
+        let response: GenerationResponse = serde_json::from_value(serde_json::json!({
+            "model": "llama3.2",
+            "created_at": "2024-01-01T00:00:00Z",
+            "response": "Hi",
+            "done": true,
+            "load_duration": 2_000_000u64,
+            "eval_duration": 300_000_000u64
+        }))
+        .unwrap();
+        let timings = ServerTimings::of_generation(&response).unwrap();
+        assert_eq!(timings.prompt, Duration::ZERO);
+        assert_eq!(timings.generation, Duration::from_millis(300));
+
+        let mut silent = response;
+        silent.load_duration = None;
+        silent.eval_duration = None;
+        assert_eq!(ServerTimings::of_generation(&silent), None);
+    }
+
+    #[test]
+    fn test_only_slow_loads_are_pointed_out() {
+        let warm = ServerTimings::from_nanos(15_000_000, 200_000_000, 1_000_000_000);
+        assert!(!warm.loaded());
+        assert_eq!(warm.load_note(), None);
+        let cold = ServerTimings::from_nanos(12_100_000_000, 800_000_000, 4_200_000_000);
+        assert!(cold.loaded());
+        assert_eq!(
+            cold.load_note().as_deref(),
+            Some("(load 12.1s, prompt 0.8s, gen 4.2s)")
+        );
+
+        let mut total = warm;
+        total.add(cold);
+        assert_eq!(total.load, Duration::from_millis(12_115));
+        assert_eq!(warm_up_note("qwen2.5", &cold), "Loaded qwen2.5 in 12.1s");
+        assert_eq!(warm_up_note("qwen2.5", &warm), "qwen2.5 was already loaded");
+        assert!(!ServerTimings::default().loaded());
+        assert!(ServerTimings::default().is_empty());
+    }
+}
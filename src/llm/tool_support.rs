@@ -0,0 +1,183 @@
+//! Whether a model can call tools.
+//!
+//! Ollama says so in /api/show: recent servers list a "tools" capability,
+//! older ones only reveal it in the prompt template. When neither can be
+//! read, a few model families are known to call tools, and any other model
+//! is sent tools until the server refuses them. What is learned about a
+//! model is kept for the rest of the session.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde_json::Value;
+
+use crate::llm::ollama::LlmClient;
+
+/// Model families known to call tools, for servers that don't say
+pub const KNOWN_TOOL_MODELS: &[&str] = &[
+    "command-r",
+    "devstral",
+    "firefunction-v2",
+    "gpt-oss",
+    "granite3.3",
+    "hermes3",
+    "llama3.1",
+    "llama3.2",
+    "llama3.3",
+    "llama4",
+    "mistral",
+    "mistral-nemo",
+    "mistral-small",
+    "mixtral",
+    "qwen2.5",
+    "qwen2.5-coder",
+    "qwen3",
+    "smollm2",
+];
+
+/// Whether the /api/show metadata of a model says it calls tools, or None if
+/// it doesn't tell
+pub fn tools_from_metadata(show: &Value) -> Option<bool> {
+    if let Some(capabilities) = show.get("capabilities").and_then(Value::as_array) {
+        return Some(capabilities.iter().any(|capability| capability == "tools"));
+    }
+    // Templates of tool-calling models render the tool definitions
+    let template = show.get("template")?.as_str()?;
+    Some(template.contains(".Tools"))
+}
+
+/// Whether `model`, e.g. "llama3.1:8b", belongs to a family known to call tools
+pub fn is_known_tool_model(model: &str) -> bool {
+    let name = model.rsplit('/').next().unwrap_or(model);
+    let family = name.split(':').next().unwrap_or(name).to_lowercase();
+    KNOWN_TOOL_MODELS.contains(&family.as_str())
+}
+
+/// Whether a request failed because the model cannot call tools, as Ollama
+/// answers e.g. "registry.ollama.ai/library/gemma:2b does not support tools"
+pub fn is_unsupported_error(error: &anyhow::Error) -> bool {
+    format!("{:#}", error).contains("does not support tools")
+}
+
+/// What to tell the user about a model that cannot call tools
+pub fn warning(model: &str) -> String {
+    format!(
+        "{} does not support tool calling, so it will answer without tools. \
+         Use a model such as llama3.1 or qwen2.5 for tools, or pass --force-tools to send them anyway.",
+        model
+    )
+}
+
+/// What is known about which models call tools, for one session
+#[derive(Debug, Default)]
+pub struct ToolSupport {
+    known: Mutex<HashMap<String, bool>>,
+    forced: bool,
+}
+
+impl ToolSupport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send tools to every model, whatever it is known to support
+    pub fn set_forced(&mut self, forced: bool) {
+        self.forced = forced;
+    }
+
+    pub fn is_forced(&self) -> bool {
+        self.forced
+    }
+
+    /// Whether tools should be sent to `model`, asking `client` the first
+    /// time. A model nobody can tell about gets them, until it refuses.
+    pub async fn check(&self, client: &dyn LlmClient, model: &str) -> bool {
+        if self.forced {
+            return true;
+        }
+        if let Some(supported) = self.known(model) {
+            return supported;
+        }
+
+        let supported = match client.supports_tools().await {
+            Some(supported) => supported,
+            None if is_known_tool_model(model) => true,
+            None => return true,
+        };
+        self.record(model, supported);
+        supported
+    }
+
+    /// What has been learned about `model`, if anything
+    pub fn known(&self, model: &str) -> Option<bool> {
+        self.known.lock().unwrap().get(model).copied()
+    }
+
+    /// Remember whether `model` calls tools; true if that was not known yet
+    pub fn record(&self, model: &str, supported: bool) -> bool {
+        self.known
+            .lock()
+            .unwrap()
+            .insert(model.to_string(), supported)
+            != Some(supported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::mock::MockLlmClient;
+    use anyhow::anyhow;
+    use serde_json::json;
+
+    #[test]
+    fn test_tools_from_metadata() {
+        let capable = json!({ "capabilities": ["completion", "tools"] });
+        assert_eq!(tools_from_metadata(&capable), Some(true));
+        let text_only =
+            json!({ "capabilities": ["completion", "vision"], "template": "{{ .Tools }}" });
+        assert_eq!(tools_from_metadata(&text_only), Some(false));
+
+        let old_server = json!({ "template": "{{- if .Tools }}[AVAILABLE_TOOLS]{{ end }}" });
+        assert_eq!(tools_from_metadata(&old_server), Some(true));
+        let plain = json!({ "template": "{{ .Prompt }}" });
+        assert_eq!(tools_from_metadata(&plain), Some(false));
+
+        assert_eq!(tools_from_metadata(&json!({})), None);
+    }
+
+    #[test]
+    fn test_known_models_and_errors() {
+        assert!(is_known_tool_model("llama3.1"));
+        assert!(is_known_tool_model("Qwen2.5-Coder:7b"));
+        assert!(is_known_tool_model("hf.co/library/mistral:latest"));
+        assert!(!is_known_tool_model("gemma:2b"));
+        assert!(!is_known_tool_model("llama2"));
+
+        let refused = anyhow!("registry.ollama.ai/library/gemma:2b does not support tools");
+        assert!(is_unsupported_error(
+            &refused.context("Chat request failed")
+        ));
+        assert!(!is_unsupported_error(&anyhow!("connection refused")));
+    }
+
+    #[tokio::test]
+    async fn test_answers_are_kept_per_model() {
+        let support = ToolSupport::new();
+        let client = MockLlmClient::new().with_tool_support(false);
+        assert!(!support.check(&client, "gemma:2b").await);
+        assert_eq!(support.known("gemma:2b"), Some(false));
+
+        // Unknown models are tried, and remembered once they refuse
+        let unknown = MockLlmClient::new();
+        assert!(support.check(&unknown, "phi").await);
+        assert!(support.record("phi", false));
+        assert!(!support.record("phi", false));
+        assert!(!support.check(&unknown, "phi").await);
+        assert!(!support.check(&unknown, "gemma:2b").await);
+
+        let mut forced = ToolSupport::new();
+        forced.set_forced(true);
+        assert!(forced.check(&client, "gemma:2b").await);
+    }
+}
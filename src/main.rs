@@ -1,465 +1,2129 @@
-pub mod llm;
-pub mod tools;
-pub mod tui;
-
-use anyhow::Result;
-use clap::{Parser, Subcommand};
-use llm::ollama::{LlmClient, OllamaClient};
-use serde::{Deserialize, Serialize};
-use std::io::Write;
-use tokio;
-
-// Terminal colors for better user experience
-pub mod terminal_colors {
-    pub const RESET: &str = "\x1b[0m";
-    pub const BOLD: &str = "\x1b[1m";
-    pub const BRIGHT_GREEN: &str = "\x1b[1;32m";
-    pub const BRIGHT_BLUE: &str = "\x1b[1;34m";
-    pub const BRIGHT_WHITE: &str = "\x1b[1;37m";
-    pub const YELLOW: &str = "\x1b[1;33m";
-    pub const CYAN: &str = "\x1b[1;36m";
-    pub const MAGENTA: &str = "\x1b[1;35m";
-    pub const RED: &str = "\x1b[1;31m";
-}
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand};
+use ollama_rs::generation::parameters::KeepAlive;
+use sentinel::activity::{self, Activity, Spinner, SpinnerHold};
+use sentinel::autosave::Autosave;
+use sentinel::batch::{self, BatchOptions, BatchRecord};
+use sentinel::candidates::{self, Candidate};
+use sentinel::compare::Comparison;
+use sentinel::config::{Config, Layered};
+use sentinel::failure::Failure;
+use sentinel::history::InputHistory;
+use sentinel::images::{self, ImageAttachment};
+use sentinel::input::{self, StdinMode};
+use sentinel::interrupt::Interrupter;
+use sentinel::line_editor::{LineEditor, ReadLine};
+use sentinel::links::Linker;
+use sentinel::llm::availability::{self, ModelRegistry};
+use sentinel::llm::deadline;
+use sentinel::llm::endpoint::Endpoint;
+use sentinel::llm::failover::{self, FailoverClient};
+use sentinel::llm::limits::{self, ResponseLimits};
+use sentinel::llm::ollama::{LlmClient, OllamaClient, Tool, ToolInvocation};
+use sentinel::llm::reasoning;
+use sentinel::llm::timings::{self, ServerTimings};
+use sentinel::llm::tool_support::{self, ToolSupport};
+use sentinel::memory::Memory;
+use sentinel::notify::Notifier;
+use sentinel::prime::{self, Primer};
+use sentinel::privacy;
+use sentinel::recording::{self, Tape};
+use sentinel::report::{self, AskReport};
+use sentinel::session::Sessions;
+use sentinel::session_blobs::{self, PruneRules};
+use sentinel::stats::{PriceTable, SessionStats, TokenBreakdown, ToolTiming};
+use sentinel::status::{self, WindowTitle};
+use sentinel::templates::{self, Template};
+use sentinel::tools::bash::{Bash, BashParams};
+use sentinel::tools::changes::{LineKind, SessionDiff};
+use sentinel::tools::custom;
+use sentinel::tools::invoke;
+use sentinel::tools::journal::Journal;
+use sentinel::tools::policy::ExecutionPolicy;
+use sentinel::tools::progress::Progress;
+use sentinel::tools::registry::{self, ToolSet};
+use sentinel::tools::review::{Decision, DiffLine, ProposedChange, Reviewer};
+use sentinel::tools::schema;
+use sentinel::{attachments, console, style, tui, wizard};
+use sentinel::{Agent, AgentEvent, AgentOutput, CommandHelp, Flow, Message, Role};
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::oneshot;
 
 #[derive(Parser)]
 #[command(name = "sentinel")]
 #[command(about = "LLM based Terminal agent", long_about = None)]
+#[command(after_help = "Without a command, sentinel runs `sentinel chat`.")]
 pub struct Cli {
+    /// Disable colored output (also honors the NO_COLOR environment variable)
+    #[arg(long, global = true)]
+    pub no_color: bool,
+
+    /// Preview mutating tool actions (file writes, non-read-only commands) without executing them
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Let the tools change paths matched by protected_paths in the config
+    #[arg(long, global = true)]
+    pub force_protected: bool,
+
+    /// Refuse every file change and every command that isn't read-only, for the whole session
+    #[arg(long, global = true)]
+    pub read_only: bool,
+
+    /// Send tools to the model even if it is reported not to support them
+    #[arg(long, global = true)]
+    pub force_tools: bool,
+
+    /// Pull the model without asking if it is not installed
+    #[arg(long, global = true)]
+    pub pull: bool,
+
+    /// Start with a snapshot of the current project: files, README, manifests and git status (also SENTINEL_PRIME=1)
+    #[arg(long, global = true)]
+    pub prime: bool,
+
+    /// How long Ollama keeps the model loaded after a request, e.g. 30m, 1h, -1 (forever) or 0
+    #[arg(long, global = true, value_name = "DURATION")]
+    pub keep_alive: Option<String>,
+
+    /// Show what reasoning models (e.g. deepseek-r1) think before answering; with ask --json, include it in the output
+    #[arg(long, global = true)]
+    pub show_reasoning: bool,
+
+    /// Record every request to the model and every tool call to PATH as JSON Lines, to replay the session later
+    #[arg(long, global = true, value_name = "PATH", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// Answer from a session recorded with --record instead of Ollama, without running any tools
+    #[arg(long, global = true, value_name = "PATH")]
+    pub replay: Option<PathBuf>,
+
+    /// Skip the setup that runs when there is no config file
+    #[arg(long, global = true)]
+    pub no_wizard: bool,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
 
+impl Cli {
+    /// Set what the global flags give over the config file's settings. A
+    /// read-only config stays read-only whatever the flags say
+    fn apply(&self, config: &mut Config) {
+        config.force_protected = self.force_protected;
+        config.read_only |= self.read_only;
+    }
+
+    /// Whether the session is recorded or replayed
+    fn recording(&self) -> recording::Mode {
+        recording::Mode::from_flags(self.record.as_deref(), self.replay.as_deref())
+    }
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
+    /// Chat with the model in the REPL, or in the TUI with --tui; what runs without a command
+    Chat(ChatArgs),
+
     /// Ask the LLM a question
-    Ask {
-        /// The message to send to the LLM
-        #[arg(required = true)]
-        message: Vec<String>,
+    Ask(AskArgs),
+
+    /// Send each prompt of a file on its own and write the outcomes to a JSON Lines file
+    Batch {
+        /// The prompts: one per non-empty line, or a .json array or .jsonl file of
+        /// prompts or {"prompt", "system"} objects
+        prompts: PathBuf,
+
+        /// The model to use (default: model.name from the config, else llama3.2:latest)
+        #[arg(short, long)]
+        model: Option<String>,
+
+        /// Where to write one JSON object per prompt, with the fields of `ask --json`
+        #[arg(long, value_name = "PATH")]
+        out: PathBuf,
 
-        /// The model to use
-        #[arg(short, long, default_value = "llama3.2:latest")]
-        model: String,
+        /// How many prompts to send at once
+        #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+        jobs: u16,
+
+        /// Skip the prompts already in the output file and append the rest
+        #[arg(long)]
+        resume: bool,
+    },
+
+    /// List the prompt templates usable with `ask --template` and /use
+    Templates,
+
+    /// Ask the LLM for a shell command that performs a task, then run it after confirmation
+    Do {
+        /// The task to perform
+        task: Vec<String>,
+
+        /// The model to use (default: model.name from the config, else llama3.2:latest)
+        #[arg(short, long)]
+        model: Option<String>,
 
-        /// Use tools
+        /// Run the suggested command without asking
         #[arg(short, long)]
-        tools: bool,
+        yes: bool,
+    },
+
+    /// Run one tool directly and print exactly what the model would see
+    Tool {
+        /// The tool to run, e.g. bash or file
+        #[arg(required_unless_present = "list")]
+        name: Option<String>,
+
+        /// Set a parameter; can be repeated, e.g. --param command="ls -la"
+        #[arg(long = "param", short, value_name = "KEY=VALUE")]
+        params: Vec<String>,
+
+        /// Give all the parameters as a JSON object instead
+        #[arg(long, value_name = "JSON", conflicts_with = "params")]
+        json: Option<String>,
+
+        /// List the tools and their parameters
+        #[arg(long, conflicts_with_all = ["name", "params", "json"])]
+        list: bool,
+
+        /// Print the tool's description and parameter schema as the model is given them, instead of running it
+        #[arg(long, requires = "name", conflicts_with_all = ["params", "json", "list"])]
+        schema: bool,
+    },
+
+    /// List the tools with one-line summaries, or write all their schemas to a file
+    Tools {
+        /// Write every tool's name, description and parameter schema to this JSON file
+        #[arg(long, value_name = "PATH")]
+        export: Option<PathBuf>,
+    },
+
+    /// List the Ollama servers and the models installed on the active one
+    Models,
+
+    /// Show what the tools changed in files during the last interactive session
+    Diff {
+        /// A saved journal to show instead, e.g. a copy of last-session-journal.json
+        journal: Option<PathBuf>,
+    },
+
+    /// Manage the saved session files
+    Sessions {
+        #[command(subcommand)]
+        command: SessionsCommand,
     },
 
     /// Change configuration
     Config {
+        /// Show the merged configuration and which file set each value
+        #[arg(long)]
+        show: bool,
+
         /// Set the model to use
         #[arg(short, long)]
         model: Option<String>,
     },
 }
 
-// Message and Role definitions used by both the CLI and TUI
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub enum Role {
-    #[serde(rename = "user")]
-    User,
-    #[serde(rename = "assistant")]
-    Assistant,
-    #[serde(rename = "system")]
-    System,
+#[derive(Subcommand)]
+pub enum SessionsCommand {
+    /// Drop old or large contents that were moved out of session files into blob files
+    Prune {
+        /// The session files (default: the TUI's last-session.json)
+        sessions: Vec<PathBuf>,
+
+        /// Drop blobs written more than this many days ago
+        #[arg(long, value_name = "DAYS", required_unless_present = "larger_than")]
+        older_than: Option<u64>,
+
+        /// Drop blobs larger than this many kilobytes
+        #[arg(long, value_name = "KB")]
+        larger_than: Option<u64>,
+
+        /// Say what would be dropped without changing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Message {
-    pub role: Role,
-    pub content: String,
-    #[serde(skip, default)]
-    pub input_tokens: usize,
-    #[serde(skip, default)]
-    pub output_tokens: usize,
-    #[serde(skip, default)]
-    pub used_tools: Vec<String>,
+// Prints agent events to the terminal
+struct CliOutput {
+    show_reasoning: bool,
+    // Tool calls at least this long are flagged as slow
+    slow_call: Duration,
+    links: Linker,
 }
 
-// Agent struct that manages conversation with LLM
-pub struct Agent {
-    pub client: OllamaClient,
-    pub model: String,
-    pub conversation: Vec<Message>,
+impl AgentOutput for CliOutput {
+    fn emit(&mut self, event: AgentEvent) {
+        let _hold = hold_spinner();
+        match event {
+            AgentEvent::Info(message) => print_info(&message),
+            AgentEvent::Sending { with_tools: true } => {
+                print_info("Processing message with tools enabled...")
+            }
+            AgentEvent::Sending { with_tools: false } => {
+                print_info("Processing message without tools...")
+            }
+            AgentEvent::Error(message) => print_error(&message),
+            AgentEvent::Warning(message) => print_warnings(&[message]),
+            AgentEvent::Help(commands) => print_help(&commands),
+            AgentEvent::Usage(command) => {
+                let usage = command.usage();
+                let (syntax, rest) = usage.split_once('\n').unwrap_or((&usage, ""));
+                style::print_line(style::CYAN, syntax);
+                println!("{}", rest);
+            }
+            AgentEvent::Tools(tools) => {
+                if tools.is_empty() {
+                    print_info("No tools available");
+                    return;
+                }
+
+                print_info("Available tools:");
+                for tool in tools {
+                    println!("  {}", style::paint(style::MAGENTA, &tool));
+                }
+            }
+            AgentEvent::Schemas(tools) => print_schema_list(&tools),
+            AgentEvent::Schema(tool) => println!("{}", schema::show(&tool)),
+            AgentEvent::ToolsUsed(tools) => {
+                print_info("Sentinel is using tools...");
+
+                style::print_line(style::MAGENTA, "Tool usage:");
+                for tool in &tools {
+                    println!("  - {}", tool);
+                }
+            }
+            AgentEvent::ToolTimes(timings) => {
+                println!(
+                    "{} {}",
+                    style::paint(style::MAGENTA, "Tool time:"),
+                    tool_times(&timings, self.slow_call)
+                );
+            }
+            AgentEvent::Response {
+                text,
+                input_tokens,
+                tool_output_tokens,
+                output_tokens,
+                model,
+                provider,
+                truncated,
+                server_timings,
+            } => {
+                let source = sentinel::source_label(&model, &provider)
+                    .map(|source| style::paint(style::DIM, &format!("({}) ", source)))
+                    .unwrap_or_default();
+                println!(
+                    "\n{}{}{}",
+                    style::paint(style::BRIGHT_BLUE, "Sentinel: "),
+                    source,
+                    self.links.linkify(&text)
+                );
+                if truncated {
+                    println!("{}", style::paint(style::YELLOW, limits::TRUNCATED_NOTE));
+                }
+                let tokens = TokenBreakdown::new(input_tokens, tool_output_tokens, output_tokens);
+                println!(
+                    "\n{}",
+                    style::paint(
+                        style::YELLOW,
+                        &with_load_note(format!("(Tokens: {})", tokens), server_timings)
+                    )
+                );
+            }
+            AgentEvent::Interrupted(text) => {
+                println!(
+                    "\n{}{}",
+                    style::paint(style::BRIGHT_BLUE, "Sentinel: "),
+                    self.links.linkify(&text)
+                );
+            }
+            AgentEvent::Stats(stats) => {
+                print_info("Session stats:");
+                for line in stats.report() {
+                    println!("  {}", line);
+                }
+            }
+            AgentEvent::SearchResults(hits) => {
+                for hit in hits {
+                    println!(
+                        "  {} {}: {}",
+                        style::paint(style::CYAN, &format!("[{}]", hit.index)),
+                        role_label(&hit.role),
+                        hit.snippet
+                    );
+                }
+            }
+            AgentEvent::Staged(attachments) => {
+                print_info("Staged for the next message:");
+                for attachment in &attachments {
+                    println!(
+                        "  {} ({} chars)",
+                        style::paint(style::MAGENTA, &attachment.path),
+                        attachment.content.chars().count()
+                    );
+                }
+            }
+            AgentEvent::History(entries) => {
+                if entries.is_empty() {
+                    print_info("No messages yet");
+                    return;
+                }
+
+                for entry in entries {
+                    let pin = if entry.pinned { " (pinned)" } else { "" };
+                    println!(
+                        "  {}{} {}: {}",
+                        style::paint(style::CYAN, &format!("[{}]", entry.index)),
+                        pin,
+                        role_label(&entry.role),
+                        entry.preview
+                    );
+                }
+            }
+            AgentEvent::Reasoning(reasoning) if self.show_reasoning => print_reasoning(&reasoning),
+            AgentEvent::Candidates(candidates) => {
+                print_candidates(&candidates, self.show_reasoning);
+                print_info("Use /pick <label> to keep one, or /pick <label> keep to save the others as alternatives");
+            }
+            AgentEvent::Comparison(comparison) => {
+                print_comparison(&comparison);
+                print_info("These replies are not saved; use /promote <label> to put one in place of the last reply");
+            }
+            AgentEvent::Templates(templates) => print_templates(&templates),
+            AgentEvent::Diff(diff) => print_session_diff(&diff),
+            AgentEvent::Branches(branches) => {
+                print_info("Branches:");
+                for branch in branches {
+                    let marker = if branch.active { "*" } else { " " };
+                    let origin = branch
+                        .parent
+                        .map(|fork| {
+                            format!(" (from {} at message {})", fork.branch, fork.message_index)
+                        })
+                        .unwrap_or_default();
+                    println!(
+                        "  {}{}{} - {} messages{}",
+                        marker,
+                        "  ".repeat(branch.depth),
+                        style::paint(style::MAGENTA, &branch.name),
+                        branch.messages,
+                        origin
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
 }
 
-impl Agent {
-    // Create a new agent with the specified model
-    pub fn new(model: &str) -> Self {
-        Self {
-            client: OllamaClient::new().with_model(model),
-            model: model.to_string(),
-            conversation: Vec::new(),
+// Shows proposed file changes as colored diffs and asks whether to apply them
+struct TerminalReviewer;
+
+impl Reviewer for TerminalReviewer {
+    fn review(&self, change: &ProposedChange) -> Decision {
+        let _hold = hold_spinner();
+        print_diff(change);
+        let answer = read_answer("Apply this change? [y]es, [a]ll for this session, [n]o: ")
+            .unwrap_or_default();
+        match answer.to_lowercase().as_str() {
+            "y" | "yes" => Decision::Approve,
+            "a" | "all" => Decision::ApproveAll,
+            _ => {
+                let reason =
+                    read_answer("Why not? (optional, sent to the model): ").unwrap_or_default();
+                Decision::Reject {
+                    reason: (!reason.is_empty()).then_some(reason),
+                }
+            }
+        }
+    }
+
+    fn confirm_command(&self, command: &str, reason: &str) -> Decision {
+        let _hold = hold_spinner();
+        print_tagged(style::MAGENTA, "[COMMAND]", command);
+        print_info(reason);
+        let answer = read_answer("Run it? [y]es, [a]ll commands for this session, [n]o: ")
+            .unwrap_or_default();
+        match answer.to_lowercase().as_str() {
+            "y" | "yes" => Decision::Approve,
+            "a" | "all" => Decision::ApproveAll,
+            _ => {
+                let reason =
+                    read_answer("Why not? (optional, sent to the model): ").unwrap_or_default();
+                Decision::Reject {
+                    reason: (!reason.is_empty()).then_some(reason),
+                }
+            }
         }
     }
+}
 
-    // Start the conversation loop
-    pub async fn start(&mut self) -> Result<()> {
-        self.print_colored_banner();
-        self.print_help();
+// Prints the output of running tools as a dimmed block
+struct TerminalProgress {
+    links: Linker,
+}
 
-        let tools = self.client.get_available_tools();
-        if !tools.is_empty() {
-            self.print_info(&format!("Available tools: {}", tools.join(", ")));
+impl Progress for TerminalProgress {
+    fn line(&self, tool: &str, line: &str) {
+        let _hold = hold_spinner();
+        if let Some(spinner) = SPINNER.get() {
+            spinner
+                .activity()
+                .set_phase(Some(format!("running {}", tool)));
         }
+        style::print_line(style::DIM, &format!("  | {}", self.links.linkify(line)));
+    }
 
-        self.print_divider();
+    fn finished(&self, _tool: &str) {
+        if let Some(spinner) = SPINNER.get() {
+            spinner.activity().set_phase(None);
+        }
+    }
+}
 
-        loop {
-            self.print_user_prompt();
+// The spinner shown on stderr while the model generates
+static SPINNER: OnceLock<Arc<Spinner>> = OnceLock::new();
 
-            let mut input = String::new();
-            std::io::stdin().read_line(&mut input)?;
-            let input = input.trim();
+// Show `activity` as a spinner on stderr, unless it is not a terminal, color
+// is off or the output is JSON
+fn start_spinner(activity: &Arc<Activity>, json: bool) {
+    let enabled =
+        activity::spinner_enabled(std::io::stderr().is_terminal(), style::enabled(), json);
+    if !enabled || SPINNER.get().is_some() {
+        return;
+    }
+    let spinner = SPINNER.get_or_init(|| {
+        Arc::new(Spinner::new(
+            Arc::clone(activity),
+            Box::new(std::io::stderr()),
+        ))
+    });
+    Spinner::spawn(spinner);
+}
 
-            if input.is_empty() {
-                continue;
-            }
+// Take the spinner off the screen until the hold is dropped, so something can be printed
+fn hold_spinner() -> Option<SpinnerHold<'static>> {
+    SPINNER.get().map(|spinner| spinner.hold())
+}
 
-            // Check for commands
-            if input.starts_with('/') {
-                if self.process_command(input) {
-                    continue;
-                } else {
-                    // If it's not a recognized command, treat it as regular input
-                    if input.to_lowercase() == "/exit" {
-                        break;
-                    }
+// The generation `activity` shows is over; erase the spinner before the reply prints
+fn finish_activity(activity: &Activity) {
+    activity.finish();
+    drop(hold_spinner());
+}
+
+// A reviewer for file changes if someone is at the terminal to answer
+fn terminal_reviewer() -> Option<Arc<dyn Reviewer>> {
+    let interactive = std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+    interactive.then(|| Arc::new(TerminalReviewer) as Arc<dyn Reviewer>)
+}
+
+// Print a proposed change as a colored unified diff
+fn print_diff(change: &ProposedChange) {
+    print_tagged(style::YELLOW, "[REVIEW]", &change.summary());
+    for hunk in &change.hunks {
+        println!("{}", style::paint(style::CYAN, &hunk.header()));
+        for line in &hunk.lines {
+            match line {
+                DiffLine::Context(_) => println!("{}", line.unified()),
+                DiffLine::Added(_) => {
+                    println!("{}", style::paint(style::BRIGHT_GREEN, &line.unified()))
                 }
+                DiffLine::Removed(_) => println!("{}", style::paint(style::RED, &line.unified())),
             }
+        }
+    }
+}
 
-            // Add user message to conversation history
-            let user_message = Message {
-                role: Role::User,
-                content: input.to_string(),
-                input_tokens: 0,
-                output_tokens: 0,
-                used_tools: Vec::new(),
-            };
-
-            self.conversation.push(user_message);
-
-            // Generate response with tools
-            self.print_info("Processing message with tools enabled...");
-
-            match self
-                .client
-                .generate_response_with_tools(&self.conversation, &[])
-                .await
-            {
-                Ok((text, input_tokens, output_tokens, used_tools)) => {
-                    // Print tool usage if any
-                    if !used_tools.is_empty() {
-                        self.print_info("Sentinel is using tools...");
-
-                        println!(
-                            "{}Tool usage:{}",
-                            terminal_colors::MAGENTA,
-                            terminal_colors::RESET
-                        );
-                        for tool in &used_tools {
-                            println!("  - {}", tool);
-                        }
-                    }
+// Display name of a message role
+fn role_label(role: &Role) -> &'static str {
+    match role {
+        Role::User => "User",
+        Role::Assistant => "Sentinel",
+        Role::System => "System",
+        Role::Tool => "Tool",
+    }
+}
 
-                    // Print Claude's response
-                    self.print_ollama_response(&text);
+// Load the model while the user types the first prompt, sending how long it
+// took; if it fails, the first request reports why
+fn warm_up_in_background(
+    endpoint: &Endpoint,
+    model: &str,
+    keep_alive: Option<KeepAlive>,
+) -> oneshot::Receiver<Option<ServerTimings>> {
+    let client = OllamaClient::new()
+        .with_endpoint(endpoint)
+        .with_model(endpoint.model.as_deref().unwrap_or(model))
+        .with_keep_alive(keep_alive);
+    let (sender, receiver) = oneshot::channel();
+    tokio::spawn(async move {
+        let _ = sender.send(client.warm_up().await.ok());
+    });
+    receiver
+}
 
-                    // Print token usage info
-                    self.print_token_info(input_tokens, output_tokens);
+// Say how long warming up took, once it is done
+fn print_warm_up(warm_up: &mut Option<oneshot::Receiver<Option<ServerTimings>>>, model: &str) {
+    let Some(receiver) = warm_up else {
+        return;
+    };
+    match receiver.try_recv() {
+        Err(oneshot::error::TryRecvError::Empty) => return,
+        Ok(Some(timings)) => print_info(&timings::warm_up_note(model, &timings)),
+        Ok(None) | Err(oneshot::error::TryRecvError::Closed) => {}
+    }
+    *warm_up = None;
+}
 
-                    // Add Claude's response to conversation history
-                    let assistant_message = Message {
-                        role: Role::Assistant,
-                        content: text,
-                        input_tokens,
-                        output_tokens,
-                        used_tools: used_tools.clone(),
-                    };
+// Ctrl+C cuts the reply being generated short; at the prompt, or pressed
+// again before the reply stops, it exits as usual
+fn interrupt_on_ctrl_c(interrupter: Interrupter) {
+    tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if interrupter.interrupt() {
+                println!();
+                print_info("Interrupting the reply; press Ctrl+C again to exit");
+            } else {
+                status::restore_window_title();
+                std::process::exit(130);
+            }
+        }
+    });
+}
 
-                    self.conversation.push(assistant_message);
-                }
-                Err(e) => {
-                    self.print_error(&format!("Error generating response: {}", e));
-                }
+// Run the interactive conversation loop
+// Print what a session changed, colored like a proposed change
+fn print_session_diff(diff: &SessionDiff) {
+    for line in diff.render().lines() {
+        let color = match LineKind::of(line) {
+            LineKind::Title => style::BRIGHT_WHITE,
+            LineKind::Header => style::BOLD,
+            LineKind::Hunk => style::CYAN,
+            LineKind::Added => style::BRIGHT_GREEN,
+            LineKind::Removed => style::RED,
+            LineKind::Context => {
+                println!("{}", line);
+                continue;
             }
+        };
+        println!("{}", style::paint(color, line));
+    }
+}
+
+// Print what the last session, or the one whose journal is at `path`, changed
+fn print_saved_diff(path: Option<PathBuf>) -> Result<()> {
+    let path = match path {
+        Some(path) => path,
+        None => Autosave::open_default()
+            .map(|autosave| autosave.journal_path())
+            .context("Cannot find the last session: neither XDG_DATA_HOME nor HOME is set")?,
+    };
+    if !path.exists() {
+        anyhow::bail!(
+            "No saved session changes at {}; they are saved when an interactive session exits",
+            path.display()
+        );
+    }
+    print_session_diff(&SessionDiff::of_journal(&Journal::load(&path)?));
+    Ok(())
+}
+
+/// Drop the blobs of `sessions` that `rules` select and say what went
+fn prune_sessions(sessions: Vec<PathBuf>, rules: &PruneRules, dry_run: bool) -> Result<()> {
+    let sessions = if sessions.is_empty() {
+        let autosave = Autosave::open_default()
+            .context("Cannot find the last session: neither XDG_DATA_HOME nor HOME is set")?;
+        vec![autosave.path()]
+    } else {
+        sessions
+    };
+    let now = SystemTime::now();
+    for path in sessions {
+        if !path.exists() {
+            print_info(&format!("No session file at {}", path.display()));
+            continue;
+        }
+        let pruned = session_blobs::prune(&path, rules, now, dry_run)?;
+        let verb = if dry_run { "Would drop" } else { "Dropped" };
+        let mut line = format!(
+            "{}: {} {} blobs ({})",
+            path.display(),
+            verb,
+            pruned.dropped,
+            session_blobs::size(pruned.bytes)
+        );
+        if pruned.removed > 0 {
+            line.push_str(&format!(
+                ", removing {} files no other session uses",
+                pruned.removed
+            ));
+        }
+        print_info(&line);
+    }
+    Ok(())
+}
+
+/// The flags of `sentinel chat`, all unset when it runs without a command
+#[derive(Args, Debug, Default, PartialEq)]
+pub struct ChatArgs {
+    /// The model to use (default: model.name from the config, else llama3.2:latest)
+    #[arg(short, long)]
+    model: Option<String>,
+
+    /// The system prompt (default: system_prompt from the config)
+    #[arg(short, long, value_name = "PROMPT")]
+    system: Option<String>,
+
+    /// Offer the model only these tools, e.g. --tools ls,file (default: tools.enabled from the config, else all)
+    #[arg(short, long, value_name = "NAMES", value_delimiter = ',')]
+    tools: Option<Vec<String>>,
+
+    /// Continue the conversation the TUI saved last
+    #[arg(short = 'c', long = "continue")]
+    resume: bool,
+
+    /// Chat in the full-screen TUI instead of the REPL
+    #[arg(long)]
+    tui: bool,
 
-            self.print_divider();
+    /// Read the REPL's input a line at a time, without editing keys (default when stdin isn't a terminal)
+    #[arg(long)]
+    dumb_terminal: bool,
+}
+
+impl ChatArgs {
+    /// Set what the flags give over the config file's settings
+    fn apply(&self, config: &mut Config) {
+        if let Some(model) = &self.model {
+            config.model.name = Some(model.clone());
+        }
+        if let Some(system) = &self.system {
+            config.system_prompt = Some(system.clone());
+        }
+        if let Some(tools) = &self.tools {
+            config.tools.enabled = Some(tools.clone());
         }
+    }
+}
+
+/// The flags of `sentinel ask`
+#[derive(Args, Debug)]
+pub struct AskArgs {
+    /// The message to send to the LLM
+    message: Vec<String>,
 
-        Ok(())
+    /// The model to use (default: model.name from the config, else llama3.2:latest)
+    #[arg(short, long)]
+    model: Option<String>,
+
+    /// Use tools
+    #[arg(short, long)]
+    tools: bool,
+
+    /// Attach a file (or glob pattern) to the message; can be repeated
+    #[arg(long = "file", value_name = "PATH")]
+    files: Vec<String>,
+
+    /// Send a PNG, JPEG or WebP image with the message to a multimodal model; can be repeated
+    #[arg(long = "image", value_name = "PATH")]
+    images: Vec<String>,
+
+    /// How to use input piped into stdin
+    #[arg(long, value_enum, default_value = "context")]
+    stdin_as: StdinMode,
+
+    /// Generate N responses with different seeds and show them all
+    #[arg(long, value_name = "N")]
+    candidates: Option<usize>,
+
+    /// Print a single JSON object with the response, usage and tool calls, and nothing else on stdout
+    #[arg(long, conflicts_with = "candidates")]
+    json: bool,
+
+    /// Start the message from a prompt template (see `sentinel templates`)
+    #[arg(long, value_name = "NAME")]
+    template: Option<String>,
+
+    /// Set a template variable, or read it from a file with name=@path; can be repeated
+    #[arg(long = "var", value_name = "NAME=VALUE", requires = "template")]
+    vars: Vec<String>,
+
+    /// Cap the response at N tokens (default: model.max_tokens from the config)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_tokens: Option<u32>,
+
+    /// End the response where the model writes this text; can be repeated, and --stop "" clears the configured ones
+    #[arg(long, value_name = "TEXT")]
+    stop: Vec<String>,
+
+    /// Give up on the response after this long, e.g. 120s, 10m or off (default: model.timeout_secs from the config)
+    #[arg(long, value_name = "DURATION")]
+    timeout: Option<String>,
+}
+
+/// Chat in the REPL or the TUI, with the flags over the config
+async fn run_chat(chat: ChatArgs, cli: &Cli) -> Result<()> {
+    let mut config = Config::load()?;
+    cli.apply(&mut config);
+    config.force_tools = cli.force_tools;
+    chat.apply(&mut config);
+    let prime = cli.prime || prime::enabled_in_env();
+
+    if chat.tui {
+        print_warnings(&config.warnings);
+        let options = tui::TuiOptions {
+            auto_pull: cli.pull,
+            prime,
+            dry_run: cli.dry_run,
+            keep_alive: cli.keep_alive.clone(),
+            resume: chat.resume,
+            recording: cli.recording(),
+        };
+        return tui::run(config, options).await;
     }
 
-    // Process special commands (prefixed with /)
-    fn process_command(&mut self, command: &str) -> bool {
-        match command.to_lowercase().as_str() {
-            "/exit" => {
-                self.print_info("Goodbye!");
-                std::process::exit(0);
-            }
-            "/help" => {
-                self.print_help();
-                true
+    let conversation = if chat.resume {
+        Some(tui::last_conversation().context("Cannot continue the last session")?)
+    } else {
+        None
+    };
+    let line_editing = !chat.dumb_terminal && LineEditor::available();
+    run_interactive(config, conversation, prime, line_editing, cli).await
+}
+
+async fn run_interactive(
+    config: Config,
+    conversation: Option<(Sessions<Message>, SessionStats)>,
+    prime: bool,
+    line_editing: bool,
+    cli: &Cli,
+) -> Result<()> {
+    let model = &config.model.name(None);
+    let keep_alive = config.model.keep_alive(cli.keep_alive.as_deref())?;
+    let recording = cli.recording();
+
+    print_colored_banner(model);
+    print_warnings(&config.warnings);
+    let mut warm_up = None;
+    let client: Box<dyn LlmClient> = match recording.replay()? {
+        Some(replay) => {
+            print_info(&replay_banner(&replay));
+            Box::new(replay)
+        }
+        None => {
+            let client = FailoverClient::connect(&config, model, keep_alive.clone()).await?;
+            if config.endpoints.len() > 1 {
+                print_info(&format!("Using Ollama at {}", client.active().address()));
             }
-            "/clear" => {
-                self.clear_conversation();
-                true
+            availability::ensure_model(&client, model, cli.pull, &mut console::Terminal::new())
+                .await?;
+            if config.model.warm_up {
+                warm_up = Some(warm_up_in_background(client.active(), model, keep_alive));
             }
-            "/tools" => {
-                self.list_tools();
-                true
+            recording.record(Box::new(client), model)?
+        }
+    };
+    client.set_dry_run(cli.dry_run);
+    if let Some(path) = &cli.record {
+        print_info(&format!("Recording the session to {}", path.display()));
+    }
+
+    let mut agent = Agent::with_client(client, model);
+    // Given before the config, which can turn the memory tool off
+    if let Some(memory) = load_memory(&config) {
+        agent = agent.with_memory(memory);
+    }
+    let mut agent = agent
+        .with_config(&config)
+        .with_notifier(Notifier::from_config(&config.notify));
+    if let Some((sessions, stats)) = conversation {
+        print_info(&format!(
+            "Continuing the last session ({} messages)",
+            sessions.messages().len()
+        ));
+        agent = agent.with_sessions(sessions).with_stats(stats);
+        restore_journal(&agent);
+    }
+    if let Some(reviewer) = terminal_reviewer() {
+        agent = agent.with_reviewer(reviewer);
+    }
+    let links = Linker::for_terminal(config.sandbox_root.as_deref());
+    agent = agent.with_progress(Arc::new(TerminalProgress {
+        links: links.clone(),
+    }));
+    let activity = Arc::new(Activity::new());
+    start_spinner(&activity, false);
+    agent = agent.with_activity(activity);
+    let mut output = CliOutput {
+        show_reasoning: cli.show_reasoning,
+        slow_call: config.tools.slow_call(),
+        links,
+    };
+    interrupt_on_ctrl_c(agent.interrupter());
+
+    if prime {
+        if let Some(primer) = load_primer().await {
+            agent.set_primer(primer);
+        }
+    }
+
+    print_help(Agent::commands());
+
+    if agent.client().dry_run() {
+        print_info("Dry-run mode is on: mutating tool actions are previewed, not executed");
+    }
+
+    let tools = agent.client().available_tools();
+    if !tools.is_empty() {
+        print_info(&format!("Available tools: {}", tools.join(", ")));
+    }
+    if let Some(warning) = agent.tool_warning().await {
+        print_warnings(&[warning]);
+    }
+
+    print_divider();
+
+    let mut history = InputHistory::load_default();
+    let prompt_format = config
+        .prompt_format
+        .as_deref()
+        .unwrap_or(status::DEFAULT_PROMPT_FORMAT);
+    let mut editor = line_editing.then(|| LineEditor::new("", style::BRIGHT_GREEN));
+    let mut window_title = WindowTitle::of_terminal();
+
+    loop {
+        print_warm_up(&mut warm_up, agent.model());
+        // A terminal that can't take a title just keeps its own
+        let _ = window_title.set(&status::window_title(None, agent.model(), false));
+        let prompt = status::expand(prompt_format, &agent.prompt_state());
+
+        // A replay offers each recorded prompt in turn
+        let recorded = replayed_prompt(agent.client());
+        if let Some(prompt) = &recorded {
+            print_info(&format!(
+                "Next recorded prompt (Enter sends it): {}",
+                prompt.lines().next().unwrap_or_default()
+            ));
+        }
+        let mut input = match &mut editor {
+            Some(editor) => {
+                println!();
+                editor.set_prompt(&prompt);
+                match editor.read(&history)? {
+                    ReadLine::Line(line) => line,
+                    ReadLine::Interrupted => continue,
+                    ReadLine::Eof => break,
+                }
             }
-            _ => {
-                if command.starts_with('/') {
-                    self.print_error(&format!("Unknown command: {}", command));
-                    self.print_info("Type /help for available commands");
-                    true
-                } else {
-                    false
+            None => {
+                print_user_prompt(&prompt);
+                let mut input = String::new();
+                if std::io::stdin().read_line(&mut input)? == 0 {
+                    break;
                 }
+                input
             }
+        };
+        if let (true, Some(prompt)) = (input.trim().is_empty(), recorded) {
+            input = prompt;
+        }
+
+        history.push(input.trim());
+        if let Err(e) = history.save() {
+            print_error(&e.to_string());
+        }
+
+        let is_message = !input.trim().is_empty() && !input.trim().starts_with('/');
+        if agent.handle_input(&input, &mut output).await == Flow::Exit {
+            break;
+        }
+
+        pick_candidate(&mut agent, &mut output).await?;
+        fill_template(&mut agent, &mut output).await?;
+
+        if is_message {
+            print_divider();
         }
     }
 
-    // List available tools
-    fn list_tools(&self) {
-        let tools = self.client.get_available_tools();
+    if let Some(autosave) = Autosave::open_default() {
+        if let Err(e) = autosave.save_journal(&agent.journal()) {
+            print_error(&format!("{:#}", e));
+        }
+    }
+    print_unplayed(agent.client());
+    Ok(())
+}
+
+// Take over the file changes of the session being continued, so /undo reaches them
+fn restore_journal(agent: &Agent) {
+    let Some(autosave) = Autosave::open_default() else {
+        return;
+    };
+    match autosave.load_journal() {
+        Ok(Some(journal)) => agent.journal().restore(journal),
+        Ok(None) => {}
+        Err(e) => print_error(&format!(
+            "{:#}; the changes of the last session can't be undone",
+            e
+        )),
+    }
+}
+
+// What a replay says before it starts, e.g. "Replaying a session with llama3.2 (6 requests)"
+fn replay_banner(client: &recording::ReplayClient) -> String {
+    format!(
+        "Replaying a session with {} ({} requests); nothing is sent to Ollama and no tools run",
+        client.replay().header().model,
+        client.replay().unplayed()
+    )
+}
+
+// The prompt of the next recorded request, when the session is a replay
+fn replayed_prompt(client: &dyn LlmClient) -> Option<String> {
+    match client.tape()? {
+        Tape::Replay(replay) => replay.next_prompt(),
+        Tape::Record(_) => None,
+    }
+}
 
-        if tools.is_empty() {
-            self.print_info("No tools available");
-            return;
+// Warn about the requests of a replay that never came up
+fn print_unplayed(client: &dyn LlmClient) {
+    if let Some(Tape::Replay(replay)) = client.tape() {
+        let unplayed = replay.unplayed();
+        if unplayed > 0 {
+            print_warnings(&[format!("{} recorded requests were not replayed", unplayed)]);
         }
+    }
+}
 
-        self.print_info("Available tools:");
-        for tool in tools {
-            println!(
-                "  {}{}{}",
-                terminal_colors::MAGENTA,
-                tool,
-                terminal_colors::RESET
-            );
+// Gather the project primer for the current directory, reporting its size
+async fn load_primer() -> Option<Primer> {
+    let directory = std::env::current_dir().ok()?;
+    match prime::prime(&directory).await {
+        Ok(primer) => {
+            print_info(&format!(
+                "Primed with project context (~{} tokens{})",
+                primer.tokens,
+                if primer.cached { ", cached" } else { "" }
+            ));
+            Some(primer)
+        }
+        Err(e) => {
+            print_error(&format!("Could not prime project context: {}", e));
+            None
         }
     }
+}
 
-    // Clear conversation history
-    fn clear_conversation(&mut self) {
-        self.conversation.clear();
-        self.print_info("Conversation cleared");
+// The facts remembered for the current directory, unless memory is turned off
+fn load_memory(config: &Config) -> Option<Arc<Memory>> {
+    let directory = std::env::current_dir().ok()?;
+    match Memory::load(&config.memory, &directory) {
+        Ok(memory) => memory,
+        Err(e) => {
+            print_warnings(&[format!("Remembered facts not loaded: {:#}", e)]);
+            None
+        }
     }
+}
 
-    // Print user prompt
-    fn print_user_prompt(&self) {
-        print!(
-            "\n{}User: {}",
-            terminal_colors::BRIGHT_GREEN,
-            terminal_colors::RESET
-        );
-        std::io::stdout().flush().unwrap();
+// The prompt for `ask`: the message with any piped input and attached files,
+// remembering which files they were
+fn ask_prompt(message: &str, files: &[String], stdin_as: StdinMode) -> Result<Message> {
+    let stdin = if stdin_as == StdinMode::Ignore {
+        None
+    } else {
+        input::read_piped_stdin()?
+    };
+    let prompt = input::build_prompt(message, stdin.as_deref(), stdin_as)?;
+
+    let loaded = attachments::load(files)?;
+    print_warnings(&loaded.warnings);
+    Ok(
+        Message::user(attachments::with_attachments(&prompt, &loaded.attachments))
+            .with_local_sources(attachments::paths(&loaded.attachments)),
+    )
+}
+
+// The message for `ask --template`: the rendered template, followed by any message given
+fn template_message(name: &str, vars: &[String], message: &str) -> Result<String> {
+    let template = templates::find(name)?;
+
+    let mut values = BTreeMap::new();
+    for assignment in vars {
+        let (variable, value) = templates::parse_assignment(assignment)?;
+        let (value, warnings) = templates::resolve_value(&value)
+            .with_context(|| format!("Failed to read the value of '{}'", variable))?;
+        print_warnings(&warnings);
+        values.insert(variable, value);
     }
 
-    // Print Claude's response
-    fn print_ollama_response(&self, text: &str) {
-        println!(
-            "\n{}Sentinel: {}{}",
-            terminal_colors::BRIGHT_BLUE,
-            terminal_colors::RESET,
-            text
+    let rendered = template.render(&values)?;
+    print_warnings(&rendered.warnings);
+    if message.is_empty() {
+        Ok(rendered.text)
+    } else {
+        Ok(format!("{}\n\n{}", rendered.text, message))
+    }
+}
+
+// Print warnings to stderr, out of the way of the answer
+fn print_warnings(warnings: &[String]) {
+    for warning in warnings {
+        eprintln!(
+            "{}",
+            style::paint(style::YELLOW, &format!("Warning: {}", warning))
         );
     }
+}
 
-    // Print token usage information
-    fn print_token_info(&self, input_tokens: usize, output_tokens: usize) {
-        println!(
-            "\n{}(Input tokens: {}, Output tokens: {}){}",
-            terminal_colors::YELLOW,
+// The client of a session with `model`: Ollama with the model installed,
+// recorded with --record, or the recording given to --replay
+async fn connect(
+    config: &Config,
+    model: &str,
+    keep_alive: Option<KeepAlive>,
+    cli: &Cli,
+) -> Result<Box<dyn LlmClient>> {
+    let recording = cli.recording();
+    if let Some(replay) = recording.replay()? {
+        return Ok(Box::new(replay));
+    }
+    let client = FailoverClient::connect(config, model, keep_alive).await?;
+    availability::ensure_model(&client, model, cli.pull, &mut console::Terminal::new()).await?;
+    recording.record(Box::new(client), model)
+}
+
+// Answer one question, with only the answer on stdout and the rest on
+// stderr, or a JSON report with --json. The kind of a failure decides the
+// status `main` exits with
+async fn run_ask(ask: AskArgs, cli: &Cli) -> Result<(), Failure> {
+    let prime = cli.prime || prime::enabled_in_env();
+    let mut config = Config::load().map_err(Failure::usage)?;
+    cli.apply(&mut config);
+    let model = config.model.name(ask.model.as_deref());
+    let keep_alive = config
+        .model
+        .keep_alive(cli.keep_alive.as_deref())
+        .map_err(Failure::usage)?;
+    let limits = ResponseLimits::from_config(&config.model)
+        .with_flags(ask.max_tokens, &ask.stop)
+        .with_timeout_flag(ask.timeout.as_deref())
+        .map_err(Failure::usage)?;
+    let message = match &ask.template {
+        Some(name) => {
+            template_message(name, &ask.vars, &ask.message.join(" ")).map_err(Failure::usage)?
+        }
+        None => ask.message.join(" "),
+    };
+    let activity = Arc::new(Activity::new());
+    start_spinner(&activity, ask.json);
+    if ask.json {
+        let request = JsonAsk {
+            message,
+            model,
+            tools: ask.tools,
+            files: ask.files,
+            images: ask.images,
+            stdin_as: ask.stdin_as,
+            prime,
+            show_reasoning: cli.show_reasoning,
+            keep_alive,
+            limits,
+            config,
+        };
+        return run_ask_json(request, cli).await;
+    }
+
+    style::set_stderr_only(true);
+    print_warnings(&config.warnings);
+    let client = connect(&config, &model, keep_alive, cli).await?;
+    let client = client.as_ref();
+    client.set_dry_run(cli.dry_run);
+    client.apply_config(&config);
+    client.set_limits(limits);
+    if let Some(reviewer) = terminal_reviewer() {
+        client.set_reviewer(reviewer);
+    }
+    client.set_progress(Arc::new(TerminalProgress {
+        links: Linker::for_terminal(config.sandbox_root.as_deref()),
+    }));
+    let mut tool_support = ToolSupport::new();
+    tool_support.set_forced(cli.force_tools);
+    let tools = ask.tools && {
+        let supported = tool_support.check(client, &model).await;
+        if !supported {
+            print_warnings(&[tool_support::warning(&model)]);
+        }
+        supported
+    };
+
+    let images = load_images(&ask.images, client, &model)
+        .await
+        .map_err(Failure::usage)?;
+    let user_message = ask_prompt(&message, &ask.files, ask.stdin_as)
+        .map_err(Failure::usage)?
+        .with_images(images);
+
+    // Print user message with colored formatting
+    print_tagged(style::CYAN, "[USER]", &user_message.content);
+    for image in &user_message.images {
+        print_human(&format!(
+            "  {}",
+            style::paint(style::DIM, &image.to_string())
+        ));
+    }
+
+    let mut messages = Vec::new();
+    if let Some(prompt) = &config.system_prompt {
+        messages.push(Message::system(prompt.clone()));
+    }
+    if prime {
+        if let Some(primer) = load_primer().await {
+            messages.push(primer.message());
+        }
+    }
+    if let Some(memory) = load_memory(&config) {
+        messages.extend(memory.prompt().map(Message::system));
+        client.set_memory(memory);
+    }
+    messages.push(user_message);
+    client
+        .check_remote(&privacy::local_sources(&messages))
+        .map_err(Failure::usage)?;
+
+    let prices = PriceTable::from_env();
+    let notifier = Notifier::from_config(&config.notify);
+    let mut stats = SessionStats::new();
+    let started = Instant::now();
+    activity.start(started);
+
+    if let Some(count) = ask.candidates {
+        print_tagged(
+            style::MAGENTA,
+            "[SENTINEL]",
+            &format!("Generating {} candidates...", count),
+        );
+
+        let candidates = candidates::generate(client, &messages, count, tools).await;
+        finish_activity(&activity);
+        let candidates = candidates?;
+        for candidate in &candidates {
+            stats.record(
+                candidate.input_tokens,
+                candidate.output_tokens,
+                &candidate.used_tools,
+                candidate.latency,
+                prices.price_for(&model),
+            );
+            stats.record_reasoning(candidate.reasoning_tokens);
+            stats.record_model(
+                &reply_model(client, &model),
+                candidate.input_tokens,
+                candidate.output_tokens,
+            );
+        }
+
+        print_candidates(&candidates, cli.show_reasoning);
+        if let Some(first) = candidates.first() {
+            notifier.finished(started.elapsed(), &first.text);
+        }
+    } else if tools {
+        print_tagged(
+            style::MAGENTA,
+            "[SENTINEL]",
+            "Using Ollama with tools enabled...",
+        );
+
+        let response = activity::while_loading(
+            &activity,
+            client,
+            client.generate_response_with_tools(&messages, &[]),
+        )
+        .await;
+        finish_activity(&activity);
+        let (text, input_tokens, output_tokens, used_tools) = response?;
+        let invocations = client.last_tool_invocations();
+        let tool_output_tokens = invocations.iter().map(ToolInvocation::output_tokens).sum();
+        let timings: Vec<ToolTiming> = invocations.iter().map(ToolInvocation::timing).collect();
+        stats.record_rate_limited(client.take_rate_limited());
+        stats.record_recovered(client.take_recovered_calls());
+        stats.record_prompt_usage(client.take_prompt_usage());
+        let server_timings = client.take_server_timings();
+        stats.record_server_timings(server_timings);
+        stats.record(
             input_tokens,
             output_tokens,
-            terminal_colors::RESET
+            &used_tools,
+            started.elapsed(),
+            prices.price_for(&model),
         );
-    }
+        stats.record_tool_output(tool_output_tokens, prices.price_for(&model));
+        stats.record_tool_timings(&timings);
+        stats.record_model(&reply_model(client, &model), input_tokens, output_tokens);
+        let text = answer_of(&text, output_tokens, &mut stats, cli.show_reasoning);
 
-    // Print error message
-    fn print_error(&self, message: &str) {
-        println!(
-            "{}Error: {}{}",
-            terminal_colors::RED,
-            message,
-            terminal_colors::RESET
+        // Print summary of tool usage
+        if !used_tools.is_empty() {
+            print_tagged(
+                style::YELLOW,
+                "[TOOL SUMMARY]",
+                "Tools used in this response:",
+            );
+
+            for tool in &used_tools {
+                print_human(&format!("  - {}", tool));
+            }
+            if !timings.is_empty() {
+                print_human(&format!(
+                    "  {}",
+                    tool_times(&timings, config.tools.slow_call())
+                ));
+            }
+        }
+
+        print_reply(client, &model, &text);
+        print_truncated(client);
+        notifier.finished(started.elapsed(), &text);
+
+        let tokens = TokenBreakdown::new(input_tokens, tool_output_tokens, output_tokens);
+        print_tagged(
+            style::BRIGHT_WHITE,
+            "[INFO]",
+            &with_load_note(format!("Tokens: {}", tokens), Some(server_timings)),
+        );
+    } else {
+        print_tagged(
+            style::MAGENTA,
+            "[SENTINEL]",
+            "Using Ollama without tools...",
+        );
+
+        let response = activity::while_loading(
+            &activity,
+            client,
+            deadline::within(&client.limits(), client.generate_response(&messages)),
+        )
+        .await;
+        finish_activity(&activity);
+        let (text, input_tokens, output_tokens) = response?;
+        let server_timings = client.take_server_timings();
+        stats.record_server_timings(server_timings);
+        stats.record(
+            input_tokens,
+            output_tokens,
+            &[],
+            started.elapsed(),
+            prices.price_for(&model),
+        );
+        stats.record_model(&reply_model(client, &model), input_tokens, output_tokens);
+        let text = answer_of(&text, output_tokens, &mut stats, cli.show_reasoning);
+
+        print_reply(client, &model, &text);
+        print_truncated(client);
+        notifier.finished(started.elapsed(), &text);
+
+        let tokens = TokenBreakdown::new(input_tokens, 0, output_tokens);
+        print_tagged(
+            style::BRIGHT_WHITE,
+            "[INFO]",
+            &with_load_note(format!("Tokens: {}", tokens), Some(server_timings)),
         );
+    };
+
+    print_tagged(style::BRIGHT_WHITE, "[SESSION]", &stats.summary());
+    print_unplayed(client);
+    Ok(())
+}
+
+// What `ask --json` was asked to do
+struct JsonAsk {
+    message: String,
+    model: String,
+    tools: bool,
+    files: Vec<String>,
+    images: Vec<String>,
+    stdin_as: StdinMode,
+    prime: bool,
+    show_reasoning: bool,
+    keep_alive: Option<KeepAlive>,
+    limits: ResponseLimits,
+    config: Config,
+}
+
+// Answer one question and print the outcome as a single JSON object on stdout,
+// with everything else on stderr, exiting with the status of its `error_kind`
+// if it failed
+async fn run_ask_json(request: JsonAsk, cli: &Cli) -> Result<(), Failure> {
+    const PROVIDER: &str = "ollama";
+
+    style::set_stderr_only(true);
+    let started = Instant::now();
+    print_warnings(&request.config.warnings);
+
+    let prepared = async {
+        let client = connect(
+            &request.config,
+            &request.model,
+            request.keep_alive.clone(),
+            cli,
+        )
+        .await?;
+        client.set_dry_run(cli.dry_run);
+        client.apply_config(&request.config);
+        client.set_limits(request.limits.clone());
+
+        let mut messages = Vec::new();
+        if let Some(prompt) = &request.config.system_prompt {
+            messages.push(Message::system(prompt.clone()));
+        }
+        if request.prime {
+            if let Some(primer) = load_primer().await {
+                messages.push(primer.message());
+            }
+        }
+        let prompt = ask_prompt(&request.message, &request.files, request.stdin_as)
+            .map_err(Failure::usage)?;
+        let images = load_images(&request.images, client.as_ref(), &request.model)
+            .await
+            .map_err(Failure::usage)?;
+        messages.push(prompt.with_images(images));
+        client
+            .check_remote(&privacy::local_sources(&messages))
+            .map_err(Failure::usage)?;
+        anyhow::Ok((client, messages))
+    }
+    .await;
+
+    let mut report = match prepared {
+        Ok((client, messages)) => {
+            let policy = ExecutionPolicy::shared(cli.dry_run);
+            policy.configure(&request.config);
+            policy.set_remote_endpoint(client.endpoint());
+            let mut tools = ToolSet::with_defaults(policy);
+            tools.retain(|name| request.config.tools.allows(name));
+            tools.set_max_malformed(request.config.tools.max_malformed_calls());
+            tools.set_auto_retry(request.config.tools.bash.auto_retry);
+            tools.set_summarize(request.config.tools.summarize.clone());
+            tools.set_tape(client.tape());
+            let tools = request.tools.then_some(&mut tools);
+            report::ask(client.as_ref(), tools, &messages, &request.model, PROVIDER).await
+        }
+        Err(e) => AskReport::failure(&request.model, PROVIDER, &e, started.elapsed()),
+    };
+    if !request.show_reasoning {
+        report.reasoning = None;
+    }
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&report).map_err(anyhow::Error::from)?
+    );
+    if let Some(code) = report.exit_code {
+        std::process::exit(code);
+    }
+    Ok(())
+}
+
+// Send every prompt of a file on its own, reporting each outcome on stderr,
+// exiting with status 1 if any of them failed
+async fn run_batch(
+    prompts: &Path,
+    model: Option<&str>,
+    out: &Path,
+    options: BatchOptions,
+    cli: &Cli,
+) -> Result<()> {
+    let config = Config::load()?;
+    print_warnings(&config.warnings);
+    let items = batch::load(prompts)?;
+    let model = &config.model.name(model);
+    let keep_alive = config.model.keep_alive(cli.keep_alive.as_deref())?;
+    let client = connect(&config, model, keep_alive, cli).await?;
+    client.set_dry_run(cli.dry_run);
+    client.apply_config(&config);
+    client.set_limits(ResponseLimits::from_config(&config.model));
+
+    let options = BatchOptions {
+        system: config.system_prompt.clone(),
+        ..options
+    };
+    let total = items.len();
+    let mut done = 0;
+    let summary = batch::run(client.as_ref(), model, items, out, &options, |record| {
+        done += 1;
+        eprintln!("{}", batch_progress(record, done, total));
+    })
+    .await?;
+
+    let mut line = format!("{} prompts sent, {} failed", summary.ran, summary.failed);
+    if summary.skipped > 0 {
+        line.push_str(&format!(", {} already done", summary.skipped));
+    }
+    eprintln!("{} {}", style::paint(style::BRIGHT_WHITE, "[BATCH]"), line);
+    if summary.failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+// One line of batch progress, e.g. "[3/10] ok 1.2s What is Rust?"
+fn batch_progress(record: &BatchRecord, done: usize, total: usize) -> String {
+    let counter = style::paint(style::DIM, &format!("[{}/{}]", done, total));
+    let seconds = record.report.duration_ms as f64 / 1000.0;
+    let prompt: String = record.prompt.chars().take(60).collect();
+    match &record.report.error {
+        Some(error) => format!(
+            "{} {} {:.1}s {}: {}",
+            counter,
+            style::paint(style::RED, "failed"),
+            seconds,
+            prompt,
+            error
+        ),
+        None => format!(
+            "{} {} {:.1}s {}",
+            counter,
+            style::paint(style::BRIGHT_GREEN, "ok"),
+            seconds,
+            prompt
+        ),
+    }
+}
+
+// Ask the model for a command that performs `task`, confirm it and run it through the Bash tool
+async fn run_do(task: &str, model: Option<&str>, yes: bool, cli: &Cli) -> Result<()> {
+    let mut config = Config::load()?;
+    cli.apply(&mut config);
+    print_warnings(&config.warnings);
+    let model = &config.model.name(model);
+    let keep_alive = config.model.keep_alive(cli.keep_alive.as_deref())?;
+    let client = connect(&config, model, keep_alive, cli).await?;
+    client.set_dry_run(cli.dry_run);
+
+    let mut agent = Agent::with_client(client, model).with_config(&config);
+    print_tagged(style::CYAN, "[TASK]", task);
+
+    let mut command = agent.suggest_command(task).await?;
+    loop {
+        print_tagged(style::MAGENTA, "[COMMAND]", &command);
+        if yes {
+            break;
+        }
+
+        let answer = read_answer("Run it? [y]es, [e]dit, [n]o: ")?;
+        match answer.to_lowercase().as_str() {
+            "y" | "yes" => break,
+            "e" | "edit" => {
+                let edited = read_answer("New command (Enter keeps it): ")?;
+                if !edited.is_empty() {
+                    command = edited;
+                }
+            }
+            _ => {
+                print_info("Not running the command");
+                return Ok(());
+            }
+        }
+    }
+
+    let policy = ExecutionPolicy::shared(cli.dry_run);
+    policy.configure(&config);
+    // The user just confirmed the command, so strict mode need not ask again
+    if !yes {
+        policy.approve_commands();
+    }
+    let mut bash = Bash::new().with_policy(policy);
+    let output = bash.run_to_text(BashParams::new(command.clone())).await;
+    print_tagged(style::BRIGHT_GREEN, "[OUTPUT]", &output);
+    agent.record_command_output(&command, &output);
+
+    print_tagged(style::BRIGHT_WHITE, "[SESSION]", &agent.stats().summary());
+    Ok(())
+}
+
+// Run one tool with arguments from key=value pairs or JSON, printing its
+// output on stdout and how long it took on stderr
+async fn run_tool(
+    tools: &mut ToolSet,
+    name: &str,
+    params: &[String],
+    json: Option<&str>,
+) -> Result<()> {
+    let definition = tools.definition(name).with_context(|| {
+        format!(
+            "No tool named '{}'; available tools: {}",
+            name,
+            tools.names().join(", ")
+        )
+    })?;
+    let arguments = match json {
+        Some(json) => {
+            let arguments = serde_json::from_str(json).context("--json is not valid JSON")?;
+            invoke::validate(&definition, &arguments)?;
+            arguments
+        }
+        None => invoke::arguments_from_pairs(&definition, params)?,
+    };
+
+    let started = Instant::now();
+    let result = tools.call(name, arguments).await;
+    let timing = format!("[{} ran in {:.2}s]", name, started.elapsed().as_secs_f64());
+    if let Ok(output) = &result {
+        print!("{}", output);
+        if !output.ends_with('\n') {
+            println!();
+        }
+    }
+    eprintln!("{}", style::paint(style::DIM, &timing));
+    result?;
+    Ok(())
+}
+
+// Print every tool with its parameters, for `sentinel tool --list`
+// Every tool that can be run by hand, as the config sets them up
+fn load_tools(cli: &Cli) -> Result<ToolSet> {
+    let policy = ExecutionPolicy::shared(cli.dry_run);
+    let mut config = Config::load()?;
+    cli.apply(&mut config);
+    print_warnings(&config.warnings);
+    policy.configure(&config);
+    if let Some(reviewer) = terminal_reviewer() {
+        policy.set_reviewer(reviewer);
+    }
+    Ok(ToolSet::with_builtins(policy, &config.tools))
+}
+
+// Print one tool's description and parameter schema, for `sentinel tool <name> --schema`
+fn print_tool_schema(tools: &ToolSet, name: &str) -> Result<()> {
+    let definition = tools
+        .definition(name)
+        .or_else(|| registry::builtin(name))
+        .with_context(|| {
+            format!(
+                "No tool named '{}'; available tools: {}",
+                name,
+                tools.names().join(", ")
+            )
+        })?;
+    println!("{}", schema::show(&definition));
+    Ok(())
+}
+
+// Write every tool's schema to `path`, or list the tools without one
+fn export_tools(tools: &ToolSet, path: Option<&Path>) -> Result<()> {
+    let definitions = tools.definitions();
+    let Some(path) = path else {
+        print_schema_list(&definitions);
+        return Ok(());
+    };
+    let json = serde_json::to_string_pretty(&schema::export(&definitions))?;
+    std::fs::write(path, json + "\n")
+        .with_context(|| format!("Cannot write {}", path.display()))?;
+    print_info(&format!(
+        "Wrote the schemas of {} tools to {}",
+        definitions.len(),
+        path.display()
+    ));
+    Ok(())
+}
+
+// Print the tools with the first line of their descriptions
+fn print_schema_list(tools: &[Tool]) {
+    if tools.is_empty() {
+        print_info("No tools available");
+        return;
+    }
+    for tool in tools {
+        let summary = schema::summary(tool);
+        let (name, rest) = summary.split_once("  - ").unwrap_or((&summary, ""));
+        println!("  {}  - {}", style::paint(style::MAGENTA, name), rest);
     }
+}
+
+fn print_tool_list(tools: &ToolSet) {
+    let custom = tools.custom_names();
+    for (index, definition) in tools.definitions().iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        let description = invoke::describe(definition);
+        let (name, rest) = description.split_once('\n').unwrap_or((&description, ""));
+        if custom.contains(&definition.name) {
+            style::print_line(style::CYAN, &format!("{} (custom)", name));
+        } else {
+            style::print_line(style::CYAN, name);
+        }
+        println!("{}", rest);
+    }
+    for failed in tools.failed_names() {
+        println!();
+        style::print_line(style::YELLOW, &failed);
+    }
+}
 
-    // Print general information
-    fn print_info(&self, message: &str) {
+// Print a question and read the trimmed answer; an empty answer on end of input
+fn read_answer(question: &str) -> Result<String> {
+    print!("\n{}", style::paint(style::BRIGHT_GREEN, question));
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+// Ask which of the candidates generated by /best to keep, until one is picked or they are discarded
+async fn pick_candidate(agent: &mut Agent, output: &mut CliOutput) -> Result<()> {
+    while let Some(last) = agent.pending_candidates().last() {
+        let choice = read_answer(&format!(
+            "Pick a candidate [A-{}] (add 'keep' to save the others), or press Enter to discard: ",
+            last.label
+        ))?;
+        if choice.is_empty() {
+            agent.discard_candidates();
+            print_info("Discarded the candidates");
+            break;
+        }
+
+        agent
+            .handle_input(&format!("/pick {}", choice), output)
+            .await;
+    }
+
+    Ok(())
+}
+
+// Ask for each variable of the template chosen with /use, then send it
+async fn fill_template(agent: &mut Agent, output: &mut CliOutput) -> Result<()> {
+    let Some(template) = agent.take_pending_template() else {
+        return Ok(());
+    };
+
+    let mut values = BTreeMap::new();
+    for variable in template.variables() {
+        loop {
+            let answer = read_answer(&format!("{}: ", variable))?;
+            match templates::resolve_value(&answer) {
+                Ok((value, warnings)) => {
+                    print_warnings(&warnings);
+                    values.insert(variable, value);
+                    break;
+                }
+                Err(e) => print_error(&e.to_string()),
+            }
+        }
+    }
+
+    agent.send_template(&template, &values, output).await;
+    print_divider();
+    Ok(())
+}
+
+// Print each template with its variables and where it comes from
+fn print_templates(templates: &[Template]) {
+    print_info("Templates:");
+    for template in templates {
+        let source = match &template.path {
+            Some(path) => path.display().to_string(),
+            None => "built-in".to_string(),
+        };
+        let variables = template.variables();
         println!(
-            "{}{}{}",
-            terminal_colors::BRIGHT_WHITE,
-            message,
-            terminal_colors::RESET
+            "  {} ({}) - {}",
+            style::paint(style::MAGENTA, &template.name),
+            if variables.is_empty() {
+                "no variables".to_string()
+            } else {
+                variables.join(", ")
+            },
+            source
         );
     }
+}
 
-    // Print command help
-    fn print_command(&self, command: &str, description: &str) {
+// Print the Ollama servers, marking the active one, and the models installed on it
+async fn print_models(client: &FailoverClient) -> Result<()> {
+    print_info("Endpoints:");
+    for endpoint in client.endpoints() {
+        let active = endpoint == client.active();
         println!(
-            "  {}{}{}  - {}",
-            terminal_colors::CYAN,
-            command,
-            terminal_colors::RESET,
-            description
+            "  {} {}{}",
+            if active { "*" } else { " " },
+            style::paint(style::MAGENTA, &endpoint.address()),
+            endpoint
+                .model
+                .as_ref()
+                .map(|model| format!(" (model: {})", model))
+                .unwrap_or_default()
         );
     }
 
-    // Print separator line
-    fn print_divider(&self) {
+    let address = client.active().address();
+    let models = match client.installed_models().await {
+        Ok(models) => models,
+        Err(e) if failover::is_connection_error(&e) => {
+            return Err(anyhow::anyhow!(
+                "Could not reach the Ollama server at {}. Is it running? Start it with `ollama serve`, or point OLLAMA_HOST at another server.",
+                address
+            ));
+        }
+        Err(e) => return Err(e),
+    };
+    print_info(&format!("Models on {}:", address));
+    for model in models {
+        println!("  {}", model);
+    }
+    Ok(())
+}
+
+// Print each configured value with the file that set it
+fn print_config(layered: &Layered) {
+    print_warnings(&layered.config.warnings);
+    let values = layered.describe();
+    if values.is_empty() {
+        print_info("No configuration set; using the defaults");
+        return;
+    }
+    for (key, value, path) in values {
         println!(
-            "{}-------------------------------------------{}",
-            terminal_colors::BRIGHT_WHITE,
-            terminal_colors::RESET
+            "{} = {}  {}",
+            key,
+            value,
+            style::paint(style::DIM, &format!("# from {}", path.display()))
         );
     }
+}
 
-    // Print application banner
-    fn print_colored_banner(&self) {
+// Print candidate responses with their labels, usage and latency
+fn print_candidates(candidates: &[Candidate], show_reasoning: bool) {
+    for candidate in candidates {
+        if let Some(reasoning) = candidate.reasoning.as_ref().filter(|_| show_reasoning) {
+            print_reasoning(reasoning);
+        }
         println!(
-            "{}{}🤖 Sentinel AI Agent{}",
-            terminal_colors::BOLD,
-            terminal_colors::BRIGHT_BLUE,
-            terminal_colors::RESET
+            "\n{}{}",
+            style::paint(
+                style::BRIGHT_BLUE,
+                &format!("Candidate {}: ", candidate.label)
+            ),
+            candidate.text
         );
+
+        let mut details = format!(
+            "(Seed: {}, Input tokens: {}, Output tokens: {}, {:.1}s",
+            candidate.seed,
+            candidate.input_tokens,
+            candidate.output_tokens,
+            candidate.latency.as_secs_f64()
+        );
+        if !candidate.used_tools.is_empty() {
+            details.push_str(&format!(", Tools: {}", candidate.used_tools.join(", ")));
+        }
+        details.push(')');
+        print_human(&style::paint(style::YELLOW, &details));
+    }
+}
+
+// Print the replies of a /compare one after another, each under its label,
+// model, tokens and latency
+fn print_comparison(comparison: &[Comparison]) {
+    for side in comparison {
         println!(
-            "{}Model: {}{}",
-            terminal_colors::BRIGHT_WHITE,
-            self.model,
-            terminal_colors::RESET
+            "\n{} {}",
+            style::paint(style::BRIGHT_BLUE, &side.heading()),
+            style::paint(style::YELLOW, &format!("- {}", side.usage()))
         );
+        match &side.reply {
+            Ok(_) => println!("{}", side.text()),
+            Err(_) => println!("{}", style::paint(style::RED, &side.text())),
+        }
     }
+}
+
+// Print what a reasoning model thought, dimmed apart from the answer
+fn print_reasoning(reasoning: &str) {
+    print_human(&format!("\n{}", style::paint(style::DIM, "Reasoning:")));
+    for line in reasoning.lines() {
+        print_human(&format!("  {}", style::paint(style::DIM, line)));
+    }
+}
+
+// Separate a reasoning model's think blocks from its answer, counting their
+// tokens and showing them if asked to, and return the answer
+fn answer_of(text: &str, output_tokens: usize, stats: &mut SessionStats, show: bool) -> String {
+    let reply = reasoning::split(text);
+    stats.record_reasoning(reply.reasoning_tokens(output_tokens));
+    if let Some(reasoning) = reply.reasoning.as_ref().filter(|_| show) {
+        print_tagged(style::DIM, "[REASONING]", reasoning);
+    }
+    reply.content
+}
+
+// Print user prompt
+fn print_user_prompt(prompt: &str) {
+    print!("\n{}", style::paint(style::BRIGHT_GREEN, prompt));
+    std::io::stdout().flush().unwrap();
+}
+
+// Print error message
+fn print_error(message: &str) {
+    style::print_line(style::RED, &format!("Error: {}", message));
+}
+
+// Print general information
+fn print_info(message: &str) {
+    style::print_line(style::BRIGHT_WHITE, message);
+}
+
+// Print separator line
+fn print_divider() {
+    style::print_line(
+        style::BRIGHT_WHITE,
+        "-------------------------------------------",
+    );
+}
 
-    // Print help message
-    fn print_help(&self) {
+// Print application banner
+fn print_colored_banner(model: &str) {
+    println!(
+        "{}",
+        style::styler().paint_all(&[style::BOLD, style::BRIGHT_BLUE], "🤖 Sentinel AI Agent")
+    );
+    style::print_line(style::BRIGHT_WHITE, &format!("Model: {}", model));
+}
+
+// Print help message
+fn print_help(commands: &[CommandHelp]) {
+    style::print_line(style::BRIGHT_WHITE, "Available commands:");
+    for command in commands {
         println!(
-            "{}Available commands:{}",
-            terminal_colors::BRIGHT_WHITE,
-            terminal_colors::RESET
+            "  {}  - {}",
+            style::paint(style::CYAN, command.command),
+            command.description
         );
-        self.print_command("/exit", "Quit the application");
-        self.print_command("/clear", "Clear the conversation history");
-        self.print_command("/tools", "List available tools");
-        self.print_command("/help", "Show this help message");
     }
+    println!("Type /help <command> for more about one of them");
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Load environment variables from .env file if it exists
-    dotenv::dotenv().ok();
+// Load the images given with --image, checking the model can read them
+async fn load_images(
+    paths: &[String],
+    client: &dyn LlmClient,
+    model: &str,
+) -> Result<Vec<ImageAttachment>> {
+    let images = paths
+        .iter()
+        .map(|path| ImageAttachment::load(path))
+        .collect::<Result<Vec<_>>>()?;
+    if !images.is_empty() {
+        images::ensure_vision(client, model).await?;
+    }
+    Ok(images)
+}
 
-    let cli = Cli::parse();
+// The model a reply from `client` came from, which may not be the requested one
+fn reply_model(client: &dyn LlmClient, model: &str) -> String {
+    client.model_name().unwrap_or_else(|| model.to_string())
+}
 
-    match cli.command {
-        Some(command) => match command {
-            Commands::Ask {
-                message,
-                model,
-                tools,
-            } => {
-                let client = OllamaClient::new().with_model(&model);
-                let prompt = message.join(" ");
-
-                let user_message = Message {
-                    role: Role::User,
-                    content: prompt,
-                    input_tokens: 0,
-                    output_tokens: 0,
-                    used_tools: Vec::new(),
-                };
+// Print the assistant's reply with the model and provider that produced it
+fn print_reply(client: &dyn LlmClient, model: &str, text: &str) {
+    let mut tag = style::paint(style::BRIGHT_GREEN, "[ASSISTANT]");
+    if let Some(source) = sentinel::source_label(&reply_model(client, model), client.provider()) {
+        tag = format!(
+            "{} {}",
+            tag,
+            style::paint(style::DIM, &format!("({})", source))
+        );
+    }
+    if style::stderr_only() {
+        // The answer alone goes to stdout, for scripts to capture
+        print_human(&format!("\n{}", tag));
+        println!("{}", text);
+    } else {
+        println!("\n{} {}", tag, text);
+    }
+}
 
-                // Print user message with colored formatting
-                println!(
-                    "\n{}[USER]{} {}",
-                    terminal_colors::CYAN,
-                    terminal_colors::RESET,
-                    user_message.content
-                );
+// Say so if the reply just printed was cut off by the token limit
+// "bash: 2.4s, file read: 12ms", with the calls that took `slow` or longer in red
+fn tool_times(timings: &[ToolTiming], slow: Duration) -> String {
+    timings
+        .iter()
+        .map(|timing| {
+            if timing.is_slow(slow) {
+                style::paint(style::RED, &format!("{} (slow)", timing))
+            } else {
+                timing.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
 
-                if tools {
-                    println!(
-                        "\n{}[SENTINEL]{} Using Ollama with tools enabled...",
-                        terminal_colors::MAGENTA,
-                        terminal_colors::RESET
-                    );
+// Follow a token line with where the server's time went, when loading the
+// model took a notable part of it
+fn with_load_note(mut line: String, timings: Option<ServerTimings>) -> String {
+    if let Some(note) = timings.and_then(|timings| timings.load_note()) {
+        line.push(' ');
+        line.push_str(&note);
+    }
+    line
+}
 
-                    let (text, input_tokens, output_tokens, used_tools) = client
-                        .generate_response_with_tools(&[user_message], &[])
-                        .await?;
-
-                    // Print summary of tool usage
-                    if !used_tools.is_empty() {
-                        println!(
-                            "\n{}[TOOL SUMMARY]{} Tools used in this response:",
-                            terminal_colors::YELLOW,
-                            terminal_colors::RESET
-                        );
-
-                        for tool in used_tools {
-                            println!("  - {}", tool);
-                        }
-                    }
+fn print_truncated(client: &dyn LlmClient) {
+    if client.take_truncated() {
+        print_human(&style::paint(style::YELLOW, limits::TRUNCATED_NOTE));
+    }
+}
 
-                    println!(
-                        "\n{}[ASSISTANT]{} {}",
-                        terminal_colors::BRIGHT_GREEN,
-                        terminal_colors::RESET,
-                        text
-                    );
+// Print a line prefixed with a colored tag like "[ASSISTANT]"
+fn print_tagged(color: &str, tag: &str, text: &str) {
+    print_human(&format!("\n{} {}", style::paint(color, tag), text));
+}
 
-                    println!(
-                        "\n{}[INFO]{} Tokens: {} input, {} output",
-                        terminal_colors::BRIGHT_WHITE,
-                        terminal_colors::RESET,
-                        input_tokens,
-                        output_tokens
-                    );
-                } else {
-                    println!(
-                        "\n{}[SENTINEL]{} Using Ollama without tools...",
-                        terminal_colors::MAGENTA,
-                        terminal_colors::RESET
-                    );
+// Print a line meant for people, which `ask` keeps off stdout
+fn print_human(line: &str) {
+    let _ = writeln!(style::human_output(), "{}", line);
+}
+
+// Set up a config file on the first run of a chat, unless told not to
+async fn run_wizard(cli: &Cli) -> Result<()> {
+    let chat = matches!(cli.command, None | Some(Commands::Chat(_)));
+    let scripted = cli.replay.is_some() || std::env::var_os(failover::FAKE_LLM_VAR).is_some();
+    if !chat || cli.no_wizard || scripted {
+        return Ok(());
+    }
+    let interactive = std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+    let global = sentinel::config::default_path();
+    let project = std::env::current_dir()
+        .ok()
+        .and_then(|dir| sentinel::config::find_project_file(&dir));
+    if !wizard::needed(global.as_deref(), project.as_deref(), interactive) {
+        return Ok(());
+    }
+    let path = global.expect("needed only with a config path");
+    let setup = wizard::Setup::new(Endpoint::from_env().unwrap_or_default());
+    wizard::run(
+        &mut console::Terminal::new(),
+        &wizard::OllamaProbe,
+        &path,
+        setup,
+    )
+    .await?;
+    println!();
+    Ok(())
+}
 
-                    let (text, input_tokens, output_tokens) =
-                        client.generate_response(&[user_message]).await?;
+#[tokio::main]
+async fn main() -> Result<()> {
+    // A custom tool's command may not start sentinel again
+    if let Ok(tool) = std::env::var(custom::RECURSION_ENV) {
+        anyhow::bail!(
+            "Sentinel was started by the custom tool '{}'; custom tools may not run sentinel",
+            tool
+        );
+    }
 
-                    println!(
-                        "\n{}[ASSISTANT]{} {}",
-                        terminal_colors::BRIGHT_GREEN,
-                        terminal_colors::RESET,
-                        text
-                    );
+    // Load environment variables from .env file if it exists
+    dotenv::dotenv().ok();
 
-                    println!(
-                        "\n{}[INFO]{} Tokens: {} input, {} output",
-                        terminal_colors::BRIGHT_WHITE,
-                        terminal_colors::RESET,
-                        input_tokens,
-                        output_tokens
+    let mut cli = Cli::parse();
+    style::init(cli.no_color);
+    run_wizard(&cli).await?;
+
+    match cli.command.take() {
+        Some(command) => match command {
+            Commands::Chat(chat) => run_chat(chat, &cli).await?,
+            Commands::Ask(ask) => {
+                if let Err(failure) = run_ask(ask, &cli).await {
+                    eprintln!(
+                        "{}",
+                        style::paint(style::RED, &format!("Error: {}", failure))
                     );
+                    std::process::exit(failure.exit_code());
+                }
+            }
+            Commands::Do { task, model, yes } => {
+                run_do(&task.join(" "), model.as_deref(), yes, &cli).await?;
+            }
+            Commands::Tool {
+                name,
+                params,
+                json,
+                list,
+                schema,
+            } => {
+                let mut tools = load_tools(&cli)?;
+                match name {
+                    Some(name) if schema => print_tool_schema(&tools, &name)?,
+                    Some(name) if !list => {
+                        run_tool(&mut tools, &name, &params, json.as_deref()).await?
+                    }
+                    _ => print_tool_list(&tools),
+                }
+            }
+            Commands::Batch {
+                prompts,
+                model,
+                out,
+                jobs,
+                resume,
+            } => {
+                let options = BatchOptions {
+                    jobs: jobs as usize,
+                    resume,
+                    system: None,
                 };
+                run_batch(&prompts, model.as_deref(), &out, options, &cli).await?
             }
+            Commands::Templates => print_templates(&templates::list()?),
+            Commands::Diff { journal } => print_saved_diff(journal)?,
+            Commands::Sessions {
+                command:
+                    SessionsCommand::Prune {
+                        sessions,
+                        older_than,
+                        larger_than,
+                        dry_run,
+                    },
+            } => {
+                let rules = PruneRules {
+                    older_than: older_than.map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+                    larger_than: larger_than.map(|kb| kb * 1024),
+                };
+                prune_sessions(sessions, &rules, dry_run)?
+            }
+            Commands::Tools { export } => {
+                let tools = load_tools(&cli)?;
+                export_tools(&tools, export.as_deref())?;
+            }
+            Commands::Models => {
+                let config = Config::load()?;
+                print_warnings(&config.warnings);
+                let client =
+                    FailoverClient::connect(&config, &config.model.name(None), None).await?;
+                print_models(&client).await?;
+            }
+            Commands::Config { show: true, .. } => print_config(&Config::load_layered()?),
             Commands::Config { .. } => {
                 println!(
-                    "{}[SENTINEL]{} Configuration not yet implemented",
-                    terminal_colors::MAGENTA,
-                    terminal_colors::RESET
+                    "{} Configuration not yet implemented",
+                    style::paint(style::MAGENTA, "[SENTINEL]")
                 );
             }
         },
-        None => {
-            // Create and start the agent
-            let mut agent = Agent::new("llama3.2:latest");
-            agent.start().await?;
-        }
+        // The bare command is `sentinel chat` with its defaults
+        None => run_chat(ChatArgs::default(), &cli).await?,
     }
 
     Ok(())
@@ -0,0 +1,164 @@
+use std::io::{self, IsTerminal, Write};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// How to tell the user a generation finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channels {
+    /// A terminal bell
+    Bell,
+    /// A desktop notification
+    Desktop,
+    #[default]
+    Both,
+}
+
+impl Channels {
+    fn bell(self) -> bool {
+        matches!(self, Self::Bell | Self::Both)
+    }
+
+    fn desktop(self) -> bool {
+        matches!(self, Self::Desktop | Self::Both)
+    }
+}
+
+/// The `[notify]` config table
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NotifyConfig {
+    pub enabled: bool,
+    /// Seconds a generation has to take before it is worth a notification
+    pub threshold: u64,
+    pub channels: Channels,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold: 10,
+            channels: Channels::Both,
+        }
+    }
+}
+
+/// Tells the user when a slow generation finishes, so they can look away meanwhile
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    enabled: bool,
+    threshold: Duration,
+    channels: Channels,
+}
+
+impl Default for Notifier {
+    fn default() -> Self {
+        Self::from_config(&NotifyConfig::default())
+    }
+}
+
+impl Notifier {
+    pub fn from_config(config: &NotifyConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            threshold: Duration::from_secs(config.threshold),
+            channels: config.channels,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Whether a generation that took `elapsed` is worth a notification
+    pub fn should_notify(&self, elapsed: Duration) -> bool {
+        self.enabled && elapsed >= self.threshold
+    }
+
+    /// Notify about a finished generation if it took long enough, returning whether it did.
+    ///
+    /// Never blocks: the desktop notification is sent from a separate thread and
+    /// any failure, e.g. no notification daemon running, is ignored.
+    pub fn finished(&self, elapsed: Duration, answer: &str) -> bool {
+        if !self.should_notify(elapsed) {
+            return false;
+        }
+
+        // The bell goes to stderr, stdout may be a pipe or owned by the TUI
+        if self.channels.bell() && io::stderr().is_terminal() {
+            let mut stderr = io::stderr();
+            let _ = stderr.write_all(b"\x07");
+            let _ = stderr.flush();
+        }
+
+        if self.channels.desktop() {
+            let body = summary(answer);
+            std::thread::spawn(move || {
+                let _ = notify_rust::Notification::new()
+                    .summary("Sentinel finished responding")
+                    .body(&body)
+                    .show();
+            });
+        }
+
+        true
+    }
+}
+
+// The first non-empty line of the answer, shortened for a notification
+fn summary(answer: &str) -> String {
+    const MAX_CHARS: usize = 120;
+
+    let line = answer
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or("");
+    if line.chars().count() > MAX_CHARS {
+        let short: String = line.chars().take(MAX_CHARS).collect();
+        format!("{}…", short)
+    } else {
+        line.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threshold() {
+        let mut notifier = Notifier::from_config(&NotifyConfig {
+            enabled: true,
+            threshold: 10,
+            channels: Channels::Bell,
+        });
+        assert!(!notifier.should_notify(Duration::from_secs(3)));
+        assert!(notifier.should_notify(Duration::from_secs(12)));
+
+        notifier.set_enabled(false);
+        assert!(!notifier.finished(Duration::from_secs(12), "done"));
+    }
+
+    #[test]
+    fn test_summary_is_the_first_line() {
+        assert_eq!(summary("\n  Done.\nMore details"), "Done.");
+        assert_eq!(summary(&"x".repeat(200)).chars().count(), 121);
+        assert_eq!(summary(""), "");
+    }
+
+    #[test]
+    fn test_config() -> anyhow::Result<()> {
+        let config: NotifyConfig = toml::from_str("enabled = true\nchannels = \"bell\"")?;
+        assert!(config.enabled);
+        assert_eq!(config.threshold, 10);
+        assert_eq!(config.channels, Channels::Bell);
+        Ok(())
+    }
+}
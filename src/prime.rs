@@ -0,0 +1,275 @@
+//! A bounded snapshot of the project Sentinel was started in, given to the
+//! model as a system message so it doesn't start out knowing nothing.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::privacy;
+use crate::stats;
+use crate::tools::bash::{Bash, BashParams};
+use crate::tools::ls::LsTool;
+use crate::truncate::{self, Strategy};
+use crate::Message;
+
+/// First line of every primer, which is how a primer message is recognized
+pub const HEADER: &str = "Project context for the current directory:";
+
+// Size of the whole primer, and of any one section of it
+const TOTAL_BUDGET: usize = 12_000;
+const SECTION_BUDGET: usize = 4_000;
+
+// Files included when present, in order
+const PROJECT_FILES: [&str; 5] = [
+    "README.md",
+    "README",
+    "readme.md",
+    "Cargo.toml",
+    "package.json",
+];
+
+// Paths whose modification times decide whether a cached primer is stale
+const WATCHED: [&str; 7] = [
+    ".",
+    "README.md",
+    "README",
+    "readme.md",
+    "Cargo.toml",
+    "package.json",
+    ".git/index",
+];
+
+/// Project context ready to be put at the start of a conversation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Primer {
+    pub content: String,
+    pub tokens: usize,
+    /// Whether it came from the cache instead of being gathered again
+    #[serde(skip)]
+    pub cached: bool,
+}
+
+impl Primer {
+    /// The system message giving the model the project context
+    pub fn message(&self) -> Message {
+        Message::system(self.content.clone())
+            .with_local_sources(vec![privacy::PROJECT_CONTEXT.to_string()])
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    directory: PathBuf,
+    fingerprint: Vec<(String, u128)>,
+    primer: Primer,
+}
+
+/// Whether priming was asked for with `SENTINEL_PRIME` (e.g. `SENTINEL_PRIME=1`)
+pub fn enabled_in_env() -> bool {
+    std::env::var("SENTINEL_PRIME")
+        .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes" | "on"))
+        .unwrap_or(false)
+}
+
+/// The primer for `directory`, from the cache if nothing it was built from has changed
+pub async fn prime(directory: &Path) -> Result<Primer> {
+    let directory = directory
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve {}", directory.display()))?;
+    let cache = cache_path(&directory);
+    let fingerprint = fingerprint(&directory);
+
+    if let Some(primer) = cache.as_deref().and_then(|path| cached(path, &fingerprint)) {
+        return Ok(primer);
+    }
+
+    let primer = gather(&directory).await;
+
+    if let Some(path) = cache {
+        let entry = CacheEntry {
+            directory,
+            fingerprint,
+            primer: primer.clone(),
+        };
+        // A cache that can't be written only makes the next start slower
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if let Ok(json) = serde_json::to_string(&entry) {
+            let _ = fs::write(path, json);
+        }
+    }
+
+    Ok(primer)
+}
+
+/// Build the primer for `directory` without looking at the cache
+pub async fn gather(directory: &Path) -> Primer {
+    let mut sections = Vec::new();
+
+    let path = directory.to_string_lossy().to_string();
+    if let Ok(listing) = LsTool::new().list(&path, None).await {
+        // The listing repeats itself as a tree; the tree alone is enough
+        let tree = listing
+            .content
+            .split_once("Tree View:\n")
+            .map(|(_, tree)| tree.to_string())
+            .unwrap_or(listing.content);
+        sections.push(("Files".to_string(), tree));
+    }
+
+    for name in PROJECT_FILES {
+        if let Ok(content) = fs::read_to_string(directory.join(name)) {
+            sections.push((name.to_string(), content));
+        }
+    }
+
+    let mut bash = Bash::new();
+    let status = bash
+        .run(BashParams::new(format!(
+            "git -C '{}' status --short --branch",
+            path.replace('\'', r"'\''")
+        )))
+        .await;
+    if let Ok(output) = status {
+        if !output.content.contains("Exit code:") && !output.content.trim().is_empty() {
+            sections.push(("git status".to_string(), output.content));
+        }
+    }
+
+    let content = assemble(&sections);
+    Primer {
+        tokens: stats::estimate_tokens(&content),
+        content,
+        cached: false,
+    }
+}
+
+// Join the sections under the size budgets, shortening long ones
+fn assemble(sections: &[(String, String)]) -> String {
+    let mut content = format!("{}\n", HEADER);
+
+    for (title, body) in sections {
+        let remaining = TOTAL_BUDGET.saturating_sub(content.len());
+        if remaining < 200 {
+            content.push_str("\n[Further project files omitted]\n");
+            break;
+        }
+
+        let body = truncate::truncate(
+            body.trim(),
+            SECTION_BUDGET.min(remaining - 100),
+            Strategy::Middle,
+        );
+        content.push_str(&format!("\n## {}\n{}\n", title, body));
+    }
+
+    content
+}
+
+// Modification times of the files a primer is built from
+fn fingerprint(directory: &Path) -> Vec<(String, u128)> {
+    WATCHED
+        .iter()
+        .filter_map(|name| {
+            let modified = fs::metadata(directory.join(name)).ok()?.modified().ok()?;
+            let nanos = modified.duration_since(UNIX_EPOCH).ok()?.as_nanos();
+            Some((name.to_string(), nanos))
+        })
+        .collect()
+}
+
+fn cached(path: &Path, fingerprint: &[(String, u128)]) -> Option<Primer> {
+    let entry: CacheEntry = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+    (entry.fingerprint == fingerprint).then_some(Primer {
+        cached: true,
+        ..entry.primer
+    })
+}
+
+// `$XDG_CACHE_HOME/sentinel/prime/<hash>.json`, or under `~/.cache`
+fn cache_path(directory: &Path) -> Option<PathBuf> {
+    let cache_home = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+
+    let mut hasher = DefaultHasher::new();
+    directory.hash(&mut hasher);
+    Some(
+        cache_home
+            .join("sentinel")
+            .join("prime")
+            .join(format!("{:016x}.json", hasher.finish())),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_gather_includes_project_files() -> Result<()> {
+        let dir = TempDir::new()?;
+        fs::write(dir.path().join("README.md"), "# Demo\nA demo project")?;
+        fs::write(dir.path().join("Cargo.toml"), "[package]\nname = \"demo\"")?;
+
+        let primer = gather(dir.path()).await;
+
+        assert!(primer.content.starts_with(HEADER));
+        assert!(primer.content.contains("## README.md\n# Demo"));
+        assert!(primer.content.contains("name = \"demo\""));
+        assert!(!primer.content.contains("package.json"));
+        assert!(!primer.content.contains("## git status"));
+        assert_eq!(primer.tokens, primer.content.len().div_ceil(4));
+        Ok(())
+    }
+
+    #[test]
+    fn test_assemble_respects_budgets() {
+        let long = "line\n".repeat(5_000);
+        let sections: Vec<(String, String)> = (0..5)
+            .map(|i| (format!("file {}", i), long.clone()))
+            .collect();
+
+        let content = assemble(&sections);
+        assert!(content.len() <= TOTAL_BUDGET);
+        assert!(content.contains("lines truncated"));
+        assert!(content.contains("[Further project files omitted]"));
+    }
+
+    #[test]
+    fn test_cache_is_invalidated_by_changes() -> Result<()> {
+        let dir = TempDir::new()?;
+        let readme = dir.path().join("README.md");
+        fs::write(&readme, "v1")?;
+        let before = fingerprint(dir.path());
+
+        let cache = dir.path().join("cache.json");
+        let primer = Primer {
+            content: "cached".to_string(),
+            tokens: 2,
+            cached: false,
+        };
+        fs::write(
+            &cache,
+            serde_json::to_string(&CacheEntry {
+                directory: dir.path().to_path_buf(),
+                fingerprint: before.clone(),
+                primer,
+            })?,
+        )?;
+        assert!(cached(&cache, &before).is_some_and(|primer| primer.cached));
+
+        let file = fs::File::options().write(true).open(&readme)?;
+        file.set_modified(UNIX_EPOCH + std::time::Duration::from_secs(1))?;
+        assert!(cached(&cache, &fingerprint(dir.path())).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+//! Asking before local content is sent to a server on another machine.
+//!
+//! Messages remember where content read from this machine came from: the
+//! files attached to them, the commands whose output they report and the
+//! project context. Tool output is local too, unless it came from the web.
+//! The first time any of it would go to a remote server, the user is asked
+//! once for the session, and again whenever requests move to another server.
+
+use std::sync::Mutex;
+
+use serde::Deserialize;
+
+use crate::tools::result::ToolError;
+use crate::tools::review::{Decision, Reviewer};
+use crate::Message;
+
+/// Where the content of a primer came from
+pub const PROJECT_CONTEXT: &str = "the project context";
+
+/// Where the output of running `command` came from
+pub fn command_output(command: &str) -> String {
+    format!("the output of `{}`", command)
+}
+
+/// Where the output of the local tool `tool` came from
+pub fn tool_output(tool: &str) -> String {
+    format!("the output of the {} tool", tool)
+}
+
+/// The `[privacy]` config table
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct PrivacyConfig {
+    /// Send local content to remote servers without asking
+    pub allow_remote_content: bool,
+}
+
+/// Whether `endpoint`, e.g. "http://gpu-box:11434", is on another machine
+pub fn is_remote(endpoint: &str) -> bool {
+    let address = endpoint
+        .split_once("://")
+        .map_or(endpoint, |(_, address)| address);
+    let address = address.split('/').next().unwrap_or_default();
+    let host = if let Some(bracketed) = address.strip_prefix('[') {
+        bracketed.split(']').next().unwrap_or_default()
+    } else if address.matches(':').count() > 1 {
+        // A bare IPv6 address has no port to take off
+        address
+    } else {
+        address.split(':').next().unwrap_or_default()
+    };
+    let host = host.to_lowercase();
+    !(host.is_empty()
+        || host == "localhost"
+        || host.ends_with(".localhost")
+        || host == "::1"
+        || host == "0.0.0.0"
+        || host.starts_with("127."))
+}
+
+/// Where the local content of `messages` came from, each once, in order
+pub fn local_sources(messages: &[Message]) -> Vec<String> {
+    let mut sources: Vec<String> = Vec::new();
+    for source in messages.iter().flat_map(|message| &message.local_sources) {
+        if !sources.contains(source) {
+            sources.push(source.clone());
+        }
+    }
+    sources
+}
+
+/// Whether local content may go to the server requests go to right now.
+///
+/// Shared between the policies of a session, like the journal, so the user
+/// agrees once whichever of them sends the content.
+#[derive(Debug, Default)]
+pub struct RemoteGate {
+    state: Mutex<GateState>,
+}
+
+#[derive(Debug, Default)]
+struct GateState {
+    allow: bool,
+    endpoint: Option<String>,
+    // The remote server the user agreed to send local content to
+    confirmed: Option<String>,
+}
+
+impl RemoteGate {
+    pub fn new(config: &PrivacyConfig) -> Self {
+        let gate = Self::default();
+        gate.set_allowed(config.allow_remote_content);
+        gate
+    }
+
+    pub fn set_allowed(&self, allow: bool) {
+        self.state.lock().unwrap().allow = allow;
+    }
+
+    /// Follow requests to `endpoint`; `None` when the client doesn't say
+    pub fn set_endpoint(&self, endpoint: Option<String>) {
+        self.state.lock().unwrap().endpoint = endpoint;
+    }
+
+    /// The server requests go to, if it is on another machine
+    pub fn remote(&self) -> Option<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .endpoint
+            .clone()
+            .filter(|endpoint| is_remote(endpoint))
+    }
+
+    /// The remote server the user has to agree to before content from
+    /// `sources` is sent to it, if there is one
+    pub fn pending(&self, sources: &[String]) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        if state.allow || sources.is_empty() {
+            return None;
+        }
+        let remote = state
+            .endpoint
+            .as_deref()
+            .filter(|endpoint| is_remote(endpoint))?;
+        (state.confirmed.as_deref() != Some(remote)).then(|| remote.to_string())
+    }
+
+    /// Put sending content from `sources` to a remote server to `reviewer`,
+    /// once per server, refusing it without one
+    pub fn check(
+        &self,
+        sources: &[String],
+        reviewer: Option<&dyn Reviewer>,
+    ) -> Result<(), ToolError> {
+        let Some(endpoint) = self.pending(sources) else {
+            return Ok(());
+        };
+        let action = format!("send {} to {}", sources.join(", "), endpoint);
+        let Some(reviewer) = reviewer else {
+            return Err(ToolError::Unsafe(format!(
+                "Not sent: {} is not on this machine, and nobody is there to confirm sending {} to it. \
+                 Set privacy.allow_remote_content = true to send local content to remote servers",
+                endpoint,
+                sources.join(", ")
+            )));
+        };
+        let reason = format!(
+            "{} is not on this machine; local content is only sent there once you agree",
+            endpoint
+        );
+        match reviewer.confirm_command(&action, &reason) {
+            Decision::Approve | Decision::ApproveAll => {
+                self.state.lock().unwrap().confirmed = Some(endpoint);
+                Ok(())
+            }
+            Decision::Reject { reason } => Err(ToolError::Unsafe(format!(
+                "The user declined to {}. Reason: {}",
+                action,
+                reason.as_deref().unwrap_or("none given")
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::review::ProposedChange;
+
+    // Answers every question the same way, keeping what it was asked
+    struct Answering {
+        decision: Decision,
+        asked: Mutex<Vec<String>>,
+    }
+
+    impl Reviewer for Answering {
+        fn review(&self, _change: &ProposedChange) -> Decision {
+            self.decision.clone()
+        }
+
+        fn confirm_command(&self, command: &str, _reason: &str) -> Decision {
+            self.asked.lock().unwrap().push(command.to_string());
+            self.decision.clone()
+        }
+    }
+
+    #[test]
+    fn test_remote_endpoints() {
+        assert!(!is_remote("http://localhost:11434"));
+        assert!(!is_remote("http://127.0.0.1:11434"));
+        assert!(!is_remote("http://[::1]:11434"));
+        assert!(!is_remote("localhost"));
+        assert!(is_remote("http://gpu-box:11434"));
+        assert!(is_remote("https://ollama.example.com"));
+        assert!(is_remote("10.0.0.7:11434"));
+    }
+
+    #[test]
+    fn test_sources_are_listed_once() {
+        let messages = [
+            Message::system("Project context").with_local_sources(vec![PROJECT_CONTEXT.into()]),
+            Message::user("Fix this").with_local_sources(vec!["src/main.rs".into()]),
+            Message::assistant("Done".to_string(), 0, 0, Vec::new()),
+            Message::user("And this")
+                .with_local_sources(vec!["src/main.rs".into(), "src/lib.rs".into()]),
+        ];
+        assert_eq!(
+            local_sources(&messages),
+            ["the project context", "src/main.rs", "src/lib.rs"]
+        );
+    }
+
+    #[test]
+    fn test_only_local_content_for_remote_servers_is_held_back() {
+        let sources = vec!["src/main.rs".to_string()];
+        let gate = RemoteGate::default();
+        assert_eq!(gate.pending(&sources), None);
+
+        gate.set_endpoint(Some("http://localhost:11434".to_string()));
+        assert_eq!(gate.remote(), None);
+        assert!(gate.check(&sources, None).is_ok());
+
+        gate.set_endpoint(Some("http://gpu-box:11434".to_string()));
+        assert_eq!(gate.remote().as_deref(), Some("http://gpu-box:11434"));
+        assert!(gate.check(&[], None).is_ok());
+        let error = gate.check(&sources, None).unwrap_err();
+        assert!(error.to_string().contains("privacy.allow_remote_content"));
+
+        let allowed = RemoteGate::new(&PrivacyConfig {
+            allow_remote_content: true,
+        });
+        allowed.set_endpoint(Some("http://gpu-box:11434".to_string()));
+        assert!(allowed.check(&sources, None).is_ok());
+    }
+
+    #[test]
+    fn test_each_remote_server_is_confirmed_once() {
+        let sources = vec!["src/main.rs".to_string(), command_output("ls")];
+        let reviewer = Answering {
+            decision: Decision::Approve,
+            asked: Mutex::new(Vec::new()),
+        };
+        let gate = RemoteGate::default();
+        gate.set_endpoint(Some("http://gpu-box:11434".to_string()));
+
+        gate.check(&sources, Some(&reviewer)).unwrap();
+        gate.check(&sources, Some(&reviewer)).unwrap();
+        assert_eq!(
+            *reviewer.asked.lock().unwrap(),
+            ["send src/main.rs, the output of `ls` to http://gpu-box:11434"]
+        );
+
+        // Moving to another server asks again, and so does coming back
+        gate.set_endpoint(Some("http://other:11434".to_string()));
+        assert_eq!(
+            gate.pending(&sources).as_deref(),
+            Some("http://other:11434")
+        );
+        gate.check(&sources, Some(&reviewer)).unwrap();
+        gate.set_endpoint(Some("http://gpu-box:11434".to_string()));
+        assert_eq!(
+            gate.pending(&sources).as_deref(),
+            Some("http://gpu-box:11434")
+        );
+    }
+
+    #[test]
+    fn test_sending_is_refused_when_the_user_declines() {
+        let sources = vec!["notes.md".to_string()];
+        let reviewer = Answering {
+            decision: Decision::Reject {
+                reason: Some("secret".to_string()),
+            },
+            asked: Mutex::new(Vec::new()),
+        };
+        let gate = RemoteGate::default();
+        gate.set_endpoint(Some("http://gpu-box:11434".to_string()));
+        let error = gate.check(&sources, Some(&reviewer)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The user declined to send notes.md to http://gpu-box:11434. Reason: secret"
+        );
+        // Nothing was agreed to, so the next request asks again
+        assert!(gate.check(&sources, Some(&reviewer)).is_err());
+        assert_eq!(reviewer.asked.lock().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,1231 @@
+//! Recording a session to a file and replaying it, for demos and bug reports.
+//!
+//! `--record <path>` writes every request to the model with its response,
+//! and every tool run for the model, to a JSON Lines file as the session
+//! goes. `--replay <path>` answers from such a file instead: nothing is sent
+//! to Ollama and no tool runs, so the conversation can be gone through again
+//! on another machine. Requests and tool calls are matched to the first
+//! recorded one that is the same and not yet replayed; one that was never
+//! recorded fails with a diff against the one the recording expected next.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use similar::TextDiff;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::config::Config;
+use crate::llm::limits::ResponseLimits;
+use crate::llm::ollama::{GenerationOptions, LlmClient, ModelTurn, Tool, ToolCall, ToolInvocation};
+use crate::llm::prefix::PromptUsage;
+use crate::llm::timings::ServerTimings;
+use crate::memory::Memory;
+use crate::privacy::RemoteGate;
+use crate::session_file::{self, StoredMessage, StoredRole};
+use crate::tools::journal::Journal;
+use crate::tools::progress::{Progress, ToolEvent};
+use crate::tools::registry::CallableTool;
+use crate::tools::result::{self, ToolError};
+use crate::tools::review::Reviewer;
+use crate::Message;
+
+/// The version of the file format written by this build, checked as session
+/// files are.
+///
+/// - Format 1 kept messages as the agent holds them, without the usage of replies.
+/// - Format 2 keeps them as session files do, as [`StoredMessage`]s.
+pub const VERSION: u32 = 2;
+
+/// One line of a recording
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Entry {
+    /// What was recorded; always the first line
+    Session(Header),
+    /// A request to the model and what came back
+    Exchange(Exchange),
+    /// A tool run for the model
+    Tool(ToolRecord),
+}
+
+/// The client a session was recorded with
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    pub version: u32,
+    pub model: String,
+    /// The model replies came from, when the client knew better than `model`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_name: Option<String>,
+    pub provider: String,
+    /// Whether the client handed tool calls back to be run by the agent,
+    /// rather than running its own tools
+    pub tool_calls: bool,
+    /// The tools the client offered the model
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<String>,
+}
+
+impl Header {
+    /// The header of a session with `client` for `model`
+    pub fn of(client: &dyn LlmClient, model: &str) -> Self {
+        Self {
+            version: VERSION,
+            model: model.to_string(),
+            model_name: client.model_name(),
+            provider: client.provider().to_string(),
+            tool_calls: client.supports_tool_calls(),
+            tools: client.available_tools(),
+        }
+    }
+}
+
+/// The client method a request was made with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Call {
+    /// An answer without tools
+    Generate,
+    /// An answer for which the client ran its own tools
+    GenerateWithTools,
+    /// One step of a conversation in which the agent runs the tools
+    ChatStep,
+}
+
+/// A request to the model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    pub call: Call,
+    pub model: String,
+    pub messages: Vec<StoredMessage>,
+    /// The names of the tools offered with the request
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i32>,
+}
+
+impl Request {
+    fn new(call: Call, model: &str, messages: &[Message]) -> Self {
+        Self {
+            call,
+            model: model.to_string(),
+            messages: messages.iter().map(StoredMessage::from).collect(),
+            tools: Vec::new(),
+            seed: None,
+        }
+    }
+
+    fn with_tools(mut self, tools: &[Tool]) -> Self {
+        self.tools = tools.iter().map(|tool| tool.name.clone()).collect();
+        self
+    }
+
+    fn with_options(mut self, options: &GenerationOptions) -> Self {
+        self.seed = options.seed;
+        self
+    }
+
+    // The request as JSON, both to compare it and to show it in a diff, with
+    // only what a recording of format `version` kept
+    fn to_json(&self, version: u32) -> Value {
+        let mut request = self.clone();
+        if version < 2 {
+            for message in &mut request.messages {
+                message.input_tokens = 0;
+                message.tool_output_tokens = 0;
+                message.output_tokens = 0;
+                message.used_tools.clear();
+                message.reasoning = None;
+            }
+        }
+        serde_json::to_value(request).unwrap_or(Value::Null)
+    }
+
+    // The last prompt of the user in the request
+    fn prompt(&self) -> Option<&str> {
+        self.messages
+            .iter()
+            .rev()
+            .find(|message| message.role == StoredRole::User)
+            .map(|message| message.content.as_str())
+    }
+}
+
+/// What the model answered a request with
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Response {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub text: String,
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub used_tools: Vec<String>,
+    /// The tools a chat step asked for
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// Whether the response used up the token limit
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// Why the request failed, instead of the rest
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A request and its response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exchange {
+    pub request: Request,
+    pub response: Response,
+}
+
+/// A tool run for the model: what it was given, what it returned and how
+/// long it took
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolRecord {
+    pub name: String,
+    pub arguments: Value,
+    pub result: ToolResult,
+    pub duration_ms: u64,
+}
+
+impl ToolRecord {
+    fn of(invocation: &ToolInvocation) -> Self {
+        Self {
+            name: invocation.name.clone(),
+            arguments: invocation.arguments.clone(),
+            result: ToolResult::Output(invocation.output.clone()),
+            duration_ms: invocation.duration.as_millis() as u64,
+        }
+    }
+
+    fn invocation(&self) -> ToolInvocation {
+        let output = match &self.result {
+            ToolResult::Output(output) => output.clone(),
+            ToolResult::NotFound(error)
+            | ToolResult::InvalidParams(error)
+            | ToolResult::Failed(error)
+            | ToolResult::Panicked(error) => format!("Error: {}", error),
+        };
+        ToolInvocation {
+            name: self.name.clone(),
+            arguments: self.arguments.clone(),
+            output,
+            summary: None,
+            duration: Duration::from_millis(self.duration_ms),
+        }
+    }
+
+    // The call as JSON, to show in a diff
+    fn call_json(name: &str, arguments: &Value) -> Value {
+        serde_json::json!({ "name": name, "arguments": arguments })
+    }
+}
+
+/// What a tool returned, with the errors the model is told about kept apart
+/// from those that end the turn
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolResult {
+    Output(String),
+    NotFound(String),
+    InvalidParams(String),
+    Failed(String),
+    Panicked(String),
+}
+
+impl ToolResult {
+    fn of(result: &Result<String, ToolError>) -> Self {
+        match result {
+            Ok(output) => Self::Output(output.clone()),
+            Err(ToolError::NotFound(error)) => Self::NotFound(error.clone()),
+            Err(ToolError::InvalidParams(error)) => Self::InvalidParams(error.clone()),
+            Err(ToolError::Panicked(error)) => Self::Panicked(error.clone()),
+            Err(error) => Self::Failed(error.to_string()),
+        }
+    }
+
+    fn into_result(self) -> Result<String, ToolError> {
+        match self {
+            Self::Output(output) => Ok(output),
+            Self::NotFound(error) => Err(ToolError::NotFound(error)),
+            Self::InvalidParams(error) => Err(ToolError::InvalidParams(error)),
+            Self::Failed(error) => Err(ToolError::Failed(error)),
+            Self::Panicked(error) => Err(ToolError::Panicked(error)),
+        }
+    }
+}
+
+/// A request or tool call of a replay that isn't in the recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diverged {
+    /// "request" or "tool call"
+    pub what: &'static str,
+    /// The next one of the recording not replayed yet, as JSON, if any is left
+    pub expected: Option<String>,
+    /// The one that was made, as JSON
+    pub actual: String,
+}
+
+impl fmt::Display for Diverged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.expected {
+            Some(expected) => {
+                let diff = TextDiff::from_lines(expected.as_str(), self.actual.as_str());
+                write!(
+                    f,
+                    "The replay diverged from the recording: this {} was not recorded\n{}",
+                    self.what,
+                    diff.unified_diff().header("recorded", "replayed")
+                )
+            }
+            None => write!(
+                f,
+                "The replay diverged from the recording: it has no {} left for\n{}",
+                self.what, self.actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Diverged {}
+
+// JSON spread over lines, so a diff shows what changed
+fn pretty(value: &Value) -> String {
+    let mut text = serde_json::to_string_pretty(value).unwrap_or_default();
+    text.push('\n');
+    text
+}
+
+/// Writes the entries of a session to a file, a line each, as they happen
+#[derive(Debug)]
+pub struct Recorder {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    /// Start a recording at `path`, replacing any file there
+    pub fn create(path: &Path, header: Header) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Could not create the recording {}", path.display()))?;
+        let recorder = Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        };
+        recorder.write(&[Entry::Session(header)])?;
+        Ok(recorder)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append `entries`, next to each other whatever else is recorded meanwhile
+    pub fn write(&self, entries: &[Entry]) -> Result<()> {
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+        let mut file = self.file.lock().unwrap();
+        file.write_all(lines.as_bytes())
+            .and_then(|_| file.flush())
+            .with_context(|| format!("Could not write to the recording {}", self.path.display()))
+    }
+}
+
+/// A recording being replayed, remembering which of its entries were
+#[derive(Debug)]
+pub struct Replay {
+    header: Header,
+    entries: Vec<Entry>,
+    played: Mutex<Vec<bool>>,
+}
+
+impl Replay {
+    /// Read the recording at `path`
+    pub fn open(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Could not read the recording {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid recording {}", path.display()))
+    }
+
+    /// Read a recording from the lines of `text`
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut entries = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Entry = serde_json::from_str(line)
+                .with_context(|| format!("Line {} is not an entry", index + 1))?;
+            entries.push(entry);
+        }
+        let header = match entries.first() {
+            Some(Entry::Session(header)) => header.clone(),
+            _ => return Err(anyhow!("The first line is not the session")),
+        };
+        session_file::check_version("recording", header.version, VERSION)?;
+        entries.remove(0);
+        Ok(Self {
+            header,
+            played: Mutex::new(vec![false; entries.len()]),
+            entries,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// The response recorded for `request`, and the tools the client ran for
+    /// it, marking them replayed
+    pub fn exchange(&self, request: &Request) -> Result<(Response, Vec<ToolRecord>), Diverged> {
+        let version = self.header.version;
+        let actual = request.to_json(version);
+        let mut played = self.played.lock().unwrap();
+        let found = self.entries.iter().zip(played.iter()).position(|(entry, played)| {
+            !played
+                && matches!(entry, Entry::Exchange(exchange) if exchange.request.to_json(version) == actual)
+        });
+        let Some(index) = found else {
+            let expected = self
+                .next_exchange(&played)
+                .map(|e| pretty(&e.request.to_json(version)));
+            return Err(Diverged {
+                what: "request",
+                expected,
+                actual: pretty(&actual),
+            });
+        };
+        played[index] = true;
+        let Entry::Exchange(exchange) = &self.entries[index] else {
+            unreachable!("only exchanges are matched");
+        };
+
+        // The tools a client runs itself are recorded right after its request
+        let mut tools = Vec::new();
+        if request.call == Call::GenerateWithTools {
+            for (index, entry) in self.entries.iter().enumerate().skip(index + 1) {
+                match entry {
+                    Entry::Tool(tool) if !played[index] => {
+                        played[index] = true;
+                        tools.push(tool.clone());
+                    }
+                    _ => break,
+                }
+            }
+        }
+        Ok((exchange.response.clone(), tools))
+    }
+
+    /// The recorded call of the tool `name` with `arguments`, marking it replayed
+    pub fn tool(&self, name: &str, arguments: &Value) -> Result<ToolRecord, Diverged> {
+        let mut played = self.played.lock().unwrap();
+        let found = self.entries.iter().zip(played.iter()).position(|(entry, played)| {
+            !played
+                && matches!(entry, Entry::Tool(tool) if tool.name == name && &tool.arguments == arguments)
+        });
+        let Some(index) = found else {
+            let expected = self
+                .entries
+                .iter()
+                .zip(played.iter())
+                .find_map(|(entry, played)| match entry {
+                    Entry::Tool(tool) if !played => {
+                        Some(pretty(&ToolRecord::call_json(&tool.name, &tool.arguments)))
+                    }
+                    _ => None,
+                });
+            return Err(Diverged {
+                what: "tool call",
+                expected,
+                actual: pretty(&ToolRecord::call_json(name, arguments)),
+            });
+        };
+        played[index] = true;
+        match &self.entries[index] {
+            Entry::Tool(tool) => Ok(tool.clone()),
+            _ => unreachable!("only tool calls are matched"),
+        }
+    }
+
+    /// The prompt of the next request not replayed yet, to send it again
+    pub fn next_prompt(&self) -> Option<String> {
+        let played = self.played.lock().unwrap();
+        self.next_exchange(&played)?
+            .request
+            .prompt()
+            .map(str::to_string)
+    }
+
+    /// How many recorded requests were not replayed
+    pub fn unplayed(&self) -> usize {
+        let played = self.played.lock().unwrap();
+        self.entries
+            .iter()
+            .zip(played.iter())
+            .filter(|(entry, played)| matches!(entry, Entry::Exchange(_)) && !**played)
+            .count()
+    }
+
+    fn next_exchange(&self, played: &[bool]) -> Option<&Exchange> {
+        self.entries
+            .iter()
+            .zip(played)
+            .find_map(|(entry, played)| match entry {
+                Entry::Exchange(exchange) if !played => Some(exchange),
+                _ => None,
+            })
+    }
+}
+
+/// Where the tools the agent runs for a client go: into a recording, or
+/// answered from one without running
+#[derive(Debug, Clone)]
+pub enum Tape {
+    Record(Arc<Recorder>),
+    Replay(Arc<Replay>),
+}
+
+impl Tape {
+    /// Run `tool` with `arguments` and record the call, or answer it as recorded
+    pub async fn call(
+        &self,
+        tool: &mut dyn CallableTool,
+        arguments: Value,
+    ) -> Result<String, ToolError> {
+        let name = tool.definition().name;
+        match self {
+            Self::Record(recorder) => {
+                let started = Instant::now();
+                let result = result::catch_panics(&name, tool.call_json(arguments.clone()))
+                    .await
+                    .and_then(|result| result);
+                let record = ToolRecord {
+                    name,
+                    arguments,
+                    result: ToolResult::of(&result),
+                    duration_ms: started.elapsed().as_millis() as u64,
+                };
+                recorder
+                    .write(&[Entry::Tool(record)])
+                    .map_err(|e| ToolError::Failed(format!("{:#}", e)))?;
+                result
+            }
+            Self::Replay(replay) => replay
+                .tool(&name, &arguments)
+                .map_err(|e| ToolError::Failed(e.to_string()))?
+                .result
+                .into_result(),
+        }
+    }
+}
+
+/// Whether a session is recorded or replayed, from `--record` and `--replay`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    Live,
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+impl Mode {
+    pub fn from_flags(record: Option<&Path>, replay: Option<&Path>) -> Self {
+        match (record, replay) {
+            (_, Some(path)) => Self::Replay(path.to_path_buf()),
+            (Some(path), None) => Self::Record(path.to_path_buf()),
+            (None, None) => Self::Live,
+        }
+    }
+
+    /// The client replaying the recording given to `--replay`, if any
+    pub fn replay(&self) -> Result<Option<ReplayClient>> {
+        match self {
+            Self::Replay(path) => Ok(Some(ReplayClient::open(path)?)),
+            _ => Ok(None),
+        }
+    }
+
+    /// `client`, recording its requests for `model` to the file given to `--record`
+    pub fn record(&self, client: Box<dyn LlmClient>, model: &str) -> Result<Box<dyn LlmClient>> {
+        match self {
+            Self::Record(path) => Ok(Box::new(RecordingClient::create(path, client, model)?)),
+            _ => Ok(client),
+        }
+    }
+}
+
+/// A client that records every request to the client it wraps, and the
+/// tools that client runs
+pub struct RecordingClient {
+    inner: Box<dyn LlmClient>,
+    model: String,
+    recorder: Arc<Recorder>,
+    // Taken from the wrapped client after each request, to record it
+    truncated: AtomicBool,
+}
+
+impl RecordingClient {
+    /// Record the requests of `inner` for `model` to `path`
+    pub fn create(path: &Path, inner: Box<dyn LlmClient>, model: &str) -> Result<Self> {
+        let recorder = Recorder::create(path, Header::of(inner.as_ref(), model))?;
+        Ok(Self::new(inner, model, Arc::new(recorder)))
+    }
+
+    pub fn new(inner: Box<dyn LlmClient>, model: &str, recorder: Arc<Recorder>) -> Self {
+        Self {
+            inner,
+            model: model.to_string(),
+            recorder,
+            truncated: AtomicBool::new(false),
+        }
+    }
+
+    // Record `request` with what it got, passing that on
+    fn record<T>(
+        &self,
+        request: Request,
+        reply: Result<T>,
+        response: impl FnOnce(&T) -> Response,
+    ) -> Result<T> {
+        let truncated = self.inner.take_truncated();
+        self.truncated.store(truncated, Ordering::Relaxed);
+        let mut entries = Vec::new();
+        let response = match &reply {
+            Ok(value) => {
+                if request.call == Call::GenerateWithTools {
+                    let tools = self.inner.last_tool_invocations();
+                    entries.extend(tools.iter().map(|tool| Entry::Tool(ToolRecord::of(tool))));
+                }
+                Response {
+                    truncated,
+                    ..response(value)
+                }
+            }
+            Err(e) => Response {
+                error: Some(format!("{:#}", e)),
+                ..Response::default()
+            },
+        };
+        entries.insert(0, Entry::Exchange(Exchange { request, response }));
+        self.recorder.write(&entries)?;
+        reply
+    }
+}
+
+// What a plain answer is recorded as
+fn text_response(&(ref text, input_tokens, output_tokens): &(String, usize, usize)) -> Response {
+    Response {
+        text: text.clone(),
+        input_tokens,
+        output_tokens,
+        ..Response::default()
+    }
+}
+
+// What an answer made with the client's own tools is recorded as
+fn tools_response(
+    (text, input_tokens, output_tokens, used_tools): &(String, usize, usize, Vec<String>),
+) -> Response {
+    Response {
+        text: text.clone(),
+        input_tokens: *input_tokens,
+        output_tokens: *output_tokens,
+        used_tools: used_tools.clone(),
+        ..Response::default()
+    }
+}
+
+// What a model turn handing back tool calls is recorded as
+fn turn_response(turn: &ModelTurn) -> Response {
+    Response {
+        text: turn.content.clone(),
+        input_tokens: turn.input_tokens,
+        output_tokens: turn.output_tokens,
+        tool_calls: turn.tool_calls.clone(),
+        ..Response::default()
+    }
+}
+
+#[async_trait]
+impl LlmClient for RecordingClient {
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    async fn generate_response(&self, messages: &[Message]) -> Result<(String, usize, usize)> {
+        let request = Request::new(Call::Generate, &self.model, messages);
+        let reply = self.inner.generate_response(messages).await;
+        self.record(request, reply, text_response)
+    }
+
+    async fn generate_response_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(String, usize, usize, Vec<String>)> {
+        let request =
+            Request::new(Call::GenerateWithTools, &self.model, messages).with_tools(tools);
+        let reply = self
+            .inner
+            .generate_response_with_tools(messages, tools)
+            .await;
+        self.record(request, reply, tools_response)
+    }
+
+    async fn generate_response_with_options(
+        &self,
+        messages: &[Message],
+        options: &GenerationOptions,
+    ) -> Result<(String, usize, usize)> {
+        let request = Request::new(Call::Generate, &self.model, messages).with_options(options);
+        let reply = self
+            .inner
+            .generate_response_with_options(messages, options)
+            .await;
+        self.record(request, reply, text_response)
+    }
+
+    async fn generate_response_with_tools_and_options(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+        options: &GenerationOptions,
+    ) -> Result<(String, usize, usize, Vec<String>)> {
+        let request = Request::new(Call::GenerateWithTools, &self.model, messages)
+            .with_tools(tools)
+            .with_options(options);
+        let reply = self
+            .inner
+            .generate_response_with_tools_and_options(messages, tools, options)
+            .await;
+        self.record(request, reply, tools_response)
+    }
+
+    async fn chat_step(&self, messages: &[Message], tools: &[Tool]) -> Result<ModelTurn> {
+        let request = Request::new(Call::ChatStep, &self.model, messages).with_tools(tools);
+        let reply = self.inner.chat_step(messages, tools).await;
+        self.record(request, reply, turn_response)
+    }
+
+    async fn chat_step_with_options(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+        options: &GenerationOptions,
+    ) -> Result<ModelTurn> {
+        let request = Request::new(Call::ChatStep, &self.model, messages)
+            .with_tools(tools)
+            .with_options(options);
+        let reply = self
+            .inner
+            .chat_step_with_options(messages, tools, options)
+            .await;
+        self.record(request, reply, turn_response)
+    }
+
+    fn supports_tool_calls(&self) -> bool {
+        self.inner.supports_tool_calls()
+    }
+
+    fn tape(&self) -> Option<Tape> {
+        Some(Tape::Record(Arc::clone(&self.recorder)))
+    }
+
+    fn available_tools(&self) -> Vec<String> {
+        self.inner.available_tools()
+    }
+
+    fn last_tool_invocations(&self) -> Vec<ToolInvocation> {
+        self.inner.last_tool_invocations()
+    }
+
+    fn reset_tools(&self) {
+        self.inner.reset_tools();
+    }
+
+    fn set_dry_run(&self, dry_run: bool) {
+        self.inner.set_dry_run(dry_run);
+    }
+
+    fn dry_run(&self) -> bool {
+        self.inner.dry_run()
+    }
+
+    fn set_reviewer(&self, reviewer: Arc<dyn Reviewer>) {
+        self.inner.set_reviewer(reviewer);
+    }
+
+    fn set_progress(&self, progress: Arc<dyn Progress>) {
+        self.inner.set_progress(progress);
+    }
+
+    fn set_tool_events(&self, events: Option<UnboundedSender<ToolEvent>>) {
+        self.inner.set_tool_events(events);
+    }
+
+    fn set_journal(&self, journal: Arc<Journal>) {
+        self.inner.set_journal(journal);
+    }
+
+    fn set_remote_gate(&self, gate: Arc<RemoteGate>) {
+        self.inner.set_remote_gate(gate);
+    }
+
+    fn set_memory(&self, memory: Arc<Memory>) {
+        self.inner.set_memory(memory);
+    }
+
+    fn protect(&self, rule: &str) {
+        self.inner.protect(rule);
+    }
+
+    fn apply_config(&self, config: &Config) {
+        self.inner.apply_config(config);
+    }
+
+    fn set_tool_enabled(&self, name: &str, enabled: bool) -> bool {
+        self.inner.set_tool_enabled(name, enabled)
+    }
+
+    fn provider(&self) -> &str {
+        self.inner.provider()
+    }
+
+    fn model_name(&self) -> Option<String> {
+        self.inner.model_name()
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        self.inner.health_check().await
+    }
+
+    async fn warm_up(&self) -> Result<ServerTimings> {
+        self.inner.warm_up().await
+    }
+
+    async fn model_loaded(&self) -> Option<bool> {
+        self.inner.model_loaded().await
+    }
+
+    async fn unload(&self) -> Result<()> {
+        self.inner.unload().await
+    }
+
+    fn for_model(&self, model: &str) -> Result<Box<dyn LlmClient>> {
+        let inner = self.inner.for_model(model)?;
+        Ok(Box::new(Self::new(
+            inner,
+            model,
+            Arc::clone(&self.recorder),
+        )))
+    }
+
+    fn endpoint(&self) -> Option<String> {
+        self.inner.endpoint()
+    }
+
+    fn check_remote(&self, sources: &[String]) -> Result<()> {
+        self.inner.check_remote(sources)
+    }
+
+    async fn supports_images(&self) -> Option<bool> {
+        self.inner.supports_images().await
+    }
+
+    async fn supports_tools(&self) -> Option<bool> {
+        self.inner.supports_tools().await
+    }
+
+    async fn context_length(&self) -> Option<usize> {
+        self.inner.context_length().await
+    }
+
+    fn set_limits(&self, limits: ResponseLimits) {
+        self.inner.set_limits(limits);
+    }
+
+    fn set_context_window(&self, tokens: usize) {
+        self.inner.set_context_window(tokens);
+    }
+
+    fn limits(&self) -> ResponseLimits {
+        self.inner.limits()
+    }
+
+    fn take_truncated(&self) -> bool {
+        self.truncated.swap(false, Ordering::Relaxed)
+    }
+
+    fn take_rate_limited(&self) -> usize {
+        self.inner.take_rate_limited()
+    }
+
+    fn take_recovered_calls(&self) -> usize {
+        self.inner.take_recovered_calls()
+    }
+
+    fn take_prompt_usage(&self) -> PromptUsage {
+        self.inner.take_prompt_usage()
+    }
+
+    fn take_server_timings(&self) -> ServerTimings {
+        self.inner.take_server_timings()
+    }
+
+    fn take_notices(&self) -> Vec<String> {
+        self.inner.take_notices()
+    }
+}
+
+/// A client that answers from a recording instead of a model, leaving the
+/// tools the recorded client ran unrun
+pub struct ReplayClient {
+    replay: Arc<Replay>,
+    model: String,
+    last_tool_invocations: Mutex<Vec<ToolInvocation>>,
+    truncated: AtomicBool,
+    dry_run: AtomicBool,
+    limits: Mutex<ResponseLimits>,
+}
+
+impl ReplayClient {
+    /// Replay the recording at `path`
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(Self::new(Arc::new(Replay::open(path)?)))
+    }
+
+    /// Replay the requests of the recorded model
+    pub fn new(replay: Arc<Replay>) -> Self {
+        let model = replay.header().model.clone();
+        Self::for_replay(replay, &model)
+    }
+
+    fn for_replay(replay: Arc<Replay>, model: &str) -> Self {
+        Self {
+            replay,
+            model: model.to_string(),
+            last_tool_invocations: Mutex::new(Vec::new()),
+            truncated: AtomicBool::new(false),
+            dry_run: AtomicBool::new(false),
+            limits: Mutex::new(ResponseLimits::default()),
+        }
+    }
+
+    pub fn replay(&self) -> &Arc<Replay> {
+        &self.replay
+    }
+
+    // The response recorded for `request`, or the error it failed with
+    fn play(&self, request: Request) -> Result<Response> {
+        let (response, tools) = self.replay.exchange(&request)?;
+        *self.last_tool_invocations.lock().unwrap() =
+            tools.iter().map(ToolRecord::invocation).collect();
+        self.truncated.store(response.truncated, Ordering::Relaxed);
+        match response.error {
+            Some(error) => Err(anyhow!(error)),
+            None => Ok(response),
+        }
+    }
+
+    fn play_text(&self, request: Request) -> Result<(String, usize, usize)> {
+        let response = self.play(request)?;
+        Ok((response.text, response.input_tokens, response.output_tokens))
+    }
+
+    fn play_with_tools(&self, request: Request) -> Result<(String, usize, usize, Vec<String>)> {
+        let response = self.play(request)?;
+        Ok((
+            response.text,
+            response.input_tokens,
+            response.output_tokens,
+            response.used_tools,
+        ))
+    }
+
+    fn play_turn(&self, request: Request) -> Result<ModelTurn> {
+        let response = self.play(request)?;
+        Ok(ModelTurn {
+            content: response.text,
+            tool_calls: response.tool_calls,
+            input_tokens: response.input_tokens,
+            output_tokens: response.output_tokens,
+        })
+    }
+}
+
+#[async_trait]
+impl LlmClient for ReplayClient {
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    async fn generate_response(&self, messages: &[Message]) -> Result<(String, usize, usize)> {
+        self.play_text(Request::new(Call::Generate, &self.model, messages))
+    }
+
+    async fn generate_response_with_tools(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+    ) -> Result<(String, usize, usize, Vec<String>)> {
+        self.play_with_tools(
+            Request::new(Call::GenerateWithTools, &self.model, messages).with_tools(tools),
+        )
+    }
+
+    async fn generate_response_with_options(
+        &self,
+        messages: &[Message],
+        options: &GenerationOptions,
+    ) -> Result<(String, usize, usize)> {
+        self.play_text(Request::new(Call::Generate, &self.model, messages).with_options(options))
+    }
+
+    async fn generate_response_with_tools_and_options(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+        options: &GenerationOptions,
+    ) -> Result<(String, usize, usize, Vec<String>)> {
+        self.play_with_tools(
+            Request::new(Call::GenerateWithTools, &self.model, messages)
+                .with_tools(tools)
+                .with_options(options),
+        )
+    }
+
+    async fn chat_step(&self, messages: &[Message], tools: &[Tool]) -> Result<ModelTurn> {
+        self.play_turn(Request::new(Call::ChatStep, &self.model, messages).with_tools(tools))
+    }
+
+    async fn chat_step_with_options(
+        &self,
+        messages: &[Message],
+        tools: &[Tool],
+        options: &GenerationOptions,
+    ) -> Result<ModelTurn> {
+        self.play_turn(
+            Request::new(Call::ChatStep, &self.model, messages)
+                .with_tools(tools)
+                .with_options(options),
+        )
+    }
+
+    fn supports_tool_calls(&self) -> bool {
+        self.replay.header().tool_calls
+    }
+
+    fn tape(&self) -> Option<Tape> {
+        Some(Tape::Replay(Arc::clone(&self.replay)))
+    }
+
+    fn available_tools(&self) -> Vec<String> {
+        self.replay.header().tools.clone()
+    }
+
+    fn last_tool_invocations(&self) -> Vec<ToolInvocation> {
+        self.last_tool_invocations.lock().unwrap().clone()
+    }
+
+    fn set_dry_run(&self, dry_run: bool) {
+        self.dry_run.store(dry_run, Ordering::Relaxed);
+    }
+
+    fn dry_run(&self) -> bool {
+        self.dry_run.load(Ordering::Relaxed)
+    }
+
+    fn provider(&self) -> &str {
+        &self.replay.header().provider
+    }
+
+    fn model_name(&self) -> Option<String> {
+        match &self.replay.header().model_name {
+            Some(name) if self.model == self.replay.header().model => Some(name.clone()),
+            _ => Some(self.model.clone()),
+        }
+    }
+
+    fn for_model(&self, model: &str) -> Result<Box<dyn LlmClient>> {
+        let client = Self::for_replay(Arc::clone(&self.replay), model);
+        client.set_limits(self.limits());
+        Ok(Box::new(client))
+    }
+
+    fn set_limits(&self, limits: ResponseLimits) {
+        *self.limits.lock().unwrap() = limits;
+    }
+
+    fn limits(&self) -> ResponseLimits {
+        self.limits.lock().unwrap().clone()
+    }
+
+    fn take_truncated(&self) -> bool {
+        self.truncated.swap(false, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::mock::MockLlmClient;
+    use crate::tool_loop;
+    use crate::tools::registry::ToolSet;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_sessions_are_replayed_as_recorded() -> Result<()> {
+        let dir = TempDir::new()?;
+        let notes = dir.path().join("notes.txt");
+        fs::write(&notes, "remember the milk")?;
+        let path = dir.path().join("session.jsonl");
+        let read = json!({ "operation": "read", "path": notes.to_string_lossy() });
+
+        let mock = MockLlmClient::new()
+            .with_tool_call("file", read.clone())
+            .with_reply("You need milk");
+        let client = RecordingClient::create(&path, Box::new(mock), "llama3.2")?;
+        let mut tools = ToolSet::with_defaults(Arc::default());
+        tools.set_tape(client.tape());
+        let messages = [Message::user("What do I need?")];
+        let recorded = tool_loop::respond(&client, &mut tools, &messages).await?;
+
+        // Every line reads back to the same bytes
+        let text = fs::read_to_string(&path)?;
+        assert_eq!(text.lines().count(), 4);
+        for line in text.lines() {
+            let entry: Entry = serde_json::from_str(line)?;
+            assert_eq!(serde_json::to_string(&entry)?, line);
+        }
+
+        // Nothing is read again, so the file need not be there
+        fs::remove_file(&notes)?;
+        let client = ReplayClient::open(&path)?;
+        let mut tools = ToolSet::with_defaults(Arc::default());
+        tools.set_tape(client.tape());
+        assert_eq!(
+            client.replay().next_prompt().as_deref(),
+            Some("What do I need?")
+        );
+        let replayed = tool_loop::respond(&client, &mut tools, &messages).await?;
+        assert_eq!(replayed.text, "You need milk");
+        assert_eq!(replayed.text, recorded.text);
+        assert_eq!(replayed.tool_calls.len(), 1);
+        assert_eq!(replayed.tool_calls[0].output, recorded.tool_calls[0].output);
+        assert!(replayed.tool_calls[0].output.contains("remember the milk"));
+        assert_eq!(client.replay().unplayed(), 0);
+        assert_eq!(client.replay().next_prompt(), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_divergence_shows_the_expected_request() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("session.jsonl");
+        let mock = MockLlmClient::new().with_reply("Paris");
+        let client = RecordingClient::create(&path, Box::new(mock), "llama3.2")?;
+        client
+            .generate_response(&[Message::user("Capital of France?")])
+            .await?;
+
+        let client = ReplayClient::open(&path)?;
+        let error = client
+            .generate_response(&[Message::user("Capital of Spain?")])
+            .await
+            .unwrap_err();
+        let diverged = error.downcast_ref::<Diverged>().unwrap();
+        assert_eq!(diverged.what, "request");
+        let text = error.to_string();
+        assert!(text
+            .starts_with("The replay diverged from the recording: this request was not recorded"));
+        assert!(text.contains("--- recorded\n+++ replayed\n"));
+        assert!(text.contains("-      \"content\": \"Capital of France?\""));
+        assert!(text.contains("+      \"content\": \"Capital of Spain?\""));
+
+        // The recorded request still gets its answer
+        let (text, _, _) = client
+            .generate_response(&[Message::user("Capital of France?")])
+            .await?;
+        assert_eq!(text, "Paris");
+        let error = client
+            .generate_response(&[Message::user("Capital of France?")])
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("it has no request left for"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tools_the_client_ran_are_replayed_with_its_answer() -> Result<()> {
+        let recording = [
+            r#"{"kind":"session","version":1,"model":"llama3.2","provider":"ollama","tool_calls":false,"tools":["ls"]}"#,
+            r#"{"kind":"exchange","request":{"call":"generate_with_tools","model":"llama3.2","messages":[{"role":"user","content":"List it"}]},"response":{"text":"Two files","input_tokens":9,"output_tokens":3,"used_tools":["ls"]}}"#,
+            r#"{"kind":"tool","name":"ls","arguments":{"path":"."},"result":{"output":"a.txt\nb.txt"},"duration_ms":12}"#,
+        ]
+        .join("\n");
+        let client = ReplayClient::new(Arc::new(Replay::parse(&recording)?));
+        assert!(!client.supports_tool_calls());
+        assert_eq!(client.available_tools(), ["ls"]);
+        assert_eq!(client.provider(), "ollama");
+
+        let (text, _, _, used) = client
+            .generate_response_with_tools(&[Message::user("List it")], &[])
+            .await?;
+        assert_eq!((text.as_str(), used), ("Two files", vec!["ls".to_string()]));
+        let invocations = client.last_tool_invocations();
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].output, "a.txt\nb.txt");
+        assert_eq!(invocations[0].duration, Duration::from_millis(12));
+
+        assert!(Replay::parse("{\"kind\":\"tool\"}").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recordings_are_versioned_like_sessions() -> Result<()> {
+        // Format 1 kept no usage, so replies that have some still match
+        let recording = [
+            r#"{"kind":"session","version":1,"model":"llama3.2","provider":"ollama","tool_calls":false}"#,
+            r#"{"kind":"exchange","request":{"call":"generate","model":"llama3.2","messages":[{"role":"user","content":"Hi"},{"role":"assistant","content":"Hello"},{"role":"user","content":"Bye"}]},"response":{"text":"Goodbye","input_tokens":9,"output_tokens":3}}"#,
+        ]
+        .join("\n");
+        let client = ReplayClient::new(Arc::new(Replay::parse(&recording)?));
+        let (text, _, _) = client
+            .generate_response(&[
+                Message::user("Hi"),
+                Message::assistant("Hello", 5, 1, vec!["ls".to_string()]),
+                Message::user("Bye"),
+            ])
+            .await?;
+        assert_eq!(text, "Goodbye");
+
+        let newer = format!(
+            r#"{{"kind":"session","version":{},"model":"llama3.2","provider":"ollama","tool_calls":false}}"#,
+            VERSION + 1
+        );
+        let error = Replay::parse(&newer).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            format!(
+                "Created by a newer sentinel (recording format {}, this one reads up to {}); upgrade sentinel to open it",
+                VERSION + 1,
+                VERSION
+            )
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+//! The JSON object printed by `sentinel ask --json`.
+//!
+//! Scripts parse this, so fields are only ever added, never renamed or removed.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio_util::sync::CancellationToken;
+
+use crate::failure::FailureKind;
+use crate::llm::ollama::{LlmClient, ToolInvocation};
+use crate::tool_loop::{self, TurnOutcome};
+use crate::tools::registry::ToolSet;
+use crate::words::WordCount;
+use crate::Message;
+
+/// Characters of each tool's output kept in the report
+pub const MAX_TOOL_OUTPUT: usize = 2000;
+
+/// The result of one `ask`, successful or not
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AskReport {
+    pub response: String,
+    pub model: String,
+    pub provider: String,
+    /// Tokens of the prompt, without the tool output in it
+    pub input_tokens: usize,
+    /// Tokens of tool output sent back to the model
+    #[serde(default)]
+    pub tool_output_tokens: usize,
+    pub output_tokens: usize,
+    /// The part of `output_tokens` estimated to have gone to reasoning
+    pub reasoning_tokens: usize,
+    /// Tokens spent summarizing long tool output, as `[tools.summarize]` asks
+    #[serde(default)]
+    pub summary_tokens: usize,
+    pub duration_ms: u64,
+    pub tool_calls: Vec<ToolCallReport>,
+    /// Whether the response was cut off by the `max_tokens` limit
+    #[serde(default)]
+    pub truncated: bool,
+    /// The words and characters of the response, its code counted apart
+    #[serde(default)]
+    pub words: WordCount,
+    /// What a reasoning model thought before answering, only included on request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
+    /// Why the request failed; the response is empty when this is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// What kind of failure `error` is, e.g. "timeout"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<FailureKind>,
+    /// The status `ask --json` exits with for `error_kind`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+}
+
+/// A tool the model called while answering
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCallReport {
+    pub name: String,
+    pub arguments: Value,
+    /// The tool's output, cut to `MAX_TOOL_OUTPUT` characters
+    pub output: String,
+    /// Whether `output` was cut
+    pub truncated: bool,
+    /// Estimated tokens of the whole output
+    #[serde(default)]
+    pub output_tokens: usize,
+    /// How long the tool ran
+    #[serde(default)]
+    pub duration_ms: u64,
+    /// What the model was sent instead of the output, if it was summarized
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+}
+
+impl AskReport {
+    pub fn success(model: &str, provider: &str, outcome: TurnOutcome, duration: Duration) -> Self {
+        Self {
+            words: WordCount::of(&outcome.text),
+            response: outcome.text,
+            input_tokens: outcome.input_tokens,
+            tool_output_tokens: outcome.tool_output_tokens,
+            output_tokens: outcome.output_tokens,
+            reasoning_tokens: outcome.reasoning_tokens,
+            summary_tokens: outcome.summary_tokens,
+            reasoning: outcome.reasoning,
+            tool_calls: outcome.tool_calls.iter().map(ToolCallReport::new).collect(),
+            truncated: outcome.truncated,
+            ..Self::empty(model, provider, duration)
+        }
+    }
+
+    pub fn failure(model: &str, provider: &str, error: &anyhow::Error, duration: Duration) -> Self {
+        let kind = FailureKind::of(error);
+        Self {
+            error: Some(format!("{:#}", error)),
+            error_kind: Some(kind),
+            exit_code: Some(kind.exit_code()),
+            ..Self::empty(model, provider, duration)
+        }
+    }
+
+    fn empty(model: &str, provider: &str, duration: Duration) -> Self {
+        Self {
+            response: String::new(),
+            model: model.to_string(),
+            provider: provider.to_string(),
+            input_tokens: 0,
+            tool_output_tokens: 0,
+            output_tokens: 0,
+            reasoning_tokens: 0,
+            summary_tokens: 0,
+            duration_ms: duration.as_millis() as u64,
+            tool_calls: Vec::new(),
+            truncated: false,
+            words: WordCount::default(),
+            reasoning: None,
+            error: None,
+            error_kind: None,
+            exit_code: None,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.error.is_some()
+    }
+}
+
+impl ToolCallReport {
+    pub fn new(invocation: &ToolInvocation) -> Self {
+        let truncated = invocation.output.chars().count() > MAX_TOOL_OUTPUT;
+        Self {
+            name: invocation.name.clone(),
+            arguments: invocation.arguments.clone(),
+            output: invocation.output.chars().take(MAX_TOOL_OUTPUT).collect(),
+            truncated,
+            output_tokens: invocation.output_tokens(),
+            duration_ms: invocation.duration.as_millis() as u64,
+            summary: invocation.summary.clone(),
+        }
+    }
+}
+
+/// Answer `messages` and describe the outcome, turning any error into the report's `error`
+pub async fn ask(
+    client: &dyn LlmClient,
+    tools: Option<&mut ToolSet>,
+    messages: &[Message],
+    model: &str,
+    provider: &str,
+) -> AskReport {
+    let started = Instant::now();
+    let outcome = match tools {
+        Some(tools) => tool_loop::respond(client, tools, messages).await,
+        None => tool_loop::respond_without_tools(client, messages, &CancellationToken::new()).await,
+    };
+
+    match outcome {
+        Ok(outcome) => AskReport::success(model, provider, outcome, started.elapsed()),
+        Err(e) => AskReport::failure(model, provider, &e, started.elapsed()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::limits::ResponseLimits;
+    use crate::llm::mock::MockLlmClient;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    // Print the report the way main does and parse it back
+    fn round_trip(report: &AskReport) -> AskReport {
+        let text = serde_json::to_string(report).unwrap();
+        serde_json::from_str(&text).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_report_for_a_mocked_run() {
+        let client = MockLlmClient::new()
+            .with_tool_call("ls", json!({ "path": "." }))
+            .with_reply("Three files");
+        let mut tools = ToolSet::with_defaults(Arc::default());
+        let messages = [Message::user("what is here?")];
+
+        let report = ask(&client, Some(&mut tools), &messages, "mock", "mock").await;
+        let parsed = round_trip(&report);
+        assert_eq!(parsed, report);
+        assert_eq!(parsed.response, "Three files");
+        assert_eq!(parsed.words.words, 2);
+        assert_eq!(parsed.model, "mock");
+        assert!(parsed.input_tokens > 0);
+        assert!(parsed.error.is_none());
+
+        assert_eq!(parsed.tool_calls.len(), 1);
+        assert_eq!(parsed.tool_calls[0].name, "ls");
+        assert_eq!(parsed.tool_calls[0].arguments, json!({ "path": "." }));
+        assert!(!parsed.tool_calls[0].output.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_report_for_a_failed_run() {
+        let client = MockLlmClient::new().with_error("model not found");
+        let report = ask(&client, None, &[Message::user("hi")], "mock", "mock").await;
+
+        let text = serde_json::to_value(&report).unwrap();
+        assert_eq!(text["error"], "model not found");
+        assert_eq!(text["error_kind"], "model_not_found");
+        assert_eq!(text["exit_code"], 4);
+        assert_eq!(text["response"], "");
+        assert!(round_trip(&report).is_error());
+    }
+
+    #[tokio::test]
+    async fn test_report_separates_reasoning() {
+        let client = MockLlmClient::new().with_reply("<think>Easy sum</think>4");
+        let report = ask(&client, None, &[Message::user("2+2?")], "mock", "mock").await;
+        assert_eq!(report.response, "4");
+        assert_eq!(report.reasoning.as_deref(), Some("Easy sum"));
+        assert!(report.reasoning_tokens > 0);
+    }
+
+    #[tokio::test]
+    async fn test_report_marks_responses_cut_off_by_the_limit() {
+        let client = MockLlmClient::new()
+            .with_reply("one two three four five six seven eight")
+            .with_reply("short");
+        client.set_limits(ResponseLimits {
+            max_tokens: Some(2),
+            ..ResponseLimits::default()
+        });
+
+        let report = ask(&client, None, &[Message::user("count")], "mock", "mock").await;
+        assert_eq!(report.response, "one two ");
+        assert!(round_trip(&report).truncated);
+
+        let report = ask(&client, None, &[Message::user("again")], "mock", "mock").await;
+        assert_eq!(report.response, "short");
+        assert!(!report.truncated);
+    }
+
+    #[test]
+    fn test_tool_output_is_truncated() {
+        let invocation = ToolInvocation {
+            name: "bash".to_string(),
+            arguments: json!({ "command": "yes" }),
+            output: "y\n".repeat(MAX_TOOL_OUTPUT),
+            summary: None,
+            duration: Duration::ZERO,
+        };
+        let report = ToolCallReport::new(&invocation);
+        assert!(report.truncated);
+        assert_eq!(report.output.chars().count(), MAX_TOOL_OUTPUT);
+
+        // Successful reports leave out the error field entirely
+        let report = AskReport::success("m", "ollama", TurnOutcome::default(), Duration::ZERO);
+        assert!(serde_json::to_value(report).unwrap().get("error").is_none());
+    }
+}
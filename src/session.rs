@@ -0,0 +1,307 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::stats::SessionStats;
+
+// Name of the branch every session starts on
+const MAIN_BRANCH: &str = "main";
+
+// Maximum characters of a message shown in history listings
+const PREVIEW_CHARS: usize = 60;
+
+/// Where a branch was forked from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ForkPoint {
+    pub branch: String,
+    pub message_index: usize,
+}
+
+/// One line of conversation history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Branch<M> {
+    pub name: String,
+    pub parent: Option<ForkPoint>,
+    pub messages: Vec<M>,
+}
+
+/// A branch's place in the branch tree, for listings
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BranchSummary {
+    pub name: String,
+    pub parent: Option<ForkPoint>,
+    pub messages: usize,
+    pub depth: usize,
+    pub active: bool,
+}
+
+/// What a saved session says about its conversation, kept apart from the messages
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionMeta {
+    /// A short name for the conversation, generated or set with /title
+    pub title: Option<String>,
+    pub created: Option<DateTime<Local>>,
+    /// When the session was last saved
+    pub updated: Option<DateTime<Local>>,
+    pub model: Option<String>,
+    pub message_count: usize,
+    /// Usage so far, so a continued session keeps counting from it
+    pub stats: SessionStats,
+}
+
+impl SessionMeta {
+    /// A conversation starting now with `model`
+    pub fn new(model: &str) -> Self {
+        Self {
+            created: Some(Local::now()),
+            model: Some(model.to_string()),
+            ..Self::default()
+        }
+    }
+
+    /// The title, or when the session was created for one that has none
+    pub fn name(&self) -> String {
+        match (&self.title, self.created) {
+            (Some(title), _) => title.clone(),
+            (None, Some(created)) => format!("Session of {}", created.format("%Y-%m-%d %H:%M")),
+            (None, None) => "Untitled session".to_string(),
+        }
+    }
+}
+
+/// A conversation and the branches forked from it.
+///
+/// Only the active branch receives new messages; the others are kept as they
+/// were when the conversation moved away from them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sessions<M> {
+    branches: Vec<Branch<M>>,
+    active: usize,
+    forks: usize,
+}
+
+impl<M: Clone> Sessions<M> {
+    pub fn new(messages: Vec<M>) -> Self {
+        Self {
+            branches: vec![Branch {
+                name: MAIN_BRANCH.to_string(),
+                parent: None,
+                messages,
+            }],
+            active: 0,
+            forks: 0,
+        }
+    }
+
+    /// Sessions with `branches`, the one at `active` receiving new messages;
+    /// None if there is no such branch
+    pub fn from_branches(branches: Vec<Branch<M>>, active: usize) -> Option<Self> {
+        if active >= branches.len() {
+            return None;
+        }
+        // Branches are never removed, so every one but the first was a fork
+        let forks = branches.len() - 1;
+        Some(Self {
+            branches,
+            active,
+            forks,
+        })
+    }
+
+    /// The same branches, each with its messages converted by `convert`
+    pub fn map_branches<N: Clone>(&self, convert: impl Fn(&[M]) -> Vec<N>) -> Sessions<N> {
+        Sessions {
+            branches: self
+                .branches
+                .iter()
+                .map(|branch| Branch {
+                    name: branch.name.clone(),
+                    parent: branch.parent.clone(),
+                    messages: convert(&branch.messages),
+                })
+                .collect(),
+            active: self.active,
+            forks: self.forks,
+        }
+    }
+
+    pub fn branches(&self) -> &[Branch<M>] {
+        &self.branches
+    }
+
+    pub fn active_index(&self) -> usize {
+        self.active
+    }
+
+    pub fn active(&self) -> &Branch<M> {
+        &self.branches[self.active]
+    }
+
+    pub fn active_name(&self) -> &str {
+        &self.active().name
+    }
+
+    /// Messages of the active branch
+    pub fn messages(&self) -> &[M] {
+        &self.active().messages
+    }
+
+    pub fn messages_mut(&mut self) -> &mut Vec<M> {
+        &mut self.branches[self.active].messages
+    }
+
+    /// Start a new branch with the active branch's messages up to and including
+    /// `index`, and make it the active one. Returns the new branch's name.
+    pub fn fork(&mut self, index: usize) -> Result<&str> {
+        let parent = self.active();
+        if index >= parent.messages.len() {
+            return Err(anyhow!(
+                "No message {} in '{}' (it has {} messages)",
+                index,
+                parent.name,
+                parent.messages.len()
+            ));
+        }
+
+        let branch = Branch {
+            name: format!("fork-{}", self.forks + 1),
+            parent: Some(ForkPoint {
+                branch: parent.name.clone(),
+                message_index: index,
+            }),
+            messages: parent.messages[..=index].to_vec(),
+        };
+
+        self.forks += 1;
+        self.branches.push(branch);
+        self.active = self.branches.len() - 1;
+        Ok(self.active_name())
+    }
+
+    /// Make another branch the active one
+    pub fn switch(&mut self, name: &str) -> Result<()> {
+        self.active = self
+            .branches
+            .iter()
+            .position(|branch| branch.name == name)
+            .ok_or_else(|| anyhow!("No branch named '{}'", name))?;
+        Ok(())
+    }
+
+    /// Every branch, with children listed under their parent
+    pub fn summaries(&self) -> Vec<BranchSummary> {
+        let mut summaries = Vec::new();
+        self.collect_summaries(None, 0, &mut summaries);
+        summaries
+    }
+
+    fn collect_summaries(
+        &self,
+        parent: Option<&str>,
+        depth: usize,
+        summaries: &mut Vec<BranchSummary>,
+    ) {
+        for (index, branch) in self.branches.iter().enumerate() {
+            let parent_name = branch.parent.as_ref().map(|fork| fork.branch.as_str());
+            if parent_name != parent {
+                continue;
+            }
+
+            summaries.push(BranchSummary {
+                name: branch.name.clone(),
+                parent: branch.parent.clone(),
+                messages: branch.messages.len(),
+                depth,
+                active: index == self.active,
+            });
+            self.collect_summaries(Some(&branch.name), depth + 1, summaries);
+        }
+    }
+}
+
+/// First line of a message, shortened for history listings
+pub fn preview(content: &str) -> String {
+    let line = content.lines().next().unwrap_or("");
+    if line.chars().count() > PREVIEW_CHARS || content.lines().nth(1).is_some() {
+        let shortened: String = line.chars().take(PREVIEW_CHARS).collect();
+        format!("{}...", shortened.trim_end())
+    } else {
+        line.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sessions(messages: &[&str]) -> Sessions<String> {
+        Sessions::new(messages.iter().map(|m| m.to_string()).collect())
+    }
+
+    #[test]
+    fn test_fork_copies_messages_up_to_index() -> Result<()> {
+        let mut sessions = sessions(&["system", "question", "answer", "follow-up"]);
+
+        assert_eq!(sessions.fork(2)?, "fork-1");
+        assert_eq!(sessions.messages(), ["system", "question", "answer"]);
+
+        // Changes to the fork leave the original untouched
+        sessions.messages_mut()[1].push_str(" edited");
+        sessions.messages_mut().push("other follow-up".to_string());
+        sessions.switch("main")?;
+        assert_eq!(
+            sessions.messages(),
+            ["system", "question", "answer", "follow-up"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fork_out_of_range() {
+        let mut sessions = sessions(&["system"]);
+        assert!(sessions.fork(1).is_err());
+        assert_eq!(sessions.active_name(), "main");
+    }
+
+    #[test]
+    fn test_summaries_nest_children_under_parents() -> Result<()> {
+        let mut sessions = sessions(&["a", "b", "c"]);
+        sessions.fork(1)?;
+        sessions.fork(0)?;
+        sessions.switch("main")?;
+        sessions.fork(2)?;
+
+        let tree: Vec<(String, usize, bool)> = sessions
+            .summaries()
+            .into_iter()
+            .map(|summary| (summary.name, summary.depth, summary.active))
+            .collect();
+        assert_eq!(
+            tree,
+            vec![
+                ("main".to_string(), 0, false),
+                ("fork-1".to_string(), 1, false),
+                ("fork-2".to_string(), 2, false),
+                ("fork-3".to_string(), 1, true),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sessions_are_named_by_title_or_date() {
+        let mut meta = SessionMeta::new("llama3.2");
+        assert!(meta.name().starts_with("Session of 20"));
+        meta.title = Some("Naming a cat".to_string());
+        assert_eq!(meta.name(), "Naming a cat");
+        assert_eq!(SessionMeta::default().name(), "Untitled session");
+    }
+
+    #[test]
+    fn test_preview_uses_first_line() {
+        assert_eq!(preview("short"), "short");
+        assert_eq!(preview("first\nsecond"), "first...");
+        assert_eq!(preview(&"x".repeat(80)), format!("{}...", "x".repeat(60)));
+    }
+}
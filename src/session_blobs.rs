@@ -0,0 +1,418 @@
+//! Keeping session files small by moving long message contents out of them.
+//!
+//! Once a session file would be larger than `[sessions] max_size_kb`, the
+//! longest contents, such as command output and attached files, are moved to
+//! blob files named by the SHA-256 of what they hold, in a `blobs` directory
+//! next to the session, oldest message first until the file fits. The message
+//! keeps a [`BlobRef`] and a line saying where its content went, so sessions
+//! without blobs read as they always did. The content is read back only when
+//! the message is expanded in the TUI.
+//!
+//! `sentinel sessions prune` drops the blobs of chosen sessions that are
+//! older or larger than asked, leaving a note in their place, and removes the
+//! blob files no other session in the directory refers to.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::autosave::write_atomically;
+use crate::session_file::SessionFile;
+
+/// The directory next to the session files that holds their blobs
+pub const BLOB_DIR: &str = "blobs";
+
+/// The `[sessions]` config table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct SessionsConfig {
+    /// Kilobytes a session file may take before long contents move to blobs
+    pub max_size_kb: u64,
+    /// Kilobytes a message's content needs to be moved to a blob
+    pub blob_min_kb: u64,
+}
+
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        Self {
+            max_size_kb: 2048,
+            blob_min_kb: 16,
+        }
+    }
+}
+
+impl SessionsConfig {
+    /// The largest session file in bytes
+    pub fn max_size(&self) -> u64 {
+        self.max_size_kb.saturating_mul(1024)
+    }
+
+    fn blob_min(&self) -> usize {
+        usize::try_from(self.blob_min_kb.saturating_mul(1024)).unwrap_or(usize::MAX)
+    }
+}
+
+/// Where a message's content went: the SHA-256 of the content, in hex, and its length
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobRef {
+    pub sha256: String,
+    pub bytes: u64,
+}
+
+/// The blob files of the sessions in one directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobStore {
+    dir: PathBuf,
+}
+
+impl BlobStore {
+    /// The blobs of the session file at `session`
+    pub fn next_to(session: &Path) -> Self {
+        let parent = session.parent().unwrap_or(Path::new("."));
+        Self {
+            dir: parent.join(BLOB_DIR),
+        }
+    }
+
+    pub fn path(&self, blob: &BlobRef) -> PathBuf {
+        self.dir.join(&blob.sha256)
+    }
+
+    /// Keep `content` as a blob, unless one with the same content is kept already
+    pub fn put(&self, content: &str) -> Result<BlobRef> {
+        let blob = BlobRef {
+            sha256: sha256(content.as_bytes()),
+            bytes: content.len() as u64,
+        };
+        let path = self.path(&blob);
+        if !path.exists() {
+            write_atomically(&path, content.as_bytes())
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+        Ok(blob)
+    }
+
+    /// The content of `blob`, checked against its hash
+    pub fn get(&self, blob: &BlobRef) -> Result<String> {
+        let path = self.path(blob);
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if sha256(content.as_bytes()) != blob.sha256 {
+            return Err(anyhow!(
+                "{} doesn't hold what the session saved there",
+                path.display()
+            ));
+        }
+        Ok(content)
+    }
+}
+
+/// The SHA-256 of `bytes`, in hex
+fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// `bytes` as "512 B", "12.3 KB" or "4.2 MB"
+pub fn size(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{} B", bytes),
+        1024..1_048_576 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+/// What a message holds while its content is in `blob`
+pub fn placeholder(blob: &BlobRef) -> String {
+    format!(
+        "[{} moved out of the session file to blob {}; select the message and press Enter to load it]",
+        size(blob.bytes),
+        &blob.sha256[..12.min(blob.sha256.len())]
+    )
+}
+
+/// What a message holds once `sentinel sessions prune` dropped its blob
+pub fn dropped(blob: &BlobRef) -> String {
+    format!(
+        "[{} dropped from the session by `sentinel sessions prune`]",
+        size(blob.bytes)
+    )
+}
+
+/// Move the contents of `file`'s messages to blobs in `store`, oldest first,
+/// until the file fits in `config`'s size. Only contents of at least
+/// `blob_min_kb` are moved; returns how many were
+pub fn extract(
+    file: &mut SessionFile,
+    store: &BlobStore,
+    config: &SessionsConfig,
+) -> Result<usize> {
+    let mut length = file.to_json()?.len() as u64;
+    let mut moved = 0;
+    for message in file.all_messages_mut() {
+        if length <= config.max_size() {
+            break;
+        }
+        if message.blob.is_some() || message.content.len() < config.blob_min() {
+            continue;
+        }
+        let blob = store.put(&message.content)?;
+        let before = serde_json::to_string(&message.content)?.len() as u64;
+        message.content = placeholder(&blob);
+        let after =
+            serde_json::to_string(&message.content)?.len() + serde_json::to_string(&blob)?.len();
+        length = length.saturating_sub(before).saturating_add(after as u64);
+        message.blob = Some(blob);
+        moved += 1;
+    }
+    Ok(moved)
+}
+
+/// Which blobs `sentinel sessions prune` drops: those older than
+/// `older_than`, and those larger than `larger_than` bytes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PruneRules {
+    pub older_than: Option<Duration>,
+    pub larger_than: Option<u64>,
+}
+
+impl PruneRules {
+    /// Whether a blob of `bytes` written at `modified` is dropped, as of `now`.
+    /// A blob whose file is gone is dropped whatever the rules
+    pub fn selects(&self, bytes: u64, modified: Option<SystemTime>, now: SystemTime) -> bool {
+        let Some(modified) = modified else {
+            return true;
+        };
+        let old = self
+            .older_than
+            .is_some_and(|limit| now.duration_since(modified).is_ok_and(|age| age > limit));
+        let large = self.larger_than.is_some_and(|limit| bytes > limit);
+        old || large
+    }
+}
+
+/// What pruning a session did
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pruned {
+    /// Blobs the session no longer refers to
+    pub dropped: usize,
+    /// Their bytes
+    pub bytes: u64,
+    /// Blob files removed, as no other session refers to them
+    pub removed: usize,
+}
+
+/// Drop the blobs of the session file at `path` that `rules` select, as of
+/// `now`, and write it back; with `dry_run`, only say what would go
+pub fn prune(path: &Path, rules: &PruneRules, now: SystemTime, dry_run: bool) -> Result<Pruned> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut file =
+        SessionFile::parse(&text).with_context(|| format!("Failed to parse {}", path.display()))?;
+    let store = BlobStore::next_to(path);
+
+    let mut pruned = Pruned::default();
+    let mut gone = Vec::new();
+    for message in file.all_messages_mut() {
+        let Some(blob) = message.blob.clone() else {
+            continue;
+        };
+        let modified = fs::metadata(store.path(&blob))
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if !rules.selects(blob.bytes, modified, now) {
+            continue;
+        }
+        pruned.dropped += 1;
+        pruned.bytes += blob.bytes;
+        message.content = dropped(&blob);
+        message.blob = None;
+        gone.push(blob);
+    }
+    if dry_run || pruned.dropped == 0 {
+        return Ok(pruned);
+    }
+
+    write_atomically(path, file.to_json()?.as_bytes())
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    for blob in gone {
+        let blob_path = store.path(&blob);
+        if blob_path.exists() && !referred_to(path, &blob)? && fs::remove_file(&blob_path).is_ok() {
+            pruned.removed += 1;
+        }
+    }
+    Ok(pruned)
+}
+
+// Whether a session file in the directory of `session`, itself included,
+// still names `blob`
+fn referred_to(session: &Path, blob: &BlobRef) -> Result<bool> {
+    let dir = session.parent().unwrap_or(Path::new("."));
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+            && fs::read_to_string(&path).is_ok_and(|text| text.contains(&blob.sha256))
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::SessionMeta;
+    use crate::tui::message::UiMessage;
+    use tempfile::TempDir;
+
+    const BLOBBED: &str = include_str!("../tests/fixtures/sessions/blobbed.json");
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    // The blobbed fixture and its blobs in a directory of their own, the
+    // blobs written `ages` ago; a blob without an age is left out
+    fn fixture(ages: [Option<Duration>; 2]) -> Result<(TempDir, PathBuf)> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("session.json");
+        fs::write(&path, BLOBBED)?;
+        let store = BlobStore::next_to(&path);
+        let file = SessionFile::parse(BLOBBED)?;
+        let blobs = file
+            .messages
+            .iter()
+            .filter_map(|message| message.blob.as_ref());
+        for (blob, age) in blobs.zip(ages) {
+            let Some(age) = age else { continue };
+            let blobs = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sessions/blobs");
+            let content = fs::read_to_string(blobs.join(&blob.sha256))?;
+            assert_eq!(store.put(&content)?, *blob);
+            fs::File::options()
+                .write(true)
+                .open(store.path(blob))?
+                .set_modified(SystemTime::now() - age)?;
+        }
+        Ok((dir, path))
+    }
+
+    #[test]
+    fn test_long_contents_move_to_blobs_and_back() -> Result<()> {
+        let dir = TempDir::new()?;
+        let store = BlobStore::next_to(&dir.path().join("session.json"));
+        let output = "line of build output\n".repeat(2_000);
+        let messages = vec![
+            UiMessage::user("Build it".to_string()),
+            UiMessage::user(output.clone()),
+            UiMessage::user("short".repeat(10)),
+            UiMessage::user(output.replace("build", "test")),
+        ];
+        let mut file = SessionFile::new(&SessionMeta::default(), &messages);
+        let config = SessionsConfig {
+            max_size_kb: 50,
+            blob_min_kb: 1,
+        };
+
+        // Only the oldest long content has to go for the file to fit
+        assert_eq!(extract(&mut file, &store, &config)?, 1);
+        let text = file.to_json()?;
+        assert!(text.len() < 50 * 1024);
+        let blob = file.messages[1].blob.clone().unwrap();
+        assert_eq!(blob.bytes, output.len() as u64);
+        assert_eq!(file.messages[1].content, placeholder(&blob));
+        assert!(file.messages[1]
+            .content
+            .starts_with("[41.0 KB moved out of the session file"));
+        assert!(file.messages[3].blob.is_none());
+
+        // The reference survives a save, and the content comes back from it
+        let read = SessionFile::parse(&text)?;
+        assert_eq!(read.messages[1].blob.as_ref(), Some(&blob));
+        assert_eq!(store.get(&blob)?, output);
+        // The same content is the same blob
+        assert_eq!(store.put(&output)?, blob);
+
+        // A blob changed behind the session's back is refused
+        fs::write(store.path(&blob), "something else")?;
+        assert!(store.get(&blob).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_prune_selects_old_and_large_blobs() -> Result<()> {
+        let now = SystemTime::now();
+        let rules = PruneRules {
+            older_than: Some(30 * DAY),
+            larger_than: None,
+        };
+        // The fixture's blobs are 1200 and 300 bytes long
+        let (dir, path) = fixture([Some(40 * DAY), Some(DAY)])?;
+        let pruned = prune(&path, &rules, now, false)?;
+        assert_eq!(
+            pruned,
+            Pruned {
+                dropped: 1,
+                bytes: 1200,
+                removed: 1
+            }
+        );
+        let file = SessionFile::parse(&fs::read_to_string(&path)?)?;
+        assert_eq!(
+            file.messages[1].content,
+            "[1.2 KB dropped from the session by `sentinel sessions prune`]"
+        );
+        assert!(file.messages[1].blob.is_none());
+        assert!(file.messages[3].blob.is_some());
+        assert_eq!(fs::read_dir(dir.path().join(BLOB_DIR))?.count(), 1);
+
+        // Size alone selects too, and a dry run leaves everything as it was
+        let (_dir, path) = fixture([Some(DAY), Some(DAY)])?;
+        let large = PruneRules {
+            older_than: None,
+            larger_than: Some(1024),
+        };
+        let pruned = prune(&path, &large, now, true)?;
+        assert_eq!((pruned.dropped, pruned.removed), (1, 0));
+        assert_eq!(fs::read_to_string(&path)?, BLOBBED);
+        assert_eq!(
+            prune(&path, &PruneRules::default(), now, false)?,
+            Pruned::default()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_blobs_other_sessions_use_are_kept() -> Result<()> {
+        // The second blob is missing, which drops it whatever the rules
+        let (dir, path) = fixture([Some(40 * DAY), None])?;
+        fs::copy(&path, dir.path().join("copy.json"))?;
+        let rules = PruneRules {
+            older_than: Some(30 * DAY),
+            larger_than: None,
+        };
+        let pruned = prune(&path, &rules, SystemTime::now(), false)?;
+        assert_eq!(
+            pruned,
+            Pruned {
+                dropped: 2,
+                bytes: 1500,
+                removed: 0
+            }
+        );
+        assert_eq!(fs::read_dir(dir.path().join(BLOB_DIR))?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sizes() {
+        assert_eq!(size(512), "512 B");
+        assert_eq!(size(12_595), "12.3 KB");
+        assert_eq!(size(4_404_019), "4.2 MB");
+    }
+}
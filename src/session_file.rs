@@ -0,0 +1,588 @@
+//! The format saved sessions are written in, and reading the older ones.
+//!
+//! A session file is JSON: a format version, the [`SessionMeta`] header and
+//! the messages as [`StoredMessage`]s. Those are kept apart from [`Message`]
+//! and [`UiMessage`], so a field added to either for the running session
+//! doesn't change what is on disk. Files of an older format are upgraded one
+//! version at a time when read; `tests/fixtures/sessions` holds one of each.
+//!
+//! - Format 0 is a bare array of messages, from before the header.
+//! - Format 1 is `{"meta": ..., "messages": [...]}`, without a version.
+//! - Format 2 adds the version, renames `meta` to `metadata` and keeps the
+//!   token counts, tools used and reasoning of each reply.
+//!
+//! A conversation that was forked also keeps its `branches`, the active one
+//! naming no messages of its own as they are `messages`. Files without them
+//! hold a conversation with only the main branch.
+//!
+//! A message of any format may have had its content moved to a blob file, in
+//! which case `blob` names the file and the content says where it went; see
+//! [`session_blobs`](crate::session_blobs).
+//!
+//! The undo journal kept with a session is versioned the same way, as a
+//! [`JournalFile`]; `tests/fixtures/journals` holds one of each format.
+//!
+//! - Format 0 is a bare array of entries.
+//! - Format 1 is `{"version": 1, "entries": [...]}`.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::images::{ImageAttachment, ImageFormat};
+use crate::session::{Branch, ForkPoint, SessionMeta, Sessions};
+use crate::session_blobs::BlobRef;
+use crate::tools::journal::Entry;
+use crate::tui::message::{MessageRole, UiMessage};
+use crate::{Message, Role};
+
+/// The format written, and the newest one read
+pub const VERSION: u32 = 2;
+
+/// The format undo journals are written in, and the newest one read
+pub const JOURNAL_VERSION: u32 = 1;
+
+/// A saved session: its format, what it is about, and its messages
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionFile {
+    pub version: u32,
+    pub metadata: SessionMeta,
+    /// The messages of the active branch
+    pub messages: Vec<StoredMessage>,
+    /// Every branch, once the conversation has been forked
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub branches: Vec<StoredBranch>,
+}
+
+/// A branch of a saved session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredBranch {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<ForkPoint>,
+    /// Whether this is the active branch, whose messages are the file's
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub active: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<StoredMessage>,
+}
+
+/// A saved undo journal: its format and the operations it can undo, oldest first
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalFile {
+    pub version: u32,
+    pub entries: Vec<Entry>,
+}
+
+/// Who sent a saved message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoredRole {
+    User,
+    Assistant,
+    System,
+    Tool,
+}
+
+/// An image sent with a saved message; the image itself is not saved
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredImage {
+    pub path: String,
+    pub format: ImageFormat,
+    pub size: u64,
+}
+
+/// A message as it is saved
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredMessage {
+    pub role: StoredRole,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub input_tokens: usize,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub tool_output_tokens: usize,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub output_tokens: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub used_tools: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternatives: Vec<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub model: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub provider: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<StoredImage>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub local_sources: Vec<String>,
+    /// Shown in the TUI but never sent back to the model
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ephemeral: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub error: bool,
+    /// The blob file the content was moved to, when the session grew too large
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<BlobRef>,
+}
+
+fn is_zero(count: &usize) -> bool {
+    *count == 0
+}
+
+impl SessionFile {
+    /// A file of the current format holding `messages`
+    pub fn new<'a, M: 'a>(meta: &SessionMeta, messages: &'a [M]) -> Self
+    where
+        StoredMessage: From<&'a M>,
+    {
+        Self {
+            version: VERSION,
+            metadata: SessionMeta {
+                message_count: messages.len(),
+                ..meta.clone()
+            },
+            messages: messages.iter().map(StoredMessage::from).collect(),
+            branches: Vec::new(),
+        }
+    }
+
+    /// A file of the current format holding every branch of `sessions`
+    pub fn with_branches<'a, M: Clone + 'a>(meta: &SessionMeta, sessions: &'a Sessions<M>) -> Self
+    where
+        StoredMessage: From<&'a M>,
+    {
+        let mut file = Self::new(meta, sessions.messages());
+        if sessions.branches().len() > 1 {
+            file.branches = sessions
+                .branches()
+                .iter()
+                .enumerate()
+                .map(|(index, branch)| {
+                    let active = index == sessions.active_index();
+                    StoredBranch {
+                        name: branch.name.clone(),
+                        parent: branch.parent.clone(),
+                        active,
+                        messages: if active {
+                            Vec::new()
+                        } else {
+                            branch.messages.iter().map(StoredMessage::from).collect()
+                        },
+                    }
+                })
+                .collect();
+        }
+        file
+    }
+
+    /// Read a session file of any format up to [`VERSION`]
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut value: Value = serde_json::from_str(text)?;
+        let version = version_of(&value)?;
+        check_version("session", version, VERSION)?;
+        for from in version..VERSION {
+            value = upgrade(value, from)?;
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// The file as it is written
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// The messages, as the TUI or the agent holds them
+    pub fn into_messages<M: From<StoredMessage>>(self) -> Vec<M> {
+        self.messages.into_iter().map(M::from).collect()
+    }
+
+    /// Every branch, with the active one active again. A file whose
+    /// branches don't name an active one holds only its messages
+    pub fn into_sessions<M: From<StoredMessage> + Clone>(self) -> Sessions<M> {
+        let active = self.branches.iter().position(|branch| branch.active);
+        let Some(active) = active else {
+            return Sessions::new(self.into_messages());
+        };
+        let mut messages = Some(self.messages);
+        let branches = self
+            .branches
+            .into_iter()
+            .map(|branch| {
+                let stored = if branch.active {
+                    messages.take().unwrap_or_default()
+                } else {
+                    branch.messages
+                };
+                Branch {
+                    name: branch.name,
+                    parent: branch.parent,
+                    messages: stored.into_iter().map(M::from).collect(),
+                }
+            })
+            .collect();
+        Sessions::from_branches(branches, active).expect("the active branch is one of them")
+    }
+
+    /// Every message of the file, the active branch's first
+    pub fn all_messages_mut(&mut self) -> impl Iterator<Item = &mut StoredMessage> {
+        let branches = self
+            .branches
+            .iter_mut()
+            .flat_map(|branch| &mut branch.messages);
+        self.messages.iter_mut().chain(branches)
+    }
+}
+
+impl JournalFile {
+    /// A file of the current format holding `entries`
+    pub fn new(entries: Vec<Entry>) -> Self {
+        Self {
+            version: JOURNAL_VERSION,
+            entries,
+        }
+    }
+
+    /// Read a journal of any format up to [`JOURNAL_VERSION`]
+    pub fn parse(text: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(text)?;
+        let version = match &value {
+            Value::Array(_) => 0,
+            Value::Object(object) => object
+                .get("version")
+                .and_then(Value::as_u64)
+                .and_then(|version| u32::try_from(version).ok())
+                .ok_or_else(|| anyhow!("The journal has no format version"))?,
+            _ => return Err(anyhow!("Not a journal file")),
+        };
+        check_version("journal", version, JOURNAL_VERSION)?;
+        if version == 0 {
+            return Ok(Self::new(serde_json::from_value(value)?));
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// The file as it is written
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+}
+
+/// Refuse a `kind` of file of a format newer than `newest`, which this
+/// version can't read
+pub fn check_version(kind: &str, version: u32, newest: u32) -> Result<()> {
+    if version > newest {
+        return Err(anyhow!(
+            "Created by a newer sentinel ({} format {}, this one reads up to {}); upgrade sentinel to open it",
+            kind,
+            version,
+            newest
+        ));
+    }
+    Ok(())
+}
+
+// The format of a file: its version, or what its shape says for older ones
+fn version_of(value: &Value) -> Result<u32> {
+    match value {
+        Value::Array(_) => Ok(0),
+        Value::Object(object) => match object.get("version") {
+            None => Ok(1),
+            Some(version) => version
+                .as_u64()
+                .and_then(|version| u32::try_from(version).ok())
+                .ok_or_else(|| anyhow!("The session format {} is not a version number", version)),
+        },
+        _ => Err(anyhow!("Not a session file")),
+    }
+}
+
+// A file of format `from` brought to the next
+fn upgrade(value: Value, from: u32) -> Result<Value> {
+    match from {
+        0 => {
+            let count = value.as_array().map_or(0, Vec::len);
+            Ok(json!({ "meta": { "message_count": count }, "messages": value }))
+        }
+        1 => {
+            let Value::Object(mut object) = value else {
+                return Err(anyhow!("Not a session file"));
+            };
+            let meta = object
+                .remove("meta")
+                .unwrap_or_else(|| Value::Object(Map::new()));
+            object.insert("version".to_string(), json!(2));
+            object.insert("metadata".to_string(), meta);
+            Ok(Value::Object(object))
+        }
+        _ => Ok(value),
+    }
+}
+
+impl From<&Role> for StoredRole {
+    fn from(role: &Role) -> Self {
+        match role {
+            Role::User => StoredRole::User,
+            Role::Assistant => StoredRole::Assistant,
+            Role::System => StoredRole::System,
+            Role::Tool => StoredRole::Tool,
+        }
+    }
+}
+
+impl From<StoredRole> for Role {
+    fn from(role: StoredRole) -> Self {
+        match role {
+            StoredRole::User => Role::User,
+            StoredRole::Assistant => Role::Assistant,
+            StoredRole::System => Role::System,
+            StoredRole::Tool => Role::Tool,
+        }
+    }
+}
+
+impl From<&MessageRole> for StoredRole {
+    fn from(role: &MessageRole) -> Self {
+        match role {
+            MessageRole::User => StoredRole::User,
+            MessageRole::Assistant => StoredRole::Assistant,
+            MessageRole::System => StoredRole::System,
+        }
+    }
+}
+
+impl From<StoredRole> for MessageRole {
+    fn from(role: StoredRole) -> Self {
+        MessageRole::from(Role::from(role))
+    }
+}
+
+impl From<&ImageAttachment> for StoredImage {
+    fn from(image: &ImageAttachment) -> Self {
+        Self {
+            path: image.path.clone(),
+            format: image.format,
+            size: image.size,
+        }
+    }
+}
+
+impl From<StoredImage> for ImageAttachment {
+    fn from(image: StoredImage) -> Self {
+        Self {
+            path: image.path,
+            format: image.format,
+            size: image.size,
+            data: String::new(),
+        }
+    }
+}
+
+impl From<&Message> for StoredMessage {
+    fn from(message: &Message) -> Self {
+        Self {
+            role: StoredRole::from(&message.role),
+            content: message.content.clone(),
+            input_tokens: message.input_tokens,
+            tool_output_tokens: message.tool_output_tokens,
+            output_tokens: message.output_tokens,
+            used_tools: message.used_tools.clone(),
+            reasoning: message.reasoning.clone(),
+            alternatives: message.alternatives.clone(),
+            model: message.model.clone(),
+            provider: message.provider.clone(),
+            images: message.images.iter().map(StoredImage::from).collect(),
+            pinned: message.pinned,
+            truncated: message.truncated,
+            local_sources: message.local_sources.clone(),
+            ephemeral: false,
+            error: false,
+            blob: None,
+        }
+    }
+}
+
+impl From<StoredMessage> for Message {
+    fn from(stored: StoredMessage) -> Self {
+        let images = stored
+            .images
+            .into_iter()
+            .map(ImageAttachment::from)
+            .collect();
+        let mut message = Message::new(Role::from(stored.role), stored.content).with_images(images);
+        message.input_tokens = stored.input_tokens;
+        message.tool_output_tokens = stored.tool_output_tokens;
+        message.output_tokens = stored.output_tokens;
+        message.used_tools = stored.used_tools;
+        message.reasoning = stored.reasoning;
+        message.alternatives = stored.alternatives;
+        message.model = stored.model;
+        message.provider = stored.provider;
+        message.pinned = stored.pinned;
+        message.truncated = stored.truncated;
+        message.local_sources = stored.local_sources;
+        message
+    }
+}
+
+impl From<&UiMessage> for StoredMessage {
+    fn from(message: &UiMessage) -> Self {
+        Self {
+            role: StoredRole::from(&message.role),
+            content: message.content.clone(),
+            input_tokens: message.input_tokens,
+            tool_output_tokens: message.tool_output_tokens,
+            output_tokens: message.output_tokens,
+            used_tools: message.used_tools.clone(),
+            reasoning: message.reasoning.clone(),
+            alternatives: Vec::new(),
+            model: message.model.clone(),
+            provider: message.provider.clone(),
+            images: message.images.iter().map(StoredImage::from).collect(),
+            pinned: message.pinned,
+            truncated: message.truncated,
+            local_sources: message.local_sources.clone(),
+            ephemeral: message.ephemeral,
+            error: message.error,
+            blob: message.blob.clone(),
+        }
+    }
+}
+
+impl From<StoredMessage> for UiMessage {
+    fn from(stored: StoredMessage) -> Self {
+        let mut message = UiMessage::new(MessageRole::from(stored.role), stored.content);
+        message.input_tokens = stored.input_tokens;
+        message.tool_output_tokens = stored.tool_output_tokens;
+        message.output_tokens = stored.output_tokens;
+        message.used_tools = stored.used_tools;
+        message.reasoning = stored.reasoning;
+        message.model = stored.model;
+        message.provider = stored.provider;
+        message.images = stored
+            .images
+            .into_iter()
+            .map(ImageAttachment::from)
+            .collect();
+        message.pinned = stored.pinned;
+        message.truncated = stored.truncated;
+        message.local_sources = stored.local_sources;
+        message.ephemeral = stored.ephemeral;
+        message.error = stored.error;
+        message.blob = stored.blob;
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V0: &str = include_str!("../tests/fixtures/sessions/v0.json");
+    const V1: &str = include_str!("../tests/fixtures/sessions/v1.json");
+    const V2: &str = include_str!("../tests/fixtures/sessions/v2.json");
+
+    #[test]
+    fn test_every_historical_format_loads() -> Result<()> {
+        for fixture in [V0, V1, V2] {
+            let file = SessionFile::parse(fixture)?;
+            assert_eq!(file.version, VERSION);
+            assert_eq!(file.metadata.message_count, 3);
+            let messages: Vec<UiMessage> = file.into_messages();
+            assert_eq!(messages[1].content, "Name my cat");
+            assert_eq!(messages[2].role, MessageRole::Assistant);
+            assert_eq!(messages[2].model, "llama3.2");
+        }
+
+        let v1 = SessionFile::parse(V1)?;
+        assert_eq!(v1.metadata.title.as_deref(), Some("Naming a cat"));
+        assert!(v1.messages[1].pinned);
+        assert_eq!(v1.messages[1].images[0].format, ImageFormat::Png);
+
+        let v2 = SessionFile::parse(V2)?;
+        assert_eq!(v2.messages[2].output_tokens, 12);
+        assert_eq!(v2.messages[2].used_tools, ["memory"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_the_current_format_survives_a_round_trip() -> Result<()> {
+        let file = SessionFile::parse(V2)?;
+        assert_eq!(SessionFile::parse(&file.to_json()?)?, file);
+
+        let messages: Vec<Message> = file.clone().into_messages();
+        let again = SessionFile::new(&file.metadata, &messages);
+        assert_eq!(again.messages, file.messages);
+        Ok(())
+    }
+
+    #[test]
+    fn test_usage_is_saved_with_the_session() -> Result<()> {
+        let mut meta = SessionMeta::new("llama3.2");
+        meta.stats.record(
+            120,
+            30,
+            &["bash".to_string()],
+            std::time::Duration::from_secs(2),
+            None,
+        );
+        let file = SessionFile::new(&meta, &[Message::user("hi")]);
+        let read = SessionFile::parse(&file.to_json()?)?;
+        assert_eq!(read.metadata.stats, meta.stats);
+        assert_eq!(read.metadata.stats.requests, 1);
+
+        // Sessions saved before usage was kept start from nothing
+        assert_eq!(SessionFile::parse(V2)?.metadata.stats.requests, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_newer_formats_are_refused() {
+        let newer = format!(
+            r#"{{"version": {}, "metadata": {{}}, "messages": []}}"#,
+            VERSION + 1
+        );
+        let error = SessionFile::parse(&newer).unwrap_err().to_string();
+        assert!(error.contains("Created by a newer sentinel"), "{}", error);
+        assert!(SessionFile::parse(r#"{"version": "two"}"#).is_err());
+        assert!(SessionFile::parse(r#""hello""#).is_err());
+        assert!(SessionFile::parse(r#"{"messages": 3}"#).is_err());
+    }
+
+    const JOURNAL_V0: &str = include_str!("../tests/fixtures/journals/v0.json");
+    const JOURNAL_V1: &str = include_str!("../tests/fixtures/journals/v1.json");
+
+    #[test]
+    fn test_every_journal_format_loads() -> Result<()> {
+        let v0 = JournalFile::parse(JOURNAL_V0)?;
+        let v1 = JournalFile::parse(JOURNAL_V1)?;
+        assert_eq!(v0, v1);
+        assert_eq!(v1.version, JOURNAL_VERSION);
+        assert_eq!(v1.entries.len(), 2);
+        assert_eq!(v1.entries[0].operation.describe(), "wrote notes.txt");
+        assert_eq!(v1.entries[1].operation.describe(), "created todo.txt");
+        assert_eq!(JournalFile::parse(&v1.to_json()?)?, v1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_newer_journal_formats_are_refused() {
+        let newer = format!(r#"{{"version": {}, "entries": []}}"#, JOURNAL_VERSION + 1);
+        let error = JournalFile::parse(&newer).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            format!(
+                "Created by a newer sentinel (journal format {}, this one reads up to {}); upgrade sentinel to open it",
+                JOURNAL_VERSION + 1,
+                JOURNAL_VERSION
+            )
+        );
+        assert!(JournalFile::parse(r#"{"entries": []}"#).is_err());
+        assert!(JournalFile::parse(r#""hello""#).is_err());
+    }
+}
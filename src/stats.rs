@@ -0,0 +1,641 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::llm::prefix::PromptUsage;
+use crate::llm::timings::{self, ServerTimings};
+
+/// Price of a model in dollars per million tokens
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input: f64,
+    pub output: f64,
+}
+
+/// Per-model prices used to estimate the cost of paid providers.
+///
+/// Local models are free and simply have no entry. Prices are read from the
+/// `SENTINEL_PRICES` environment variable as JSON, e.g.
+/// `{"claude-3-5-sonnet": {"input": 3.0, "output": 15.0}}`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PriceTable {
+    prices: HashMap<String, ModelPrice>,
+}
+
+impl PriceTable {
+    pub fn new(prices: HashMap<String, ModelPrice>) -> Self {
+        Self { prices }
+    }
+
+    /// Load the price table from `SENTINEL_PRICES`, ignoring it when unset or malformed
+    pub fn from_env() -> Self {
+        std::env::var("SENTINEL_PRICES")
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .map(Self::new)
+            .unwrap_or_default()
+    }
+
+    pub fn price_for(&self, model: &str) -> Option<ModelPrice> {
+        self.prices.get(model).copied()
+    }
+}
+
+/// Estimate the tokens of `text` at about four characters a token, as the
+/// clients do when the server doesn't count them
+pub fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
+
+/// Where the tokens of one turn went: the prompt, the tool output folded into
+/// it, and the reply
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenBreakdown {
+    pub prompt: usize,
+    pub tool_output: usize,
+    pub output: usize,
+}
+
+impl TokenBreakdown {
+    pub fn new(prompt: usize, tool_output: usize, output: usize) -> Self {
+        Self {
+            prompt,
+            tool_output,
+            output,
+        }
+    }
+
+    /// Everything sent to the model: the prompt and the tool output
+    pub fn input(&self) -> usize {
+        self.prompt + self.tool_output
+    }
+}
+
+impl fmt::Display for TokenBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "input {}", compact(self.prompt))?;
+        if self.tool_output > 0 {
+            write!(f, " + tools {}", compact(self.tool_output))?;
+        }
+        write!(f, " → output {}", compact(self.output))
+    }
+}
+
+/// A token count in a few characters: 950, 1.2k, 34.0k
+pub fn compact(tokens: usize) -> String {
+    if tokens < 1000 {
+        tokens.to_string()
+    } else {
+        format!("{:.1}k", tokens as f64 / 1000.0)
+    }
+}
+
+/// Tool calls taking longer than this are highlighted, unless the config
+/// sets `tools.slow_call_secs`
+pub const DEFAULT_SLOW_CALL: Duration = Duration::from_secs(10);
+
+/// How long one tool call took, e.g. "bash: 2.4s" or "file read: 12ms"
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ToolTiming {
+    /// The tool, followed by the operation for tools that have several
+    pub label: String,
+    /// The tool alone, which totals are kept by
+    pub tool: String,
+    pub duration: Duration,
+}
+
+impl ToolTiming {
+    /// Whether the call took `threshold` or longer
+    pub fn is_slow(&self, threshold: Duration) -> bool {
+        self.duration >= threshold
+    }
+}
+
+impl fmt::Display for ToolTiming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.label, elapsed(self.duration))
+    }
+}
+
+/// A duration in a few characters: 12ms under a second, 2.4s above
+pub fn elapsed(duration: Duration) -> String {
+    if duration < Duration::from_secs(1) {
+        format!("{}ms", duration.as_millis())
+    } else {
+        format!("{:.1}s", duration.as_secs_f64())
+    }
+}
+
+/// Usage of one model within a session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelUsage {
+    pub requests: usize,
+    pub input_tokens: usize,
+    pub output_tokens: usize,
+}
+
+/// Cumulative usage of a session
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionStats {
+    pub requests: usize,
+    /// Tokens of the prompts, without the tool output folded into them
+    pub input_tokens: usize,
+    /// Tokens of tool output sent back to the model, counted each time it is sent
+    #[serde(default)]
+    pub tool_output_tokens: usize,
+    pub output_tokens: usize,
+    /// The part of `output_tokens` spent in think blocks
+    #[serde(default)]
+    pub reasoning_tokens: usize,
+    /// Tokens of the requests summarizing long tool output, prompts and
+    /// replies together
+    #[serde(default)]
+    pub summary_tokens: usize,
+    pub tool_calls: BTreeMap<String, usize>,
+    /// How long the calls of each tool took in total
+    #[serde(default)]
+    pub tool_time: BTreeMap<String, Duration>,
+    /// Tool calls refused for going over a rate limit
+    #[serde(default)]
+    pub rate_limited_calls: usize,
+    /// Tool calls with malformed arguments that were fixed up or sent back
+    /// to the model to correct, instead of failing the turn
+    #[serde(default)]
+    pub recovered_tool_calls: usize,
+    /// Requests and tokens broken down by the model that served them
+    #[serde(default)]
+    pub per_model: BTreeMap<String, ModelUsage>,
+    /// How much of the prompts Ollama evaluated, and how much it had cached
+    #[serde(default)]
+    pub prompt_usage: PromptUsage,
+    pub generation_time: Duration,
+    /// Where the server said the generation time went
+    #[serde(default)]
+    pub server_timings: ServerTimings,
+    pub estimated_cost: f64,
+}
+
+impl SessionStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the usage of one completed request
+    pub fn record(
+        &mut self,
+        input_tokens: usize,
+        output_tokens: usize,
+        used_tools: &[String],
+        elapsed: Duration,
+        price: Option<ModelPrice>,
+    ) {
+        self.requests += 1;
+        self.input_tokens += input_tokens;
+        self.output_tokens += output_tokens;
+        self.generation_time += elapsed;
+
+        for tool in used_tools {
+            *self.tool_calls.entry(tool.clone()).or_insert(0) += 1;
+        }
+
+        if let Some(price) = price {
+            self.estimated_cost += (input_tokens as f64 * price.input
+                + output_tokens as f64 * price.output)
+                / 1_000_000.0;
+        }
+    }
+
+    /// Add the tool output sent to the model while answering a recorded
+    /// request, priced as input
+    pub fn record_tool_output(&mut self, tokens: usize, price: Option<ModelPrice>) {
+        self.tool_output_tokens += tokens;
+        if let Some(price) = price {
+            self.estimated_cost += tokens as f64 * price.input / 1_000_000.0;
+        }
+    }
+
+    /// Attribute output tokens of a recorded request to reasoning
+    pub fn record_reasoning(&mut self, tokens: usize) {
+        self.reasoning_tokens += tokens;
+    }
+
+    /// Add the tokens spent summarizing tool output while answering a recorded request
+    pub fn record_summaries(&mut self, tokens: usize) {
+        self.summary_tokens += tokens;
+    }
+
+    /// Attribute a recorded request to the model that served it
+    pub fn record_model(&mut self, model: &str, input_tokens: usize, output_tokens: usize) {
+        let usage = self.per_model.entry(model.to_string()).or_default();
+        usage.requests += 1;
+        usage.input_tokens += input_tokens;
+        usage.output_tokens += output_tokens;
+    }
+
+    /// Add how long the tool calls of a recorded request took
+    pub fn record_tool_timings(&mut self, timings: &[ToolTiming]) {
+        for timing in timings {
+            *self.tool_time.entry(timing.tool.clone()).or_default() += timing.duration;
+        }
+    }
+
+    /// Count tool calls that were refused for going over a rate limit
+    pub fn record_rate_limited(&mut self, calls: usize) {
+        self.rate_limited_calls += calls;
+    }
+
+    /// Count tool calls whose malformed arguments were recovered from
+    pub fn record_recovered(&mut self, calls: usize) {
+        self.recovered_tool_calls += calls;
+    }
+
+    /// Add what Ollama reported evaluating of the prompts of recorded requests
+    pub fn record_prompt_usage(&mut self, usage: PromptUsage) {
+        self.prompt_usage.add(usage);
+    }
+
+    /// Add what the server reported spending on recorded requests
+    pub fn record_server_timings(&mut self, timings: ServerTimings) {
+        self.server_timings.add(timings);
+    }
+
+    /// Total number of tool calls across all tools
+    pub fn total_tool_calls(&self) -> usize {
+        self.tool_calls.values().sum()
+    }
+
+    /// One-line summary printed when a session ends
+    pub fn summary(&self) -> String {
+        let mut tokens = if self.tool_output_tokens > 0 {
+            format!(
+                "{} input + {} tool output / {} output tokens",
+                self.input_tokens, self.tool_output_tokens, self.output_tokens
+            )
+        } else {
+            format!(
+                "{} input / {} output tokens",
+                self.input_tokens, self.output_tokens
+            )
+        };
+        if self.reasoning_tokens > 0 {
+            tokens.push_str(&format!(" (~{} reasoning)", self.reasoning_tokens));
+        }
+
+        let mut summary = format!(
+            "{} {}, {}, {} tool {}, {:.1}s generating",
+            self.requests,
+            plural(self.requests, "request", "requests"),
+            tokens,
+            self.total_tool_calls(),
+            plural(self.total_tool_calls(), "call", "calls"),
+            self.generation_time.as_secs_f64()
+        );
+
+        if self.rate_limited_calls > 0 {
+            summary.push_str(&format!(", {} rate-limited", self.rate_limited_calls));
+        }
+        if self.recovered_tool_calls > 0 {
+            summary.push_str(&format!(
+                ", {} malformed recovered",
+                self.recovered_tool_calls
+            ));
+        }
+        if self.estimated_cost > 0.0 {
+            summary.push_str(&format!(", ~${:.4}", self.estimated_cost));
+        }
+
+        summary
+    }
+
+    /// Detailed report for the /stats command
+    pub fn report(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("Requests: {}", self.requests),
+            format!("Input tokens: {}", self.input_tokens),
+        ];
+        if self.tool_output_tokens > 0 {
+            lines.push(format!("Tool output tokens: {}", self.tool_output_tokens));
+            lines.push(format!(
+                "  sent in total: {}",
+                self.input_tokens + self.tool_output_tokens
+            ));
+        }
+        lines.push(format!("Output tokens: {}", self.output_tokens));
+        if self.reasoning_tokens > 0 {
+            lines.push(format!("  of which reasoning: ~{}", self.reasoning_tokens));
+        }
+        if self.summary_tokens > 0 {
+            lines.push(format!(
+                "Summarizing tool output: {} tokens",
+                self.summary_tokens
+            ));
+        }
+        lines.extend([format!(
+            "Generation time: {:.1}s",
+            self.generation_time.as_secs_f64()
+        )]);
+        if !self.server_timings.is_empty() {
+            lines.push(format!(
+                "  loading the model: {}",
+                timings::seconds(self.server_timings.load)
+            ));
+            lines.push(format!(
+                "  reading prompts: {}",
+                timings::seconds(self.server_timings.prompt)
+            ));
+            lines.push(format!(
+                "  writing replies: {}",
+                timings::seconds(self.server_timings.generation)
+            ));
+        }
+
+        if self.estimated_cost > 0.0 {
+            lines.push(format!("Estimated cost: ${:.4}", self.estimated_cost));
+        }
+
+        if !self.per_model.is_empty() {
+            lines.push("Tokens by model:".to_string());
+            for (model, usage) in &self.per_model {
+                lines.push(format!(
+                    "  {}: {} input / {} output ({} {})",
+                    model,
+                    usage.input_tokens,
+                    usage.output_tokens,
+                    usage.requests,
+                    plural(usage.requests, "request", "requests")
+                ));
+            }
+        }
+
+        if self.tool_calls.is_empty() {
+            lines.push("Tool calls: none".to_string());
+        } else {
+            lines.push(format!("Tool calls: {}", self.total_tool_calls()));
+            for (tool, count) in &self.tool_calls {
+                match self.tool_time.get(tool) {
+                    Some(time) => lines.push(format!("  {}: {} ({})", tool, count, elapsed(*time))),
+                    None => lines.push(format!("  {}: {}", tool, count)),
+                }
+            }
+        }
+        if self.rate_limited_calls > 0 {
+            lines.push(format!(
+                "Rate-limited tool calls: {}",
+                self.rate_limited_calls
+            ));
+        }
+        if self.recovered_tool_calls > 0 {
+            lines.push(format!(
+                "Malformed tool calls recovered: {}",
+                self.recovered_tool_calls
+            ));
+        }
+        let usage = &self.prompt_usage;
+        if usage.requests > 0 {
+            lines.push(format!(
+                "Prompt tokens evaluated: {} of ~{} sent (~{:.0}% from Ollama's cache)",
+                usage.evaluated,
+                usage.sent,
+                usage.reused_percent()
+            ));
+            lines.push(format!(
+                "  {} of {} {} began with the whole request before",
+                usage.extended,
+                usage.requests,
+                plural(usage.requests, "request", "requests")
+            ));
+        }
+
+        lines
+    }
+}
+
+fn plural<'a>(count: usize, singular: &'a str, plural: &'a str) -> &'a str {
+    if count == 1 {
+        singular
+    } else {
+        plural
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_accumulates() {
+        let mut stats = SessionStats::new();
+        stats.record(
+            100,
+            50,
+            &["bash".to_string(), "ls".to_string()],
+            Duration::from_millis(1500),
+            None,
+        );
+        stats.record(
+            200,
+            25,
+            &["bash".to_string()],
+            Duration::from_millis(500),
+            None,
+        );
+
+        assert_eq!(stats.requests, 2);
+        assert_eq!(stats.input_tokens, 300);
+        assert_eq!(stats.output_tokens, 75);
+        assert_eq!(stats.tool_calls["bash"], 2);
+        assert_eq!(stats.total_tool_calls(), 3);
+        assert_eq!(stats.generation_time, Duration::from_secs(2));
+        assert_eq!(
+            stats.summary(),
+            "2 requests, 300 input / 75 output tokens, 3 tool calls, 2.0s generating"
+        );
+    }
+
+    #[test]
+    fn test_estimated_cost_from_price_table() {
+        let table = PriceTable::new(HashMap::from([(
+            "claude".to_string(),
+            ModelPrice {
+                input: 3.0,
+                output: 15.0,
+            },
+        )]));
+
+        let mut stats = SessionStats::new();
+        stats.record(
+            1_000_000,
+            100_000,
+            &[],
+            Duration::ZERO,
+            table.price_for("claude"),
+        );
+        assert!((stats.estimated_cost - 4.5).abs() < 1e-9);
+        assert!(stats.summary().ends_with("~$4.5000"));
+
+        assert!(table.price_for("llama3.2:latest").is_none());
+    }
+
+    #[test]
+    fn test_reasoning_tokens_are_part_of_the_output() {
+        let mut stats = SessionStats::new();
+        stats.record(10, 80, &[], Duration::from_secs(1), None);
+        stats.record_reasoning(60);
+
+        assert_eq!(
+            stats.summary(),
+            "1 request, 10 input / 80 output tokens (~60 reasoning), 0 tool calls, 1.0s generating"
+        );
+        assert!(stats
+            .report()
+            .contains(&"  of which reasoning: ~60".to_string()));
+    }
+
+    #[test]
+    fn test_tokens_are_broken_down_per_model() {
+        let mut stats = SessionStats::new();
+        stats.record(100, 50, &[], Duration::from_secs(1), None);
+        stats.record_model("llama3.2", 100, 50);
+        stats.record(40, 10, &[], Duration::from_secs(1), None);
+        stats.record_model("qwen2.5", 40, 10);
+        stats.record(60, 30, &[], Duration::from_secs(1), None);
+        stats.record_model("llama3.2", 60, 30);
+
+        assert_eq!(
+            stats.per_model["llama3.2"],
+            ModelUsage {
+                requests: 2,
+                input_tokens: 160,
+                output_tokens: 80,
+            }
+        );
+        let report = stats.report();
+        assert!(report.contains(&"  llama3.2: 160 input / 80 output (2 requests)".to_string()));
+        assert!(report.contains(&"  qwen2.5: 40 input / 10 output (1 request)".to_string()));
+
+        // Stats saved before the breakdown existed still load
+        let old = r#"{"requests":1,"input_tokens":1,"output_tokens":1,"tool_calls":{},
+            "generation_time":{"secs":1,"nanos":0},"estimated_cost":0.0}"#;
+        let restored: SessionStats = serde_json::from_str(old).unwrap();
+        assert!(restored.per_model.is_empty());
+    }
+
+    #[test]
+    fn test_tool_output_is_counted_apart_from_the_prompt() {
+        let table = PriceTable::new(HashMap::from([(
+            "claude".to_string(),
+            ModelPrice {
+                input: 3.0,
+                output: 15.0,
+            },
+        )]));
+        let mut stats = SessionStats::new();
+        stats.record(
+            1200,
+            400,
+            &["file".to_string()],
+            Duration::from_secs(1),
+            table.price_for("claude"),
+        );
+        stats.record_tool_output(6800, table.price_for("claude"));
+
+        assert_eq!(
+            stats.summary(),
+            "1 request, 1200 input + 6800 tool output / 400 output tokens, 1 tool call, 1.0s generating, ~$0.0300"
+        );
+        let report = stats.report();
+        assert_eq!(
+            report[1..4],
+            [
+                "Input tokens: 1200",
+                "Tool output tokens: 6800",
+                "  sent in total: 8000"
+            ]
+        );
+        assert!((stats.estimated_cost - 0.03).abs() < 1e-9);
+
+        let turn = TokenBreakdown::new(1200, 6800, 400);
+        assert_eq!(turn.input(), 8000);
+        assert_eq!(turn.to_string(), "input 1.2k + tools 6.8k → output 400");
+        assert_eq!(
+            TokenBreakdown::new(90, 0, 12).to_string(),
+            "input 90 → output 12"
+        );
+        assert_eq!(estimate_tokens("12345"), 2);
+    }
+
+    #[test]
+    fn test_tool_time_is_totalled_per_tool() {
+        let timing = |label: &str, tool: &str, millis| ToolTiming {
+            label: label.to_string(),
+            tool: tool.to_string(),
+            duration: Duration::from_millis(millis),
+        };
+        let timings = [
+            timing("bash", "bash", 2400),
+            timing("file read", "file", 12),
+            timing("file write", "file", 30),
+        ];
+        assert_eq!(timings[0].to_string(), "bash: 2.4s");
+        assert_eq!(timings[1].to_string(), "file read: 12ms");
+        assert!(timings[0].is_slow(Duration::from_secs(2)));
+        assert!(!timings[1].is_slow(DEFAULT_SLOW_CALL));
+
+        let mut stats = SessionStats::new();
+        stats.record(
+            10,
+            20,
+            &["bash".to_string(), "file".to_string()],
+            Duration::from_secs(3),
+            None,
+        );
+        stats.record_tool_timings(&timings);
+        assert_eq!(stats.tool_time["file"], Duration::from_millis(42));
+        let report = stats.report();
+        assert!(report.contains(&"  bash: 1 (2.4s)".to_string()));
+        assert!(report.contains(&"  file: 1 (42ms)".to_string()));
+    }
+
+    #[test]
+    fn test_prompt_tokens_served_from_the_cache_are_reported() {
+        let mut stats = SessionStats::new();
+        assert!(!stats.report().iter().any(|line| line.contains("evaluated")));
+        for (extended, sent, evaluated) in [(0, 400, 410), (1, 600, 150)] {
+            stats.record_prompt_usage(PromptUsage {
+                requests: 1,
+                extended,
+                sent,
+                evaluated,
+            });
+        }
+        let report = stats.report();
+        assert!(report.contains(
+            &"Prompt tokens evaluated: 560 of ~1000 sent (~44% from Ollama's cache)".to_string()
+        ));
+        assert!(
+            report.contains(&"  1 of 2 requests began with the whole request before".to_string())
+        );
+    }
+
+    #[test]
+    fn test_stats_round_trip_through_json() {
+        let mut stats = SessionStats::new();
+        stats.record(10, 20, &["file".to_string()], Duration::from_secs(1), None);
+
+        stats.record_reasoning(12);
+        stats.record_model("llama3.2", 10, 20);
+        stats.record_tool_output(30, None);
+        stats.record_prompt_usage(PromptUsage {
+            requests: 1,
+            extended: 1,
+            sent: 40,
+            evaluated: 8,
+        });
+
+        let json = serde_json::to_string(&stats).unwrap();
+        let restored: SessionStats = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, stats);
+    }
+}
@@ -0,0 +1,293 @@
+//! The state of a session where the user looks anyway: the title of the
+//! terminal window and the REPL prompt.
+//!
+//! The window is titled "sentinel — <session title> — <model>", starting
+//! with "* " while the TUI has changes it hasn't saved yet, and the title it
+//! had before is put back on exit. The REPL prompt follows `prompt_format`
+//! from the config. Neither writes escape sequences when stdout isn't a
+//! terminal, on a dumb terminal or with colors turned off.
+
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crossterm::execute;
+use crossterm::terminal::SetTitle;
+
+use crate::stats;
+use crate::style;
+
+/// The REPL prompt unless the config sets `prompt_format`
+pub const DEFAULT_PROMPT_FORMAT: &str = "[{model} | {context} ctx] User: ";
+
+// xterm's title stack: save the window's title before the first change and
+// put it back on exit. Terminals without one ignore both.
+const PUSH_TITLE: &str = "\x1b[22;0t";
+const POP_TITLE: &str = "\x1b[23;0t";
+
+// Whether the terminal's title was changed and not yet put back, for exits
+// that skip the destructors
+static TITLED: AtomicBool = AtomicBool::new(false);
+
+/// What a prompt can show about the session
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PromptState {
+    pub model: String,
+    /// Who serves the model, e.g. "ollama"
+    pub provider: String,
+    /// Tokens of context the last request took
+    pub context: usize,
+    /// The model's context window, once known
+    pub window: Option<usize>,
+    /// Messages waiting for the reply being generated
+    pub queue: usize,
+}
+
+/// `format` with each placeholder, e.g. `{model}`, replaced by the state it
+/// names; `{{` and `}}` stand for braces, and unknown placeholders are kept
+/// as written
+pub fn expand(format: &str, state: &PromptState) -> String {
+    let mut expanded = String::new();
+    let mut rest = format;
+    while let Some(at) = rest.find(['{', '}']) {
+        expanded.push_str(&rest[..at]);
+        let brace = &rest[at..at + 1];
+        rest = &rest[at + 1..];
+        if rest.starts_with(brace) {
+            expanded.push_str(brace);
+            rest = &rest[1..];
+            continue;
+        }
+        let placeholder = rest
+            .split_once('}')
+            .filter(|_| brace == "{")
+            .and_then(|(name, after)| Some((value(name, state)?, after)));
+        match placeholder {
+            Some((value, after)) => {
+                expanded.push_str(&value);
+                rest = after;
+            }
+            None => expanded.push_str(brace),
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+// What the placeholder `name` stands for, if it is one
+fn value(name: &str, state: &PromptState) -> Option<String> {
+    Some(match name {
+        "model" => state.model.clone(),
+        "provider" => state.provider.clone(),
+        "context" => stats::compact(state.context),
+        "window" => state.window.map_or("?".to_string(), stats::compact),
+        "queue" => state.queue.to_string(),
+        _ => return None,
+    })
+}
+
+/// The title of the terminal window, e.g. "sentinel — Parser rewrite — llama3.2"
+pub fn window_title(title: Option<&str>, model: &str, unsaved: bool) -> String {
+    let mut parts = vec!["sentinel", model];
+    if let Some(title) = title {
+        parts.insert(1, title);
+    }
+    let text = parts.join(" — ");
+    if unsaved {
+        format!("* {}", text)
+    } else {
+        text
+    }
+}
+
+/// Whether the terminal on stdout can be given a title
+pub fn can_set_title() -> bool {
+    io::stdout().is_terminal()
+        && env::var("TERM").map_or(true, |term| term != "dumb")
+        && style::enabled()
+}
+
+/// The title of a terminal window, changed only when it is different and
+/// put back when dropped
+pub struct WindowTitle {
+    out: Box<dyn Write + Send>,
+    enabled: bool,
+    // Whether `out` is this process's terminal
+    terminal: bool,
+    shown: Option<String>,
+}
+
+impl WindowTitle {
+    /// The title of the terminal on stdout, left alone if it can't have one
+    pub fn of_terminal() -> Self {
+        Self {
+            out: Box::new(io::stdout()),
+            enabled: can_set_title(),
+            terminal: true,
+            shown: None,
+        }
+    }
+
+    /// A title written to `out`
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        Self {
+            out: Box::new(out),
+            enabled: true,
+            terminal: false,
+            shown: None,
+        }
+    }
+
+    /// Show `title`, unless it is shown already
+    pub fn set(&mut self, title: &str) -> io::Result<()> {
+        if !self.enabled || self.shown.as_deref() == Some(title) {
+            return Ok(());
+        }
+        if self.shown.is_none() {
+            write!(self.out, "{}", PUSH_TITLE)?;
+            if self.terminal {
+                TITLED.store(true, Ordering::Relaxed);
+            }
+        }
+        execute!(self.out, SetTitle(title))?;
+        self.shown = Some(title.to_string());
+        Ok(())
+    }
+
+    /// Put back the title from before the first change
+    pub fn reset(&mut self) -> io::Result<()> {
+        if self.shown.take().is_none() {
+            return Ok(());
+        }
+        if self.terminal {
+            TITLED.store(false, Ordering::Relaxed);
+        }
+        write_reset(&mut self.out)
+    }
+}
+
+impl Drop for WindowTitle {
+    fn drop(&mut self) {
+        let _ = self.reset();
+    }
+}
+
+/// Put back the terminal's title on the way out of `process::exit`, if it
+/// was changed
+pub fn restore_window_title() {
+    if TITLED.swap(false, Ordering::Relaxed) {
+        let _ = write_reset(&mut io::stdout());
+    }
+}
+
+// Clear the title for terminals without a title stack, then pop the old one
+fn write_reset(out: &mut impl Write) -> io::Result<()> {
+    execute!(out, SetTitle(""))?;
+    write!(out, "{}", POP_TITLE)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // Output kept where the test can still read it after the title is dropped
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Shared {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn state() -> PromptState {
+        PromptState {
+            model: "llama3.2".to_string(),
+            provider: "ollama".to_string(),
+            context: 3_140,
+            window: None,
+            queue: 2,
+        }
+    }
+
+    #[test]
+    fn test_placeholders_are_expanded() {
+        assert_eq!(
+            expand(DEFAULT_PROMPT_FORMAT, &state()),
+            "[llama3.2 | 3.1k ctx] User: "
+        );
+        assert_eq!(
+            expand("{provider}/{model} {context}/{window} q{queue}> ", &state()),
+            "ollama/llama3.2 3.1k/? q2> "
+        );
+        let state = PromptState {
+            window: Some(16_384),
+            context: 900,
+            ..state()
+        };
+        assert_eq!(expand("{context} of {window}", &state), "900 of 16.4k");
+    }
+
+    #[test]
+    fn test_braces_and_unknown_placeholders_are_kept() {
+        assert_eq!(expand("{{model}} {nope} }{", &state()), "{model} {nope} }{");
+        assert_eq!(expand("{model", &state()), "{model");
+        assert_eq!(expand("plain> ", &state()), "plain> ");
+        assert_eq!(expand("", &state()), "");
+    }
+
+    #[test]
+    fn test_window_titles() {
+        assert_eq!(window_title(None, "llama3.2", false), "sentinel — llama3.2");
+        assert_eq!(
+            window_title(Some("Parser rewrite"), "qwen2.5", true),
+            "* sentinel — Parser rewrite — qwen2.5"
+        );
+    }
+
+    #[test]
+    fn test_title_is_set_once_and_reset_when_dropped() {
+        let out = Shared::default();
+        let mut title = WindowTitle::new(out.clone());
+        title.set("sentinel — llama3.2").unwrap();
+        title.set("sentinel — llama3.2").unwrap();
+        assert_eq!(
+            out.text(),
+            format!("{}\x1b]0;sentinel — llama3.2\x07", PUSH_TITLE)
+        );
+
+        title.set("* sentinel — llama3.2").unwrap();
+        drop(title);
+        assert!(out.text().ends_with(&format!(
+            "\x1b]0;* sentinel — llama3.2\x07\x1b]0;\x07{}",
+            POP_TITLE
+        )));
+        assert_eq!(out.text().matches(PUSH_TITLE).count(), 1);
+    }
+
+    #[test]
+    fn test_an_untouched_title_is_left_alone() {
+        let out = Shared::default();
+        let mut title = WindowTitle::new(out.clone());
+        title.reset().unwrap();
+        drop(title);
+
+        let mut disabled = WindowTitle::new(out.clone());
+        disabled.enabled = false;
+        disabled.set("sentinel — llama3.2").unwrap();
+        drop(disabled);
+        assert_eq!(out.text(), "");
+    }
+}
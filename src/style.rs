@@ -0,0 +1,176 @@
+use std::ffi::OsString;
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Terminal colors for better user experience
+pub const RESET: &str = "\x1b[0m";
+pub const BOLD: &str = "\x1b[1m";
+pub const BRIGHT_GREEN: &str = "\x1b[1;32m";
+pub const BRIGHT_BLUE: &str = "\x1b[1;34m";
+pub const BRIGHT_WHITE: &str = "\x1b[1;37m";
+pub const YELLOW: &str = "\x1b[1;33m";
+pub const CYAN: &str = "\x1b[1;36m";
+pub const MAGENTA: &str = "\x1b[1;35m";
+pub const RED: &str = "\x1b[1;31m";
+pub const DIM: &str = "\x1b[2m";
+
+static COLOR_ENABLED: AtomicBool = AtomicBool::new(true);
+static TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Styler that emits either ANSI-colored or plain text
+#[derive(Debug, Clone, Copy)]
+pub struct Styler {
+    enabled: bool,
+}
+
+impl Styler {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled }
+    }
+
+    /// Whether this styler emits escape sequences
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Wrap text in the given color, or return it unchanged when color is disabled
+    pub fn paint(&self, color: &str, text: &str) -> String {
+        if self.enabled {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Like `paint`, for callers combining several codes (e.g. BOLD + color)
+    pub fn paint_all(&self, codes: &[&str], text: &str) -> String {
+        if self.enabled {
+            format!("{}{}{}", codes.concat(), text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+/// Decide whether color should be used.
+///
+/// Color is disabled by the `--no-color` flag, by a non-empty `NO_COLOR`
+/// environment variable (see https://no-color.org), or when output is not a terminal.
+pub fn should_use_color(no_color_flag: bool, no_color_env: Option<OsString>, is_tty: bool) -> bool {
+    if no_color_flag {
+        return false;
+    }
+
+    if no_color_env.map(|v| !v.is_empty()).unwrap_or(false) {
+        return false;
+    }
+
+    is_tty
+}
+
+/// Initialize the global color setting from the flag, environment and stdout
+pub fn init(no_color_flag: bool) {
+    let enabled = should_use_color(
+        no_color_flag,
+        std::env::var_os("NO_COLOR"),
+        std::io::stdout().is_terminal(),
+    );
+    set_enabled(enabled);
+}
+
+/// Override the global color setting
+pub fn set_enabled(enabled: bool) {
+    COLOR_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether global output is colored
+pub fn enabled() -> bool {
+    COLOR_ENABLED.load(Ordering::Relaxed)
+}
+
+/// The styler for the global color setting
+pub fn styler() -> Styler {
+    Styler::new(enabled())
+}
+
+/// Paint text using the global color setting
+pub fn paint(color: &str, text: &str) -> String {
+    styler().paint(color, text)
+}
+
+/// Send output meant for people to stderr, keeping stdout for machine-readable output
+pub fn set_stderr_only(stderr_only: bool) {
+    TO_STDERR.store(stderr_only, Ordering::Relaxed);
+}
+
+/// Whether output meant for people goes to stderr
+pub fn stderr_only() -> bool {
+    TO_STDERR.load(Ordering::Relaxed)
+}
+
+/// Where output meant for people goes: stdout, or stderr with `set_stderr_only`
+pub fn human_output() -> Box<dyn std::io::Write> {
+    if stderr_only() {
+        Box::new(std::io::stderr())
+    } else {
+        Box::new(std::io::stdout())
+    }
+}
+
+/// Whether output meant for people goes to a terminal
+pub fn human_output_is_terminal() -> bool {
+    if stderr_only() {
+        std::io::stderr().is_terminal()
+    } else {
+        std::io::stdout().is_terminal()
+    }
+}
+
+/// Print a single colored line using the global color setting
+pub fn print_line(color: &str, text: &str) {
+    let _ = writeln!(human_output(), "{}", paint(color, text));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paint_without_color_has_no_escape_sequences() {
+        let styler = Styler::new(false);
+
+        let painted = styler.paint(RED, "Error: something failed");
+        assert_eq!(painted, "Error: something failed");
+        assert!(!painted.contains('\x1b'));
+
+        let painted = styler.paint_all(&[BOLD, BRIGHT_BLUE], "Sentinel");
+        assert_eq!(painted, "Sentinel");
+        assert!(!painted.contains('\x1b'));
+    }
+
+    #[test]
+    fn test_paint_with_color_wraps_text() {
+        let styler = Styler::new(true);
+
+        let painted = styler.paint(CYAN, "[USER]");
+        assert_eq!(painted, format!("{}[USER]{}", CYAN, RESET));
+    }
+
+    #[test]
+    fn test_should_use_color() {
+        // A terminal with no overrides gets color
+        assert!(should_use_color(false, None, true));
+
+        // Piped output never gets color
+        assert!(!should_use_color(false, None, false));
+
+        // The flag wins even on a terminal
+        assert!(!should_use_color(true, None, true));
+
+        // NO_COLOR disables color when set to any non-empty value
+        assert!(!should_use_color(false, Some(OsString::from("1")), true));
+
+        // An empty NO_COLOR is ignored, per the convention
+        assert!(should_use_color(false, Some(OsString::new()), true));
+    }
+}
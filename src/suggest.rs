@@ -0,0 +1,87 @@
+//! Turning a task into a single shell command, for `sentinel do`.
+
+/// System prompt asking the model for nothing but a command
+pub const SYSTEM_PROMPT: &str = "You turn tasks into shell commands. Reply with exactly one \
+single-line shell command that performs the task on this machine, and nothing else: no \
+explanation, no markdown, no code fences.";
+
+/// Follow-up sent once when the first reply was not a usable command
+pub const STRICT_PROMPT: &str = "That was not a single shell command. Reply with ONLY the \
+command, on one line. Do not explain, refuse or format it.";
+
+// Openings of replies that decline the task instead of answering it
+const REFUSALS: [&str; 7] = [
+    "i can't",
+    "i cannot",
+    "i can not",
+    "i'm sorry",
+    "i am sorry",
+    "i'm unable",
+    "as an ai",
+];
+
+/// The command in a model's reply, or `None` if the reply is a refusal,
+/// empty, or more than one line.
+///
+/// A surrounding code fence, inline backticks and a leading `$ ` prompt are
+/// removed.
+pub fn extract_command(reply: &str) -> Option<String> {
+    let mut lines: Vec<&str> = reply
+        .trim()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    // Drop a ```bash ... ``` fence around the command
+    if lines.len() >= 2 && lines[0].starts_with("```") && lines[lines.len() - 1] == "```" {
+        lines = lines[1..lines.len() - 1].to_vec();
+    }
+
+    let [line] = lines.as_slice() else {
+        return None;
+    };
+
+    let command = line.trim_matches('`').trim();
+    let command = command.strip_prefix("$ ").unwrap_or(command).trim();
+
+    let lowered = command.to_lowercase();
+    if command.is_empty() || REFUSALS.iter().any(|refusal| lowered.starts_with(refusal)) {
+        return None;
+    }
+
+    Some(command.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_and_formatted_commands() {
+        assert_eq!(
+            extract_command("grep -rn TODO . | wc -l").as_deref(),
+            Some("grep -rn TODO . | wc -l")
+        );
+        assert_eq!(
+            extract_command("```bash\nls -la\n```").as_deref(),
+            Some("ls -la")
+        );
+        assert_eq!(extract_command("`pwd`").as_deref(), Some("pwd"));
+        assert_eq!(extract_command("$ df -h\n").as_deref(), Some("df -h"));
+    }
+
+    #[test]
+    fn test_prose_and_refusals_are_rejected() {
+        assert_eq!(extract_command(""), None);
+        assert_eq!(
+            extract_command("Run this:\n```\nls\n```\nIt lists files."),
+            None
+        );
+        assert_eq!(extract_command("cd src\nls"), None);
+        assert_eq!(
+            extract_command("I'm sorry, but I can't help with that."),
+            None
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::attachments;
+use crate::config;
+
+lazy_static! {
+    // `{{name}}`, with optional spaces inside the braces
+    static ref PLACEHOLDER: Regex = Regex::new(r"\{\{\s*([A-Za-z0-9_-]+)\s*\}\}").unwrap();
+}
+
+// Templates available even when the user has none of their own
+const BUILTINS: [(&str, &str); 3] = [
+    (
+        "review",
+        "You are a senior {{language}} reviewer. Analyze the following diff for bugs, unclear code \
+         and missing tests, and suggest concrete improvements.\n\n```diff\n{{diff}}\n```",
+    ),
+    (
+        "explain",
+        "Explain what the following code does, step by step, for someone new to the project. \
+         Point out anything surprising.\n\n{{code}}",
+    ),
+    (
+        "commit",
+        "Write a git commit message for this diff: a subject line under 72 characters, a blank \
+         line, then a short body explaining why.\n\n```diff\n{{diff}}\n```",
+    ),
+];
+
+/// A reusable prompt with `{{placeholder}}` variables
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Template {
+    pub name: String,
+    pub body: String,
+    /// The file it was loaded from, or None for built-in templates
+    pub path: Option<PathBuf>,
+}
+
+/// A rendered template, with warnings about variables it did not use
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rendered {
+    pub text: String,
+    pub warnings: Vec<String>,
+}
+
+impl Template {
+    /// The variables the template uses, in order of first appearance
+    pub fn variables(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for capture in PLACEHOLDER.captures_iter(&self.body) {
+            let name = capture[1].to_string();
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    /// Fill in the placeholders; every variable must have a value
+    pub fn render(&self, values: &BTreeMap<String, String>) -> Result<Rendered> {
+        let variables = self.variables();
+        let missing: Vec<&str> = variables
+            .iter()
+            .filter(|name| !values.contains_key(*name))
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            return Err(anyhow!(
+                "Template '{}' is missing values for: {}",
+                self.name,
+                missing.join(", ")
+            ));
+        }
+
+        let text = PLACEHOLDER
+            .replace_all(&self.body, |capture: &regex::Captures| {
+                values[&capture[1]].clone()
+            })
+            .into_owned();
+        let warnings = values
+            .keys()
+            .filter(|name| !variables.contains(name))
+            .map(|name| format!("Template '{}' does not use '{}'", self.name, name))
+            .collect();
+
+        Ok(Rendered { text, warnings })
+    }
+
+    pub fn is_builtin(&self) -> bool {
+        self.path.is_none()
+    }
+}
+
+/// `$XDG_CONFIG_HOME/sentinel/templates`, or `~/.config/sentinel/templates`
+pub fn directory() -> Option<PathBuf> {
+    Some(config::directory()?.join("templates"))
+}
+
+/// Every template, the user's own replacing built-in ones of the same name
+pub fn list() -> Result<Vec<Template>> {
+    match directory() {
+        Some(dir) => list_in(&dir),
+        None => list_in(Path::new("")),
+    }
+}
+
+/// The template called `name`
+pub fn find(name: &str) -> Result<Template> {
+    list()?
+        .into_iter()
+        .find(|template| template.name == name)
+        .ok_or_else(|| anyhow!("No template named '{}'. Use /templates to list them", name))
+}
+
+// Built-in templates overlaid with the files in `dir`, sorted by name
+fn list_in(dir: &Path) -> Result<Vec<Template>> {
+    let mut templates: BTreeMap<String, Template> = BUILTINS
+        .iter()
+        .map(|(name, body)| {
+            let template = Template {
+                name: name.to_string(),
+                body: body.to_string(),
+                path: None,
+            };
+            (name.to_string(), template)
+        })
+        .collect();
+
+    if dir.is_dir() {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(name) = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+            else {
+                continue;
+            };
+            if !path.is_file() || name.starts_with('.') {
+                continue;
+            }
+
+            let body = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read template '{}'", path.display()))?;
+            let template = Template {
+                name: name.clone(),
+                body,
+                path: Some(path),
+            };
+            templates.insert(name, template);
+        }
+    }
+
+    Ok(templates.into_values().collect())
+}
+
+/// Split a `name=value` assignment from the command line
+pub fn parse_assignment(assignment: &str) -> Result<(String, String)> {
+    match assignment.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.to_string()))
+        }
+        _ => Err(anyhow!(
+            "Expected name=value (or name=@file), got '{}'",
+            assignment
+        )),
+    }
+}
+
+/// A variable's value: the text itself, or for `@path` the content of the
+/// file, read with the same limits as attachments. Returns warnings alongside.
+pub fn resolve_value(value: &str) -> Result<(String, Vec<String>)> {
+    let Some(path) = value.strip_prefix('@') else {
+        return Ok((value.to_string(), Vec::new()));
+    };
+
+    let loaded = attachments::load(&[path.to_string()])?;
+    let attachment = loaded
+        .attachments
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("{}", loaded.warnings.join("; ")))?;
+    Ok((attachment.content, loaded.warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn template(body: &str) -> Template {
+        Template {
+            name: "test".to_string(),
+            body: body.to_string(),
+            path: None,
+        }
+    }
+
+    fn values(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_render() -> Result<()> {
+        let template = template("Review {{ lang }} code:\n{{diff}}\nAgain: {{lang}}");
+        assert_eq!(template.variables(), ["lang", "diff"]);
+
+        let rendered = template.render(&values(&[("lang", "Rust"), ("diff", "+ x")]))?;
+        assert_eq!(rendered.text, "Review Rust code:\n+ x\nAgain: Rust");
+        assert!(rendered.warnings.is_empty());
+
+        // Values may contain placeholder syntax without it being expanded again
+        let rendered = template.render(&values(&[("lang", "{{diff}}"), ("diff", "d")]))?;
+        assert!(rendered.text.starts_with("Review {{diff}} code"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_and_unused_variables() -> Result<()> {
+        let template = template("{{a}} and {{b}}");
+
+        let error = template.render(&values(&[("a", "1")])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Template 'test' is missing values for: b"
+        );
+
+        let rendered = template.render(&values(&[("a", "1"), ("b", "2"), ("c", "3")]))?;
+        assert_eq!(rendered.text, "1 and 2");
+        assert_eq!(rendered.warnings, ["Template 'test' does not use 'c'"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_values() -> Result<()> {
+        let dir = tempdir()?;
+        let patch = dir.path().join("changes.patch");
+        fs::write(&patch, "+ added line\n")?;
+
+        let (value, warnings) = resolve_value(&format!("@{}", patch.display()))?;
+        assert_eq!(value, "+ added line\n");
+        assert!(warnings.is_empty());
+
+        assert_eq!(resolve_value("plain")?.0, "plain");
+        assert!(resolve_value("@/does/not/exist.patch").is_err());
+
+        let secrets = dir.path().join(".env");
+        fs::write(&secrets, "TOKEN=1")?;
+        let error = resolve_value(&format!("@{}", secrets.display())).unwrap_err();
+        assert!(error.to_string().contains("secrets file"));
+
+        assert_eq!(
+            parse_assignment("diff=@x.patch")?,
+            ("diff".to_string(), "@x.patch".to_string())
+        );
+        assert!(parse_assignment("diff").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_templates_replace_builtins() -> Result<()> {
+        let dir = tempdir()?;
+        fs::write(dir.path().join("review.md"), "Mine: {{diff}}")?;
+        fs::write(dir.path().join("standup.txt"), "Summarize {{notes}}")?;
+
+        let templates = list_in(dir.path())?;
+        let names: Vec<&str> = templates.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["commit", "explain", "review", "standup"]);
+
+        let review = templates.iter().find(|t| t.name == "review").unwrap();
+        assert_eq!(review.body, "Mine: {{diff}}");
+        assert!(!review.is_builtin());
+        Ok(())
+    }
+}
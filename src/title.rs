@@ -0,0 +1,109 @@
+//! Short titles naming a conversation, so a saved session can be told apart
+//! from the others by more than its timestamp.
+//!
+//! After the first reply the model is asked, in a request of its own, to sum
+//! up the exchange. The answer is cleaned up into a title; anything that goes
+//! wrong just leaves the conversation untitled.
+
+use std::time::Duration;
+
+use crate::llm::ollama::LlmClient;
+use crate::llm::reasoning;
+use crate::Message;
+
+/// How long the title request may take before it is given up
+pub const TITLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most characters a title keeps
+pub const MAX_TITLE_CHARS: usize = 60;
+
+// Most characters of each message quoted in the title request
+const QUOTED_CHARS: usize = 1_000;
+
+const INSTRUCTION: &str = "Summarize this exchange in 6 words or fewer. \
+                           Reply with the summary only, without quotes or punctuation at the end.";
+
+/// The request asking the model to name an exchange
+pub fn request(question: &str, answer: &str) -> Vec<Message> {
+    let quote = |text: &str| -> String { text.chars().take(QUOTED_CHARS).collect() };
+    vec![Message::user(format!(
+        "{}\n\nUser: {}\n\nAssistant: {}",
+        INSTRUCTION,
+        quote(question),
+        quote(answer)
+    ))]
+}
+
+/// Ask `client` for a title for an exchange, or None if it fails, takes
+/// longer than [`TITLE_TIMEOUT`] or answers with nothing usable
+pub async fn generate(client: &dyn LlmClient, question: &str, answer: &str) -> Option<String> {
+    let messages = request(question, answer);
+    let (text, _, _) = tokio::time::timeout(TITLE_TIMEOUT, client.generate_response(&messages))
+        .await
+        .ok()?
+        .ok()?;
+    clean(&reasoning::split(&text).content)
+}
+
+/// A model's answer as a title: its first line, without a "Title:" label,
+/// quotes, markdown or a trailing full stop, and at most [`MAX_TITLE_CHARS`]
+pub fn clean(text: &str) -> Option<String> {
+    let line = text.lines().map(str::trim).find(|line| !line.is_empty())?;
+    let line = match line.split_once(':') {
+        Some((label, rest)) if label.eq_ignore_ascii_case("title") => rest,
+        _ => line,
+    };
+    let line = line
+        .trim_matches(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '*' | '#' | '`'))
+        .trim_end_matches(['.', '!']);
+    let title = normalize(line);
+    (!title.is_empty()).then_some(title)
+}
+
+/// A title as typed with /title: spaces collapsed and cut to [`MAX_TITLE_CHARS`]
+pub fn normalize(text: &str) -> String {
+    let title = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if title.chars().count() <= MAX_TITLE_CHARS {
+        return title;
+    }
+    let cut: String = title.chars().take(MAX_TITLE_CHARS).collect();
+    format!("{}...", cut.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::mock::MockLlmClient;
+
+    #[test]
+    fn test_clean_keeps_the_title_only() {
+        assert_eq!(clean("Naming a cat").as_deref(), Some("Naming a cat"));
+        assert_eq!(
+            clean("\n  Title: \"Fixing the  borrow checker.\"\nHope that helps!").as_deref(),
+            Some("Fixing the borrow checker")
+        );
+        assert_eq!(
+            clean("**Rust lifetimes**").as_deref(),
+            Some("Rust lifetimes")
+        );
+        assert_eq!(clean(" \n\"\"\n"), None);
+        let long = clean(&"word ".repeat(30)).unwrap();
+        assert!(long.ends_with("word..."));
+        assert!(long.chars().count() <= MAX_TITLE_CHARS + 3);
+    }
+
+    #[tokio::test]
+    async fn test_generate_asks_about_the_exchange() {
+        let client = MockLlmClient::new().with_reply("<think>A cat</think>Naming a new cat.");
+        let title = generate(&client, "name my cat", "How about Miso?").await;
+        assert_eq!(title.as_deref(), Some("Naming a new cat"));
+
+        let prompt = &client.received()[0][0].content;
+        assert!(prompt.starts_with("Summarize this exchange in 6 words or fewer."));
+        assert!(prompt.ends_with("User: name my cat\n\nAssistant: How about Miso?"));
+
+        // Failures leave the conversation untitled
+        let failing = MockLlmClient::new().with_error("model not found");
+        assert_eq!(generate(&failing, "hi", "hello").await, None);
+    }
+}
@@ -0,0 +1,515 @@
+//! Running the tools a model asks for ourselves, for clients that hand tool
+//! calls back instead of going through the ollama-rs Coordinator.
+
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use tokio_util::sync::CancellationToken;
+
+use crate::interrupt;
+use crate::llm::deadline::Deadline;
+use crate::llm::ollama::{GenerationOptions, LlmClient, NoText, ToolInvocation};
+use crate::llm::reasoning;
+use crate::llm::tool_support::{self, ToolSupport};
+use crate::stats::{TokenBreakdown, ToolTiming};
+use crate::tools::progress::ToolEvent;
+use crate::tools::registry::ToolSet;
+use crate::tools::result::ToolError;
+use crate::tools::summarize;
+use crate::{Message, Role};
+
+/// Model turns allowed in one response before giving up
+pub const MAX_ITERATIONS: usize = 8;
+
+// Lines of a failed command's output quoted back to the model
+const FAILURE_LINES: usize = 20;
+
+/// The model's final answer to a message, with the usage of every step
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TurnOutcome {
+    pub text: String,
+    /// Tokens of the prompts, without the tool output in them
+    pub input_tokens: usize,
+    /// Tokens of tool output sent back to the model, counted each time it is sent
+    pub tool_output_tokens: usize,
+    pub output_tokens: usize,
+    pub used_tools: Vec<String>,
+    /// Every tool call made on the way to the answer, in order
+    pub tool_calls: Vec<ToolInvocation>,
+    /// The think blocks of a reasoning model, removed from `text`
+    pub reasoning: Option<String>,
+    /// The part of `output_tokens` estimated to have gone to reasoning
+    pub reasoning_tokens: usize,
+    /// Tokens spent summarizing long tool output, prompts and replies together
+    pub summary_tokens: usize,
+    /// Whether the turn was cut short, leaving `text` as what the model had
+    /// said so far, marked "(interrupted)"
+    pub interrupted: bool,
+    /// Whether the model refused the tools it was sent, so this answer was
+    /// generated again without them
+    pub tools_refused: bool,
+    /// Whether the answer used up the `max_tokens` limit, so it is most
+    /// likely incomplete
+    pub truncated: bool,
+    /// Failed bash commands the model was asked to correct, as
+    /// `tools.bash.auto_retry` allows
+    pub retries: usize,
+    /// Whether the last command still failed when the retries ran out
+    pub retries_exhausted: bool,
+}
+
+impl TurnOutcome {
+    /// The turn's tokens: prompt, tool output and reply
+    pub fn tokens(&self) -> TokenBreakdown {
+        TokenBreakdown::new(
+            self.input_tokens,
+            self.tool_output_tokens,
+            self.output_tokens,
+        )
+    }
+
+    /// How long each tool call took, in order
+    pub fn timings(&self) -> Vec<ToolTiming> {
+        self.tool_calls.iter().map(ToolInvocation::timing).collect()
+    }
+
+    // Use `text` as the answer, keeping any reasoning in it apart; a blank
+    // answer is an error rather than an empty reply
+    fn answer(&mut self, text: &str, output_tokens: usize) -> Result<()> {
+        if text.trim().is_empty() {
+            return Err(NoText::default().into());
+        }
+        let reply = reasoning::split(text);
+        self.reasoning_tokens = reply.reasoning_tokens(output_tokens);
+        self.reasoning = reply.reasoning;
+        self.text = reply.content;
+        Ok(())
+    }
+
+    // End the turn early with what the model had said so far
+    fn interrupt(mut self, partial: &str) -> Self {
+        self.interrupted = true;
+        self.text = interrupt::mark_interrupted(partial);
+        self
+    }
+
+    /// The bash commands of a turn in which failed commands were retried,
+    /// each with how it ended, so the chain of attempts can be checked
+    pub fn retry_report(&self) -> Option<String> {
+        if self.retries == 0 {
+            return None;
+        }
+        let mut report = format!(
+            "Asked the model to correct a failed command {} time{}{}:",
+            self.retries,
+            if self.retries == 1 { "" } else { "s" },
+            if self.retries_exhausted {
+                ", and the last command still failed"
+            } else {
+                ""
+            }
+        );
+        for call in self.tool_calls.iter().filter(|call| call.name == BASH) {
+            let command = call.arguments["command"].as_str().unwrap_or_default();
+            match FailedCommand::of(call) {
+                Some(failure) => report.push_str(&format!(
+                    "\n  `{}`: exit code {}",
+                    command, failure.exit_code
+                )),
+                None => report.push_str(&format!("\n  `{}`: ok", command)),
+            }
+        }
+        Some(report)
+    }
+
+    // The correction to ask for if the last command the step starting at
+    // call `from` ran failed and retries are left, noting when they have run out
+    fn retry(&mut self, from: usize, auto_retry: usize) -> Option<String> {
+        let last = self.tool_calls[from..]
+            .iter()
+            .rev()
+            .find(|call| call.name == BASH)?;
+        let Some(failure) = FailedCommand::of(last) else {
+            self.retries_exhausted = false;
+            return None;
+        };
+        if self.retries >= auto_retry {
+            self.retries_exhausted = auto_retry > 0;
+            return None;
+        }
+        self.retries += 1;
+        Some(failure.correction())
+    }
+}
+
+const BASH: &str = "bash";
+
+/// A bash command that ran and exited non-zero
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailedCommand {
+    pub command: String,
+    pub exit_code: i32,
+    /// The last lines it printed, stdout and stderr together
+    pub output: String,
+}
+
+impl FailedCommand {
+    /// The failure of a bash call, if it ran and exited non-zero
+    pub fn of(call: &ToolInvocation) -> Option<Self> {
+        if call.name != BASH {
+            return None;
+        }
+        let lines: Vec<&str> = call.output.lines().collect();
+        let at = lines
+            .iter()
+            .rposition(|line| line.starts_with("Exit code: "))?;
+        let exit_code = lines[at]["Exit code: ".len()..].trim().parse().ok()?;
+        let printed = &lines[..at];
+        Some(Self {
+            command: call.arguments["command"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            exit_code,
+            output: printed[printed.len().saturating_sub(FAILURE_LINES)..]
+                .join("\n")
+                .trim_end()
+                .to_string(),
+        })
+    }
+
+    /// The instruction added to the turn, asking the model to fix the command
+    pub fn correction(&self) -> String {
+        let output = if self.output.trim().is_empty() {
+            "no output".to_string()
+        } else {
+            format!("this output:\n```\n{}\n```", self.output)
+        };
+        format!(
+            "The command `{}` failed with exit code {} and {}\n\
+             Work out what went wrong, then propose and run a corrected command.",
+            self.command, self.exit_code, output
+        )
+    }
+}
+
+/// Get the model's answer to `messages`, with tools enabled.
+///
+/// Clients that hand tool calls back have them run from `tools`; the others
+/// run their own tools.
+pub async fn respond(
+    client: &dyn LlmClient,
+    tools: &mut ToolSet,
+    messages: &[Message],
+) -> Result<TurnOutcome> {
+    respond_until(client, tools, messages, &CancellationToken::new()).await
+}
+
+/// Like [`respond`], but stop once `interrupt` is cancelled. A request to the
+/// model is dropped on the spot; a tool already running finishes first.
+///
+/// The whole turn is kept within the timeout of the client's limits.
+pub async fn respond_until(
+    client: &dyn LlmClient,
+    tools: &mut ToolSet,
+    messages: &[Message],
+    interrupt: &CancellationToken,
+) -> Result<TurnOutcome> {
+    let options = GenerationOptions::default();
+    respond_until_with_options(client, tools, messages, interrupt, &options).await
+}
+
+/// Like [`respond_until`], with per-request settings such as a sampling seed
+pub async fn respond_until_with_options(
+    client: &dyn LlmClient,
+    tools: &mut ToolSet,
+    messages: &[Message],
+    interrupt: &CancellationToken,
+    options: &GenerationOptions,
+) -> Result<TurnOutcome> {
+    if client.supports_tool_calls() {
+        return run_steps(client, tools, messages, MAX_ITERATIONS, interrupt, options).await;
+    }
+
+    // The client runs the tools itself, so a failed command is corrected
+    // with another request, each counting against the iterations
+    let mut deadline = Deadline::start(&client.limits());
+    let mut conversation = messages.to_vec();
+    let mut outcome = TurnOutcome::default();
+    for _ in 0..MAX_ITERATIONS {
+        let generated = tokio::select! {
+            generated = deadline.run(client.generate_response_with_tools_and_options(&conversation, &[], options)) => generated?,
+            _ = interrupt.cancelled() => return Ok(outcome.interrupt("")),
+        };
+        deadline.progress();
+        let (text, input_tokens, output_tokens, used_tools) = generated;
+        let tool_calls = client.last_tool_invocations();
+        outcome.input_tokens += input_tokens;
+        outcome.tool_output_tokens += tool_calls
+            .iter()
+            .map(ToolInvocation::sent_tokens)
+            .sum::<usize>();
+        outcome.output_tokens += output_tokens;
+        for tool in used_tools {
+            if !outcome.used_tools.contains(&tool) {
+                outcome.used_tools.push(tool);
+            }
+        }
+        outcome.truncated = client.take_truncated();
+        outcome.answer(&text, output_tokens)?;
+        let from = outcome.tool_calls.len();
+        outcome.tool_calls.extend(tool_calls);
+        let Some(correction) = outcome.retry(from, tools.auto_retry()) else {
+            return Ok(outcome);
+        };
+        conversation.push(Message::assistant(
+            outcome.text.clone(),
+            input_tokens,
+            output_tokens,
+            Vec::new(),
+        ));
+        conversation.push(Message::user(correction));
+    }
+    outcome.retries_exhausted = true;
+    Ok(outcome)
+}
+
+/// Like [`respond_until`], for `model`: answer without tools when `support`
+/// knows it can't call them. A model that refuses tools is remembered to,
+/// and answered again without them, unless tools are forced.
+pub async fn respond_checked(
+    client: &dyn LlmClient,
+    tools: &mut ToolSet,
+    messages: &[Message],
+    interrupt: &CancellationToken,
+    support: &ToolSupport,
+    model: &str,
+) -> Result<TurnOutcome> {
+    let options = GenerationOptions::default();
+    respond_checked_with_options(client, tools, messages, interrupt, support, model, &options).await
+}
+
+/// Like [`respond_checked`], with per-request settings such as a sampling seed
+pub async fn respond_checked_with_options(
+    client: &dyn LlmClient,
+    tools: &mut ToolSet,
+    messages: &[Message],
+    interrupt: &CancellationToken,
+    support: &ToolSupport,
+    model: &str,
+    options: &GenerationOptions,
+) -> Result<TurnOutcome> {
+    if !support.check(client, model).await {
+        return answer_without_tools(client, messages, interrupt, options).await;
+    }
+
+    match respond_until_with_options(client, tools, messages, interrupt, options).await {
+        Err(e) if !support.is_forced() && tool_support::is_unsupported_error(&e) => {
+            support.record(model, false);
+            let mut outcome = answer_without_tools(client, messages, interrupt, options).await?;
+            outcome.tools_refused = true;
+            Ok(outcome)
+        }
+        outcome => outcome,
+    }
+}
+
+/// Get the model's answer to `messages` without offering it any tools
+pub async fn respond_without_tools(
+    client: &dyn LlmClient,
+    messages: &[Message],
+    interrupt: &CancellationToken,
+) -> Result<TurnOutcome> {
+    answer_without_tools(client, messages, interrupt, &GenerationOptions::default()).await
+}
+
+// Like `respond_without_tools`, with per-request settings
+async fn answer_without_tools(
+    client: &dyn LlmClient,
+    messages: &[Message],
+    interrupt: &CancellationToken,
+    options: &GenerationOptions,
+) -> Result<TurnOutcome> {
+    let deadline = Deadline::start(&client.limits());
+    let generated = tokio::select! {
+        generated = deadline.run(client.generate_response_with_options(messages, options)) => generated?,
+        _ = interrupt.cancelled() => return Ok(TurnOutcome::default().interrupt("")),
+    };
+    let (text, input_tokens, output_tokens) = generated;
+    let mut outcome = TurnOutcome {
+        input_tokens,
+        output_tokens,
+        truncated: client.take_truncated(),
+        ..TurnOutcome::default()
+    };
+    outcome.answer(&text, output_tokens)?;
+    Ok(outcome)
+}
+
+/// Ask the model for an answer, running the tools it calls and passing their
+/// output back, until it answers without calling any.
+///
+/// Calls to unknown tools or with bad arguments are reported back to the model
+/// so it can correct itself, until too many calls in a row are malformed, as
+/// are tools that panic. A tool that fails outright ends the turn with its error, as does running out
+/// of iterations, or out of time: the client's timeout counts the whole turn,
+/// or in progress mode restarts with every step and every tool that returns.
+/// Cancelling `interrupt` ends it with what the model said before its tool
+/// calls.
+///
+/// When a step's last bash command exits non-zero, the model is asked to
+/// correct it, up to the `auto_retry` of `tools` times in the turn. The
+/// corrected commands go through the same checks and confirmations.
+///
+/// Output over the budget `tools` summarizes it at is condensed for the
+/// question of the last user message, and the model sent the summary. The
+/// output stays in the call's record, and making the same call again in the
+/// turn sends it in full without running the tool again.
+pub async fn run(
+    client: &dyn LlmClient,
+    tools: &mut ToolSet,
+    messages: &[Message],
+    max_iterations: usize,
+    interrupt: &CancellationToken,
+) -> Result<TurnOutcome> {
+    let options = GenerationOptions::default();
+    run_steps(client, tools, messages, max_iterations, interrupt, &options).await
+}
+
+// `run`, with per-request settings sent with every step
+async fn run_steps(
+    client: &dyn LlmClient,
+    tools: &mut ToolSet,
+    messages: &[Message],
+    max_iterations: usize,
+    interrupt: &CancellationToken,
+    options: &GenerationOptions,
+) -> Result<TurnOutcome> {
+    let definitions = tools.definitions();
+    tools.start_turn();
+    let mut conversation = messages.to_vec();
+    let mut outcome = TurnOutcome::default();
+    let mut partial = Vec::new();
+    // Tool output in `conversation`, sent again with every step
+    let mut tool_output_tokens = 0;
+    let mut deadline = Deadline::start(&client.limits());
+    let auto_retry = tools.auto_retry();
+    let question = messages
+        .iter()
+        .rev()
+        .find(|message| message.role == Role::User)
+        .map_or("", |message| message.content.as_str());
+
+    for _ in 0..max_iterations {
+        let turn = tokio::select! {
+            turn = deadline.run(client.chat_step_with_options(&conversation, &definitions, options)) => turn?,
+            _ = interrupt.cancelled() => return Ok(outcome.interrupt(&partial.join("\n\n"))),
+        };
+        let sent_tool_output = tool_output_tokens.min(turn.input_tokens);
+        outcome.input_tokens += turn.input_tokens - sent_tool_output;
+        outcome.tool_output_tokens += sent_tool_output;
+        outcome.output_tokens += turn.output_tokens;
+        deadline.progress();
+
+        if turn.tool_calls.is_empty() {
+            outcome.truncated = client.take_truncated();
+            outcome.answer(&turn.content, turn.output_tokens)?;
+            return Ok(outcome);
+        }
+
+        // Reasoning before a tool call is not worth replaying either
+        let names: Vec<String> = turn.tool_calls.iter().map(|c| c.name.clone()).collect();
+        let content = reasoning::split(&turn.content).content;
+        if !content.trim().is_empty() {
+            partial.push(content.trim().to_string());
+        }
+        conversation.push(Message::assistant(
+            content,
+            turn.input_tokens,
+            turn.output_tokens,
+            names,
+        ));
+
+        let step_calls = outcome.tool_calls.len();
+        for call in turn.tool_calls {
+            // Tools are never stopped halfway; the ones not started yet are skipped
+            if interrupt.is_cancelled() {
+                return Ok(outcome.interrupt(&partial.join("\n\n")));
+            }
+
+            tools.policy().report_tool(ToolEvent::Started {
+                name: call.name.clone(),
+                args: call.arguments.clone(),
+            });
+            let started = Instant::now();
+            let full_output = outcome
+                .tool_calls
+                .iter()
+                .find(|earlier| {
+                    earlier.summary.is_some()
+                        && earlier.name == call.name
+                        && earlier.arguments == call.arguments
+                })
+                .map(|earlier| earlier.output.clone());
+            let output = match full_output.clone() {
+                Some(output) => output,
+                None => match tools.call(&call.name, call.arguments.clone()).await {
+                    Ok(output) => output,
+                    Err(
+                        e @ (ToolError::NotFound(_)
+                        | ToolError::InvalidParams(_)
+                        | ToolError::Panicked(_)),
+                    ) => {
+                        format!("Error: {}", e)
+                    }
+                    Err(e) => return Err(anyhow!("Tool '{}' failed: {}", call.name, e)),
+                },
+            };
+            let duration = started.elapsed();
+            tools.policy().report_tool(ToolEvent::Finished {
+                name: call.name.clone(),
+                output: output.clone(),
+                duration,
+            });
+
+            // A summary that fails or times out leaves the output as it is
+            let config = tools.summarize();
+            let summary = if full_output.is_none() && config.applies(&call.name, &output) {
+                summarize::summarize(client, config, question, &call.name, &output)
+                    .await
+                    .ok()
+            } else {
+                None
+            };
+
+            if !outcome.used_tools.contains(&call.name) {
+                outcome.used_tools.push(call.name.clone());
+            }
+            outcome.summary_tokens += summary.as_ref().map_or(0, |summary| summary.tokens);
+            let sent = summary
+                .as_ref()
+                .map_or(&output, |summary| &summary.text)
+                .clone();
+            let invocation = ToolInvocation {
+                name: call.name,
+                arguments: call.arguments,
+                output,
+                summary: summary.map(|summary| summary.text),
+                duration,
+            };
+            tool_output_tokens += invocation.sent_tokens();
+            outcome.tool_calls.push(invocation);
+            conversation.push(Message::tool(sent));
+            deadline.progress();
+        }
+
+        if let Some(correction) = outcome.retry(step_calls, auto_retry) {
+            conversation.push(Message::user(correction));
+        }
+    }
+
+    Err(anyhow!(
+        "Stopped after {} model turns without a final answer",
+        max_iterations
+    ))
+}
@@ -1,5 +1,8 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::time::timeout;
 
@@ -7,46 +10,30 @@ use anyhow::Result;
 use ollama_rs::generation::tools::Tool;
 use schemars::JsonSchema;
 use serde::Deserialize;
-use tokio::process::Command as TokioCommand;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command as TokioCommand};
+use tokio::sync::mpsc;
+
+use crate::tools::command_rules;
+use crate::tools::policy::{self, ExecutionPolicy};
+use crate::tools::result::{ToolError, ToolOutput, ToolResult};
+use crate::truncate::{self, Fences, Strategy};
 
 const DEFAULT_TIMEOUT: u64 = 60 * 1000; // 1 minute in milliseconds
 const MAX_TIMEOUT: u64 = 10 * 60 * 1000; // 10 minutes in milliseconds
 const MAX_OUTPUT_LENGTH: usize = 30000;
+// Printed after every command so directory changes anywhere in the command can be tracked
+const CWD_MARKER: &str = "__SENTINEL_CWD__";
 
-lazy_static::lazy_static! {
-    static ref BANNED_COMMANDS: HashSet<&'static str> = {
-        let mut s = HashSet::new();
-        s.insert("alias");
-        s.insert("curl");
-        s.insert("curlie");
-        s.insert("wget");
-        s.insert("axel");
-        s.insert("aria2c");
-        s.insert("nc");
-        s.insert("telnet");
-        s.insert("lynx");
-        s.insert("w3m");
-        s.insert("links");
-        s.insert("httpie");
-        s.insert("xh");
-        s.insert("http-prompt");
-        s.insert("chrome");
-        s.insert("firefox");
-        s.insert("safari");
-        s
-    };
-}
+// Commands whose operands they change, and those that only change their last operand
+const CHANGING_COMMANDS: [&str; 8] = [
+    "rm", "rmdir", "unlink", "shred", "truncate", "mv", "tee", "touch",
+];
+const CHANGING_LAST_OPERAND: [&str; 2] = ["cp", "ln"];
 
-// Safe read-only commands that are always allowed
-lazy_static::lazy_static! {
-    static ref SAFE_READ_ONLY_COMMANDS: Vec<&'static str> = vec![
-        "ls", "echo", "pwd", "date", "cal", "uptime", "whoami", "id", "groups", "env", "printenv", "set", "unset", "which", "type", "whereis",
-        "whatis", "uname", "hostname", "df", "du", "free", "top", "ps", "kill", "killall", "nice", "nohup", "time", "timeout",
-        "git status", "git log", "git diff", "git show", "git branch", "git tag", "git remote", "git ls-files", "git ls-remote",
-        "git rev-parse", "git config --get", "git config --list", "git describe", "git blame", "git grep", "git shortlog",
-        "go version", "go help", "go list", "go env", "go doc", "go vet", "go fmt", "go mod", "go test", "go build", "go run", "go install", "go clean",
-    ];
-}
+// Environment variable name patterns that are never passed to commands
+const SECRET_ENV_SUFFIXES: [&str; 3] = ["_KEY", "_TOKEN", "_SECRET"];
+const SECRET_ENV_PREFIXES: [&str; 1] = ["AWS_"];
 
 #[derive(Deserialize, JsonSchema)]
 pub struct BashParams {
@@ -55,130 +42,268 @@ pub struct BashParams {
 
     #[schemars(description = "Optional timeout in milliseconds (max 600000)")]
     timeout: Option<u64>,
+
+    #[schemars(
+        description = "Optional: stop the command if it prints nothing for this many milliseconds"
+    )]
+    #[serde(default)]
+    idle_timeout: Option<u64>,
+
+    #[schemars(
+        description = "Optional environment variables to set for this command only. Secret-looking names (*_KEY, *_TOKEN, *_SECRET, AWS_*) are ignored"
+    )]
+    #[serde(default)]
+    env: HashMap<String, String>,
+}
+
+impl BashParams {
+    /// Parameters for running `command` with the default timeout
+    pub fn new(command: impl Into<String>) -> Self {
+        Self {
+            command: command.into(),
+            timeout: None,
+            idle_timeout: None,
+            env: HashMap::new(),
+        }
+    }
+
+    /// Give the command this many milliseconds instead of the default
+    pub fn with_timeout(mut self, timeout_ms: u64) -> Self {
+        self.timeout = Some(timeout_ms);
+        self
+    }
+}
+
+// Which pipe a line of output came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+// Output kept for the model while a command runs: the most recent lines, with
+// a count of the lines dropped before them and the code block they left open
+#[derive(Debug, Default)]
+struct CappedOutput {
+    tail: VecDeque<String>,
+    tail_len: usize,
+    dropped: usize,
+    dropped_len: usize,
+    fences: Fences,
+}
+
+impl CappedOutput {
+    fn push(&mut self, line: String) {
+        self.tail_len += line.len();
+        self.tail.push_back(line);
+        while self.tail_len > MAX_OUTPUT_LENGTH && self.tail.len() > 1 {
+            if let Some(dropped) = self.tail.pop_front() {
+                self.tail_len -= dropped.len();
+                self.dropped += 1;
+                self.dropped_len += dropped.len();
+                self.fences.push(&dropped);
+            }
+        }
+    }
+
+    fn into_string(self) -> String {
+        let reopening = self
+            .tail
+            .front()
+            .and_then(|next| self.fences.reopening(next))
+            .unwrap_or_default();
+        let tail: String = self.tail.into_iter().collect();
+        if self.dropped == 0 {
+            tail
+        } else {
+            format!(
+                "{}\n\n{}{}",
+                truncate::marker(self.dropped, self.dropped_len),
+                reopening,
+                tail
+            )
+        }
+    }
 }
 
 pub struct Bash {
     working_directory: String,
+    extra_env: HashMap<String, String>,
+    policy: Arc<ExecutionPolicy>,
 }
 
 impl Default for Bash {
     fn default() -> Self {
         Self {
             working_directory: String::from("."),
+            extra_env: HashMap::new(),
+            policy: Arc::default(),
         }
     }
 }
 
+/// Whether an environment variable name looks like it holds a credential
+pub fn is_secret_env_var(name: &str) -> bool {
+    let name = name.to_uppercase();
+    SECRET_ENV_SUFFIXES
+        .iter()
+        .any(|suffix| name.ends_with(suffix))
+        || SECRET_ENV_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+}
+
 impl Bash {
     pub fn new() -> Self {
         Self::default()
     }
 
-    fn truncate_output(content: &str) -> String {
-        if content.len() <= MAX_OUTPUT_LENGTH {
-            return content.to_string();
+    /// Use a shared execution policy, e.g. to enable dry-run mode
+    pub fn with_policy(mut self, policy: Arc<ExecutionPolicy>) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Set extra environment variables (e.g. from configuration) for every command
+    pub fn with_env(mut self, vars: HashMap<String, String>) -> Self {
+        self.extra_env = vars;
+        self
+    }
+
+    // Build the environment for a command: the inherited environment without secrets,
+    // the variables of the config and of `with_env`, then per-command variables that
+    // pass the denylist. Inherited variables that are not Unicode pass through as they are
+    fn command_env(
+        &self,
+        inherited: impl IntoIterator<Item = (OsString, OsString)>,
+        command_env: &HashMap<String, String>,
+    ) -> (Vec<(OsString, OsString)>, Vec<String>) {
+        let mut env: HashMap<OsString, OsString> = inherited
+            .into_iter()
+            .filter(|(name, _)| !is_secret_env_var(&name.to_string_lossy()))
+            .collect();
+        let configured = self
+            .policy
+            .bash_env()
+            .into_iter()
+            .chain(self.extra_env.clone());
+        env.extend(configured.map(|(name, value)| (name.into(), value.into())));
+
+        let mut ignored = Vec::new();
+        for (name, value) in command_env {
+            if is_secret_env_var(name) {
+                ignored.push(name.clone());
+            } else {
+                env.insert(name.into(), value.into());
+            }
         }
+        ignored.sort();
 
-        let half_length = MAX_OUTPUT_LENGTH / 2;
-        let start = &content[..half_length];
-        let end = &content[content.len() - half_length..];
+        (env.into_iter().collect(), ignored)
+    }
 
-        // Count truncated lines
-        let middle_content = &content[half_length..content.len() - half_length];
-        let truncated_lines_count = middle_content.chars().filter(|&c| c == '\n').count();
+    /// The directory the next command will run in
+    pub fn working_directory(&self) -> &str {
+        &self.working_directory
+    }
 
+    // Wrap a command so the shell reports its final working directory after running it
+    fn wrap_command(command: &str) -> String {
         format!(
-            "{}\n\n... [{} lines truncated] ...\n\n{}",
-            start, truncated_lines_count, end
+            "{}\n__sentinel_status=$?\nprintf '\\n{}%s\\n' \"$(pwd)\"\nexit $__sentinel_status",
+            command, CWD_MARKER
         )
     }
 
-    fn is_command_safe(&self, command: &str) -> bool {
-        let base_cmd = command
-            .split_whitespace()
-            .next()
-            .unwrap_or("")
-            .to_lowercase();
-        if BANNED_COMMANDS.contains(base_cmd.as_str()) {
-            return false;
-        }
-
-        for safe_cmd in SAFE_READ_ONLY_COMMANDS.iter() {
-            let safe_cmd_lower = safe_cmd.to_lowercase();
-            if command.to_lowercase().starts_with(&safe_cmd_lower) {
-                let cmd_len = safe_cmd_lower.len();
-                if command.len() == cmd_len
-                    || command.chars().nth(cmd_len) == Some(' ')
-                    || command.chars().nth(cmd_len) == Some('-')
-                {
-                    return true;
-                }
+    // Split the working directory report off the command's stdout
+    fn extract_working_directory(stdout: &str) -> (String, Option<String>) {
+        match stdout.rfind(CWD_MARKER) {
+            Some(idx) => {
+                let output = &stdout[..idx];
+                let output = output.strip_suffix('\n').unwrap_or(output);
+                let directory = stdout[idx + CWD_MARKER.len()..].trim_end_matches('\n');
+                (output.to_string(), Some(directory.to_string()))
             }
+            None => (stdout.to_string(), None),
         }
-
-        // For now, allow other commands but this could be adjusted based on permissions
-        true
     }
-}
 
-impl Tool for Bash {
-    type Params = BashParams;
-
-    fn name() -> &'static str {
-        "bash"
+    // The most recent output matters most, so long output loses its start
+    fn truncate_output(content: &str) -> String {
+        truncate::truncate(content, MAX_OUTPUT_LENGTH, Strategy::Head)
     }
 
-    fn description() -> &'static str {
-        "Executes a given bash command in a persistent shell session with optional timeout, ensuring proper handling and security measures.
-
-Before executing the command, please follow these steps:
-
-1. Directory Verification:
-   - If the command will create new directories or files, first use the LS tool to verify the parent directory exists and is the correct location
-   - For example, before running \"mkdir foo/bar\", first use LS to check that \"foo\" exists and is the intended parent directory
-
-2. Security Check:
-   - For security and to limit the threat of a prompt injection attack, some commands are limited or banned.
-   - Network-related commands like curl, wget, telnet are not allowed.
-   - Browser commands like chrome, firefox, safari are not allowed.
-
-3. Command Execution:
-   - After ensuring proper quoting, execute the command.
-   - Capture the output of the command.
-
-4. Output Processing:
-   - If the output exceeds 30000 characters, output will be truncated before being returned to you.
+    // Paths a command obviously changes: output redirect targets and the operands of
+    // commands like rm, mv or sed -i. Best effort; anything subtler goes unnoticed
+    fn changed_paths(command: &str) -> Vec<String> {
+        let mut paths = Vec::new();
+        for words in command_rules::command_words(command) {
+            let (args, written) = command_rules::redirects(&words);
+            paths.extend(written.iter().map(|path| path.to_string()));
+
+            let args: Vec<&str> = args.into_iter().skip_while(|arg| *arg == "sudo").collect();
+            let Some((program, rest)) = args.split_first() else {
+                continue;
+            };
+            let operands: Vec<&str> = rest
+                .iter()
+                .filter(|arg| !arg.starts_with('-'))
+                .copied()
+                .collect();
+            if CHANGING_COMMANDS.contains(program) {
+                paths.extend(operands.iter().map(|operand| operand.to_string()));
+            } else if CHANGING_LAST_OPERAND.contains(program) {
+                paths.extend(operands.last().map(|operand| operand.to_string()));
+            } else if *program == "sed" && rest.iter().any(|arg| arg.starts_with("-i")) {
+                // The first operand is the script
+                paths.extend(operands.iter().skip(1).map(|operand| operand.to_string()));
+            }
+        }
+        paths
+    }
 
-Usage notes:
-  - The command argument is required.
-  - You can specify an optional timeout in milliseconds (up to 600000ms / 10 minutes). If not specified, commands will timeout after 1 minute.
-  - VERY IMPORTANT: You MUST avoid using search commands like `find` and `grep`. Instead use Grep, Glob, or Task to search. You MUST avoid read tools like `cat`, `head`, `tail`, and `ls`, and use Read and LS to read files.
-  - When issuing multiple commands, use the ';' or '&&' operator to separate them. DO NOT use newlines (newlines are ok in quoted strings).
-  - Try to maintain your current working directory throughout the session by using absolute paths and avoiding usage of `cd`. You may use `cd` if the User explicitly requests it.
-    <good-example>
-    pytest /foo/bar/tests
-    </good-example>
-    <bad-example>
-    cd /foo/bar && pytest tests
-    </bad-example>"
+    // Refuse commands that obviously change a protected path
+    fn check_protected(&self, command: &str) -> Result<(), ToolError> {
+        let directory = PathBuf::from(&self.working_directory);
+        let directory = if directory.is_absolute() {
+            directory
+        } else {
+            std::env::current_dir()
+                .map(|cwd| cwd.join(&directory))
+                .unwrap_or(directory)
+        };
+        for path in Self::changed_paths(command) {
+            self.policy
+                .check_protected(&directory.join(Path::new(&path)))?;
+        }
+        Ok(())
     }
 
-    async fn call(
-        &mut self,
-        parameters: Self::Params,
-    ) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
+    /// Run a command, returning its combined output or why it could not run.
+    ///
+    /// A command that runs and exits non-zero is not an error; its exit code is
+    /// part of the output.
+    pub async fn run(&mut self, parameters: BashParams) -> ToolResult {
         let command = parameters.command.trim();
         if command.is_empty() {
-            return Ok("Error: Command is empty".to_string());
+            return Err(ToolError::InvalidParams("Command is empty".to_string()));
         }
 
-        // Check if command is allowed
-        if !self.is_command_safe(command) {
-            let base_cmd = command.split_whitespace().next().unwrap_or("");
-            return Ok(format!(
-                "Error: Command '{}' is not allowed for security reasons",
-                base_cmd
-            ));
+        // A read-only session only runs the built-in read-only commands
+        let read_only = command_rules::is_read_only_line(command);
+        if !read_only {
+            self.policy.check_writable(&format!("run: {}", command))?;
+        }
+        self.policy.check_command(command)?;
+        self.policy.check_untrusted(command)?;
+        self.check_protected(command)?;
+
+        // In dry-run mode, only read-only commands actually run
+        if self.policy.dry_run() && !read_only {
+            return Ok(ToolOutput::new(policy::simulated(&format!(
+                "run: {}",
+                command
+            ))));
         }
 
         // Get timeout duration
@@ -202,77 +327,270 @@ Usage notes:
             "-c"
         };
 
+        // Track the working directory through the shell on unix
+        let script = if cfg!(target_os = "windows") {
+            command.to_string()
+        } else {
+            Self::wrap_command(command)
+        };
+
+        // Only pass through the environment allowed by the policy
+        let (env, ignored_env) = self.command_env(std::env::vars_os(), &parameters.env);
+
         // Use tokio's async Command for timeout support
         let mut cmd = TokioCommand::new(shell);
         cmd.arg(shell_arg)
-            .arg(command)
+            .arg(script)
             .current_dir(&self.working_directory)
+            .env_clear()
+            .envs(env)
+            .stdin(Stdio::null())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
 
-        // Execute with timeout
-        let result = match timeout(timeout_duration, cmd.output()).await {
-            Ok(result) => match result {
-                Ok(output) => {
-                    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-                    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-                    let exit_code = output.status.code().unwrap_or(-1);
+        // Run the shell in its own process group so a timeout stops everything it started
+        #[cfg(unix)]
+        cmd.process_group(0);
+        cmd.kill_on_drop(true);
+
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| ToolError::io("Error executing command", e))?;
+        let idle_timeout = parameters.idle_timeout.map(Duration::from_millis);
+        let streamed = self
+            .stream_output(&mut child, start_time + timeout_duration, idle_timeout)
+            .await;
+        self.policy.report_finished(<Self as Tool>::name());
+        let (stdout, stderr, exit_code) = streamed
+            .inspect_err(|_| kill_process_group(&mut child))
+            .map_err(|error| match error {
+                StreamError::Timeout => ToolError::Timeout(timeout_duration),
+                StreamError::Idle(after) => ToolError::Idle(after),
+                StreamError::Io(e) => ToolError::io("Error executing command", e),
+            })?;
+
+        let (stdout, working_directory) = Self::extract_working_directory(&stdout);
+
+        let mut result = String::new();
+
+        // Add stdout if not empty
+        if !stdout.is_empty() {
+            result.push_str(&stdout);
+        }
 
-                    let mut result = String::new();
+        // Add stderr if not empty
+        if !stderr.is_empty() {
+            if !result.is_empty() {
+                result.push('\n');
+            }
+            result.push_str(&stderr);
+        }
 
-                    // Add stdout if not empty
-                    if !stdout.is_empty() {
-                        result.push_str(&stdout);
-                    }
+        // Add exit code if not successful
+        if exit_code != 0 {
+            if !result.is_empty() {
+                result.push('\n');
+            }
+            result.push_str(&format!("Exit code: {}", exit_code));
+        }
 
-                    // Add stderr if not empty
-                    if !stderr.is_empty() {
-                        if !result.is_empty() {
-                            result.push_str("\n");
-                        }
-                        result.push_str(&stderr);
-                    }
+        // Keep any directory change for the next command
+        if let Some(directory) = working_directory {
+            self.working_directory = directory;
+        } else if command.starts_with("cd ") && exit_code == 0 {
+            let dir = command.trim_start_matches("cd ").trim();
+            self.working_directory = dir.to_string();
+        }
 
-                    // Add exit code if not successful
-                    if exit_code != 0 {
-                        if !result.is_empty() {
-                            result.push_str("\n");
-                        }
-                        result.push_str(&format!("Exit code: {}", exit_code));
-                    }
+        let mut output = ToolOutput::new(result);
 
-                    // Check for CD command to update working directory
-                    if command.starts_with("cd ") {
-                        let dir = command.trim_start_matches("cd ").trim();
-                        // Update working directory logic would go here
-                        // For a simple implementation without proper path resolution:
-                        if exit_code == 0 {
-                            self.working_directory = dir.to_string();
-                        }
+        // Let the model know which variables it asked for were dropped
+        if !ignored_env.is_empty() {
+            output = output.with_warning(format!(
+                "ignored secret-looking environment variables: {}",
+                ignored_env.join(", ")
+            ));
+        }
+
+        Ok(output.timed(start_time))
+    }
+
+    // Read the child's output as it is printed, reporting each line to the policy's
+    // progress listener, until both pipes close and the child exits
+    async fn stream_output(
+        &self,
+        child: &mut Child,
+        deadline: Instant,
+        idle_timeout: Option<Duration>,
+    ) -> Result<(String, String, i32), StreamError> {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(read_lines(stdout, Stream::Stdout, sender.clone()));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(read_lines(stderr, Stream::Stderr, sender));
+        }
+
+        let mut stdout = CappedOutput::default();
+        let mut stderr = CappedOutput::default();
+        let mut last_output = Instant::now();
+        loop {
+            let idle_deadline = idle_timeout.map(|idle| last_output + idle);
+            let wake = idle_deadline.map_or(deadline, |idle| idle.min(deadline));
+
+            tokio::select! {
+                line = receiver.recv() => {
+                    let Some((stream, line)) = line else {
+                        break;
+                    };
+                    last_output = Instant::now();
+
+                    let shown = line.trim_end_matches(['\n', '\r']);
+                    if !shown.starts_with(CWD_MARKER) {
+                        self.policy.report_progress(<Self as Tool>::name(), shown);
+                    }
+                    match stream {
+                        Stream::Stdout => stdout.push(line),
+                        Stream::Stderr => stderr.push(line),
                     }
+                }
+                _ = tokio::time::sleep_until(wake.into()) => {
+                    return Err(match idle_timeout {
+                        Some(idle) if wake < deadline => StreamError::Idle(idle),
+                        _ => StreamError::Timeout,
+                    });
+                }
+            }
+        }
+
+        // Both pipes are closed, so the shell is exiting
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let status = timeout(remaining, child.wait())
+            .await
+            .map_err(|_| StreamError::Timeout)?
+            .map_err(StreamError::Io)?;
+
+        Ok((
+            stdout.into_string(),
+            stderr.into_string(),
+            status.code().unwrap_or(-1),
+        ))
+    }
 
-                    result
+    /// Run a command and flatten the result into the truncated text the model sees
+    pub async fn run_to_text(&mut self, parameters: BashParams) -> String {
+        let started = Instant::now();
+        match self.run(parameters).await {
+            Ok(output) => {
+                let text = Self::truncate_output(&output.with_notes());
+                if text.is_empty() && self.policy.verbose_output() {
+                    format!(
+                        "Command executed successfully in {}ms (no output)",
+                        started.elapsed().as_millis()
+                    )
+                } else if text.is_empty() {
+                    "Command executed successfully (no output)".to_string()
+                } else {
+                    text
                 }
-                Err(e) => format!("Error executing command: {}", e),
-            },
-            Err(_) => "Command execution timed out".to_string(),
-        };
+            }
+            Err(e) => format!("Error: {}", e),
+        }
+    }
+}
 
-        // Calculate execution time
-        let execution_time = start_time.elapsed().as_millis();
+// Why streaming a command's output stopped early
+enum StreamError {
+    Timeout,
+    Idle(Duration),
+    Io(std::io::Error),
+}
 
-        // Truncate output if needed
-        let truncated_result = Self::truncate_output(&result);
+// Send each line read from a pipe to `sender`, with its newline, until the pipe closes
+async fn read_lines(
+    pipe: impl AsyncRead + Unpin,
+    stream: Stream,
+    sender: mpsc::UnboundedSender<(Stream, String)>,
+) {
+    let mut reader = BufReader::new(pipe);
+    let mut buffer = Vec::new();
+    loop {
+        buffer.clear();
+        match reader.read_until(b'\n', &mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&buffer).into_owned();
+                if sender.send((stream, line)).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
 
-        if truncated_result.is_empty() {
-            Ok(format!(
-                "Command executed successfully in {}ms (no output)",
-                execution_time
-            ))
-        } else {
-            Ok(truncated_result)
+// Stop a command and everything it started
+fn kill_process_group(child: &mut Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // The shell leads its own process group, whose id is its pid
+        unsafe {
+            libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
         }
     }
+    let _ = child.start_kill();
+}
+
+impl Tool for Bash {
+    type Params = BashParams;
+
+    fn name() -> &'static str {
+        "bash"
+    }
+
+    fn description() -> &'static str {
+        "Executes a given bash command in a persistent shell session with optional timeout, ensuring proper handling and security measures.
+
+Before executing the command, please follow these steps:
+
+1. Directory Verification:
+   - If the command will create new directories or files, first use the LS tool to verify the parent directory exists and is the correct location
+   - For example, before running \"mkdir foo/bar\", first use LS to check that \"foo\" exists and is the intended parent directory
+
+2. Security Check:
+   - For security and to limit the threat of a prompt injection attack, some commands are limited or banned.
+   - Network-related commands like curl, wget, telnet are not allowed.
+   - Browser commands like chrome, firefox, safari are not allowed.
+   - Environment variables that look like secrets (names ending in _KEY, _TOKEN or _SECRET, or starting with AWS_) are removed from the environment the command runs in.
+
+3. Command Execution:
+   - After ensuring proper quoting, execute the command.
+   - Capture the output of the command.
+
+4. Output Processing:
+   - If the output exceeds 30000 characters, its start is left out and only the most recent output is returned to you.
+
+Usage notes:
+  - The command argument is required.
+  - You can specify an optional timeout in milliseconds (up to 600000ms / 10 minutes). If not specified, commands will timeout after 1 minute.
+  - You can also specify an optional idle_timeout in milliseconds to stop a command that prints nothing for that long, e.g. one waiting for input.
+  - You can pass extra environment variables for a single command with the optional env parameter. Secret-looking names are ignored.
+  - VERY IMPORTANT: You MUST avoid using search commands like `find` and `grep`. Instead use Grep, Glob, or Task to search. You MUST avoid read tools like `cat`, `head`, `tail`, and `ls`, and use Read and LS to read files.
+  - When issuing multiple commands, use the ';' or '&&' operator to separate them. DO NOT use newlines (newlines are ok in quoted strings).
+  - Try to maintain your current working directory throughout the session by using absolute paths and avoiding usage of `cd`. You may use `cd` if the User explicitly requests it.
+    <good-example>
+    pytest /foo/bar/tests
+    </good-example>
+    <bad-example>
+    cd /foo/bar && pytest tests
+    </bad-example>"
+    }
+
+    async fn call(
+        &mut self,
+        parameters: Self::Params,
+    ) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
+        Ok(self.run_to_text(parameters).await)
+    }
 }
 
 // Implement BashTool struct for our specific application
@@ -280,22 +598,27 @@ pub struct BashTool {
     bash: Bash,
 }
 
+impl Default for BashTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BashTool {
     pub fn new() -> Self {
         Self { bash: Bash::new() }
     }
 
     // Method to execute a bash command
-    pub async fn execute(&mut self, command: &str, timeout_ms: Option<u64>) -> Result<String> {
+    pub async fn execute(&mut self, command: &str, timeout_ms: Option<u64>) -> ToolResult {
         let params = BashParams {
             command: command.to_string(),
             timeout: timeout_ms,
+            idle_timeout: None,
+            env: HashMap::new(),
         };
 
-        match self.bash.call(params).await {
-            Ok(output) => Ok(output),
-            Err(e) => Err(anyhow::anyhow!("Failed to execute bash command: {}", e)),
-        }
+        self.bash.run(params).await
     }
 }
 
@@ -303,6 +626,10 @@ impl BashTool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Config;
+    use crate::tools::command_rules::{BashConfig, CommandRules, SafetyMode};
+    use crate::tools::progress::Progress;
+    use crate::tools::review::{Decision, ProposedChange, Reviewer};
     use tempfile::tempdir;
     use tokio::fs::File;
     use tokio::io::AsyncWriteExt;
@@ -326,15 +653,15 @@ mod tests {
             .execute("echo 'Hello, world!'", None)
             .await
             .unwrap();
-        assert!(result.contains("Hello, world!"));
+        assert!(result.content.contains("Hello, world!"));
 
         // Test pwd command
         let result = bash_tool.execute("pwd", None).await.unwrap();
-        assert!(!result.is_empty()); // Just check that we get some output
+        assert!(!result.content.is_empty()); // Just check that we get some output
 
         // Test ls command
         let result = bash_tool.execute("ls -la", None).await.unwrap();
-        assert!(!result.is_empty()); // Just check that we get some output
+        assert!(!result.content.is_empty()); // Just check that we get some output
     }
 
     #[tokio::test]
@@ -342,8 +669,18 @@ mod tests {
         let mut bash_tool = BashTool::new();
 
         // Test a command that should time out (sleep for 3 seconds with 1 second timeout)
-        let result = bash_tool.execute("sleep 3", Some(1000)).await.unwrap();
-        assert!(result.contains("timed out"));
+        let result = bash_tool.execute("sleep 3", Some(1000)).await;
+        assert!(matches!(result, Err(ToolError::Timeout(_))));
+
+        // The model sees a stable message
+        let params = BashParams {
+            command: "sleep 3".to_string(),
+            timeout: Some(100),
+            idle_timeout: None,
+            env: HashMap::new(),
+        };
+        let text = Bash::new().call(params).await.unwrap();
+        assert_eq!(text, "Error: Command execution timed out after 100ms");
     }
 
     #[tokio::test]
@@ -351,18 +688,16 @@ mod tests {
         let mut bash_tool = BashTool::new();
 
         // Test curl command which is banned
-        let result = bash_tool
-            .execute("curl https://example.com", None)
-            .await
-            .unwrap();
-        assert!(result.contains("not allowed"));
+        let result = bash_tool.execute("curl https://example.com", None).await;
+        assert!(matches!(result, Err(ToolError::Unsafe(_))));
 
         // Test wget command which is banned
-        let result = bash_tool
-            .execute("wget https://example.com", None)
-            .await
-            .unwrap();
-        assert!(result.contains("not allowed"));
+        let result = bash_tool.execute("wget https://example.com", None).await;
+        assert!(result.unwrap_err().to_string().contains("not allowed"));
+
+        // Empty commands are rejected as invalid
+        let result = bash_tool.execute("   ", None).await;
+        assert!(matches!(result, Err(ToolError::InvalidParams(_))));
     }
 
     #[tokio::test]
@@ -374,7 +709,10 @@ mod tests {
             .execute("cd /path/that/does/not/exist", None)
             .await
             .unwrap();
-        assert!(result.contains("No such file or directory") || result.contains("Exit code: 1"));
+        assert!(
+            result.content.contains("No such file or directory")
+                || result.content.contains("Exit code: 1")
+        );
     }
 
     #[tokio::test]
@@ -386,7 +724,7 @@ mod tests {
         let result = bash_tool
             .execute(&format!("cat {}", file_path), None)
             .await?;
-        assert!(result.contains("Test content"));
+        assert!(result.content.contains("Test content"));
 
         // Test appending to the file
         let append_cmd = format!("echo 'Additional content' >> {}", file_path);
@@ -396,8 +734,8 @@ mod tests {
         let result = bash_tool
             .execute(&format!("cat {}", file_path), None)
             .await?;
-        assert!(result.contains("Test content"));
-        assert!(result.contains("Additional content"));
+        assert!(result.content.contains("Test content"));
+        assert!(result.content.contains("Additional content"));
 
         // Keep temp_dir in scope until the end of the test
         drop(temp_dir);
@@ -418,18 +756,381 @@ mod tests {
         assert!(truncated.contains("lines truncated"));
     }
 
+    #[test]
+    fn test_secret_env_vars_not_visible() {
+        let inherited = [
+            ("SENTINEL_TEST_API_KEY".into(), "hunter2".into()),
+            ("SENTINEL_TEST_VISIBLE".into(), "visible".into()),
+        ];
+
+        let (env, ignored) = Bash::new().command_env(inherited, &HashMap::new());
+        assert_eq!(
+            env,
+            vec![("SENTINEL_TEST_VISIBLE".into(), "visible".into())]
+        );
+        assert!(ignored.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_env_vars_that_are_not_unicode_pass_through() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let value = OsString::from_vec(vec![b'a', 0xff, b'b']);
+        let inherited = [("SENTINEL_TEST_BYTES".into(), value.clone())];
+        let (env, _) = Bash::new().command_env(inherited, &HashMap::new());
+        assert_eq!(env, vec![("SENTINEL_TEST_BYTES".into(), value)]);
+    }
+
+    #[tokio::test]
+    async fn test_configured_and_per_command_env() {
+        let policy = Arc::new(ExecutionPolicy::new());
+        let mut config = Config::default();
+        config.tools.bash.env = HashMap::from([("RUST_BACKTRACE".to_string(), "1".to_string())]);
+        policy.configure(&config);
+        let mut bash = Bash::new().with_policy(policy).with_env(HashMap::from([(
+            "PROJECT_MODE".to_string(),
+            "dev".to_string(),
+        )]));
+
+        let params = BashParams {
+            command: "printenv RUST_BACKTRACE PROJECT_MODE EXTRA GITHUB_TOKEN".to_string(),
+            timeout: None,
+            idle_timeout: None,
+            env: HashMap::from([
+                ("EXTRA".to_string(), "1".to_string()),
+                ("GITHUB_TOKEN".to_string(), "ghp_secret".to_string()),
+            ]),
+        };
+        let result = bash.call(params).await.unwrap();
+
+        assert!(result.contains("1\ndev\n1"));
+        assert!(!result.contains("ghp_secret"));
+        assert!(result.contains("ignored secret-looking environment variables: GITHUB_TOKEN"));
+    }
+
     #[tokio::test]
-    async fn test_command_safety_check() {
-        let bash = Bash::new();
-
-        // Test safe commands
-        assert!(bash.is_command_safe("ls -la"));
-        assert!(bash.is_command_safe("echo 'test'"));
-        assert!(bash.is_command_safe("git status"));
-
-        // Test banned commands
-        assert!(!bash.is_command_safe("curl https://example.com"));
-        assert!(!bash.is_command_safe("wget https://example.com"));
-        assert!(!bash.is_command_safe("chrome index.html"));
+    async fn test_dry_run_skips_mutating_commands() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let target = dir.path().join("created.txt");
+        let mut bash = Bash::new().with_policy(ExecutionPolicy::shared(true));
+
+        let params = BashParams {
+            command: format!("touch {}", target.display()),
+            timeout: None,
+            idle_timeout: None,
+            env: HashMap::new(),
+        };
+        let result = bash.call(params).await.unwrap();
+        assert!(result.starts_with("[DRY RUN] Would run: touch"));
+        assert!(!target.exists());
+
+        // A redirect without spaces around it is a write too
+        let redirected = dir.path().join("e.txt");
+        let result = bash
+            .call(BashParams::new(format!(
+                "echo dry>{}",
+                redirected.display()
+            )))
+            .await
+            .unwrap();
+        assert!(result.starts_with("[DRY RUN] Would run: echo dry>"));
+        assert!(!redirected.exists());
+
+        // Read-only commands still run
+        let params = BashParams {
+            command: "echo still running".to_string(),
+            timeout: None,
+            idle_timeout: None,
+            env: HashMap::new(),
+        };
+        let result = bash.call(params).await.unwrap();
+        assert_eq!(result.trim(), "still running");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_only_session_only_runs_read_only_commands() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let target = dir.path().join("created.txt");
+        let policy = ExecutionPolicy::shared(false);
+        policy.set_read_only();
+        let mut bash = Bash::new().with_policy(policy);
+
+        for command in [
+            format!("touch {}", target.display()),
+            format!("echo hi > {}", target.display()),
+            format!("env touch {}", target.display()),
+            format!("ls && touch {}", target.display()),
+            format!("git diff --output={}", target.display()),
+            format!("git log --output {}", target.display()),
+            format!("tree -o {}", target.display()),
+        ] {
+            let error = bash.run(BashParams::new(command)).await.unwrap_err();
+            assert!(matches!(error, ToolError::Unsafe(_)));
+            assert!(error.to_string().contains("this is a read-only session"));
+        }
+        assert!(!target.exists());
+
+        let output = bash.run(BashParams::new("echo still running")).await?;
+        assert_eq!(output.content.trim(), "still running");
+        Ok(())
+    }
+
+    // Collects the lines reported by running tools
+    #[derive(Default)]
+    struct Recorded(std::sync::Mutex<Vec<String>>);
+
+    impl Progress for Recorded {
+        fn line(&self, tool: &str, line: &str) {
+            self.0.lock().unwrap().push(format!("{}: {}", tool, line));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_output_is_streamed() {
+        let policy = Arc::new(ExecutionPolicy::new());
+        let recorded = Arc::new(Recorded::default());
+        policy.set_progress(recorded.clone());
+        let mut bash = Bash::new().with_policy(policy);
+
+        let result = bash
+            .call(BashParams::new("echo one; echo two >&2; echo three"))
+            .await
+            .unwrap();
+        assert!(result.starts_with("one\nthree\n"));
+        assert!(result.trim_end().ends_with("two"));
+
+        let mut lines = recorded.0.lock().unwrap().clone();
+        lines.sort();
+        assert_eq!(lines, ["bash: ", "bash: one", "bash: three", "bash: two"]);
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let params = BashParams {
+            idle_timeout: Some(200),
+            ..BashParams::new("echo started; sleep 5")
+        };
+        let started = Instant::now();
+        let result = Bash::new().run(params).await;
+        assert!(matches!(result, Err(ToolError::Idle(_))));
+        assert!(started.elapsed() < Duration::from_secs(3));
+
+        // Output keeps the command alive
+        let params = BashParams {
+            idle_timeout: Some(500),
+            ..BashParams::new("for i in 1 2 3 4; do echo $i; sleep 0.2; done")
+        };
+        let result = Bash::new().run(params).await.unwrap();
+        assert_eq!(result.content.trim_end(), "1\n2\n3\n4");
+    }
+
+    #[tokio::test]
+    async fn test_timeout_stops_subprocesses() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let marker = dir.path().join("still-running");
+
+        let params = BashParams {
+            timeout: Some(300),
+            ..BashParams::new(format!("(sleep 1; touch {}) & wait", marker.display()))
+        };
+        let result = Bash::new().run(params).await;
+        assert!(matches!(result, Err(ToolError::Timeout(_))));
+
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(!marker.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_capped_output() {
+        let mut output = CappedOutput::default();
+        output.push("```diff\n".to_string());
+        for i in 0..MAX_OUTPUT_LENGTH {
+            output.push(format!("line {}\n", i));
+        }
+        let text = output.into_string();
+        assert!(text.len() <= MAX_OUTPUT_LENGTH + 100);
+        assert!(text.starts_with("... ["));
+        assert!(text.contains(" bytes] ...\n\n```diff\nline "));
+        assert!(text.ends_with(&format!("line {}\n", MAX_OUTPUT_LENGTH - 1)));
+    }
+
+    #[test]
+    fn test_changed_paths() {
+        assert_eq!(
+            Bash::changed_paths("rm -rf target migrations/01.sql && ls"),
+            vec!["target", "migrations/01.sql"]
+        );
+        assert_eq!(
+            Bash::changed_paths("cargo build 2>&1 | tee build.log"),
+            vec!["build.log"]
+        );
+        assert_eq!(
+            Bash::changed_paths("echo done > out.txt; cat a >>'b.txt'"),
+            vec!["out.txt", "b.txt"]
+        );
+        assert_eq!(
+            Bash::changed_paths("cp a.rs b.rs src/; sed -i 's/a/b/' Cargo.lock"),
+            vec!["src/", "Cargo.lock"]
+        );
+        assert_eq!(
+            Bash::changed_paths("cat Cargo.lock > /dev/null; grep x migrations/*"),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            Bash::changed_paths("echo b>Cargo.lock; ls >&out.txt 2>&1"),
+            vec!["Cargo.lock", "out.txt"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_protected_paths_refuse_commands() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("Cargo.lock"), "locked")?;
+        let policy = Arc::new(ExecutionPolicy::new());
+        policy.set_sandbox_root(dir.path().to_path_buf());
+        policy.protect("Cargo.lock");
+        let mut bash = Bash::new().with_policy(policy);
+        bash.working_directory = dir.path().to_string_lossy().to_string();
+
+        // Reading a protected file is fine
+        let output = bash.run(BashParams::new("cat Cargo.lock")).await?;
+        assert!(output.content.contains("locked"));
+
+        for command in ["echo x > Cargo.lock", "echo x>Cargo.lock"] {
+            let refused = bash.run(BashParams::new(command)).await;
+            assert!(matches!(
+                refused,
+                Err(ToolError::Unsafe(message)) if message.contains("write-protected by the rule 'Cargo.lock'")
+            ));
+        }
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("Cargo.lock"))?,
+            "locked"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_secret_env_var() {
+        assert!(is_secret_env_var("OPENAI_API_KEY"));
+        assert!(is_secret_env_var("CLAUDE_API_KEY"));
+        assert!(is_secret_env_var("github_token"));
+        assert!(is_secret_env_var("CLIENT_SECRET"));
+        assert!(is_secret_env_var("AWS_REGION"));
+        assert!(!is_secret_env_var("PATH"));
+        assert!(!is_secret_env_var("KEYBOARD"));
+    }
+
+    #[tokio::test]
+    async fn test_command_rules_are_applied() {
+        let mut bash = BashTool::new();
+        let error = bash
+            .execute("ls && /usr/bin/env curl https://example.com", None)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Command 'curl' is not allowed"));
+
+        let policy = Arc::new(ExecutionPolicy::new());
+        policy.set_command_rules(CommandRules::new(&BashConfig {
+            deny: vec!["git push".to_string()],
+            ..BashConfig::default()
+        }));
+        let mut bash = Bash::new().with_policy(Arc::clone(&policy));
+        let result = bash.run(BashParams::new("true; git push")).await;
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("matches the deny rule 'git push'"));
+    }
+
+    // Answers command confirmations with a fixed decision, recording what it was asked
+    struct Confirm {
+        decision: Decision,
+        asked: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl Reviewer for Confirm {
+        fn review(&self, _change: &ProposedChange) -> Decision {
+            Decision::Approve
+        }
+
+        fn confirm_command(&self, command: &str, _reason: &str) -> Decision {
+            self.asked.lock().unwrap().push(command.to_string());
+            self.decision.clone()
+        }
+    }
+
+    #[test]
+    fn test_read_only_sessions_check_strictly_without_asking() {
+        let policy = Arc::new(ExecutionPolicy::new());
+        policy.set_command_rules(CommandRules::new(&BashConfig {
+            mode: SafetyMode::Permissive,
+            allow: vec!["make".to_string()],
+            ..BashConfig::default()
+        }));
+        let approving = Arc::new(Confirm {
+            decision: Decision::ApproveAll,
+            asked: Default::default(),
+        });
+        policy.set_reviewer(approving.clone());
+        policy.approve_commands();
+        policy.set_read_only();
+
+        let error = policy.check_command("make install").unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("this is a read-only session, and strict mode"));
+        assert!(policy.check_command("git status").is_ok());
+        assert!(approving.asked.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_strict_mode_asks_before_unlisted_commands() {
+        let strict = || {
+            let policy = Arc::new(ExecutionPolicy::new());
+            policy.set_command_rules(CommandRules::new(&BashConfig {
+                mode: SafetyMode::Strict,
+                allow: vec!["printf".to_string()],
+                ..BashConfig::default()
+            }));
+            policy
+        };
+
+        // Allowlisted and read-only commands run without asking
+        let mut bash = Bash::new().with_policy(strict());
+        let output = bash
+            .run(BashParams::new("printf hi | wc -c"))
+            .await
+            .unwrap();
+        assert!(output.content.contains('2'));
+
+        // Nobody to ask
+        let error = bash.run(BashParams::new("mkdir -p x")).await.unwrap_err();
+        assert!(error.to_string().contains("'mkdir' is not on the list"));
+
+        let policy = strict();
+        let declining = Arc::new(Confirm {
+            decision: Decision::Reject {
+                reason: Some("no new directories".to_string()),
+            },
+            asked: Default::default(),
+        });
+        policy.set_reviewer(declining.clone());
+        let mut bash = Bash::new().with_policy(policy);
+        let error = bash.run(BashParams::new("mkdir -p x")).await.unwrap_err();
+        assert!(error.to_string().contains("Reason: no new directories"));
+        assert_eq!(*declining.asked.lock().unwrap(), ["mkdir -p x"]);
+
+        let policy = strict();
+        policy.set_reviewer(Arc::new(Confirm {
+            decision: Decision::Approve,
+            asked: Default::default(),
+        }));
+        let mut bash = Bash::new().with_policy(policy);
+        let output = bash.run(BashParams::new("expr 1 + 1")).await.unwrap();
+        assert!(output.content.contains('2'));
     }
 }
@@ -0,0 +1,483 @@
+//! What the tools changed in a session, as one diff.
+//!
+//! The [`Journal`](crate::tools::journal::Journal) keeps what a file held
+//! before each change; the first of those is what it held before the session
+//! touched it. Comparing that with what is on disk now gives one diff per
+//! file, however many times it was written in between. Files created or
+//! deleted are diffed whole, and binary files are listed without a diff.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::tools::journal::{Entry, Journal, Operation};
+use crate::tools::review::{DiffLine, Hunk, ProposedChange};
+
+/// What a file held before the session first touched it
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Before {
+    Missing,
+    Content(Vec<u8>),
+    /// Moved there from a file the session hadn't touched
+    Unknown,
+}
+
+/// Whether a file is new, gone or changed since the session began
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Created,
+    Deleted,
+    Modified,
+}
+
+/// How the content of a file changed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Content {
+    Text(Vec<Hunk>),
+    /// Binary on either side, so not diffed
+    Binary,
+    /// A directory copied there
+    Directory,
+}
+
+/// One file the session changed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileDiff {
+    pub path: PathBuf,
+    /// Where a moved file came from
+    pub renamed_from: Option<PathBuf>,
+    pub status: Status,
+    pub content: Content,
+}
+
+impl FileDiff {
+    pub fn added(&self) -> usize {
+        self.count(|line| matches!(line, DiffLine::Added(_)))
+    }
+
+    pub fn removed(&self) -> usize {
+        self.count(|line| matches!(line, DiffLine::Removed(_)))
+    }
+
+    fn count(&self, predicate: impl Fn(&DiffLine) -> bool) -> usize {
+        match &self.content {
+            Content::Text(hunks) => hunks
+                .iter()
+                .flat_map(|hunk| &hunk.lines)
+                .filter(|line| predicate(line))
+                .count(),
+            Content::Binary | Content::Directory => 0,
+        }
+    }
+
+    /// "path (+3 -1)", "path (new file, +3)", "path (binary, deleted)" and so on
+    pub fn summary(&self) -> String {
+        let mut details = Vec::new();
+        if let Some(source) = &self.renamed_from {
+            details.push(format!("moved from {}", source.display()));
+        }
+        let kind = match self.content {
+            Content::Text(_) => None,
+            Content::Binary => Some("binary"),
+            Content::Directory => Some("directory"),
+        };
+        details.extend(kind.map(str::to_string));
+        match (self.status, kind) {
+            (Status::Created, None) => {
+                details.push(format!("new file, +{}", self.added()));
+            }
+            (Status::Deleted, None) => details.push(format!("deleted, -{}", self.removed())),
+            (Status::Modified, None) => {
+                details.push(format!("+{} -{}", self.added(), self.removed()))
+            }
+            (Status::Created, Some(_)) => details.push("created".to_string()),
+            (Status::Deleted, Some(_)) => details.push("deleted".to_string()),
+            (Status::Modified, Some(_)) => details.push("modified".to_string()),
+        }
+        format!("{} ({})", self.path.display(), details.join(", "))
+    }
+
+    /// The change as a unified diff, or None if it isn't text or only moved
+    pub fn unified_diff(&self) -> Option<String> {
+        let Content::Text(hunks) = &self.content else {
+            return None;
+        };
+        if hunks.is_empty() {
+            return None;
+        }
+
+        let old = match self.status {
+            Status::Created => "/dev/null".to_string(),
+            _ => format!(
+                "a/{}",
+                self.renamed_from.as_deref().unwrap_or(&self.path).display()
+            ),
+        };
+        let new = match self.status {
+            Status::Deleted => "/dev/null".to_string(),
+            _ => format!("b/{}", self.path.display()),
+        };
+        let mut diff = format!("--- {}\n+++ {}\n", old, new);
+        for hunk in hunks {
+            diff.push_str(&hunk.header());
+            diff.push('\n');
+            for line in &hunk.lines {
+                diff.push_str(&line.unified());
+                diff.push('\n');
+            }
+        }
+        Some(diff)
+    }
+}
+
+/// Every file a session changed, in the order they were first changed
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SessionDiff {
+    pub files: Vec<FileDiff>,
+}
+
+impl SessionDiff {
+    /// Compare what each file in `entries` held before the first of them with
+    /// what it holds now
+    pub fn from_entries(entries: &[Entry]) -> Self {
+        let mut touched: Vec<(PathBuf, Before, Option<PathBuf>)> = Vec::new();
+        for operation in operations(entries) {
+            match operation {
+                Operation::Write { path, previous }
+                | Operation::Copy {
+                    destination: path,
+                    previous,
+                } => first_touch(&mut touched, path, previous.clone()),
+                Operation::Delete { path, previous } => {
+                    first_touch(&mut touched, path, Some(previous.clone()))
+                }
+                // A moved file takes what it held before with it
+                Operation::Move {
+                    source,
+                    destination,
+                } => {
+                    let index = touched.iter().position(|(path, ..)| path == source);
+                    let (before, renamed_from) = match index.map(|index| touched.remove(index)) {
+                        Some((_, Before::Missing, _)) => (Before::Missing, None),
+                        Some((_, before, renamed_from)) => {
+                            (before, renamed_from.or_else(|| Some(source.clone())))
+                        }
+                        None => (Before::Unknown, Some(source.clone())),
+                    };
+                    touched.retain(|(path, ..)| path != destination);
+                    touched.push((destination.clone(), before, renamed_from));
+                }
+                Operation::Patch { .. } => {}
+            }
+        }
+
+        let files = touched
+            .into_iter()
+            .filter_map(|(path, before, renamed_from)| diff_file(path, before, renamed_from))
+            .collect();
+        Self { files }
+    }
+
+    /// What the journal's changes did, with paths relative to the current directory
+    pub fn of_journal(journal: &Journal) -> Self {
+        let diff = Self::from_entries(&journal.entries());
+        match std::env::current_dir() {
+            Ok(dir) => diff.relative_to(&dir),
+            Err(_) => diff,
+        }
+    }
+
+    /// Show paths relative to `dir` where they are inside it
+    pub fn relative_to(mut self, dir: &Path) -> Self {
+        let relative = |path: &Path| path.strip_prefix(dir).unwrap_or(path).to_path_buf();
+        for file in &mut self.files {
+            file.path = relative(&file.path);
+            file.renamed_from = file.renamed_from.as_deref().map(relative);
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn added(&self) -> usize {
+        self.files.iter().map(FileDiff::added).sum()
+    }
+
+    pub fn removed(&self) -> usize {
+        self.files.iter().map(FileDiff::removed).sum()
+    }
+
+    /// "4 files changed, +120 -35"
+    pub fn summary(&self) -> String {
+        let files = match self.files.len() {
+            1 => "1 file".to_string(),
+            count => format!("{} files", count),
+        };
+        format!("{} changed, +{} -{}", files, self.added(), self.removed())
+    }
+
+    /// Each file's summary and diff, then the totals
+    pub fn render(&self) -> String {
+        if self.is_empty() {
+            return "No files changed".to_string();
+        }
+        let mut text = String::new();
+        for file in &self.files {
+            text.push_str(&file.summary());
+            text.push('\n');
+            if let Some(diff) = file.unified_diff() {
+                text.push_str(&diff);
+            }
+            text.push('\n');
+        }
+        text.push_str(&self.summary());
+        text
+    }
+}
+
+/// What a line of [`SessionDiff::render`] is, for frontends that color it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind {
+    /// A file's summary, or the totals
+    Title,
+    /// The ---/+++ lines naming the files compared
+    Header,
+    Hunk,
+    Added,
+    Removed,
+    Context,
+}
+
+impl LineKind {
+    pub fn of(line: &str) -> Self {
+        if line.starts_with("--- ") || line.starts_with("+++ ") {
+            Self::Header
+        } else if line.starts_with("@@ ") {
+            Self::Hunk
+        } else if line.starts_with('+') {
+            Self::Added
+        } else if line.starts_with('-') {
+            Self::Removed
+        } else if line.starts_with(' ') {
+            Self::Context
+        } else {
+            Self::Title
+        }
+    }
+}
+
+// Every operation, with the files of each patch in its place
+fn operations(entries: &[Entry]) -> Vec<&Operation> {
+    entries
+        .iter()
+        .flat_map(|entry| match &entry.operation {
+            Operation::Patch { files } => operations(files),
+            operation => vec![operation],
+        })
+        .collect()
+}
+
+// Note what `path` held, unless an earlier change already did
+fn first_touch(
+    touched: &mut Vec<(PathBuf, Before, Option<PathBuf>)>,
+    path: &Path,
+    previous: Option<Vec<u8>>,
+) {
+    let before = previous.map_or(Before::Missing, Before::Content);
+    match touched.iter_mut().find(|(touched, ..)| touched == path) {
+        // Written after it was moved there: now it is known what it held
+        Some((_, known @ Before::Unknown, _)) => *known = before,
+        Some(_) => {}
+        None => touched.push((path.to_path_buf(), before, None)),
+    }
+}
+
+// How `path` differs from what it held before, or None if it doesn't
+fn diff_file(path: PathBuf, before: Before, renamed_from: Option<PathBuf>) -> Option<FileDiff> {
+    let now = if path.is_dir() {
+        None
+    } else {
+        fs::read(&path).ok()
+    };
+    let status = match (&before, &now) {
+        (Before::Missing, None) if !path.is_dir() => return None,
+        (Before::Missing, _) => Status::Created,
+        (_, None) if !path.is_dir() => Status::Deleted,
+        _ => Status::Modified,
+    };
+    if let (Before::Content(old), Some(now)) = (&before, &now) {
+        if old == now && renamed_from.is_none() {
+            return None;
+        }
+    }
+
+    let content = if path.is_dir() {
+        Content::Directory
+    } else {
+        let old = match &before {
+            Before::Content(content) => Some(text(content)),
+            // What a moved file held is only known if it was written after
+            Before::Unknown => now.as_deref().map(text),
+            Before::Missing => None,
+        };
+        let new = now.as_deref().map_or(Some(""), text);
+        match (old, new) {
+            (Some(None), _) | (_, None) => Content::Binary,
+            (old, Some(new)) => {
+                let old = old.flatten().unwrap_or("");
+                Content::Text(ProposedChange::new("", path.clone(), Some(old), new).hunks)
+            }
+        }
+    };
+
+    Some(FileDiff {
+        path,
+        renamed_from,
+        status,
+        content,
+    })
+}
+
+// The content as text, or None if it is binary
+fn text(content: &[u8]) -> Option<&str> {
+    if content.contains(&0) {
+        return None;
+    }
+    std::str::from_utf8(content).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::journal::backup;
+    use tempfile::tempdir;
+
+    // Write `content` to `path` the way the file tool does, recording it
+    fn write(journal: &Journal, path: &Path, content: &[u8]) {
+        let before = backup(path);
+        fs::write(path, content).unwrap();
+        journal.record_write(path, before);
+    }
+
+    fn delete(journal: &Journal, path: &Path) {
+        let before = backup(path);
+        fs::remove_file(path).unwrap();
+        journal.record_delete(path, before);
+    }
+
+    #[test]
+    fn test_each_file_is_diffed_against_its_first_backup() {
+        let dir = tempdir().unwrap();
+        let main = dir.path().join("main.rs");
+        let notes = dir.path().join("notes.txt");
+        let old = dir.path().join("old.txt");
+        let logo = dir.path().join("logo.png");
+        fs::write(&main, "fn main() {\n    println!(\"hi\");\n}\n").unwrap();
+        fs::write(&old, "one\ntwo\n").unwrap();
+        fs::write(&logo, [137u8, 80, 78, 71, 0, 1]).unwrap();
+
+        let journal = Journal::new();
+        write(
+            &journal,
+            &main,
+            b"fn main() {\n    println!(\"hello\");\n}\n",
+        );
+        write(&journal, &notes, b"first\n");
+        write(
+            &journal,
+            &main,
+            b"fn main() {\n    println!(\"hello\");\n    run();\n}\n",
+        );
+        delete(&journal, &old);
+        write(&journal, &logo, &[137u8, 80, 78, 71, 0, 2]);
+        // Created and removed again, so nothing to show
+        let scratch = dir.path().join("scratch.txt");
+        write(&journal, &scratch, b"temporary\n");
+        delete(&journal, &scratch);
+
+        let diff = SessionDiff::from_entries(&journal.entries()).relative_to(dir.path());
+        assert_eq!(
+            diff.render(),
+            "main.rs (+2 -1)\n\
+             --- a/main.rs\n\
+             +++ b/main.rs\n\
+             @@ -1,3 +1,4 @@\n \
+             fn main() {\n\
+             -    println!(\"hi\");\n\
+             +    println!(\"hello\");\n\
+             +    run();\n \
+             }\n\
+             \n\
+             notes.txt (new file, +1)\n\
+             --- /dev/null\n\
+             +++ b/notes.txt\n\
+             @@ -0,0 +1,1 @@\n\
+             +first\n\
+             \n\
+             old.txt (deleted, -2)\n\
+             --- a/old.txt\n\
+             +++ /dev/null\n\
+             @@ -1,2 +0,0 @@\n\
+             -one\n\
+             -two\n\
+             \n\
+             logo.png (binary, modified)\n\
+             \n\
+             4 files changed, +3 -3"
+        );
+    }
+
+    #[test]
+    fn test_moves_and_patches() {
+        let dir = tempdir().unwrap();
+        let source = dir.path().join("lib.rs");
+        let destination = dir.path().join("core.rs");
+        let untouched = dir.path().join("a.txt");
+        fs::write(&source, "pub fn a() {}\n").unwrap();
+        fs::write(&untouched, "a\n").unwrap();
+
+        let journal = Journal::new();
+        write(&journal, &source, b"pub fn a() {}\npub fn b() {}\n");
+        fs::rename(&source, &destination).unwrap();
+        journal.record(Operation::Move {
+            source: source.clone(),
+            destination: destination.clone(),
+        });
+
+        // A file the session never wrote, moved and then patched
+        let moved = dir.path().join("b.txt");
+        fs::rename(&untouched, &moved).unwrap();
+        journal.record(Operation::Move {
+            source: untouched.clone(),
+            destination: moved.clone(),
+        });
+        let before = backup(&moved);
+        fs::write(&moved, "b\n").unwrap();
+        journal.record_patch(vec![(moved.clone(), before)]);
+
+        let diff = SessionDiff::from_entries(&journal.entries()).relative_to(dir.path());
+        let summaries: Vec<String> = diff.files.iter().map(FileDiff::summary).collect();
+        assert_eq!(
+            summaries,
+            [
+                "core.rs (moved from lib.rs, +1 -0)",
+                "b.txt (moved from a.txt, +1 -1)"
+            ]
+        );
+        assert!(diff.files[0]
+            .unified_diff()
+            .unwrap()
+            .starts_with("--- a/lib.rs\n+++ b/core.rs\n@@ -1,1 +1,2 @@\n"));
+        assert_eq!(diff.summary(), "2 files changed, +2 -1");
+        assert_eq!(LineKind::of("+++ b/core.rs"), LineKind::Header);
+        assert_eq!(LineKind::of("+pub fn b() {}"), LineKind::Added);
+        assert_eq!(LineKind::of(&diff.summary()), LineKind::Title);
+
+        assert_eq!(SessionDiff::default().render(), "No files changed");
+    }
+}
@@ -0,0 +1,972 @@
+//! Which commands the bash tool may run.
+//!
+//! A command line is split into the simple commands it runs: the parts of
+//! pipelines and `;`, `&&` and `||` chains, subshells, `$(...)` and backtick
+//! substitutions, and the scripts given to `sh -c`, `eval` or `find -exec`.
+//! Each is reduced to the program it runs by dropping wrappers like `env`,
+//! `sudo` or `timeout` and leading `VAR=value` assignments, and by taking the
+//! basename of an absolute path. The split is a light one; it is meant to
+//! catch a banned command hidden in a chain, not to parse every shell script.
+//!
+//! The `[tools.bash]` config table picks a mode and adds allow and deny rules.
+//! A rule is a command with optional leading arguments, e.g. "git push", and
+//! matches any command starting with those words.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::Deserialize;
+
+/// Network and browser commands refused unless an allow rule lets them run
+pub const BANNED_COMMANDS: [&str; 17] = [
+    "alias",
+    "curl",
+    "curlie",
+    "wget",
+    "axel",
+    "aria2c",
+    "nc",
+    "telnet",
+    "lynx",
+    "w3m",
+    "links",
+    "httpie",
+    "xh",
+    "http-prompt",
+    "chrome",
+    "firefox",
+    "safari",
+];
+
+/// Commands that only inspect state: they still run in dry-run mode, and
+/// strict mode runs them without asking
+pub const READ_ONLY_COMMANDS: [&str; 46] = [
+    "ls",
+    "echo",
+    "pwd",
+    "date",
+    "cal",
+    "uptime",
+    "whoami",
+    "id",
+    "groups",
+    "env",
+    "printenv",
+    "which",
+    "type",
+    "whereis",
+    "whatis",
+    "uname",
+    "hostname",
+    "df",
+    "du",
+    "free",
+    "ps",
+    "cat",
+    "head",
+    "tail",
+    "wc",
+    "grep",
+    "rg",
+    "tree",
+    "stat",
+    "file",
+    "git status",
+    "git log",
+    "git diff",
+    "git show",
+    "git ls-files",
+    "git rev-parse",
+    "git blame",
+    "git grep",
+    "git shortlog",
+    "go version",
+    "go list",
+    "go env",
+    "go doc",
+    "cargo --version",
+    "rustc --version",
+    "true",
+];
+
+// Options that make a read-only command write a file or run another program,
+// e.g. `git diff --output=patch`. Long options also match with "=value", short
+// ones anywhere in a group of flags
+const WRITE_OPTIONS: [(&str, &[&str]); 7] = [
+    ("git diff", &["--output"]),
+    ("git log", &["--output"]),
+    ("git show", &["--output"]),
+    ("git grep", &["-O", "--open-files-in-pager"]),
+    ("tree", &["-o"]),
+    ("rg", &["--pre"]),
+    ("file", &["-C", "--compile"]),
+];
+
+// Commands that run the rest of their arguments as another command
+const WRAPPERS: [&str; 13] = [
+    "env", "sudo", "doas", "command", "builtin", "exec", "nohup", "time", "nice", "timeout",
+    "xargs", "stdbuf", "then",
+];
+
+// Shell words that come before a command rather than being one
+const RESERVED_WORDS: [&str; 10] = [
+    "!", "{", "}", "if", "elif", "else", "do", "while", "until", "done",
+];
+
+// Shells whose -c option takes a script
+const SHELLS: [&str; 6] = ["sh", "bash", "zsh", "dash", "ksh", "fish"];
+
+// How deeply nested scripts are looked into
+const MAX_DEPTH: usize = 8;
+
+/// How strictly commands are checked
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SafetyMode {
+    /// Only the first command of a line is checked, as typed
+    Permissive,
+    /// Banned and denied commands are refused anywhere in a line
+    #[default]
+    Balanced,
+    /// Only allowed and read-only commands run; anything else needs confirmation
+    Strict,
+}
+
+impl fmt::Display for SafetyMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Permissive => "permissive",
+            Self::Balanced => "balanced",
+            Self::Strict => "strict",
+        })
+    }
+}
+
+/// The `[tools.bash]` config table
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct BashConfig {
+    pub mode: SafetyMode,
+    /// Commands that may run, even if banned by default, e.g. "curl"
+    pub allow: Vec<String>,
+    /// Commands that never run, e.g. "git push"; these win over `allow`
+    pub deny: Vec<String>,
+    /// Times in a turn the model is asked to correct a command that exited
+    /// non-zero before it reports the failure; 0 leaves it to the model
+    pub auto_retry: usize,
+    /// Environment variables set for every command, e.g. RUST_BACKTRACE = "1"
+    pub env: HashMap<String, String>,
+}
+
+/// Why a command was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    /// One of the built-in network and browser commands
+    Banned,
+    /// A deny rule from the config
+    Denied(String),
+}
+
+/// What the rules make of a command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Allowed,
+    Refused {
+        /// The command refused, e.g. "curl" or "git push"
+        token: String,
+        rule: Rule,
+        mode: SafetyMode,
+    },
+    /// Strict mode: the commands that are not allowlisted, for the user to confirm
+    Unlisted(Vec<String>),
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Allowed => f.write_str("allowed"),
+            Self::Refused {
+                token,
+                rule: Rule::Banned,
+                mode,
+            } => write!(
+                f,
+                "Command '{}' is not allowed: it is a built-in banned network or browser \
+                 command ({} mode). Add it to [tools.bash] allow to let it run",
+                token, mode
+            ),
+            Self::Refused {
+                token,
+                rule: Rule::Denied(rule),
+                mode,
+            } => write!(
+                f,
+                "Command '{}' is not allowed: it matches the deny rule '{}' in [tools.bash] ({} mode)",
+                token, rule, mode
+            ),
+            Self::Unlisted(tokens) => write!(
+                f,
+                "strict mode only runs allowlisted commands, and {} {} not on the list",
+                tokens
+                    .iter()
+                    .map(|token| format!("'{}'", token))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                if tokens.len() == 1 { "is" } else { "are" }
+            ),
+        }
+    }
+}
+
+// An allow or deny rule, split into words
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Pattern {
+    text: String,
+    words: Vec<String>,
+}
+
+impl Pattern {
+    fn new(text: &str) -> Option<Self> {
+        let mut words: Vec<String> = text.split_whitespace().map(str::to_string).collect();
+        let program = program_name(words.first()?);
+        words[0] = program;
+        Some(Self {
+            text: words.join(" "),
+            words,
+        })
+    }
+
+    fn matches(&self, command: &[String]) -> bool {
+        command.len() >= self.words.len()
+            && self
+                .words
+                .iter()
+                .zip(command)
+                .all(|(word, arg)| word == arg)
+    }
+}
+
+/// The command rules of a session
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandRules {
+    mode: SafetyMode,
+    allow: Vec<Pattern>,
+    deny: Vec<Pattern>,
+}
+
+impl CommandRules {
+    pub fn new(config: &BashConfig) -> Self {
+        let patterns =
+            |rules: &[String]| rules.iter().filter_map(|rule| Pattern::new(rule)).collect();
+        Self {
+            mode: config.mode,
+            allow: patterns(&config.allow),
+            deny: patterns(&config.deny),
+        }
+    }
+
+    pub fn mode(&self) -> SafetyMode {
+        self.mode
+    }
+
+    /// The rules of a read-only session: strict, with only the read-only
+    /// commands running, whatever the allow rules say
+    pub fn read_only(&self) -> Self {
+        Self {
+            mode: SafetyMode::Strict,
+            allow: Vec::new(),
+            deny: self.deny.clone(),
+        }
+    }
+
+    /// Check every command `line` runs, as far as the mode looks
+    pub fn check(&self, line: &str) -> Verdict {
+        let commands = match self.mode {
+            // Only the first word, as the tool always did
+            SafetyMode::Permissive => {
+                let mut commands = Vec::new();
+                split(line, 0, &mut commands);
+                commands.truncate(1);
+                for command in &mut commands {
+                    if let Some(program) = command.first_mut() {
+                        *program = program.to_lowercase();
+                    }
+                }
+                commands
+            }
+            SafetyMode::Balanced | SafetyMode::Strict => simple_commands(line),
+        };
+
+        let mut unlisted = Vec::new();
+        for command in commands.iter().filter(|command| !command.is_empty()) {
+            if let Some(rule) = self.deny.iter().find(|rule| rule.matches(command)) {
+                return Verdict::Refused {
+                    token: rule.text.clone(),
+                    rule: Rule::Denied(rule.text.clone()),
+                    mode: self.mode,
+                };
+            }
+            if self.allow.iter().any(|rule| rule.matches(command)) {
+                continue;
+            }
+            if BANNED_COMMANDS.contains(&command[0].as_str()) {
+                return Verdict::Refused {
+                    token: command[0].clone(),
+                    rule: Rule::Banned,
+                    mode: self.mode,
+                };
+            }
+            if self.mode == SafetyMode::Strict
+                && !is_read_only(command)
+                && !unlisted.contains(&command[0])
+            {
+                unlisted.push(command[0].clone());
+            }
+        }
+
+        if unlisted.is_empty() {
+            Verdict::Allowed
+        } else {
+            Verdict::Unlisted(unlisted)
+        }
+    }
+}
+
+/// Whether every command `line` runs is one of the built-in read-only ones,
+/// writing nothing through its options or redirects
+pub fn is_read_only_line(line: &str) -> bool {
+    simple_commands(line)
+        .iter()
+        .all(|command| command.is_empty() || is_read_only(command))
+}
+
+// Whether a command is one of the built-in read-only ones, without an option
+// or redirect that writes
+fn is_read_only(command: &[String]) -> bool {
+    if !redirects(command).1.is_empty() {
+        return false;
+    }
+    READ_ONLY_COMMANDS.iter().any(|read_only| {
+        let words: Vec<&str> = read_only.split(' ').collect();
+        command.len() >= words.len()
+            && words.iter().zip(command).all(|(word, arg)| word == arg)
+            && !has_write_option(read_only, &command[words.len()..])
+    })
+}
+
+// Whether the arguments of `read_only` include one of its write options
+fn has_write_option(read_only: &str, args: &[String]) -> bool {
+    let Some((_, options)) = WRITE_OPTIONS
+        .iter()
+        .find(|(command, _)| *command == read_only)
+    else {
+        return false;
+    };
+    args.iter().any(|arg| {
+        options.iter().any(|option| {
+            if option.starts_with("--") {
+                arg == option || arg.starts_with(&format!("{}=", option))
+            } else {
+                arg.starts_with('-') && !arg.starts_with("--") && arg.contains(&option[1..])
+            }
+        })
+    })
+}
+
+/// Split the words of a command into its arguments and the files its
+/// redirects write to. Redirects to /dev/null or to another descriptor, like
+/// 2>&1 or >&-, write nothing
+pub fn redirects(words: &[String]) -> (Vec<&str>, Vec<&str>) {
+    let mut args = Vec::new();
+    let mut written = Vec::new();
+    let mut words = words.iter().map(String::as_str);
+    while let Some(word) = words.next() {
+        let Some((operator, target)) = redirect_operator(word) else {
+            args.push(word);
+            continue;
+        };
+        let target = match target {
+            "" => words.next().unwrap_or_default(),
+            target => target,
+        };
+        // >&word duplicates a descriptor when word is one, and writes to
+        // the file word otherwise
+        let is_descriptor =
+            target == "-" || (!target.is_empty() && target.chars().all(|c| c.is_ascii_digit()));
+        if operator.contains('>')
+            && target != "/dev/null"
+            && !(operator.ends_with('&') && is_descriptor)
+        {
+            written.push(target);
+        }
+    }
+    (args, written)
+}
+
+/// The simple commands a line runs, each reduced to the program it runs and
+/// its arguments, with the program as a lowercase basename
+pub fn simple_commands(line: &str) -> Vec<Vec<String>> {
+    let mut commands = Vec::new();
+    collect(line, 0, &mut commands);
+    commands
+}
+
+// Add the commands of `line`, and of the scripts they run, to `commands`
+fn collect(line: &str, depth: usize, commands: &mut Vec<Vec<String>>) {
+    let mut split_commands = Vec::new();
+    split(line, depth, &mut split_commands);
+    for words in split_commands {
+        let mut nested = Vec::new();
+        let command = unwrap(words, &mut nested);
+        for script in nested {
+            collect(&script, depth + 1, commands);
+        }
+        if let Some(command) = command {
+            scripts_of(&command, depth, commands);
+            commands.push(command);
+        }
+    }
+}
+
+// Look into the scripts a command runs: `sh -c`, `eval` and `find -exec`
+fn scripts_of(command: &[String], depth: usize, commands: &mut Vec<Vec<String>>) {
+    let program = command[0].as_str();
+    let args = &command[1..];
+    if SHELLS.contains(&program) {
+        // -c, or combined flags like -lc or -ec
+        let script = args
+            .iter()
+            .position(|arg| arg.starts_with('-') && !arg.starts_with("--") && arg.contains('c'))
+            .and_then(|index| args.get(index + 1));
+        if let Some(script) = script {
+            collect(script, depth + 1, commands);
+        }
+    } else if program == "eval" {
+        collect(&args.join(" "), depth + 1, commands);
+    } else if program == "find" {
+        let mut args = args.iter();
+        while args.any(|arg| ["-exec", "-execdir", "-ok", "-okdir"].contains(&arg.as_str())) {
+            let executed: Vec<String> = args
+                .by_ref()
+                .take_while(|arg| *arg != ";" && *arg != "+")
+                .cloned()
+                .collect();
+            let mut nested = Vec::new();
+            if let Some(command) = unwrap(executed, &mut nested) {
+                commands.push(command);
+            }
+        }
+    }
+}
+
+// Drop assignments, redirects, reserved words and wrappers from the front of a
+// command, leaving the program with a lowercase basename. Scripts passed to
+// `env -S` are added to `nested`. None if nothing is left to run
+fn unwrap(words: Vec<String>, nested: &mut Vec<String>) -> Option<Vec<String>> {
+    let mut words = words.into_iter().peekable();
+    loop {
+        let word = words.peek()?;
+        if is_assignment(word) || RESERVED_WORDS.contains(&word.as_str()) {
+            words.next();
+            continue;
+        }
+        if let Some((_, target)) = redirect_operator(word) {
+            let takes_target = target.is_empty();
+            words.next();
+            if takes_target {
+                words.next();
+            }
+            continue;
+        }
+
+        let program = program_name(word);
+        if !WRAPPERS.contains(&program.as_str()) {
+            break;
+        }
+        words.next();
+        // The wrapper's own options, and the arguments some of them take
+        while let Some(option) = words.peek().filter(|word| word.starts_with('-')).cloned() {
+            words.next();
+            if option == "--" {
+                break;
+            }
+            // `command -v curl` looks a command up without running it
+            if program == "command" && (option == "-v" || option == "-V") {
+                return None;
+            }
+            if program == "env" && (option == "-S" || option == "--split-string") {
+                nested.extend(words.next());
+            } else if takes_argument(&program, &option) {
+                words.next();
+            }
+        }
+        if program == "timeout" {
+            // The duration
+            words.next();
+        } else if program == "nice" && words.peek().is_some_and(|word| word.parse::<i32>().is_ok())
+        {
+            words.next();
+        }
+    }
+
+    let mut command: Vec<String> = words.collect();
+    command[0] = program_name(&command[0]);
+    Some(command)
+}
+
+// Whether a wrapper's option takes the next word as its argument
+fn takes_argument(program: &str, option: &str) -> bool {
+    let options: &[&str] = match program {
+        "sudo" => &["-u", "-g", "-h", "-p", "-C", "-D", "-r", "-t", "-U"],
+        "doas" => &["-u", "-C"],
+        "env" => &["-u", "-C", "--unset", "--chdir"],
+        "nice" => &["-n", "--adjustment"],
+        "timeout" => &["-s", "-k", "--signal", "--kill-after"],
+        "xargs" => &["-I", "-n", "-P", "-L", "-d", "-E", "-s", "-a"],
+        "exec" => &["-a"],
+        "time" => &["-f", "-o"],
+        "stdbuf" => &["-i", "-o", "-e"],
+        _ => &[],
+    };
+    options.contains(&option)
+}
+
+// A command's name without its directory, e.g. "curl" for "/usr/bin/curl"
+fn program_name(word: &str) -> String {
+    word.rsplit('/').next().unwrap_or(word).to_lowercase()
+}
+
+// Whether a word sets a variable for the command, e.g. LANG=C
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty()
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+// The operator and target of a redirect word like ">out", "2>>log", ">&2" or
+// "<in", the target being empty when it is the next word
+fn redirect_operator(word: &str) -> Option<(&str, &str)> {
+    let operator = word.trim_start_matches(|c: char| c.is_ascii_digit());
+    let operator = operator.strip_prefix('&').unwrap_or(operator);
+    let target = [">>", ">|", ">&", ">", "<<<", "<<", "<&", "<"]
+        .iter()
+        .find_map(|redirect| operator.strip_prefix(redirect))?;
+    Some((&word[..word.len() - target.len()], target))
+}
+
+/// The words of each simple command a line runs, with quotes removed but
+/// wrappers and redirects kept
+pub fn command_words(line: &str) -> Vec<Vec<String>> {
+    let mut commands = Vec::new();
+    split(line, 0, &mut commands);
+    commands
+}
+
+// Split a line into the words of each simple command, with quotes removed.
+// Substitutions are split too, their commands coming before the one using them
+fn split(line: &str, depth: usize, commands: &mut Vec<Vec<String>>) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+    let chars: Vec<char> = line.chars().collect();
+    let mut words: Vec<String> = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+
+    let end_word = |words: &mut Vec<String>, word: &mut String, in_word: &mut bool| {
+        if *in_word {
+            words.push(std::mem::take(word));
+            *in_word = false;
+        }
+    };
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' => {
+                if let Some(&next) = chars.get(i + 1) {
+                    if next != '\n' {
+                        word.push(next);
+                        in_word = true;
+                    }
+                    i += 1;
+                }
+            }
+            '\'' => {
+                let end = find(&chars, i + 1, '\'');
+                word.extend(&chars[i + 1..end]);
+                in_word = true;
+                i = end;
+            }
+            '"' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    match chars[i] {
+                        '\\' if i + 1 < chars.len() => {
+                            i += 1;
+                            word.push(chars[i]);
+                        }
+                        '`' => {
+                            let end = find(&chars, i + 1, '`');
+                            split(
+                                &chars[i + 1..end].iter().collect::<String>(),
+                                depth + 1,
+                                commands,
+                            );
+                            i = end;
+                        }
+                        '$' if chars.get(i + 1) == Some(&'(') => {
+                            let end = closing(&chars, i + 2);
+                            split(
+                                &chars[i + 2..end].iter().collect::<String>(),
+                                depth + 1,
+                                commands,
+                            );
+                            i = end;
+                        }
+                        c => word.push(c),
+                    }
+                    i += 1;
+                }
+                in_word = true;
+            }
+            '`' => {
+                let end = find(&chars, i + 1, '`');
+                split(
+                    &chars[i + 1..end].iter().collect::<String>(),
+                    depth + 1,
+                    commands,
+                );
+                in_word = true;
+                i = end;
+            }
+            '$' if chars.get(i + 1) == Some(&'(') => {
+                let end = closing(&chars, i + 2);
+                split(
+                    &chars[i + 2..end].iter().collect::<String>(),
+                    depth + 1,
+                    commands,
+                );
+                in_word = true;
+                i = end;
+            }
+            // Process substitution, e.g. diff <(ls a) <(ls b)
+            '<' | '>' if !in_word && chars.get(i + 1) == Some(&'(') => {
+                let end = closing(&chars, i + 2);
+                split(
+                    &chars[i + 2..end].iter().collect::<String>(),
+                    depth + 1,
+                    commands,
+                );
+                i = end;
+            }
+            '#' if !in_word => {
+                while i + 1 < chars.len() && chars[i + 1] != '\n' {
+                    i += 1;
+                }
+            }
+            ' ' | '\t' => end_word(&mut words, &mut word, &mut in_word),
+            // A redirect starts a word of its own even inside one, as in
+            // echo x>out, unless the word so far is its descriptor, as in 2>out
+            '>' => {
+                if !word
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || c == '&' || c == '>')
+                {
+                    end_word(&mut words, &mut word, &mut in_word);
+                }
+                word.push(c);
+                in_word = true;
+            }
+            // The & of redirects like 2>&1 or &>file
+            '&' if word.ends_with('>') || chars.get(i + 1) == Some(&'>') => {
+                if !word.ends_with('>') {
+                    end_word(&mut words, &mut word, &mut in_word);
+                }
+                word.push(c);
+                in_word = true;
+            }
+            // The | of >|file, which writes even with noclobber set
+            '|' if word.ends_with('>') => {
+                word.push(c);
+                in_word = true;
+            }
+            ';' | '\n' | '|' | '&' | '(' | ')' => {
+                end_word(&mut words, &mut word, &mut in_word);
+                if !words.is_empty() {
+                    commands.push(std::mem::take(&mut words));
+                }
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+        i += 1;
+    }
+    end_word(&mut words, &mut word, &mut in_word);
+    if !words.is_empty() {
+        commands.push(words);
+    }
+}
+
+// The index of the next `target` from `start`, or the end of the line
+fn find(chars: &[char], start: usize, target: char) -> usize {
+    (start..chars.len())
+        .find(|&i| chars[i] == target)
+        .unwrap_or(chars.len())
+}
+
+// The index of the `)` closing a `(` just before `start`, skipping nested
+// parentheses and quotes, or the end of the line
+fn closing(chars: &[char], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' | '"' => i = find(chars, i + 1, chars[i]),
+            '(' => depth += 1,
+            ')' if depth == 0 => return i,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn programs(line: &str) -> Vec<String> {
+        simple_commands(line)
+            .into_iter()
+            .map(|command| command[0].clone())
+            .collect()
+    }
+
+    fn rules(mode: SafetyMode, allow: &[&str], deny: &[&str]) -> CommandRules {
+        CommandRules::new(&BashConfig {
+            mode,
+            allow: allow.iter().map(|rule| rule.to_string()).collect(),
+            deny: deny.iter().map(|rule| rule.to_string()).collect(),
+            ..BashConfig::default()
+        })
+    }
+
+    fn refused(verdict: Verdict) -> (String, Rule) {
+        match verdict {
+            Verdict::Refused { token, rule, .. } => (token, rule),
+            verdict => panic!("expected a refusal, got {:?}", verdict),
+        }
+    }
+
+    #[test]
+    fn test_wrappers_are_stripped() {
+        assert_eq!(programs("/usr/bin/curl example.com"), ["curl"]);
+        assert_eq!(programs("env -u HOME LANG=C curl x"), ["curl"]);
+        assert_eq!(programs("sudo -u root command wget x"), ["wget"]);
+        assert_eq!(
+            programs("HTTPS_PROXY=p nohup nice -n 5 timeout 10 /bin/nc -l 80"),
+            ["nc"]
+        );
+        assert_eq!(programs("exec -a name xargs -n 1 curl"), ["curl"]);
+        assert_eq!(programs("env -S 'curl x' "), ["curl"]);
+        assert_eq!(
+            simple_commands("time git  push --force 2>&1"),
+            [["git", "push", "--force", "2>&1"]]
+        );
+        assert_eq!(programs("> out.txt LANG=C ls"), ["ls"]);
+        // Looking a command up doesn't run it
+        assert!(programs("command -v curl").is_empty());
+        assert_eq!(programs("env"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_chains_pipelines_and_subshells_are_split() {
+        assert_eq!(
+            programs("ls -la | grep x && curl y; wget z || nc w"),
+            ["ls", "grep", "curl", "wget", "nc"]
+        );
+        assert_eq!(programs("echo a\ncurl b & wait"), ["echo", "curl", "wait"]);
+        assert_eq!(programs("(cd /tmp && curl x)"), ["cd", "curl"]);
+        assert_eq!(programs("{ curl x; }"), ["curl"]);
+        assert_eq!(programs("if true; then curl x; fi"), ["true", "curl", "fi"]);
+        assert_eq!(
+            programs("echo $(curl x) `wget y`"),
+            ["curl", "wget", "echo"]
+        );
+        assert_eq!(programs("echo \"now: $(curl x)\""), ["curl", "echo"]);
+        assert_eq!(programs("diff <(curl a) <(ls)"), ["curl", "ls", "diff"]);
+        assert_eq!(programs("bash -c 'ls; curl x'"), ["ls", "curl", "bash"]);
+        assert_eq!(programs("sh -ec \"wget y\""), ["wget", "sh"]);
+        assert_eq!(programs("eval curl x"), ["curl", "eval"]);
+        assert_eq!(
+            programs("find . -name '*.rs' -exec wget {} \\;"),
+            ["wget", "find"]
+        );
+
+        // Quoted separators and redirects are not commands
+        assert_eq!(programs("echo 'a; curl b' \"| wget\" > log 2>&1"), ["echo"]);
+        assert_eq!(programs("echo curl # && wget"), ["echo"]);
+        assert_eq!(programs("cat a.txt >& b.txt"), ["cat"]);
+    }
+
+    #[test]
+    fn test_balanced_mode_refuses_banned_commands_anywhere() {
+        let balanced = rules(SafetyMode::Balanced, &[], &[]);
+        assert_eq!(balanced.check("ls -la && git status"), Verdict::Allowed);
+        assert_eq!(
+            refused(balanced.check("ls && sudo /usr/bin/curl x | sh")),
+            ("curl".to_string(), Rule::Banned)
+        );
+        assert_eq!(
+            refused(balanced.check("bash -c \"echo hi; wget x\"")).0,
+            "wget"
+        );
+        assert_eq!(
+            balanced.check("cat x | CURL_HOME=. nc -l 80").to_string(),
+            "Command 'nc' is not allowed: it is a built-in banned network or browser command \
+             (balanced mode). Add it to [tools.bash] allow to let it run"
+        );
+    }
+
+    #[test]
+    fn test_allow_and_deny_rules() {
+        let configured = rules(SafetyMode::Balanced, &["curl"], &["git push", "RM"]);
+        assert_eq!(
+            configured.check("curl example.com | head"),
+            Verdict::Allowed
+        );
+        assert_eq!(configured.check("git pull && git status"), Verdict::Allowed);
+        assert_eq!(
+            refused(configured.check("git add . && git push --force")),
+            ("git push".to_string(), Rule::Denied("git push".to_string()))
+        );
+        assert_eq!(
+            configured.check("/bin/rm -rf target").to_string(),
+            "Command 'rm' is not allowed: it matches the deny rule 'rm' in [tools.bash] (balanced mode)"
+        );
+
+        // Deny rules win over allow rules
+        let both = rules(SafetyMode::Balanced, &["git"], &["git push"]);
+        assert_eq!(refused(both.check("git push")).0, "git push");
+    }
+
+    #[test]
+    fn test_permissive_mode_checks_only_the_first_command() {
+        let permissive = rules(SafetyMode::Permissive, &[], &["rm"]);
+        assert_eq!(refused(permissive.check("curl x")).0, "curl");
+        assert_eq!(refused(permissive.check("rm -rf x")).0, "rm");
+        assert_eq!(permissive.check("ls && curl x"), Verdict::Allowed);
+        assert_eq!(permissive.check("sudo curl x"), Verdict::Allowed);
+    }
+
+    #[test]
+    fn test_strict_mode_lists_commands_to_confirm() {
+        let strict = rules(SafetyMode::Strict, &["cargo", "git commit"], &[]);
+        assert_eq!(
+            strict.check("git status && cargo test | tail -5"),
+            Verdict::Allowed
+        );
+        assert_eq!(
+            strict.check("make && git commit -m x && npm install && make clean"),
+            Verdict::Unlisted(vec!["make".to_string(), "npm".to_string()])
+        );
+        assert_eq!(
+            strict.check("git push").to_string(),
+            "strict mode only runs allowlisted commands, and 'git' is not on the list"
+        );
+        // Banned commands are refused rather than asked about
+        assert_eq!(refused(strict.check("make; curl x")).1, Rule::Banned);
+    }
+
+    #[test]
+    fn test_read_only_lines() {
+        assert!(is_read_only_line("ls -la"));
+        assert!(is_read_only_line("git status && git diff --stat | tail -5"));
+        assert!(is_read_only_line("env"));
+        assert!(is_read_only_line("grep -r x . 2>/dev/null"));
+        assert!(is_read_only_line("git log --oneline 2>&1 | head"));
+        assert!(is_read_only_line("git diff --output-indicator-new=+"));
+        assert!(!is_read_only_line("cargo build"));
+        assert!(!is_read_only_line("lsof"));
+        assert!(!is_read_only_line("ls && rm -rf target"));
+        assert!(!is_read_only_line("env rm -rf target"));
+        assert!(!is_read_only_line("echo $(rm -rf target)"));
+        assert!(!is_read_only_line("echo hi > out.txt"));
+        assert!(!is_read_only_line("cat a >>b"));
+        assert!(!is_read_only_line("ls &> listing"));
+    }
+
+    #[test]
+    fn test_redirects_inside_words_write() {
+        assert!(!is_read_only_line("echo x>f"));
+        assert!(!is_read_only_line("echo x>>f"));
+        assert!(!is_read_only_line("ls >&f"));
+        assert!(!is_read_only_line("ls >& f"));
+        assert!(!is_read_only_line("ls 2>f"));
+        assert!(!is_read_only_line("cat a>|f"));
+        assert!(!is_read_only_line("echo x&>f"));
+        assert!(is_read_only_line("ls 2>&1|head"));
+        assert!(is_read_only_line("ls x>/dev/null 2>&-"));
+        assert_eq!(programs("echo x>f"), vec!["echo"]);
+        assert_eq!(programs("cat a>|f"), vec!["cat"]);
+    }
+
+    #[test]
+    fn test_write_options_make_read_only_commands_mutating() {
+        assert!(!is_read_only_line("git diff --output=f"));
+        assert!(!is_read_only_line("git diff --output f"));
+        assert!(!is_read_only_line("git log -p --output=f"));
+        assert!(!is_read_only_line("git show HEAD --output=f"));
+        assert!(!is_read_only_line("tree -o f"));
+        assert!(!is_read_only_line("tree -ao f"));
+        assert!(!is_read_only_line("git grep -Ovim x"));
+        assert!(!is_read_only_line("rg --pre ./script x"));
+        assert!(!is_read_only_line("file -C -m magic"));
+        assert!(is_read_only_line("tree -a -L 2"));
+        assert!(is_read_only_line("rg -o x"));
+
+        let strict = rules(SafetyMode::Strict, &[], &[]);
+        assert_eq!(
+            strict.check("tree -o f"),
+            Verdict::Unlisted(vec!["tree".to_string()])
+        );
+        assert_eq!(
+            strict.check("echo hi > out.txt"),
+            Verdict::Unlisted(vec!["echo".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_read_only_rules_ignore_the_allowlist() {
+        let configured = rules(SafetyMode::Permissive, &["cargo"], &["git log"]);
+        let read_only = configured.read_only();
+        assert_eq!(read_only.mode(), SafetyMode::Strict);
+        assert_eq!(
+            read_only.check("cargo build"),
+            Verdict::Unlisted(vec!["cargo".to_string()])
+        );
+        assert_eq!(refused(read_only.check("git log")).0, "git log");
+        assert_eq!(read_only.check("git status"), Verdict::Allowed);
+    }
+
+    #[test]
+    fn test_the_mode_is_read_from_config() {
+        let config: BashConfig =
+            toml::from_str("mode = \"strict\"\nallow = [\"make\"]\ndeny = [\"git push\"]").unwrap();
+        assert_eq!(config.mode, SafetyMode::Strict);
+        assert_eq!(config.allow, ["make"]);
+        assert_eq!(BashConfig::default().mode, SafetyMode::Balanced);
+        assert!(toml::from_str::<BashConfig>("mode = \"lax\"").is_err());
+    }
+}
@@ -0,0 +1,497 @@
+//! Project-specific tools declared in the config as shell commands.
+//!
+//! A `[[tools.custom]]` table names a tool, says what it does, declares its
+//! parameters and gives a command template such as `cargo test {filter} --quiet`.
+//! Each `{param}` in the template is replaced by the argument's value, quoted
+//! as one shell word, and the command runs through the bash tool, with its
+//! timeout and safety checks.
+//!
+//! A custom tool may not run sentinel: commands that name it are refused, and
+//! sentinel refuses to start under a custom tool, which it tells by
+//! [`RECURSION_ENV`] in its environment.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ollama_rs::generation::tools::Tool as OllamaTool;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::llm::ollama::Tool;
+use crate::privacy;
+use crate::tools::bash::{Bash, BashParams};
+use crate::tools::command_rules;
+use crate::tools::invoke;
+use crate::tools::policy::ExecutionPolicy;
+use crate::tools::registry::CallableTool;
+use crate::tools::result::ToolError;
+
+/// Set to the name of the custom tool for the commands it runs
+pub const RECURSION_ENV: &str = "SENTINEL_CUSTOM_TOOL";
+
+/// The name of the one tool the ollama-rs Coordinator calls custom tools through
+pub const DISPATCHER: &str = "custom";
+
+/// A `[[tools.custom]]` config table
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CustomToolConfig {
+    /// What the model calls the tool, e.g. "test"
+    pub name: String,
+    /// What the tool does, for the model
+    pub description: String,
+    /// The command to run, with a `{param}` for each parameter
+    pub command: String,
+    /// The tool's parameters, in order
+    #[serde(default)]
+    pub params: Vec<ParamConfig>,
+    /// Seconds the command may run; the bash tool's default of a minute if unset
+    pub timeout_secs: Option<u64>,
+}
+
+/// A parameter of a custom tool
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ParamConfig {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub kind: ParamType,
+    #[serde(default)]
+    pub description: String,
+    /// Whether the model must give it; a missing optional one leaves its
+    /// placeholder empty
+    #[serde(default = "required_by_default")]
+    pub required: bool,
+}
+
+fn required_by_default() -> bool {
+    true
+}
+
+/// The JSON type a parameter takes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamType {
+    #[default]
+    String,
+    Integer,
+    Number,
+    Boolean,
+}
+
+impl ParamType {
+    fn schema_name(self) -> &'static str {
+        match self {
+            Self::String => "string",
+            Self::Integer => "integer",
+            Self::Number => "number",
+            Self::Boolean => "boolean",
+        }
+    }
+}
+
+impl CustomToolConfig {
+    /// Check the declaration makes a tool the model can call
+    pub fn check(&self) -> Result<(), String> {
+        let valid_name = |name: &str| {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        };
+        if !valid_name(&self.name) {
+            return Err(format!(
+                "'{}' is not a tool name: use letters, digits, '_' and '-'",
+                self.name
+            ));
+        }
+        if self.command.trim().is_empty() {
+            return Err("the command is empty".to_string());
+        }
+        for (index, param) in self.params.iter().enumerate() {
+            if !valid_name(&param.name) {
+                return Err(format!("'{}' is not a parameter name", param.name));
+            }
+            if self.params[..index].iter().any(|p| p.name == param.name) {
+                return Err(format!("the parameter '{}' is declared twice", param.name));
+            }
+            if !self.command.contains(&format!("{{{}}}", param.name)) {
+                return Err(format!(
+                    "the command has no {{{}}} for the parameter '{}'",
+                    param.name, param.name
+                ));
+            }
+        }
+        check_not_recursive(&self.command).map_err(|e| e.to_string())
+    }
+
+    /// Name, description and parameter schema, to offer to the model
+    pub fn definition(&self) -> Tool {
+        let properties: Map<String, Value> = self
+            .params
+            .iter()
+            .map(|param| {
+                let mut schema = json!({ "type": param.kind.schema_name() });
+                if !param.description.is_empty() {
+                    schema["description"] = json!(param.description);
+                }
+                (param.name.clone(), schema)
+            })
+            .collect();
+        let required: Vec<&str> = self
+            .params
+            .iter()
+            .filter(|param| param.required)
+            .map(|param| param.name.as_str())
+            .collect();
+
+        Tool {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            input_schema: json!({
+                "type": "object",
+                "properties": properties,
+                "required": required,
+            }),
+        }
+    }
+
+    /// The command to run for `arguments`, each value quoted as one shell word
+    pub fn render(&self, arguments: &Value) -> Result<String, ToolError> {
+        invoke::validate(&self.definition(), arguments)?;
+
+        // One pass over the template, so a value holding {name} is never
+        // filled in again
+        let mut command = String::new();
+        let mut rest = self.command.as_str();
+        while let Some(open) = rest.find('{') {
+            command.push_str(&rest[..open]);
+            let after = &rest[open + 1..];
+            let param = after.find('}').and_then(|close| {
+                let name = &after[..close];
+                self.params
+                    .iter()
+                    .find(|param| param.name == name)
+                    .map(|param| (param, close))
+            });
+            let Some((param, close)) = param else {
+                command.push('{');
+                rest = after;
+                continue;
+            };
+            match arguments.get(&param.name) {
+                Some(Value::String(text)) => command.push_str(&quote(text)),
+                Some(value) => command.push_str(&quote(&value.to_string())),
+                None => {}
+            }
+            rest = &after[close + 1..];
+        }
+        command.push_str(rest);
+        Ok(command)
+    }
+}
+
+/// `text` as one shell word, however hostile: single-quoted, with each
+/// single quote closed, escaped and reopened
+pub fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+// Refuse a command that runs sentinel itself
+fn check_not_recursive(command: &str) -> Result<(), ToolError> {
+    let recursive = command_rules::simple_commands(command)
+        .iter()
+        .any(|words| words.first().is_some_and(|program| program == "sentinel"));
+    if recursive {
+        return Err(ToolError::Unsafe(
+            "custom tools may not run sentinel".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// A custom tool, ready to run
+pub struct CustomTool {
+    config: CustomToolConfig,
+    bash: Bash,
+    policy: Arc<ExecutionPolicy>,
+}
+
+impl CustomTool {
+    /// Run the tool's commands through a bash tool following `policy`
+    pub fn new(config: CustomToolConfig, policy: Arc<ExecutionPolicy>) -> Self {
+        let bash = Bash::new()
+            .with_policy(Arc::clone(&policy))
+            .with_env(HashMap::from([(
+                RECURSION_ENV.to_string(),
+                config.name.clone(),
+            )]));
+        Self {
+            config,
+            bash,
+            policy,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// Run the command for `arguments`, returning its output as the bash tool does
+    pub async fn run(&mut self, arguments: &Value) -> Result<String, ToolError> {
+        let command = self.config.render(arguments)?;
+        check_not_recursive(&command)?;
+
+        let mut parameters = BashParams::new(command);
+        if let Some(secs) = self.config.timeout_secs {
+            parameters = parameters.with_timeout(secs * 1000);
+        }
+        let output = self.bash.run_to_text(parameters).await;
+        self.policy
+            .check_remote(&[privacy::tool_output(&self.config.name)])?;
+        Ok(output)
+    }
+}
+
+#[async_trait]
+impl CallableTool for CustomTool {
+    fn definition(&self) -> Tool {
+        self.config.definition()
+    }
+
+    fn is_custom(&self) -> bool {
+        true
+    }
+
+    async fn call_json(&mut self, arguments: Value) -> Result<String, ToolError> {
+        self.run(&arguments).await
+    }
+}
+
+/// The custom tools as one tool for the ollama-rs Coordinator, whose tools
+/// are types with fixed names; [`catalog`] tells the model what they are
+pub struct CustomTools {
+    tools: Vec<CustomTool>,
+}
+
+impl CustomTools {
+    pub fn new(configs: &[CustomToolConfig], policy: &Arc<ExecutionPolicy>) -> Self {
+        let tools = configs
+            .iter()
+            .map(|config| CustomTool::new(config.clone(), Arc::clone(policy)))
+            .collect();
+        Self { tools }
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct CustomToolsParams {
+    #[schemars(description = "The name of the custom tool to run")]
+    tool: String,
+
+    #[schemars(description = "The custom tool's arguments, by parameter name")]
+    #[serde(default)]
+    arguments: Map<String, Value>,
+}
+
+impl OllamaTool for CustomTools {
+    type Params = CustomToolsParams;
+
+    fn name() -> &'static str {
+        DISPATCHER
+    }
+
+    fn description() -> &'static str {
+        "Runs one of the project's custom tools, which are listed in the system prompt with their parameters."
+    }
+
+    async fn call(
+        &mut self,
+        parameters: Self::Params,
+    ) -> ollama_rs::generation::tools::Result<String> {
+        let Some(tool) = self
+            .tools
+            .iter_mut()
+            .find(|tool| tool.name() == parameters.tool)
+        else {
+            let names: Vec<&str> = self.tools.iter().map(CustomTool::name).collect();
+            return Ok(format!(
+                "Error: No custom tool named '{}'; the custom tools are: {}",
+                parameters.tool,
+                names.join(", ")
+            ));
+        };
+        match tool.run(&Value::Object(parameters.arguments)).await {
+            Ok(output) => Ok(output),
+            Err(e) => Ok(format!("Error: {}", e)),
+        }
+    }
+}
+
+/// The custom tools and their parameters, for a model that calls them through
+/// the [`DISPATCHER`] tool
+pub fn catalog(configs: &[CustomToolConfig]) -> String {
+    let mut text = format!(
+        "This project has custom tools. Run one by calling the `{}` tool with its name as `tool` and its parameters as `arguments`:",
+        DISPATCHER
+    );
+    for config in configs {
+        let params: Vec<String> = config
+            .params
+            .iter()
+            .map(|param| {
+                let optional = if param.required { "" } else { "?" };
+                format!("{}{}: {}", param.name, optional, param.kind.schema_name())
+            })
+            .collect();
+        text.push_str(&format!(
+            "\n- {}({}): {}",
+            config.name,
+            params.join(", "),
+            config.description
+        ));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_tool() -> CustomToolConfig {
+        toml::from_str(
+            r#"
+            name = "test"
+            description = "Run the project's tests"
+            command = "cargo test {filter} --quiet --jobs {jobs}"
+            params = [
+                { name = "filter", description = "Only tests whose name contains this", required = false },
+                { name = "jobs", type = "integer" },
+            ]
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_schema_comes_from_the_declaration() {
+        let config = test_tool();
+        assert_eq!(config.check(), Ok(()));
+
+        let definition = config.definition();
+        assert_eq!(definition.name, "test");
+        assert_eq!(
+            definition.input_schema,
+            json!({
+                "type": "object",
+                "properties": {
+                    "filter": { "type": "string", "description": "Only tests whose name contains this" },
+                    "jobs": { "type": "integer" },
+                },
+                "required": ["jobs"],
+            })
+        );
+        assert!(catalog(&[config])
+            .ends_with("\n- test(filter?: string, jobs: integer): Run the project's tests"));
+    }
+
+    #[test]
+    fn test_bad_declarations_are_refused() {
+        let mut config = test_tool();
+        config.name = "run tests".to_string();
+        assert!(config.check().unwrap_err().contains("not a tool name"));
+
+        let mut config = test_tool();
+        config.command = "cargo test --quiet --jobs {jobs}".to_string();
+        assert_eq!(
+            config.check(),
+            Err("the command has no {filter} for the parameter 'filter'".to_string())
+        );
+
+        let mut config = test_tool();
+        config.command = "FOO=1 /usr/local/bin/sentinel ask {filter} {jobs}".to_string();
+        assert!(config.check().unwrap_err().contains("may not run sentinel"));
+    }
+
+    #[test]
+    fn test_hostile_values_stay_one_word() {
+        let config = test_tool();
+        let command = config
+            .render(&json!({ "filter": "x'; rm -rf / #", "jobs": 2 }))
+            .unwrap();
+        assert_eq!(
+            command,
+            r#"cargo test 'x'\''; rm -rf / #' --quiet --jobs '2'"#
+        );
+        let programs: Vec<String> = command_rules::simple_commands(&command)
+            .into_iter()
+            .map(|words| words[0].clone())
+            .collect();
+        assert_eq!(programs, ["cargo"]);
+
+        // Optional parameters may be left out; the others are checked
+        assert_eq!(
+            config.render(&json!({ "jobs": 4 })).unwrap(),
+            "cargo test  --quiet --jobs '4'"
+        );
+        assert!(matches!(
+            config.render(&json!({ "filter": "x" })),
+            Err(ToolError::InvalidParams(_))
+        ));
+        assert!(matches!(
+            config.render(&json!({ "jobs": "$(reboot)" })),
+            Err(ToolError::InvalidParams(_))
+        ));
+    }
+
+    #[test]
+    fn test_values_naming_other_parameters_are_not_filled_in() {
+        let config: CustomToolConfig = toml::from_str(
+            r#"
+            name = "search"
+            description = "Search files"
+            command = "grep -r {pattern} {path} | head -${LINES:-20}"
+            params = [{ name = "pattern" }, { name = "path" }]
+            "#,
+        )
+        .unwrap();
+        let command = config
+            .render(&json!({ "pattern": "{path}", "path": "; rm -rf / #" }))
+            .unwrap();
+        assert_eq!(
+            command,
+            "grep -r '{path}' '; rm -rf / #' | head -${LINES:-20}"
+        );
+        let programs: Vec<String> = command_rules::simple_commands(&command)
+            .into_iter()
+            .map(|words| words[0].clone())
+            .collect();
+        assert_eq!(programs, ["grep", "head"]);
+    }
+
+    #[tokio::test]
+    async fn test_commands_run_through_bash() {
+        let config: CustomToolConfig = toml::from_str(
+            r#"
+            name = "greet"
+            description = "Say hello"
+            command = "printf 'hello %s from %s' {name} \"$SENTINEL_CUSTOM_TOOL\""
+            params = [{ name = "name" }]
+            "#,
+        )
+        .unwrap();
+        let mut tool = CustomTool::new(config, Arc::default());
+        let output = tool
+            .run(&json!({ "name": "$(whoami) & co" }))
+            .await
+            .unwrap();
+        assert_eq!(output, "hello $(whoami) & co from greet");
+
+        let mut tools = CustomTools::new(&[tool.config.clone()], &Arc::default());
+        let params = CustomToolsParams {
+            tool: "greet".to_string(),
+            arguments: json!({ "name": "you" }).as_object().unwrap().clone(),
+        };
+        assert_eq!(tools.call(params).await.unwrap(), "hello you from greet");
+    }
+}
@@ -1,17 +1,21 @@
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::time::Instant;
-use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use crate::tools::filename;
+use crate::tools::journal::{self, Operation};
+use crate::tools::lines::{self, LineRange, ReadMode};
+use crate::tools::policy::{self, ExecutionPolicy};
+use crate::tools::read_cache::{self, Freshness, ReadCache};
+use crate::tools::result::{ToolError, ToolOutput, ToolResult};
+use crate::tools::review::{self, Decision, ProposedChange};
 use ollama_rs::generation::tools::Tool;
 use schemars::JsonSchema;
 use serde::Deserialize;
 use tokio::fs::File as TokioFile;
 use tokio::io::AsyncWriteExt;
 
-const MAX_OUTPUT_LENGTH: usize = 30000;
-
 // Removed the individual parameter structs as they are now merged into FileParams
 
 #[derive(Deserialize, JsonSchema)]
@@ -33,191 +37,482 @@ pub struct FileParams {
     
     #[schemars(description = "The destination path for move or copy operations")]
     destination: Option<String>,
+    
+    #[schemars(description = "Return the content even if the file is unchanged since it was last read (for read operation), or delete a directory over the size limits (for delete operation)")]
+    force: Option<bool>,
+    
+    #[schemars(description = "Delete a directory and everything in it (for delete operation)")]
+    recursive: Option<bool>,
+    
+    #[schemars(description = "The first line to read, counting from 1 (for read operation)")]
+    offset: Option<usize>,
+    
+    #[schemars(description = "How many lines to read from 'offset' (for read operation)")]
+    limit: Option<usize>,
+    
+    #[schemars(description = "How to read the file: 'overview' for its size, first and last lines and the line ranges of chunks to read one at a time, 'range' for the lines from 'offset' and 'limit', or 'full' for all of it, refused above 120,000 characters. Without it, a range is read if one is given, a file that fits is returned whole, and a larger one gets an overview (for read operation)")]
+    mode: Option<String>,
 }
 
-pub struct FileTool {
+// How long a directory's files are counted before a delete is refused as too large to count
+const COUNT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The `[tools.delete]` config table: how large a directory may be to be
+/// deleted without `force`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct DeleteConfig {
+    pub max_files: usize,
+    pub max_size_mb: u64,
 }
 
-impl Default for FileTool {
+impl Default for DeleteConfig {
     fn default() -> Self {
-        Self {}
+        Self {
+            max_files: 100,
+            max_size_mb: 50,
+        }
+    }
+}
+
+impl DeleteConfig {
+    fn max_bytes(&self) -> u64 {
+        self.max_size_mb.saturating_mul(1024 * 1024)
+    }
+}
+
+// What a directory holds
+#[derive(Debug, Default, PartialEq, Eq)]
+struct DirSize {
+    files: usize,
+    bytes: u64,
+    // False if counting gave up at the deadline
+    complete: bool,
+}
+
+impl DirSize {
+    // Count the files under `dir` until `deadline`. Symbolic links are
+    // counted as themselves and never followed, so the count stays inside
+    fn measure(dir: &Path, deadline: Instant) -> Self {
+        let mut size = DirSize { complete: true, ..DirSize::default() };
+        let mut pending = vec![dir.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if Instant::now() > deadline {
+                    size.complete = false;
+                    return size;
+                }
+                let Ok(metadata) = entry.path().symlink_metadata() else {
+                    continue;
+                };
+                if metadata.is_dir() {
+                    pending.push(entry.path());
+                } else {
+                    size.files += 1;
+                    size.bytes += metadata.len();
+                }
+            }
+        }
+        size
+    }
+    
+    fn exceeds(&self, limits: &DeleteConfig) -> bool {
+        !self.complete || self.files > limits.max_files || self.bytes > limits.max_bytes()
+    }
+    
+    // e.g. "240 files (12.5 MB)"
+    fn describe(&self) -> String {
+        format!(
+            "{}{} files ({})",
+            if self.complete { "" } else { "at least " },
+            self.files,
+            policy::format_size(self.bytes as usize)
+        )
     }
 }
 
+#[derive(Default)]
+pub struct FileTool {
+    policy: Arc<ExecutionPolicy>,
+    cache: Arc<ReadCache>,
+}
+
 impl FileTool {
     pub fn new() -> Self {
         Self::default()
     }
     
-    // Helper function to ensure paths are absolute
-    fn resolve_path(&self, path_str: &str) -> Result<PathBuf, Box<dyn std::error::Error + Sync + Send>> {
-        let path = Path::new(path_str);
-        
-        // If already absolute, return it
-        if path.is_absolute() {
-            return Ok(path.to_path_buf());
-        }
+    /// Use a shared execution policy, e.g. to enable dry-run mode
+    pub fn with_policy(policy: Arc<ExecutionPolicy>) -> Self {
+        Self { policy, ..Self::default() }
+    }
+    
+    /// Share the record of files already read, e.g. with the find_file tool
+    pub fn with_cache(mut self, cache: Arc<ReadCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+    
+    // What a mutating operation would do, or None for read-only operations
+    fn action(operation: &str, parameters: &FileParams) -> Option<String> {
+        let path = parameters.path.as_deref().unwrap_or("<missing path>");
+        let source = parameters.source.as_deref().unwrap_or("<missing source>");
+        let destination = parameters.destination.as_deref().unwrap_or("<missing destination>");
         
-        // Otherwise, make it absolute by prepending the current working directory
-        match env::current_dir() {
-            Ok(current_dir) => {
-                let absolute_path = current_dir.join(path);
-                println!("\x1b[1;33m[FILE TOOL] Converting relative path '{}' to absolute path '{}'\x1b[0m", 
-                    path_str, absolute_path.display());
-                Ok(absolute_path)
+        let action = match operation {
+            "write" => {
+                let size = policy::format_size(parameters.content.as_deref().unwrap_or("").len());
+                if parameters.append.unwrap_or(false) {
+                    format!("append {} to {}", size, path)
+                } else {
+                    format!("write {} to {}", size, path)
+                }
             },
-            Err(e) => Err(format!("Failed to get current directory: {}", e).into()),
+            "delete" if parameters.recursive.unwrap_or(false) => format!("delete {} and everything in it", path),
+            "delete" => format!("delete {}", path),
+            "move" => format!("move {} to {}", source, destination),
+            "copy" => format!("copy {} to {}", source, destination),
+            _ => return None,
+        };
+        
+        Some(action)
+    }
+    
+    // Describe what a mutating operation would do, or None for read-only operations
+    fn preview(operation: &str, parameters: &FileParams) -> Option<String> {
+        Self::action(operation, parameters).map(|action| policy::simulated(&action))
+    }
+    
+    // Helper function to ensure paths are absolute, inside the sandbox if there is one
+    fn resolve_path(&self, path_str: &str) -> Result<PathBuf, ToolError> {
+        let path_str = &filename::expand(path_str)?;
+        let absolute_path = self.policy.resolve_path(path_str)?;
+        if Path::new(path_str).is_absolute() {
+            return Ok(absolute_path);
         }
+        
+        Ok(absolute_path)
     }
-
-    fn truncate_output(content: &str) -> String {
-        if content.len() <= MAX_OUTPUT_LENGTH {
-            return content.to_string();
+    
+    // Like resolve_path, for a path about to be written, moved or copied to
+    fn resolve_destination(&self, path_str: &str) -> Result<PathBuf, ToolError> {
+        let path = self.resolve_path(&filename::check_new(path_str)?)?;
+        filename::check_length(&path)?;
+        Ok(path)
+    }
+    
+    // Make sure the parent directory of a path exists
+    fn create_parent(path: &Path) -> Result<(), ToolError> {
+        if let Some(parent) = path.parent() {
+            if !parent.exists() {
+                fs::create_dir_all(parent)
+                    .map_err(|e| ToolError::io(format!("Failed to create directory '{}'", parent.display()), e))?;
+            }
         }
-
-        let half_length = MAX_OUTPUT_LENGTH / 2;
-        let start = &content[..half_length];
-        let end = &content[content.len() - half_length..];
-
-        // Count truncated lines
-        let middle_content = &content[half_length..content.len() - half_length];
-        let truncated_lines_count = middle_content.chars().filter(|&c| c == '\n').count();
-
-        format!(
-            "{}\n\n... [{} lines truncated] ...\n\n{}",
-            start, truncated_lines_count, end
-        )
+        Ok(())
     }
 
-    async fn read_file(&self, path_str: &str) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
+    async fn read_file(&self, path_str: &str, force: bool, range: LineRange, mode: ReadMode) -> ToolResult {
         // Resolve to absolute path
         let path = self.resolve_path(path_str)?;
         
         if !path.exists() {
-            return Err(format!("Error: File '{}' does not exist", path.display()).into());
+            return Err(ToolError::NotFound(format!("File '{}' does not exist", path.display())));
         }
         
         if !path.is_file() {
-            return Err(format!("Error: Path '{}' is not a file", path.display()).into());
+            return Err(ToolError::InvalidParams(format!("Path '{}' is not a file", path.display())));
         }
         
-        match fs::read_to_string(&path) {
-            Ok(content) => Ok(Self::truncate_output(&content)),
-            Err(e) => Err(format!("Error reading file: {}", e).into()),
+        let metadata = fs::metadata(&path)
+            .map_err(|e| ToolError::io("Error reading file", e))?;
+        let freshness = self.cache.check(&path, &metadata);
+        let whole = range.is_whole() && mode != ReadMode::Overview;
+        if let (Freshness::Unchanged { read_at }, false, true) = (freshness, force, whole) {
+            return Ok(read_cache::unchanged(&path, read_at));
         }
+        
+        let content = fs::read_to_string(&path)
+            .map_err(|e| ToolError::io("Error reading file", e))?;
+        
+        // Only a read of every line lets a later read be skipped as unchanged
+        let excerpt = lines::read(&content, range, mode)?;
+        if excerpt.complete {
+            self.cache.record(&path, &metadata);
+        }
+        
+        let output = ToolOutput::new(excerpt.text);
+        Ok(match freshness {
+            Freshness::Changed { read_at } => output.with_warning(read_cache::changed_note(read_at)),
+            _ => output,
+        })
     }
     
-    async fn write_file(&self, path_str: &str, content: &str, append: bool) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
+    async fn write_file(&self, path_str: &str, content: &str, append: bool) -> ToolResult {
         // Resolve to absolute path
-        let path = self.resolve_path(path_str)?;
-        
-        // Make sure the parent directory exists
-        if let Some(parent) = path.parent() {
-            if !parent.exists() {
-                fs::create_dir_all(parent)?;
+        let path = self.resolve_destination(path_str)?;
+        self.policy.check_protected(&path)?;
+        
+        // Let the user review the change first if they asked to
+        if self.policy.reviews_changes() {
+            let old = path.exists().then(|| fs::read_to_string(&path).unwrap_or_default());
+            let new = match (&old, append) {
+                (Some(old), true) => format!("{}{}", old, content),
+                _ => content.to_string(),
+            };
+            let change = ProposedChange::new("file", path.clone(), old.as_deref(), &new);
+            if !change.is_empty() {
+                if let Decision::Reject { reason } = self.policy.review(&change) {
+                    return Ok(ToolOutput::new(review::declined(&change, reason.as_deref())));
+                }
             }
         }
         
+        // Keep what the file held, so the write can be undone
+        let before = journal::backup(&path);
+        
+        // Make sure the parent directory exists
+        Self::create_parent(&path)?;
+        
+        let write_error = |e| ToolError::io(format!("Error writing file '{}'", path.display()), e);
+        
         let mut file = if append {
-            TokioFile::options().append(true).create(true).open(&path).await?
+            TokioFile::options().append(true).create(true).open(&path).await.map_err(write_error)?
         } else {
-            TokioFile::create(&path).await?
+            TokioFile::create(&path).await.map_err(write_error)?
         };
         
-        file.write_all(content.as_bytes()).await?;
-        file.flush().await?; // Ensure content is written to disk
+        file.write_all(content.as_bytes()).await.map_err(write_error)?;
+        file.flush().await.map_err(write_error)?; // Ensure content is written to disk
+        self.policy.journal().record_write(&path, before);
         
-        Ok(format!("Successfully {} file: {}", 
+        Ok(ToolOutput::new(format!("Successfully {} file: {}", 
             if append { "appended to" } else { "wrote" }, 
             path.display()
-        ))
+        )))
     }
     
-    async fn file_exists(&self, path_str: &str) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
+    /// Whether a file or directory exists at the path
+    pub fn file_exists(&self, path_str: &str) -> Result<bool, ToolError> {
         // Resolve to absolute path
         let path = self.resolve_path(path_str)?;
-        let exists = path.exists();
-        
-        Ok(format!("Path '{}' {} exist", 
-            path.display(),
-            if exists { "does" } else { "does not" }
-        ))
+        Ok(path.exists())
     }
     
-    async fn delete_file(&self, path_str: &str) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
+    async fn delete_file(&self, path_str: &str, recursive: bool, force: bool) -> ToolResult {
         // Resolve to absolute path
         let path = self.resolve_path(path_str)?;
+        self.policy.check_protected(&path)?;
         
-        if !path.exists() {
-            return Err(format!("Error: Path '{}' does not exist", path.display()).into());
-        }
+        // A symbolic link is deleted itself, never what it points to
+        let Ok(metadata) = path.symlink_metadata() else {
+            return Err(ToolError::NotFound(format!("Path '{}' does not exist", path.display())));
+        };
         
-        if path.is_file() {
-            fs::remove_file(&path)?;
-            Ok(format!("Successfully deleted file: {}", path.display()))
-        } else if path.is_dir() {
-            fs::remove_dir_all(&path)?;
-            Ok(format!("Successfully deleted directory: {}", path.display()))
+        let delete_error = |e| ToolError::io(format!("Error deleting '{}'", path.display()), e);
+        
+        if metadata.is_file() || metadata.is_symlink() {
+            let before = journal::backup(&path);
+            fs::remove_file(&path).map_err(delete_error)?;
+            self.policy.journal().record_delete(&path, before);
+            Ok(ToolOutput::new(format!("Successfully deleted file: {}", path.display())))
+        } else if metadata.is_dir() {
+            if !recursive {
+                return Err(ToolError::InvalidParams(format!(
+                    "'{}' is a directory; set recursive to true to delete it and everything in it",
+                    path.display()
+                )));
+            }
+            
+            // Large trees need force, and the user's say if anyone reviews changes
+            let limits = self.policy.delete_limits();
+            let size = DirSize::measure(&path, Instant::now() + COUNT_TIMEOUT);
+            if size.exceeds(&limits) {
+                let reason = format!(
+                    "'{}' holds {}, over the limit of {} files ({})",
+                    path.display(),
+                    size.describe(),
+                    limits.max_files,
+                    policy::format_size(limits.max_bytes() as usize)
+                );
+                if !force {
+                    return Err(ToolError::Unsafe(format!(
+                        "Not deleted: {}. Tell the user, and only retry with force set to true if they want it gone",
+                        reason
+                    )));
+                }
+                self.policy.confirm_delete(&format!("delete {}", path.display()), &reason)?;
+            }
+            
+            // remove_dir_all removes symbolic links without following them
+            fs::remove_dir_all(&path).map_err(delete_error)?;
+            Ok(ToolOutput::new(format!("Successfully deleted directory: {} ({})", path.display(), size.describe())))
         } else {
-            Err(format!("Error: Path '{}' is neither a file nor a directory", path.display()).into())
+            Err(ToolError::InvalidParams(format!("Path '{}' is neither a file nor a directory", path.display())))
         }
     }
     
-    async fn move_file(&self, source_str: &str, destination_str: &str) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
+    async fn move_file(&self, source_str: &str, destination_str: &str) -> ToolResult {
         // Resolve to absolute paths
         let source_path = self.resolve_path(source_str)?;
-        let dest_path = self.resolve_path(destination_str)?;
+        let dest_path = self.resolve_destination(destination_str)?;
+        self.policy.check_protected(&source_path)?;
+        self.policy.check_protected(&dest_path)?;
         
         if !source_path.exists() {
-            return Err(format!("Error: Source path '{}' does not exist", source_path.display()).into());
+            return Err(ToolError::NotFound(format!("Source path '{}' does not exist", source_path.display())));
         }
         
         // Make sure the parent directory of the destination exists
-        if let Some(parent) = dest_path.parent() {
-            if !parent.exists() {
-                fs::create_dir_all(parent)?;
-            }
+        Self::create_parent(&dest_path)?;
+        
+        // Moving over an existing file loses it, so only moves to a new path can be undone
+        let replaces = dest_path.exists();
+        fs::rename(&source_path, &dest_path)
+            .map_err(|e| ToolError::io(format!("Error moving '{}'", source_path.display()), e))?;
+        if !replaces {
+            self.policy.journal().record(Operation::Move {
+                source: source_path.clone(),
+                destination: dest_path.clone(),
+            });
         }
         
-        fs::rename(&source_path, &dest_path)?;
-        
-        Ok(format!("Successfully moved from '{}' to '{}'", 
+        Ok(ToolOutput::new(format!("Successfully moved from '{}' to '{}'", 
             source_path.display(), 
             dest_path.display()
-        ))
+        )))
     }
     
-    async fn copy_file(&self, source_str: &str, destination_str: &str) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
+    async fn copy_file(&self, source_str: &str, destination_str: &str) -> ToolResult {
         // Resolve to absolute paths
         let source_path = self.resolve_path(source_str)?;
-        let dest_path = self.resolve_path(destination_str)?;
+        let dest_path = self.resolve_destination(destination_str)?;
+        self.policy.check_protected(&dest_path)?;
         
         if !source_path.exists() {
-            return Err(format!("Error: Source path '{}' does not exist", source_path.display()).into());
+            return Err(ToolError::NotFound(format!("Source path '{}' does not exist", source_path.display())));
         }
         
         // Make sure the parent directory of the destination exists
-        if let Some(parent) = dest_path.parent() {
-            if !parent.exists() {
-                fs::create_dir_all(parent)?;
-            }
-        }
+        Self::create_parent(&dest_path)?;
+        
+        let copy_error = |e| ToolError::io(format!("Error copying '{}'", source_path.display()), e);
+        let before = journal::backup(&dest_path);
         
         if source_path.is_file() {
-            fs::copy(&source_path, &dest_path)?;
-            Ok(format!("Successfully copied file from '{}' to '{}'", 
+            fs::copy(&source_path, &dest_path).map_err(copy_error)?;
+            self.policy.journal().record_copy(&dest_path, before);
+            Ok(ToolOutput::new(format!("Successfully copied file from '{}' to '{}'", 
                 source_path.display(), 
                 dest_path.display()
-            ))
+            )))
         } else if source_path.is_dir() {
-            copy_dir_all(&source_path, &dest_path)?;
-            Ok(format!("Successfully copied directory from '{}' to '{}'", 
+            copy_dir_all(&source_path, &dest_path).map_err(copy_error)?;
+            self.policy.journal().record_copy(&dest_path, before);
+            Ok(ToolOutput::new(format!("Successfully copied directory from '{}' to '{}'", 
                 source_path.display(), 
                 dest_path.display()
-            ))
+            )))
         } else {
-            Err(format!("Error: Source path '{}' is neither a file nor a directory", source_path.display()).into())
+            Err(ToolError::InvalidParams(format!("Source path '{}' is neither a file nor a directory", source_path.display())))
+        }
+    }
+    
+    /// Perform a file operation, returning its output or why it failed
+    pub async fn run(&self, parameters: FileParams) -> ToolResult {
+        // Start timing the execution
+        let start_time = Instant::now();
+        
+        // Get operation type
+        let operation = parameters.operation.as_deref().unwrap_or("").to_lowercase();
+        
+        // A read-only session refuses mutating operations before anything else
+        if let Some(action) = Self::action(&operation, &parameters) {
+            self.policy.check_writable(&format!("file: {}", action))?;
+        }
+        
+        // In dry-run mode, only describe mutating operations
+        if self.policy.dry_run() {
+            if let Some(preview) = Self::preview(&operation, &parameters) {
+                return Ok(ToolOutput::new(preview).timed(start_time));
+            }
         }
+        
+        // Mutating operations wait for the user after suspicious web content
+        if let Some(action) = Self::action(&operation, &parameters) {
+            self.policy.check_untrusted(&format!("file: {}", action))?;
+        }
+        
+        let invalid = |message: &str| Err(ToolError::InvalidParams(message.to_string()));
+        
+        // Process the request based on the operation
+        let result = match operation.as_str() {
+            "read" => {
+                if let Some(path) = parameters.path.as_ref() {
+                    let range = LineRange::new(parameters.offset, parameters.limit);
+                    let mode = ReadMode::parse(parameters.mode.as_deref())?;
+                    self.read_file(path, parameters.force.unwrap_or(false), range, mode).await
+                } else {
+                    invalid("Path is required for 'read' operation. Example: { operation: 'read', path: '/full/path/to/file.txt' }")
+                }
+            },
+            "write" => {
+                match (parameters.path.as_ref(), parameters.content.as_ref()) {
+                    (Some(path), Some(content)) => {
+                        self.write_file(path, content, parameters.append.unwrap_or(false)).await
+                    },
+                    (None, Some(_)) => invalid("Missing 'path' parameter. Example: { operation: 'write', path: '/full/path/to/file.txt', content: 'file content' }"),
+                    (Some(_), None) => invalid("Missing 'content' parameter. Example: { operation: 'write', path: '/full/path/to/file.txt', content: 'file content' }"),
+                    _ => invalid("Both 'path' and 'content' are required for 'write' operation. Example: { operation: 'write', path: '/full/path/to/file.txt', content: 'file content' }")
+                }
+            },
+            "exists" => {
+                if let Some(path) = parameters.path.as_ref() {
+                    let exists = self.file_exists(path)?;
+                    let path = self.resolve_path(path)?;
+                    Ok(ToolOutput::new(format!("Path '{}' {} exist", 
+                        path.display(),
+                        if exists { "does" } else { "does not" }
+                    )))
+                } else {
+                    invalid("Path is required for 'exists' operation. Example: { operation: 'exists', path: '/full/path/to/file.txt' }")
+                }
+            },
+            "delete" => {
+                if let Some(path) = parameters.path.as_ref() {
+                    self.delete_file(path, parameters.recursive.unwrap_or(false), parameters.force.unwrap_or(false)).await
+                } else {
+                    invalid("Path is required for 'delete' operation. Example: { operation: 'delete', path: '/full/path/to/file.txt' }")
+                }
+            },
+            "move" => {
+                match (parameters.source.as_ref(), parameters.destination.as_ref()) {
+                    (Some(source), Some(destination)) => {
+                        self.move_file(source, destination).await
+                    },
+                    (None, Some(_)) => invalid("Missing 'source' parameter. Example: { operation: 'move', source: '/path/to/source.txt', destination: '/path/to/dest.txt' }"),
+                    (Some(_), None) => invalid("Missing 'destination' parameter. Example: { operation: 'move', source: '/path/to/source.txt', destination: '/path/to/dest.txt' }"),
+                    _ => invalid("Both 'source' and 'destination' are required for 'move' operation. Example: { operation: 'move', source: '/path/to/source.txt', destination: '/path/to/dest.txt' }")
+                }
+            },
+            "copy" => {
+                match (parameters.source.as_ref(), parameters.destination.as_ref()) {
+                    (Some(source), Some(destination)) => {
+                        self.copy_file(source, destination).await
+                    },
+                    (None, Some(_)) => invalid("Missing 'source' parameter. Example: { operation: 'copy', source: '/path/to/source.txt', destination: '/path/to/dest.txt' }"),
+                    (Some(_), None) => invalid("Missing 'destination' parameter. Example: { operation: 'copy', source: '/path/to/source.txt', destination: '/path/to/dest.txt' }"),
+                    _ => invalid("Both 'source' and 'destination' are required for 'copy' operation. Example: { operation: 'copy', source: '/path/to/source.txt', destination: '/path/to/dest.txt' }")
+                }
+            },
+            "" => invalid("'operation' parameter is required. Valid operations are: 'read', 'write', 'exists', 'delete', 'move', 'copy'"),
+            _ => Err(ToolError::InvalidParams(format!("Unknown operation: '{}'. Valid operations are: 'read', 'write', 'exists', 'delete', 'move', 'copy'", operation)))
+        };
+        
+        result.map(|output| output.timed(start_time))
     }
 }
 
@@ -260,22 +555,28 @@ SUPPORTED OPERATIONS (must use exactly these keywords):
 - 'read' - Read content from a file
 - 'write' - Write content to a file (creates a new file or overwrites existing one)
 - 'exists' - Check if a file or directory exists
-- 'delete' - Delete a file or directory
+- 'delete' - Delete a file, or a directory with 'recursive' set to true
 - 'move' - Move/rename a file or directory
 - 'copy' - Copy a file or directory
 
 HOW TO USE:
 1. Set the 'operation' parameter to one of the values above (e.g., 'write' not 'create')
 2. Provide the required parameters for the chosen operation:
-   - For read: 'path' to the file
+   - For read: 'path' to the file (set 'force' to true to get the content of a file that is unchanged since you last read it,
+     'offset' and 'limit' to read only some of its lines, and 'mode' to 'overview', 'range' or 'full' to choose how it is read)
    - For write: 'path' to the file and 'content' to write (with optional 'append' flag set to true/false)
    - For exists: 'path' to check
-   - For delete: 'path' to the file to delete
+   - For delete: 'path' to the file to delete; a directory also needs 'recursive' set to true, and one holding
+     more than 100 files or 50 MB (by default) is refused with its counts unless 'force' is set to true, which only the user should decide
    - For move: 'source' and 'destination' paths
    - For copy: 'source' and 'destination' paths
 
 EXAMPLES:
 - To create a new file: use operation='write' with path and content parameters
+- To read lines 200-320 of a file: use operation='read' with path, offset=200 and limit=121
+- To work through a huge file such as a long log: read it without a range to get an overview listing its chunks
+  (e.g. '1-812, 813-1650, ...'), then read the chunks you need one per call with offset and limit, e.g. offset=813
+  and limit=838; the chunks are the same every time the file is read, so they can be read in any order
 - To check if a file exists: use operation='exists' with path parameter
 - To rename a file: use operation='move' with source and destination parameters
 
@@ -283,10 +584,13 @@ FEATURES:
 - Supports multiple file operations
 - Can handle both files and directories
 - Creates parent directories if they don't exist when writing or copying files
-- Handles large files by truncating output when necessary
+- Reads of a line range, and of files too large to return whole, give numbered lines ('42:text')
+  under a header with the file's total line count
+- Re-reading a file that hasn't changed returns a short notice instead of the content
 
 LIMITATIONS:
-- Output is truncated if it exceeds 30,000 characters
+- At most 30,000 characters are returned; a larger file returns an overview, so read the rest by chunk
+- mode='full' returns a file of up to 120,000 characters whole, and refuses a larger one
 - For security reasons, restricted to standard file operations
 - Cannot access system-protected files or directories
 
@@ -300,105 +604,20 @@ TIPS:
         &mut self,
         parameters: Self::Params,
     ) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
-        // Start timing the execution
         let start_time = Instant::now();
+        let verbose = self.policy.verbose_output();
         
-        // Get operation type
-        let operation = parameters.operation.as_deref().unwrap_or("").to_lowercase();
-        
-        // Print colorful message indicating tool is being called
-        println!("\x1b[1;32m[FILE TOOL] Being called with operation: {}\x1b[0m", operation);
-        
-        // Log all parameters for debugging
-        let content_str = if let Some(content) = &parameters.content {
-            if content.len() > 30 {
-                format!("[{} chars]", content.len())
+        // Flatten the result into text for the model, with the execution time if asked for
+        match self.run(parameters).await {
+            Ok(output) if output.content.is_empty() => Ok(if verbose {
+                format!("File operation completed in {}ms (no output)", output.duration_ms)
             } else {
-                format!("{:?}", content)
-            }
-        } else {
-            "None".to_string()
-        };
-        
-        println!("\x1b[1;34m[FILE TOOL DEBUG] Parameters received: operation={:?}, path={:?}, content={}, append={:?}, source={:?}, destination={:?}\x1b[0m", 
-            parameters.operation, 
-            parameters.path,
-            content_str,
-            parameters.append,
-            parameters.source,
-            parameters.destination
-        );
-            
-        // Process the request based on the operation
-        let result = match operation.as_str() {
-            "read" => {
-                if let Some(path) = parameters.path.as_ref() {
-                    self.read_file(path).await
-                } else {
-                    Err(format!("ERROR: Path is required for 'read' operation. Example: {{ operation: 'read', path: '/full/path/to/file.txt' }}").into())
-                }
-            },
-            "write" => {
-                match (parameters.path.as_ref(), parameters.content.as_ref()) {
-                    (Some(path), Some(content)) => {
-                        self.write_file(path, content, parameters.append.unwrap_or(false)).await
-                    },
-                    (None, Some(_)) => Err(format!("ERROR: Missing 'path' parameter. Example: {{ operation: 'write', path: '/full/path/to/file.txt', content: 'file content' }}").into()),
-                    (Some(_), None) => Err(format!("ERROR: Missing 'content' parameter. Example: {{ operation: 'write', path: '/full/path/to/file.txt', content: 'file content' }}").into()),
-                    _ => Err(format!("ERROR: Both 'path' and 'content' are required for 'write' operation. Example: {{ operation: 'write', path: '/full/path/to/file.txt', content: 'file content' }}").into())
-                }
-            },
-            "exists" => {
-                if let Some(path) = parameters.path.as_ref() {
-                    self.file_exists(path).await
-                } else {
-                    Err(format!("ERROR: Path is required for 'exists' operation. Example: {{ operation: 'exists', path: '/full/path/to/file.txt' }}").into())
-                }
-            },
-            "delete" => {
-                if let Some(path) = parameters.path.as_ref() {
-                    self.delete_file(path).await
-                } else {
-                    Err(format!("ERROR: Path is required for 'delete' operation. Example: {{ operation: 'delete', path: '/full/path/to/file.txt' }}").into())
-                }
-            },
-            "move" => {
-                match (parameters.source.as_ref(), parameters.destination.as_ref()) {
-                    (Some(source), Some(destination)) => {
-                        self.move_file(source, destination).await
-                    },
-                    (None, Some(_)) => Err(format!("ERROR: Missing 'source' parameter. Example: {{ operation: 'move', source: '/path/to/source.txt', destination: '/path/to/dest.txt' }}").into()),
-                    (Some(_), None) => Err(format!("ERROR: Missing 'destination' parameter. Example: {{ operation: 'move', source: '/path/to/source.txt', destination: '/path/to/dest.txt' }}").into()),
-                    _ => Err(format!("ERROR: Both 'source' and 'destination' are required for 'move' operation. Example: {{ operation: 'move', source: '/path/to/source.txt', destination: '/path/to/dest.txt' }}").into())
-                }
-            },
-            "copy" => {
-                match (parameters.source.as_ref(), parameters.destination.as_ref()) {
-                    (Some(source), Some(destination)) => {
-                        self.copy_file(source, destination).await
-                    },
-                    (None, Some(_)) => Err(format!("ERROR: Missing 'source' parameter. Example: {{ operation: 'copy', source: '/path/to/source.txt', destination: '/path/to/dest.txt' }}").into()),
-                    (Some(_), None) => Err(format!("ERROR: Missing 'destination' parameter. Example: {{ operation: 'copy', source: '/path/to/source.txt', destination: '/path/to/dest.txt' }}").into()),
-                    _ => Err(format!("ERROR: Both 'source' and 'destination' are required for 'copy' operation. Example: {{ operation: 'copy', source: '/path/to/source.txt', destination: '/path/to/dest.txt' }}").into())
-                }
-            },
-            "" => Err("ERROR: 'operation' parameter is required. Valid operations are: 'read', 'write', 'exists', 'delete', 'move', 'copy'".into()),
-            _ => Err(format!("ERROR: Unknown operation: '{}'. Valid operations are: 'read', 'write', 'exists', 'delete', 'move', 'copy'", operation).into())
-        };
-        
-        // Calculate execution time
-        let execution_time = start_time.elapsed().as_millis();
-        
-        // Return result with execution time
-        match result {
-            Ok(output) => {
-                if output.is_empty() {
-                    Ok(format!("File operation completed in {}ms (no output)", execution_time))
-                } else {
-                    Ok(format!("{}\n\nOperation completed in {}ms", output, execution_time))
-                }
-            },
-            Err(e) => Ok(format!("Error: {}\n\nOperation failed after {}ms", e, execution_time)),
+                "File operation completed (no output)".to_string()
+            }),
+            Ok(output) if verbose => Ok(format!("{}\n\nOperation completed in {}ms", output.with_notes(), output.duration_ms)),
+            Ok(output) => Ok(output.with_notes()),
+            Err(e) if verbose => Ok(format!("Error: {}\n\nOperation failed after {}ms", e, start_time.elapsed().as_millis())),
+            Err(e) => Ok(format!("Error: {}", e)),
         }
     }
 }
@@ -408,12 +627,22 @@ pub struct File {
     file_tool: FileTool,
 }
 
+impl Default for File {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl File {
     pub fn new() -> Self {
         Self { file_tool: FileTool::new() }
     }
     
-    pub async fn read(&mut self, path: &str) -> Result<String> {
+    pub fn with_policy(policy: Arc<ExecutionPolicy>) -> Self {
+        Self { file_tool: FileTool::with_policy(policy) }
+    }
+    
+    pub async fn read(&mut self, path: &str) -> Result<String, ToolError> {
         let params = FileParams {
             operation: Some("read".to_string()),
             path: Some(path.to_string()),
@@ -421,22 +650,17 @@ impl File {
             append: None,
             source: None,
             destination: None,
+            force: None,
+            recursive: None,
+            offset: None,
+            limit: None,
+            mode: None,
         };
         
-        match self.file_tool.call(params).await {
-            Ok(output) => {
-                // Extract the actual file content before the "Operation completed" message
-                if let Some(idx) = output.find("\n\nOperation completed") {
-                    Ok(output[..idx].to_string())
-                } else {
-                    Ok(output)
-                }
-            },
-            Err(e) => Err(anyhow::anyhow!("Failed to read file: {}", e)),
-        }
+        self.file_tool.run(params).await.map(|output| output.content)
     }
     
-    pub async fn write(&mut self, path: &str, content: &str, append: bool) -> Result<String> {
+    pub async fn write(&mut self, path: &str, content: &str, append: bool) -> ToolResult {
         let params = FileParams {
             operation: Some("write".to_string()),
             path: Some(path.to_string()),
@@ -444,31 +668,41 @@ impl File {
             append: Some(append),
             source: None,
             destination: None,
+            force: None,
+            recursive: None,
+            offset: None,
+            limit: None,
+            mode: None,
         };
         
-        match self.file_tool.call(params).await {
-            Ok(output) => Ok(output),
-            Err(e) => Err(anyhow::anyhow!("Failed to write file: {}", e)),
-        }
+        self.file_tool.run(params).await
+    }
+    
+    pub async fn exists(&mut self, path: &str) -> Result<bool, ToolError> {
+        self.file_tool.file_exists(path)
     }
     
-    pub async fn exists(&mut self, path: &str) -> Result<bool> {
+    pub async fn delete(&mut self, path: &str) -> ToolResult {
         let params = FileParams {
-            operation: Some("exists".to_string()),
+            operation: Some("delete".to_string()),
             path: Some(path.to_string()),
             content: None,
             append: None,
             source: None,
             destination: None,
+            force: None,
+            recursive: None,
+            offset: None,
+            limit: None,
+            mode: None,
         };
         
-        match self.file_tool.call(params).await {
-            Ok(output) => Ok(output.contains("does exist")),
-            Err(e) => Err(anyhow::anyhow!("Failed to check file existence: {}", e)),
-        }
+        self.file_tool.run(params).await
     }
     
-    pub async fn delete(&mut self, path: &str) -> Result<String> {
+    /// Delete a directory and everything in it, `force` deleting it even
+    /// over the size limits
+    pub async fn delete_recursive(&mut self, path: &str, force: bool) -> ToolResult {
         let params = FileParams {
             operation: Some("delete".to_string()),
             path: Some(path.to_string()),
@@ -476,15 +710,17 @@ impl File {
             append: None,
             source: None,
             destination: None,
+            force: Some(force),
+            recursive: Some(true),
+            offset: None,
+            limit: None,
+            mode: None,
         };
         
-        match self.file_tool.call(params).await {
-            Ok(output) => Ok(output),
-            Err(e) => Err(anyhow::anyhow!("Failed to delete file: {}", e)),
-        }
+        self.file_tool.run(params).await
     }
     
-    pub async fn r#move(&mut self, source: &str, destination: &str) -> Result<String> {
+    pub async fn r#move(&mut self, source: &str, destination: &str) -> ToolResult {
         let params = FileParams {
             operation: Some("move".to_string()),
             path: None,
@@ -492,15 +728,17 @@ impl File {
             append: None,
             source: Some(source.to_string()),
             destination: Some(destination.to_string()),
+            force: None,
+            recursive: None,
+            offset: None,
+            limit: None,
+            mode: None,
         };
         
-        match self.file_tool.call(params).await {
-            Ok(output) => Ok(output),
-            Err(e) => Err(anyhow::anyhow!("Failed to move file: {}", e)),
-        }
+        self.file_tool.run(params).await
     }
     
-    pub async fn copy(&mut self, source: &str, destination: &str) -> Result<String> {
+    pub async fn copy(&mut self, source: &str, destination: &str) -> ToolResult {
         let params = FileParams {
             operation: Some("copy".to_string()),
             path: None,
@@ -508,12 +746,14 @@ impl File {
             append: None,
             source: Some(source.to_string()),
             destination: Some(destination.to_string()),
+            force: None,
+            recursive: None,
+            offset: None,
+            limit: None,
+            mode: None,
         };
         
-        match self.file_tool.call(params).await {
-            Ok(output) => Ok(output),
-            Err(e) => Err(anyhow::anyhow!("Failed to copy file: {}", e)),
-        }
+        self.file_tool.run(params).await
     }
 }
 
@@ -521,6 +761,7 @@ impl File {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tools::find_file_tool::{FindAndReadFileParams, FindAndReadFileTool};
     use tempfile::tempdir;
     
     #[tokio::test]
@@ -535,7 +776,7 @@ mod tests {
         // Test writing to a file
         let content = "Hello, world!";
         let write_result = file_tool.write(&file_path, content, false).await?;
-        assert!(write_result.contains("Successfully wrote file"));
+        assert!(write_result.content.contains("Successfully wrote file"));
         
         // Test reading from the file
         let read_result = file_tool.read(&file_path).await?;
@@ -543,7 +784,7 @@ mod tests {
         
         // Test appending to the file
         let append_result = file_tool.write(&file_path, "\nMore content", true).await?;
-        assert!(append_result.contains("Successfully appended to file"));
+        assert!(append_result.content.contains("Successfully appended to file"));
         
         // Read the file again to confirm appending worked
         let read_result = file_tool.read(&file_path).await?;
@@ -555,6 +796,52 @@ mod tests {
         Ok(())
     }
     
+    // Approves or rejects every change, remembering what it was shown
+    struct Reviewer {
+        decision: Decision,
+        seen: std::sync::Mutex<Vec<ProposedChange>>,
+    }
+    
+    impl review::Reviewer for Reviewer {
+        fn review(&self, change: &ProposedChange) -> Decision {
+            self.seen.lock().unwrap().push(change.clone());
+            self.decision.clone()
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_writes_are_reviewed() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("greeting.txt");
+        fs::write(&path, "hello\n")?;
+        let path_str = path.to_string_lossy().to_string();
+        
+        let reject = Arc::new(Reviewer {
+            decision: Decision::Reject { reason: Some("keep it short".to_string()) },
+            seen: Default::default(),
+        });
+        let policy = Arc::new(ExecutionPolicy::new());
+        policy.set_reviewer(reject.clone());
+        let mut file_tool = File::with_policy(Arc::clone(&policy));
+        
+        let output = file_tool.write(&path_str, "hello, world\n", false).await?;
+        assert!(output.content.contains("The user declined the change"));
+        assert!(output.content.contains("Reason: keep it short"));
+        assert_eq!(fs::read_to_string(&path)?, "hello\n");
+        
+        let seen = reject.seen.lock().unwrap().clone();
+        assert_eq!(seen.len(), 1);
+        assert!(seen[0].unified_diff().contains("-hello\n+hello, world\n"));
+        
+        // Appending is reviewed as the whole file after the append
+        let approve = Arc::new(Reviewer { decision: Decision::Approve, seen: Default::default() });
+        policy.set_reviewer(approve.clone());
+        file_tool.write(&path_str, "bye\n", true).await?;
+        assert_eq!(fs::read_to_string(&path)?, "hello\nbye\n");
+        assert_eq!(approve.seen.lock().unwrap()[0].added(), 1);
+        Ok(())
+    }
+    
     #[tokio::test]
     async fn test_file_exists() -> anyhow::Result<()> {
         let mut file_tool = File::new();
@@ -603,7 +890,7 @@ mod tests {
         
         // Delete the file
         let delete_result = file_tool.delete(&file_path).await?;
-        assert!(delete_result.contains("Successfully deleted file"));
+        assert!(delete_result.content.contains("Successfully deleted file"));
         
         // Confirm file no longer exists
         let exists = file_tool.exists(&file_path).await?;
@@ -632,7 +919,7 @@ mod tests {
         
         // Move the file
         let move_result = file_tool.r#move(&source_path, &dest_path).await?;
-        assert!(move_result.contains("Successfully moved"));
+        assert!(move_result.content.contains("Successfully moved"));
         
         // Confirm source no longer exists
         let source_exists = file_tool.exists(&source_path).await?;
@@ -669,7 +956,7 @@ mod tests {
         
         // Copy the file
         let copy_result = file_tool.copy(&source_path, &dest_path).await?;
-        assert!(copy_result.contains("Successfully copied file"));
+        assert!(copy_result.content.contains("Successfully copied file"));
         
         // Confirm source still exists
         let source_exists = file_tool.exists(&source_path).await?;
@@ -716,7 +1003,7 @@ mod tests {
         // Copy the directory
         let copy_result = file_tool.copy(&source_dir_str, &dest_dir_str).await?;
         
-        assert!(copy_result.contains("Successfully copied directory"));
+        assert!(copy_result.content.contains("Successfully copied directory"));
         
         // Confirm the file was copied in the destination directory
         let dest_file = dest_dir.join("test.txt").to_str()
@@ -735,16 +1022,348 @@ mod tests {
     }
     
     #[tokio::test]
-    async fn test_truncate_output() {
-        // Generate a string longer than MAX_OUTPUT_LENGTH
-        let long_string = "A".repeat(MAX_OUTPUT_LENGTH + 10000);
+    async fn test_directory_deletes_are_guarded() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let tree = dir.path().join("src");
+        fs::create_dir_all(tree.join("nested"))?;
+        for name in ["a.rs", "b.rs", "nested/c.rs"] {
+            fs::write(tree.join(name), "fn main() {}")?;
+        }
+        // A link out of the tree is neither counted nor followed
+        let outside = tempdir()?;
+        fs::write(outside.path().join("keep.txt"), "x".repeat(4096))?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(outside.path(), tree.join("link"))?;
+        let tree_path = tree.to_string_lossy().to_string();
+        
+        let mut config = crate::config::Config::default();
+        config.tools.delete.max_files = 2;
+        let policy = Arc::new(ExecutionPolicy::new());
+        policy.configure(&config);
+        let mut file_tool = File::with_policy(policy);
+        
+        match file_tool.delete(&tree_path).await {
+            Err(ToolError::InvalidParams(message)) => assert!(message.ends_with("is a directory; set recursive to true to delete it and everything in it")),
+            other => panic!("expected a refusal, got {:?}", other),
+        }
+        
+        let files = if cfg!(unix) { 4 } else { 3 };
+        match file_tool.delete_recursive(&tree_path, false).await {
+            Err(ToolError::Unsafe(message)) => {
+                assert!(message.starts_with(&format!("Not deleted: '{}' holds {} files (", tree_path, files)), "{}", message);
+                assert!(message.contains("over the limit of 2 files (50.0 MB)"));
+            }
+            other => panic!("expected a refusal, got {:?}", other),
+        }
+        assert!(tree.join("nested/c.rs").exists());
+        
+        let result = file_tool.delete_recursive(&tree_path, true).await?;
+        assert!(result.content.contains("Successfully deleted directory"));
+        assert!(!tree.exists());
+        assert!(outside.path().join("keep.txt").exists());
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_dry_run_leaves_filesystem_untouched() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let existing = dir.path().join("existing.txt");
+        fs::write(&existing, "original")?;
+        let existing = existing.to_string_lossy().to_string();
+        let new_file = dir.path().join("new.txt").to_string_lossy().to_string();
+        let moved = dir.path().join("moved.txt").to_string_lossy().to_string();
+        
+        let mut file_tool = File::with_policy(ExecutionPolicy::shared(true));
+        
+        let result = file_tool.write(&new_file, &"x".repeat(2150), false).await?;
+        assert!(result.content.contains("[DRY RUN] Would write 2.1 KB to"));
+        
+        let result = file_tool.write(&existing, "changed", false).await?;
+        assert!(result.content.contains("[DRY RUN]"));
+        
+        let result = file_tool.delete(&existing).await?;
+        assert!(result.content.contains("Would delete"));
+        
+        let result = file_tool.r#move(&existing, &moved).await?;
+        assert!(result.content.contains("Would move"));
+        
+        // Reads still execute so the agent can gather context
+        assert_eq!(file_tool.read(&existing).await?, "original");
+        
+        assert!(!Path::new(&new_file).exists());
+        assert!(!Path::new(&moved).exists());
+        assert_eq!(fs::read_to_string(&existing)?, "original");
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_read_only_session_refuses_every_change() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let existing = dir.path().join("existing.txt");
+        fs::write(&existing, "original")?;
+        fs::create_dir_all(dir.path().join("tree/inner"))?;
+        let existing = existing.to_string_lossy().to_string();
+        let tree = dir.path().join("tree").to_string_lossy().to_string();
+        let other = dir.path().join("other.txt").to_string_lossy().to_string();
+        
+        let policy = Arc::new(ExecutionPolicy::new());
+        policy.set_read_only();
+        // Dry-run mode doesn't turn a refusal into a preview
+        policy.set_dry_run(true);
+        let mut file_tool = File::with_policy(policy);
+        
+        let refusals = [
+            file_tool.write(&other, "new", false).await,
+            file_tool.write(&existing, "more", true).await,
+            file_tool.delete(&existing).await,
+            file_tool.delete_recursive(&tree, true).await,
+            file_tool.r#move(&existing, &other).await,
+            file_tool.copy(&existing, &other).await,
+        ];
+        for refusal in refusals {
+            let error = refusal.unwrap_err();
+            assert!(matches!(error, ToolError::Unsafe(_)));
+            assert!(error.to_string().contains("this is a read-only session"));
+        }
+        
+        // Reads still work, and nothing changed
+        assert_eq!(file_tool.read(&existing).await?, "original");
+        assert!(file_tool.exists(&tree).await?);
+        assert!(dir.path().join("tree/inner").is_dir());
+        assert!(!Path::new(&other).exists());
+        assert_eq!(fs::read_to_string(&existing)?, "original");
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_sandbox_root() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let policy = Arc::new(ExecutionPolicy::new());
+        policy.set_sandbox_root(dir.path().to_path_buf());
+        let mut file_tool = File::with_policy(policy);
+        
+        // Relative paths are inside the sandbox root
+        file_tool.write("notes/todo.txt", "milk", false).await?;
+        assert_eq!(fs::read_to_string(dir.path().join("notes/todo.txt"))?, "milk");
+        
+        let outside = dir.path().join("../outside.txt").to_string_lossy().to_string();
+        assert!(matches!(file_tool.write(&outside, "x", false).await, Err(ToolError::Unsafe(_))));
+        assert!(matches!(file_tool.read("../../etc/passwd").await, Err(ToolError::Unsafe(_))));
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_invalid_destinations_are_refused_before_anything_is_created() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let policy = Arc::new(ExecutionPolicy::new());
+        policy.set_sandbox_root(dir.path().to_path_buf());
+        let mut file_tool = File::with_policy(policy);
+        file_tool.write("source.txt", "data", false).await?;
+        
+        let refused = |result: ToolResult| match result {
+            Err(ToolError::InvalidParams(message)) => message,
+            other => panic!("expected invalid params, got {:?}", other),
+        };
+        assert!(refused(file_tool.write("notes\n.txt", "x", false).await).contains("control character"));
+        assert!(refused(file_tool.copy("source.txt", "out/aux.txt").await).contains("reserved by Windows"));
+        assert!(refused(file_tool.r#move("source.txt", &"a".repeat(300)).await).contains("limit of 255"));
+        
+        // Only the file written first is there
+        let entries: Vec<_> = fs::read_dir(dir.path())?.collect();
+        assert_eq!(entries.len(), 1);
+        assert!(dir.path().join("source.txt").exists());
+        
+        // Existing files are read whatever their names, and a leading ~ outside the sandbox is refused
+        assert!(matches!(file_tool.read("~/anything").await, Err(ToolError::Unsafe(_))));
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_protected_paths_are_read_only() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join("migrations"))?;
+        fs::write(dir.path().join("migrations/01.sql"), "create table users")?;
+        let policy = Arc::new(ExecutionPolicy::new());
+        policy.set_sandbox_root(dir.path().to_path_buf());
+        policy.protect("migrations/**");
+        let mut file_tool = File::with_policy(policy);
+        
+        // Reads still succeed
+        assert!(file_tool.read("migrations/01.sql").await?.contains("create table users"));
+        
+        let refused = file_tool.write("migrations/01.sql", "drop table users", false).await;
+        match refused {
+            Err(ToolError::Unsafe(message)) => assert!(message.ends_with(
+                "is write-protected by the rule 'migrations/**'. It can still be read; restart with --force-protected to allow changes"
+            )),
+            other => panic!("expected a refusal, got {:?}", other),
+        }
+        assert!(matches!(file_tool.write("migrations/02.sql", "x", false).await, Err(ToolError::Unsafe(_))));
+        assert!(matches!(file_tool.delete("migrations").await, Err(ToolError::Unsafe(_))));
+        assert!(matches!(file_tool.r#move("migrations/01.sql", "old.sql").await, Err(ToolError::Unsafe(_))));
+        assert_eq!(fs::read_to_string(dir.path().join("migrations/01.sql"))?, "create table users");
+        
+        // Copying out of a protected directory only changes the destination
+        file_tool.copy("migrations/01.sql", "backup.sql").await?;
+        assert!(dir.path().join("backup.sql").exists());
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_typed_errors() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let missing = dir.path().join("missing.txt").to_string_lossy().to_string();
+        let mut file_tool = File::new();
+        
+        assert!(matches!(file_tool.read(&missing).await, Err(ToolError::NotFound(_))));
+        assert!(matches!(file_tool.delete(&missing).await, Err(ToolError::NotFound(_))));
         
-        let truncated = FileTool::truncate_output(&long_string);
+        // The model sees the message once, followed by the timing only when verbose
+        let params = || FileParams {
+            operation: Some("read".to_string()),
+            path: None,
+            content: None,
+            append: None,
+            source: None,
+            destination: None,
+            force: None,
+            recursive: None,
+            offset: None,
+            limit: None,
+            mode: None,
+        };
+        let text = FileTool::new().call(params()).await.unwrap();
+        assert!(text.starts_with("Error: Path is required for 'read' operation"));
+        assert!(!text.contains("failed after"));
+        
+        let policy = Arc::new(ExecutionPolicy::new());
+        let mut config = crate::config::Config::default();
+        config.tools.verbose_tool_output = true;
+        policy.configure(&config);
+        let text = FileTool::with_policy(policy).call(params()).await.unwrap();
+        assert!(text.starts_with("Error: Path is required for 'read' operation"));
+        assert!(text.contains("Operation failed after"));
+        Ok(())
+    }
+    
+    // Read a file through the tool, optionally forcing the content
+    async fn read(file_tool: &FileTool, path: &str, force: bool) -> ToolResult {
+        let params = FileParams {
+            operation: Some("read".to_string()),
+            path: Some(path.to_string()),
+            content: None,
+            append: None,
+            source: None,
+            destination: None,
+            force: Some(force),
+            recursive: None,
+            offset: None,
+            limit: None,
+            mode: None,
+        };
+        file_tool.run(params).await
+    }
+    
+    #[tokio::test]
+    async fn test_unchanged_reads_are_omitted() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "remember the milk")?;
+        let path = path.to_string_lossy().to_string();
+        
+        let cache = ReadCache::shared();
+        let file_tool = FileTool::new().with_cache(Arc::clone(&cache));
+        assert_eq!(read(&file_tool, &path, false).await?.content, "remember the milk");
+        
+        let again = read(&file_tool, &path, false).await?;
+        assert!(again.content.contains("unchanged since last read at"));
+        assert!(!again.content.contains("milk"));
+        
+        // The find_file tool shares the cache
+        let find_file = FindAndReadFileTool::new().with_cache(Arc::clone(&cache));
+        let params: FindAndReadFileParams = serde_json::from_value(serde_json::json!({
+            "filename": "notes.txt",
+            "search_path": dir.path().to_string_lossy(),
+        }))?;
+        assert!(find_file.run(&params).await?.content.contains("content omitted"));
+        
+        // Clearing the cache sends the content again
+        cache.clear();
+        assert_eq!(read(&file_tool, &path, false).await?.content, "remember the milk");
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_modified_files_are_sent_with_a_note() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "remember the milk")?;
+        let path_str = path.to_string_lossy().to_string();
         
-        // The truncated string should be shorter than the original
-        assert!(truncated.len() < long_string.len());
+        let file_tool = FileTool::new();
+        read(&file_tool, &path_str, false).await?;
+        fs::write(&path, "remember the milk and the eggs")?;
         
-        // The truncated string should contain the truncation notice
-        assert!(truncated.contains("lines truncated"));
+        let output = read(&file_tool, &path_str, false).await?;
+        assert_eq!(output.content, "remember the milk and the eggs");
+        assert!(output.with_notes().starts_with("Note: The file changed since it was last read at"));
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_force_rereads_unchanged_files() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "remember the milk")?;
+        let path = path.to_string_lossy().to_string();
+        
+        let file_tool = FileTool::new();
+        read(&file_tool, &path, false).await?;
+        let output = read(&file_tool, &path, true).await?;
+        assert_eq!(output.content, "remember the milk");
+        assert!(output.warnings.is_empty());
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_line_ranges() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("long.txt");
+        let content: String = (1..=5000).map(|n| format!("line {}\n", n)).collect();
+        fs::write(&path, &content)?;
+        let path = path.to_string_lossy().to_string();
+        let file_tool = FileTool::new();
+        
+        let params: FileParams = serde_json::from_value(serde_json::json!({
+            "operation": "read", "path": path, "offset": 200, "limit": 3,
+        }))?;
+        let output = file_tool.run(params).await?;
+        assert_eq!(output.content, "[Lines 200-202 of 5000]\n200:line 200\n201:line 201\n202:line 202\n");
+        
+        // Too large to return whole, so an overview comes back instead, not
+        // recorded as read
+        let output = read(&file_tool, &path, false).await?;
+        assert!(output.content.starts_with("[Overview of a file of 5000 lines (47.7 KB), too large to read whole]"));
+        assert!(output.content.contains("[Read a chunk with offset"));
+        let again = read(&file_tool, &path, false).await?;
+        assert!(!again.content.contains("unchanged since last read"));
+        
+        // Asked for by mode, the start comes back as before, or the whole file
+        let params: FileParams = serde_json::from_value(serde_json::json!({
+            "operation": "read", "path": path, "mode": "range",
+        }))?;
+        let output = file_tool.run(params).await?;
+        assert!(output.content.contains("Read further with offset="));
+        let params: FileParams = serde_json::from_value(serde_json::json!({
+            "operation": "read", "path": path, "mode": "full",
+        }))?;
+        assert_eq!(file_tool.run(params).await?.content, content);
+        
+        let params: FileParams = serde_json::from_value(serde_json::json!({
+            "operation": "read", "path": path, "offset": 5001,
+        }))?;
+        let error = file_tool.run(params).await.unwrap_err();
+        assert!(error.to_string().contains("past the end of the file, which has 5000 lines"));
+        Ok(())
     }
 }
\ No newline at end of file
@@ -0,0 +1,255 @@
+//! Finding files by name without walking the tree for every call.
+//!
+//! The find_file tool looks files up in an index of the paths under a search
+//! root, keyed by file name. The index is built on first use with a parallel
+//! walk that leaves out what the walk rules do, and kept per root and rules.
+//! It is built again when it is older than [`MAX_AGE`], when asked to, and
+//! when a name is missing from it and one of the directories it holds has
+//! changed since, as creating or renaming a file in a directory changes the
+//! directory's modification time.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use ignore::WalkState;
+
+use crate::tools::walk::WalkRules;
+
+/// How long an index is used before it is built again
+pub const MAX_AGE: Duration = Duration::from_secs(60);
+
+// How close to the build a directory's modification time may be for a change
+// made just after it to go unnoticed on filesystems with coarse timestamps
+const RACY: Duration = Duration::from_secs(1);
+
+/// The indexes of the search roots looked in, shared by every call of a tool
+#[derive(Debug, Default)]
+pub struct FileIndex {
+    indexes: Mutex<HashMap<Key, Arc<Index>>>,
+    builds: Mutex<usize>,
+}
+
+// A search root, the rules of the walk and how deep it goes
+type Key = (PathBuf, WalkRules, usize);
+
+// The files under one root, and the directories walked to find them
+#[derive(Debug)]
+struct Index {
+    by_name: HashMap<OsString, Vec<PathBuf>>,
+    directories: Vec<(PathBuf, Option<SystemTime>)>,
+    built: Instant,
+    built_at: SystemTime,
+}
+
+impl FileIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The first file called `name` under `root`, in the order a sorted walk
+    /// would meet it, going at most `max_depth` levels down. `refresh` builds
+    /// the index again first. This walks the filesystem, so async callers run
+    /// it on a blocking task
+    pub fn find(
+        &self,
+        root: &Path,
+        rules: &WalkRules,
+        name: &str,
+        max_depth: usize,
+        refresh: bool,
+    ) -> Option<PathBuf> {
+        if !root.is_dir() {
+            return None;
+        }
+        let key = (root.to_path_buf(), rules.clone(), max_depth);
+        let cached = self.indexes.lock().unwrap().get(&key).cloned();
+        let index = match cached {
+            Some(index) if !refresh && index.built.elapsed() < MAX_AGE => index,
+            _ => self.build(key.clone()),
+        };
+        if let Some(path) = index.first(name) {
+            if path.is_file() {
+                return Some(path);
+            }
+        } else if !index.changed() {
+            return None;
+        }
+        self.build(key).first(name)
+    }
+
+    /// How many times an index was built, for tests and the curious
+    pub fn builds(&self) -> usize {
+        *self.builds.lock().unwrap()
+    }
+
+    // Walk the key's root and keep what was found as its index
+    fn build(&self, key: Key) -> Arc<Index> {
+        let index = Arc::new(Index::build(&key.0, &key.1, key.2));
+        *self.builds.lock().unwrap() += 1;
+        self.indexes.lock().unwrap().insert(key, Arc::clone(&index));
+        index
+    }
+}
+
+impl Index {
+    fn build(root: &Path, rules: &WalkRules, max_depth: usize) -> Self {
+        let built = Instant::now();
+        let built_at = SystemTime::now();
+        let files = Mutex::new(Vec::new());
+        let directories = Mutex::new(Vec::new());
+        rules
+            .directory_walker(root)
+            .max_depth(Some(max_depth))
+            .build_parallel()
+            .run(|| {
+                Box::new(|entry| {
+                    let Ok(entry) = entry else {
+                        return WalkState::Continue;
+                    };
+                    match entry.file_type() {
+                        Some(kind) if kind.is_dir() => {
+                            let modified = entry.metadata().ok().and_then(|m| m.modified().ok());
+                            directories
+                                .lock()
+                                .unwrap()
+                                .push((entry.into_path(), modified));
+                        }
+                        Some(_) if entry.depth() > 0 => {
+                            files.lock().unwrap().push(entry.into_path())
+                        }
+                        _ => {}
+                    }
+                    WalkState::Continue
+                })
+            });
+
+        // Paths compare component by component, which is the order a walk
+        // sorted by file name meets them in
+        let mut files = files.into_inner().unwrap();
+        files.sort();
+        let mut by_name: HashMap<OsString, Vec<PathBuf>> = HashMap::new();
+        for path in files {
+            if let Some(name) = path.file_name() {
+                by_name.entry(name.to_os_string()).or_default().push(path);
+            }
+        }
+        Self {
+            by_name,
+            directories: directories.into_inner().unwrap(),
+            built,
+            built_at,
+        }
+    }
+
+    fn first(&self, name: &str) -> Option<PathBuf> {
+        self.by_name
+            .get(&OsString::from(name))
+            .and_then(|paths| paths.first())
+            .cloned()
+    }
+
+    // Whether a directory walked has changed since, or changed so close to
+    // the build that a change after it might not show
+    fn changed(&self) -> bool {
+        self.directories.iter().any(|(path, modified)| {
+            let now = path.metadata().ok().and_then(|m| m.modified().ok());
+            let racy = modified.is_some_and(|modified| modified + RACY >= self.built_at);
+            now != *modified || racy
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn project() -> anyhow::Result<TempDir> {
+        let dir = TempDir::new()?;
+        fs::create_dir_all(dir.path().join("src/nested"))?;
+        fs::create_dir_all(dir.path().join("out"))?;
+        fs::create_dir_all(dir.path().join("target"))?;
+        fs::write(dir.path().join(".gitignore"), "out/\n")?;
+        fs::write(dir.path().join("src/nested/lib.rs"), "")?;
+        fs::write(dir.path().join("src/lib.rs"), "")?;
+        fs::write(dir.path().join("out/build.rs"), "")?;
+        fs::write(dir.path().join("target/cache.rs"), "")?;
+        Ok(dir)
+    }
+
+    #[test]
+    fn test_lookups_hit_the_index() -> anyhow::Result<()> {
+        let dir = project()?;
+        let index = FileIndex::new();
+        let rules = WalkRules::default();
+        let found = index.find(dir.path(), &rules, "lib.rs", 11, false);
+        // src/lib.rs comes before src/nested/, as in a walk sorted by name
+        assert_eq!(found, Some(dir.path().join("src/lib.rs")));
+        assert_eq!(index.builds(), 1);
+
+        let found = index.find(dir.path(), &rules, "lib.rs", 11, false);
+        assert_eq!(found, Some(dir.path().join("src/lib.rs")));
+        assert_eq!(index.builds(), 1);
+
+        // Asking for a rebuild walks the tree again
+        index.find(dir.path(), &rules, "lib.rs", 11, true);
+        assert_eq!(index.builds(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_a_new_file_rebuilds_a_stale_index() -> anyhow::Result<()> {
+        let dir = project()?;
+        let index = FileIndex::new();
+        let rules = WalkRules::default();
+        assert_eq!(index.find(dir.path(), &rules, "new.rs", 11, false), None);
+        let builds = index.builds();
+
+        fs::write(dir.path().join("src/nested/new.rs"), "")?;
+        assert_eq!(
+            index.find(dir.path(), &rules, "new.rs", 11, false),
+            Some(dir.path().join("src/nested/new.rs"))
+        );
+        assert!(index.builds() > builds);
+
+        // A file removed since is not handed out
+        fs::remove_file(dir.path().join("src/lib.rs"))?;
+        assert_eq!(
+            index.find(dir.path(), &rules, "lib.rs", 11, false),
+            Some(dir.path().join("src/nested/lib.rs"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_the_index_leaves_out_what_the_walk_rules_do() -> anyhow::Result<()> {
+        let dir = project()?;
+        let index = FileIndex::new();
+        let rules = WalkRules::default();
+        assert_eq!(index.find(dir.path(), &rules, "build.rs", 11, false), None);
+        assert_eq!(index.find(dir.path(), &rules, "cache.rs", 11, false), None);
+        // Files are found by their own name even where the built-in rules hide them
+        assert_eq!(
+            index.find(dir.path(), &rules, ".gitignore", 11, false),
+            Some(dir.path().join(".gitignore"))
+        );
+
+        // Other rules have an index of their own
+        let all = WalkRules::default()
+            .respect_gitignore(Some(false))
+            .unhide(["target".to_string()]);
+        assert!(index
+            .find(dir.path(), &all, "build.rs", 11, false)
+            .is_some());
+        assert!(index
+            .find(dir.path(), &all, "cache.rs", 11, false)
+            .is_some());
+        // A shallow search doesn't reach nested files
+        assert_eq!(index.find(dir.path(), &all, "lib.rs", 1, false), None);
+        Ok(())
+    }
+}
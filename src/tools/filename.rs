@@ -0,0 +1,222 @@
+//! Checking the paths the model names before the file tool touches them.
+//!
+//! Models invent paths with newlines in them, expect a shell to expand `~`,
+//! or pick names Windows keeps for devices. Rather than leave a bizarre
+//! entry behind or fail deep inside the filesystem, each is refused with a
+//! message saying what to change. Control characters and `~` are handled
+//! for every path; names and lengths only for paths about to be created.
+
+use std::env;
+use std::path::{is_separator, Component, Path, PathBuf};
+
+use crate::tools::result::ToolError;
+
+/// The longest name a path component may have, in bytes
+pub const MAX_NAME: usize = 255;
+
+/// The longest path that may be created, in bytes
+#[cfg(windows)]
+pub const MAX_PATH: usize = 260;
+#[cfg(not(windows))]
+pub const MAX_PATH: usize = 4096;
+
+// Names Windows keeps for devices, whatever the extension
+const RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// `path` with a leading `~/` expanded to the home directory, after refusing
+/// control characters
+pub fn expand(path: &str) -> Result<String, ToolError> {
+    expand_with_home(path, env::var_os("HOME").map(PathBuf::from).as_deref())
+}
+
+/// Like [`expand`], with `home` as the home directory
+pub fn expand_with_home(path: &str, home: Option<&Path>) -> Result<String, ToolError> {
+    check_characters(path)?;
+    let Some(rest) = path.strip_prefix('~') else {
+        return Ok(path.to_string());
+    };
+    if !rest.is_empty() && !rest.starts_with(is_separator) {
+        return Err(ToolError::InvalidParams(format!(
+            "'{}' names another user's home, which is not expanded; write the full path",
+            path
+        )));
+    }
+    let home = home.ok_or_else(|| {
+        ToolError::InvalidParams(format!(
+            "'{}' can't be expanded because HOME is not set; write the full path",
+            path
+        ))
+    })?;
+    let home = match rest.trim_start_matches(is_separator) {
+        "" => home.to_path_buf(),
+        rest => home.join(rest),
+    };
+    Ok(home.to_string_lossy().into_owned())
+}
+
+/// Refuse newlines, tabs, NUL and other control characters anywhere in `path`
+pub fn check_characters(path: &str) -> Result<(), ToolError> {
+    match path.chars().find(|c| c.is_control()) {
+        Some(c) => Err(ToolError::InvalidParams(format!(
+            "The path {:?} contains the control character {:?}; paths can't hold newlines, tabs or other control characters",
+            path, c
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// `path` checked as a path to create: refuses reserved device names and
+/// overlong names, and names ending in whitespace. Windows drops trailing
+/// spaces and dots from names anyway, so there they are trimmed instead.
+pub fn check_new(path: &str) -> Result<String, ToolError> {
+    check_characters(path)?;
+    let mut checked = PathBuf::new();
+    for component in Path::new(path).components() {
+        let Component::Normal(name) = component else {
+            checked.push(component);
+            continue;
+        };
+        let name = name.to_string_lossy();
+        let name = check_name(&name)?;
+        checked.push(name);
+    }
+    if cfg!(windows) {
+        Ok(checked.to_string_lossy().into_owned())
+    } else {
+        Ok(path.to_string())
+    }
+}
+
+/// Refuse a resolved path longer than the filesystem allows
+pub fn check_length(path: &Path) -> Result<(), ToolError> {
+    let length = path.as_os_str().len();
+    if length > MAX_PATH {
+        return Err(ToolError::InvalidParams(format!(
+            "The path is {} bytes long, over the limit of {}; use a shorter path",
+            length, MAX_PATH
+        )));
+    }
+    Ok(())
+}
+
+// One component of a path to create, as it should be written
+fn check_name(name: &str) -> Result<&str, ToolError> {
+    let name = if cfg!(windows) {
+        name.trim_end_matches([' ', '.'])
+    } else if name.ends_with(char::is_whitespace) {
+        return Err(ToolError::InvalidParams(format!(
+            "The name '{}' ends in whitespace, which is easy to miss; remove it",
+            name
+        )));
+    } else {
+        name
+    };
+    if name.is_empty() {
+        return Err(ToolError::InvalidParams(
+            "A name in the path is only spaces and dots; give it a real name".to_string(),
+        ));
+    }
+    if name.len() > MAX_NAME {
+        return Err(ToolError::InvalidParams(format!(
+            "The name '{}…' is {} bytes long, over the limit of {}; use a shorter name",
+            name.chars().take(32).collect::<String>(),
+            name.len(),
+            MAX_NAME
+        )));
+    }
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    if RESERVED
+        .iter()
+        .any(|device| stem.eq_ignore_ascii_case(device))
+    {
+        return Err(ToolError::InvalidParams(format!(
+            "'{}' is a device name reserved by Windows (CON, PRN, AUX, NUL, COM1-9, LPT1-9); pick another name",
+            name
+        )));
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refused(result: Result<impl std::fmt::Debug, ToolError>) -> String {
+        match result {
+            Err(ToolError::InvalidParams(message)) => message,
+            other => panic!("expected invalid params, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_control_characters_are_refused() {
+        let message = refused(expand("notes\n.txt"));
+        assert!(message.contains("'\\n'"), "{}", message);
+        assert!(refused(expand("a\tb")).contains("'\\t'"));
+        assert!(refused(check_new("bell\u{7}")).contains("control character"));
+        assert!(refused(expand("nul\0")).contains("'\\0'"));
+        assert_eq!(expand("src/main.rs").unwrap(), "src/main.rs");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_a_leading_tilde_is_the_home_directory() {
+        let home = Path::new("/home/ada");
+        assert_eq!(
+            expand_with_home("~/notes/todo.md", Some(home)).unwrap(),
+            "/home/ada/notes/todo.md"
+        );
+        assert_eq!(expand_with_home("~", Some(home)).unwrap(), "/home/ada");
+        assert_eq!(
+            expand_with_home("notes/~/x", Some(home)).unwrap(),
+            "notes/~/x"
+        );
+
+        assert!(refused(expand_with_home("~bob/x", Some(home))).contains("another user's home"));
+        assert!(refused(expand_with_home("~/x", None)).contains("HOME is not set"));
+    }
+
+    #[test]
+    fn test_reserved_device_names_are_refused() {
+        for name in ["CON", "aux.txt", "out/Nul.tar.gz", "com1", "LPT9.log"] {
+            let message = refused(check_new(name));
+            assert!(message.contains("reserved by Windows"), "{}", message);
+        }
+        for name in ["console.txt", "auxiliary", "com10", "docs/connect.md"] {
+            assert_eq!(Path::new(&check_new(name).unwrap()), Path::new(name));
+        }
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn test_trailing_whitespace_is_refused() {
+        assert!(refused(check_new("notes.txt ")).contains("ends in whitespace"));
+        assert!(refused(check_new("dir /file")).contains("'dir '"));
+        // Dots are ordinary characters outside Windows
+        assert_eq!(check_new("weird.").unwrap(), "weird.");
+        assert_eq!(check_new("../up/./here").unwrap(), "../up/./here");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_trailing_spaces_and_dots_are_trimmed() {
+        assert_eq!(check_new("notes.txt. ").unwrap(), "notes.txt");
+        assert_eq!(check_new("dir. \\file").unwrap(), "dir\\file");
+        assert!(refused(check_new(". .")).contains("only spaces and dots"));
+    }
+
+    #[test]
+    fn test_overlong_names_and_paths_are_refused() {
+        let name = "a".repeat(MAX_NAME + 1);
+        let message = refused(check_new(&format!("dir/{}", name)));
+        assert!(message.contains("over the limit of 255"), "{}", message);
+        assert!(check_new(&"a".repeat(MAX_NAME)).is_ok());
+
+        let long = PathBuf::from("/").join(["abc"; MAX_PATH / 4 + 1].join("/"));
+        assert!(refused(check_length(&long).map(|_| ())).contains("use a shorter path"));
+        assert!(check_length(Path::new("/tmp/file")).is_ok());
+    }
+}
@@ -1,14 +1,18 @@
 use std::env;
-use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Instant;
 
-use anyhow::Result;
+use crate::tools::file_index::FileIndex;
+use crate::tools::lines::{self, LineRange};
+use crate::tools::policy::ExecutionPolicy;
+use crate::tools::read_cache::{self, Freshness, ReadCache};
+use crate::tools::result::{ToolError, ToolOutput, ToolResult};
+use crate::tools::walk::WalkRules;
 use ollama_rs::generation::tools::Tool;
 use schemars::JsonSchema;
 use serde::Deserialize;
 
-const MAX_OUTPUT_LENGTH: usize = 30000;
 const MAX_SEARCH_DEPTH: usize = 10; // Maximum directory depth to search
 
 /// Parameters for the FindAndReadFileTool
@@ -28,14 +32,39 @@ pub struct FindAndReadFileParams {
         description = "Optional. Whether to search inside hidden directories (like '.git', '.build'). Defaults to false."
     )]
     include_hidden_dirs: Option<bool>,
-}
 
-pub struct FindAndReadFileTool {}
+    #[schemars(
+        description = "Optional. Whether to leave out what the project's .gitignore files ignore. Defaults to true."
+    )]
+    respect_gitignore: Option<bool>,
 
-impl Default for FindAndReadFileTool {
-    fn default() -> Self {
-        Self {}
-    }
+    #[schemars(
+        description = "Optional. Names or glob patterns of directories hidden by default to search anyway, e.g. ['bin', 'vendor']."
+    )]
+    unhide: Option<Vec<String>>,
+
+    #[schemars(
+        description = "Optional. Return the content even if the file is unchanged since it was last read. Defaults to false."
+    )]
+    force: Option<bool>,
+
+    #[schemars(
+        description = "Optional. Look the file up in a fresh index of the tree rather than the cached one, e.g. when a file was just created. Defaults to false."
+    )]
+    index_refresh: Option<bool>,
+
+    #[schemars(description = "Optional. The first line to read, counting from 1.")]
+    offset: Option<usize>,
+
+    #[schemars(description = "Optional. How many lines to read from 'offset'.")]
+    limit: Option<usize>,
+}
+
+#[derive(Default)]
+pub struct FindAndReadFileTool {
+    cache: Arc<ReadCache>,
+    policy: Arc<ExecutionPolicy>,
+    index: Arc<FileIndex>,
 }
 
 impl FindAndReadFileTool {
@@ -43,148 +72,123 @@ impl FindAndReadFileTool {
         Self::default()
     }
 
-    fn truncate_output(content: &str) -> String {
-        if content.len() <= MAX_OUTPUT_LENGTH {
-            return content.to_string();
-        }
-
-        let half_length = MAX_OUTPUT_LENGTH / 2;
-        let start = &content[..half_length];
-        let end = &content[content.len() - half_length..];
-
-        // Count truncated lines
-        let middle_content = &content[half_length..content.len() - half_length];
-        let truncated_lines_count = middle_content.chars().filter(|&c| c == '\n').count();
+    /// Share the record of files already read, e.g. with the file tool
+    pub fn with_cache(mut self, cache: Arc<ReadCache>) -> Self {
+        self.cache = cache;
+        self
+    }
 
-        format!(
-            "{}\n\n... [{} lines truncated] ...\n\n{}",
-            start, truncated_lines_count, end
-        )
+    /// Leave out what the policy's walk settings say, like the ls tool
+    pub fn with_policy(mut self, policy: Arc<ExecutionPolicy>) -> Self {
+        self.policy = policy;
+        self
     }
 
-    // Perform recursive file search
-    fn find_file(
+    // Look up the first file called `filename` under `search_path`, leaving
+    // out what `rules` do, on a blocking task as the index may walk the tree
+    async fn find_file(
         &self,
         filename: &str,
         search_path: &Path,
-        include_hidden_dirs: bool,
-        depth: usize,
+        rules: &WalkRules,
+        refresh: bool,
     ) -> Option<PathBuf> {
-        // Check maximum search depth to prevent infinite recursion
-        if depth > MAX_SEARCH_DEPTH {
-            return None;
-        }
-
-        // Skip if path doesn't exist or isn't a directory
-        if !search_path.exists() || !search_path.is_dir() {
-            return None;
-        }
-
-        // Try to read directory entries
-        let entries = match fs::read_dir(search_path) {
-            Ok(entries) => entries,
-            Err(e) => {
-                println!(
-                    "\x1b[1;33m[FIND FILE TOOL] Error reading directory '{}': {}\x1b[0m",
-                    search_path.display(),
-                    e
-                );
-                return None;
-            }
-        };
-
-        // Check each entry
-        for entry in entries {
-            let entry = match entry {
-                Ok(entry) => entry,
-                Err(_) => continue,
-            };
-
-            let path = entry.path();
-            let file_name_os = entry.file_name();
-            let file_name = match file_name_os.to_str() {
-                Some(name) => name,
-                None => continue, // Skip entries with invalid Unicode names
-            };
-
-            // Skip hidden directories if not included
-            if !include_hidden_dirs && file_name.starts_with('.') && path.is_dir() {
-                continue;
-            }
-
-            // Check if this is the target file
-            if file_name == filename && path.is_file() {
-                return Some(path);
-            }
-
-            // Recursively search subdirectories
-            if path.is_dir() {
-                if let Some(found_path) =
-                    self.find_file(filename, &path, include_hidden_dirs, depth + 1)
-                {
-                    return Some(found_path);
-                }
-            }
-        }
-
-        None
+        let index = Arc::clone(&self.index);
+        let (filename, search_path, rules) = (
+            filename.to_string(),
+            search_path.to_path_buf(),
+            rules.clone(),
+        );
+        // Limit the depth to keep the search quick in large trees
+        tokio::task::spawn_blocking(move || {
+            index.find(
+                &search_path,
+                &rules,
+                &filename,
+                MAX_SEARCH_DEPTH + 1,
+                refresh,
+            )
+        })
+        .await
+        .ok()
+        .flatten()
     }
 
-    async fn find_and_read_file(
-        &self,
-        params: &FindAndReadFileParams,
-    ) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
+    /// Find a file by name and return its content
+    pub async fn run(&self, params: &FindAndReadFileParams) -> ToolResult {
+        let start_time = Instant::now();
         let filename = &params.filename;
         let include_hidden_dirs = params.include_hidden_dirs.unwrap_or(false);
 
+        // The same entries are left out as for ls, except that files are
+        // found by name wherever the built-in rules would hide them
+        let mut unhide = params.unhide.clone().unwrap_or_default();
+        if include_hidden_dirs {
+            unhide.push(".*".to_string());
+        }
+        let rules = self
+            .policy
+            .walk_rules()
+            .respect_gitignore(params.respect_gitignore)
+            .unhide(unhide);
+
         // Determine the search root directory
         let search_root = if let Some(search_path) = &params.search_path {
             let path = Path::new(search_path);
             if path.is_absolute() {
                 path.to_path_buf()
             } else {
-                match env::current_dir() {
-                    Ok(current_dir) => current_dir.join(path),
-                    Err(e) => return Err(format!("Failed to get current directory: {}", e).into()),
-                }
+                env::current_dir()
+                    .map_err(|e| ToolError::io("Failed to get current directory", e))?
+                    .join(path)
             }
         } else {
-            match env::current_dir() {
-                Ok(current_dir) => current_dir,
-                Err(e) => return Err(format!("Failed to get current directory: {}", e).into()),
-            }
+            env::current_dir().map_err(|e| ToolError::io("Failed to get current directory", e))?
         };
 
-        // Log search parameters
-        println!("\x1b[1;34m[FIND FILE TOOL] Searching for '{}' starting from '{}' (include hidden: {})\x1b[0m",
-            filename, search_root.display(), include_hidden_dirs);
-
         // Perform the recursive search
-        if let Some(file_path) = self.find_file(filename, &search_root, include_hidden_dirs, 0) {
-            println!(
-                "\x1b[1;32m[FIND FILE TOOL] Found '{}' at: {}\x1b[0m",
-                filename,
-                file_path.display()
-            );
+        let refresh = params.index_refresh.unwrap_or(false);
+        if let Some(file_path) = self
+            .find_file(filename, &search_root, &rules, refresh)
+            .await
+        {
+            let read_error =
+                |e| ToolError::io(format!("Error reading file '{}'", file_path.display()), e);
+
+            // Skip the content if the model already has it
+            let metadata = tokio::fs::metadata(&file_path).await.map_err(read_error)?;
+            let range = LineRange::new(params.offset, params.limit);
+            let freshness = self.cache.check(&file_path, &metadata);
+            if let (Freshness::Unchanged { read_at }, false, true) =
+                (freshness, params.force.unwrap_or(false), range.is_whole())
+            {
+                return Ok(read_cache::unchanged(&file_path, read_at).timed(start_time));
+            }
 
             // Read the file content
-            match fs::read_to_string(&file_path) {
-                Ok(content) => {
-                    // Truncate content if necessary
-                    let content = Self::truncate_output(&content);
-                    Ok(content)
-                }
-                Err(e) => {
-                    Err(format!("Error reading file '{}': {}", file_path.display(), e).into())
-                }
+            let content = tokio::fs::read_to_string(&file_path)
+                .await
+                .map_err(read_error)?;
+
+            // Return the lines asked for, or as many as fit
+            let excerpt = lines::excerpt(&content, range)?;
+            if excerpt.complete {
+                self.cache.record(&file_path, &metadata);
             }
+            let output = ToolOutput::new(excerpt.text);
+            let output = match freshness {
+                Freshness::Changed { read_at } => {
+                    output.with_warning(read_cache::changed_note(read_at))
+                }
+                _ => output,
+            };
+            Ok(output.timed(start_time))
         } else {
-            Err(format!(
+            Err(ToolError::NotFound(format!(
                 "File '{}' not found in search path: {}",
                 filename,
                 search_root.display()
-            )
-            .into())
+            )))
         }
     }
 }
@@ -208,6 +212,11 @@ SUPPORTED PARAMETERS:
 - 'filename': (REQUIRED) The exact name of the file to search for (e.g., 'main.rs', 'README.md')
 - 'search_path': (OPTIONAL) The relative path of the directory where the recursive search should begin. Defaults to the current working directory if omitted.
 - 'include_hidden_dirs': (OPTIONAL) Whether to search inside hidden directories (like '.git', '.build'). Defaults to false.
+- 'respect_gitignore': (OPTIONAL) Whether to skip what the project's .gitignore files ignore. Defaults to true.
+- 'unhide': (OPTIONAL) Directories skipped by default to search anyway, e.g. ['bin', 'vendor'].
+- 'force': (OPTIONAL) Return the content even if the file is unchanged since it was last read. Defaults to false.
+- 'index_refresh': (OPTIONAL) Look the file up in a fresh index of the tree, e.g. right after creating it. Defaults to false.
+- 'offset' and 'limit': (OPTIONAL) Read only 'limit' lines starting at line 'offset' (counting from 1).
 
 HOW TO USE:
 1. Provide the 'filename' parameter with the exact name of the file you're looking for
@@ -218,16 +227,22 @@ EXAMPLES:
 - To find and read the main.rs file anywhere in the project: { filename: 'main.rs' }
 - To search for config.json in the src directory: { filename: 'config.json', search_path: 'src' }
 - To find .gitignore including hidden directories: { filename: '.gitignore', include_hidden_dirs: true }
+- To read lines 200-320 of lib.rs: { filename: 'lib.rs', offset: 200, limit: 121 }
 
 FEATURES:
 - Recursive search down to multiple directory levels
+- Files are looked up in an index of the tree kept between calls, so repeated searches are quick;
+  it is rebuilt after a minute, when a name is missing and the tree has changed, or with 'index_refresh'
 - Option to include or exclude hidden directories
-- Handles large files by truncating output when necessary
+- Skips the same entries as the ls tool: build directories like node_modules and what .gitignore ignores
+- Reads of a line range, and of files too large to return whole, give numbered lines ('42:text')
+  under a header with the file's total line count
+- Re-reading a file that hasn't changed returns a short notice instead of the content
 - Provides informative error messages if the file isn't found
 
 LIMITATIONS:
 - Search is limited to 10 directory levels deep to prevent excessive recursion
-- Output is truncated if it exceeds 30,000 characters
+- At most 30,000 characters are returned; a larger file returns its first lines, so read the rest by range
 - Searching with 'include_hidden_dirs: true' may be slower
 - Matches only by exact filename, not by path patterns or content"
     }
@@ -239,28 +254,21 @@ LIMITATIONS:
         // Start timing the execution
         let start_time = Instant::now();
 
-        // Print colorful message indicating tool is being called
-        println!(
-            "\x1b[1;32m[FIND FILE TOOL] Being called to find file: {}\x1b[0m",
-            parameters.filename
-        );
-
-        // Execute the find and read operation
-        let result = self.find_and_read_file(&parameters).await;
-
-        // Calculate execution time
-        let execution_time = start_time.elapsed().as_millis();
-
-        // Return result with execution time
-        match result {
-            Ok(output) => Ok(format!(
+        // Flatten the result into text for the model, with the execution time if asked for
+        let verbose = self.policy.verbose_output();
+        match self.run(&parameters).await {
+            Ok(output) if verbose => Ok(format!(
                 "{}\n\nOperation completed in {}ms",
-                output, execution_time
+                output.with_notes(),
+                output.duration_ms
             )),
-            Err(e) => Ok(format!(
+            Ok(output) => Ok(output.with_notes()),
+            Err(e) if verbose => Ok(format!(
                 "Error: {}\n\nOperation failed after {}ms",
-                e, execution_time
+                e,
+                start_time.elapsed().as_millis()
             )),
+            Err(e) => Ok(format!("Error: {}", e)),
         }
     }
 }
@@ -270,6 +278,12 @@ pub struct FindFile {
     tool: FindAndReadFileTool,
 }
 
+impl Default for FindFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FindFile {
     pub fn new() -> Self {
         Self {
@@ -282,23 +296,89 @@ impl FindFile {
         filename: &str,
         search_path: Option<&str>,
         include_hidden_dirs: bool,
-    ) -> Result<String> {
+    ) -> Result<String, ToolError> {
         let params = FindAndReadFileParams {
             filename: filename.to_string(),
             search_path: search_path.map(|s| s.to_string()),
             include_hidden_dirs: Some(include_hidden_dirs),
+            respect_gitignore: None,
+            unhide: None,
+            force: None,
+            index_refresh: None,
+            offset: None,
+            limit: None,
         };
 
-        match self.tool.call(params).await {
-            Ok(output) => {
-                // Extract the actual file content before the "Operation completed" message
-                if let Some(idx) = output.find("\n\nOperation completed") {
-                    Ok(output[..idx].to_string())
-                } else {
-                    Ok(output)
-                }
-            }
-            Err(e) => Err(anyhow::anyhow!("Failed to find and read file: {}", e)),
-        }
+        self.tool.run(&params).await.map(|output| output.content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_find_and_read_nested_file() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join("a").join("b"))?;
+        fs::write(dir.path().join("a").join("b").join("notes.md"), "found me")?;
+        let root = dir.path().to_string_lossy().to_string();
+
+        let mut find_file = FindFile::new();
+        assert_eq!(
+            find_file
+                .find_and_read("notes.md", Some(&root), false)
+                .await?,
+            "found me"
+        );
+
+        let missing = find_file
+            .find_and_read("missing.md", Some(&root), false)
+            .await;
+        assert!(matches!(missing, Err(ToolError::NotFound(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_find_skips_what_ls_skips() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        fs::create_dir_all(dir.path().join("out"))?;
+        fs::create_dir_all(dir.path().join("bin"))?;
+        fs::write(dir.path().join(".gitignore"), "out/\n")?;
+        fs::write(dir.path().join("out").join("config.toml"), "generated")?;
+        fs::write(dir.path().join("bin").join("tool.rs"), "source")?;
+        let root = dir.path().to_string_lossy().to_string();
+        let params = |filename: &str, respect_gitignore, unhide| FindAndReadFileParams {
+            filename: filename.to_string(),
+            search_path: Some(root.clone()),
+            include_hidden_dirs: None,
+            respect_gitignore,
+            unhide,
+            force: None,
+            index_refresh: None,
+            offset: None,
+            limit: None,
+        };
+
+        let tool = FindAndReadFileTool::new();
+        let ignored = tool.run(&params("config.toml", None, None)).await;
+        assert!(matches!(ignored, Err(ToolError::NotFound(_))));
+        let found = tool.run(&params("config.toml", Some(false), None)).await?;
+        assert_eq!(found.content, "generated");
+
+        let hidden = tool.run(&params("tool.rs", None, None)).await;
+        assert!(matches!(hidden, Err(ToolError::NotFound(_))));
+        let unhidden = vec!["bin".to_string()];
+        let found = tool.run(&params("tool.rs", None, Some(unhidden))).await?;
+        assert_eq!(found.content, "source");
+
+        // The file asked for is found even where the built-in rules hide it
+        assert_eq!(
+            tool.run(&params(".gitignore", None, None)).await?.content,
+            "out/\n"
+        );
+        Ok(())
     }
 }
@@ -0,0 +1,340 @@
+//! Running one tool by hand, as `sentinel tool` does.
+//!
+//! Arguments come as `key=value` pairs or as a JSON object, and are checked
+//! against the tool's parameter schema before the tool runs: unknown and
+//! missing parameters, and values of the wrong type, are reported by name
+//! rather than as a serde error.
+
+use serde_json::{Map, Value};
+
+use crate::llm::ollama::Tool;
+use crate::tools::result::ToolError;
+
+/// Build a tool's arguments from `key=value` pairs, converting each value to
+/// the type its parameter takes
+pub fn arguments_from_pairs(tool: &Tool, pairs: &[String]) -> Result<Value, ToolError> {
+    let mut arguments = Map::new();
+    for pair in pairs {
+        let (key, value) = pair.split_once('=').ok_or_else(|| {
+            ToolError::InvalidParams(format!("Expected key=value, got '{}'", pair))
+        })?;
+        let schema = property(tool, key)?;
+        if arguments.contains_key(key) {
+            return Err(ToolError::InvalidParams(format!(
+                "'{}' is given more than once",
+                key
+            )));
+        }
+        arguments.insert(key.to_string(), convert(key, value, schema)?);
+    }
+
+    let arguments = Value::Object(arguments);
+    validate(tool, &arguments)?;
+    Ok(arguments)
+}
+
+/// Check `arguments` against the tool's schema: an object with only known
+/// parameters, every required one, each of the type it takes
+pub fn validate(tool: &Tool, arguments: &Value) -> Result<(), ToolError> {
+    let object = arguments.as_object().ok_or_else(|| {
+        ToolError::InvalidParams(format!("Arguments for {} must be a JSON object", tool.name))
+    })?;
+
+    for (key, value) in object {
+        let schema = property(tool, key)?;
+        let types = types(schema);
+        if !types.is_empty() && !types.iter().any(|kind| has_type(value, kind)) {
+            return Err(ToolError::InvalidParams(format!(
+                "'{}' must be {}, not {}",
+                key,
+                type_name(schema),
+                value
+            )));
+        }
+    }
+
+    let missing: Vec<&str> = required(tool)
+        .filter(|name| !object.contains_key(*name))
+        .collect();
+    if !missing.is_empty() {
+        return Err(ToolError::InvalidParams(format!(
+            "{} needs {}",
+            tool.name,
+            missing
+                .iter()
+                .map(|name| format!("'{}'", name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// A tool's name, description and parameters, for `sentinel tool --list`
+pub fn describe(tool: &Tool) -> String {
+    let mut text = tool.name.clone();
+    // The first paragraph says what it does; the rest is advice for the model
+    let summary = tool.description.split("\n\n").next().unwrap_or_default();
+    for line in summary.lines() {
+        text.push_str(&format!("\n  {}", line.trim()));
+    }
+
+    let required: Vec<&str> = required(tool).collect();
+    for (name, schema) in properties(tool) {
+        let mut kind = type_name(schema);
+        if required.contains(&name.as_str()) {
+            kind.push_str(", required");
+        }
+        text.push_str(&format!("\n    {} ({})", name, kind));
+        if let Some(description) = schema.get("description").and_then(Value::as_str) {
+            text.push_str(&format!(": {}", description));
+        }
+    }
+    text
+}
+
+/// A tool's parameters and their types on one line, e.g.
+/// "command (string, required), timeout (integer)"
+pub fn parameters(tool: &Tool) -> String {
+    let required: Vec<&str> = required(tool).collect();
+    let parameters: Vec<String> = properties(tool)
+        .map(|(name, schema)| {
+            let mut kind = type_name(schema);
+            if required.contains(&name.as_str()) {
+                kind.push_str(", required");
+            }
+            format!("{} ({})", name, kind)
+        })
+        .collect();
+    if parameters.is_empty() {
+        "no parameters".to_string()
+    } else {
+        parameters.join(", ")
+    }
+}
+
+fn properties(tool: &Tool) -> impl Iterator<Item = (&String, &Value)> {
+    tool.input_schema
+        .get("properties")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+}
+
+fn required(tool: &Tool) -> impl Iterator<Item = &str> {
+    tool.input_schema
+        .get("required")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+}
+
+// The schema of the parameter `key`, or an error naming the ones there are
+fn property<'a>(tool: &'a Tool, key: &str) -> Result<&'a Value, ToolError> {
+    properties(tool)
+        .find(|(name, _)| *name == key)
+        .map(|(_, schema)| schema)
+        .ok_or_else(|| {
+            let known: Vec<&str> = properties(tool).map(|(name, _)| name.as_str()).collect();
+            ToolError::InvalidParams(format!(
+                "{} has no parameter '{}'; it takes {}",
+                tool.name,
+                key,
+                known.join(", ")
+            ))
+        })
+}
+
+// The JSON types a parameter takes; an `Option` is also "null"
+fn types(schema: &Value) -> Vec<&str> {
+    match schema.get("type") {
+        Some(Value::String(kind)) => vec![kind.as_str()],
+        Some(Value::Array(kinds)) => kinds.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn has_type(value: &Value, kind: &str) -> bool {
+    match kind {
+        "string" => value.is_string(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "boolean" => value.is_boolean(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+// How a parameter's type is shown, e.g. "integer" or "array of string"
+fn type_name(schema: &Value) -> String {
+    let kind = types(schema).into_iter().find(|kind| *kind != "null");
+    match kind {
+        Some("array") => match schema.get("items").map(type_name) {
+            Some(items) => format!("array of {}", items),
+            None => "array".to_string(),
+        },
+        Some(kind) => kind.to_string(),
+        // A reference to a definition, e.g. "#/definitions/Edit"
+        None => schema
+            .get("$ref")
+            .and_then(Value::as_str)
+            .and_then(|reference| reference.rsplit('/').next())
+            .unwrap_or("JSON")
+            .to_string(),
+    }
+}
+
+// Turn the text given for `key` into the JSON its parameter takes
+fn convert(key: &str, value: &str, schema: &Value) -> Result<Value, ToolError> {
+    let kind = types(schema).into_iter().find(|kind| *kind != "null");
+    let invalid = || {
+        ToolError::InvalidParams(format!(
+            "'{}' must be {}, not '{}'",
+            key,
+            type_name(schema),
+            value
+        ))
+    };
+
+    match kind {
+        Some("string") => Ok(Value::String(value.to_string())),
+        Some("integer") => value.parse::<i64>().map(Value::from).map_err(|_| invalid()),
+        Some("number") => value
+            .parse::<f64>()
+            .ok()
+            .and_then(|number| serde_json::Number::from_f64(number).map(Value::Number))
+            .ok_or_else(invalid),
+        Some("boolean") => value
+            .parse::<bool>()
+            .map(Value::Bool)
+            .map_err(|_| invalid()),
+        // Lists of strings can be given as a,b,c instead of JSON
+        Some("array") if !value.trim_start().starts_with('[') => {
+            match schema.get("items").map(types).as_deref() {
+                Some(["string"]) => Ok(Value::Array(
+                    value
+                        .split(',')
+                        .map(|item| Value::String(item.trim().to_string()))
+                        .collect(),
+                )),
+                _ => Err(invalid()),
+            }
+        }
+        Some(_) => serde_json::from_str(value).map_err(|_| invalid()),
+        // Anything else is JSON if it parses, and text if not
+        None => {
+            Ok(serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string())))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::registry::ToolSet;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn tool(name: &str) -> Tool {
+        ToolSet::with_defaults(Arc::default())
+            .definition(name)
+            .unwrap()
+    }
+
+    fn pairs(pairs: &[&str]) -> Vec<String> {
+        pairs.iter().map(|pair| pair.to_string()).collect()
+    }
+
+    #[test]
+    fn test_pairs_are_converted_by_the_schema() {
+        let bash = tool("bash");
+        assert_eq!(
+            arguments_from_pairs(&bash, &pairs(&["command=echo a=b", "timeout=5000"])).unwrap(),
+            json!({ "command": "echo a=b", "timeout": 5000 })
+        );
+
+        let file = tool("file");
+        assert_eq!(
+            arguments_from_pairs(
+                &file,
+                &pairs(&[
+                    "operation=read",
+                    "path=src/main.rs",
+                    "offset=10",
+                    "force=true"
+                ])
+            )
+            .unwrap(),
+            json!({ "operation": "read", "path": "src/main.rs", "offset": 10, "force": true })
+        );
+
+        let ls = tool("ls");
+        assert_eq!(
+            arguments_from_pairs(&ls, &pairs(&["path=/tmp", "ignore=target, *.log"])).unwrap(),
+            json!({ "path": "/tmp", "ignore": ["target", "*.log"] })
+        );
+        assert_eq!(
+            arguments_from_pairs(&ls, &pairs(&["path=/tmp", r#"ignore=["a,b"]"#])).unwrap(),
+            json!({ "path": "/tmp", "ignore": ["a,b"] })
+        );
+    }
+
+    #[test]
+    fn test_bad_pairs_are_explained() {
+        let bash = tool("bash");
+        let error = |given: &[&str]| {
+            arguments_from_pairs(&bash, &pairs(given))
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(
+            error(&["cmd=ls"]),
+            "bash has no parameter 'cmd'; it takes command, env, idle_timeout, timeout"
+        );
+        assert_eq!(error(&["timeout=5000"]), "bash needs 'command'");
+        assert_eq!(
+            error(&["command=ls", "timeout=soon"]),
+            "'timeout' must be integer, not 'soon'"
+        );
+        assert_eq!(error(&["command"]), "Expected key=value, got 'command'");
+        assert_eq!(
+            error(&["command=ls", "command=pwd"]),
+            "'command' is given more than once"
+        );
+    }
+
+    #[test]
+    fn test_json_arguments_are_validated() {
+        let bash = tool("bash");
+        assert!(validate(&bash, &json!({ "command": "ls", "timeout": null })).is_ok());
+        assert_eq!(
+            validate(&bash, &json!({ "command": 5 }))
+                .unwrap_err()
+                .to_string(),
+            "'command' must be string, not 5"
+        );
+        assert_eq!(
+            validate(&bash, &json!(["ls"])).unwrap_err().to_string(),
+            "Arguments for bash must be a JSON object"
+        );
+
+        // Edits refer to a definition, left for serde to check
+        let patch = tool("apply_patch");
+        assert!(validate(&patch, &json!({ "edits": [{ "path": "a" }] })).is_ok());
+    }
+
+    #[test]
+    fn test_describe_lists_parameters() {
+        let description = describe(&tool("find_file"));
+        assert!(description.starts_with("find_file\n  "));
+        assert!(description.contains("\n    filename (string, required): The exact name"));
+        assert!(description.contains("\n    offset (integer): Optional."));
+
+        let patch = describe(&tool("apply_patch"));
+        assert!(patch.contains("\n    edits (array of Edit): "));
+    }
+}
@@ -0,0 +1,505 @@
+//! Undoing the file changes tools made in a session.
+//!
+//! Tools record each change in the [`Journal`] shared through the
+//! [`ExecutionPolicy`](crate::tools::policy::ExecutionPolicy), with the
+//! content it replaced. Undoing a change checks the file still looks the way
+//! the tool left it, so edits made outside Sentinel are never overwritten.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::session_file::JournalFile;
+use crate::tools::result::ToolError;
+
+/// Files larger than this are changed without a backup, and can't be undone
+pub const MAX_BACKUP_SIZE: u64 = 10 * 1024 * 1024;
+
+/// What was at a path before a tool changed it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Backup {
+    Missing,
+    Content(Vec<u8>),
+    /// A directory, or a file too large to keep
+    Unavailable,
+}
+
+/// Back up what is at `path` before changing it
+pub fn backup(path: &Path) -> Backup {
+    match fs::metadata(path) {
+        Err(_) => Backup::Missing,
+        Ok(metadata) if metadata.is_file() && metadata.len() <= MAX_BACKUP_SIZE => {
+            fs::read(path).map_or(Backup::Unavailable, Backup::Content)
+        }
+        Ok(_) => Backup::Unavailable,
+    }
+}
+
+/// A change a tool made, with what is needed to reverse it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Operation {
+    /// A file was written; `previous` is None if the write created it
+    Write {
+        path: PathBuf,
+        previous: Option<Vec<u8>>,
+    },
+    Delete {
+        path: PathBuf,
+        previous: Vec<u8>,
+    },
+    Move {
+        source: PathBuf,
+        destination: PathBuf,
+    },
+    /// A file or directory was copied to `destination`, replacing `previous`
+    Copy {
+        destination: PathBuf,
+        previous: Option<Vec<u8>>,
+    },
+    /// Files written or deleted together by one patch, undone together
+    Patch {
+        files: Vec<Entry>,
+    },
+}
+
+impl Operation {
+    // The path whose state the operation left behind; a patch has one per file
+    fn target(&self) -> Option<&Path> {
+        match self {
+            Self::Write { path, .. } | Self::Delete { path, .. } => Some(path),
+            Self::Move { destination, .. } | Self::Copy { destination, .. } => Some(destination),
+            Self::Patch { .. } => None,
+        }
+    }
+
+    // "it was ...", for messages
+    fn past_participle(&self) -> &'static str {
+        match self {
+            Self::Write { previous: None, .. } => "created",
+            Self::Write { .. } => "written",
+            Self::Delete { .. } => "deleted",
+            Self::Move { .. } => "moved there",
+            Self::Copy { .. } => "copied there",
+            Self::Patch { .. } => "patched",
+        }
+    }
+
+    /// What the operation did, e.g. "wrote src/main.rs"
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Write {
+                path,
+                previous: None,
+            } => format!("created {}", path.display()),
+            Self::Write { path, .. } => format!("wrote {}", path.display()),
+            Self::Delete { path, .. } => format!("deleted {}", path.display()),
+            Self::Move {
+                source,
+                destination,
+            } => format!("moved {} to {}", source.display(), destination.display()),
+            Self::Copy { destination, .. } => format!("copied to {}", destination.display()),
+            Self::Patch { files } => {
+                let paths: Vec<String> = files
+                    .iter()
+                    .filter_map(|file| file.operation.target())
+                    .map(|path| path.display().to_string())
+                    .collect();
+                format!("patched {}", paths.join(", "))
+            }
+        }
+    }
+}
+
+// What is at a path, to tell whether it changed since a tool left it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum State {
+    Missing,
+    File(u64),
+    Directory,
+}
+
+impl State {
+    fn of(path: &Path) -> Self {
+        if path.is_dir() {
+            return Self::Directory;
+        }
+        match fs::read(path) {
+            Ok(content) => Self::File(fingerprint(&content)),
+            Err(_) => Self::Missing,
+        }
+    }
+}
+
+// FNV-1a, which stays the same across builds so saved journals can be checked
+fn fingerprint(content: &[u8]) -> u64 {
+    content.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// A recorded operation and the state it left its target in
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub operation: Operation,
+    after: State,
+}
+
+impl Entry {
+    fn new(operation: Operation) -> Self {
+        let after = operation.target().map_or(State::Missing, State::of);
+        Self { operation, after }
+    }
+}
+
+/// The file changes tools made in a session, oldest first
+#[derive(Debug, Default)]
+pub struct Journal {
+    entries: Mutex<Vec<Entry>>,
+    // Bumped on every change, to tell whether the journal needs saving again
+    revision: AtomicUsize,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an operation that just finished
+    pub fn record(&self, operation: Operation) {
+        self.entries.lock().unwrap().push(Entry::new(operation));
+        self.revision.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a write to `path`, which held `before`
+    pub fn record_write(&self, path: &Path, before: Backup) {
+        let path = path.to_path_buf();
+        match before {
+            Backup::Missing => self.record(Operation::Write {
+                path,
+                previous: None,
+            }),
+            Backup::Content(previous) => self.record(Operation::Write {
+                path,
+                previous: Some(previous),
+            }),
+            Backup::Unavailable => {}
+        }
+    }
+
+    /// Record that the file at `path`, which held `before`, was deleted
+    pub fn record_delete(&self, path: &Path, before: Backup) {
+        if let Backup::Content(previous) = before {
+            self.record(Operation::Delete {
+                path: path.to_path_buf(),
+                previous,
+            });
+        }
+    }
+
+    /// Record a copy to `destination`, which held `before`
+    pub fn record_copy(&self, destination: &Path, before: Backup) {
+        let destination = destination.to_path_buf();
+        match before {
+            Backup::Missing => self.record(Operation::Copy {
+                destination,
+                previous: None,
+            }),
+            Backup::Content(previous) => self.record(Operation::Copy {
+                destination,
+                previous: Some(previous),
+            }),
+            Backup::Unavailable => {}
+        }
+    }
+
+    /// Record a patch as one operation, from each file it changed and what
+    /// that file held before. Files too large to back up are left out
+    pub fn record_patch(&self, files: Vec<(PathBuf, Backup)>) {
+        let files = files
+            .into_iter()
+            .filter_map(|(path, before)| {
+                let previous = match before {
+                    Backup::Missing => None,
+                    Backup::Content(previous) => Some(previous),
+                    Backup::Unavailable => return None,
+                };
+                let operation = match previous {
+                    Some(previous) if !path.exists() => Operation::Delete { path, previous },
+                    previous => Operation::Write { path, previous },
+                };
+                Some(Entry::new(operation))
+            })
+            .collect();
+        self.record(Operation::Patch { files });
+    }
+
+    pub fn entries(&self) -> Vec<Entry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().unwrap().is_empty()
+    }
+
+    /// How often the journal changed, e.g. to save it only when it did
+    pub fn revision(&self) -> usize {
+        self.revision.load(Ordering::Relaxed)
+    }
+
+    /// Take over the operations of a saved journal, e.g. when its session is
+    /// continued, so they can be undone
+    pub fn restore(&self, saved: Journal) {
+        *self.entries.lock().unwrap() = saved.entries.into_inner().unwrap();
+        self.revision.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Reverse operation `number`, counting from 1 as listed, or the last one.
+    /// Returns the operation that was undone.
+    pub fn undo(&self, number: Option<usize>) -> Result<Operation, ToolError> {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.is_empty() {
+            return Err(ToolError::NotFound("Nothing to undo".to_string()));
+        }
+
+        let index = match number {
+            Some(number) if (1..=entries.len()).contains(&number) => number - 1,
+            Some(number) => {
+                return Err(ToolError::NotFound(format!(
+                    "No operation {} (the journal has {})",
+                    number,
+                    entries.len()
+                )))
+            }
+            None => entries.len() - 1,
+        };
+
+        revert(&entries[index])?;
+        self.revision.fetch_add(1, Ordering::Relaxed);
+        Ok(entries.remove(index).operation)
+    }
+
+    /// Write the journal to `path`, to be loaded with the session it belongs to
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = JournalFile::new(self.entries()).to_json()?;
+        fs::write(path, json)
+            .with_context(|| format!("Failed to write undo journal '{}'", path.display()))
+    }
+
+    /// Read a journal saved by this or an older version
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read undo journal '{}'", path.display()))?;
+        let file = JournalFile::parse(&json)
+            .with_context(|| format!("Invalid undo journal '{}'", path.display()))?;
+        Ok(Self {
+            entries: Mutex::new(file.entries),
+            revision: AtomicUsize::new(0),
+        })
+    }
+}
+
+// Refuse to undo an operation whose target, or any file of a patch, changed since
+fn check_unchanged(entry: &Entry) -> Result<(), ToolError> {
+    if let Operation::Patch { files } = &entry.operation {
+        return files.iter().try_for_each(check_unchanged);
+    }
+    match entry.operation.target() {
+        Some(target) if State::of(target) != entry.after => Err(ToolError::Unsafe(format!(
+            "'{}' was changed outside Sentinel after it was {}; undoing would lose those changes",
+            target.display(),
+            entry.operation.past_participle()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+// Put back what the operation replaced, unless its target changed since
+fn revert(entry: &Entry) -> Result<(), ToolError> {
+    check_unchanged(entry)?;
+
+    match &entry.operation {
+        Operation::Write { path, previous } => restore(path, previous.as_deref()),
+        Operation::Copy {
+            destination,
+            previous,
+        } => restore(destination, previous.as_deref()),
+        Operation::Delete { path, previous } => restore(path, Some(previous)),
+        Operation::Move {
+            source,
+            destination,
+        } => {
+            if source.exists() {
+                return Err(ToolError::Unsafe(format!(
+                    "'{}' exists again; undoing the move would replace it",
+                    source.display()
+                )));
+            }
+            create_parent(source)?;
+            fs::rename(destination, source).map_err(|e| {
+                ToolError::io(format!("Error moving back '{}'", destination.display()), e)
+            })
+        }
+        // Every file was checked above, so the patch is undone whole or not at all
+        Operation::Patch { files } => files.iter().rev().try_for_each(revert),
+    }
+}
+
+// Write `content` back to `path`, or remove it if there was nothing there
+fn restore(path: &Path, content: Option<&[u8]>) -> Result<(), ToolError> {
+    let error = |e| ToolError::io(format!("Error restoring '{}'", path.display()), e);
+    match content {
+        Some(content) => {
+            create_parent(path)?;
+            fs::write(path, content).map_err(error)
+        }
+        None if path.is_dir() => fs::remove_dir_all(path).map_err(error),
+        None => fs::remove_file(path).map_err(error),
+    }
+}
+
+fn create_parent(path: &Path) -> Result<(), ToolError> {
+    match path.parent() {
+        Some(parent) if !parent.exists() => fs::create_dir_all(parent).map_err(|e| {
+            ToolError::io(
+                format!("Failed to create directory '{}'", parent.display()),
+                e,
+            )
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    // Write `content` to `path` the way the file tool does, recording it
+    fn write(journal: &Journal, path: &Path, content: &str) {
+        let before = backup(path);
+        fs::write(path, content).unwrap();
+        journal.record_write(path, before);
+    }
+
+    #[test]
+    fn test_write_then_undo() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        let journal = Journal::new();
+
+        write(&journal, &path, "first\n");
+        write(&journal, &path, "second\n");
+        assert_eq!(
+            journal.entries()[0].operation.describe(),
+            format!("created {}", path.display())
+        );
+
+        journal.undo(None).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first\n");
+        journal.undo(None).unwrap();
+        assert!(!path.exists());
+        assert!(matches!(journal.undo(None), Err(ToolError::NotFound(_))));
+    }
+
+    #[test]
+    fn test_delete_then_undo() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("data.bin");
+        fs::write(&path, [0u8, 159, 146, 150]).unwrap();
+        let journal = Journal::new();
+
+        let before = backup(&path);
+        fs::remove_file(&path).unwrap();
+        journal.record_delete(&path, before);
+
+        let undone = journal.undo(Some(1)).unwrap();
+        assert!(matches!(undone, Operation::Delete { .. }));
+        assert_eq!(fs::read(&path).unwrap(), [0u8, 159, 146, 150]);
+        assert!(journal.is_empty());
+    }
+
+    #[test]
+    fn test_refuses_after_external_modification() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "a = 1\n").unwrap();
+        let journal = Journal::new();
+        write(&journal, &path, "a = 2\n");
+
+        // Someone else edits the file after the tool
+        fs::write(&path, "a = 3\n").unwrap();
+        let error = journal.undo(None).unwrap_err();
+        assert!(matches!(error, ToolError::Unsafe(_)));
+        assert!(error.to_string().contains("changed outside Sentinel"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a = 3\n");
+        assert_eq!(journal.entries().len(), 1);
+    }
+
+    #[test]
+    fn test_patch_is_undone_whole_or_not_at_all() {
+        let dir = tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.rs"), dir.path().join("b.rs"));
+        fs::write(&a, "fn a() {}\n").unwrap();
+        let journal = Journal::new();
+
+        let backups = vec![(a.clone(), backup(&a)), (b.clone(), backup(&b))];
+        fs::write(&a, "fn a2() {}\n").unwrap();
+        fs::write(&b, "fn b() {}\n").unwrap();
+        journal.record_patch(backups);
+        assert_eq!(
+            journal.entries()[0].operation.describe(),
+            format!("patched {}, {}", a.display(), b.display())
+        );
+
+        // One of the files changes afterwards, so neither is reverted
+        fs::write(&b, "fn b2() {}\n").unwrap();
+        let error = journal.undo(None).unwrap_err();
+        assert!(error.to_string().contains("changed outside Sentinel"));
+        assert_eq!(fs::read_to_string(&a).unwrap(), "fn a2() {}\n");
+
+        fs::write(&b, "fn b() {}\n").unwrap();
+        journal.undo(None).unwrap();
+        assert_eq!(fs::read_to_string(&a).unwrap(), "fn a() {}\n");
+        assert!(!b.exists());
+    }
+
+    #[test]
+    fn test_move_and_numbered_undo() {
+        let dir = tempdir().unwrap();
+        let (source, destination) = (dir.path().join("a.txt"), dir.path().join("b.txt"));
+        let journal = Journal::new();
+        write(&journal, &source, "a\n");
+        fs::rename(&source, &destination).unwrap();
+        journal.record(Operation::Move {
+            source: source.clone(),
+            destination: destination.clone(),
+        });
+        write(&journal, &dir.path().join("c.txt"), "c\n");
+
+        assert!(journal.undo(Some(4)).is_err());
+        journal.undo(Some(2)).unwrap();
+        assert_eq!(fs::read_to_string(&source).unwrap(), "a\n");
+        assert!(!destination.exists());
+        assert_eq!(journal.entries().len(), 2);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        let journal = Journal::new();
+        write(&journal, &path, "kept\n");
+
+        let saved = dir.path().join("journal.json");
+        journal.save(&saved).unwrap();
+        let loaded = Journal::load(&saved).unwrap();
+        assert_eq!(loaded.entries(), journal.entries());
+
+        loaded.undo(None).unwrap();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,500 @@
+//! Reading files by line range.
+//!
+//! The file and find_file tools return small files as they are. A range, or a
+//! file too large to send whole, is returned as numbered lines (`42:text`, the
+//! way `grep -n` prints them) under a header with the file's line count, so
+//! the model can ask for the part it needs and refer to exact lines.
+//!
+//! The file tool answers a file too large to send whole with an overview
+//! instead: its size, its first and last lines, and the line ranges of chunks
+//! that each fit in one read. The chunks only depend on the content, so every
+//! overview of the same file lists the same ones.
+
+use crate::tools::policy;
+use crate::tools::result::ToolError;
+
+/// The most characters a read returns
+pub const MAX_OUTPUT_LENGTH: usize = 30000;
+
+/// The most characters `mode: "full"` returns; larger files are refused
+pub const MAX_FULL_LENGTH: usize = 120_000;
+
+/// How many lines an overview shows from each end of the file
+pub const OVERVIEW_LINES: usize = 20;
+
+// The most characters of a line an overview shows
+const OVERVIEW_LINE_LENGTH: usize = 300;
+
+// The most characters of numbered lines in a chunk, leaving room for the
+// header and notes of the read that returns it
+const CHUNK_LENGTH: usize = MAX_OUTPUT_LENGTH - 500;
+
+/// How a file is read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReadMode {
+    /// A range if one is given, the whole file if it fits, an overview otherwise
+    #[default]
+    Auto,
+    /// The size, first and last lines, and chunks of the file
+    Overview,
+    /// The lines of a range, or as many from the start as fit
+    Range,
+    /// The whole file, up to [`MAX_FULL_LENGTH`] characters
+    Full,
+}
+
+impl ReadMode {
+    /// The mode named by the `mode` parameter, `Auto` when there is none
+    pub fn parse(mode: Option<&str>) -> Result<Self, ToolError> {
+        match mode {
+            None => Ok(Self::Auto),
+            Some("overview") => Ok(Self::Overview),
+            Some("range") => Ok(Self::Range),
+            Some("full") => Ok(Self::Full),
+            Some(other) => Err(ToolError::InvalidParams(format!(
+                "Unknown mode: '{}'. Valid modes are: 'overview', 'range', 'full'",
+                other
+            ))),
+        }
+    }
+}
+
+/// Which lines of a file to read, counting from 1
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LineRange {
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+impl LineRange {
+    pub fn new(offset: Option<usize>, limit: Option<usize>) -> Self {
+        Self { offset, limit }
+    }
+
+    /// Whether no range was asked for
+    pub fn is_whole(&self) -> bool {
+        self.offset.is_none() && self.limit.is_none()
+    }
+}
+
+/// What a read returns from a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Excerpt {
+    pub text: String,
+    /// Whether every line of the file is in `text`
+    pub complete: bool,
+}
+
+/// What a read in `mode` returns from `content`
+pub fn read(content: &str, range: LineRange, mode: ReadMode) -> Result<Excerpt, ToolError> {
+    let ranged = |mode: &str| {
+        Err(ToolError::InvalidParams(format!(
+            "'offset' and 'limit' read a range; leave them out with mode '{}', or use mode 'range'",
+            mode
+        )))
+    };
+    match mode {
+        ReadMode::Auto if range.is_whole() && content.len() > MAX_OUTPUT_LENGTH => {
+            Ok(overview(content))
+        }
+        ReadMode::Auto | ReadMode::Range => excerpt(content, range),
+        ReadMode::Overview if range.is_whole() => Ok(overview(content)),
+        ReadMode::Overview => ranged("overview"),
+        ReadMode::Full if !range.is_whole() => ranged("full"),
+        ReadMode::Full if content.len() > MAX_FULL_LENGTH => {
+            Err(ToolError::InvalidParams(format!(
+                "The file is too large to read whole: {}, over the limit of {} characters. \
+                 Use mode 'overview' to see how it is laid out, then read it in chunks with 'offset' and 'limit'",
+                policy::format_size(content.len()),
+                MAX_FULL_LENGTH
+            )))
+        }
+        ReadMode::Full => Ok(Excerpt {
+            text: content.to_string(),
+            complete: true,
+        }),
+    }
+}
+
+/// The line ranges, from the first line to the last, that split `content`
+/// into chunks each returned whole by one read
+pub fn chunks(content: &str) -> Vec<(usize, usize)> {
+    let mut chunks = Vec::new();
+    let mut first = 1;
+    let mut length = 0;
+    for (index, line) in content.lines().enumerate() {
+        let number = index + 1;
+        let numbered = number.to_string().len() + line.len() + 2;
+        if length > 0 && length + numbered > CHUNK_LENGTH {
+            chunks.push((first, number - 1));
+            first = number;
+            length = 0;
+        }
+        length += numbered;
+    }
+    if length > 0 {
+        chunks.push((first, content.lines().count()));
+    }
+    chunks
+}
+
+/// The size, first and last lines, and chunks of `content`, for a file too
+/// large to read whole
+pub fn overview(content: &str) -> Excerpt {
+    let lines: Vec<&str> = content.lines().collect();
+    let total = lines.len();
+    if total == 0 {
+        return Excerpt {
+            text: "[The file is empty: 0 lines]".to_string(),
+            complete: true,
+        };
+    }
+    let numbered = |(index, line): (usize, &&str)| {
+        let mut numbered = format!("{}:{}", index + 1, line);
+        if numbered.len() > OVERVIEW_LINE_LENGTH {
+            let mut end = OVERVIEW_LINE_LENGTH;
+            while !numbered.is_char_boundary(end) {
+                end -= 1;
+            }
+            numbered.truncate(end);
+            numbered.push_str(" ...");
+        }
+        numbered + "\n"
+    };
+
+    let mut text = format!(
+        "[Overview of a file of {} {} ({}), too large to read whole]\n",
+        total,
+        plural(total),
+        policy::format_size(content.len())
+    );
+    let head = OVERVIEW_LINES.min(total);
+    text.push_str(&format!("[First {} {}]\n", head, plural(head)));
+    text.extend(lines.iter().enumerate().take(head).map(numbered));
+    let tail = OVERVIEW_LINES.min(total - head);
+    if tail > 0 {
+        text.push_str(&format!("[Last {} {}]\n", tail, plural(tail)));
+        text.extend(lines.iter().enumerate().skip(total - tail).map(numbered));
+    }
+
+    // List as many chunks as fit, so the overview itself stays under the cap
+    let chunks = chunks(content);
+    let mut index = String::new();
+    let mut listed = 0;
+    for (first, last) in &chunks {
+        let entry = format!("{}-{}", first, last);
+        if text.len() + index.len() + entry.len() + 400 > MAX_OUTPUT_LENGTH {
+            break;
+        }
+        if !index.is_empty() {
+            index.push_str(", ");
+        }
+        index.push_str(&entry);
+        listed += 1;
+    }
+    text.push_str(&format!(
+        "[{} chunks, each returned whole by one read]\n{}\n",
+        chunks.len(),
+        index
+    ));
+    if listed < chunks.len() {
+        text.push_str(&format!(
+            "[{} more chunks follow from line {}; each read says where to go on]\n",
+            chunks.len() - listed,
+            chunks[listed].0
+        ));
+    }
+    let (first, last) = chunks[chunks.len().min(2) - 1];
+    text.push_str(&format!(
+        "[Read a chunk with offset set to its first line and limit to its line count, \
+         e.g. offset={} and limit={} for the chunk {}-{}]",
+        first,
+        last - first + 1,
+        first,
+        last
+    ));
+    Excerpt {
+        text,
+        complete: false,
+    }
+}
+
+/// The lines of `content` in `range`, numbered, or all of it as is when no
+/// range is given and it fits
+pub fn excerpt(content: &str, range: LineRange) -> Result<Excerpt, ToolError> {
+    if range.is_whole() && content.len() <= MAX_OUTPUT_LENGTH {
+        return Ok(Excerpt {
+            text: content.to_string(),
+            complete: true,
+        });
+    }
+    if range.offset == Some(0) || range.limit == Some(0) {
+        return Err(ToolError::InvalidParams(
+            "'offset' counts lines from 1 and 'limit' must be at least 1".to_string(),
+        ));
+    }
+
+    let lines: Vec<&str> = content.lines().collect();
+    let total = lines.len();
+    if total == 0 {
+        return Ok(Excerpt {
+            text: "[The file is empty: 0 lines]".to_string(),
+            complete: true,
+        });
+    }
+    let first = range.offset.unwrap_or(1);
+    if first > total {
+        return Err(ToolError::InvalidParams(format!(
+            "Line {} is past the end of the file, which has {} {}",
+            first,
+            total,
+            plural(total)
+        )));
+    }
+    let end = range
+        .limit
+        .map_or(total, |limit| (first - 1).saturating_add(limit).min(total));
+
+    // Stop at the last whole line that fits
+    let mut body = String::new();
+    let mut last = first - 1;
+    for (number, line) in (first..=end).zip(&lines[first - 1..end]) {
+        let numbered = format!("{}:{}\n", number, line);
+        if body.len() + numbered.len() > MAX_OUTPUT_LENGTH {
+            if body.is_empty() {
+                // A single line too long to send, as in minified files
+                body = cut_line(&numbered);
+                last = number;
+            }
+            break;
+        }
+        body.push_str(&numbered);
+        last = number;
+    }
+
+    let mut text = format!("[Lines {}-{} of {}]\n{}", first, last, total, body);
+    if last < end {
+        text.push_str(&format!(
+            "[Stopped after line {} to stay under {} characters. Read further with offset={} and a limit, or ask for the lines you need.]",
+            last,
+            MAX_OUTPUT_LENGTH,
+            last + 1
+        ));
+    } else if range.is_whole() {
+        text.push_str("[Ask for the lines you need with offset and limit.]");
+    }
+    Ok(Excerpt {
+        text,
+        complete: first == 1 && last == total,
+    })
+}
+
+fn plural(count: usize) -> &'static str {
+    if count == 1 {
+        "line"
+    } else {
+        "lines"
+    }
+}
+
+// The start of a line too long to send whole
+fn cut_line(line: &str) -> String {
+    let mut end = MAX_OUTPUT_LENGTH;
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!(
+        "{} ... [{} more characters on this line]\n",
+        &line[..end],
+        line.len() - end
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(count: usize) -> String {
+        (1..=count).map(|n| format!("line {}\n", n)).collect()
+    }
+
+    #[test]
+    fn test_ranges_are_numbered_under_a_header() {
+        let content = lines(10);
+        let excerpt = excerpt(&content, LineRange::new(Some(4), Some(3))).unwrap();
+        assert_eq!(
+            excerpt.text,
+            "[Lines 4-6 of 10]\n4:line 4\n5:line 5\n6:line 6\n"
+        );
+        assert!(!excerpt.complete);
+
+        // Small files without a range are returned as they are
+        let whole = super::excerpt(&content, LineRange::default()).unwrap();
+        assert_eq!(whole.text, content);
+        assert!(whole.complete);
+
+        let from_start = super::excerpt(&content, LineRange::new(None, Some(100))).unwrap();
+        assert!(from_start
+            .text
+            .starts_with("[Lines 1-10 of 10]\n1:line 1\n"));
+        assert!(from_start.complete);
+    }
+
+    #[test]
+    fn test_ranges_past_the_end() {
+        let content = lines(10);
+        let error = excerpt(&content, LineRange::new(Some(11), None)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Line 11 is past the end of the file, which has 10 lines"
+        );
+
+        // A limit running past the end stops at the last line
+        let tail = excerpt(&content, LineRange::new(Some(9), Some(50))).unwrap();
+        assert_eq!(tail.text, "[Lines 9-10 of 10]\n9:line 9\n10:line 10\n");
+
+        assert!(excerpt(&content, LineRange::new(Some(0), None)).is_err());
+        assert!(excerpt(&content, LineRange::new(None, Some(0))).is_err());
+    }
+
+    #[test]
+    fn test_empty_files() {
+        let excerpt = excerpt("", LineRange::new(Some(1), Some(20))).unwrap();
+        assert_eq!(excerpt.text, "[The file is empty: 0 lines]");
+        assert!(excerpt.complete);
+    }
+
+    #[test]
+    fn test_large_files_return_their_first_lines() {
+        let content = lines(10000);
+        let excerpt = excerpt(&content, LineRange::default()).unwrap();
+        assert!(excerpt.text.len() <= MAX_OUTPUT_LENGTH + 200);
+        assert!(excerpt.text.starts_with("[Lines 1-"));
+        assert!(excerpt.text.contains(" of 10000]\n1:line 1\n2:line 2\n"));
+        assert!(excerpt
+            .text
+            .ends_with("and a limit, or ask for the lines you need.]"));
+        assert!(!excerpt.complete);
+
+        // One enormous line is cut rather than dropped
+        let minified = "x".repeat(MAX_OUTPUT_LENGTH * 2);
+        let excerpt = super::excerpt(&minified, LineRange::default()).unwrap();
+        assert!(excerpt.text.starts_with("[Lines 1-1 of 1]\n1:xxx"));
+        assert!(excerpt.text.contains("more characters on this line]"));
+    }
+
+    // A log of `count` lines of varying length
+    fn log(count: usize) -> String {
+        (1..=count)
+            .map(|n| {
+                format!(
+                    "{} INFO request {} took {}ms\n",
+                    n,
+                    "x".repeat(n % 37),
+                    n % 900
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_oversized_files_get_an_overview() {
+        let content = log(200_000);
+        let overview = read(&content, LineRange::default(), ReadMode::Auto).unwrap();
+        assert!(!overview.complete);
+        assert!(overview.text.len() <= MAX_OUTPUT_LENGTH);
+        assert!(overview.text.starts_with(&format!(
+            "[Overview of a file of 200000 lines ({}), too large to read whole]\n[First 20 lines]\n1:1 INFO",
+            policy::format_size(content.len())
+        )));
+        assert!(overview.text.contains("\n20:20 INFO"));
+        assert!(!overview.text.contains("\n21:21 INFO"));
+        assert!(overview
+            .text
+            .contains("[Last 20 lines]\n199981:199981 INFO"));
+
+        // The chunks listed are the ones a read returns whole
+        let chunks = chunks(&content);
+        let (first, last) = chunks[1];
+        assert!(overview.text.contains(&format!(
+            "[{} chunks, each returned whole by one read]\n1-{}, {}-{}, ",
+            chunks.len(),
+            first - 1,
+            first,
+            last
+        )));
+        assert!(overview.text.contains("-200000\n[Read a chunk"));
+        let hint = format!("e.g. offset={} and limit={}", first, last - first + 1);
+        assert!(overview
+            .text
+            .ends_with(&format!("{} for the chunk {}-{}]", hint, first, last)));
+        let chunk = read(
+            &content,
+            LineRange::new(Some(first), Some(last - first + 1)),
+            ReadMode::Range,
+        )
+        .unwrap();
+        assert!(chunk
+            .text
+            .starts_with(&format!("[Lines {}-{} of 200000]\n", first, last)));
+        assert!(!chunk.text.contains("Stopped after"));
+
+        // Asking for an overview works for smaller files too
+        let small = read(&lines(3), LineRange::default(), ReadMode::Overview).unwrap();
+        assert!(small
+            .text
+            .contains("[First 3 lines]\n1:line 1\n2:line 2\n3:line 3\n"));
+        assert!(small
+            .text
+            .contains("[1 chunks, each returned whole by one read]\n1-3\n"));
+    }
+
+    #[test]
+    fn test_chunks_are_stable_and_cover_the_file() {
+        let content = log(20_000);
+        let chunks = chunks(&content);
+        assert_eq!(chunks, super::chunks(&content));
+        assert_eq!(chunks.first().map(|chunk| chunk.0), Some(1));
+        assert_eq!(chunks.last().map(|chunk| chunk.1), Some(20_000));
+        for pair in chunks.windows(2) {
+            assert_eq!(pair[0].1 + 1, pair[1].0);
+        }
+        for (first, last) in chunks {
+            let chunk = excerpt(
+                &content,
+                LineRange::new(Some(first), Some(last - first + 1)),
+            )
+            .unwrap();
+            assert!(chunk.text.len() <= MAX_OUTPUT_LENGTH);
+            assert!(!chunk.text.contains("Stopped after"), "{}-{}", first, last);
+        }
+
+        // Changing the end of the file leaves the chunks before it alone
+        let longer = format!("{}{}", content, log(100));
+        assert_eq!(super::chunks(&longer)[..3], super::chunks(&content)[..3]);
+    }
+
+    #[test]
+    fn test_full_reads_are_refused_above_the_hard_limit() {
+        let content = log(1_500);
+        assert!(content.len() > MAX_OUTPUT_LENGTH && content.len() < MAX_FULL_LENGTH);
+        let full = read(&content, LineRange::default(), ReadMode::Full).unwrap();
+        assert_eq!(full.text, content);
+        assert!(full.complete);
+
+        let huge = log(20_000);
+        let error = read(&huge, LineRange::default(), ReadMode::Full).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("The file is too large to read whole"));
+        assert!(error.to_string().contains("Use mode 'overview'"));
+
+        assert!(read(&content, LineRange::new(Some(5), None), ReadMode::Full).is_err());
+        assert!(read(&content, LineRange::new(Some(5), None), ReadMode::Overview).is_err());
+        assert_eq!(ReadMode::parse(Some("range")).unwrap(), ReadMode::Range);
+        assert_eq!(
+            ReadMode::parse(Some("all")).unwrap_err().to_string(),
+            "Unknown mode: 'all'. Valid modes are: 'overview', 'range', 'full'"
+        );
+    }
+}
@@ -1,15 +1,27 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::Metadata;
 use std::path::{Path, PathBuf};
-use std::time::Instant;
-use tokio::fs;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
 
 use anyhow::Result;
-use glob_match;
+use chrono::{DateTime, Local};
+use crate::tools::policy::{self, ExecutionPolicy};
+use crate::tools::result::{ToolError, ToolOutput, ToolResult};
+use crate::tools::walk::WalkRules;
+use crate::truncate::{self, Strategy};
 use ollama_rs::generation::tools::Tool;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 const MAX_OUTPUT_LENGTH: usize = 30000;
 const MAX_LS_FILES: usize = 1000;
+// Upper bound on entries walked, so huge trees still finish quickly
+const MAX_WALK_ENTRIES: usize = 100_000;
+/// How many levels below the listed directory are shown unless a call says otherwise
+pub const DEFAULT_MAX_DEPTH: usize = 3;
 
 #[derive(Deserialize, JsonSchema)]
 pub struct LsParams {
@@ -18,6 +30,21 @@ pub struct LsParams {
     
     #[schemars(description = "List of glob patterns to ignore")]
     ignore: Option<Vec<String>>,
+
+    #[schemars(description = "Show the size, modification time and permissions of each entry")]
+    details: Option<bool>,
+
+    #[schemars(description = "How to order the entries: \"name\" (the default), \"size\" (largest first) or \"mtime\" (most recently modified first)")]
+    sort_by: Option<String>,
+
+    #[schemars(description = "Leave out what the project's .gitignore files ignore (default true)")]
+    respect_gitignore: Option<bool>,
+
+    #[schemars(description = "Names or glob patterns of entries hidden by default to list anyway, e.g. [\"bin\", \".github\"]")]
+    unhide: Option<Vec<String>>,
+
+    #[schemars(description = "How many levels deep to list (default 3); deeper directories are summed up in one line. 0 lists everything")]
+    max_depth: Option<usize>,
 }
 
 #[derive(Serialize)]
@@ -26,6 +53,179 @@ pub struct TreeNode {
     path: String,
     node_type: String, // "file" or "directory"
     children: Vec<TreeNode>,
+    // Size, modification time and permissions, when asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<String>,
+    // What a directory at the depth limit holds, e.g. "… (42 files, 7 dirs)"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+}
+
+// What a directory at the depth limit holds, counted rather than listed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Subtree {
+    files: usize,
+    dirs: usize,
+}
+
+impl fmt::Display for Subtree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |count: usize, one: &str, many: &str| format!("{} {}", count, if count == 1 { one } else { many });
+        write!(f, "… ({}, {})", plural(self.files, "file", "files"), plural(self.dirs, "dir", "dirs"))
+    }
+}
+
+// The entries to list, and the directories at the depth limit whose
+// contents were counted instead, by their path below the listed directory
+struct Listing {
+    files: Vec<String>,
+    collapsed: HashMap<PathBuf, Subtree>,
+    truncated: bool,
+}
+
+/// How the entries of a listing are ordered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortBy {
+    #[default]
+    Name,
+    /// Largest first; a directory is as large as the files listed in it
+    Size,
+    /// Most recently modified first
+    Mtime,
+}
+
+impl SortBy {
+    fn parse(value: Option<&str>) -> Result<Self, ToolError> {
+        match value.map(|value| value.trim().to_lowercase()).as_deref() {
+            None | Some("") | Some("name") => Ok(Self::Name),
+            Some("size") => Ok(Self::Size),
+            Some("mtime") => Ok(Self::Mtime),
+            Some(other) => Err(ToolError::InvalidParams(format!(
+                "Unknown sort_by '{}': use name, size or mtime",
+                other
+            ))),
+        }
+    }
+
+    // Order two entries, by path when they tie so the order is stable
+    fn compare(self, a: &Path, b: &Path, stats: &EntryStats) -> cmp::Ordering {
+        let by_key = match self {
+            Self::Name => cmp::Ordering::Equal,
+            Self::Size => stats.size(b).cmp(&stats.size(a)),
+            Self::Mtime => stats.modified(b).cmp(&stats.modified(a)),
+        };
+        by_key.then_with(|| a.cmp(b))
+    }
+}
+
+// What is known about a listed entry
+struct EntryStat {
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+    // Permission bits, on unix
+    mode: Option<u32>,
+}
+
+impl EntryStat {
+    // e.g. "2.0 KB  2024-05-01 09:30  rw-r--r--"
+    fn describe(&self) -> String {
+        let modified = self
+            .modified
+            .map(|modified| DateTime::<Local>::from(modified).format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let mut details = format!("{}  {}", policy::format_size(self.size as usize), modified);
+        if let Some(mode) = self.mode {
+            details.push_str("  ");
+            details.push_str(&format_mode(mode));
+        }
+        details
+    }
+}
+
+// The listed entries by path, looked up for details and sorting
+struct EntryStats {
+    entries: HashMap<PathBuf, EntryStat>,
+}
+
+impl EntryStats {
+    // Look up the listed paths; directories end in '/'
+    fn collect(paths: &[String]) -> Self {
+        let mut entries = HashMap::new();
+        for path in paths {
+            let is_dir = path.ends_with('/');
+            let key = Self::key(path);
+            let Ok(metadata) = std::fs::symlink_metadata(&key) else {
+                continue;
+            };
+            entries.insert(
+                key,
+                EntryStat {
+                    is_dir,
+                    size: if is_dir { 0 } else { metadata.len() },
+                    modified: metadata.modified().ok(),
+                    mode: permissions(&metadata),
+                },
+            );
+        }
+
+        // A directory is as large as the files listed in it
+        let files: Vec<(PathBuf, u64)> = entries
+            .iter()
+            .filter(|(_, stat)| !stat.is_dir)
+            .map(|(path, stat)| (path.clone(), stat.size))
+            .collect();
+        for (path, size) in files {
+            for ancestor in path.ancestors().skip(1) {
+                if let Some(stat) = entries.get_mut(ancestor) {
+                    stat.size += size;
+                }
+            }
+        }
+
+        Self { entries }
+    }
+
+    fn key(path: &str) -> PathBuf {
+        PathBuf::from(path.trim_end_matches('/'))
+    }
+
+    fn get(&self, path: &Path) -> Option<&EntryStat> {
+        self.entries.get(path)
+    }
+
+    fn size(&self, path: &Path) -> u64 {
+        self.get(path).map_or(0, |stat| stat.size)
+    }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        self.get(path).and_then(|stat| stat.modified)
+    }
+}
+
+#[cfg(unix)]
+fn permissions(metadata: &Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode())
+}
+
+#[cfg(not(unix))]
+fn permissions(_metadata: &Metadata) -> Option<u32> {
+    None
+}
+
+// Permission bits the way `ls -l` shows them, e.g. "rwxr-xr-x"
+fn format_mode(mode: u32) -> String {
+    let flags = ['r', 'w', 'x'];
+    (0..9)
+        .map(|bit| {
+            if mode & (1 << (8 - bit)) != 0 {
+                flags[bit % 3]
+            } else {
+                '-'
+            }
+        })
+        .collect()
 }
 
 #[derive(Serialize)]
@@ -36,12 +236,14 @@ pub struct LsResponseMetadata {
 
 pub struct Ls {
     working_directory: String,
+    policy: Arc<ExecutionPolicy>,
 }
 
 impl Default for Ls {
     fn default() -> Self {
         Self {
             working_directory: String::from("."),
+            policy: Arc::default(),
         }
     }
 }
@@ -51,212 +253,174 @@ impl Ls {
         Self::default()
     }
 
-    fn truncate_output(content: &str) -> String {
-        if content.len() <= MAX_OUTPUT_LENGTH {
-            return content.to_string();
-        }
-
-        let half_length = MAX_OUTPUT_LENGTH / 2;
-        let start = &content[..half_length];
-        let end = &content[content.len() - half_length..];
-
-        // Count truncated lines
-        let middle_content = &content[half_length..content.len() - half_length];
-        let truncated_lines_count = middle_content.chars().filter(|&c| c == '\n').count();
+    /// Leave out what the policy's walk settings say
+    pub fn with_policy(mut self, policy: Arc<ExecutionPolicy>) -> Self {
+        self.policy = policy;
+        self
+    }
 
-        format!(
-            "{}\n\n... [{} lines truncated] ...\n\n{}",
-            start, truncated_lines_count, end
-        )
+    fn truncate_output(content: &str) -> String {
+        truncate::truncate(content, MAX_OUTPUT_LENGTH, Strategy::Middle)
     }
 
     async fn list_directory(
         &self, 
         path: &str, 
-        ignore_patterns: &[String]
-    ) -> Result<(Vec<String>, bool), Box<dyn std::error::Error + Sync + Send>> {
+        rules: WalkRules,
+        max_depth: usize,
+    ) -> Result<Listing, ToolError> {
         let path = Path::new(path);
         
         if !path.exists() {
-            return Err(format!("Error: Path '{}' does not exist", path.display()).into());
+            return Err(ToolError::NotFound(format!("Path '{}' does not exist", path.display())));
         }
         
         if !path.is_dir() {
-            return Err(format!("Error: Path '{}' is not a directory", path.display()).into());
+            return Err(ToolError::InvalidParams(format!("Path '{}' is not a directory", path.display())));
         }
         
-        let mut files = Vec::new();
-        let mut truncated = false;
+        let root = path.to_path_buf();
+        let (files, mut truncated) = tokio::task::spawn_blocking(move || {
+            Self::walk_directory(&root, &rules, MAX_WALK_ENTRIES)
+        })
+        .await
+        .map_err(|e| ToolError::Failed(format!("Directory walk failed: {}", e)))?;
         
-        self.walk_directory(path, ignore_patterns, &mut files, &mut truncated, MAX_LS_FILES).await?;
+        let (mut files, collapsed) = Self::limit_depth(files, path, max_depth);
         
-        Ok((files, truncated))
+        if files.len() > MAX_LS_FILES {
+            files.truncate(MAX_LS_FILES);
+            truncated = true;
+        }
+        
+        Ok(Listing { files, collapsed, truncated })
     }
     
-    async fn walk_directory(
-        &self,
-        path: &Path,
-        ignore_patterns: &[String],
-        files: &mut Vec<String>,
-        truncated: &mut bool,
-        limit: usize
-    ) -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
-        if files.len() >= limit {
-            *truncated = true;
-            return Ok(());
+    // Keep the entries at most `max_depth` levels below `root`, counting the
+    // deeper ones in the directory at the limit they are under; 0 keeps everything
+    fn limit_depth(files: Vec<String>, root: &Path, max_depth: usize) -> (Vec<String>, HashMap<PathBuf, Subtree>) {
+        let mut collapsed: HashMap<PathBuf, Subtree> = HashMap::new();
+        if max_depth == 0 {
+            return (files, collapsed);
         }
-        
-        let mut entries = fs::read_dir(path).await?;
-        
-        while let Some(entry) = entries.next_entry().await? {
-            if files.len() >= limit {
-                *truncated = true;
-                break;
-            }
-            
-            let entry_path = entry.path();
-            
-            if self.should_skip(&entry_path, ignore_patterns) {
+        let mut kept = Vec::new();
+        for file in files {
+            let path = Path::new(&file);
+            let relative = path.strip_prefix(root).unwrap_or(path);
+            if relative.components().count() <= max_depth {
+                kept.push(file);
                 continue;
             }
-            
-            let metadata = entry.metadata().await?;
-            let is_dir = metadata.is_dir();
-            
-            if entry_path != path {
-                let path_str = if is_dir {
-                    format!("{}/", entry_path.to_string_lossy())
-                } else {
-                    entry_path.to_string_lossy().to_string()
-                };
-                files.push(path_str);
-            }
-            
-            if is_dir {
-                // Use Box::pin to handle recursive async calls
-                Box::pin(self.walk_directory(&entry_path, ignore_patterns, files, truncated, limit)).await?;
+            let subtree = collapsed.entry(relative.components().take(max_depth).collect()).or_default();
+            if file.ends_with('/') {
+                subtree.dirs += 1;
+            } else {
+                subtree.files += 1;
             }
         }
-        
-        Ok(())
+        (kept, collapsed)
     }
     
-    fn should_skip(&self, path: &Path, ignore_patterns: &[String]) -> bool {
-        let file_name = path.file_name()
-            .map(|name| name.to_string_lossy().to_string())
-            .unwrap_or_default();
-        
-        // Skip hidden files (starting with .)
-        if file_name != "." && file_name.starts_with(".") {
-            return true;
-        }
-        
-        // Common directories to ignore
-        let common_ignored = [
-            "__pycache__",
-            "node_modules",
-            "dist",
-            "build",
-            "target",
-            "vendor",
-            "bin",
-            "obj",
-            ".git",
-            ".idea",
-            ".vscode",
-            ".DS_Store",
-        ];
-        
-        if common_ignored.contains(&file_name.as_str()) {
-            return true;
-        }
+    // Walk the directory in file name order, collecting at most `limit` entries,
+    // so a capped walk always keeps the same ones. Directories are suffixed with '/'
+    fn walk_directory(root: &Path, rules: &WalkRules, limit: usize) -> (Vec<String>, bool) {
+        let mut files = Vec::new();
         
-        // Common file extensions to ignore
-        let ignored_extensions = [
-            ".pyc", ".pyo", ".pyd", ".so", ".dll", ".exe"
-        ];
+        let walker = rules.walker(root)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build();
         
-        for ext in &ignored_extensions {
-            if file_name.ends_with(ext) {
-                return true;
+        for entry in walker {
+            let Ok(entry) = entry else {
+                continue;
+            };
+            
+            // The root itself is not listed
+            if entry.depth() == 0 {
+                continue;
             }
-        }
-        
-        // Check custom ignore patterns
-        for pattern in ignore_patterns {
-            if glob_match::glob_match(pattern, &file_name) {
-                return true;
+            
+            if files.len() >= limit {
+                return (files, true);
             }
+            
+            let is_dir = entry.file_type().is_some_and(|file_type| file_type.is_dir());
+            let path_str = if is_dir {
+                format!("{}/", entry.path().to_string_lossy())
+            } else {
+                entry.path().to_string_lossy().to_string()
+            };
+            files.push(path_str);
         }
         
-        false
+        (files, false)
     }
     
-    fn create_file_tree(&self, sorted_paths: &[String]) -> Vec<TreeNode> {
+    // Build the directory hierarchy of the listed paths, relative to the listed root
+    fn create_file_tree(&self, sorted_paths: &[String], root_path: &str) -> Vec<TreeNode> {
+        let root_dir = Path::new(root_path);
         let mut root = Vec::new();
-        let mut path_map = std::collections::HashMap::new();
         
         for path_str in sorted_paths {
-            let path = PathBuf::from(path_str);
-            let components: Vec<_> = path.components()
+            let path = Path::new(path_str);
+            let relative = path.strip_prefix(root_dir).unwrap_or(path);
+            let components: Vec<String> = relative.components()
                 .map(|comp| comp.as_os_str().to_string_lossy().to_string())
-                .filter(|s| !s.is_empty())
+                .filter(|s| !s.is_empty() && s != "/")
                 .collect();
             
-            if components.is_empty() {
-                continue;
-            }
-            
-            let mut current_path = String::new();
-            let mut parent_path = String::new();
+            let mut siblings: &mut Vec<TreeNode> = &mut root;
+            let mut current_path = PathBuf::new();
             
             for (i, component) in components.iter().enumerate() {
-                if current_path.is_empty() {
-                    current_path = component.clone();
-                } else {
-                    current_path = format!("{}/{}", current_path, component);
-                }
-                
-                if path_map.contains_key(&current_path) {
-                    parent_path = current_path.clone();
-                    continue;
-                }
+                current_path.push(component);
                 
                 let is_last_part = i == components.len() - 1;
                 let is_dir = !is_last_part || path_str.ends_with('/');
-                let node_type = if is_dir { "directory" } else { "file" };
-                
-                let node = TreeNode {
-                    name: component.clone(),
-                    path: current_path.clone(),
-                    node_type: node_type.to_string(),
-                    children: Vec::new(),
-                };
                 
-                // Clone the node before inserting into path_map
-                let node_for_map = TreeNode {
-                    name: node.name.clone(),
-                    path: node.path.clone(),
-                    node_type: node.node_type.clone(),
-                    children: Vec::new(),
-                };
-                
-                path_map.insert(current_path.clone(), node_for_map);
-                
-                if i > 0 && !parent_path.is_empty() {
-                    if let Some(parent) = path_map.get_mut(&parent_path) {
-                        parent.children.push(node);
+                // Reuse the node if an earlier path already created it
+                let index = match siblings.iter().position(|node| &node.name == component) {
+                    Some(index) => index,
+                    None => {
+                        siblings.push(TreeNode {
+                            name: component.clone(),
+                            path: current_path.to_string_lossy().to_string(),
+                            node_type: if is_dir { "directory" } else { "file" }.to_string(),
+                            children: Vec::new(),
+                            details: None,
+                            summary: None,
+                        });
+                        siblings.len() - 1
                     }
-                } else {
-                    root.push(node);
-                }
+                };
                 
-                parent_path = current_path.clone();
+                siblings = &mut siblings[index].children;
             }
         }
         
         root
     }
+
+    // Order every level of the tree, and add the details of each node if asked for
+    fn arrange_tree(nodes: &mut [TreeNode], root: &Path, sort_by: SortBy, stats: &EntryStats, details: bool) {
+        if sort_by != SortBy::Name {
+            nodes.sort_by(|a, b| sort_by.compare(&root.join(&a.path), &root.join(&b.path), stats));
+        }
+        for node in nodes {
+            if details {
+                node.details = stats.get(&root.join(&node.path)).map(EntryStat::describe);
+            }
+            Self::arrange_tree(&mut node.children, root, sort_by, stats, details);
+        }
+    }
+    
+    // Sum up the directories at the depth limit
+    fn summarize(nodes: &mut [TreeNode], collapsed: &HashMap<PathBuf, Subtree>) {
+        for node in nodes {
+            node.summary = collapsed.get(Path::new(&node.path)).map(Subtree::to_string);
+            Self::summarize(&mut node.children, collapsed);
+        }
+    }
     
     fn print_tree(&self, tree: &[TreeNode], root_path: &str) -> String {
         let mut result = String::new();
@@ -273,13 +437,19 @@ impl Ls {
     fn print_node(&self, builder: &mut String, node: &TreeNode, level: usize) {
         let indent = "  ".repeat(level);
         
-        let node_name = if node.node_type == "directory" {
+        let mut node_name = if node.node_type == "directory" {
             format!("{}/", node.name)
         } else {
             node.name.clone()
         };
+        if let Some(summary) = &node.summary {
+            node_name = format!("{} {}", node_name, summary);
+        }
         
-        builder.push_str(&format!("{}- {}\n", indent, node_name));
+        match &node.details {
+            Some(details) => builder.push_str(&format!("{}- {}  {}\n", indent, node_name, details)),
+            None => builder.push_str(&format!("{}- {}\n", indent, node_name)),
+        }
         
         if node.node_type == "directory" && !node.children.is_empty() {
             for child in &node.children {
@@ -287,6 +457,70 @@ impl Ls {
             }
         }
     }
+    /// List a directory as a flat list followed by a tree view, optionally
+    /// with the details of each entry and ordered by size or modification time
+    pub async fn run(&self, parameters: LsParams) -> ToolResult {
+        let path = parameters.path.trim();
+        let path = if path.is_empty() {
+            &self.working_directory
+        } else {
+            path
+        };
+
+        // The configured walk rules, as the call changes them
+        let rules = self.policy.walk_rules()
+            .respect_gitignore(parameters.respect_gitignore)
+            .unhide(parameters.unhide.unwrap_or_default())
+            .ignore(parameters.ignore.unwrap_or_default());
+        let details = parameters.details.unwrap_or(false);
+        let sort_by = SortBy::parse(parameters.sort_by.as_deref())?;
+        let max_depth = parameters.max_depth.unwrap_or(DEFAULT_MAX_DEPTH);
+        
+        // Start timing the execution
+        let start_time = Instant::now();
+        
+        // List directory contents
+        let Listing { mut files, collapsed, truncated } = self.list_directory(path, rules, max_depth).await?;
+        
+        // Entries are only looked up when their details or order need them
+        let stats = (details || sort_by != SortBy::Name).then(|| EntryStats::collect(&files));
+        if let Some(stats) = &stats {
+            files.sort_by(|a, b| sort_by.compare(&EntryStats::key(a), &EntryStats::key(b), stats));
+        }
+        
+        // For basic output to pass tests (just listing files)
+        let mut simple_output = String::new();
+        for file in &files {
+            let relative = Path::new(file).strip_prefix(path).unwrap_or(Path::new(file));
+            let entry = match collapsed.get(relative) {
+                Some(subtree) => format!("{} {}", file, subtree),
+                None => file.clone(),
+            };
+            match stats.as_ref().filter(|_| details).and_then(|stats| stats.get(&EntryStats::key(file))) {
+                Some(stat) => simple_output.push_str(&format!("{}  {}\n", entry, stat.describe())),
+                None => simple_output.push_str(&format!("{}\n", entry)),
+            }
+        }
+        
+        // Also generate tree output
+        let mut tree = self.create_file_tree(&files, path);
+        if let Some(stats) = &stats {
+            Self::arrange_tree(&mut tree, Path::new(path), sort_by, stats, details);
+        }
+        Self::summarize(&mut tree, &collapsed);
+        let tree_output = self.print_tree(&tree, path);
+        
+        let mut output = simple_output + "\n\nTree View:\n" + &tree_output;
+        
+        if truncated {
+            output = format!(
+                "There are more than {} files in the directory. Use a more specific path or use the Glob tool to find specific files. The first {} files and directories are included below:\n\n{}",
+                MAX_LS_FILES, MAX_LS_FILES, output
+            );
+        }
+        
+        Ok(ToolOutput::new(output).timed(start_time))
+    }
 }
 
 impl Tool for Ls {
@@ -307,18 +541,23 @@ WHEN TO USE THIS TOOL:
 HOW TO USE:
 - Provide a path to list (defaults to current working directory)
 - Optionally specify glob patterns to ignore
+- Set respect_gitignore to false to list what .gitignore hides, and unhide to list entries hidden by default (e.g. [\"bin\"])
+- Set details to true to see each entry's size, modification time and permissions
+- Set sort_by to \"size\" or \"mtime\" to find the largest or most recently modified files first
+- Lists 3 levels deep by default; deeper directories show as one line such as \"components/ … (42 files, 7 dirs)\". Set max_depth to list more or fewer levels, or 0 to list everything
 - Results are displayed in a tree structure
 
 FEATURES:
 - Displays a hierarchical view of files and directories
 - Automatically skips hidden files/directories (starting with '.')
-- Skips common system directories like __pycache__
+- Skips common system directories like __pycache__, unless listed in unhide
+- Skips what the project's .gitignore files ignore, unless respect_gitignore is false
 - Can filter out files matching specific patterns
 
 LIMITATIONS:
 - Results are limited to 1000 files
-- Very large directories will be truncated
-- Does not show file sizes or permissions
+- The size of a directory only counts the files listed in it
+- Very large directories will be truncated, before sorting
 - Cannot recursively list all directories in a large project
 
 TIPS:
@@ -331,60 +570,24 @@ TIPS:
         &mut self,
         parameters: Self::Params,
     ) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
-        // Print colorful message indicating tool is being called
-        println!("\x1b[1;32m[LS TOOL] I am being called with path: {}\x1b[0m", parameters.path);
-        
-        let path = parameters.path.trim();
-        let path = if path.is_empty() {
-            &self.working_directory
-        } else {
-            path
-        };
-
-        // Get ignore patterns or use empty vec if none provided
-        let ignore_patterns = parameters.ignore.unwrap_or_default();
-        
-        // Start timing the execution
         let start_time = Instant::now();
         
-        // List directory contents
-        let result = match self.list_directory(path, &ignore_patterns).await {
-            Ok((files, truncated)) => {
-                // For basic output to pass tests (just listing files)
-                let mut simple_output = String::new();
-                for file in &files {
-                    simple_output.push_str(&format!("{}\n", file));
-                }
-                
-                // Also generate tree output
-                let tree = self.create_file_tree(&files);
-                let tree_output = self.print_tree(&tree, path);
-                
-                let mut output = simple_output + "\n\nTree View:\n" + &tree_output;
-                
-                if truncated {
-                    output = format!(
-                        "There are more than {} files in the directory. Use a more specific path or use the Glob tool to find specific files. The first {} files and directories are included below:\n\n{}",
-                        MAX_LS_FILES, MAX_LS_FILES, output
-                    );
-                }
-                
-                output
-            },
+        // Flatten the result into text for the model
+        let result = match self.run(parameters).await {
+            Ok(output) => output.with_notes(),
             Err(e) => format!("Error listing directory: {}", e),
         };
         
-        // Calculate execution time
-        let execution_time = start_time.elapsed().as_millis();
-        
         // Truncate output if needed
         let truncated_result = Self::truncate_output(&result);
         
-        if truncated_result.is_empty() {
+        if truncated_result.is_empty() && self.policy.verbose_output() {
             Ok(format!(
                 "Directory listing completed in {}ms (no output)",
-                execution_time
+                start_time.elapsed().as_millis()
             ))
+        } else if truncated_result.is_empty() {
+            Ok("Directory listing completed (no output)".to_string())
         } else {
             Ok(truncated_result)
         }
@@ -396,22 +599,30 @@ pub struct LsTool {
     ls: Ls,
 }
 
+impl Default for LsTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl LsTool {
     pub fn new() -> Self {
         Self { ls: Ls::new() }
     }
 
     // Method to list directory contents
-    pub async fn list(&mut self, path: &str, ignore_patterns: Option<Vec<String>>) -> Result<String> {
+    pub async fn list(&mut self, path: &str, ignore_patterns: Option<Vec<String>>) -> ToolResult {
         let params = LsParams {
             path: path.to_string(),
             ignore: ignore_patterns,
+            details: None,
+            sort_by: None,
+            respect_gitignore: None,
+            unhide: None,
+            max_depth: None,
         };
 
-        match self.ls.call(params).await {
-            Ok(output) => Ok(output),
-            Err(e) => Err(anyhow::anyhow!("Failed to list directory: {}", e)),
-        }
+        self.ls.run(params).await
     }
 }
 
@@ -460,7 +671,7 @@ mod tests {
         let (temp_dir, dir_path) = create_temp_dir_with_files().await?;
         
         // Test listing the directory
-        let result = ls_tool.list(&dir_path, None).await?;
+        let result = ls_tool.list(&dir_path, None).await?.content;
         
         // Check that the output contains expected files
         assert!(result.contains("file1.txt"));
@@ -483,7 +694,7 @@ mod tests {
         
         // Test listing the directory with ignore patterns
         let ignore_patterns = vec!["*.tmp".to_string()];
-        let result = ls_tool.list(&dir_path, Some(ignore_patterns)).await?;
+        let result = ls_tool.list(&dir_path, Some(ignore_patterns)).await?.content;
         
         // Check that the output contains expected files but not ignored ones
         assert!(result.contains("file1.txt"));
@@ -504,26 +715,296 @@ mod tests {
         let result = ls_tool.list("/path/that/does/not/exist", None).await;
         
         // Check that we get an error
-        assert!(result.is_err() || result.unwrap().contains("does not exist"));
+        assert!(matches!(result, Err(ToolError::NotFound(_))));
+        
+        // The model sees a stable message
+        let params = LsParams {
+            path: "/path/that/does/not/exist".to_string(),
+            ignore: None,
+            details: None,
+            sort_by: None,
+            respect_gitignore: None,
+            unhide: None,
+            max_depth: None,
+        };
+        let text = Ls::new().call(params).await.unwrap();
+        assert_eq!(text, "Error listing directory: Path '/path/that/does/not/exist' does not exist");
+    }
+    
+    // Create `width` files and `width` subdirectories in every directory down to `depth` levels
+    fn create_tree(dir: &Path, depth: usize, width: usize) -> anyhow::Result<usize> {
+        let mut files = 0;
+        for i in 0..width {
+            std::fs::write(dir.join(format!("file{}.txt", i)), "content")?;
+            files += 1;
+        }
+        if depth > 0 {
+            for i in 0..width {
+                let subdir = dir.join(format!("dir{}", i));
+                create_dir(&subdir)?;
+                files += create_tree(&subdir, depth - 1, width)?;
+            }
+        }
+        Ok(files)
     }
     
     #[tokio::test]
-    async fn test_should_skip() {
+    async fn test_ls_deep_tree_lists_everything_in_order() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let dir_path = dir.path().to_string_lossy().to_string();
+        let file_count = create_tree(dir.path(), 4, 3)?;
+        
+        let ls = Ls::new();
+        let Listing { files, truncated, .. } = ls.list_directory(&dir_path, WalkRules::default(), 0).await.unwrap();
+        
+        // 3 + 9 + 27 + 81 directories plus every file
+        assert!(!truncated);
+        assert_eq!(files.len(), file_count + 120);
+        assert_eq!(files.iter().filter(|f| f.ends_with(".txt")).count(), file_count);
+        
+        // Output is sorted and stable across runs
+        let again = ls.list_directory(&dir_path, WalkRules::default(), 0).await.unwrap().files;
+        assert_eq!(files, again);
+        assert!(files[0].ends_with("/dir0/"));
+        assert!(files[1].ends_with("/dir0/dir0/"));
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_ls_wide_tree_is_capped() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let dir_path = dir.path().to_string_lossy().to_string();
+        for i in 0..(MAX_LS_FILES + 200) {
+            std::fs::write(dir.path().join(format!("file{:05}.txt", i)), "")?;
+        }
+        
+        let ls = Ls::new();
+        let Listing { files, truncated, .. } = ls.list_directory(&dir_path, WalkRules::default(), 0).await.unwrap();
+        
+        assert!(truncated);
+        assert_eq!(files.len(), MAX_LS_FILES);
+        assert!(files[0].ends_with("file00000.txt"));
+        assert!(files[MAX_LS_FILES - 1].ends_with(&format!("file{:05}.txt", MAX_LS_FILES - 1)));
+        Ok(())
+    }
+    
+    #[test]
+    fn test_a_capped_walk_keeps_the_first_entries_by_name() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        for i in (0..50).rev() {
+            std::fs::write(dir.path().join(format!("file{:02}.txt", i)), "")?;
+        }
+        
+        let (files, truncated) = Ls::walk_directory(dir.path(), &WalkRules::default(), 10);
+        assert!(truncated);
+        let names: Vec<String> = (0..10).map(|i| format!("file{:02}.txt", i)).collect();
+        assert_eq!(files.iter().map(|f| f.rsplit('/').next().unwrap().to_string()).collect::<Vec<_>>(), names);
+        assert_eq!(Ls::walk_directory(dir.path(), &WalkRules::default(), 10), (files, true));
+        
+        let (all, truncated) = Ls::walk_directory(dir.path(), &WalkRules::default(), 50);
+        assert!(!truncated);
+        assert_eq!(all.len(), 50);
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_tree_keeps_grandchildren() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let dir_path = dir.path().to_string_lossy().to_string();
+        create_tree(dir.path(), 2, 2)?;
+        
         let ls = Ls::new();
+        let files = ls.list_directory(&dir_path, WalkRules::default(), 0).await.unwrap().files;
+        let tree = ls.create_file_tree(&files, &dir_path);
+        
+        assert_eq!(tree.len(), 4);
+        let dir0 = tree.iter().find(|node| node.name == "dir0").unwrap();
+        assert_eq!(dir0.node_type, "directory");
+        let grandchild = dir0.children.iter().find(|node| node.name == "dir1").unwrap();
+        assert_eq!(grandchild.children.len(), 2);
+        assert_eq!(grandchild.path, "dir0/dir1");
+        
+        let output = ls.print_tree(&tree, &dir_path);
+        assert!(output.contains("  - dir0/\n    - dir0/\n      - file0.txt\n"));
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_deep_directories_are_summed_up_at_the_depth_limit() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let dir_path = dir.path().to_string_lossy().to_string();
+        // 62 files in 30 directories, 5 levels deep
+        let file_count = create_tree(dir.path(), 4, 2)?;
+        let list = |max_depth| LsParams {
+            path: dir_path.clone(),
+            ignore: None,
+            details: None,
+            sort_by: None,
+            respect_gitignore: None,
+            unhide: None,
+            max_depth,
+        };
+
+        // Three levels by default, with the fourth and fifth counted
+        let output = Ls::new().run(list(None)).await?.content;
+        let entries = flat_list(&output, &dir_path);
+        assert!(entries.contains(&"dir0/dir1/dir0/ … (6 files, 2 dirs)".to_string()), "{:?}", entries);
+        assert!(!entries.iter().any(|entry| entry.starts_with("dir0/dir0/dir0/dir0")));
+        assert!(output.contains("\n    - dir1/\n      - dir0/ … (6 files, 2 dirs)\n      - dir1/ … (6 files, 2 dirs)\n"));
+        assert_eq!(output, Ls::new().run(list(Some(DEFAULT_MAX_DEPTH))).await?.content);
+
+        let output = Ls::new().run(list(Some(1))).await?.content;
+        assert_eq!(
+            flat_list(&output, &dir_path),
+            ["dir0/ … (30 files, 14 dirs)", "dir1/ … (30 files, 14 dirs)", "file0.txt", "file1.txt"]
+        );
+        assert!(output.ends_with("  - dir1/ … (30 files, 14 dirs)\n  - file0.txt\n  - file1.txt\n"));
+
+        // 0 lists everything, as before there was a limit
+        let everything = Ls::new().run(list(Some(0))).await?.content;
+        assert_eq!(flat_list(&everything, &dir_path).len(), file_count + 30);
+        assert!(!everything.contains('…'));
+        assert_eq!(everything, Ls::new().run(list(Some(10))).await?.content);
+        Ok(())
+    }
+    
+    // small.txt (10 bytes, newest), big.txt (2000 bytes, oldest) and sub/mid.txt (500 bytes)
+    fn create_sized_files(dir: &Path) -> anyhow::Result<SystemTime> {
+        let now = SystemTime::now();
+        let hour = std::time::Duration::from_secs(60 * 60);
+        create_dir(dir.join("sub"))?;
+        for (name, size, age) in [("small.txt", 10, 1), ("big.txt", 2000, 3), ("sub/mid.txt", 500, 2)] {
+            let path = dir.join(name);
+            std::fs::write(&path, "x".repeat(size))?;
+            std::fs::File::options().append(true).open(&path)?.set_modified(now - hour * age)?;
+        }
+        Ok(now - hour * 3)
+    }
+
+    // The entries of the flat list, without the directory they are in
+    fn flat_list(output: &str, dir: &str) -> Vec<String> {
+        output
+            .split("\n\nTree View:")
+            .next()
+            .unwrap()
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| line.strip_prefix(dir).unwrap().trim_start_matches('/').to_string())
+            .collect()
+    }
+
+    async fn listing(dir: &str, details: Option<bool>, sort_by: Option<&str>) -> anyhow::Result<String> {
+        let params = LsParams {
+            path: dir.to_string(),
+            ignore: None,
+            details,
+            sort_by: sort_by.map(str::to_string),
+            respect_gitignore: None,
+            unhide: None,
+            max_depth: None,
+        };
+        Ok(Ls::new().run(params).await?.content)
+    }
+
+    #[tokio::test]
+    async fn test_details_show_size_time_and_permissions() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let dir_path = dir.path().to_string_lossy().to_string();
+        let oldest = create_sized_files(dir.path())?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(dir.path().join("big.txt"), std::fs::Permissions::from_mode(0o640))?;
+        }
+
+        let output = listing(&dir_path, Some(true), None).await?;
+        let modified = DateTime::<Local>::from(oldest).format("%Y-%m-%d %H:%M").to_string();
+        let big = flat_list(&output, &dir_path).into_iter().find(|line| line.starts_with("big.txt")).unwrap();
+        assert!(big.starts_with(&format!("big.txt  2.0 KB  {}", modified)), "{}", big);
+        #[cfg(unix)]
+        assert!(big.ends_with("  rw-r-----"), "{}", big);
+        // Directories count the files listed in them
+        assert!(output.contains("/sub/  500 B  "));
+        assert!(output.contains("\n  - small.txt  10 B  "));
+        assert!(output.contains("\n    - mid.txt  500 B  "));
+
+        // Without details the listing is as it always was
+        let plain = listing(&dir_path, None, None).await?;
+        assert_eq!(flat_list(&plain, &dir_path), ["big.txt", "small.txt", "sub/", "sub/mid.txt"]);
+        assert!(plain.ends_with(&format!("- {}/\n  - big.txt\n  - small.txt\n  - sub/\n    - mid.txt\n", dir_path)));
+        assert_eq!(format_mode(0o755), "rwxr-xr-x");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sort_by_orders_the_list_and_the_tree() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let dir_path = dir.path().to_string_lossy().to_string();
+        create_sized_files(dir.path())?;
+
+        let by_size = listing(&dir_path, None, Some("size")).await?;
+        assert_eq!(flat_list(&by_size, &dir_path), ["big.txt", "sub/", "sub/mid.txt", "small.txt"]);
+        assert!(by_size.ends_with("  - big.txt\n  - sub/\n    - mid.txt\n  - small.txt\n"));
+
+        let by_mtime = listing(&dir_path, None, Some("MTIME")).await?;
+        let files: Vec<String> = flat_list(&by_mtime, &dir_path)
+            .into_iter()
+            .filter(|entry| entry.ends_with(".txt"))
+            .collect();
+        assert_eq!(files, ["small.txt", "sub/mid.txt", "big.txt"]);
+
+        let by_name = listing(&dir_path, None, Some("name")).await?;
+        assert_eq!(by_name, listing(&dir_path, None, None).await?);
+
+        let error = listing(&dir_path, None, Some("colour")).await.unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(ToolError::InvalidParams(_))));
+        assert_eq!(error.to_string(), "Unknown sort_by 'colour': use name, size or mtime");
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_ls_follows_gitignore_unless_told_not_to() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let dir_path = dir.path().to_string_lossy().to_string();
+        std::fs::write(dir.path().join(".gitignore"), "*.log\n!keep.log\n")?;
+        create_dir(dir.path().join("bin"))?;
+        for file in ["main.rs", "trace.log", "keep.log", "bin/tool.rs"] {
+            std::fs::write(dir.path().join(file), "x")?;
+        }
+        let list = |respect_gitignore, unhide| LsParams {
+            path: dir_path.clone(),
+            ignore: None,
+            details: None,
+            sort_by: None,
+            respect_gitignore,
+            unhide,
+            max_depth: None,
+        };
+        
+        let listed = Ls::new().run(list(None, None)).await?.content;
+        assert!(listed.contains("keep.log") && listed.contains("main.rs"));
+        assert!(!listed.contains("trace.log") && !listed.contains("tool.rs"));
         
+        let listed = Ls::new().run(list(Some(false), Some(vec!["bin".to_string()]))).await?.content;
+        assert!(listed.contains("trace.log") && listed.contains("tool.rs"));
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_should_skip() {
         // Test hidden files
-        assert!(ls.should_skip(&PathBuf::from(".hidden"), &[]));
+        assert!(WalkRules::default().skips(&PathBuf::from(".hidden")));
         
         // Test common ignored directories
-        assert!(ls.should_skip(&PathBuf::from("node_modules"), &[]));
-        assert!(ls.should_skip(&PathBuf::from("__pycache__"), &[]));
+        assert!(WalkRules::default().skips(&PathBuf::from("node_modules")));
+        assert!(WalkRules::default().skips(&PathBuf::from("__pycache__")));
         
         // Test ignored extensions
-        assert!(ls.should_skip(&PathBuf::from("script.pyc"), &[]));
-        assert!(ls.should_skip(&PathBuf::from("binary.exe"), &[]));
+        assert!(WalkRules::default().skips(&PathBuf::from("script.pyc")));
+        assert!(WalkRules::default().skips(&PathBuf::from("binary.exe")));
         
         // Test custom ignore patterns
-        assert!(ls.should_skip(&PathBuf::from("ignored.txt"), &["*.txt".to_string()]));
-        assert!(!ls.should_skip(&PathBuf::from("important.md"), &["*.txt".to_string()]));
+        assert!(WalkRules::default().ignore(["*.txt".to_string()]).skips(&PathBuf::from("ignored.txt")));
+        assert!(!WalkRules::default().ignore(["*.txt".to_string()]).skips(&PathBuf::from("important.md")));
     }
 }
\ No newline at end of file
@@ -0,0 +1,198 @@
+//! Letting the model remember facts across sessions.
+//!
+//! The tool works on the [`Memory`] the conversation was given: it can
+//! remember a fact, recall the facts matching some words, forget one or list
+//! them all. Facts that look like secrets are refused, and the refusal is
+//! sent back to the model rather than ending the turn.
+
+use std::sync::Arc;
+
+use ollama_rs::generation::tools::Tool;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::memory::{Memory, Priority};
+use crate::tools::result::ToolError;
+
+/// What the memory tool is asked to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Remember,
+    Recall,
+    Forget,
+    List,
+}
+
+/// How much a fact matters when they don't all fit in the prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FactPriority {
+    Low,
+    Normal,
+    High,
+}
+
+impl From<FactPriority> for Priority {
+    fn from(priority: FactPriority) -> Self {
+        match priority {
+            FactPriority::Low => Self::Low,
+            FactPriority::Normal => Self::Normal,
+            FactPriority::High => Self::High,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct MemoryParams {
+    #[schemars(description = "remember, recall, forget or list")]
+    operation: Operation,
+
+    #[schemars(
+        description = "Optional: for remember, one short sentence to keep for later sessions; for forget, the fact to forget if there is no id"
+    )]
+    #[serde(default)]
+    fact: Option<String>,
+
+    #[schemars(description = "Optional: for remember, low, normal or high. Defaults to normal")]
+    #[serde(default)]
+    priority: Option<FactPriority>,
+
+    #[schemars(description = "Optional: for recall, words the facts must contain")]
+    #[serde(default)]
+    query: Option<String>,
+
+    #[schemars(description = "Optional: for forget, the id of the fact, as listed")]
+    #[serde(default)]
+    id: Option<u64>,
+}
+
+/// The memory tool, working on the facts of one store
+#[derive(Debug, Clone)]
+pub struct MemoryTool {
+    memory: Arc<Memory>,
+}
+
+impl MemoryTool {
+    pub fn new(memory: Arc<Memory>) -> Self {
+        Self { memory }
+    }
+
+    /// Carry out `params`, explaining what went wrong as an error
+    pub fn run(&self, params: MemoryParams) -> Result<String, ToolError> {
+        let result = match params.operation {
+            Operation::Remember => {
+                let fact = params.fact.as_deref().unwrap_or_default();
+                if fact.trim().is_empty() {
+                    return Err(ToolError::InvalidParams(
+                        "remember needs 'fact'".to_string(),
+                    ));
+                }
+                let priority = params.priority.map_or(Priority::Normal, Priority::from);
+                self.memory
+                    .remember(fact, priority)
+                    .map(|fact| format!("Remembered {}", fact))
+            }
+            Operation::Forget => match (params.id, params.fact.as_deref()) {
+                (Some(id), _) => self.memory.forget(id),
+                (None, Some(fact)) => self.memory.forget_text(fact),
+                (None, None) => {
+                    return Err(ToolError::InvalidParams(
+                        "forget needs 'id' or 'fact'".to_string(),
+                    ));
+                }
+            }
+            .map(|fact| format!("Forgot {}", fact)),
+            Operation::Recall | Operation::List => {
+                let query = match params.operation {
+                    Operation::Recall => params.query.as_deref().unwrap_or_default(),
+                    _ => "",
+                };
+                let facts = self.memory.recall(query);
+                if facts.is_empty() {
+                    return Ok("No facts found".to_string());
+                }
+                let lines: Vec<String> = facts.iter().map(|fact| fact.to_string()).collect();
+                Ok(lines.join("\n"))
+            }
+        };
+        result.map_err(|e| ToolError::InvalidParams(e.to_string()))
+    }
+}
+
+impl Tool for MemoryTool {
+    type Params = MemoryParams;
+
+    fn name() -> &'static str {
+        "memory"
+    }
+
+    fn description() -> &'static str {
+        "Remember facts for later sessions, such as project conventions or the user's preferences, and recall, forget or list them. Remembered facts are shown at the start of each conversation. Never store passwords, keys or tokens."
+    }
+
+    async fn call(
+        &mut self,
+        parameters: Self::Params,
+    ) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
+        // The model sees what went wrong, such as a refused secret, and can carry on
+        Ok(self
+            .run(parameters)
+            .unwrap_or_else(|e| format!("Error: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_memory_tool_operations() -> anyhow::Result<()> {
+        let dir = TempDir::new()?;
+        let memory = Arc::new(Memory::open(&dir.path().join("memory.json"))?);
+        let mut tool = MemoryTool::new(Arc::clone(&memory));
+        let call = |arguments: serde_json::Value| {
+            let params = serde_json::from_value(arguments).unwrap();
+            tool.run(params)
+        };
+
+        assert_eq!(
+            call(
+                json!({ "operation": "remember", "fact": "Use tabs in Makefiles", "priority": "high" })
+            )?,
+            "Remembered #1 [high] Use tabs in Makefiles"
+        );
+        call(json!({ "operation": "remember", "fact": "The CI runs on Fridays" }))?;
+        assert_eq!(
+            call(json!({ "operation": "recall", "query": "makefiles" }))?,
+            "#1 [high] Use tabs in Makefiles"
+        );
+        assert_eq!(
+            call(json!({ "operation": "forget", "fact": "the ci runs on fridays" }))?,
+            "Forgot #2 The CI runs on Fridays"
+        );
+        assert_eq!(
+            call(json!({ "operation": "list" }))?,
+            "#1 [high] Use tabs in Makefiles"
+        );
+
+        let refused = call(json!({ "operation": "remember", "fact": "token=abcdef123456" }));
+        assert!(refused
+            .unwrap_err()
+            .to_string()
+            .contains("looks like a secret"));
+        assert!(call(json!({ "operation": "forget" })).is_err());
+        assert_eq!(memory.facts().len(), 1);
+
+        let output = tool
+            .call(serde_json::from_value(
+                json!({ "operation": "forget", "id": 9 }),
+            )?)
+            .await
+            .unwrap();
+        assert_eq!(output, "Error: No fact #9");
+        Ok(())
+    }
+}
@@ -1,4 +1,29 @@
 pub mod bash;
+pub mod changes;
+pub mod command_rules;
+pub mod custom;
 pub mod file;
+pub mod file_index;
+pub mod filename;
 pub mod find_file_tool;
+pub mod invoke;
+pub mod journal;
+pub mod lines;
 pub mod ls;
+pub mod memory;
+pub mod patch;
+pub mod policy;
+pub mod progress;
+pub mod protected;
+pub mod rate_limit;
+pub mod read_cache;
+pub mod recovery;
+pub mod registry;
+pub mod result;
+pub mod review;
+pub mod schema;
+pub mod shared;
+pub mod summarize;
+pub mod untrusted;
+pub mod walk;
+pub mod weather;
@@ -0,0 +1,872 @@
+//! Changing several files at once, all or nothing.
+//!
+//! The `apply_patch` tool takes a unified diff, or a list of search and
+//! replace edits, checks every hunk against the files as they are now, and
+//! only then writes anything. New contents are staged next to their targets
+//! and renamed into place, so a failure part way puts back what was already
+//! replaced. The whole patch is one journal entry, undone by a single `/undo`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use ollama_rs::generation::tools::Tool;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::tools::journal::{self, MAX_BACKUP_SIZE};
+use crate::tools::policy::{self, ExecutionPolicy};
+use crate::tools::result::{ToolError, ToolOutput, ToolResult};
+use crate::tools::review::{self, Decision, ProposedChange};
+
+/// One search and replace edit
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct Edit {
+    #[schemars(description = "The file to edit")]
+    pub path: String,
+    #[schemars(
+        description = "The exact text to replace, which must appear once in the file; empty to create a new file"
+    )]
+    pub old_string: String,
+    #[schemars(description = "The text to put in its place")]
+    pub new_string: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct PatchParams {
+    #[schemars(
+        description = "A unified diff, as printed by `git diff` or `diff -u`, changing one or more files"
+    )]
+    pub patch: Option<String>,
+    #[schemars(description = "Instead of a diff, edits to make, in order")]
+    pub edits: Option<Vec<Edit>>,
+}
+
+// A hunk of a unified diff: the lines it expects and the lines it leaves
+#[derive(Debug, Clone, PartialEq)]
+struct DiffHunk {
+    header: String,
+    // First old line, counting from 1, or the line before an insertion
+    old_start: usize,
+    old: Vec<String>,
+    new: Vec<String>,
+    // "\ No newline at end of file" after the old or new side's last line
+    old_missing_newline: bool,
+    new_missing_newline: bool,
+}
+
+// One file's part of a unified diff; None stands for /dev/null
+#[derive(Debug, Clone, PartialEq)]
+struct FileDiff {
+    old_path: Option<String>,
+    new_path: Option<String>,
+    hunks: Vec<DiffHunk>,
+}
+
+// What a file holds before the patch and after it, None meaning no file
+#[derive(Debug, Clone)]
+struct PlannedChange {
+    path: PathBuf,
+    old: Option<String>,
+    new: Option<String>,
+}
+
+impl PlannedChange {
+    fn proposed(&self) -> ProposedChange {
+        ProposedChange::new(
+            ApplyPatch::name(),
+            self.path.clone(),
+            self.old.as_deref(),
+            self.new.as_deref().unwrap_or(""),
+        )
+    }
+
+    // "path (+3 -1)", with deletions called out
+    fn summary(&self) -> String {
+        let change = self.proposed();
+        match self.new {
+            None => format!("{} (deleted, -{})", self.path.display(), change.removed()),
+            Some(_) => change.summary(),
+        }
+    }
+}
+
+/// Applies multi-file patches atomically
+#[derive(Default)]
+pub struct ApplyPatch {
+    policy: Arc<ExecutionPolicy>,
+}
+
+impl ApplyPatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use a shared execution policy, for the sandbox, reviews and the journal
+    pub fn with_policy(policy: Arc<ExecutionPolicy>) -> Self {
+        Self { policy }
+    }
+
+    /// Apply a patch, returning a summary of what changed or why nothing did
+    pub async fn run(&self, parameters: PatchParams) -> ToolResult {
+        let started = Instant::now();
+        let changes = match (&parameters.patch, &parameters.edits) {
+            (Some(patch), None) => self.plan_diff(patch)?,
+            (None, Some(edits)) => self.plan_edits(edits)?,
+            (Some(_), Some(_)) => {
+                return Err(ToolError::InvalidParams(
+                    "Pass either 'patch' or 'edits', not both".to_string(),
+                ))
+            }
+            (None, None) => {
+                return Err(ToolError::InvalidParams(
+                    "Pass a unified diff as 'patch', or a list of {path, old_string, new_string} as 'edits'"
+                        .to_string(),
+                ))
+            }
+        };
+        let changes: Vec<PlannedChange> = changes.into_iter().filter(|c| c.old != c.new).collect();
+        if changes.is_empty() {
+            return Ok(ToolOutput::new("The patch changes nothing").timed(started));
+        }
+
+        let summaries: Vec<String> = changes.iter().map(PlannedChange::summary).collect();
+        self.policy
+            .check_writable(&format!("apply_patch to {}", summaries.join(", ")))?;
+        if self.policy.dry_run() {
+            let preview = policy::simulated(&format!("apply a patch to {}", summaries.join(", ")));
+            return Ok(ToolOutput::new(preview).timed(started));
+        }
+
+        self.policy
+            .check_untrusted(&format!("apply_patch to {}", summaries.join(", ")))?;
+
+        // Every file is reviewed before any is written
+        if self.policy.reviews_changes() {
+            for change in &changes {
+                let proposed = change.proposed();
+                if let Decision::Reject { reason } = self.policy.review(&proposed) {
+                    return Ok(ToolOutput::new(format!(
+                        "{}\nNone of the {} files in the patch were changed.",
+                        review::declined(&proposed, reason.as_deref()),
+                        changes.len()
+                    ))
+                    .timed(started));
+                }
+            }
+        }
+
+        let backups: Vec<(PathBuf, journal::Backup)> = changes
+            .iter()
+            .map(|change| (change.path.clone(), journal::backup(&change.path)))
+            .collect();
+        commit(&changes)?;
+        self.policy.journal().record_patch(backups);
+
+        let (added, removed) = changes.iter().fold((0, 0), |(added, removed), change| {
+            let proposed = change.proposed();
+            (added + proposed.added(), removed + proposed.removed())
+        });
+        let mut output = format!(
+            "Applied the patch to {} {} (+{} -{}):",
+            changes.len(),
+            if changes.len() == 1 { "file" } else { "files" },
+            added,
+            removed
+        );
+        for summary in &summaries {
+            output.push_str(&format!("\n- {}", summary));
+        }
+        Ok(ToolOutput::new(output).timed(started))
+    }
+
+    // Check a unified diff against the files, returning what each would become
+    fn plan_diff(&self, patch: &str) -> Result<Vec<PlannedChange>, ToolError> {
+        let mut plan = Plan::default();
+        for diff in parse_diff(patch)? {
+            let name = diff
+                .new_path
+                .as_ref()
+                .or(diff.old_path.as_ref())
+                .ok_or_else(|| {
+                    ToolError::InvalidParams("A file in the patch has no path".to_string())
+                })?;
+            let path = self.target(name)?;
+            let old = plan.current(&path)?;
+
+            let new = match (&old, diff.old_path.is_none()) {
+                (Some(_), true) => {
+                    return Err(mismatch(&format!(
+                        "'{}' already exists, but the patch creates it",
+                        name
+                    )))
+                }
+                (None, false) => {
+                    return Err(mismatch(&format!("'{}' does not exist", name)));
+                }
+                (old, _) => apply_hunks(name, old.as_deref().unwrap_or(""), &diff.hunks)?,
+            };
+
+            if diff.new_path.is_none() {
+                if !new.is_empty() {
+                    return Err(mismatch(&format!(
+                        "the patch deletes '{}' but leaves lines in it",
+                        name
+                    )));
+                }
+                plan.set(path, None);
+            } else {
+                plan.set(path, Some(new));
+            }
+        }
+        Ok(plan.changes())
+    }
+
+    // Check search and replace edits against the files, in order
+    fn plan_edits(&self, edits: &[Edit]) -> Result<Vec<PlannedChange>, ToolError> {
+        let mut plan = Plan::default();
+        for (number, edit) in edits.iter().enumerate() {
+            let path = self.target(&edit.path)?;
+            let new = match (plan.current(&path)?, edit.old_string.is_empty()) {
+                (None, true) => edit.new_string.clone(),
+                (Some(_), true) => {
+                    return Err(mismatch(&format!(
+                        "edit {} has an empty old_string, but '{}' already exists",
+                        number + 1,
+                        edit.path
+                    )))
+                }
+                (None, false) => {
+                    return Err(mismatch(&format!(
+                        "edit {}: '{}' does not exist",
+                        number + 1,
+                        edit.path
+                    )))
+                }
+                (Some(content), false) => match content.matches(&edit.old_string).count() {
+                    1 => content.replacen(&edit.old_string, &edit.new_string, 1),
+                    0 => {
+                        return Err(mismatch(&format!(
+                            "edit {}: old_string was not found in '{}'",
+                            number + 1,
+                            edit.path
+                        )))
+                    }
+                    count => {
+                        return Err(mismatch(&format!(
+                            "edit {}: old_string appears {} times in '{}'; include more surrounding lines",
+                            number + 1,
+                            count,
+                            edit.path
+                        )))
+                    }
+                },
+            };
+            plan.set(path, Some(new));
+        }
+        Ok(plan.changes())
+    }
+
+    // The absolute path of a file the patch changes, if it may be changed
+    fn target(&self, name: &str) -> Result<PathBuf, ToolError> {
+        let path = self.policy.resolve_path(name)?;
+        self.policy.check_protected(&path)?;
+        Ok(path)
+    }
+}
+
+// The files a patch touches, in order, with their contents as the patch goes
+#[derive(Default)]
+struct Plan {
+    order: Vec<PathBuf>,
+    contents: BTreeMap<PathBuf, (Option<String>, Option<String>)>,
+}
+
+impl Plan {
+    // What `path` holds at this point of the patch
+    fn current(&mut self, path: &Path) -> Result<Option<String>, ToolError> {
+        if let Some((_, current)) = self.contents.get(path) {
+            return Ok(current.clone());
+        }
+        let original = read(path)?;
+        self.order.push(path.to_path_buf());
+        self.contents
+            .insert(path.to_path_buf(), (original.clone(), original.clone()));
+        Ok(original)
+    }
+
+    fn set(&mut self, path: PathBuf, content: Option<String>) {
+        if let Some((_, current)) = self.contents.get_mut(&path) {
+            *current = content;
+        }
+    }
+
+    fn changes(mut self) -> Vec<PlannedChange> {
+        self.order
+            .into_iter()
+            .filter_map(|path| {
+                let (old, new) = self.contents.remove(&path)?;
+                Some(PlannedChange { path, old, new })
+            })
+            .collect()
+    }
+}
+
+// The text of a file, or None if there is none
+fn read(path: &Path) -> Result<Option<String>, ToolError> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(None),
+    };
+    if !metadata.is_file() {
+        return Err(ToolError::InvalidParams(format!(
+            "'{}' is not a file",
+            path.display()
+        )));
+    }
+    if metadata.len() > MAX_BACKUP_SIZE {
+        return Err(ToolError::InvalidParams(format!(
+            "'{}' is too large to patch ({})",
+            path.display(),
+            policy::format_size(metadata.len() as usize)
+        )));
+    }
+    fs::read_to_string(path)
+        .map(Some)
+        .map_err(|e| ToolError::io(format!("Error reading '{}'", path.display()), e))
+}
+
+fn mismatch(reason: &str) -> ToolError {
+    ToolError::Failed(format!(
+        "Patch not applied: {}. No files were changed; read the files again and retry.",
+        reason
+    ))
+}
+
+// Split a unified diff into the files it changes
+fn parse_diff(patch: &str) -> Result<Vec<FileDiff>, ToolError> {
+    let mut files: Vec<FileDiff> = Vec::new();
+    let mut lines = patch.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        if let Some(old) = line.strip_prefix("--- ") {
+            let Some(new) = lines.next().and_then(|line| line.strip_prefix("+++ ")) else {
+                return Err(ToolError::InvalidParams(format!(
+                    "Expected a '+++' line after '{}'",
+                    line
+                )));
+            };
+            files.push(FileDiff {
+                old_path: diff_path(old, "a/"),
+                new_path: diff_path(new, "b/"),
+                hunks: Vec::new(),
+            });
+        } else if line.starts_with("@@") {
+            let file = files.last_mut().ok_or_else(|| {
+                ToolError::InvalidParams(format!(
+                    "Hunk '{}' comes before any '---'/'+++' file header",
+                    line
+                ))
+            })?;
+            let (old_start, old_len, new_len) = parse_header(line)?;
+            let mut hunk = DiffHunk {
+                header: line.to_string(),
+                old_start,
+                old: Vec::new(),
+                new: Vec::new(),
+                old_missing_newline: false,
+                new_missing_newline: false,
+            };
+
+            // Take lines until the header's counts are met, or the next header
+            let mut last_side = ' ';
+            while hunk.old.len() < old_len || hunk.new.len() < new_len {
+                let Some(&line) = lines.peek() else {
+                    break;
+                };
+                if line.starts_with("@@") || line.starts_with("--- ") {
+                    break;
+                }
+                lines.next();
+                let (side, text) = match line.chars().next() {
+                    Some(side @ (' ' | '-' | '+' | '\\')) => (side, &line[1..]),
+                    // Some tools drop the space of empty context lines
+                    None => (' ', ""),
+                    Some(_) => {
+                        return Err(ToolError::InvalidParams(format!(
+                            "Unexpected line in hunk '{}': {}",
+                            hunk.header, line
+                        )))
+                    }
+                };
+                match side {
+                    ' ' => {
+                        hunk.old.push(text.to_string());
+                        hunk.new.push(text.to_string());
+                    }
+                    '-' => hunk.old.push(text.to_string()),
+                    '+' => hunk.new.push(text.to_string()),
+                    _ => {}
+                }
+                last_side = side;
+            }
+
+            // The marker follows the last line it applies to
+            if let Some(&marker) = lines.peek() {
+                if marker.starts_with('\\') {
+                    lines.next();
+                    match last_side {
+                        '-' => hunk.old_missing_newline = true,
+                        '+' => hunk.new_missing_newline = true,
+                        _ => {
+                            hunk.old_missing_newline = true;
+                            hunk.new_missing_newline = true;
+                        }
+                    }
+                }
+            }
+            file.hunks.push(hunk);
+        }
+        // Anything else, like "diff --git" or "index" lines, is skipped
+    }
+
+    if files.is_empty() {
+        return Err(ToolError::InvalidParams(
+            "The patch has no '---'/'+++' file headers".to_string(),
+        ));
+    }
+    Ok(files)
+}
+
+// The path in a "---" or "+++" line, without its a/ or b/ prefix or timestamp
+fn diff_path(header: &str, prefix: &str) -> Option<String> {
+    let path = header.split('\t').next().unwrap_or(header).trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    Some(path.strip_prefix(prefix).unwrap_or(path).to_string())
+}
+
+// The old start, old length and new length of "@@ -l,n +l,n @@"
+fn parse_header(header: &str) -> Result<(usize, usize, usize), ToolError> {
+    let invalid = || ToolError::InvalidParams(format!("Invalid hunk header '{}'", header));
+    let ranges = header
+        .strip_prefix("@@ ")
+        .and_then(|rest| rest.split(" @@").next())
+        .ok_or_else(invalid)?;
+    let (old, new) = ranges.split_once(' ').ok_or_else(invalid)?;
+
+    let range = |range: &str, sign: char| -> Option<(usize, usize)> {
+        let range = range.strip_prefix(sign)?;
+        match range.split_once(',') {
+            Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_len) = range(old, '-').ok_or_else(invalid)?;
+    let (_, new_len) = range(new, '+').ok_or_else(invalid)?;
+    Ok((old_start, old_len, new_len))
+}
+
+// Apply the hunks of one file to its content, refusing any that doesn't match
+fn apply_hunks(name: &str, content: &str, hunks: &[DiffHunk]) -> Result<String, ToolError> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut ends_with_newline = content.is_empty() || content.ends_with('\n');
+    let mut result: Vec<&str> = Vec::new();
+    let mut cursor = 0;
+
+    for (number, hunk) in hunks.iter().enumerate() {
+        let at = find_block(&lines, &hunk.old, hunk.old_start, cursor).ok_or_else(|| {
+            mismatch(&format!(
+                "hunk {} ({}) does not match the current content of '{}' near line {}",
+                number + 1,
+                hunk.header,
+                name,
+                hunk.old_start
+            ))
+        })?;
+        result.extend(&lines[cursor..at]);
+        result.extend(hunk.new.iter().map(String::as_str));
+        cursor = at + hunk.old.len();
+
+        if cursor == lines.len() {
+            if hunk.new_missing_newline {
+                ends_with_newline = false;
+            } else if hunk.old_missing_newline || !hunk.new.is_empty() {
+                ends_with_newline = true;
+            }
+        }
+    }
+    result.extend(&lines[cursor..]);
+
+    let mut new = result.join("\n");
+    if ends_with_newline && !result.is_empty() {
+        new.push('\n');
+    }
+    Ok(new)
+}
+
+// Where `block` starts in `lines`, at or after `cursor`: at the line the hunk
+// names if it matches there, else at the first place it does
+fn find_block(lines: &[&str], block: &[String], start: usize, cursor: usize) -> Option<usize> {
+    let matches_at = |at: usize| {
+        at >= cursor
+            && at + block.len() <= lines.len()
+            && block.iter().zip(&lines[at..]).all(|(expected, line)| {
+                expected.trim_end_matches('\r') == line.trim_end_matches('\r')
+            })
+    };
+
+    // An insertion names the line it goes after
+    if block.is_empty() {
+        return Some(start.clamp(cursor, lines.len()));
+    }
+    let expected = start.saturating_sub(1);
+    if matches_at(expected) {
+        return Some(expected);
+    }
+    (cursor..lines.len()).find(|&at| matches_at(at))
+}
+
+// Write every new content next to its target, then rename them into place.
+// If any step fails, what was already replaced is put back
+fn commit(changes: &[PlannedChange]) -> Result<(), ToolError> {
+    let mut staged: Vec<Option<PathBuf>> = Vec::new();
+    for change in changes {
+        let Some(new) = &change.new else {
+            staged.push(None);
+            continue;
+        };
+        let temp = staging_path(&change.path);
+        let written = create_parent(&change.path).and_then(|()| {
+            fs::write(&temp, new)
+                .map_err(|e| ToolError::io(format!("Error writing '{}'", temp.display()), e))
+        });
+        if let Err(e) = written {
+            remove_staged(&staged);
+            let _ = fs::remove_file(&temp);
+            return Err(e);
+        }
+        staged.push(Some(temp));
+    }
+
+    for (index, (change, temp)) in changes.iter().zip(&staged).enumerate() {
+        let result = match temp {
+            Some(temp) => fs::rename(temp, &change.path),
+            None => fs::remove_file(&change.path),
+        };
+        if let Err(e) = result {
+            roll_back(&changes[..index]);
+            remove_staged(&staged[index..]);
+            return Err(ToolError::io(
+                format!(
+                    "Error changing '{}'; the files already changed were put back",
+                    change.path.display()
+                ),
+                e,
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Where the new content of `path` waits before it is renamed into place
+fn staging_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.sentinel-patch", name))
+}
+
+fn create_parent(path: &Path) -> Result<(), ToolError> {
+    match path.parent() {
+        Some(parent) if !parent.exists() => fs::create_dir_all(parent).map_err(|e| {
+            ToolError::io(
+                format!("Failed to create directory '{}'", parent.display()),
+                e,
+            )
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn remove_staged(staged: &[Option<PathBuf>]) {
+    for temp in staged.iter().flatten() {
+        let _ = fs::remove_file(temp);
+    }
+}
+
+// Put back what the patch replaced in files it already changed
+fn roll_back(changes: &[PlannedChange]) {
+    for change in changes.iter().rev() {
+        let _ = match &change.old {
+            Some(old) => fs::write(&change.path, old),
+            None => fs::remove_file(&change.path),
+        };
+    }
+}
+
+impl Tool for ApplyPatch {
+    type Params = PatchParams;
+
+    fn name() -> &'static str {
+        "apply_patch"
+    }
+
+    fn description() -> &'static str {
+        "Change one or more files at once, all or nothing.
+
+Pass either 'patch', a unified diff like `git diff` prints (with '--- a/path' and '+++ b/path' headers, \
+'@@' hunks, and /dev/null for created or deleted files), or 'edits', a list of \
+{path, old_string, new_string} replacements where old_string must appear exactly once \
+(an empty old_string creates the file).
+
+Every hunk is checked against the files first; if any does not match, nothing is written. \
+Prefer this tool over writing whole files when changing parts of existing files, \
+and for refactors spanning several files. Read the files first so the context lines match."
+    }
+
+    async fn call(
+        &mut self,
+        parameters: Self::Params,
+    ) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
+        match self.run(parameters).await {
+            Ok(output) => Ok(output.with_notes()),
+            Err(e) => Ok(format!("Error: {}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn setup() -> (TempDir, Arc<ExecutionPolicy>, ApplyPatch) {
+        let dir = TempDir::new().unwrap();
+        fs::write(
+            dir.path().join("lib.rs"),
+            "mod a;\nmod b;\n\nfn main() {}\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("a.rs"), "pub fn old_name() {}\n").unwrap();
+        let policy = Arc::new(ExecutionPolicy::new());
+        policy.set_sandbox_root(dir.path().to_path_buf());
+        let tool = ApplyPatch::with_policy(Arc::clone(&policy));
+        (dir, policy, tool)
+    }
+
+    fn diff(patch: &str) -> PatchParams {
+        PatchParams {
+            patch: Some(patch.to_string()),
+            edits: None,
+        }
+    }
+
+    const RENAME: &str = "\
+diff --git a/a.rs b/a.rs
+--- a/a.rs
++++ b/a.rs
+@@ -1 +1 @@
+-pub fn old_name() {}
++pub fn new_name() {}
+--- a/lib.rs
++++ b/lib.rs
+@@ -1,4 +1,5 @@
+ mod a;
+ mod b;
++mod c;
+
+ fn main() {}
+--- /dev/null
++++ b/c.rs
+@@ -0,0 +1,2 @@
++// New module
++pub use crate::a::new_name;
+";
+
+    #[tokio::test]
+    async fn test_patch_spans_files_and_undoes_at_once() -> anyhow::Result<()> {
+        let (dir, policy, tool) = setup();
+
+        let output = tool.run(diff(RENAME)).await?;
+        assert_eq!(
+            output.content.lines().next(),
+            Some("Applied the patch to 3 files (+4 -1):")
+        );
+        assert!(output.content.contains("c.rs (new file, +2)"));
+        assert_eq!(
+            fs::read_to_string(dir.path().join("lib.rs"))?,
+            "mod a;\nmod b;\nmod c;\n\nfn main() {}\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.rs"))?,
+            "pub fn new_name() {}\n"
+        );
+        assert!(dir.path().join("c.rs").exists());
+
+        // One journal entry, undone as a whole
+        let journal = policy.journal();
+        assert_eq!(journal.entries().len(), 1);
+        journal.undo(None)?;
+        assert_eq!(
+            fs::read_to_string(dir.path().join("lib.rs"))?,
+            "mod a;\nmod b;\n\nfn main() {}\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.rs"))?,
+            "pub fn old_name() {}\n"
+        );
+        assert!(!dir.path().join("c.rs").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mismatched_hunk_changes_nothing() -> anyhow::Result<()> {
+        let (dir, policy, tool) = setup();
+        let patch = RENAME.replace(" mod b;\n+mod c;", " mod z;\n+mod c;");
+
+        let error = tool.run(diff(&patch)).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Patch not applied: hunk 1 (@@ -1,4 +1,5 @@) does not match"));
+        assert!(error.to_string().contains("No files were changed"));
+
+        // a.rs came first in the patch and matched, but was left alone too
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.rs"))?,
+            "pub fn old_name() {}\n"
+        );
+        assert!(!dir.path().join("c.rs").exists());
+        assert!(policy.journal().is_empty());
+        let leftovers: Vec<_> = fs::read_dir(dir.path())?
+            .flatten()
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .ends_with(".sentinel-patch")
+            })
+            .collect();
+        assert!(leftovers.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_edits() -> anyhow::Result<()> {
+        let (dir, _policy, tool) = setup();
+        let edit = |path: &str, old: &str, new: &str| Edit {
+            path: path.to_string(),
+            old_string: old.to_string(),
+            new_string: new.to_string(),
+        };
+
+        let ambiguous = PatchParams {
+            patch: None,
+            edits: Some(vec![
+                edit("a.rs", "old_name", "new_name"),
+                edit("lib.rs", "mod", "pub mod"),
+            ]),
+        };
+        let error = tool.run(ambiguous).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("edit 2: old_string appears 2 times in 'lib.rs'"));
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.rs"))?,
+            "pub fn old_name() {}\n"
+        );
+
+        let edits = PatchParams {
+            patch: None,
+            edits: Some(vec![
+                edit("a.rs", "old_name", "new_name"),
+                edit("a.rs", "{}", "{ todo!() }"),
+                edit("b.rs", "", "pub fn b() {}\n"),
+            ]),
+        };
+        let output = tool.run(edits).await?;
+        assert!(output.content.starts_with("Applied the patch to 2 files"));
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.rs"))?,
+            "pub fn new_name() { todo!() }\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("b.rs"))?,
+            "pub fn b() {}\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_patches_respect_the_sandbox_and_protected_paths() {
+        let (_dir, policy, tool) = setup();
+        policy.protect("lib.rs");
+
+        let error = tool.run(diff(RENAME)).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("write-protected by the rule 'lib.rs'"));
+
+        let outside = "--- a/../outside.rs\n+++ b/../outside.rs\n@@ -0,0 +1 @@\n+oops\n";
+        let error = tool.run(diff(outside)).await.unwrap_err();
+        assert!(error.to_string().contains("outside the sandbox root"));
+    }
+
+    #[tokio::test]
+    async fn test_read_only_session_refuses_patches_and_edits() {
+        let (dir, policy, tool) = setup();
+        policy.set_read_only();
+
+        let edits = PatchParams {
+            patch: None,
+            edits: Some(vec![Edit {
+                path: "a.rs".to_string(),
+                old_string: "old_name".to_string(),
+                new_string: "new_name".to_string(),
+            }]),
+        };
+        for params in [diff(RENAME), edits] {
+            let error = tool.run(params).await.unwrap_err();
+            assert!(matches!(error, ToolError::Unsafe(_)));
+            assert!(error.to_string().contains("this is a read-only session"));
+        }
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.rs")).unwrap(),
+            "pub fn old_name() {}\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("lib.rs")).unwrap(),
+            "mod a;\nmod b;\n\nfn main() {}\n"
+        );
+        assert!(!dir.path().join("c.rs").exists());
+        assert!(policy.journal().is_empty());
+    }
+
+    #[test]
+    fn test_hunks_tolerate_offsets_and_missing_newlines() {
+        let hunk = |old_start: usize, old: &[&str], new: &[&str]| DiffHunk {
+            header: "@@".to_string(),
+            old_start,
+            old: old.iter().map(|s| s.to_string()).collect(),
+            new: new.iter().map(|s| s.to_string()).collect(),
+            old_missing_newline: false,
+            new_missing_newline: false,
+        };
+
+        // The hunk says line 1, but the file gained a line above it
+        let content = "// header\nfn a() {}\nfn b() {}\n";
+        let patched =
+            apply_hunks("f.rs", content, &[hunk(1, &["fn a() {}"], &["fn a2() {}"])]).unwrap();
+        assert_eq!(patched, "// header\nfn a2() {}\nfn b() {}\n");
+
+        let mut last = hunk(2, &["fn b() {}"], &["fn b() {}", "fn c() {}"]);
+        last.new_missing_newline = true;
+        let patched = apply_hunks("f.rs", content, &[last]).unwrap();
+        assert_eq!(patched, "// header\nfn a() {}\nfn b() {}\nfn c() {}");
+    }
+}
@@ -0,0 +1,33 @@
+//! Output from tools that are still running.
+//!
+//! Long commands report each line as it is printed through the
+//! [`ExecutionPolicy`](crate::tools::policy::ExecutionPolicy), so frontends
+//! can show progress before the tool returns its final output to the model.
+//! Tool calls themselves are reported as [`ToolEvent`]s when they start and end.
+
+use std::time::Duration;
+
+/// Receives output from running tools
+pub trait Progress: Send + Sync {
+    /// A line printed by `tool`, without its trailing newline
+    fn line(&self, tool: &str, line: &str);
+
+    /// `tool` is done and prints no more lines
+    fn finished(&self, _tool: &str) {}
+}
+
+/// A tool call the model made starting or ending, reported as it happens
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolEvent {
+    /// `name` is about to run with `args`
+    Started {
+        name: String,
+        args: serde_json::Value,
+    },
+    /// `name` returned `output` after running for `duration`
+    Finished {
+        name: String,
+        output: String,
+        duration: Duration,
+    },
+}
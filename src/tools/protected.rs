@@ -0,0 +1,234 @@
+//! Parts of the tree the tools may read but never change.
+//!
+//! Rules are glob patterns from the `protected_paths` config key or
+//! `/protect`. Relative patterns are matched against paths relative to the
+//! sandbox root, or the current directory without one; a pattern protects
+//! whatever it matches and everything below it.
+
+use std::fs;
+use std::path::{Component, Path};
+
+use glob_match::glob_match;
+
+// Entries looked at when checking whether a directory holds protected paths
+const MAX_WALKED_ENTRIES: usize = 10_000;
+
+/// The write-protect rules of a session
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProtectedPaths {
+    rules: Vec<String>,
+}
+
+impl ProtectedPaths {
+    pub fn new(rules: Vec<String>) -> Self {
+        Self { rules }
+    }
+
+    pub fn rules(&self) -> &[String] {
+        &self.rules
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Add a rule, unless it is already there
+    pub fn add(&mut self, rule: &str) {
+        if !self.rules.iter().any(|existing| existing == rule) {
+            self.rules.push(rule.to_string());
+        }
+    }
+
+    /// The rule protecting `path`, which must be absolute and normalized,
+    /// with relative rules resolved against `base`
+    pub fn rule_for(&self, base: &Path, path: &Path) -> Option<&str> {
+        let relative = path.strip_prefix(base).ok().map(components);
+        let absolute = components(path);
+
+        self.rules
+            .iter()
+            .find(|rule| {
+                let pattern = rule.trim_end_matches('/');
+                let parts = if Path::new(pattern).is_absolute() {
+                    &absolute
+                } else {
+                    match &relative {
+                        Some(relative) => relative,
+                        None => return false,
+                    }
+                };
+                matches(pattern, parts)
+            })
+            .map(String::as_str)
+    }
+
+    /// The first protected path found under the directory `dir`, with the
+    /// rule protecting it
+    pub fn first_inside(&self, base: &Path, dir: &Path) -> Inside<'_> {
+        self.first_within(base, dir, MAX_WALKED_ENTRIES)
+    }
+
+    // `first_inside`, looking at no more than `limit` entries
+    fn first_within(&self, base: &Path, dir: &Path, limit: usize) -> Inside<'_> {
+        let mut pending = vec![dir.to_path_buf()];
+        let mut walked = 0;
+        while let Some(current) = pending.pop() {
+            let Ok(entries) = fs::read_dir(&current) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                walked += 1;
+                if walked > limit {
+                    return Inside::TooManyEntries;
+                }
+
+                let path = entry.path();
+                if let Some(rule) = self.rule_for(base, &path) {
+                    return Inside::Protected(path.display().to_string(), rule);
+                }
+                if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                    pending.push(path);
+                }
+            }
+        }
+        Inside::Nothing
+    }
+}
+
+/// What walking a directory for protected paths found
+#[derive(Debug, PartialEq, Eq)]
+pub enum Inside<'a> {
+    Nothing,
+    /// A protected path, with the rule protecting it
+    Protected(String, &'a str),
+    /// The directory held too many entries to look at them all
+    TooManyEntries,
+}
+
+// The names making up a path, with "/" for the root of an absolute one
+fn components(path: &Path) -> Vec<String> {
+    path.components()
+        .filter_map(|component| match component {
+            Component::RootDir => Some(String::new()),
+            Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+// Whether `pattern` matches the path made of `parts` or one of its parents
+fn matches(pattern: &str, parts: &[String]) -> bool {
+    // "dir/**" also covers the directory itself
+    let directory = pattern.strip_suffix("/**");
+    // A bare name like "Cargo.lock" or "generated" matches at any depth
+    let bare = !pattern.contains('/');
+
+    (1..=parts.len()).any(|end| {
+        let prefix = parts[..end].join("/");
+        let prefix = if prefix.is_empty() { "/" } else { &prefix };
+        glob_match(pattern, prefix)
+            || directory.is_some_and(|directory| glob_match(directory, prefix))
+            || (bare && glob_match(pattern, &parts[end - 1]))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn rule_for<'a>(rules: &'a ProtectedPaths, path: &str) -> Option<&'a str> {
+        rules.rule_for(
+            Path::new("/work/app"),
+            &PathBuf::from("/work/app").join(path),
+        )
+    }
+
+    #[test]
+    fn test_rules_match_nested_paths() {
+        let rules = ProtectedPaths::new(vec![
+            "migrations/".to_string(),
+            "Cargo.lock".to_string(),
+            "src/**/generated/*.rs".to_string(),
+            "/etc/**".to_string(),
+        ]);
+
+        assert_eq!(rule_for(&rules, "migrations"), Some("migrations/"));
+        assert_eq!(
+            rule_for(&rules, "migrations/2024/01_init.sql"),
+            Some("migrations/")
+        );
+        assert_eq!(rule_for(&rules, "Cargo.lock"), Some("Cargo.lock"));
+        assert_eq!(
+            rule_for(&rules, "crates/core/Cargo.lock"),
+            Some("Cargo.lock")
+        );
+        assert_eq!(
+            rule_for(&rules, "src/api/v1/generated/types.rs"),
+            Some("src/**/generated/*.rs")
+        );
+        assert_eq!(rule_for(&rules, "src/api/generated.rs"), None);
+        assert_eq!(rule_for(&rules, "db/migrations.rs"), None);
+        assert_eq!(rule_for(&rules, "Cargo.toml"), None);
+
+        // Absolute rules apply anywhere, relative ones only under the base
+        assert_eq!(
+            rules.rule_for(Path::new("/work/app"), Path::new("/etc/hosts")),
+            Some("/etc/**")
+        );
+        assert_eq!(
+            rules.rule_for(Path::new("/work/app"), Path::new("/tmp/migrations/a.sql")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_directory_rules_cover_their_contents() {
+        let rules = ProtectedPaths::new(vec!["vendor/**".to_string()]);
+        assert_eq!(rule_for(&rules, "vendor"), Some("vendor/**"));
+        assert_eq!(rule_for(&rules, "vendor/a/b.rs"), Some("vendor/**"));
+        assert_eq!(rule_for(&rules, "vendored.rs"), None);
+    }
+
+    #[test]
+    fn test_protected_paths_inside_a_directory() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir_all(dir.path().join("db/migrations"))?;
+        fs::write(dir.path().join("db/migrations/01.sql"), "create table")?;
+        fs::write(dir.path().join("db/seed.sql"), "insert")?;
+
+        let rules = ProtectedPaths::new(vec!["**/migrations/*.sql".to_string()]);
+        let Inside::Protected(path, rule) = rules.first_inside(dir.path(), &dir.path().join("db"))
+        else {
+            panic!("the migration was not found");
+        };
+        assert!(path.ends_with("01.sql"));
+        assert_eq!(rule, "**/migrations/*.sql");
+
+        fs::remove_file(dir.path().join("db/migrations/01.sql"))?;
+        assert_eq!(
+            rules.first_inside(dir.path(), &dir.path().join("db")),
+            Inside::Nothing
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_directories_too_large_to_walk_are_not_cleared() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        for i in 0..5 {
+            fs::write(dir.path().join(format!("{}.txt", i)), "")?;
+        }
+
+        let rules = ProtectedPaths::new(vec!["Cargo.lock".to_string()]);
+        assert_eq!(
+            rules.first_within(dir.path(), dir.path(), 4),
+            Inside::TooManyEntries
+        );
+        assert_eq!(
+            rules.first_within(dir.path(), dir.path(), 5),
+            Inside::Nothing
+        );
+        Ok(())
+    }
+}
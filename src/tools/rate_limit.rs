@@ -0,0 +1,318 @@
+//! Keeping the model from hammering network tools.
+//!
+//! [`RateLimited`] wraps a tool with a token bucket per tool name, shared
+//! through a [`RateLimiter`], and answers identical calls made in the same
+//! turn from memory instead of fetching again.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use ollama_rs::generation::tools::Tool;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::de::{DeserializeOwned, Error as _};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+
+/// The `[tools.rate_limit]` config table
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RateLimit {
+    /// Calls per minute each network tool may make on average
+    pub requests_per_minute: f64,
+    /// Calls that may be made back to back before the average applies
+    pub burst: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: 10.0,
+            burst: 3,
+        }
+    }
+}
+
+// Calls a tool may still make, refilled over time up to the burst size
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn full(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: f64::from(limit.burst),
+            updated: now,
+        }
+    }
+
+    fn take(&mut self, limit: &RateLimit, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * limit.requests_per_minute / 60.0).min(f64::from(limit.burst));
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Rate limits and the memo of the current turn, shared by the rate-limited tools
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    limit: Mutex<RateLimit>,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+    // Output of each call made this turn, by tool name and JSON arguments
+    memo: Mutex<HashMap<(String, String), String>>,
+    suppressed: AtomicUsize,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit: Mutex::new(limit),
+            ..Self::default()
+        }
+    }
+
+    pub fn shared(limit: RateLimit) -> Arc<Self> {
+        Arc::new(Self::new(limit))
+    }
+
+    pub fn set_limit(&self, limit: RateLimit) {
+        *self.limit.lock().unwrap() = limit;
+    }
+
+    /// Forget the calls of the last turn, so new ones are fetched again
+    pub fn start_turn(&self) {
+        self.memo.lock().unwrap().clear();
+    }
+
+    /// Whether `tool` may make a call at `now`, using up one of its tokens if so
+    pub fn try_acquire(&self, tool: &str, now: Instant) -> bool {
+        let limit = *self.limit.lock().unwrap();
+        let allowed = self
+            .buckets
+            .lock()
+            .unwrap()
+            .entry(tool.to_string())
+            .or_insert_with(|| TokenBucket::full(&limit, now))
+            .take(&limit, now);
+        if !allowed {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+        }
+        allowed
+    }
+
+    /// Calls refused since the last time this was asked
+    pub fn take_suppressed(&self) -> usize {
+        self.suppressed.swap(0, Ordering::Relaxed)
+    }
+
+    fn recall(&self, tool: &str, arguments: &str) -> Option<String> {
+        self.memo
+            .lock()
+            .unwrap()
+            .get(&(tool.to_string(), arguments.to_string()))
+            .cloned()
+    }
+
+    fn remember(&self, tool: &str, arguments: String, output: String) {
+        self.memo
+            .lock()
+            .unwrap()
+            .insert((tool.to_string(), arguments), output);
+    }
+
+    /// Tool output telling the model a call was refused
+    pub fn refusal(&self, tool: &str) -> String {
+        let limit = self.limit.lock().unwrap();
+        format!(
+            "[RATE LIMITED] The {} tool was called too often (at most {} calls a minute, {} in a row), \
+             so this call was not made. Try again later or refine your query instead of repeating it.",
+            tool, limit.requests_per_minute, limit.burst
+        )
+    }
+}
+
+/// A tool's parameters along with the JSON they were parsed from, so calls
+/// can be compared without the parameters implementing `Serialize`
+pub struct Arguments<P> {
+    json: Value,
+    params: P,
+}
+
+impl<'de, P: DeserializeOwned> Deserialize<'de> for Arguments<P> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let json = Value::deserialize(deserializer)?;
+        let params = serde_json::from_value(json.clone()).map_err(D::Error::custom)?;
+        Ok(Self { json, params })
+    }
+}
+
+impl<P: JsonSchema> JsonSchema for Arguments<P> {
+    fn schema_name() -> String {
+        P::schema_name()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        P::json_schema(generator)
+    }
+
+    fn is_referenceable() -> bool {
+        P::is_referenceable()
+    }
+}
+
+/// A tool whose calls go through a [`RateLimiter`]
+pub struct RateLimited<T> {
+    tool: T,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<T: Tool> RateLimited<T> {
+    pub fn new(tool: T, limiter: Arc<RateLimiter>) -> Self {
+        Self { tool, limiter }
+    }
+}
+
+impl<T> Tool for RateLimited<T>
+where
+    T: Tool,
+    T::Params: Send + Sync,
+{
+    type Params = Arguments<T::Params>;
+
+    fn name() -> &'static str {
+        T::name()
+    }
+
+    fn description() -> &'static str {
+        T::description()
+    }
+
+    async fn call(
+        &mut self,
+        arguments: Self::Params,
+    ) -> ollama_rs::generation::tools::Result<String> {
+        let key = arguments.json.to_string();
+        if let Some(output) = self.limiter.recall(T::name(), &key) {
+            return Ok(output);
+        }
+        if !self.limiter.try_acquire(T::name(), Instant::now()) {
+            return Ok(self.limiter.refusal(T::name()));
+        }
+
+        let output = self.tool.call(arguments.params).await?;
+        self.limiter.remember(T::name(), key, output.clone());
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[derive(Deserialize, JsonSchema)]
+    struct Query {
+        query: String,
+    }
+
+    // Counts the searches it is asked to make
+    struct Search(Arc<AtomicUsize>);
+
+    impl Tool for Search {
+        type Params = Query;
+
+        fn name() -> &'static str {
+            "search"
+        }
+
+        fn description() -> &'static str {
+            "Search the web"
+        }
+
+        async fn call(
+            &mut self,
+            parameters: Query,
+        ) -> ollama_rs::generation::tools::Result<String> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(format!("results for {}", parameters.query))
+        }
+    }
+
+    fn query(text: &str) -> Arguments<Query> {
+        serde_json::from_value(serde_json::json!({ "query": text })).unwrap()
+    }
+
+    #[test]
+    fn test_token_bucket_refills() {
+        let limit = RateLimit {
+            requests_per_minute: 60.0,
+            burst: 2,
+        };
+        let limiter = RateLimiter::new(limit);
+        let start = Instant::now();
+
+        assert!(limiter.try_acquire("search", start));
+        assert!(limiter.try_acquire("search", start));
+        assert!(!limiter.try_acquire("search", start));
+        // Other tools have buckets of their own
+        assert!(limiter.try_acquire("weather", start));
+
+        // One call a second comes back, but never more than the burst
+        assert!(limiter.try_acquire("search", start + Duration::from_secs(1)));
+        assert!(!limiter.try_acquire("search", start + Duration::from_secs(1)));
+        assert!(limiter.try_acquire("search", start + Duration::from_secs(60)));
+        assert!(limiter.try_acquire("search", start + Duration::from_secs(60)));
+        assert!(!limiter.try_acquire("search", start + Duration::from_secs(60)));
+        assert_eq!(limiter.take_suppressed(), 3);
+        assert_eq!(limiter.take_suppressed(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_calls_are_suppressed_and_memoized() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let limiter = RateLimiter::shared(RateLimit {
+            requests_per_minute: 1.0,
+            burst: 2,
+        });
+        let mut search = RateLimited::new(Search(Arc::clone(&calls)), Arc::clone(&limiter));
+
+        assert_eq!(
+            search.call(query("rust")).await.unwrap(),
+            "results for rust"
+        );
+        // The same call again this turn is answered from memory
+        assert_eq!(
+            search.call(query("rust")).await.unwrap(),
+            "results for rust"
+        );
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+        assert_eq!(
+            search.call(query("tokio")).await.unwrap(),
+            "results for tokio"
+        );
+        let refused = search.call(query("serde")).await.unwrap();
+        assert!(refused.starts_with("[RATE LIMITED] The search tool was called too often"));
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+        assert_eq!(limiter.take_suppressed(), 1);
+
+        // A new turn fetches again, which the bucket refuses
+        limiter.start_turn();
+        let refused = search.call(query("rust")).await.unwrap();
+        assert!(refused.starts_with("[RATE LIMITED]"));
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use chrono::{DateTime, Local};
+
+use crate::tools::result::ToolOutput;
+
+/// How many files are remembered before the least recently read is forgotten
+const MAX_ENTRIES: usize = 512;
+
+/// Files the model has already read in this conversation.
+///
+/// The file and find_file tools share one cache so a file that has not changed
+/// since it was last read is not sent to the model again. Only the path, size
+/// and modification time are kept, never the content.
+#[derive(Debug, Default)]
+pub struct ReadCache {
+    entries: Mutex<HashMap<PathBuf, Entry>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    modified: Option<SystemTime>,
+    size: u64,
+    read_at: DateTime<Local>,
+}
+
+/// How a file compares to the last time it was read
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Freshness {
+    /// Not read before in this conversation
+    New,
+    /// Same size and modification time as when it was read
+    Unchanged { read_at: DateTime<Local> },
+    /// Modified since it was read
+    Changed { read_at: DateTime<Local> },
+}
+
+impl ReadCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a cache to hand to several tools
+    pub fn shared() -> Arc<Self> {
+        Arc::new(Self::new())
+    }
+
+    /// Compare the file at `path` with the last read of it
+    pub fn check(&self, path: &Path, metadata: &Metadata) -> Freshness {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(&key(path)) {
+            None => Freshness::New,
+            Some(entry) if entry.matches(metadata) => Freshness::Unchanged {
+                read_at: entry.read_at,
+            },
+            Some(entry) => Freshness::Changed {
+                read_at: entry.read_at,
+            },
+        }
+    }
+
+    /// Remember that the file at `path` was just read
+    pub fn record(&self, path: &Path, metadata: &Metadata) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.read_at)
+                .map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(
+            key(path),
+            Entry {
+                modified: metadata.modified().ok(),
+                size: metadata.len(),
+                read_at: Local::now(),
+            },
+        );
+    }
+
+    /// Forget every read, e.g. when the conversation is cleared
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Entry {
+    fn matches(&self, metadata: &Metadata) -> bool {
+        self.size == metadata.len() && self.modified == metadata.modified().ok()
+    }
+}
+
+/// Output standing in for the content of a file the model has already seen
+pub fn unchanged(path: &Path, read_at: DateTime<Local>) -> ToolOutput {
+    ToolOutput::new(format!(
+        "File '{}' unchanged since last read at {}, content omitted (use force=true to re-read)",
+        path.display(),
+        read_at.format("%H:%M:%S")
+    ))
+}
+
+/// Note added to the content of a file that changed since it was read
+pub fn changed_note(read_at: DateTime<Local>) -> String {
+    format!(
+        "The file changed since it was last read at {}",
+        read_at.format("%H:%M:%S")
+    )
+}
+
+// The same file reached through different relative paths shares one entry
+fn key(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_tracks_changes() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "one")?;
+
+        let cache = ReadCache::new();
+        assert_eq!(cache.check(&path, &path.metadata()?), Freshness::New);
+
+        cache.record(&path, &path.metadata()?);
+        assert!(matches!(
+            cache.check(&path, &path.metadata()?),
+            Freshness::Unchanged { .. }
+        ));
+
+        std::fs::write(&path, "one and two")?;
+        assert!(matches!(
+            cache.check(&path, &path.metadata()?),
+            Freshness::Changed { .. }
+        ));
+
+        cache.clear();
+        assert!(cache.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_size_is_capped() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "one")?;
+        let metadata = path.metadata()?;
+
+        let cache = ReadCache::new();
+        for i in 0..MAX_ENTRIES + 10 {
+            cache.record(&dir.path().join(format!("missing-{}", i)), &metadata);
+        }
+        assert_eq!(cache.len(), MAX_ENTRIES);
+        Ok(())
+    }
+}
@@ -0,0 +1,360 @@
+//! Recovering from tool calls whose arguments don't fit the tool.
+//!
+//! Models often get the arguments of a call slightly wrong: JSON wrapped in a
+//! code fence or sent as a string, arguments nested under "properties", a
+//! number in quotes or a field left out. Common wrappers are taken off before
+//! giving up; arguments that still don't fit are answered with a message
+//! naming what is wrong and the parameters the tool takes, so the model can
+//! call it again in the same turn. Only after too many malformed calls in a
+//! row does the turn fail.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use ollama_rs::generation::tools::Tool;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+
+use crate::llm::ollama;
+use crate::tools::invoke;
+use crate::tools::result::{self, ToolError};
+
+/// Malformed calls in a row after which a turn fails, unless configured otherwise
+pub const DEFAULT_MAX_MALFORMED: usize = 3;
+
+// Keys models nest the real arguments under
+const WRAPPER_KEYS: [&str; 3] = ["properties", "arguments", "parameters"];
+
+// How many wrappers are taken off, at most
+const MAX_UNWRAP_DEPTH: usize = 3;
+
+/// Counts malformed calls for the tools of a conversation, deciding when to
+/// give up on them
+#[derive(Debug)]
+pub struct Recovery {
+    max_malformed: AtomicUsize,
+    // Malformed calls since the last one that fit
+    consecutive: AtomicUsize,
+    // Malformed calls that did not end the turn, since the last time this was asked
+    recovered: AtomicUsize,
+}
+
+impl Default for Recovery {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_MALFORMED)
+    }
+}
+
+impl Recovery {
+    pub fn new(max_malformed: usize) -> Self {
+        Self {
+            max_malformed: AtomicUsize::new(max_malformed.max(1)),
+            consecutive: AtomicUsize::new(0),
+            recovered: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn shared(max_malformed: usize) -> Arc<Self> {
+        Arc::new(Self::new(max_malformed))
+    }
+
+    pub fn set_max_malformed(&self, max_malformed: usize) {
+        self.max_malformed
+            .store(max_malformed.max(1), Ordering::Relaxed);
+    }
+
+    /// Forget the malformed calls of the last turn
+    pub fn start_turn(&self) {
+        self.consecutive.store(0, Ordering::Relaxed);
+    }
+
+    /// Malformed calls recovered from since the last time this was asked
+    pub fn take_recovered(&self) -> usize {
+        self.recovered.swap(0, Ordering::Relaxed)
+    }
+
+    /// Parse the arguments of a call to `tool`, taking common wrappers off if
+    /// they don't fit as they are. Arguments that still don't fit give
+    /// `InvalidParams` explaining what is wrong, to send back to the model, or
+    /// `Failed` once too many calls in a row were malformed
+    pub fn parse<P: DeserializeOwned>(
+        &self,
+        tool: &ollama::Tool,
+        arguments: Value,
+    ) -> Result<P, ToolError> {
+        let error = match serde_json::from_value(arguments.clone()) {
+            Ok(params) => {
+                self.consecutive.store(0, Ordering::Relaxed);
+                return Ok(params);
+            }
+            Err(error) => error,
+        };
+
+        let mut candidate = arguments.clone();
+        for _ in 0..MAX_UNWRAP_DEPTH {
+            let Some(inner) = unwrap(&candidate) else {
+                break;
+            };
+            if let Ok(params) = serde_json::from_value(inner.clone()) {
+                self.consecutive.store(0, Ordering::Relaxed);
+                self.recovered.fetch_add(1, Ordering::Relaxed);
+                return Ok(params);
+            }
+            candidate = inner;
+        }
+
+        let explanation = explain(tool, &candidate, &error);
+        let malformed = self.consecutive.fetch_add(1, Ordering::Relaxed) + 1;
+        let max_malformed = self.max_malformed.load(Ordering::Relaxed);
+        if malformed >= max_malformed {
+            return Err(ToolError::Failed(format!(
+                "Gave up after {} malformed calls in a row to {}. {}",
+                malformed, tool.name, explanation
+            )));
+        }
+        self.recovered.fetch_add(1, Ordering::Relaxed);
+        Err(ToolError::InvalidParams(format!(
+            "{} Call {} again with corrected arguments.",
+            explanation, tool.name
+        )))
+    }
+}
+
+/// The arguments inside a common wrapper: JSON sent as a string or in a code
+/// fence, or an object whose only useful key holds the real arguments.
+/// None if `arguments` is no wrapper
+pub fn unwrap(arguments: &Value) -> Option<Value> {
+    match arguments {
+        Value::String(text) => {
+            let inner: Value = serde_json::from_str(strip_fence(text)).ok()?;
+            inner.is_object().then_some(inner)
+        }
+        Value::Object(object) => WRAPPER_KEYS.iter().find_map(|key| {
+            let inner = object.get(*key)?;
+            // A schema echoed back, e.g. {"type": "object", "properties": {...}}
+            let others_are_schema = object
+                .keys()
+                .all(|other| other == key || matches!(other.as_str(), "type" | "required"));
+            if !others_are_schema {
+                return None;
+            }
+            match inner {
+                Value::Object(_) => Some(inner.clone()),
+                Value::String(_) => unwrap(inner),
+                _ => None,
+            }
+        }),
+        _ => None,
+    }
+}
+
+// The text inside a ```json fenced block, or all of it if there is none
+fn strip_fence(text: &str) -> &str {
+    let text = text.trim();
+    let Some(rest) = text.strip_prefix("```") else {
+        return text;
+    };
+    // The language tag runs to the end of the first line
+    let body = rest.split_once('\n').map_or(rest, |(_, body)| body);
+    body.trim_end().strip_suffix("```").unwrap_or(body).trim()
+}
+
+/// What is wrong with `arguments` for `tool`, naming the field where the
+/// schema can tell, and the parameters the tool takes
+pub fn explain(tool: &ollama::Tool, arguments: &Value, error: &serde_json::Error) -> String {
+    let problem = match invoke::validate(tool, arguments) {
+        Err(problem) => problem.to_string(),
+        Ok(()) => error.to_string(),
+    };
+    format!(
+        "Invalid arguments for {}: {}. It takes {}.",
+        tool.name,
+        problem,
+        invoke::parameters(tool)
+    )
+}
+
+/// A tool's arguments, parsed when the tool is called rather than when the
+/// Coordinator hands them over, so parsing can fail without ending the turn
+pub struct Unparsed<P> {
+    json: Value,
+    marker: std::marker::PhantomData<P>,
+}
+
+impl<'de, P> Deserialize<'de> for Unparsed<P> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self {
+            json: Value::deserialize(deserializer)?,
+            marker: std::marker::PhantomData,
+        })
+    }
+}
+
+impl<P: JsonSchema> JsonSchema for Unparsed<P> {
+    fn schema_name() -> String {
+        P::schema_name()
+    }
+
+    fn json_schema(generator: &mut SchemaGenerator) -> Schema {
+        P::json_schema(generator)
+    }
+
+    fn is_referenceable() -> bool {
+        P::is_referenceable()
+    }
+}
+
+/// A tool for the ollama-rs Coordinator whose malformed calls go through a
+/// [`Recovery`] instead of failing the whole turn
+pub struct Recovering<T> {
+    tool: T,
+    recovery: Arc<Recovery>,
+}
+
+impl<T: Tool> Recovering<T> {
+    pub fn new(tool: T, recovery: Arc<Recovery>) -> Self {
+        Self { tool, recovery }
+    }
+}
+
+impl<T> Tool for Recovering<T>
+where
+    T: Tool,
+    T::Params: Send + Sync,
+{
+    type Params = Unparsed<T::Params>;
+
+    fn name() -> &'static str {
+        T::name()
+    }
+
+    fn description() -> &'static str {
+        T::description()
+    }
+
+    async fn call(
+        &mut self,
+        arguments: Self::Params,
+    ) -> ollama_rs::generation::tools::Result<String> {
+        let definition = crate::tools::registry::definition_of::<T>();
+        match self.recovery.parse(&definition, arguments.json) {
+            // A tool that panics is reported to the model like a bad call
+            Ok(params) => match result::catch_panics(T::name(), self.tool.call(params)).await {
+                Ok(output) => output,
+                Err(error) => Ok(format!("Error: {}", error)),
+            },
+            // The model sees the explanation as the tool's output and can try again
+            Err(error @ ToolError::InvalidParams(_)) => Ok(format!("Error: {}", error)),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::ls::Ls;
+    use crate::tools::registry::ToolSet;
+    use serde_json::json;
+
+    #[derive(Debug, Deserialize, JsonSchema, PartialEq)]
+    struct Params {
+        command: String,
+        timeout: Option<u64>,
+    }
+
+    fn bash() -> ollama::Tool {
+        ToolSet::with_defaults(Arc::default())
+            .definition("bash")
+            .unwrap()
+    }
+
+    #[test]
+    fn test_common_wrappers_are_taken_off() {
+        let recovery = Recovery::default();
+        let expected = Params {
+            command: "ls".to_string(),
+            timeout: None,
+        };
+        for arguments in [
+            json!({ "command": "ls" }),
+            json!({ "properties": { "command": "ls" } }),
+            json!({ "type": "object", "properties": { "command": "ls" } }),
+            json!({ "arguments": "{\"command\": \"ls\"}" }),
+            json!("```json\n{ \"command\": \"ls\" }\n```"),
+            json!("{\"parameters\": {\"command\": \"ls\"}}"),
+        ] {
+            let params: Params = recovery.parse(&bash(), arguments.clone()).unwrap();
+            assert_eq!(params, expected, "{}", arguments);
+        }
+        assert_eq!(recovery.take_recovered(), 5);
+        assert_eq!(recovery.take_recovered(), 0);
+    }
+
+    #[test]
+    fn test_malformed_calls_are_explained_then_given_up_on() {
+        let recovery = Recovery::new(3);
+        let error = recovery
+            .parse::<Params>(&bash(), json!({ "command": "ls", "timeout": "soon" }))
+            .unwrap_err();
+        assert!(matches!(error, ToolError::InvalidParams(_)));
+        let message = error.to_string();
+        assert!(message.starts_with(
+            "Invalid arguments for bash: 'timeout' must be integer, not \"soon\". It takes command (string, required), "
+        ));
+        assert!(message.ends_with("Call bash again with corrected arguments."));
+
+        let error = recovery
+            .parse::<Params>(&bash(), json!({ "properties": { "timeout": 5 } }))
+            .unwrap_err();
+        assert!(error.to_string().contains(": bash needs 'command'."));
+
+        // A call that fits resets the count
+        recovery
+            .parse::<Params>(&bash(), json!({ "command": "ls" }))
+            .unwrap();
+        for _ in 0..2 {
+            let error = recovery
+                .parse::<Params>(&bash(), json!("not json"))
+                .unwrap_err();
+            assert!(matches!(error, ToolError::InvalidParams(_)));
+        }
+        let error = recovery.parse::<Params>(&bash(), json!([1])).unwrap_err();
+        assert!(matches!(error, ToolError::Failed(_)));
+        assert!(error
+            .to_string()
+            .starts_with("Gave up after 3 malformed calls in a row to bash."));
+        assert_eq!(recovery.take_recovered(), 4);
+
+        // A new turn starts over
+        recovery.start_turn();
+        let error = recovery.parse::<Params>(&bash(), json!({})).unwrap_err();
+        assert!(matches!(error, ToolError::InvalidParams(_)));
+    }
+
+    #[tokio::test]
+    async fn test_coordinator_tools_answer_malformed_calls() {
+        let mut ls = Recovering::new(Ls::new(), Recovery::shared(2));
+        let arguments = |json: Value| serde_json::from_value(json).unwrap();
+
+        let output = ls.call(arguments(json!({ "path": 5 }))).await.unwrap();
+        assert!(output.starts_with("Error: Invalid arguments for ls: 'path' must be string"));
+        let error = ls.call(arguments(json!([]))).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Gave up after 2 malformed calls"));
+    }
+
+    #[test]
+    fn test_fences_are_stripped() {
+        assert_eq!(strip_fence("```json\n{}\n```"), "{}");
+        assert_eq!(strip_fence("```\n{\"a\": 1}```"), "{\"a\": 1}");
+        assert_eq!(strip_fence("  {} "), "{}");
+        assert_eq!(unwrap(&json!("[1, 2]")), None);
+        assert_eq!(unwrap(&json!({ "properties": {}, "command": "ls" })), None);
+    }
+}
@@ -0,0 +1,598 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ollama_rs::generation::tools::implementations::{Calculator, DDGSearcher, Scraper};
+use ollama_rs::generation::tools::Tool as OllamaTool;
+use serde_json::Value;
+
+use crate::config::ToolsConfig;
+use crate::llm::ollama::{LlmClient, Tool};
+use crate::recording::Tape;
+use crate::tools::bash::Bash;
+use crate::tools::custom::{CustomTool, CustomToolConfig};
+use crate::tools::file::FileTool;
+use crate::tools::find_file_tool::FindAndReadFileTool;
+use crate::tools::ls::Ls;
+use crate::tools::memory::MemoryTool;
+use crate::tools::patch::ApplyPatch;
+use crate::tools::policy::ExecutionPolicy;
+use crate::tools::read_cache::ReadCache;
+use crate::tools::recovery::{Recovery, DEFAULT_MAX_MALFORMED};
+use crate::tools::result::{self, ToolError};
+use crate::tools::summarize::SummarizeConfig;
+use crate::tools::untrusted::Guarded;
+use crate::tools::weather::Weather;
+
+/// A tool that can be called with JSON arguments, as requested by a model
+#[async_trait]
+pub trait CallableTool: Send {
+    fn definition(&self) -> Tool;
+
+    /// Whether the tool was declared in the config rather than built in
+    fn is_custom(&self) -> bool {
+        false
+    }
+
+    async fn call_json(&mut self, arguments: Value) -> Result<String, ToolError>;
+}
+
+/// Name, description and parameter schema of a tool written for the ollama-rs Coordinator
+pub fn definition_of<T: OllamaTool>() -> Tool {
+    Tool {
+        name: T::name().to_string(),
+        description: T::description().to_string(),
+        input_schema: serde_json::to_value(schemars::schema_for!(T::Params)).unwrap_or(Value::Null),
+    }
+}
+
+/// Like [`definition_of`], or why no definition can be made: the parameter
+/// schema panicked, or doesn't describe an object of named parameters
+pub fn try_definition_of<T: OllamaTool>() -> Result<Tool, String> {
+    let schema =
+        std::panic::catch_unwind(|| serde_json::to_value(schemars::schema_for!(T::Params)))
+            .map_err(|panic| {
+                format!(
+                    "its parameter schema panicked: {}",
+                    result::panic_message(&*panic)
+                )
+            })?
+            .map_err(|e| format!("its parameter schema is not JSON: {}", e))?;
+    if schema.get("type").and_then(Value::as_str) != Some("object") {
+        return Err("its parameters are not an object of named fields".to_string());
+    }
+    Ok(Tool {
+        name: T::name().to_string(),
+        description: T::description().to_string(),
+        input_schema: schema,
+    })
+}
+
+/// Name, description and parameter schema of each of Sentinel's own tools
+/// and the network tools the Ollama client offers, whether enabled or not
+pub fn builtin_definitions() -> Vec<Tool> {
+    vec![
+        definition_of::<Calculator>(),
+        definition_of::<Weather>(),
+        definition_of::<DDGSearcher>(),
+        definition_of::<Scraper>(),
+        definition_of::<Bash>(),
+        definition_of::<Ls>(),
+        definition_of::<FileTool>(),
+        definition_of::<FindAndReadFileTool>(),
+        definition_of::<ApplyPatch>(),
+        definition_of::<MemoryTool>(),
+    ]
+}
+
+// The names the config and clients give the ollama-rs tools, with the names
+// the model sees them by
+const CONFIG_NAMES: [(&str, &str); 3] = [
+    ("Calculator", "calculator"),
+    ("DDGSearcher", "ddg_searcher"),
+    ("Scraper", "website_scraper"),
+];
+
+/// The name the model sees the tool called `name` in the config by
+pub fn model_name(name: &str) -> &str {
+    CONFIG_NAMES
+        .iter()
+        .find(|(config_name, _)| *config_name == name)
+        .map_or(name, |(_, model_name)| model_name)
+}
+
+/// The name the config gives the tool the model sees as `name`
+pub fn config_name(name: &str) -> &str {
+    CONFIG_NAMES
+        .iter()
+        .find(|(_, model_name)| *model_name == name)
+        .map_or(name, |(config_name, _)| config_name)
+}
+
+/// The built-in tool called `name`, in the config or as the model sees it
+pub fn builtin(name: &str) -> Option<Tool> {
+    let name = model_name(name);
+    builtin_definitions()
+        .into_iter()
+        .find(|definition| definition.name == name)
+}
+
+/// Offer the tool called `name`, as `/tools` lists it or as the model sees
+/// it, to the model or stop offering it, whether the client or `tools` runs
+/// it. What to tell the user, or that there is no such tool
+pub fn switch_tool(
+    client: &dyn LlmClient,
+    tools: &mut ToolSet,
+    name: &str,
+    enabled: bool,
+) -> Result<String, String> {
+    let config_name = config_name(name);
+    let client_has_it = client.set_tool_enabled(config_name, enabled);
+    let tools_have_it = tools.set_enabled(model_name(config_name), enabled);
+    if !client_has_it && !tools_have_it {
+        return Err(format!("No tool named '{}'; /tools lists them", name));
+    }
+    Ok(if enabled {
+        format!("{} is offered to the model again", name)
+    } else {
+        format!(
+            "{} is no longer offered to the model; /tools {} on offers it again",
+            name, name
+        )
+    })
+}
+
+/// The tools offered to the model: those a client names, as `tools` has
+/// them or else as built in, then the rest of `tools`, such as custom tools
+pub fn offered(names: &[String], tools: &ToolSet) -> Vec<Tool> {
+    let mut offered: Vec<Tool> = names
+        .iter()
+        .filter_map(|name| tools.definition(name).or_else(|| builtin(name)))
+        .collect();
+    for definition in tools.definitions() {
+        if !offered.iter().any(|known| known.name == definition.name) {
+            offered.push(definition);
+        }
+    }
+    offered
+}
+
+// Adapts a tool written for the ollama-rs Coordinator
+struct Adapter<T> {
+    tool: T,
+    definition: Tool,
+    recovery: Arc<Recovery>,
+}
+
+#[async_trait]
+impl<T> CallableTool for Adapter<T>
+where
+    T: OllamaTool + 'static,
+    T::Params: Send,
+{
+    fn definition(&self) -> Tool {
+        self.definition.clone()
+    }
+
+    async fn call_json(&mut self, arguments: Value) -> Result<String, ToolError> {
+        let parameters: T::Params = self.recovery.parse(&self.definition, arguments)?;
+        self.tool
+            .call(parameters)
+            .await
+            .map_err(|e| ToolError::Failed(e.to_string()))
+    }
+}
+
+/// The tools an agent can run itself when a model asks for them
+pub struct ToolSet {
+    tools: Vec<Box<dyn CallableTool>>,
+    read_cache: Arc<ReadCache>,
+    // Malformed calls to any of the tools, and when to give up on them
+    recovery: Arc<Recovery>,
+    // Guards the output of web tools, and makes mutating tools ask after suspicious content
+    policy: Arc<ExecutionPolicy>,
+    // Records the calls, or answers them from a recording, when the session is
+    tape: Option<Tape>,
+    // Failed commands in a turn the model is asked to correct
+    auto_retry: usize,
+    // Tools that could not be loaded, each with why
+    failed: Vec<(String, String)>,
+    // Which long outputs are summarized before the model is sent them
+    summarize: SummarizeConfig,
+    // Tools not offered to the model for now, kept to be switched on again
+    switched_off: Vec<String>,
+}
+
+impl Default for ToolSet {
+    fn default() -> Self {
+        Self {
+            tools: Vec::new(),
+            read_cache: Arc::default(),
+            recovery: Recovery::shared(DEFAULT_MAX_MALFORMED),
+            policy: Arc::default(),
+            tape: None,
+            auto_retry: 0,
+            failed: Vec::new(),
+            summarize: SummarizeConfig::default(),
+            switched_off: Vec::new(),
+        }
+    }
+}
+
+impl ToolSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sentinel's own tools, sharing one execution policy
+    pub fn with_defaults(policy: Arc<ExecutionPolicy>) -> Self {
+        let mut tools = Self {
+            policy: Arc::clone(&policy),
+            ..Self::new()
+        };
+        let cache = Arc::clone(&tools.read_cache);
+        tools.register(Bash::new().with_policy(Arc::clone(&policy)));
+        tools.register(Ls::new().with_policy(Arc::clone(&policy)));
+        tools.register(FileTool::with_policy(Arc::clone(&policy)).with_cache(Arc::clone(&cache)));
+        tools.register(
+            FindAndReadFileTool::new()
+                .with_policy(Arc::clone(&policy))
+                .with_cache(cache),
+        );
+        tools.register(ApplyPatch::with_policy(policy));
+        tools
+    }
+
+    /// Sentinel's own tools, the network tools the Ollama client offers and
+    /// the custom tools of the config, for running by hand
+    pub fn with_builtins(policy: Arc<ExecutionPolicy>, config: &ToolsConfig) -> Self {
+        let mut tools = Self::with_defaults(policy);
+        tools.register(Weather::new(config.weather.clone()));
+        tools.register(Calculator {});
+        tools.register(DDGSearcher::new());
+        tools.register(Scraper {});
+        for tool in &config.custom {
+            tools.register_custom(tool);
+        }
+        tools
+    }
+
+    /// Keep only the tools for which `keep` returns true
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        self.tools.retain(|tool| keep(&tool.definition().name));
+        self.failed.retain(|(name, _)| keep(name));
+    }
+
+    /// Offer the tool called `name` to the model, or stop offering it until it
+    /// is switched on again; false if there is no such tool
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        let Some(name) = self
+            .tools
+            .iter()
+            .map(|tool| tool.definition().name)
+            .find(|tool| tool.eq_ignore_ascii_case(name))
+        else {
+            return false;
+        };
+        self.switched_off.retain(|tool| *tool != name);
+        if !enabled {
+            self.switched_off.push(name);
+        }
+        true
+    }
+
+    // Whether the tool called `name` is offered, unless it was switched off
+    fn is_switched_off(&self, name: &str) -> bool {
+        self.switched_off.iter().any(|tool| tool == name)
+    }
+
+    /// Forget which files were already read, e.g. when the conversation is cleared
+    pub fn reset(&self) {
+        self.read_cache.clear();
+    }
+
+    /// Fail a turn after this many malformed calls in a row instead of the default
+    pub fn set_max_malformed(&self, max_malformed: usize) {
+        self.recovery.set_max_malformed(max_malformed);
+    }
+
+    /// Ask the model to correct up to this many failed bash commands in a turn
+    pub fn set_auto_retry(&mut self, auto_retry: usize) {
+        self.auto_retry = auto_retry;
+    }
+
+    /// Failed bash commands in a turn the model is asked to correct
+    pub fn auto_retry(&self) -> usize {
+        self.auto_retry
+    }
+
+    /// Summarize long tool output as `config` says, before the model is sent it
+    pub fn set_summarize(&mut self, config: SummarizeConfig) {
+        self.summarize = config;
+    }
+
+    /// Which long tool output is summarized
+    pub fn summarize(&self) -> &SummarizeConfig {
+        &self.summarize
+    }
+
+    /// Start counting malformed calls afresh and forget the web content of
+    /// the last turn, at the start of a turn
+    pub fn start_turn(&self) {
+        self.recovery.start_turn();
+        self.policy.start_turn();
+    }
+
+    /// The execution policy the tools run under
+    pub fn policy(&self) -> &Arc<ExecutionPolicy> {
+        &self.policy
+    }
+
+    /// Record every call on `tape`, or answer it from there without running
+    /// the tool, as the client of a recorded or replayed session says
+    pub fn set_tape(&mut self, tape: Option<Tape>) {
+        self.tape = tape;
+    }
+
+    /// Malformed calls recovered from since the last time this was asked
+    pub fn take_recovered(&self) -> usize {
+        self.recovery.take_recovered()
+    }
+
+    /// Add a tool, replacing any tool with the same name. A tool whose
+    /// parameter schema can't be made is left out, and listed as failed
+    pub fn register<T>(&mut self, tool: T)
+    where
+        T: OllamaTool + 'static,
+        T::Params: Send + Sync,
+    {
+        self.forget(T::name());
+        match try_definition_of::<T>() {
+            Ok(definition) => self.tools.push(Box::new(Adapter {
+                tool: Guarded::new(tool, Arc::clone(&self.policy)),
+                definition,
+                recovery: Arc::clone(&self.recovery),
+            })),
+            Err(reason) => self.failed.push((T::name().to_string(), reason)),
+        }
+    }
+
+    /// Add a tool declared in the config, replacing any tool with the same
+    /// name. A declaration the model couldn't call is left out, and listed as failed
+    pub fn register_custom(&mut self, config: &CustomToolConfig) {
+        self.forget(&config.name);
+        match config.check() {
+            Ok(()) => self.tools.push(Box::new(CustomTool::new(
+                config.clone(),
+                Arc::clone(&self.policy),
+            ))),
+            Err(reason) => self.failed.push((config.name.clone(), reason)),
+        }
+    }
+
+    // Drop the tool called `name`, and any failure to load it
+    fn forget(&mut self, name: &str) {
+        self.tools
+            .retain(|existing| existing.definition().name != name);
+        self.failed.retain(|(failed, _)| failed != name);
+    }
+
+    /// Tools that could not be loaded, each with why
+    pub fn failed(&self) -> &[(String, String)] {
+        &self.failed
+    }
+
+    /// The tools that could not be loaded as `/tools` lists them, e.g.
+    /// "greet (failed to load: the command is empty)"
+    pub fn failed_names(&self) -> Vec<String> {
+        self.failed
+            .iter()
+            .map(|(name, reason)| format!("{} (failed to load: {})", name, reason))
+            .collect()
+    }
+
+    /// Names of the tools declared in the config that are offered
+    pub fn custom_names(&self) -> Vec<String> {
+        self.tools
+            .iter()
+            .filter(|tool| tool.is_custom())
+            .map(|tool| tool.definition().name)
+            .filter(|name| !self.is_switched_off(name))
+            .collect()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.tools
+            .iter()
+            .map(|tool| tool.definition().name)
+            .collect()
+    }
+
+    /// Name, description and parameter schema of the tool called `name`, if offered
+    pub fn definition(&self, name: &str) -> Option<Tool> {
+        self.definitions()
+            .into_iter()
+            .find(|definition| definition.name == name)
+    }
+
+    /// Name, description and parameter schema of every tool, to offer to the
+    /// model, but for those switched off
+    pub fn definitions(&self) -> Vec<Tool> {
+        self.tools
+            .iter()
+            .map(|tool| tool.definition())
+            .filter(|definition| !self.is_switched_off(&definition.name))
+            .collect()
+    }
+
+    /// Run the tool called `name` with the given arguments. A tool that
+    /// panics fails the call with [`ToolError::Panicked`]
+    pub async fn call(&mut self, name: &str, arguments: Value) -> Result<String, ToolError> {
+        if self.is_switched_off(name) {
+            return Err(ToolError::NotFound(format!(
+                "The tool '{}' is switched off for this session",
+                name
+            )));
+        }
+        let tool = self
+            .tools
+            .iter_mut()
+            .find(|tool| tool.definition().name == name)
+            .ok_or_else(|| ToolError::NotFound(format!("No tool named '{}'", name)))?;
+        match &self.tape {
+            Some(tape) => tape.call(tool.as_mut(), arguments).await,
+            None => result::catch_panics(name, tool.call_json(arguments)).await?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_switched_off_tools_are_neither_offered_nor_run() {
+        let mut tools = ToolSet::with_defaults(Arc::default());
+        assert!(!tools.set_enabled("nonexistent", false));
+        assert!(tools.set_enabled("BASH", false));
+
+        assert!(tools.definition("bash").is_none());
+        assert!(!tools.definitions().iter().any(|tool| tool.name == "bash"));
+        let error = tools
+            .call("bash", json!({ "command": "echo hi" }))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("switched off"));
+
+        assert!(tools.set_enabled("bash", true));
+        assert!(tools.definition("bash").is_some());
+        assert!(tools
+            .call("bash", json!({ "command": "echo hi" }))
+            .await
+            .unwrap()
+            .contains("hi"));
+    }
+
+    #[tokio::test]
+    async fn test_calls_tools_by_name() -> anyhow::Result<()> {
+        let dir = TempDir::new()?;
+        std::fs::write(dir.path().join("notes.txt"), "remember the milk")?;
+
+        let mut tools = ToolSet::with_defaults(Arc::default());
+        assert_eq!(
+            tools.names(),
+            ["bash", "ls", "file", "find_file", "apply_patch"]
+        );
+
+        let path = dir.path().join("notes.txt");
+        let output = tools
+            .call(
+                "file",
+                json!({ "operation": "read", "path": path.to_string_lossy() }),
+            )
+            .await?;
+        assert!(output.contains("remember the milk"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unknown_tools_and_bad_arguments() {
+        let mut tools = ToolSet::with_defaults(Arc::default());
+
+        let error = tools.call("teleport", json!({})).await.unwrap_err();
+        assert!(matches!(error, ToolError::NotFound(_)));
+
+        let error = tools
+            .call("bash", json!({ "cmd": "ls" }))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ToolError::InvalidParams(_)));
+    }
+
+    #[test]
+    fn test_offered_tools_follow_the_client_then_the_tool_set() {
+        let mut tools = ToolSet::with_defaults(Arc::default());
+        tools.retain(|name| name != "apply_patch");
+        let names = ["Calculator".to_string(), "bash".to_string()];
+        let offered: Vec<String> = offered(&names, &tools)
+            .into_iter()
+            .map(|definition| definition.name)
+            .collect();
+        assert_eq!(offered, ["calculator", "bash", "ls", "file", "find_file"]);
+        assert_eq!(model_name("DDGSearcher"), "ddg_searcher");
+        assert_eq!(model_name("bash"), "bash");
+        assert!(builtin("memory").is_some());
+        assert!(builtin("teleport").is_none());
+    }
+
+    #[derive(serde::Deserialize)]
+    struct UnschemedParams {}
+
+    impl schemars::JsonSchema for UnschemedParams {
+        fn schema_name() -> String {
+            "UnschemedParams".to_string()
+        }
+
+        fn json_schema(_gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+            panic!("no schema for you")
+        }
+    }
+
+    // A tool whose parameter schema panics
+    struct Unschemed;
+
+    impl OllamaTool for Unschemed {
+        type Params = UnschemedParams;
+
+        fn name() -> &'static str {
+            "unschemed"
+        }
+
+        fn description() -> &'static str {
+            "Has no schema"
+        }
+
+        async fn call(
+            &mut self,
+            _parameters: UnschemedParams,
+        ) -> ollama_rs::generation::tools::Result<String> {
+            Ok(String::new())
+        }
+    }
+
+    #[test]
+    fn test_tools_without_a_schema_are_left_out() {
+        assert_eq!(
+            try_definition_of::<Unschemed>().unwrap_err(),
+            "its parameter schema panicked: no schema for you"
+        );
+        let mut tools = ToolSet::with_defaults(Arc::default());
+        tools.register(Unschemed);
+        assert!(tools.definition("unschemed").is_none());
+        assert_eq!(
+            tools.failed_names(),
+            ["unschemed (failed to load: its parameter schema panicked: no schema for you)"]
+        );
+        // Every built-in tool has one
+        for definition in builtin_definitions() {
+            assert_eq!(
+                definition.input_schema["type"], "object",
+                "{}",
+                definition.name
+            );
+        }
+
+        // Nor are the failures of tools the config leaves out listed
+        tools.retain(|name| name != "unschemed");
+        assert!(tools.failed().is_empty());
+    }
+
+    #[test]
+    fn test_definitions_include_schemas() {
+        let tools = ToolSet::with_defaults(Arc::default());
+        let bash = &tools.definitions()[0];
+        assert_eq!(bash.name, "bash");
+        assert!(bash.input_schema["properties"]["command"].is_object());
+    }
+}
@@ -0,0 +1,192 @@
+use std::any::Any;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::panic::AssertUnwindSafe;
+use std::time::{Duration, Instant};
+
+use futures::FutureExt;
+
+/// Why a tool could not do what it was asked.
+///
+/// The `Display` text is what the model sees, so keep it stable.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ToolError {
+    /// A file, directory or other target does not exist
+    NotFound(String),
+    /// The operating system refused access
+    PermissionDenied(String),
+    /// The parameters were missing, malformed or contradictory
+    InvalidParams(String),
+    /// The operation did not finish in time
+    Timeout(Duration),
+    /// The command printed nothing for this long and was stopped
+    Idle(Duration),
+    /// Any other I/O failure
+    Io { context: String, source: io::Error },
+    /// The operation was refused for safety reasons
+    Unsafe(String),
+    /// The operation failed for a reason not covered above
+    Failed(String),
+    /// The tool panicked, a bug in the tool rather than in the call
+    Panicked(String),
+}
+
+impl ToolError {
+    /// Classify an I/O error, keeping `context` (e.g. "Error reading file") in the message
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        let context = context.into();
+        match source.kind() {
+            io::ErrorKind::NotFound => Self::NotFound(format!("{}: {}", context, source)),
+            io::ErrorKind::PermissionDenied => {
+                Self::PermissionDenied(format!("{}: {}", context, source))
+            }
+            _ => Self::Io { context, source },
+        }
+    }
+}
+
+impl fmt::Display for ToolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(message)
+            | Self::PermissionDenied(message)
+            | Self::InvalidParams(message)
+            | Self::Unsafe(message)
+            | Self::Failed(message)
+            | Self::Panicked(message) => write!(f, "{}", message),
+            Self::Timeout(after) => write!(
+                f,
+                "Command execution timed out after {}ms",
+                after.as_millis()
+            ),
+            Self::Idle(after) => write!(
+                f,
+                "Command produced no output for {}ms and was stopped",
+                after.as_millis()
+            ),
+            Self::Io { context, source } => write!(f, "{}: {}", context, source),
+        }
+    }
+}
+
+impl std::error::Error for ToolError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// What a tool produced, before it is flattened into text for the model
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ToolOutput {
+    pub content: String,
+    pub duration_ms: u128,
+    pub warnings: Vec<String>,
+}
+
+impl ToolOutput {
+    pub fn new(content: impl Into<String>) -> Self {
+        Self {
+            content: content.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_warning(mut self, warning: impl Into<String>) -> Self {
+        self.warnings.push(warning.into());
+        self
+    }
+
+    /// Record how long the tool took, measured from `started`
+    pub fn timed(mut self, started: Instant) -> Self {
+        self.duration_ms = started.elapsed().as_millis();
+        self
+    }
+
+    /// The content with each warning on a "Note:" line above it
+    pub fn with_notes(&self) -> String {
+        let mut text = String::new();
+        for warning in &self.warnings {
+            text.push_str(&format!("Note: {}\n", warning));
+        }
+        text.push_str(&self.content);
+        text
+    }
+}
+
+pub type ToolResult = Result<ToolOutput, ToolError>;
+
+/// Await a call of the tool called `tool`, turning a panic inside it into
+/// [`ToolError::Panicked`] so one broken tool doesn't take the session down
+pub async fn catch_panics<T>(tool: &str, call: impl Future<Output = T>) -> Result<T, ToolError> {
+    AssertUnwindSafe(call)
+        .catch_unwind()
+        .await
+        .map_err(|panic| {
+            ToolError::Panicked(format!(
+            "The tool '{}' crashed: {}. It may work with other arguments; otherwise do without it",
+            tool,
+            panic_message(&*panic)
+        ))
+        })
+}
+
+/// The message a panic was raised with, if it has one
+pub fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("no message")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_errors_are_classified() {
+        let error = ToolError::io(
+            "Error reading file",
+            io::Error::new(io::ErrorKind::NotFound, "missing"),
+        );
+        assert!(matches!(error, ToolError::NotFound(_)));
+        assert_eq!(error.to_string(), "Error reading file: missing");
+
+        let error = ToolError::io(
+            "Error writing file",
+            io::Error::new(io::ErrorKind::PermissionDenied, "denied"),
+        );
+        assert!(matches!(error, ToolError::PermissionDenied(_)));
+
+        let error = ToolError::io("Error copying", io::Error::other("disk full"));
+        assert!(matches!(error, ToolError::Io { .. }));
+        assert_eq!(error.to_string(), "Error copying: disk full");
+    }
+
+    #[tokio::test]
+    async fn test_panics_become_errors() {
+        let error = catch_panics::<()>("broken", async { panic!("index out of bounds") })
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ToolError::Panicked(_)));
+        assert!(error
+            .to_string()
+            .starts_with("The tool 'broken' crashed: index out of bounds."));
+
+        let owned = catch_panics::<()>("broken", async { panic!("{} of {}", 3, 2) }).await;
+        assert!(owned.unwrap_err().to_string().contains("crashed: 3 of 2."));
+        assert_eq!(catch_panics("fine", async { 7 }).await.unwrap(), 7);
+    }
+
+    #[test]
+    fn test_warnings_render_as_notes() {
+        let output = ToolOutput::new("done").with_warning("ignored FOO_TOKEN");
+        assert_eq!(output.with_notes(), "Note: ignored FOO_TOKEN\ndone");
+        assert_eq!(ToolOutput::new("done").with_notes(), "done");
+    }
+}
@@ -0,0 +1,254 @@
+//! Reviewing file changes before a tool makes them.
+//!
+//! Tools describe a change as a [`ProposedChange`] and ask the
+//! [`ExecutionPolicy`](crate::tools::policy::ExecutionPolicy) for a decision;
+//! frontends install a [`Reviewer`] that shows the diff and asks the user.
+//! The same reviewer confirms commands the bash tool's strict mode asks about.
+
+use std::path::PathBuf;
+
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+
+/// Unchanged lines shown around each hunk
+const CONTEXT_LINES: usize = 3;
+
+/// One line of a diff, without its trailing newline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffLine {
+    Context(String),
+    Added(String),
+    Removed(String),
+}
+
+impl DiffLine {
+    /// The line as it appears in a unified diff
+    pub fn unified(&self) -> String {
+        match self {
+            Self::Context(line) => format!(" {}", line),
+            Self::Added(line) => format!("+{}", line),
+            Self::Removed(line) => format!("-{}", line),
+        }
+    }
+}
+
+/// Nearby changed lines with their context; the unit a partial approval would pick
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Hunk {
+    /// First line in the old file, counting from 1
+    pub old_start: usize,
+    pub old_len: usize,
+    /// First line in the new file, counting from 1
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+impl Hunk {
+    pub fn header(&self) -> String {
+        format!(
+            "@@ -{},{} +{},{} @@",
+            self.old_start, self.old_len, self.new_start, self.new_len
+        )
+    }
+}
+
+/// A change a tool is about to make to a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProposedChange {
+    /// The tool making the change
+    pub tool: String,
+    pub path: PathBuf,
+    /// Whether the file does not exist yet
+    pub creates: bool,
+    pub hunks: Vec<Hunk>,
+}
+
+impl ProposedChange {
+    /// The change from `old` content, or a new file if None, to `new`
+    pub fn new(tool: &str, path: PathBuf, old: Option<&str>, new: &str) -> Self {
+        let diff = TextDiff::from_lines(old.unwrap_or(""), new);
+        let hunks = diff
+            .grouped_ops(CONTEXT_LINES)
+            .iter()
+            .filter_map(|group| {
+                let (first, last) = (group.first()?, group.last()?);
+                let old_range = first.old_range().start..last.old_range().end;
+                let new_range = first.new_range().start..last.new_range().end;
+
+                let lines = group
+                    .iter()
+                    .flat_map(|op| diff.iter_changes(op))
+                    .map(|change| {
+                        let line = change.value().trim_end_matches(['\n', '\r']).to_string();
+                        match change.tag() {
+                            ChangeTag::Equal => DiffLine::Context(line),
+                            ChangeTag::Insert => DiffLine::Added(line),
+                            ChangeTag::Delete => DiffLine::Removed(line),
+                        }
+                    })
+                    .collect();
+
+                Some(Hunk {
+                    old_start: start_line(old_range.start, old_range.len()),
+                    old_len: old_range.len(),
+                    new_start: start_line(new_range.start, new_range.len()),
+                    new_len: new_range.len(),
+                    lines,
+                })
+            })
+            .collect();
+
+        Self {
+            tool: tool.to_string(),
+            path,
+            creates: old.is_none(),
+            hunks,
+        }
+    }
+
+    /// Whether applying the change would leave the file as it is
+    pub fn is_empty(&self) -> bool {
+        self.hunks.is_empty() && !self.creates
+    }
+
+    pub fn added(&self) -> usize {
+        self.count(|line| matches!(line, DiffLine::Added(_)))
+    }
+
+    pub fn removed(&self) -> usize {
+        self.count(|line| matches!(line, DiffLine::Removed(_)))
+    }
+
+    fn count(&self, predicate: impl Fn(&DiffLine) -> bool) -> usize {
+        self.hunks
+            .iter()
+            .flat_map(|hunk| &hunk.lines)
+            .filter(|line| predicate(line))
+            .count()
+    }
+
+    /// "path (+3 -1)", or "path (new file, +3)"
+    pub fn summary(&self) -> String {
+        if self.creates {
+            format!("{} (new file, +{})", self.path.display(), self.added())
+        } else {
+            format!(
+                "{} (+{} -{})",
+                self.path.display(),
+                self.added(),
+                self.removed()
+            )
+        }
+    }
+
+    /// The change as a unified diff
+    pub fn unified_diff(&self) -> String {
+        let old = if self.creates {
+            "/dev/null".to_string()
+        } else {
+            format!("a/{}", self.path.display())
+        };
+        let mut diff = format!("--- {}\n+++ b/{}\n", old, self.path.display());
+        for hunk in &self.hunks {
+            diff.push_str(&hunk.header());
+            diff.push('\n');
+            for line in &hunk.lines {
+                diff.push_str(&line.unified());
+                diff.push('\n');
+            }
+        }
+        diff
+    }
+}
+
+// Unified diffs count from 1, except for an empty range, which names the line before it
+fn start_line(index: usize, len: usize) -> usize {
+    if len == 0 {
+        index
+    } else {
+        index + 1
+    }
+}
+
+/// What the user decided about a proposed change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Approve,
+    /// Approve this change and every later one in the session
+    ApproveAll,
+    Reject {
+        reason: Option<String>,
+    },
+}
+
+/// Asks the user about file changes before tools make them.
+///
+/// Reviews block the tool until the user answers, like any other prompt.
+pub trait Reviewer: Send + Sync {
+    fn review(&self, change: &ProposedChange) -> Decision;
+
+    /// Whether to run a command line the command rules ask about, `reason`
+    /// saying which commands are not allowlisted. Declined unless overridden
+    fn confirm_command(&self, _command: &str, _reason: &str) -> Decision {
+        Decision::Reject { reason: None }
+    }
+}
+
+/// Tool output telling the model the user declined a change
+pub fn declined(change: &ProposedChange, reason: Option<&str>) -> String {
+    format!(
+        "The user declined the change to '{}' proposed with the {} tool; nothing was written.\n\
+         Reason: {}\n\
+         Do not make the same change again: adjust it to the reason, or ask the user how to proceed.",
+        change.path.display(),
+        change.tool,
+        reason.unwrap_or("none given")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\n";
+        let new = "one\ntwo\nthree\nfour\nFIVE\nsix\nseven\neight\nnine\nten\n";
+        let change = ProposedChange::new("file", PathBuf::from("notes.txt"), Some(old), new);
+
+        assert_eq!(change.hunks.len(), 1);
+        assert_eq!(change.hunks[0].header(), "@@ -2,8 +2,9 @@");
+        assert_eq!((change.added(), change.removed()), (2, 1));
+        assert_eq!(change.summary(), "notes.txt (+2 -1)");
+        assert_eq!(
+            change.unified_diff(),
+            "--- a/notes.txt\n+++ b/notes.txt\n@@ -2,8 +2,9 @@\n two\n three\n four\n\
+             -five\n+FIVE\n six\n seven\n eight\n nine\n+ten\n"
+        );
+    }
+
+    #[test]
+    fn test_new_and_unchanged_files() {
+        let change = ProposedChange::new("file", PathBuf::from("new.rs"), None, "fn main() {}\n");
+        assert!(change.creates);
+        assert_eq!(change.hunks[0].header(), "@@ -0,0 +1,1 @@");
+        assert!(change
+            .unified_diff()
+            .starts_with("--- /dev/null\n+++ b/new.rs\n"));
+        assert_eq!(change.summary(), "new.rs (new file, +1)");
+
+        let change = ProposedChange::new("file", PathBuf::from("same.rs"), Some("x\n"), "x\n");
+        assert!(change.is_empty());
+    }
+
+    #[test]
+    fn test_declined_message() {
+        let change = ProposedChange::new("file", PathBuf::from("a.txt"), Some("a\n"), "b\n");
+        let message = declined(&change, Some("keep the old greeting"));
+        assert!(message.starts_with("The user declined the change to 'a.txt'"));
+        assert!(message.contains("Reason: keep the old greeting"));
+        assert!(declined(&change, None).contains("Reason: none given"));
+    }
+}
@@ -0,0 +1,123 @@
+//! Showing tools as the model is given them, for `/schema`, `sentinel tool
+//! --schema` and `sentinel tools --export`.
+//!
+//! A tool reaches the model as its name, its description word for word and
+//! the JSON schema schemars generates for its parameters. The export writes
+//! them in the shape of the `tools` field of an Ollama chat request.
+
+use serde_json::{json, Value};
+
+use crate::llm::ollama::Tool;
+use crate::tools::registry;
+
+/// The tool called `name`, in the config or as the model sees it, matched
+/// without regard to case if no name matches exactly
+pub fn find<'a>(tools: &'a [Tool], name: &str) -> Option<&'a Tool> {
+    let name = registry::model_name(name);
+    tools.iter().find(|tool| tool.name == name).or_else(|| {
+        tools
+            .iter()
+            .find(|tool| tool.name.eq_ignore_ascii_case(name))
+    })
+}
+
+/// Why `/schema <name>` has nothing to show
+pub fn not_offered(name: &str) -> String {
+    format!(
+        "No tool named '{}' is offered to the model; /schema lists them",
+        name
+    )
+}
+
+/// The tool's name and the first line of its description, e.g.
+/// "bash  - Executes a given bash command ..."
+pub fn summary(tool: &Tool) -> String {
+    let line = tool.description.lines().next().unwrap_or_default().trim();
+    format!("{}  - {}", tool.name, line)
+}
+
+/// The tool's description as the model sees it and its parameter schema,
+/// pretty-printed
+pub fn show(tool: &Tool) -> String {
+    let schema = serde_json::to_string_pretty(&tool.input_schema).unwrap_or_default();
+    format!(
+        "{}\n\n{}\n\nParameters (JSON schema):\n{}",
+        tool.name,
+        tool.description.trim(),
+        schema
+    )
+}
+
+/// Every tool as an Ollama function definition, for validators and docs
+pub fn export(tools: &[Tool]) -> Value {
+    let functions: Vec<Value> = tools
+        .iter()
+        .map(|tool| {
+            json!({
+                "type": "function",
+                "function": {
+                    "name": tool.name,
+                    "description": tool.description,
+                    "parameters": tool.input_schema,
+                },
+            })
+        })
+        .collect();
+    Value::Array(functions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exported_schemas_keep_the_documented_fields() {
+        let tools = registry::builtin_definitions();
+        let exported = export(&tools);
+        let bash = exported
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|tool| tool["function"]["name"] == "bash")
+            .expect("bash is exported");
+
+        assert_eq!(bash["type"], "function");
+        let description = bash["function"]["description"].as_str().unwrap();
+        assert!(description.starts_with("Executes a given bash command"));
+        let parameters = &bash["function"]["parameters"];
+        assert_eq!(
+            parameters["properties"]["command"]["description"],
+            "The command to execute"
+        );
+        assert_eq!(
+            parameters["properties"]["timeout"]["description"],
+            "Optional timeout in milliseconds (max 600000)"
+        );
+        assert!(parameters["properties"]["env"].is_object());
+        assert_eq!(parameters["required"], json!(["command"]));
+    }
+
+    #[test]
+    fn test_one_tool_is_shown_in_full() {
+        let tools = registry::builtin_definitions();
+        let file = find(&tools, "FILE").expect("file is found in any case");
+        assert_eq!(file.name, "file");
+
+        let shown = show(file);
+        assert!(shown.starts_with("file\n\n"));
+        assert!(shown.contains("Parameters (JSON schema):\n{\n"));
+        assert!(shown.contains("\"operation\""));
+        assert!(summary(file).starts_with("file  - "));
+        assert!(!summary(file).contains('\n'));
+
+        assert_eq!(
+            find(&tools, "DDGSearcher").map(|tool| tool.name.as_str()),
+            Some("ddg_searcher")
+        );
+        assert!(find(&tools, "teleport").is_none());
+        assert_eq!(
+            not_offered("teleport"),
+            "No tool named 'teleport' is offered to the model; /schema lists them"
+        );
+    }
+}
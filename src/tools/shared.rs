@@ -0,0 +1,161 @@
+use std::sync::Arc;
+
+use ollama_rs::generation::tools::Tool;
+use tokio::sync::Mutex;
+
+/// A tool whose state outlives a single request.
+///
+/// The coordinator takes ownership of the tools it is given, so stateful tools
+/// (like the Bash working directory) are registered through a clone of this
+/// handle while the conversation keeps the original.
+pub struct Shared<T> {
+    inner: Arc<Mutex<T>>,
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<T: Default> Default for Shared<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> Shared<T> {
+    pub fn new(tool: T) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(tool)),
+        }
+    }
+
+    /// Lock the underlying tool
+    pub async fn lock(&self) -> tokio::sync::MutexGuard<'_, T> {
+        self.inner.lock().await
+    }
+
+    /// Replace the tool state with a fresh instance, e.g. when the conversation is cleared
+    pub fn reset(&self)
+    where
+        T: Default + Send + 'static,
+    {
+        self.replace_with(T::default);
+    }
+
+    /// Replace the tool state with one built by `make`
+    pub fn replace_with<F>(&self, make: F)
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        match self.inner.try_lock() {
+            Ok(mut tool) => *tool = make(),
+            Err(_) => {
+                // A call is in flight; reset once it finishes
+                let inner = Arc::clone(&self.inner);
+                tokio::spawn(async move {
+                    *inner.lock().await = make();
+                });
+            }
+        }
+    }
+}
+
+impl<T> Tool for Shared<T>
+where
+    T: Tool,
+    T::Params: Send + Sync,
+{
+    type Params = T::Params;
+
+    fn name() -> &'static str {
+        T::name()
+    }
+
+    fn description() -> &'static str {
+        T::description()
+    }
+
+    async fn call(
+        &mut self,
+        parameters: Self::Params,
+    ) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
+        self.inner.lock().await.call(parameters).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::bash::{Bash, BashParams};
+    use tempfile::tempdir;
+
+    fn command(command: &str) -> BashParams {
+        serde_json::from_value(serde_json::json!({ "command": command })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_bash_working_directory_persists_across_turns() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let conversation_bash = Shared::new(Bash::new());
+
+        // First turn: the coordinator for this request gets its own handle
+        let mut first_turn = conversation_bash.clone();
+        first_turn
+            .call(command(&format!("cd {}", dir.path().display())))
+            .await
+            .unwrap();
+
+        // Second turn: a new handle still sees the directory change
+        let mut second_turn = conversation_bash.clone();
+        let output = second_turn.call(command("pwd")).await.unwrap();
+
+        let expected = dir.path().canonicalize()?;
+        assert_eq!(
+            std::path::Path::new(output.trim()).canonicalize()?,
+            expected
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cd_inside_compound_command_is_tracked() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        std::fs::create_dir(dir.path().join("nested"))?;
+        let mut bash = Shared::new(Bash::new());
+
+        let output = bash
+            .call(command(&format!(
+                "cd {} && cd nested && echo moved",
+                dir.path().display()
+            )))
+            .await
+            .unwrap();
+        assert_eq!(output.trim(), "moved");
+
+        let working_directory = bash.lock().await.working_directory().to_string();
+        assert_eq!(
+            std::path::Path::new(&working_directory).canonicalize()?,
+            dir.path().join("nested").canonicalize()?
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reset_restores_default_state() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let mut bash = Shared::new(Bash::new());
+
+        bash.call(command(&format!("cd {}", dir.path().display())))
+            .await
+            .unwrap();
+        bash.reset();
+
+        assert_eq!(bash.lock().await.working_directory(), ".");
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+//! Condensing long tool output before it goes back to the model.
+//!
+//! A build log or a large file can take up most of a small context window.
+//! When `[tools.summarize]` is enabled and a tool's output is over its token
+//! budget, the model is asked, in a request of its own, for the facts in the
+//! output that bear on the user's question, and the main loop is sent that
+//! summary instead, marked as one and noting how long the output was. The
+//! output itself stays in the tool call's record, and the model gets it in
+//! full by making the same call again in the turn.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use crate::llm::ollama::LlmClient;
+use crate::llm::reasoning;
+use crate::stats;
+use crate::Message;
+
+/// Tokens of output a tool may return before it is summarized, unless
+/// configured otherwise
+pub const DEFAULT_BUDGET: usize = 2_000;
+
+/// How long a summary may take before the output is sent as it is
+pub const SUMMARY_TIMEOUT: Duration = Duration::from_secs(60);
+
+const INSTRUCTION: &str = "Condense the output of a tool to the facts that matter for the \
+                           user's question: names, numbers, paths, errors and results. Keep \
+                           exact values as they are and leave out everything else. Reply with \
+                           the condensed facts only.";
+
+/// The `[tools.summarize]` config table
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct SummarizeConfig {
+    /// Summarize long tool output at all; off unless set
+    pub enabled: bool,
+    /// Tokens of output over which a tool's output is summarized; 2000 if unset
+    pub budget_tokens: Option<usize>,
+    /// A smaller model to write the summaries; the session's model if unset
+    pub model: Option<String>,
+    /// Names of the tools whose output is summarized; all of them if unset
+    pub tools: Option<Vec<String>>,
+    /// Budgets of single tools, overriding `budget_tokens`, e.g. `bash = 4000`
+    pub budgets: BTreeMap<String, usize>,
+}
+
+impl SummarizeConfig {
+    /// The budget of the tool called `name`, or None if its output is never summarized
+    pub fn budget_for(&self, name: &str) -> Option<usize> {
+        let chosen = self
+            .tools
+            .as_ref()
+            .is_none_or(|tools| tools.iter().any(|tool| tool.eq_ignore_ascii_case(name)));
+        if !self.enabled || !chosen {
+            return None;
+        }
+        let budget = self
+            .budgets
+            .iter()
+            .find(|(tool, _)| tool.eq_ignore_ascii_case(name))
+            .map(|(_, budget)| *budget);
+        Some(budget.or(self.budget_tokens).unwrap_or(DEFAULT_BUDGET))
+    }
+
+    /// Whether `output` of the tool called `name` is over its budget
+    pub fn applies(&self, name: &str, output: &str) -> bool {
+        self.budget_for(name)
+            .is_some_and(|budget| stats::estimate_tokens(output) > budget)
+    }
+}
+
+/// A condensed tool output, with the tokens it took to write
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Summary {
+    /// What the model is sent in place of the output, marked as a summary
+    pub text: String,
+    /// Tokens of the summary request and its reply together
+    pub tokens: usize,
+}
+
+/// The request asking for the facts in `output` that answer `question`
+pub fn request(question: &str, tool: &str, output: &str) -> Vec<Message> {
+    vec![Message::user(format!(
+        "{}\n\nQuestion: {}\n\nOutput of {}:\n```\n{}\n```",
+        INSTRUCTION, question, tool, output
+    ))]
+}
+
+/// A summary as the model is sent it: marked as one, with how long the
+/// output was and how to get all of it
+pub fn marked(tool: &str, output: &str, summary: &str) -> String {
+    format!(
+        "[Summary of the output of {}, which was ~{} tokens long. Make the same call again \
+         to get the full output.]\n{}",
+        tool,
+        stats::estimate_tokens(output),
+        summary.trim()
+    )
+}
+
+/// Ask for a summary of `output` as it bears on `question`, from the
+/// configured model or else `client`'s. Fails if that takes longer than
+/// [`SUMMARY_TIMEOUT`] or comes back empty, and the output is sent as it is
+pub async fn summarize(
+    client: &dyn LlmClient,
+    config: &SummarizeConfig,
+    question: &str,
+    tool: &str,
+    output: &str,
+) -> Result<Summary> {
+    let other = match &config.model {
+        Some(model) => Some(client.for_model(model)?),
+        None => None,
+    };
+    let client = other.as_deref().unwrap_or(client);
+    let messages = request(question, tool, output);
+    let (text, input_tokens, output_tokens) =
+        tokio::time::timeout(SUMMARY_TIMEOUT, client.generate_response(&messages))
+            .await
+            .map_err(|_| anyhow!("The summary of {}'s output timed out", tool))??;
+    let summary = reasoning::split(&text).content;
+    if summary.trim().is_empty() {
+        return Err(anyhow!("The summary of {}'s output came back empty", tool));
+    }
+    Ok(Summary {
+        text: marked(tool, output, &summary),
+        tokens: input_tokens + output_tokens,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::mock::MockLlmClient;
+
+    #[test]
+    fn test_budgets_are_chosen_per_tool() {
+        let mut config = SummarizeConfig::default();
+        assert_eq!(config.budget_for("bash"), None);
+
+        config.enabled = true;
+        assert_eq!(config.budget_for("bash"), Some(DEFAULT_BUDGET));
+        config.budget_tokens = Some(500);
+        config.budgets.insert("bash".to_string(), 4_000);
+        assert_eq!(config.budget_for("BASH"), Some(4_000));
+        assert_eq!(config.budget_for("file"), Some(500));
+
+        config.tools = Some(vec!["bash".to_string()]);
+        assert_eq!(config.budget_for("file"), None);
+        assert!(config.applies("bash", &"x".repeat(4 * 4_001)));
+        assert!(!config.applies("bash", &"x".repeat(4 * 4_000)));
+    }
+
+    #[tokio::test]
+    async fn test_summaries_come_from_the_configured_model() -> Result<()> {
+        let small = MockLlmClient::new().with_reply("<think>Long log</think>3 tests failed");
+        let client = MockLlmClient::new().with_model_client("tiny", small.clone());
+        let config = SummarizeConfig {
+            enabled: true,
+            model: Some("tiny".to_string()),
+            ..SummarizeConfig::default()
+        };
+        let output = "ok\n".repeat(100);
+        let summary = summarize(&client, &config, "did it pass?", "bash", &output).await?;
+        assert_eq!(
+            summary.text,
+            "[Summary of the output of bash, which was ~75 tokens long. Make the same call \
+             again to get the full output.]\n3 tests failed"
+        );
+        assert!(summary.tokens > 75);
+        assert!(client.received().is_empty());
+        let prompt = &small.received()[0][0].content;
+        assert!(prompt.contains("Question: did it pass?"));
+        assert!(prompt.ends_with(&format!("Output of bash:\n```\n{}\n```", output)));
+
+        // An empty summary leaves the output as it is
+        let empty = MockLlmClient::new().with_reply("<think>hm</think>");
+        let error = summarize(&empty, &SummarizeConfig::default(), "q", "ls", &output).await;
+        assert!(error.is_err());
+        Ok(())
+    }
+}
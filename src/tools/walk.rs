@@ -0,0 +1,250 @@
+//! Which entries the tools that walk a directory tree leave out.
+//!
+//! The ls and find_file tools see the same tree. Built-in rules hide hidden
+//! entries, common build and cache directories and compiled files, and the
+//! project's `.gitignore` files hide what git ignores, nested ones and
+//! negations such as `!keep.log` included. A path is left out if either hides
+//! it. The `[tools.walk]` config table, or the parameters of a call, can turn
+//! the gitignore rules off and show built-in entries anyway.
+
+use std::path::Path;
+
+use ignore::WalkBuilder;
+use serde::Deserialize;
+
+// Directories and files hidden by name
+const HIDDEN_NAMES: [&str; 12] = [
+    "__pycache__",
+    "node_modules",
+    "dist",
+    "build",
+    "target",
+    "vendor",
+    "bin",
+    "obj",
+    ".git",
+    ".idea",
+    ".vscode",
+    ".DS_Store",
+];
+
+// Compiled files, hidden by extension
+const HIDDEN_EXTENSIONS: [&str; 6] = [".pyc", ".pyo", ".pyd", ".so", ".dll", ".exe"];
+
+/// The `[tools.walk]` config table
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct WalkConfig {
+    /// Leave out what the project's `.gitignore` files ignore
+    pub respect_gitignore: bool,
+    /// Names or glob patterns of entries the built-in rules hide that are
+    /// shown anyway, e.g. "bin" or ".github"
+    pub unhide: Vec<String>,
+}
+
+impl Default for WalkConfig {
+    fn default() -> Self {
+        Self {
+            respect_gitignore: true,
+            unhide: Vec::new(),
+        }
+    }
+}
+
+/// What one walk leaves out: the config, as the call's parameters change it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WalkRules {
+    respect_gitignore: bool,
+    unhide: Vec<String>,
+    // Glob patterns of names the call itself asked to leave out
+    ignore: Vec<String>,
+}
+
+impl Default for WalkRules {
+    fn default() -> Self {
+        Self::new(&WalkConfig::default())
+    }
+}
+
+impl WalkRules {
+    pub fn new(config: &WalkConfig) -> Self {
+        Self {
+            respect_gitignore: config.respect_gitignore,
+            unhide: config.unhide.clone(),
+            ignore: Vec::new(),
+        }
+    }
+
+    /// Follow or ignore the gitignore files, if the call says which
+    pub fn respect_gitignore(mut self, respect: Option<bool>) -> Self {
+        if let Some(respect) = respect {
+            self.respect_gitignore = respect;
+        }
+        self
+    }
+
+    /// Also show the built-in entries matching `patterns`
+    pub fn unhide(mut self, patterns: impl IntoIterator<Item = String>) -> Self {
+        self.unhide.extend(patterns);
+        self
+    }
+
+    /// Also leave out the entries whose names match `patterns`
+    pub fn ignore(mut self, patterns: impl IntoIterator<Item = String>) -> Self {
+        self.ignore.extend(patterns);
+        self
+    }
+
+    /// Whether the built-in rules or the call's own patterns leave out `path`.
+    /// The gitignore files are only read by a walk
+    pub fn skips(&self, path: &Path) -> bool {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let matches = |patterns: &[String]| {
+            patterns
+                .iter()
+                .any(|pattern| pattern == &name || glob_match::glob_match(pattern, &name))
+        };
+        if matches(&self.ignore) {
+            return true;
+        }
+        let hidden = (name != "." && name.starts_with('.'))
+            || HIDDEN_NAMES.contains(&name.as_str())
+            || HIDDEN_EXTENSIONS.iter().any(|ext| name.ends_with(ext));
+        hidden && !matches(&self.unhide)
+    }
+
+    /// A walk of `root` that leaves out what these rules do. The root itself
+    /// is always walked
+    pub fn walker(&self, root: &Path) -> WalkBuilder {
+        let mut builder = self.gitignore_walker(root);
+        let rules = self.clone();
+        builder.filter_entry(move |entry| entry.depth() == 0 || !rules.skips(entry.path()));
+        builder
+    }
+
+    /// A walk of `root` that leaves out the directories these rules do, and
+    /// only the files the gitignores do, for lookups by a file's own name
+    pub fn directory_walker(&self, root: &Path) -> WalkBuilder {
+        let mut builder = self.gitignore_walker(root);
+        let rules = self.clone();
+        builder.filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|kind| kind.is_dir());
+            entry.depth() == 0 || !is_dir || !rules.skips(entry.path())
+        });
+        builder
+    }
+
+    // A walk of `root` leaving out only what the gitignores do, if followed
+    fn gitignore_walker(&self, root: &Path) -> WalkBuilder {
+        let mut builder = WalkBuilder::new(root);
+        builder
+            // Hidden entries are left to the built-in rules, so they can be unhidden
+            .standard_filters(false)
+            .git_ignore(self.respect_gitignore)
+            .git_exclude(self.respect_gitignore)
+            .parents(self.respect_gitignore)
+            // A project that isn't a git repository yet still means its .gitignore
+            .require_git(false);
+        builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    // The files a walk of `root` finds, relative to it
+    fn walk(rules: &WalkRules, root: &Path) -> Vec<String> {
+        let mut files: Vec<String> = rules
+            .walker(root)
+            .build()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_some_and(|kind| kind.is_file()))
+            .map(|entry| {
+                let relative = entry.path().strip_prefix(root).unwrap();
+                relative.to_string_lossy().replace('\\', "/")
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn project() -> anyhow::Result<TempDir> {
+        let dir = TempDir::new()?;
+        let root = dir.path();
+        fs::create_dir_all(root.join("out"))?;
+        fs::create_dir_all(root.join("logs/old"))?;
+        fs::create_dir_all(root.join("bin"))?;
+        fs::write(root.join(".gitignore"), "out/\n*.log\n!keep.log\n")?;
+        // The nested file takes precedence over the one above it
+        fs::write(root.join("logs/.gitignore"), "!debug.log\nold/\n")?;
+        for file in [
+            "main.rs",
+            "out/app",
+            "trace.log",
+            "keep.log",
+            "logs/debug.log",
+            "logs/other.log",
+            "logs/old/a.txt",
+            "bin/tool.rs",
+        ] {
+            fs::write(root.join(file), "x")?;
+        }
+        Ok(dir)
+    }
+
+    #[test]
+    fn test_gitignores_and_built_in_rules_are_merged() -> anyhow::Result<()> {
+        let dir = project()?;
+        assert_eq!(
+            walk(&WalkRules::default(), dir.path()),
+            ["keep.log", "logs/debug.log", "main.rs"]
+        );
+
+        // Built-in entries can be shown again, and the gitignores turned off
+        let rules = WalkRules::default().unhide(["bin".to_string()]);
+        assert!(walk(&rules, dir.path()).contains(&"bin/tool.rs".to_string()));
+        let rules = WalkRules::new(&WalkConfig {
+            respect_gitignore: false,
+            unhide: Vec::new(),
+        });
+        assert_eq!(
+            walk(&rules, dir.path()),
+            [
+                "keep.log",
+                "logs/debug.log",
+                "logs/old/a.txt",
+                "logs/other.log",
+                "main.rs",
+                "out/app",
+                "trace.log"
+            ]
+        );
+        let rules = WalkRules::default().respect_gitignore(Some(false));
+        assert_eq!(walk(&rules, dir.path()).len(), 7);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unhidden_and_ignored_names() {
+        let rules = WalkRules::default()
+            .unhide([".github".to_string(), "*.so".to_string()])
+            .ignore(["*.txt".to_string()]);
+        assert!(!rules.skips(&PathBuf::from(".github")));
+        assert!(!rules.skips(&PathBuf::from("libfoo.so")));
+        assert!(rules.skips(&PathBuf::from("notes.txt")));
+    }
+
+    #[test]
+    fn test_config() {
+        let config: WalkConfig = toml::from_str("unhide = [\"bin\"]").unwrap();
+        assert!(config.respect_gitignore);
+        assert_eq!(config.unhide, ["bin"]);
+    }
+}
@@ -0,0 +1,315 @@
+//! Looking up the weather.
+//!
+//! The tool asks a wttr.in-style service for one line about a city's weather,
+//! as `{base_url}/{city}?format={format}&m` (or `&u` for imperial units). The
+//! service, units, format and timeout come from the `[tools.weather]` config
+//! table, and the model can ask for other units or another format in a call.
+
+use std::time::Duration;
+
+use ollama_rs::generation::tools::Tool;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::tools::result::ToolError;
+
+/// The service asked unless the config names another
+pub const DEFAULT_BASE_URL: &str = "https://wttr.in";
+
+/// Condition, temperature, humidity and wind, e.g. "Partly cloudy +12°C 71% ↙11km/h"
+pub const DEFAULT_FORMAT: &str = "%C+%t+%h+%w";
+
+/// Whether temperatures and speeds are given in metric or imperial units
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    #[default]
+    Metric,
+    Imperial,
+}
+
+impl Units {
+    // The wttr.in query flag for these units
+    fn flag(self) -> &'static str {
+        match self {
+            Self::Metric => "m",
+            Self::Imperial => "u",
+        }
+    }
+}
+
+/// The `[tools.weather]` config table
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct WeatherConfig {
+    /// The wttr.in-compatible service to ask, e.g. an internal mirror
+    pub base_url: String,
+    pub units: Units,
+    /// The wttr.in format string, e.g. "%C+%t"
+    pub format: String,
+    /// How long the service has to answer
+    pub timeout_secs: u64,
+}
+
+impl Default for WeatherConfig {
+    fn default() -> Self {
+        Self {
+            base_url: DEFAULT_BASE_URL.to_string(),
+            units: Units::default(),
+            format: DEFAULT_FORMAT.to_string(),
+            timeout_secs: 10,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct WeatherParams {
+    #[schemars(description = "City to get the weather for.")]
+    city: String,
+
+    #[schemars(description = "Optional: metric or imperial. Defaults to the user's setting")]
+    #[serde(default)]
+    units: Option<Units>,
+
+    #[schemars(
+        description = "Optional: a wttr.in format string, e.g. %C+%t for the condition and temperature only. Defaults to the condition, temperature, humidity and wind"
+    )]
+    #[serde(default)]
+    format: Option<String>,
+}
+
+impl WeatherParams {
+    /// Parameters for the weather in `city`, as configured
+    pub fn new(city: impl Into<String>) -> Self {
+        Self {
+            city: city.into(),
+            units: None,
+            format: None,
+        }
+    }
+}
+
+/// The weather tool, asking the configured service
+#[derive(Debug, Clone)]
+pub struct Weather {
+    config: WeatherConfig,
+    client: reqwest::Client,
+}
+
+impl Weather {
+    pub fn new(config: WeatherConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()
+            .unwrap_or_default();
+        Self { config, client }
+    }
+
+    /// The address asked for the weather described by `params`
+    pub fn url(&self, params: &WeatherParams) -> String {
+        format!(
+            "{}/{}?format={}&{}",
+            self.config.base_url.trim_end_matches('/'),
+            encode(params.city.trim()),
+            params.format.as_deref().unwrap_or(&self.config.format),
+            params.units.unwrap_or(self.config.units).flag()
+        )
+    }
+
+    /// Ask the service, explaining a timeout or an error status
+    pub async fn fetch(&self, params: &WeatherParams) -> Result<String, ToolError> {
+        if params.city.trim().is_empty() {
+            return Err(ToolError::InvalidParams("'city' is empty".to_string()));
+        }
+        let url = self.url(params);
+        let response = self.client.get(&url).send().await.map_err(|e| {
+            if e.is_timeout() {
+                ToolError::Failed(format!(
+                    "The weather service at {} did not answer within {}s",
+                    self.config.base_url, self.config.timeout_secs
+                ))
+            } else {
+                ToolError::Failed(format!("Could not reach the weather service: {}", e))
+            }
+        })?;
+
+        let status = response.status();
+        let text = response
+            .text()
+            .await
+            .map_err(|e| ToolError::Failed(format!("Could not read the weather: {}", e)))?;
+        if !status.is_success() {
+            return Err(ToolError::Failed(format!(
+                "The weather service answered {}: {}",
+                status,
+                text.trim()
+            )));
+        }
+        Ok(text.trim().to_string())
+    }
+}
+
+impl Tool for Weather {
+    type Params = WeatherParams;
+
+    fn name() -> &'static str {
+        "get_weather"
+    }
+
+    fn description() -> &'static str {
+        "Get the weather for a given city."
+    }
+
+    async fn call(
+        &mut self,
+        parameters: Self::Params,
+    ) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
+        Ok(self.fetch(&parameters).await?)
+    }
+}
+
+// Percent-encode a city for the path, keeping letters of any script as they are
+fn encode(city: &str) -> String {
+    let mut encoded = String::new();
+    for c in city.chars() {
+        match c {
+            c if c.is_alphanumeric() && !c.is_ascii() => encoded.push(c),
+            'A'..='Z' | 'a'..='z' | '0'..='9' | '-' | '_' | '.' | '~' => encoded.push(c),
+            c => {
+                let mut bytes = [0; 4];
+                for byte in c.encode_utf8(&mut bytes).bytes() {
+                    encoded.push_str(&format!("%{:02X}", byte));
+                }
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // A weather service answering `status` and `body`, or never answering if
+    // `body` is None; records the request lines it gets
+    async fn serve(
+        status: &'static str,
+        body: Option<&'static str>,
+    ) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buffer = [0u8; 4096];
+                let read = stream.read(&mut buffer).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buffer[..read]).to_string();
+                seen.lock()
+                    .unwrap()
+                    .push(request.lines().next().unwrap_or("").to_string());
+
+                let Some(body) = body else {
+                    tokio::time::sleep(Duration::from_secs(30)).await;
+                    continue;
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (base_url, requests)
+    }
+
+    fn service(base_url: &str) -> Weather {
+        Weather::new(WeatherConfig {
+            base_url: format!("{}/", base_url),
+            ..WeatherConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_urls_use_the_config_and_the_call() {
+        let weather = service("https://weather.internal");
+        assert_eq!(
+            weather.url(&WeatherParams::new("New York")),
+            "https://weather.internal/New%20York?format=%C+%t+%h+%w&m"
+        );
+        assert_eq!(
+            weather.url(&WeatherParams::new("Zürich")),
+            "https://weather.internal/Zürich?format=%C+%t+%h+%w&m"
+        );
+
+        let params = WeatherParams {
+            city: "Tbilisi".to_string(),
+            units: Some(Units::Imperial),
+            format: Some("%t".to_string()),
+        };
+        assert_eq!(
+            weather.url(&params),
+            "https://weather.internal/Tbilisi?format=%t&u"
+        );
+
+        let imperial = Weather::new(WeatherConfig {
+            units: Units::Imperial,
+            ..WeatherConfig::default()
+        });
+        assert_eq!(
+            imperial.url(&WeatherParams::new("Oslo")),
+            "https://wttr.in/Oslo?format=%C+%t+%h+%w&u"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_the_service_is_asked_for_the_city() {
+        let (base_url, requests) = serve("200 OK", Some("Sunny +25°C 40% ↑5km/h\n")).await;
+        let mut weather = service(&base_url);
+        let output = weather.call(WeatherParams::new("Tbilisi")).await.unwrap();
+        assert_eq!(output, "Sunny +25°C 40% ↑5km/h");
+        assert_eq!(
+            requests.lock().unwrap().as_slice(),
+            ["GET /Tbilisi?format=%C+%t+%h+%w&m HTTP/1.1"]
+        );
+
+        let (base_url, _) = serve("404 Not Found", Some("Unknown location")).await;
+        let error = service(&base_url)
+            .fetch(&WeatherParams::new("Atlantis"))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The weather service answered 404 Not Found: Unknown location"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_slow_services_time_out() {
+        let (base_url, _) = serve("200 OK", None).await;
+        let weather = Weather::new(WeatherConfig {
+            base_url: base_url.clone(),
+            timeout_secs: 1,
+            ..WeatherConfig::default()
+        });
+
+        let started = std::time::Instant::now();
+        let error = weather
+            .fetch(&WeatherParams::new("Oslo"))
+            .await
+            .unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "The weather service at {} did not answer within 1s",
+                base_url
+            )
+        );
+    }
+}
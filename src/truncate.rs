@@ -0,0 +1,278 @@
+//! Shortening text for the model without cutting lines or code blocks in half.
+//!
+//! Text is only cut between lines, so CRLF endings stay whole. When a cut
+//! falls inside a fenced code block, the block is closed before the marker
+//! and opened again after it, so the model never sees an unbalanced fence.
+//! Which part goes depends on the text: files lose their middle, command
+//! output its start, since the latest output matters most.
+
+/// Which part of a text too long to send is left out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Keep the start and the end, as for files
+    Middle,
+    /// Keep the end, as for command output
+    Head,
+}
+
+/// What stands in for the lines left out, e.g.
+/// "... [120 lines truncated, 4812 bytes] ..."
+pub fn marker(lines: usize, bytes: usize) -> String {
+    format!("... [{} lines truncated, {} bytes] ...", lines, bytes)
+}
+
+/// `content` cut down to about `max_length` bytes, whole lines at a time.
+/// A text of a single line too long to keep loses characters instead.
+pub fn truncate(content: &str, max_length: usize, strategy: Strategy) -> String {
+    if content.len() <= max_length {
+        return content.to_string();
+    }
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let head_length = match strategy {
+        Strategy::Middle => max_length / 2,
+        Strategy::Head => 0,
+    };
+    let head = fitting(lines.iter(), head_length);
+    let tail = lines.len() - fitting(lines[head..].iter().rev(), max_length - head_length);
+    if head == 0 && tail == lines.len() {
+        return cut_characters(content, max_length, strategy);
+    }
+
+    let mut fences = Fences::default();
+    lines[..head].iter().for_each(|line| fences.push(line));
+    let mut text: String = lines[..head].concat();
+    if let Some(closing) = fences.closing() {
+        text.push_str(&closing);
+        text.push_str(ending(lines[head - 1]));
+    }
+    if head > 0 {
+        text.push('\n');
+    }
+    lines[head..tail].iter().for_each(|line| fences.push(line));
+    text.push_str(&marker(tail - head, lines[head..tail].concat().len()));
+    text.push_str("\n\n");
+    if let Some(opening) = lines.get(tail).and_then(|next| fences.reopening(next)) {
+        text.push_str(&opening);
+    }
+    text.push_str(&lines[tail..].concat());
+    text
+}
+
+/// The fenced code block open after the lines pushed so far, if any
+#[derive(Debug, Clone, Default)]
+pub struct Fences {
+    open: Option<Fence>,
+}
+
+#[derive(Debug, Clone)]
+struct Fence {
+    // The line that opened the block, without its ending
+    opening: String,
+    mark: char,
+    length: usize,
+}
+
+impl Fences {
+    /// Follow the next line of the text
+    pub fn push(&mut self, line: &str) {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        if indent > 3 {
+            return;
+        }
+        let rest = &line[indent..];
+        let Some(mark) = rest.chars().next().filter(|c| *c == '`' || *c == '~') else {
+            return;
+        };
+        let length = rest.len() - rest.trim_start_matches(mark).len();
+        if length < 3 {
+            return;
+        }
+        match &self.open {
+            Some(fence) => {
+                let closes = mark == fence.mark
+                    && length >= fence.length
+                    && rest[length..].trim().is_empty();
+                if closes {
+                    self.open = None;
+                }
+            }
+            None => {
+                self.open = Some(Fence {
+                    opening: line.to_string(),
+                    mark,
+                    length,
+                })
+            }
+        }
+    }
+
+    /// The line that opened the block still open, to open it again after a cut
+    pub fn opening(&self) -> Option<&str> {
+        self.open.as_ref().map(|fence| fence.opening.as_str())
+    }
+
+    /// The line that opened the block still open, ended like `next`, to go
+    /// before `next` when the lines in between were cut
+    pub fn reopening(&self, next: &str) -> Option<String> {
+        self.opening()
+            .map(|opening| format!("{}{}", opening, ending(next)))
+    }
+
+    /// A fence closing the block still open
+    pub fn closing(&self) -> Option<String> {
+        self.open
+            .as_ref()
+            .map(|fence| fence.mark.to_string().repeat(fence.length))
+    }
+}
+
+// How many of `lines` fit in `length` bytes, taken in order
+fn fitting<'a>(lines: impl Iterator<Item = &'a &'a str>, length: usize) -> usize {
+    let mut used = 0;
+    lines
+        .take_while(|line| {
+            used += line.len();
+            used <= length
+        })
+        .count()
+}
+
+// The line ending of `line`, or a newline for the last line of a text
+fn ending(line: &str) -> &'static str {
+    if line.ends_with("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    }
+}
+
+// A text too long to keep on one line, cut at character boundaries
+fn cut_characters(content: &str, max_length: usize, strategy: Strategy) -> String {
+    let boundary = |mut at: usize| {
+        while !content.is_char_boundary(at) {
+            at -= 1;
+        }
+        at
+    };
+    let (head, tail) = match strategy {
+        Strategy::Middle => (
+            boundary(max_length / 2),
+            boundary(content.len() - max_length / 2),
+        ),
+        Strategy::Head => (0, boundary(content.len() - max_length)),
+    };
+    let cut = &content[head..tail];
+    let marker = marker(cut.matches('\n').count(), cut.len());
+    match strategy {
+        Strategy::Middle => format!("{}\n\n{}\n\n{}", &content[..head], marker, &content[tail..]),
+        Strategy::Head => format!("{}\n\n{}", marker, &content[tail..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(range: std::ops::Range<usize>) -> String {
+        range.map(|n| format!("line {}\n", n)).collect()
+    }
+
+    #[test]
+    fn test_short_text_is_unchanged() {
+        let text = numbered(0..5);
+        assert_eq!(truncate(&text, 1000, Strategy::Middle), text);
+        assert_eq!(truncate(&text, text.len(), Strategy::Head), text);
+    }
+
+    #[test]
+    fn test_the_middle_is_cut_between_lines() {
+        let text = numbered(0..100);
+        let cut = truncate(&text, 100, Strategy::Middle);
+        assert!(cut.starts_with("line 0\nline 1\n"), "{}", cut);
+        assert!(cut.ends_with("line 98\nline 99\n"), "{}", cut);
+        assert_eq!(
+            cut,
+            format!(
+                "{}\n{}\n\n{}",
+                numbered(0..7),
+                marker(87, 693),
+                numbered(94..100)
+            )
+        );
+    }
+
+    #[test]
+    fn test_the_head_of_command_output_is_cut() {
+        let text = numbered(0..100);
+        let cut = truncate(&text, 80, Strategy::Head);
+        assert_eq!(cut, format!("{}\n\n{}", marker(90, 710), numbered(90..100)));
+    }
+
+    #[test]
+    fn test_fences_straddling_the_cut_are_closed_and_opened_again() {
+        let text = format!(
+            "intro\n```rust\n{}```\n\nafter the block\n",
+            numbered(0..50)
+        );
+        let cut = truncate(&text, 120, Strategy::Middle);
+        let (head, tail) = cut.split_once("... [").unwrap();
+        assert!(head.ends_with("line 5\n```\n\n"), "{}", head);
+        assert!(tail.contains("] ...\n\n```rust\nline 46\n"), "{}", tail);
+        assert!(cut.ends_with("line 49\n```\n\nafter the block\n"));
+        assert_eq!(cut.matches("```").count() % 2, 0);
+
+        // Output ending in the middle of a block is opened again after the marker
+        let text = format!("~~~~ text\n{}", numbered(0..50));
+        let cut = truncate(&text, 60, Strategy::Head);
+        assert!(cut.contains("] ...\n\n~~~~ text\nline 43\n"), "{}", cut);
+
+        // A block that ends before the cut is left alone
+        let text = format!("```\ncode\n```\n{}", numbered(0..50));
+        let cut = truncate(&text, 100, Strategy::Middle);
+        assert_eq!(cut.matches("```").count(), 2);
+        assert!(cut.starts_with("```\ncode\n```\nline 0\n"));
+    }
+
+    #[test]
+    fn test_fences_need_three_marks_and_close_with_at_least_as_many() {
+        let mut fences = Fences::default();
+        fences.push("``not a fence\n");
+        assert_eq!(fences.opening(), None);
+        fences.push("   ````python\n");
+        assert_eq!(fences.opening(), Some("   ````python"));
+        assert_eq!(fences.closing().as_deref(), Some("````"));
+        fences.push("```\n");
+        fences.push("~~~~\n");
+        assert!(fences.opening().is_some());
+        fences.push("`````  \r\n");
+        assert_eq!(fences.opening(), None);
+        fences.push("    ```indented code\n");
+        assert_eq!(fences.opening(), None);
+    }
+
+    #[test]
+    fn test_crlf_lines_stay_whole() {
+        let text: String = (0..50).map(|n| format!("row {}\r\n", n)).collect();
+        let cut = truncate(&format!("```\r\n{}```\r\n", text), 100, Strategy::Middle);
+        assert_eq!(cut.matches('\r').count(), cut.matches("\r\n").count());
+        assert!(cut.contains("row 5\r\n```\r\n\n... ["), "{:?}", cut);
+        assert!(cut.contains("] ...\n\n```\r\nrow "), "{:?}", cut);
+
+        let cut = truncate(&text, 50, Strategy::Head);
+        assert!(cut.ends_with("row 49\r\n"));
+        assert!(cut.split("\n\n").nth(1).unwrap().starts_with("row "));
+    }
+
+    #[test]
+    fn test_one_long_line_loses_characters() {
+        let text = "é".repeat(1000);
+        let cut = truncate(&text, 101, Strategy::Middle);
+        assert!(cut.starts_with("éé") && cut.ends_with("éé"));
+        assert!(cut.contains("[0 lines truncated, 1900 bytes]"), "{}", cut);
+
+        let cut = truncate(&text, 100, Strategy::Head);
+        assert!(cut.starts_with("... [0 lines truncated, 1900 bytes] ...\n\né"));
+        assert_eq!(cut.matches('é').count(), 50);
+    }
+}
@@ -1,69 +1,505 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{backend::CrosstermBackend, Terminal};
 use std::{
-    io,
+    collections::VecDeque,
+    io::{self, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     time::{Duration, Instant},
 };
+use tokio::sync::oneshot;
 
+use crate::activity::Activity;
+use crate::agent::{Agent, AgentEvent};
+use crate::attachments::{self, Attachment};
+use crate::autosave::{Autosave, Previous};
+use crate::session_blobs::{BlobStore, SessionsConfig};
+use crate::session_file::{SessionFile, StoredRole};
+use crate::candidates::Candidate;
+use crate::compare::Comparison;
+use crate::commands::{self, CommandError, CommandHelp, Parsed, COMMANDS};
+use crate::config::{self, Config, LayoutMode};
+use crate::console;
+use crate::context::Window;
+use crate::history::{HistoryCursor, InputHistory};
+use crate::images::ImageAttachment;
+use crate::interrupt::Interrupter;
+use crate::links::{self, Target};
+use crate::llm::availability;
+use crate::llm::endpoint::Endpoint;
+use crate::llm::failover::FailoverClient;
 use crate::llm::ollama::{LlmClient, OllamaClient};
+use crate::llm::timings::{self, ServerTimings};
+use crate::memory::Memory;
+use crate::notify::Notifier;
+use crate::prime::Primer;
+use crate::privacy;
+use crate::recording::{self, Tape};
+use crate::session::{self, SessionMeta, Sessions};
+use crate::stats::{self, SessionStats};
+use crate::status::{self, WindowTitle};
+use crate::title;
+use crate::tools::progress::Progress;
+use crate::tools::review::Reviewer;
+use crate::tools::schema;
 use crate::tui::{
-    message::UiMessage,
-    ui::render_ui,
+    clipboard,
+    keymap::{Action, Keymap, Mode},
+    message::{self, MessageRole, UiMessage},
+    search::Search,
+    progress::{self, TuiProgress},
+    review::TuiReviewer,
+    theme::{self, Theme},
+    ui::{self, render_ui},
 };
 
+/// The system prompt unless the config sets one
+const DEFAULT_SYSTEM_PROMPT: &str = "You are a helpful AI assistant.";
+
+/// How long the status bar flashes after a slow response finishes
+const FLASH_DURATION: Duration = Duration::from_secs(2);
+
+/// The most messages that can wait for the reply being generated
+const MAX_QUEUED: usize = 5;
+
+/// How far PageUp and PageDown scroll the /diff overlay
+const DIFF_PAGE: isize = 20;
+
 /// Input mode for the TUI
 enum InputMode {
     Normal,
     Editing,
+    Search,
 }
 
 /// TUI Application state
 pub struct SentinelApp {
-    // LLM client
-    llm_client: OllamaClient,
+    // The agent that sends the messages and runs the commands shared with the REPL
+    agent: Agent,
+    // The input waiting for the agent, a message or a command
+    pending: Option<String>,
     
-    // Message history
-    messages: Vec<UiMessage>,
+    // Message history as shown, with notes between the messages and any
+    // branches forked from it; the agent is handed the active branch each time
+    sessions: Sessions<UiMessage>,
+    
+    // The title and start of the conversation, and the client asking the
+    // model for a title after the first reply, if titles are generated
+    meta: SessionMeta,
+    titler: Option<Arc<dyn LlmClient>>,
+    title_requested: bool,
+    pending_title: Option<oneshot::Receiver<Option<String>>>,
     
     // Input state
     input: String,
-    input_history: Vec<String>,
-    input_history_index: usize,
+    input_history: InputHistory,
+    history_cursor: HistoryCursor,
+    history_search: Option<HistorySearch>,
+    
+    // How long loading the model in the background took, once it is done
+    warming_up: Option<oneshot::Receiver<Option<ServerTimings>>>,
+    
+    // The candidates of a /best, and the replies of a /compare beside each other
+    candidate_picker: Option<CandidatePicker>,
+    comparison: Option<ComparisonView>,
+    
+    // The links of the selected message, when it has several to choose from
+    link_picker: Option<LinkPicker>,
+    
+    // Where the contents moved out of the saved session are read back from
+    blobs: Option<BlobStore>,
+    
+    // Conversation search, and whether its input is open
+    search: Search,
+    searching: bool,
+    
+    // The message picked with the arrow keys, or None to follow the latest one
+    selected: Option<usize>,
+    
+    // Key bindings, and whether the overlay listing them is open
+    keymap: Keymap,
+    show_help: bool,
+    
+    // The colors, shared with the overlays drawn while a reply is awaited
+    theme: Arc<Mutex<Theme>>,
+    
+    // What the tools changed this session, shown by /diff until a key closes it
+    diff_view: Option<DiffView>,
+    
+    // Whether the reasoning of reasoning models is shown with their replies
+    show_reasoning: bool,
+    
+    // Whether long system messages are shown in full rather than as one line
+    expand_system: bool,
     
-    // Loading state
+    // Whether the stats panel, or its line on narrow terminals, is shown
+    show_stats: bool,
+    
+    // Whether the stats panel counts the words of the replies, from the config
+    word_counts: bool,
+    
+    // The full or the zen layout, the config file a switch is saved to, and
+    // whether zen shows the stats over the conversation
+    layout: LayoutMode,
+    layout_file: Option<PathBuf>,
+    zen_stats: bool,
+    
+    // Until when the status bar flashes after a slow response
+    flash_until: Option<Instant>,
+    
+    // How long a tool call takes to be slow
+    slow_call: Duration,
+    
+    // Whether the agent is busy with the pending input
     is_loading: bool,
+    
+    // Messages sent while a reply is generated, oldest first
+    queued: VecDeque<UiMessage>,
+    
+    // Whether /exit asked to quit
+    exit_requested: bool,
 }
 
 impl SentinelApp {
-    /// Create a new application
-    fn new() -> Self {
-        // Create LLM client
-        let llm_client = OllamaClient::new();
+    /// Create an application talking to any LLM client
+    pub fn with_client(llm_client: Box<dyn LlmClient>, model: &str) -> Self {
+        let agent = Agent::with_client(llm_client, model).with_system_prompt(DEFAULT_SYSTEM_PROMPT);
+        
+        // Load prompts from previous sessions
+        let input_history = InputHistory::load_default();
         
-        // Add a system message to start
-        let mut messages = Vec::new();
-        messages.push(UiMessage::system(
-            "You are a helpful AI assistant.".to_string(),
-        ));
+        // Start with the system message
+        let messages = agent.conversation().iter().cloned().map(UiMessage::from).collect();
         
         Self {
-            llm_client,
-            messages,
+            agent,
+            pending: None,
+            sessions: Sessions::new(messages),
+            meta: SessionMeta::new(model),
+            titler: None,
+            title_requested: false,
+            pending_title: None,
             input: String::new(),
-            input_history: Vec::new(),
-            input_history_index: 0,
+            history_cursor: HistoryCursor::default(),
+            input_history,
+            history_search: None,
+            warming_up: None,
+            candidate_picker: None,
+            comparison: None,
+            link_picker: None,
+            blobs: None,
+            search: Search::new(),
+            searching: false,
+            selected: None,
+            keymap: Keymap::default(),
+            show_help: false,
+            theme: Arc::default(),
+            diff_view: None,
+            show_reasoning: false,
+            expand_system: false,
+            show_stats: true,
+            word_counts: false,
+            layout: LayoutMode::Full,
+            layout_file: None,
+            zen_stats: false,
+            flash_until: None,
+            slow_call: stats::DEFAULT_SLOW_CALL,
             is_loading: false,
+            queued: VecDeque::new(),
+            exit_requested: false,
+        }
+    }
+    
+    /// Apply a config: its system prompt, response limits, the tools it
+    /// enables, its sandbox root and protected paths
+    pub fn with_config(mut self, config: &Config) -> Self {
+        self.agent = self.agent.with_config(config);
+        self.adopt(usize::MAX);
+        self.slow_call = config.tools.slow_call();
+        self.word_counts = config.word_counts;
+        self.layout = config.layout;
+        
+        for warning in &config.warnings {
+            self.push_note(warning.clone());
+        }
+        self
+    }
+    
+    /// Continue a conversation and the branches forked from it, e.g. ones
+    /// restored after a crash
+    pub fn with_sessions(mut self, sessions: Sessions<UiMessage>) -> Self {
+        self.sessions = sessions;
+        let conversation = self.conversation();
+        self.agent = self.agent.with_messages(conversation);
+        self.adopt(usize::MAX);
+        self
+    }
+    
+    /// Continue a saved session, keeping its title and branches
+    pub fn with_session(mut self, session: SessionFile) -> Self {
+        self.meta.title = session.metadata.title.clone();
+        self.meta.created = session.metadata.created.or(self.meta.created);
+        self.agent = self.agent.with_stats(session.metadata.stats.clone());
+        self.with_sessions(session.into_sessions())
+    }
+    
+    /// Remember facts in `memory` across sessions, giving the model the memory tool
+    pub fn with_memory(mut self, memory: Arc<Memory>) -> Self {
+        self.agent = self.agent.with_memory(memory);
+        self.adopt(usize::MAX);
+        self
+    }
+    
+    /// Ask `titler` for a title after the first reply, in the background
+    pub fn with_titler(mut self, titler: Arc<dyn LlmClient>) -> Self {
+        self.titler = Some(titler);
+        self
+    }
+    
+    /// Use key bindings other than the defaults
+    pub fn with_keymap(mut self, keymap: Keymap) -> Self {
+        self.keymap = keymap;
+        self
+    }
+    
+    /// Draw in `theme`, shared with the overlays, instead of the dark theme
+    pub fn with_theme(mut self, theme: Arc<Mutex<Theme>>) -> Self {
+        self.theme = theme;
+        self
+    }
+    
+    /// Save switches of the layout to the config file at `path`
+    pub fn with_layout_file(mut self, path: Option<PathBuf>) -> Self {
+        self.layout_file = path;
+        self
+    }
+    
+    /// Preview mutating tool actions instead of running them
+    pub fn with_dry_run(self, dry_run: bool) -> Self {
+        self.agent.policy().set_dry_run(dry_run);
+        self.agent.client().set_dry_run(dry_run);
+        self
+    }
+    
+    /// Notify about slow responses as configured instead of not at all
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.agent = self.agent.with_notifier(notifier);
+        self
+    }
+    
+    /// Ask the reviewer before the tools change any file
+    pub fn with_reviewer(mut self, reviewer: Arc<dyn Reviewer>) -> Self {
+        self.agent = self.agent.with_reviewer(reviewer);
+        self
+    }
+    
+    /// Record what replies are doing in `activity`, e.g. to share it with
+    /// the tool progress
+    pub fn with_activity(mut self, activity: Arc<Activity>) -> Self {
+        self.agent = self.agent.with_activity(activity);
+        self
+    }
+    
+    /// Show the output of running tools as it is printed
+    pub fn with_progress(mut self, progress: Arc<dyn Progress>) -> Self {
+        self.agent = self.agent.with_progress(progress);
+        self
+    }
+    
+    /// Show that the model is loading until `warming_up` sends how long it
+    /// took, or None if it failed
+    pub fn with_warming_up(mut self, warming_up: oneshot::Receiver<Option<ServerTimings>>) -> Self {
+        self.warming_up = Some(warming_up);
+        self
+    }
+    
+    /// Check if the model is still being loaded in the background
+    pub fn is_warming_up(&self) -> bool {
+        self.warming_up.is_some()
+    }
+    
+    /// Say how long loading the model took, once it is done
+    pub fn poll_warm_up(&mut self) {
+        let Some(receiver) = &mut self.warming_up else {
+            return;
+        };
+        match receiver.try_recv() {
+            Err(oneshot::error::TryRecvError::Empty) => {}
+            Ok(Some(timings)) => {
+                self.warming_up = None;
+                self.push_note(timings::warm_up_note(self.agent.model(), &timings));
+            }
+            // A failed warm-up is left for the first request to report
+            Ok(None) | Err(oneshot::error::TryRecvError::Closed) => self.warming_up = None,
+        }
+    }
+    
+    /// Check if the status bar should flash because a slow response just finished
+    pub fn is_flashing(&self) -> bool {
+        self.flash_until.is_some_and(|until| Instant::now() < until)
+    }
+    
+    /// Get the key bindings
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+    
+    /// Get the colors to draw in
+    pub fn theme(&self) -> Theme {
+        self.theme.lock().unwrap().clone()
+    }
+    
+    /// Check if the key binding overlay is open
+    pub fn help_visible(&self) -> bool {
+        self.show_help
+    }
+    
+    /// The /diff overlay, if it is open
+    pub fn diff_view(&self) -> Option<&DiffView> {
+        self.diff_view.as_ref()
+    }
+    
+    /// Scroll the /diff overlay by `lines`, up if negative
+    fn scroll_diff(&mut self, lines: isize) {
+        if let Some(view) = &mut self.diff_view {
+            let last = view.lines.len().saturating_sub(1);
+            view.scroll = view.scroll.saturating_add_signed(lines).min(last);
+        }
+    }
+    
+    /// Check if the reasoning of reasoning models is shown
+    pub fn reasoning_visible(&self) -> bool {
+        self.show_reasoning
+    }
+    
+    /// Check if long system messages are shown in full
+    pub fn system_expanded(&self) -> bool {
+        self.expand_system
+    }
+    
+    /// Check if the session stats are shown
+    pub fn stats_visible(&self) -> bool {
+        self.show_stats
+    }
+    
+    /// The full or the zen layout
+    pub fn layout(&self) -> LayoutMode {
+        self.layout
+    }
+    
+    /// Check if the zen layout shows the stats over the conversation
+    pub fn zen_stats_visible(&self) -> bool {
+        self.layout == LayoutMode::Zen && self.zen_stats
+    }
+    
+    /// Switch to the other layout and save it as the one to start in
+    fn toggle_layout(&mut self) {
+        self.layout = self.layout.toggled();
+        self.zen_stats = false;
+        if let Some(path) = &self.layout_file {
+            if let Err(e) = config::save_setting(path, "layout", self.layout.name()) {
+                self.push_note(format!("The {} layout is not saved: {:#}", self.layout.name(), e));
+            }
         }
     }
     
+    /// Check if the stats panel counts the words of the replies
+    pub fn word_counts_visible(&self) -> bool {
+        self.word_counts
+    }
+    
     /// Get the current message history
     pub fn messages(&self) -> &[UiMessage] {
-        &self.messages
+        self.sessions.messages()
+    }
+    
+    /// Get every branch of the conversation
+    pub fn sessions(&self) -> &Sessions<UiMessage> {
+        &self.sessions
+    }
+    
+    /// Get the name of the active branch
+    pub fn branch_name(&self) -> &str {
+        self.sessions.active_name()
+    }
+    
+    /// Get the title of the conversation, once it has one
+    pub fn title(&self) -> Option<&str> {
+        self.meta.title.as_deref()
+    }
+    
+    /// Get what the saved session says about the conversation
+    pub fn session_meta(&self) -> SessionMeta {
+        SessionMeta {
+            stats: self.agent.stats().clone(),
+            ..self.meta.clone()
+        }
+    }
+    
+    /// Ask for a title in the background once the conversation has a reply,
+    /// unless it has a title or one was asked for already
+    fn request_title(&mut self) {
+        if self.meta.title.is_some() || self.title_requested {
+            return;
+        }
+        let Some(titler) = self.titler.clone() else {
+            return;
+        };
+        let messages = self.sessions.messages();
+        let question = messages.iter().find(|message| message.role == MessageRole::User);
+        let answer = messages.iter().rev().find(|message| message.role == MessageRole::Assistant);
+        let (Some(question), Some(answer)) = (question, answer) else {
+            return;
+        };
+        
+        let (question, answer) = (question.content.clone(), answer.content.clone());
+        let (sender, receiver) = oneshot::channel();
+        tokio::spawn(async move {
+            let _ = sender.send(title::generate(titler.as_ref(), &question, &answer).await);
+        });
+        self.title_requested = true;
+        self.pending_title = Some(receiver);
+    }
+    
+    /// Take the title generated in the background, if it is in
+    pub fn poll_title(&mut self) {
+        let Some(receiver) = &mut self.pending_title else {
+            return;
+        };
+        match receiver.try_recv() {
+            Err(oneshot::error::TryRecvError::Empty) => {}
+            // A failed request leaves the conversation untitled
+            Ok(title) => {
+                self.meta.title = self.meta.title.take().or(title);
+                self.pending_title = None;
+            }
+            Err(oneshot::error::TryRecvError::Closed) => self.pending_title = None,
+        }
+    }
+    
+    /// Show the title, or set it instead of the generated one
+    fn set_title(&mut self, args: &str) {
+        if args.is_empty() {
+            let note = match &self.meta.title {
+                Some(title) => format!("Title: {}", title),
+                None => "This session has no title yet; /title <text> sets one".to_string(),
+            };
+            self.push_note(note);
+            return;
+        }
+        
+        let title = title::normalize(args);
+        self.pending_title = None;
+        self.push_note(format!("Session titled: {}", title));
+        self.meta.title = Some(title);
     }
     
     /// Get the current input text
@@ -76,14 +512,302 @@ impl SentinelApp {
         self.is_loading
     }
     
+    /// Messages waiting for the reply being generated, oldest first
+    pub fn queued(&self) -> &VecDeque<UiMessage> {
+        &self.queued
+    }
+    
+    /// A handle that cuts the reply being generated short
+    pub fn interrupter(&self) -> Interrupter {
+        self.agent.interrupter()
+    }
+    
+    /// What the reply being generated is doing, and for how long
+    pub fn activity(&self) -> Arc<Activity> {
+        self.agent.activity()
+    }
+    
     /// Get the model name
     pub fn model_name(&self) -> &str {
-        "llama3.2:latest" // Hardcoded for now as model is private in OllamaClient
+        self.agent.model()
+    }
+    
+    /// The Ollama server requests go to, if the client reports one
+    pub fn endpoint(&self) -> Option<String> {
+        self.agent.client().endpoint()
     }
     
-    /// Get the current tools that were used
+    /// Whether requests go to a server on another machine, for the status bar
+    pub fn remote(&self) -> bool {
+        self.endpoint().is_some_and(|endpoint| privacy::is_remote(&endpoint))
+    }
+    
+    /// Whether the tools refuse every change this session, for the status bar
+    pub fn read_only(&self) -> bool {
+        self.agent.policy().read_only()
+    }
+    
+    /// Whether the model is known to answer without tools, for the status bar
+    pub fn tools_unsupported(&self) -> bool {
+        self.agent.tools_unsupported()
+    }
+    
+    /// Warn in the conversation if the model is known not to call tools
+    pub async fn check_tool_support(&mut self) {
+        if let Some(warning) = self.agent.tool_warning().await {
+            self.push_note(format!("Warning: {}", warning));
+        }
+    }
+    
+    /// Size the context window for the model, warning in the conversation if
+    /// the configured one is more than the model supports
+    pub async fn check_context_window(&mut self) {
+        if let Some(warning) = self.agent.check_context_window().await {
+            self.push_note(format!("Warning: {}", warning));
+        }
+    }
+    
+    /// The context window of the model, once it was looked up
+    pub fn context_window(&self) -> Option<Window> {
+        self.agent.context_window()
+    }
+    
+    /// Get the files staged for the next message
+    pub fn staged_attachments(&self) -> &[Attachment] {
+        self.agent.staged_attachments()
+    }
+    
+    /// Get the images staged for the next message
+    pub fn staged_images(&self) -> &[ImageAttachment] {
+        self.agent.staged_images()
+    }
+    
+    /// Get the conversation search state
+    pub fn search(&self) -> &Search {
+        &self.search
+    }
+    
+    /// Whether the search input is open
+    pub fn is_searching(&self) -> bool {
+        self.searching
+    }
+    
+    /// Get the cumulative usage for the session
+    pub fn stats(&self) -> &SessionStats {
+        self.agent.stats()
+    }
+    
+    /// How long a tool call takes before it is highlighted as slow
+    pub fn slow_call(&self) -> Duration {
+        self.slow_call
+    }
+    
+    /// Get the tools the model is offered
+    pub fn available_tools(&self) -> Vec<String> {
+        self.agent.client().available_tools()
+    }
+    
+    /// Get the tools used for the latest response
     pub fn get_current_tools(&self) -> Vec<String> {
-        self.llm_client.get_last_used_tools()
+        self.sessions
+            .messages()
+            .iter()
+            .rev()
+            .find(|message| message.role == MessageRole::Assistant)
+            .map(|message| message.used_tools.clone())
+            .unwrap_or_default()
+    }
+    
+    /// Open the search input with an empty query
+    fn start_search(&mut self) {
+        self.search.clear();
+        self.searching = true;
+    }
+    
+    /// Add a character to the search query
+    fn search_input(&mut self, c: char) {
+        self.search.push(c);
+        self.refresh_search();
+    }
+    
+    /// Remove the last character from the search query
+    fn search_backspace(&mut self) {
+        self.search.pop();
+        self.refresh_search();
+    }
+    
+    /// Switch between substring and regex search
+    fn toggle_search_regex(&mut self) {
+        self.search.toggle_regex();
+        self.refresh_search();
+    }
+    
+    /// Close the search input, keeping the matches for n/N
+    fn finish_search(&mut self) {
+        self.searching = false;
+    }
+    
+    /// Close the search and remove all highlighting
+    fn clear_search(&mut self) {
+        self.search.clear();
+        self.searching = false;
+    }
+    
+    /// Jump to the next search match
+    fn next_match(&mut self) {
+        self.search.next();
+        self.selected = None;
+    }
+    
+    /// Jump to the previous search match
+    fn previous_match(&mut self) {
+        self.search.previous();
+        self.selected = None;
+    }
+    
+    /// The selected message, if any; without one the view follows the latest message
+    pub fn selected(&self) -> Option<usize> {
+        self.selected.filter(|&index| index < self.sessions.messages().len())
+    }
+    
+    /// Select the message above the selected one, starting from the latest
+    fn select_previous(&mut self) {
+        self.selected = match self.selected() {
+            Some(index) => Some(index.saturating_sub(1)),
+            None => self.sessions.messages().len().checked_sub(1),
+        };
+    }
+    
+    /// Select the message below the selected one, stopping at the latest
+    fn select_next(&mut self) {
+        let last = self.sessions.messages().len().saturating_sub(1);
+        self.selected = self.selected().map(|index| (index + 1).min(last));
+    }
+    
+    /// Drop the selection, so the view follows the latest message again
+    fn follow_latest(&mut self) {
+        self.selected = None;
+    }
+    
+    /// The selected message, or a note saying how to select one
+    fn require_selection(&mut self) -> Option<usize> {
+        let selected = self.selected();
+        if selected.is_none() {
+            let key = self.keymap.key(Mode::Normal, Action::SelectPrevious);
+            self.push_note(format!("No message is selected; press {} to select one", key));
+        }
+        selected
+    }
+    
+    /// Copy the selected message to the clipboard
+    fn copy_selected(&mut self) {
+        let Some(index) = self.require_selection() else {
+            return;
+        };
+        let content = self.sessions.messages()[index].content.clone();
+        if clipboard::copy(&content) {
+            self.push_note(format!("Copied message {} ({} characters)", index, content.chars().count()));
+        } else {
+            self.push_error(format!("Error: Could not copy message {}: the output is not a terminal", index));
+        }
+    }
+    
+    /// Open the URL or file path in the selected message, or offer a choice
+    /// if it has several
+    fn open_link(&mut self) {
+        let Some(index) = self.require_selection() else {
+            return;
+        };
+        // Relative paths are looked up where the tools would find them
+        let base = self.agent.policy().sandbox_root().or_else(|| std::env::current_dir().ok()).unwrap_or_default();
+        let content = &self.sessions.messages()[index].content;
+        let mut found: Vec<(String, Target)> = Vec::new();
+        for link in links::find(content, &base) {
+            if !found.iter().any(|(_, target)| *target == link.target) {
+                found.push((content[link.range].to_string(), link.target));
+            }
+        }
+        
+        match found.len() {
+            0 => self.push_note(format!("Message {} has no URLs or paths of existing files", index)),
+            1 => self.open_target(&found[0].1),
+            _ => self.link_picker = Some(LinkPicker { links: found, selected: 0 }),
+        }
+    }
+    
+    /// Hand `target` to the system opener
+    fn open_target(&mut self, target: &Target) {
+        match links::open(target, self.agent.policy()) {
+            Ok(()) => self.push_note(format!("Opened {}", target)),
+            Err(e) => self.push_error(format!("Error: Could not open {}: {:#}", target, e)),
+        }
+    }
+    
+    /// The links of the selected message, while one is being chosen
+    pub fn link_picker(&self) -> Option<&LinkPicker> {
+        self.link_picker.as_ref()
+    }
+    
+    /// Move the link selection up or down
+    fn select_link(&mut self, forward: bool) {
+        if let Some(picker) = &mut self.link_picker {
+            let last = picker.links.len() - 1;
+            picker.selected = match (forward, picker.selected) {
+                (true, selected) if selected < last => selected + 1,
+                (false, selected) if selected > 0 => selected - 1,
+                (_, selected) => selected,
+            };
+        }
+    }
+    
+    /// Open the chosen link and close the list
+    fn open_picked_link(&mut self) {
+        if let Some(mut picker) = self.link_picker.take() {
+            let (_, target) = picker.links.remove(picker.selected);
+            self.open_target(&target);
+        }
+    }
+    
+    /// Pin the selected message, or unpin it if it is pinned
+    fn toggle_pin(&mut self) {
+        if let Some(index) = self.require_selection() {
+            let pinned = !self.sessions.messages()[index].pinned;
+            self.pin(index, pinned);
+        }
+    }
+    
+    /// Start a new branch that ends with the selected message
+    fn fork_selected(&mut self) {
+        if let Some(index) = self.require_selection() {
+            self.fork_at(index);
+        }
+    }
+    
+    /// Show or hide all the details of the selected message
+    fn toggle_details(&mut self) {
+        let Some(index) = self.require_selection() else {
+            return;
+        };
+        let message = &mut self.sessions.messages_mut()[index];
+        message.expanded = !message.expanded;
+        
+        // Content moved out of a large saved session is read back only when asked for
+        let (Some(blob), Some(store)) = (message.blob.clone().filter(|_| message.expanded), &self.blobs) else {
+            return;
+        };
+        match store.get(&blob) {
+            Ok(content) => {
+                message.content = content;
+                message.blob = None;
+                self.refresh_search();
+            }
+            Err(e) => self.push_error(format!("Error: {:#}; the message's content could not be loaded", e)),
+        }
+    }
+    
+    /// Recompute the search matches against the current messages
+    fn refresh_search(&mut self) {
+        self.search.update(self.sessions.messages().iter().map(|msg| msg.content.as_str()));
     }
     
     /// Add a character to the input
@@ -98,104 +822,668 @@ impl SentinelApp {
     
     /// Go to the previous input in history
     fn previous_input(&mut self) {
-        if self.input_history.is_empty() {
-            return;
-        }
-        
-        if self.input_history_index > 0 {
-            self.input_history_index -= 1;
-            self.input = self.input_history[self.input_history_index].clone();
+        if let Some(entry) = self.history_cursor.previous(&self.input_history, &self.input) {
+            self.input = entry;
         }
     }
     
-    /// Go to the next input in history
+    /// Go to the next input in history, or back to what was being typed
     fn next_input(&mut self) {
-        if self.input_history.is_empty() {
-            return;
-        }
-        
-        if self.input_history_index < self.input_history.len() - 1 {
-            self.input_history_index += 1;
-            self.input = self.input_history[self.input_history_index].clone();
-        } else {
-            self.input_history_index = self.input_history.len();
-            self.input.clear();
+        if let Some(entry) = self.history_cursor.next(&self.input_history) {
+            self.input = entry;
         }
     }
     
-    /// Submit the current input as a message
-    fn submit_message(&mut self) -> Result<()> {
-        if self.input.trim().is_empty() || self.is_loading {
-            return Ok(());
-        }
-        
-        // Add the user message to our UI
-        let user_message = UiMessage::user(self.input.clone());
-        self.messages.push(user_message);
+    /// Get the reverse history search, if one is open
+    pub fn history_search(&self) -> Option<&HistorySearch> {
+        self.history_search.as_ref()
+    }
+    
+    /// The history entry the reverse search is on
+    pub fn history_search_match(&self) -> Option<&str> {
+        let index = self.history_search.as_ref()?.index?;
+        self.input_history.get(index)
+    }
+    
+    /// Start a reverse history search, or move to the next older match
+    fn reverse_search(&mut self) {
+        let Some(search) = &self.history_search else {
+            self.history_search = Some(HistorySearch::default());
+            return;
+        };
         
-        // Add to input history
-        if !self.input.trim().is_empty() {
-            self.input_history.push(self.input.clone());
-            self.input_history_index = self.input_history.len();
+        let before = search.index.unwrap_or(self.input_history.len());
+        if let Some(index) = self.input_history.find_before(&search.query, before) {
+            self.history_search = Some(HistorySearch {
+                query: search.query.clone(),
+                index: Some(index),
+            });
         }
-        
-        // Clear the input field and set loading state
-        self.input.clear();
-        self.is_loading = true;
-        
-        Ok(())
     }
     
-    /// Process the LLM response
-    async fn process_response(&mut self) -> Result<()> {
-        if !self.is_loading {
-            return Ok(());
-        }
-        
-        // Find the last user message
-        let message_index = self.messages.len() - 1;
-        let user_message = &self.messages[message_index];
-        
-        // Get previous conversation history - not using for now as we're just sending the last message
-        let _history = &self.messages[..message_index];
-            
-        // Add the user message
-        let last_user_message = crate::Message {
-            role: crate::Role::User,
-            content: user_message.content.clone(),
-            input_tokens: 0,
-            output_tokens: 0,
-            used_tools: Vec::new(),
+    /// Update the reverse search query and find the newest match for it
+    fn edit_history_search(&mut self, edit: impl FnOnce(&mut String)) {
+        let Some(search) = &mut self.history_search else {
+            return;
         };
         
-        // Generate response with tools
-        let (response_text, input_tokens, output_tokens, used_tools) = self
-            .llm_client
-            .generate_response_with_tools(&[last_user_message], &[])
-            .await?;
-            
-        // Create the response message
-        let response = UiMessage::assistant_with_tools(
-            response_text,
-            input_tokens,
-            output_tokens,
-            used_tools,
-        );
+        edit(&mut search.query);
+        search.index = if search.query.is_empty() {
+            None
+        } else {
+            self.input_history.find_before(&search.query, self.input_history.len())
+        };
+    }
+    
+    /// Put the matched entry in the input and close the search
+    fn accept_history_search(&mut self) {
+        if let Some(entry) = self.history_search_match() {
+            self.input = entry.to_string();
+        }
+        self.history_search = None;
+        self.history_cursor.reset();
+    }
+    
+    /// Close the search, leaving the input as it was
+    fn cancel_history_search(&mut self) {
+        self.history_search = None;
+    }
+    
+    /// The project context sent with each message, if any
+    pub fn primer(&self) -> Option<&Primer> {
+        self.agent.primer()
+    }
+    
+    /// Candidates from /best waiting to be picked
+    pub fn candidate_picker(&self) -> Option<&CandidatePicker> {
+        self.candidate_picker.as_ref()
+    }
+    
+    /// Move the candidate selection up or down
+    fn select_candidate(&mut self, forward: bool) {
+        if let Some(picker) = &mut self.candidate_picker {
+            let last = picker.candidates.len() - 1;
+            picker.selected = match (forward, picker.selected) {
+                (true, selected) if selected < last => selected + 1,
+                (false, selected) if selected > 0 => selected - 1,
+                (_, selected) => selected,
+            };
+        }
+    }
+    
+    /// Have the agent pick the selected candidate, running the tool calls it only simulated
+    fn accept_candidate(&mut self) {
+        if let Some(picker) = self.candidate_picker.take() {
+            let label = picker.candidates[picker.selected].label;
+            self.queue_input(format!("/pick {}", label));
+        }
+    }
+    
+    /// Close the candidate picker without keeping any candidate
+    fn discard_candidates(&mut self) {
+        if let Some(picker) = self.candidate_picker.take() {
+            self.agent.discard_candidates();
+            self.push_note(format!("Discarded {} candidates", picker.candidates.len()));
+        }
+    }
+    
+    /// The replies of a /compare, shown beside each other until one is promoted
+    pub fn comparison(&self) -> Option<&ComparisonView> {
+        self.comparison.as_ref()
+    }
+    
+    /// Move to the reply to the left or right in the comparison
+    fn select_compared(&mut self, forward: bool) {
+        if let Some(view) = &mut self.comparison {
+            let last = view.comparison.len() - 1;
+            view.selected = match (forward, view.selected) {
+                (true, selected) if selected < last => selected + 1,
+                (false, selected) if selected > 0 => selected - 1,
+                (_, selected) => selected,
+            };
+        }
+    }
+    
+    /// Put the selected reply of the comparison in place of the last reply
+    fn promote_compared(&mut self) {
+        let Some(view) = &self.comparison else {
+            return;
+        };
+        let label = view.comparison[view.selected].label.to_string();
         
-        // Add the response to the messages
-        self.messages.push(response);
+        // The conversation may have changed since, e.g. by pinning a message
+        self.agent.replace_conversation(self.conversation());
+        match self.agent.promote(&label) {
+            Ok(kept) => {
+                self.comparison = None;
+                self.adopt(usize::MAX);
+                self.push_note(kept);
+                self.refresh_search();
+            }
+            // A reply that failed stays open beside the others
+            Err(e) => self.push_note(e.to_string()),
+        }
+    }
+    
+    /// Close the comparison, leaving the conversation as it was
+    fn discard_comparison(&mut self) {
+        if self.comparison.take().is_some() {
+            self.agent.discard_comparison();
+            self.push_note("Discarded the comparison".to_string());
+        }
+    }
+    
+    /// Submit the current input as a message
+    fn submit_message(&mut self) -> Result<()> {
+        if self.input.trim().is_empty() {
+            return Ok(());
+        }
         
-        // Reset loading state
-        self.is_loading = false;
+        // Add to input history, keeping it on disk for the next session
+        self.input_history.push(self.input.clone());
+        self.history_cursor.reset();
+        if let Err(e) = self.input_history.save() {
+            self.push_error(format!("Error: {}", e));
+        }
+        
+        // Commands the TUI handles itself run now, the others wait for the agent
+        let input = self.input.trim().to_string();
+        match commands::parse(&input) {
+            Parsed::NotACommand if self.is_loading && self.queued.len() >= MAX_QUEUED => {
+                self.push_note(format!(
+                    "{} messages are queued already; wait for a reply, or cancel them with /queue clear",
+                    MAX_QUEUED
+                ));
+                return Ok(());
+            }
+            Parsed::NotACommand => {}
+            Parsed::Unknown(name) => {
+                self.input.clear();
+                self.push_error(format!("Error: {}", commands::unknown(name)));
+                return Ok(());
+            }
+            // Only the queue can be managed while a reply is generated
+            Parsed::Command { command, .. } if self.is_loading && command.name() != "/queue" => {
+                self.input.clear();
+                self.push_error(format!("Error: {} can run once the reply is in", command.name()));
+                return Ok(());
+            }
+            Parsed::Command { command, args } => {
+                self.input.clear();
+                if let Err(e) = self.run_command(command, args) {
+                    self.push_error(format!("Error: {}", e));
+                }
+                return Ok(());
+            }
+        }
+        self.input.clear();
         
+        // Messages sent during a reply wait their turn
+        if self.is_loading {
+            self.queued.push_back(UiMessage::user(input));
+            return Ok(());
+        }
+        self.send(input);
+        Ok(())
+    }
+    
+    /// Show `input` as the next message, with the staged attachments and
+    /// images it goes with, and hand it to the agent to send
+    fn send(&mut self, input: String) {
+        let content = attachments::with_attachments(&input, self.agent.staged_attachments());
+        let message = UiMessage::user(content)
+            .with_images(self.agent.staged_images().to_vec())
+            .with_local_sources(attachments::paths(self.agent.staged_attachments()));
+        self.sessions.messages_mut().push(message);
+        self.refresh_search();
+        self.queue_input(input);
+    }
+    
+    /// Leave `input` for the agent, once the loop gets to it
+    fn queue_input(&mut self, input: String) {
+        self.pending = Some(input);
+        self.is_loading = true;
+    }
+    
+    /// Send the oldest queued message, once the reply before it is in
+    fn send_next_queued(&mut self) {
+        if self.is_loading {
+            return;
+        }
+        if let Some(message) = self.queued.pop_front() {
+            self.send(message.content);
+        }
+    }
+    
+    /// List the queued messages, or cancel one or all of them
+    fn manage_queue(&mut self, command: &'static CommandHelp, args: &str) -> Result<(), CommandError> {
+        let note = match args.split_once(' ').unwrap_or((args, "")) {
+            ("", _) if self.queued.is_empty() => "No messages are queued".to_string(),
+            ("", _) => {
+                let lines: Vec<String> = self
+                    .queued
+                    .iter()
+                    .enumerate()
+                    .map(|(index, message)| format!("  {}. {}", index + 1, session::preview(&message.content)))
+                    .collect();
+                format!("Queued messages:\n{}", lines.join("\n"))
+            }
+            ("clear", "") => {
+                let count = self.queued.len();
+                self.queued.clear();
+                format!("Cancelled {} queued message{}", count, if count == 1 { "" } else { "s" })
+            }
+            ("remove", number) => {
+                let number = number.trim().parse::<usize>().map_err(|_| CommandError::Usage(command))?;
+                match number.checked_sub(1).and_then(|index| self.queued.remove(index)) {
+                    Some(message) => format!("Cancelled queued message {}: {}", number, session::preview(&message.content)),
+                    None => format!("No queued message {}; /queue lists them", number),
+                }
+            }
+            _ => return Err(CommandError::Usage(command)),
+        };
+        self.push_note(note);
         Ok(())
     }
+    
+    /// Run one of the commands, with the rest of its line. Those that act
+    /// on what the TUI shows run here, the others are left for the agent
+    fn run_command(&mut self, command: &'static CommandHelp, args: &str) -> Result<(), CommandError> {
+        match command.name() {
+            "/exit" => {
+                commands::no_args(command, args)?;
+                self.exit_requested = true;
+            }
+            "/help" if args.is_empty() => {
+                let lines: Vec<String> = COMMANDS
+                    .iter()
+                    .map(|command| format!("{}  - {}", command.command, command.description))
+                    .collect();
+                self.push_note(format!("{}\nType /help <command> for more about one of them", lines.join("\n")));
+            }
+            "/help" => match commands::find(args) {
+                Some(command) => self.push_note(command.usage()),
+                None => return Err(commands::unknown(args)),
+            },
+            // Start over with a fresh conversation and tool state
+            "/clear" => {
+                commands::no_args(command, args)?;
+                self.clear_conversation();
+            }
+            // Branch commands act on the conversation as shown, notes included
+            "/history" => {
+                commands::no_args(command, args)?;
+                self.history();
+            }
+            "/fork" => self.fork(args),
+            "/pin" => self.set_pinned(command, args, true)?,
+            "/unpin" => self.set_pinned(command, args, false)?,
+            "/sessions" => self.list_sessions(args),
+            "/title" => self.set_title(args),
+            "/queue" => self.manage_queue(command, args)?,
+            "/theme" => {
+                if !args.is_empty() {
+                    match Theme::named(args) {
+                        Some(theme) => *self.theme.lock().unwrap() = theme,
+                        None => {
+                            self.push_error(format!("Error: Unknown theme '{}'; use {}", args, theme::THEMES.join(" or ")));
+                            return Ok(());
+                        }
+                    }
+                }
+                self.push_note(format!("Theme: {} (available: {})", self.theme().name, theme::THEMES.join(", ")));
+            }
+            // Asking for the variables of a template needs a prompt the TUI doesn't have
+            "/use" => self.push_note("/use is not available in the TUI".to_string()),
+            name if args.is_empty() => self.queue_input(name.to_string()),
+            name => self.queue_input(format!("{} {}", name, args)),
+        }
+        Ok(())
+    }
+    
+    /// Clear what is shown of the conversation and the session's title, and
+    /// have the agent start over with a fresh conversation and tool state
+    fn clear_conversation(&mut self) {
+        self.sessions.messages_mut().clear();
+        self.meta = SessionMeta::new(self.agent.model());
+        self.title_requested = false;
+        self.pending_title = None;
+        self.candidate_picker = None;
+        self.comparison = None;
+        self.clear_search();
+        self.queue_input("/clear".to_string());
+    }
+    
+    /// Add a system note to the conversation
+    fn push_note(&mut self, note: String) {
+        self.sessions.messages_mut().push(UiMessage::note(note));
+    }
+    
+    /// Add an error note to the conversation
+    fn push_error(&mut self, error: String) {
+        self.sessions.messages_mut().push(UiMessage::error(error));
+    }
+    
+    /// List the messages of the active branch
+    fn history(&mut self) {
+        let lines: Vec<String> = self
+            .sessions
+            .messages()
+            .iter()
+            .enumerate()
+            .map(|(index, message)| {
+                let pin = if message.pinned { " (pinned)" } else { "" };
+                format!("[{}]{} {:?}: {}", index, pin, message.role, session::preview(&message.content))
+            })
+            .collect();
+        self.push_note(lines.join("\n"));
+    }
+    
+    /// Pin or unpin a message, by its /history number
+    fn set_pinned(&mut self, command: &'static CommandHelp, args: &str, pinned: bool) -> Result<(), CommandError> {
+        let index = args.parse::<usize>().map_err(|_| CommandError::Usage(command))?;
+        self.pin(index, pinned);
+        Ok(())
+    }
+    
+    /// Pin or unpin a message, and say which
+    fn pin(&mut self, index: usize, pinned: bool) {
+        let count = self.sessions.messages().len();
+        let note = match self.sessions.messages_mut().get_mut(index) {
+            Some(message) => {
+                message.pinned = pinned;
+                if pinned {
+                    format!("Pinned message {}", index)
+                } else {
+                    format!("Unpinned message {}", index)
+                }
+            }
+            None => format!("No message {}; /history lists the {} there are", index, count),
+        };
+        self.push_note(note);
+    }
+    
+    /// Continue the conversation in a new branch from an earlier message
+    fn fork(&mut self, args: &str) {
+        let Ok(index) = args.parse::<usize>() else {
+            self.push_note("Usage: /fork <message index>".to_string());
+            return;
+        };
+        self.fork_at(index);
+    }
+    
+    /// Continue the conversation in a new branch that ends with message `index`
+    fn fork_at(&mut self, index: usize) {
+        let parent = self.sessions.active_name().to_string();
+        let forked = self.sessions.fork(index).map(str::to_string);
+        match forked {
+            Ok(name) => {
+                self.selected = None;
+                self.push_note(format!("Forked '{}' at message {} into '{}'", parent, index, name));
+            }
+            Err(e) => self.push_error(format!("Error: {}", e)),
+        }
+        self.refresh_search();
+    }
+    
+    /// List the branches of this session, or switch to one
+    fn list_sessions(&mut self, args: &str) {
+        if !args.is_empty() {
+            if let Err(e) = self.sessions.switch(args) {
+                self.push_error(format!("Error: {}", e));
+                return;
+            }
+            self.selected = None;
+            self.refresh_search();
+        }
+        
+        let lines: Vec<String> = self
+            .sessions
+            .summaries()
+            .into_iter()
+            .map(|branch| {
+                let origin = branch
+                    .parent
+                    .map(|fork| format!(" (from {} at message {})", fork.branch, fork.message_index))
+                    .unwrap_or_default();
+                format!(
+                    "{}{}{} - {} messages{}",
+                    if branch.active { "* " } else { "  " },
+                    "  ".repeat(branch.depth),
+                    branch.name,
+                    branch.messages,
+                    origin
+                )
+            })
+            .collect();
+        self.push_note(format!("Session: {}\n{}", self.meta.name(), lines.join("\n")));
+    }
+    
+    /// The conversation as the model sees it: the messages without the notes,
+    /// with the contents moved to blobs read back
+    fn conversation(&self) -> Vec<crate::Message> {
+        self.sessions
+            .messages()
+            .iter()
+            .filter(|message| !message.ephemeral)
+            .map(|message| self.model_message(message))
+            .collect()
+    }
+    
+    /// `message` as the model sees it, with its content read back if it was moved to a blob
+    fn model_message(&self, message: &UiMessage) -> crate::Message {
+        let mut converted = crate::Message::from(message);
+        if let (Some(blob), Some(store)) = (&message.blob, &self.blobs) {
+            if let Ok(content) = store.get(blob) {
+                converted.content = content;
+            }
+        }
+        converted
+    }
+    
+    /// Bring the first `count` messages of the agent's conversation into the
+    /// one shown. Notes stay where they are, and so do the messages the agent
+    /// kept, with what is shown of them; the ones it dropped go
+    fn adopt(&mut self, count: usize) {
+        let conversation = self.agent.conversation();
+        let conversation = &conversation[..count.min(conversation.len())];
+        let shown = std::mem::take(self.sessions.messages_mut());
+        let mut messages = Vec::with_capacity(shown.len());
+        let mut next = 0;
+        for message in shown {
+            if message.ephemeral {
+                messages.push(message);
+                continue;
+            }
+            let seen = self.model_message(&message);
+            let Some(offset) = conversation[next..]
+                .iter()
+                .position(|kept| kept.role == seen.role && kept.content == seen.content)
+            else {
+                continue;
+            };
+            messages.extend(conversation[next..next + offset].iter().cloned().map(UiMessage::from));
+            messages.push(message);
+            next += offset + 1;
+        }
+        messages.extend(conversation[next..].iter().cloned().map(UiMessage::from));
+        *self.sessions.messages_mut() = messages;
+    }
+    
+    /// Show an event of the agent that isn't part of the conversation
+    fn show(&mut self, event: AgentEvent) {
+        match event {
+            AgentEvent::Info(note) => self.push_note(note),
+            AgentEvent::Error(error) => self.push_error(format!("Error: {}", error)),
+            AgentEvent::Warning(warning) => self.push_note(format!("Warning: {}", warning)),
+            AgentEvent::Tools(tools) if tools.is_empty() => self.push_note("No tools available".to_string()),
+            AgentEvent::Tools(tools) => self.push_note(format!("Available tools: {}", tools.join(", "))),
+            AgentEvent::Schemas(tools) => {
+                let lines: Vec<String> = tools.iter().map(schema::summary).collect();
+                self.push_note(format!("{}\nType /schema <tool> for one tool's parameter schema", lines.join("\n")));
+            }
+            AgentEvent::Schema(tool) => self.push_note(schema::show(&tool)),
+            AgentEvent::Stats(stats) => self.push_note(format!("Session: {}", stats.summary())),
+            AgentEvent::SearchResults(hits) => {
+                let lines: Vec<String> = hits
+                    .iter()
+                    .map(|hit| format!("[{}] {:?}: {}", hit.index, MessageRole::from(hit.role.clone()), hit.snippet))
+                    .collect();
+                self.push_note(lines.join("\n"));
+            }
+            AgentEvent::Staged(staged) => {
+                let paths: Vec<&str> = staged.iter().map(|attachment| attachment.path.as_str()).collect();
+                self.push_note(format!("Staged for the next message: {}", paths.join(", ")));
+            }
+            AgentEvent::Templates(templates) if templates.is_empty() => self.push_note("No templates found".to_string()),
+            AgentEvent::Templates(templates) => {
+                let names: Vec<&str> = templates.iter().map(|template| template.name.as_str()).collect();
+                self.push_note(format!("Templates: {}", names.join(", ")));
+            }
+            AgentEvent::Diff(diff) if diff.is_empty() => self.push_note("No files changed this session".to_string()),
+            AgentEvent::Diff(diff) => {
+                let lines = diff.render().lines().map(str::to_string).collect();
+                self.diff_view = Some(DiffView { lines, scroll: 0 });
+            }
+            AgentEvent::Candidates(candidates) => self.candidate_picker = Some(CandidatePicker { candidates, selected: 0 }),
+            AgentEvent::Comparison(comparison) => self.comparison = Some(ComparisonView { comparison, selected: 0 }),
+            // The status bar, the overlays and the messages themselves show the rest
+            _ => {}
+        }
+    }
+    
+    /// Process the LLM response, then send the next queued message
+    async fn process_response(&mut self) -> Result<()> {
+        let result = self.respond().await;
+        if result.is_ok() {
+            self.send_next_queued();
+            self.offer_recorded_prompt();
+        }
+        result
+    }
+    
+    /// When replaying, type the prompt of the next recorded request into an
+    /// empty input, for Enter to send it
+    fn offer_recorded_prompt(&mut self) {
+        if self.is_loading || !self.input.is_empty() {
+            return;
+        }
+        if let Some(Tape::Replay(replay)) = self.agent.client().tape() {
+            if let Some(prompt) = replay.next_prompt() {
+                self.input = prompt;
+            }
+        }
+    }
+    
+    /// Hand the pending input to the agent, with the conversation as shown,
+    /// and show what it did
+    async fn respond(&mut self) -> Result<()> {
+        let Some(input) = self.pending.take() else {
+            self.is_loading = false;
+            return Ok(());
+        };
+        
+        // A message is shown before it is sent; the agent adds it itself
+        let mut conversation = self.conversation();
+        if matches!(commands::parse(&input), Parsed::NotACommand) {
+            conversation.pop();
+        }
+        let mut adopted = conversation.len();
+        self.agent.replace_conversation(conversation);
+        let mut events = Vec::new();
+        self.agent.handle_input(&input, &mut events).await;
+        
+        // The messages come in where the agent added them, between its notes,
+        // or before them if it changed the conversation otherwise, e.g. with /pick
+        let adds_messages = events
+            .iter()
+            .any(|event| matches!(event, AgentEvent::UserMessage(_) | AgentEvent::AssistantComplete(_)));
+        if !adds_messages {
+            self.adopt(usize::MAX);
+        }
+        let mut interrupted = false;
+        for event in events {
+            match event {
+                AgentEvent::UserMessage(_) => {
+                    adopted += 1;
+                    self.adopt(adopted);
+                }
+                AgentEvent::Interrupted(_) => interrupted = true,
+                AgentEvent::AssistantComplete(reply) => {
+                    adopted += 1;
+                    self.adopt(adopted);
+                    let slow = reply.latency.is_some_and(|latency| self.agent.notifier().should_notify(latency));
+                    if slow && !interrupted {
+                        self.flash_until = Some(Instant::now() + FLASH_DURATION);
+                    }
+                }
+                event => self.show(event),
+            }
+        }
+        self.adopt(usize::MAX);
+        
+        // Picking a candidate or sending a message settles what was waiting
+        if self.agent.pending_candidates().is_empty() {
+            self.candidate_picker = None;
+        }
+        if self.agent.pending_comparison().is_empty() {
+            self.comparison = None;
+        }
+        self.refresh_search();
+        if !interrupted {
+            self.request_title();
+        }
+        self.is_loading = false;
+        Ok(())
+    }
+}
+
+/// Candidates from /best, shown in an overlay until one is picked
+#[derive(Debug)]
+pub struct CandidatePicker {
+    pub candidates: Vec<Candidate>,
+    pub selected: usize,
+}
+
+/// The replies of a /compare, shown beside each other until one is promoted
+#[derive(Debug)]
+pub struct ComparisonView {
+    pub comparison: Vec<Comparison>,
+    pub selected: usize,
+}
+
+/// The links of a message, listed until one is opened
+#[derive(Debug)]
+pub struct LinkPicker {
+    /// Each link as the message writes it, and where it points
+    pub links: Vec<(String, Target)>,
+    pub selected: usize,
+}
+
+/// The lines of a /diff, and the first one shown
+#[derive(Debug)]
+pub struct DiffView {
+    pub lines: Vec<String>,
+    pub scroll: usize,
+}
+
+/// Ctrl+R reverse search through the input history
+#[derive(Debug, Default)]
+pub struct HistorySearch {
+    pub query: String,
+    index: Option<usize>,
 }
 
 /// TUI-specific state
 struct TuiState {
     input_mode: InputMode,
     last_tick: Instant,
+    // Where the conversation is saved as it goes, unless another TUI is saving there
+    autosave: Option<Autosave>,
+    // The title of the terminal window
+    window_title: WindowTitle,
+    // Whether the note that the saved session is too large was shown
+    oversize_noted: bool,
 }
 
 impl Default for TuiState {
@@ -203,28 +1491,261 @@ impl Default for TuiState {
         Self {
             input_mode: InputMode::Editing, // Start in editing mode
             last_tick: Instant::now(),
+            autosave: None,
+            window_title: WindowTitle::of_terminal(),
+            oversize_noted: false,
         }
     }
 }
 
-/// Run the TUI application
-pub async fn run() -> Result<()> {
+/// Offer to restore the session of a run that didn't exit cleanly, or
+/// continue the last one however it ended if `resume`, then mark this run as
+/// the one saving, or None if another running TUI already is
+fn start_autosave(app: SentinelApp, resume: bool, limits: SessionsConfig) -> Result<(SentinelApp, Option<Autosave>)> {
+    let Some(autosave) = Autosave::open_default().map(|autosave| autosave.with_limits(limits)) else {
+        return Ok((app, None));
+    };
+    let mut app = match autosave.previous() {
+        Previous::Running(pid) => {
+            let mut app = app;
+            app.push_note(format!("Another Sentinel (process {}) is saving its session, so this one is not autosaved", pid));
+            return Ok((app, None));
+        }
+        _ if resume => restore(app, &autosave),
+        Previous::Unfinished => offer_restore(app, &autosave)?,
+        Previous::Finished => app,
+    };
+    
+    if let Err(e) = autosave.start() {
+        app.push_error(format!("Error: {:#}; this session is not autosaved", e));
+        return Ok((app, None));
+    }
+    app.blobs = Some(autosave.blobs());
+    Ok((app, Some(autosave)))
+}
+
+/// Continue the conversation the last run saved, without asking
+fn restore(mut app: SentinelApp, autosave: &Autosave) -> SentinelApp {
+    if !autosave.path().exists() {
+        app.push_note("No saved session to continue".to_string());
+        return app;
+    }
+    match autosave.load() {
+        Ok(session) => {
+            let mut app = app.with_session(session);
+            restore_journal(&mut app, autosave);
+            app.push_note(format!("Continued the last session from {}", autosave.path().display()));
+            app
+        }
+        Err(e) => {
+            app.push_error(format!("Error: {:#}; the last session could not be restored", e));
+            app
+        }
+    }
+}
+
+/// The conversation the TUI saved last, with its branches, as the model saw
+/// it, and its usage so far, for the REPL to continue
+pub fn last_conversation() -> Result<(Sessions<crate::Message>, SessionStats)> {
+    let autosave = Autosave::open_default().context("Cannot find the last session: neither XDG_DATA_HOME nor HOME is set")?;
+    let mut session = autosave.load()?;
+    
+    // The REPL has no way to expand a message, so contents moved to blobs are read back now
+    let blobs = autosave.blobs();
+    for message in session.all_messages_mut() {
+        if let Some(content) = message.blob.take().and_then(|blob| blobs.get(&blob).ok()) {
+            message.content = content;
+        }
+    }
+    let stats = session.metadata.stats.clone();
+    let sessions: Sessions<UiMessage> = session.into_sessions();
+    Ok((sessions.map_branches(message::history), stats))
+}
+
+/// How the TUI starts, from the flags of `sentinel chat --tui`
+#[derive(Debug, Clone, Default)]
+pub struct TuiOptions {
+    /// Pull the model without asking if it is not installed
+    pub auto_pull: bool,
+    /// Start with a snapshot of the project
+    pub prime: bool,
+    /// Preview mutating tool actions instead of running them
+    pub dry_run: bool,
+    /// The --keep-alive flag, over the config's
+    pub keep_alive: Option<String>,
+    /// Continue the last session instead of starting a new one
+    pub resume: bool,
+    /// Record the session with --record, or replay one with --replay
+    pub recording: recording::Mode,
+}
+
+/// Ask whether to continue the conversation the last run saved
+fn offer_restore(mut app: SentinelApp, autosave: &Autosave) -> Result<SentinelApp> {
+    let session = match autosave.load() {
+        Ok(session) => session,
+        Err(e) => {
+            app.push_error(format!("Error: {:#}; the last session could not be restored", e));
+            return Ok(app);
+        }
+    };
+    let messages = &session.messages;
+    let Some(last) = messages.iter().rev().find(|message| message.role != StoredRole::System) else {
+        return Ok(app);
+    };
+    
+    let title = session.metadata.title.as_ref().map(|title| format!(" \"{}\"", title)).unwrap_or_default();
+    print!(
+        "The last session{} did not exit cleanly. It had {} messages, the last: \"{}\"\nRestore it? [y/N] ",
+        title,
+        messages.len(),
+        session::preview(&last.content)
+    );
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    
+    if matches!(answer.trim(), "y" | "Y" | "yes") {
+        let mut app = app.with_session(session);
+        restore_journal(&mut app, autosave);
+        app.push_note(format!("Restored the last session from {}", autosave.path().display()));
+        return Ok(app);
+    }
+    Ok(app)
+}
+
+/// Take over the file changes of the session being continued, so /undo reaches them
+fn restore_journal(app: &mut SentinelApp, autosave: &Autosave) {
+    match autosave.load_journal() {
+        Ok(Some(journal)) => app.agent.journal().restore(journal),
+        Ok(None) => {}
+        Err(e) => app.push_error(format!("Error: {:#}; the changes of the last session can't be undone", e)),
+    }
+}
+
+/// Save the conversation in the background, if it changed since the last
+/// save, and say once when the saved file has grown too large
+fn autosave(app: &mut SentinelApp, state: &mut TuiState) {
+    if let Some(autosave) = &mut state.autosave {
+        autosave.save_if_changed(&app.session_meta(), app.sessions());
+        if let Err(e) = autosave.save_journal_if_changed(&app.agent.journal()) {
+            app.push_error(format!("Error: {:#}", e));
+        }
+        let warning = autosave.size_warning();
+        if let (Some(warning), false) = (&warning, state.oversize_noted) {
+            app.push_note(warning.clone());
+        }
+        state.oversize_noted = warning.is_some();
+    }
+}
+
+/// Run the TUI application with `config`, as the flags already changed it
+pub async fn run(config: Config, options: TuiOptions) -> Result<()> {
+    // Check the key bindings and the model before taking over the terminal so errors and prompts are visible
+    let keymap = Keymap::with_overrides(&config.keys).with_context(|| {
+        let path = config::default_path().unwrap_or_default();
+        format!("Invalid key bindings in {}", path.display())
+    })?;
+    let theme = Theme::from_config(&config.theme, theme::truecolor()).with_context(|| {
+        let path = config::default_path().unwrap_or_default();
+        format!("Invalid theme in {}", path.display())
+    })?;
+    let theme = Arc::new(Mutex::new(theme));
+    let keep_alive = config.model.keep_alive(options.keep_alive.as_deref())?;
+    let model = config.model.name.clone().unwrap_or_else(|| OllamaClient::new().model().to_string());
+    // A replay answers from its recording, without a server to reach
+    let (llm_client, endpoint): (Box<dyn LlmClient>, Option<Endpoint>) = match options.recording.replay()? {
+        Some(replay) => (Box::new(replay), None),
+        None => {
+            let client = FailoverClient::connect(&config, &model, keep_alive.clone()).await?;
+            availability::ensure_model(&client, &model, options.auto_pull, &mut console::Terminal::new()).await?;
+            let endpoint = client.active().clone();
+            (options.recording.record(Box::new(client), &model)?, Some(endpoint))
+        }
+    };
+    let endpoint_client = |endpoint: &Endpoint| {
+        OllamaClient::new()
+            .with_endpoint(endpoint)
+            .with_model(endpoint.model.as_deref().unwrap_or(&model))
+            .with_keep_alive(keep_alive.clone())
+    };
+    
+    // Name the conversation after its first reply, on a client of its own so it never holds up the next one;
+    // the titles of a recorded or replayed session go through its recording
+    let titler: Option<Arc<dyn LlmClient>> = match &endpoint {
+        Some(endpoint) if options.recording == recording::Mode::Live => Some(Arc::new(endpoint_client(endpoint))),
+        _ => llm_client.for_model(&model).ok().map(Arc::from),
+    };
+    
+    // Create app state; file changes are reviewed, and tool output shown, by overlays
+    // drawn while the response is awaited
+    let overlay_drawn = Arc::new(AtomicBool::new(false));
+    let reviewer = TuiReviewer::new(keymap.clone(), Arc::clone(&theme), Arc::clone(&overlay_drawn));
+    let activity = Arc::new(Activity::new());
+    let progress = TuiProgress::new(Arc::clone(&overlay_drawn), Arc::clone(&activity), Arc::clone(&theme));
+    let mut app = SentinelApp::with_client(llm_client, &model).with_activity(activity);
+    // Given before the config, which can turn the memory tool off
+    let memory = Memory::load(&config.memory, &std::env::current_dir()?);
+    if let Ok(Some(memory)) = &memory {
+        app = app.with_memory(Arc::clone(memory));
+    }
+    let mut app = app
+        .with_config(&config)
+        .with_keymap(keymap)
+        .with_theme(theme)
+        .with_layout_file(config::file_for("layout"))
+        .with_dry_run(options.dry_run)
+        .with_notifier(Notifier::from_config(&config.notify))
+        .with_reviewer(Arc::new(reviewer))
+        .with_progress(Arc::new(progress));
+    if let Err(e) = memory {
+        app.push_note(format!("Remembered facts not loaded: {:#}", e));
+    }
+    app.check_tool_support().await;
+    app.check_context_window().await;
+    
+    if let (true, Some(titler)) = (config.model.auto_title, titler) {
+        app = app.with_titler(titler);
+    }
+    match &options.recording {
+        recording::Mode::Live => {}
+        recording::Mode::Record(path) => app.push_note(format!("Recording the session to {}", path.display())),
+        recording::Mode::Replay(path) => app.push_note(format!(
+            "Replaying {}: nothing is sent to Ollama and no tools run. Enter sends each recorded prompt in turn",
+            path.display()
+        )),
+    }
+    
+    // Load the model in the background while the first prompt is typed
+    if let (true, Some(endpoint)) = (config.model.warm_up, &endpoint) {
+        let client = endpoint_client(endpoint);
+        let (sender, receiver) = oneshot::channel();
+        tokio::spawn(async move {
+            let _ = sender.send(client.warm_up().await.ok());
+        });
+        app = app.with_warming_up(receiver);
+    }
+    if options.prime {
+        app.queue_input("/prime".to_string());
+        app.process_response().await?;
+    }
+    
+    // Offer to restore a session that crashed before taking over the terminal, too
+    let (mut app, autosave) = start_autosave(app, options.resume, config.sessions)?;
+    app.offer_recorded_prompt();
+    
     // Setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
-
-    // Create app state
-    let mut app = SentinelApp::new();
     
     // Create UI state
-    let mut state = TuiState::default();
+    let mut state = TuiState { autosave, ..TuiState::default() };
     
     // Start the main loop
     let tick_rate = Duration::from_millis(100);
-    let result = run_app(&mut terminal, &mut app, &mut state, tick_rate).await;
+    let result = run_app(&mut terminal, &mut app, &mut state, &overlay_drawn, tick_rate).await;
     
     // Restore terminal
     disable_raw_mode()?;
@@ -235,19 +1756,212 @@ pub async fn run() -> Result<()> {
     )?;
     terminal.show_cursor()?;
     
+    // Save what the last tick didn't, and mark a clean exit; after an error the
+    // marker stays so the next run offers to restore the session
+    if let Some(autosave) = &mut state.autosave {
+        if let Err(e) = autosave.save_now(&app.session_meta(), app.sessions()) {
+            eprintln!("Error: {:#}", e);
+        }
+        if let Err(e) = autosave.save_journal(&app.agent.journal()) {
+            eprintln!("Error: {:#}", e);
+        }
+        if result.is_ok() {
+            autosave.finish()?;
+        }
+    }
+    
+    println!("Session: {}", app.stats().summary());
+    
     result
 }
 
+/// Which set of key bindings applies right now
+fn key_mode(app: &SentinelApp, state: &TuiState) -> Mode {
+    if app.candidate_picker().is_some() || app.comparison().is_some() || app.link_picker().is_some() {
+        return Mode::Picker;
+    }
+    
+    match state.input_mode {
+        InputMode::Normal => Mode::Normal,
+        InputMode::Search => Mode::Search,
+        InputMode::Editing if app.history_search().is_some() => Mode::HistorySearch,
+        InputMode::Editing => Mode::Editing,
+    }
+}
+
+/// Handle a key press, returning false when the user quits
+fn handle_key(app: &mut SentinelApp, state: &mut TuiState, key: KeyEvent) -> Result<bool> {
+    // Any key closes the help overlay
+    if app.show_help {
+        app.show_help = false;
+        return Ok(true);
+    }
+    // The diff overlay scrolls; any other key closes it
+    if app.diff_view.is_some() {
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => app.scroll_diff(-1),
+            KeyCode::Down | KeyCode::Char('j') => app.scroll_diff(1),
+            KeyCode::PageUp => app.scroll_diff(-DIFF_PAGE),
+            KeyCode::PageDown | KeyCode::Char(' ') => app.scroll_diff(DIFF_PAGE),
+            _ => app.diff_view = None,
+        }
+        return Ok(true);
+    }
+    
+    let mode = key_mode(app, state);
+    let Some(action) = app.keymap.action(mode, &key) else {
+        // Unbound keys edit whichever input is open
+        match (mode, key.code) {
+            (Mode::Editing, KeyCode::Char(c)) => app.handle_input(c),
+            (Mode::Editing, KeyCode::Backspace) => app.backspace(),
+            (Mode::HistorySearch, KeyCode::Char(c)) => app.edit_history_search(|query| query.push(c)),
+            (Mode::HistorySearch, KeyCode::Backspace) => app.edit_history_search(|query| {
+                query.pop();
+            }),
+            (Mode::Search, KeyCode::Char(c)) => app.search_input(c),
+            (Mode::Search, KeyCode::Backspace) => app.search_backspace(),
+            _ => {}
+        }
+        return Ok(true);
+    };
+    
+    match action {
+        Action::Quit => return Ok(false),
+        Action::Help => app.show_help = true,
+        Action::ToggleReasoning => app.show_reasoning = !app.show_reasoning,
+        Action::ToggleSystem => app.expand_system = !app.expand_system,
+        Action::ToggleStats if app.layout == LayoutMode::Zen => app.zen_stats = !app.zen_stats,
+        Action::ToggleStats => app.show_stats = !app.show_stats,
+        Action::ToggleLayout => app.toggle_layout(),
+        Action::StartEditing => state.input_mode = InputMode::Editing,
+        Action::StopEditing => state.input_mode = InputMode::Normal,
+        Action::Submit => {
+            app.submit_message()?;
+            if app.exit_requested {
+                return Ok(false);
+            }
+        }
+        Action::PreviousInput => app.previous_input(),
+        Action::NextInput => app.next_input(),
+        Action::HistorySearch => app.reverse_search(),
+        Action::AcceptHistory => app.accept_history_search(),
+        Action::CancelHistory => app.cancel_history_search(),
+        Action::Search => {
+            app.start_search();
+            state.input_mode = InputMode::Search;
+        }
+        Action::NextMatch => app.next_match(),
+        Action::PreviousMatch => app.previous_match(),
+        Action::ClearSearch => {
+            app.clear_search();
+            app.follow_latest();
+        }
+        Action::FinishSearch => {
+            app.finish_search();
+            state.input_mode = InputMode::Normal;
+        }
+        Action::CancelSearch => {
+            app.clear_search();
+            state.input_mode = InputMode::Normal;
+        }
+        Action::ToggleRegex => app.toggle_search_regex(),
+        Action::SelectPrevious => app.select_previous(),
+        Action::SelectNext => app.select_next(),
+        Action::FollowLatest => app.follow_latest(),
+        Action::CopyMessage => app.copy_selected(),
+        Action::OpenLink => app.open_link(),
+        Action::TogglePin => app.toggle_pin(),
+        Action::ForkHere => app.fork_selected(),
+        Action::ToggleDetails => app.toggle_details(),
+        // The links are listed only from normal mode, so never over candidates or a comparison
+        Action::PreviousCandidate if app.link_picker.is_some() => app.select_link(false),
+        Action::NextCandidate if app.link_picker.is_some() => app.select_link(true),
+        Action::AcceptCandidate if app.link_picker.is_some() => app.open_picked_link(),
+        Action::DiscardCandidates if app.link_picker.is_some() => app.link_picker = None,
+        // The comparison opens only when no candidates are waiting
+        Action::PreviousCandidate if app.comparison.is_some() => app.select_compared(false),
+        Action::NextCandidate if app.comparison.is_some() => app.select_compared(true),
+        Action::AcceptCandidate if app.comparison.is_some() => app.promote_compared(),
+        Action::DiscardCandidates if app.comparison.is_some() => app.discard_comparison(),
+        Action::PreviousCandidate => app.select_candidate(false),
+        Action::NextCandidate => app.select_candidate(true),
+        Action::AcceptCandidate => app.accept_candidate(),
+        Action::DiscardCandidates => app.discard_candidates(),
+        // Bound only in the review overlay, which reads its own keys, and while a reply is awaited
+        Action::ApproveChange
+        | Action::ApproveAllChanges
+        | Action::RejectChange
+        | Action::ScrollUp
+        | Action::ScrollDown
+        | Action::SendReason
+        | Action::SkipReason
+        | Action::Interrupt => {}
+    }
+    Ok(true)
+}
+
+/// Read keys while a reply is generated: the interrupt key cuts it short, and
+/// the others are held for when it is done. Only returns if reading fails.
+/// Meanwhile the spinner and elapsed time of `activity` are kept up to date
+/// in the title of the input box, which `draw_title` draws.
+///
+/// Runs in the same task as the reply, so it pauses while a review overlay
+/// reads keys itself.
+async fn watch_for_interrupt(
+    keymap: &Keymap,
+    draw_title: impl Fn(&str) -> Result<()>,
+    interrupter: &Interrupter,
+    activity: &Activity,
+    overlay_drawn: &AtomicBool,
+    held: &mut Vec<KeyEvent>,
+    resized: &mut bool,
+) -> Result<()> {
+    let mut shown = None;
+    loop {
+        if let Some(status) = activity.status() {
+            let title = ui::busy_title(Some(&status), keymap);
+            // The status is best effort; the reply goes on without it
+            if shown.as_ref() != Some(&title) && draw_title(&title).is_ok() {
+                overlay_drawn.store(true, Ordering::Relaxed);
+                shown = Some(title);
+            }
+        }
+        
+        while crossterm::event::poll(Duration::ZERO)? {
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    if keymap.action(Mode::Busy, &key) == Some(Action::Interrupt) {
+                        interrupter.interrupt();
+                    } else {
+                        held.push(key);
+                    }
+                }
+                Event::Resize(..) => *resized = true,
+                _ => {}
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
 /// Main application loop
 async fn run_app(
     terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
     app: &mut SentinelApp,
     state: &mut TuiState,
+    overlay_drawn: &AtomicBool,
     tick_rate: Duration,
 ) -> Result<()> {
     loop {
+        // Name the window after the conversation and the model, marking changes not saved yet;
+        // a terminal that can't take a title just keeps its own
+        app.poll_title();
+        app.poll_warm_up();
+        let unsaved = state.autosave.as_ref().is_some_and(|autosave| !autosave.is_saved(&app.session_meta(), app.sessions()));
+        let _ = state.window_title.set(&status::window_title(app.title(), app.model_name(), unsaved));
+        
         // Draw the UI
-        terminal.draw(|f| render_ui::<CrosstermBackend<io::Stdout>>(f, app))?;
+        terminal.draw(|f| render_ui(f, app))?;
         
         // Handle events with timeout
         let timeout = tick_rate
@@ -255,52 +1969,934 @@ async fn run_app(
             .unwrap_or_else(|| Duration::from_secs(0));
         
         if crossterm::event::poll(timeout)? {
-            if let Event::Key(key) = event::read()? {
-                if key.kind == KeyEventKind::Press {
-                    match state.input_mode {
-                        InputMode::Normal => match key.code {
-                            KeyCode::Char('e') => {
-                                state.input_mode = InputMode::Editing;
-                            }
-                            KeyCode::Char('q') => {
-                                return Ok(());
-                            }
-                            _ => {}
-                        },
-                        InputMode::Editing => match key.code {
-                            KeyCode::Enter => {
-                                app.submit_message()?;
-                            }
-                            KeyCode::Esc => {
-                                state.input_mode = InputMode::Normal;
-                            }
-                            KeyCode::Char(c) => {
-                                app.handle_input(c);
-                            }
-                            KeyCode::Backspace => {
-                                app.backspace();
-                            }
-                            KeyCode::Up => {
-                                app.previous_input();
-                            }
-                            KeyCode::Down => {
-                                app.next_input();
-                            }
-                            _ => {}
-                        },
-                    }
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press && !handle_key(app, state, key)? => {
+                    return Ok(());
                 }
+                // Draw everything again at the new size instead of only what changed
+                Event::Resize(..) => terminal.clear()?,
+                _ => {}
             }
         }
         
-        // Process LLM response if loading
+        // Process LLM response if loading, watching for the interrupt key meanwhile
         if app.is_loading() {
-            app.process_response().await?;
+            let keymap = app.keymap().clone();
+            let theme = app.theme();
+            let layout = app.layout();
+            let draw_title = |title: &str| progress::draw_activity(title, &theme, layout);
+            let interrupter = app.interrupter();
+            let activity = app.activity();
+            let mut held_keys = Vec::new();
+            let mut resized = false;
+            tokio::select! {
+                result = app.process_response() => result?,
+                Err(e) = watch_for_interrupt(&keymap, draw_title, &interrupter, &activity, overlay_drawn, &mut held_keys, &mut resized) => return Err(e),
+            }
+            
+            // Keys pressed meanwhile apply once the reply is in
+            for key in held_keys {
+                if !handle_key(app, state, key)? {
+                    return Ok(());
+                }
+            }
+            
+            // An overlay drew behind the UI's back, or the terminal was resized
+            // meanwhile, so redraw everything
+            if overlay_drawn.swap(false, Ordering::Relaxed) || resized {
+                terminal.clear()?;
+            }
+            autosave(app, state);
         }
         
         // Update tick
         if state.last_tick.elapsed() >= tick_rate {
             state.last_tick = Instant::now();
+            if state.autosave.as_ref().is_some_and(Autosave::is_due) {
+                autosave(app, state);
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::mock::MockLlmClient;
+    use crate::stats::ToolTiming;
+    use ratatui::{backend::TestBackend, style::Modifier};
+    use serde_json::json;
+
+    fn app(client: MockLlmClient) -> SentinelApp {
+        let mut app = SentinelApp::with_client(Box::new(client), "mock");
+        app.input_history = InputHistory::in_memory(10);
+        app
+    }
+
+    fn send(app: &mut SentinelApp, input: &str) -> Result<()> {
+        app.input = input.to_string();
+        app.submit_message()
+    }
+
+    // Submit `input`, and have the agent handle what it left waiting
+    async fn run(app: &mut SentinelApp, input: &str) -> Result<()> {
+        send(app, input)?;
+        app.process_response().await
+    }
+
+    #[tokio::test]
+    async fn test_process_response_adds_the_reply() -> Result<()> {
+        let client = MockLlmClient::new()
+            .with_tool_call("ls", json!({ "path": "." }))
+            .with_reply("Three files");
+        let mut app = app(client.clone());
+
+        send(&mut app, "what is here?")?;
+        assert!(app.is_loading());
+        app.process_response().await?;
+
+        assert!(!app.is_loading());
+        let reply = app.messages().last().unwrap();
+        assert_eq!(reply.role, MessageRole::Assistant);
+        assert_eq!(reply.content, "Three files");
+        assert_eq!(app.get_current_tools(), vec!["ls".to_string()]);
+        assert_eq!(app.stats().requests, 1);
+        assert_eq!(app.activity().status(), None);
+        assert_eq!(client.remaining(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_replies_are_kept_and_marked() -> Result<()> {
+        let client = MockLlmClient::new()
+            .with_slow_reply("A very long answer", Duration::from_secs(30))
+            .with_reply("Short");
+        let mut app = app(client.clone());
+        let interrupter = app.interrupter();
+        tokio::spawn(async move {
+            while !interrupter.interrupt() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        });
+        
+        send(&mut app, "explain everything")?;
+        let started = Instant::now();
+        app.process_response().await?;
+        
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(!app.is_loading());
+        let reply = app.messages().last().unwrap();
+        assert_eq!(reply.role, MessageRole::Assistant);
+        assert_eq!(reply.content, "(interrupted)");
+        
+        send(&mut app, "just the gist")?;
+        app.process_response().await?;
+        assert_eq!(app.messages().last().unwrap().content, "Short");
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_tools_can_be_switched_off_and_on() -> Result<()> {
+        let mut app = app(MockLlmClient::new());
+        let last = |app: &SentinelApp| app.messages().last().unwrap().content.clone();
+        
+        run(&mut app, "/tools ls off").await?;
+        assert_eq!(last(&app), "ls is no longer offered to the model; /tools ls on offers it again");
+        run(&mut app, "/schema ls").await?;
+        assert_eq!(last(&app), format!("Error: {}", schema::not_offered("ls")));
+        
+        run(&mut app, "/tools ls on").await?;
+        assert_eq!(last(&app), "ls is offered to the model again");
+        run(&mut app, "/schema ls").await?;
+        assert!(last(&app).starts_with("ls"));
+        
+        run(&mut app, "/tools teleport off").await?;
+        assert_eq!(last(&app), "Error: No tool named 'teleport'; /tools lists them");
+        run(&mut app, "/tools ls maybe").await?;
+        assert_eq!(last(&app), "Error: Usage: /tools [<tool> on|off]");
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_messages_sent_during_a_reply_are_queued() -> Result<()> {
+        let client = MockLlmClient::new()
+            .with_slow_reply("first reply", Duration::from_millis(50))
+            .with_reply("second reply");
+        let mut app = app(client.clone());
+
+        send(&mut app, "first")?;
+        send(&mut app, "second")?;
+        send(&mut app, "third")?;
+        send(&mut app, "fourth")?;
+        let queued: Vec<&str> = app.queued().iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(queued, ["second", "third", "fourth"]);
+        assert_eq!(app.messages().last().unwrap().content, "first");
+
+        // Only /queue runs while a reply is generated
+        send(&mut app, "/tools")?;
+        assert_eq!(app.messages().last().unwrap().content, "Error: /tools can run once the reply is in");
+        send(&mut app, "/queue remove 2")?;
+        assert_eq!(app.messages().last().unwrap().content, "Cancelled queued message 2: third");
+        send(&mut app, "/queue remove 9")?;
+        assert_eq!(app.queued().len(), 2);
+
+        // Each reply sends the next queued message
+        app.process_response().await?;
+        assert!(app.is_loading());
+        assert_eq!(app.queued().len(), 1);
+        send(&mut app, "/queue clear")?;
+        assert_eq!(app.messages().last().unwrap().content, "Cancelled 1 queued message");
+        app.process_response().await?;
+        assert!(!app.is_loading());
+
+        let prompts: Vec<String> = client
+            .received()
+            .iter()
+            .map(|request| request.last().unwrap().content.clone())
+            .collect();
+        assert_eq!(prompts, ["first", "second"]);
+        let replies: Vec<&str> = app
+            .messages()
+            .iter()
+            .filter(|m| m.role == MessageRole::Assistant)
+            .map(|m| m.content.as_str())
+            .collect();
+        assert_eq!(replies, ["first reply", "second reply"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_the_first_reply_titles_the_session() -> Result<()> {
+        let titler = MockLlmClient::new().with_slow_reply("Title: Naming a cat.", Duration::from_millis(50));
+        let mut app = app(MockLlmClient::new().with_reply("Miso").with_reply("Or Tofu"))
+            .with_titler(Arc::new(titler.clone()));
+
+        send(&mut app, "name my cat")?;
+        app.process_response().await?;
+        // The title comes in the background, after the reply
+        assert_eq!(app.title(), None);
+        while app.title().is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            app.poll_title();
+        }
+        assert_eq!(app.title(), Some("Naming a cat"));
+        assert!(titler.received()[0][0].content.ends_with("User: name my cat\n\nAssistant: Miso"));
+
+        // Only the first reply asks for one
+        send(&mut app, "another")?;
+        app.process_response().await?;
+        assert_eq!(titler.received().len(), 1);
+
+        send(&mut app, "/sessions")?;
+        assert!(app.messages().last().unwrap().content.starts_with("Session: Naming a cat\n* main"));
+        send(&mut app, "/title   Cat  names ")?;
+        assert_eq!(app.session_meta().title.as_deref(), Some("Cat names"));
+        send(&mut app, "/title")?;
+        assert_eq!(app.messages().last().unwrap().content, "Title: Cat names");
+
+        // A new conversation starts untitled
+        send(&mut app, "/clear")?;
+        assert_eq!(app.title(), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_restored_sessions_keep_their_branches() -> Result<()> {
+        let mut sessions = Sessions::new(vec![
+            UiMessage::user("name my cat".to_string()),
+            UiMessage::assistant("Miso".to_string(), 10, 2),
+        ]);
+        sessions.fork(0)?;
+        sessions.messages_mut().push(UiMessage::assistant("Tofu".to_string(), 10, 2));
+        let saved = SessionFile::with_branches(&SessionMeta::default(), &sessions).to_json()?;
+        
+        let client = MockLlmClient::new().with_reply("Mochi");
+        let mut app = app(client.clone()).with_session(SessionFile::parse(&saved)?);
+        assert_eq!(app.branch_name(), "fork-1");
+        run(&mut app, "another").await?;
+        let sent: Vec<String> = client.received()[0].iter().map(|message| message.content.clone()).collect();
+        assert_eq!(&sent[sent.len() - 3..], ["name my cat", "Tofu", "another"]);
+        
+        send(&mut app, "/sessions main")?;
+        assert_eq!(app.messages()[1].content, "Miso");
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_failed_titles_leave_the_session_untitled() -> Result<()> {
+        let titler = MockLlmClient::new().with_error("model not found");
+        let mut app = app(MockLlmClient::new().with_reply("Hello")).with_titler(Arc::new(titler));
+
+        send(&mut app, "hi")?;
+        app.process_response().await?;
+        while app.pending_title.is_some() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            app.poll_title();
+        }
+        assert_eq!(app.title(), None);
+        assert_eq!(app.messages().last().unwrap().content, "Hello");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_set_limits_reach_the_request_and_mark_cut_off_replies() -> Result<()> {
+        let client = MockLlmClient::new().with_reply("one two three four five six seven eight");
+        let mut config = Config::default();
+        config.model.stop = vec!["END".to_string()];
+        let mut app = app(client.clone()).with_config(&config);
+
+        run(&mut app, "/set max_tokens 2").await?;
+        run(&mut app, "/set").await?;
+        assert_eq!(
+            app.messages().last().unwrap().content,
+            "Response limits: max_tokens 2, stop \"END\", timeout 10m"
+        );
+        run(&mut app, "/set max_tokens many").await?;
+        assert!(app.messages().last().unwrap().content.starts_with("Error: Usage: /set"));
+
+        send(&mut app, "count")?;
+        app.process_response().await?;
+        let reply = app.messages().last().unwrap();
+        assert_eq!(reply.content, "one two ");
+        assert!(reply.truncated);
+        assert_eq!(client.received_limits()[0].max_tokens, Some(2));
+        assert_eq!(client.received_limits()[0].stop, ["END"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_notes_and_errors_stay_out_of_the_history() -> Result<()> {
+        let client = MockLlmClient::new()
+            .with_error("connection refused")
+            .with_reply("Hello");
+        let mut app = app(client.clone());
+
+        send(&mut app, "/sessions")?;
+        send(&mut app, "hi")?;
+        app.process_response().await?;
+        let error = app.messages().last().unwrap();
+        assert_eq!(error.role, MessageRole::System);
+        assert!(error.error && error.ephemeral);
+        assert!(error.content.starts_with("Error: ") && error.content.contains("connection refused"));
+
+        // The UI lists every message, the model gets only the conversation
+        let contents: Vec<&str> = app.messages().iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents.len(), 4);
+        assert_eq!(contents[0], DEFAULT_SYSTEM_PROMPT);
+        let history = message::history(app.messages());
+        let sent: Vec<&str> = history.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(sent, [DEFAULT_SYSTEM_PROMPT, "hi"]);
+        
+        send(&mut app, "hi again")?;
+        app.process_response().await?;
+        let request = client.received().pop().unwrap();
+        let sent: Vec<&str> = request.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(sent, [DEFAULT_SYSTEM_PROMPT, "hi", "hi again"]);
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_the_conversation_follows_the_agent() -> Result<()> {
+        let client = MockLlmClient::new().with_reply("Hello").with_reply("Hi").with_reply("Hey");
+        let mut app = app(client);
+        let contents = |app: &SentinelApp| -> Vec<String> { app.messages().iter().map(|m| m.content.clone()).collect() };
+
+        run(&mut app, "hi").await?;
+        run(&mut app, "/stats").await?;
+        run(&mut app, "/best 2").await?;
+        app.accept_candidate();
+        app.process_response().await?;
+
+        // The picked candidate replaces the reply, and the notes stay where they were
+        let shown = contents(&app);
+        assert_eq!(shown[..2], [DEFAULT_SYSTEM_PROMPT, "hi"]);
+        assert!(shown[2].starts_with("Session: 1 request,"));
+        assert_eq!(shown[3..], ["Generating 2 candidates...", "Hi", "Kept candidate A"]);
+        assert_eq!(app.agent.conversation().len(), 3);
+
+        run(&mut app, "/clear").await?;
+        assert_eq!(contents(&app), [DEFAULT_SYSTEM_PROMPT, "Conversation cleared"]);
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_run_last_sends_the_output_to_the_model() -> Result<()> {
+        let client = MockLlmClient::new()
+            .with_reply("Try this:\n```sh\necho from the block\n```")
+            .with_reply("It printed what it should");
+        let mut app = app(client.clone());
+        
+        run(&mut app, "/run-last cat {}").await?;
+        assert_eq!(app.messages().last().unwrap().content, "Error: There is no reply to run a command over yet");
+        
+        send(&mut app, "print something")?;
+        app.process_response().await?;
+        send(&mut app, "/run-last cat {1}")?;
+        assert!(app.is_loading);
+        app.process_response().await?;
+        
+        let request = client.received().pop().unwrap();
+        let sent = &request.last().unwrap().content;
+        assert!(sent.starts_with("I ran `cat "));
+        assert!(sent.contains("sentinel-last-1.sh`. Output:\necho from the block"));
+        assert_eq!(app.messages().last().unwrap().content, "It printed what it should");
+        
+        // A reply without code has nothing to run over
+        run(&mut app, "/run-last cat {}").await?;
+        assert_eq!(
+            app.messages().last().unwrap().content,
+            "Error: The last reply has no code blocks to run a command over"
+        );
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_warm_up_and_replies_report_where_the_time_went() -> Result<()> {
+        let cold = ServerTimings::from_nanos(12_100_000_000, 800_000_000, 4_200_000_000);
+        let client = MockLlmClient::new().with_server_timings(cold).with_reply("Hello");
+        let (sender, receiver) = oneshot::channel();
+        let mut app = app(client.clone()).with_warming_up(receiver);
+        app.poll_warm_up();
+        assert!(app.is_warming_up());
+        
+        sender.send(client.warm_up().await.ok()).unwrap();
+        app.poll_warm_up();
+        assert!(!app.is_warming_up());
+        assert_eq!(app.messages().last().unwrap().content, "Loaded mock in 12.1s");
+        
+        send(&mut app, "hi")?;
+        app.process_response().await?;
+        assert_eq!(app.messages().last().unwrap().server_timings, Some(cold));
+        assert_eq!(app.stats().server_timings, cold);
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_apply_lists_the_blocks_before_writing_them() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let client = MockLlmClient::new()
+            .with_reply("```rust src/lib.rs\npub fn one() {}\n```\n```sh\necho hi\n```")
+            .with_reply("2: scripts/hi.sh");
+        let mut app = app(client);
+        app.agent.policy().set_sandbox_root(dir.path().to_path_buf());
+        send(&mut app, "write two files")?;
+        app.process_response().await?;
+        
+        // The model is asked about the block without a path in the loop
+        send(&mut app, "/apply")?;
+        assert!(app.is_loading);
+        app.process_response().await?;
+        let listed = &app.messages().last().unwrap().content;
+        assert!(listed.contains("  1. src/lib.rs (from its fence), 1 line\n  2. scripts/hi.sh (guessed by the model), 1 line"));
+        
+        // Paths can be changed, but not so two blocks share one
+        run(&mut app, "/apply 2 src/lib.rs").await?;
+        run(&mut app, "/apply all").await?;
+        assert!(!app.is_loading);
+        assert!(app.messages().last().unwrap().content.starts_with("Error: Blocks 1 and 2 both target src/lib.rs"));
+        
+        send(&mut app, "/apply 2")?;
+        app.process_response().await?;
+        assert_eq!(std::fs::read_to_string(dir.path().join("src/lib.rs"))?, "echo hi\n");
+        assert!(app.messages().last().unwrap().content.starts_with("Block 2: Successfully wrote file: "));
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_timed_out_replies_become_a_note() -> Result<()> {
+        let client = MockLlmClient::new().with_hang().with_reply("Back again");
+        let mut app = app(client).with_config(&Config::default());
+
+        run(&mut app, "/set timeout 1 fixed").await?;
+        send(&mut app, "hello?")?;
+        app.process_response().await?;
+        let note = app.messages().last().unwrap();
+        assert_eq!(note.role, MessageRole::System);
+        assert_eq!(note.content, "Error: Generation timed out after 1s");
+        assert!(note.error && note.ephemeral);
+        assert!(!app.is_loading);
+
+        // The session carries on with the next message
+        send(&mut app, "hello")?;
+        app.process_response().await?;
+        assert_eq!(app.messages().last().unwrap().content, "Back again");
+        Ok(())
+    }
+
+    #[test]
+    fn test_the_queue_is_bounded() -> Result<()> {
+        let mut app = app(MockLlmClient::new());
+        send(&mut app, "first")?;
+        for n in 0..MAX_QUEUED {
+            send(&mut app, &format!("queued {}", n))?;
         }
+        send(&mut app, "one too many")?;
+        assert_eq!(app.queued().len(), MAX_QUEUED);
+        assert_eq!(app.input(), "one too many");
+        assert!(app.messages().last().unwrap().content.contains("are queued already"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_best_opens_the_candidate_picker() -> Result<()> {
+        let client = MockLlmClient::new()
+            .with_reply("first")
+            .with_reply("second");
+        let mut app = app(client);
+
+        send(&mut app, "/best 2 name my cat")?;
+        app.process_response().await?;
+        assert_eq!(app.candidate_picker().map(|p| p.candidates.len()), Some(2));
+
+        app.select_candidate(true);
+        app.accept_candidate();
+        assert!(app.candidate_picker().is_none());
+        app.process_response().await?;
+        let reply = app.messages().iter().rev().find(|m| m.role == MessageRole::Assistant).unwrap();
+        assert_eq!(reply.content, "second");
+        assert_eq!(app.messages().last().unwrap().content, "Kept candidate B");
+        Ok(())
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_compare_opens_beside_the_last_reply() -> Result<()> {
+        let client = MockLlmClient::new()
+            .with_reply("llama says 4")
+            .with_model_client("qwen", MockLlmClient::new().with_reply("qwen says four"));
+        let mut app = app(client);
+        let mut state = TuiState::default();
+        
+        send(&mut app, "what is 2 + 2?")?;
+        app.process_response().await?;
+        run(&mut app, "/compare qwen").await?;
+        let headings: Vec<String> = app.comparison().unwrap().comparison.iter().map(|side| side.heading()).collect();
+        assert_eq!(headings.len(), 2);
+        assert_eq!(headings[1], "B qwen (not saved)");
+        assert_eq!(key_mode(&app, &state), Mode::Picker);
+        let reply = app.messages().iter().rev().find(|m| m.role == MessageRole::Assistant).unwrap();
+        assert_eq!(reply.content, "llama says 4");
+        
+        // Right moves to qwen's reply, and enter puts it in place of the last one
+        handle_key(&mut app, &mut state, KeyEvent::new(KeyCode::Right, crossterm::event::KeyModifiers::NONE))?;
+        handle_key(&mut app, &mut state, KeyEvent::new(KeyCode::Enter, crossterm::event::KeyModifiers::NONE))?;
+        assert!(app.comparison().is_none());
+        let replies: Vec<&str> = app
+            .messages()
+            .iter()
+            .filter(|m| m.role == MessageRole::Assistant)
+            .map(|m| m.content.as_str())
+            .collect();
+        assert_eq!(replies, vec!["qwen says four"]);
+        Ok(())
+    }
+    
+    #[tokio::test]
+    async fn test_commands_are_not_sent_to_the_model() -> Result<()> {
+        let mut app = app(MockLlmClient::new());
+
+        send(&mut app, "/load mysession")?;
+        run(&mut app, "/NOTIFY sometimes").await?;
+        send(&mut app, "/help undo")?;
+        assert!(!app.is_loading());
+        assert_eq!(app.messages().len(), 4);
+        let contents: Vec<&str> = app.messages()[1..].iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents[0], "Error: Unknown command: /load; type /help for available commands");
+        assert_eq!(contents[1], "Error: Usage: /notify on|off");
+        assert!(contents[2].starts_with("Usage: /undo [list|n]\n"));
+
+        // /exit quits once the input is submitted
+        let mut state = TuiState::default();
+        app.input = "/exit".to_string();
+        let enter = KeyEvent::new(KeyCode::Enter, crossterm::event::KeyModifiers::NONE);
+        assert!(!handle_key(&mut app, &mut state, enter)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_theme_switches_the_colors() -> Result<()> {
+        let mut app = app(MockLlmClient::new());
+        assert_eq!(app.theme(), Theme::dark());
+
+        send(&mut app, "/theme light")?;
+        assert_eq!(app.theme(), Theme::light());
+        send(&mut app, "/theme solarized")?;
+        assert_eq!(app.theme(), Theme::light());
+        let contents: Vec<&str> = app.messages()[1..].iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["Theme: light (available: dark, light)", "Error: Unknown theme 'solarized'; use dark or light"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_keys_follow_the_keymap() -> Result<()> {
+        let overrides = [("quit".to_string(), "ctrl-q".to_string())].into();
+        let mut app = app(MockLlmClient::new()).with_keymap(Keymap::with_overrides(&overrides)?);
+        let mut state = TuiState::default();
+        let press = |code| KeyEvent::new(code, crossterm::event::KeyModifiers::NONE);
+
+        // Typing goes into the input until editing stops
+        handle_key(&mut app, &mut state, press(KeyCode::Char('q')))?;
+        assert_eq!(app.input(), "q");
+        handle_key(&mut app, &mut state, press(KeyCode::Esc))?;
+
+        // '?' opens the help, and any key closes it without acting
+        handle_key(&mut app, &mut state, press(KeyCode::Char('?')))?;
+        assert!(app.help_visible());
+        assert!(handle_key(&mut app, &mut state, press(KeyCode::Char('q')))?);
+        assert!(!app.help_visible());
+
+        // 'q' no longer quits, ctrl-q does
+        assert!(handle_key(&mut app, &mut state, press(KeyCode::Char('q')))?);
+        let quit = KeyEvent::new(KeyCode::Char('q'), crossterm::event::KeyModifiers::CONTROL);
+        assert!(!handle_key(&mut app, &mut state, quit)?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_diff_opens_a_scrolling_overlay() -> Result<()> {
+        let mut app = app(MockLlmClient::new());
+        run(&mut app, "/diff").await?;
+        assert_eq!(app.messages().last().unwrap().content, "No files changed this session");
+        assert!(app.diff_view().is_none());
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("notes.txt");
+        let before = crate::tools::journal::backup(&path);
+        std::fs::write(&path, "one\ntwo\n")?;
+        app.agent.policy().journal().record_write(&path, before);
+
+        run(&mut app, "/diff").await?;
+        let lines = app.diff_view().unwrap().lines.clone();
+        assert!(lines[0].ends_with("notes.txt (new file, +2)"));
+        assert_eq!(lines.last().unwrap(), "1 file changed, +2 -0");
+
+        let mut state = TuiState::default();
+        let press = |code| KeyEvent::new(code, crossterm::event::KeyModifiers::NONE);
+        handle_key(&mut app, &mut state, press(KeyCode::Down))?;
+        handle_key(&mut app, &mut state, press(KeyCode::PageDown))?;
+        assert_eq!(app.diff_view().unwrap().scroll, lines.len() - 1);
+        handle_key(&mut app, &mut state, press(KeyCode::Up))?;
+        assert_eq!(app.diff_view().unwrap().scroll, lines.len() - 2);
+
+        // Any other key closes it without acting
+        assert!(handle_key(&mut app, &mut state, press(KeyCode::Char('x')))?);
+        assert!(app.diff_view().is_none());
+        assert_eq!(app.input(), "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats_toggle_and_the_cursor_stays_in_the_input_box() -> Result<()> {
+        let mut app = app(MockLlmClient::new());
+        let mut state = TuiState::default();
+        let press = |code| KeyEvent::new(code, crossterm::event::KeyModifiers::NONE);
+        let mut terminal = ratatui::Terminal::new(ratatui::backend::TestBackend::new(60, 20))?;
+        let status_row = |terminal: &ratatui::Terminal<ratatui::backend::TestBackend>, y| -> String {
+            (0..60).map(|x| terminal.backend().buffer().get(x, y).symbol.clone()).collect()
+        };
+
+        terminal.draw(|f| render_ui(f, &app))?;
+        assert!(status_row(&terminal, 2).starts_with("│In: 0 |"));
+        handle_key(&mut app, &mut state, press(KeyCode::Esc))?;
+        handle_key(&mut app, &mut state, press(KeyCode::Char('s')))?;
+        assert!(!app.stats_visible());
+        terminal.draw(|f| render_ui(f, &app))?;
+        assert!(status_row(&terminal, 3).starts_with("└"));
+
+        // Typing past the width of the box scrolls the input instead
+        app.input = "x".repeat(100);
+        terminal.draw(|f| render_ui(f, &app))?;
+        assert_eq!(terminal.get_cursor()?, (58, 18));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reasoning_is_kept_apart_and_toggled() -> Result<()> {
+        let client = MockLlmClient::new().with_reply("<think>Greet them back</think>Hello!");
+        let mut app = app(client);
+        let mut state = TuiState::default();
+        let press = |code| KeyEvent::new(code, crossterm::event::KeyModifiers::NONE);
+
+        send(&mut app, "hi")?;
+        app.process_response().await?;
+        let reply = app.messages().last().unwrap();
+        assert_eq!(reply.content, "Hello!");
+        assert_eq!(reply.reasoning.as_deref(), Some("Greet them back"));
+        assert!(app.stats().reasoning_tokens > 0);
+
+        handle_key(&mut app, &mut state, press(KeyCode::Esc))?;
+        assert!(!app.reasoning_visible());
+        handle_key(&mut app, &mut state, press(KeyCode::Char('t')))?;
+        assert!(app.reasoning_visible());
+        Ok(())
+    }
+    
+    // `app` drawn on a `width` x `height` terminal
+    fn screen(app: &SentinelApp, width: u16, height: u16) -> Terminal<TestBackend> {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|f| render_ui(f, app)).unwrap();
+        terminal
+    }
+    
+    // The rows of the screen, and whether each is drawn reversed, as the selection is
+    fn rows(app: &SentinelApp) -> Vec<(String, bool)> {
+        let terminal = screen(app, 40, 20);
+        let buffer = terminal.backend().buffer();
+        (0..20)
+            .map(|y| {
+                let text = (0..40).map(|x| buffer.get(x, y).symbol.as_str()).collect();
+                (text, buffer.get(1, y).modifier.contains(Modifier::REVERSED))
+            })
+            .collect()
+    }
+    
+    // Whether the row showing `text` is reversed, if a row shows it
+    fn shown(app: &SentinelApp, text: &str) -> Option<bool> {
+        rows(app)
+            .into_iter()
+            .find(|(row, _)| row.starts_with('│') && row.contains(text))
+            .map(|(_, reversed)| reversed)
+    }
+    
+    #[test]
+    fn test_messages_are_selected_with_the_arrow_keys() -> Result<()> {
+        let mut messages = vec![UiMessage::system("Be brief.".to_string())];
+        messages.extend((1..=20).map(|n| UiMessage::user(format!("message {}", n))));
+        let mut app = app(MockLlmClient::new()).with_sessions(Sessions::new(messages));
+        let mut state = TuiState { input_mode: InputMode::Normal, ..TuiState::default() };
+        let press = |code| KeyEvent::new(code, crossterm::event::KeyModifiers::NONE);
+        
+        // Without a selection the view follows the latest message
+        assert_eq!(app.selected(), None);
+        assert_eq!(shown(&app, "message 20"), Some(false));
+        assert_eq!(shown(&app, "Be brief."), None);
+        
+        // Up selects the latest message, and moving up scrolls the selection into view
+        handle_key(&mut app, &mut state, press(KeyCode::Up))?;
+        assert_eq!(app.selected(), Some(20));
+        assert_eq!(shown(&app, "message 20"), Some(true));
+        for _ in 0..30 {
+            handle_key(&mut app, &mut state, press(KeyCode::Char('k')))?;
+        }
+        assert_eq!(app.selected(), Some(0));
+        assert_eq!(shown(&app, "Be brief."), Some(true));
+        assert_eq!(shown(&app, "message 20"), None);
+        assert!(rows(&app)[5].0.contains("Conversation (message 0: y copy, p pin"));
+        
+        handle_key(&mut app, &mut state, press(KeyCode::Down))?;
+        handle_key(&mut app, &mut state, press(KeyCode::Char('j')))?;
+        assert_eq!(app.selected(), Some(2));
+        assert_eq!(shown(&app, "message 2"), Some(true));
+        assert_eq!(shown(&app, "message 1"), Some(false));
+        for _ in 0..30 {
+            handle_key(&mut app, &mut state, press(KeyCode::Down))?;
+        }
+        assert_eq!(app.selected(), Some(20));
+        
+        // G and Esc go back to following the latest message
+        handle_key(&mut app, &mut state, press(KeyCode::Char('G')))?;
+        assert_eq!(app.selected(), None);
+        assert!(rows(&app).iter().all(|(_, reversed)| !reversed));
+        handle_key(&mut app, &mut state, press(KeyCode::Char('k')))?;
+        handle_key(&mut app, &mut state, press(KeyCode::Esc))?;
+        assert_eq!(app.selected(), None);
+        assert_eq!(shown(&app, "message 20"), Some(false));
+        Ok(())
+    }
+    
+    #[test]
+    fn test_the_zen_layout_is_toggled_and_saved() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "word_counts = true\n")?;
+        let mut messages = vec![UiMessage::system("Be brief.".to_string())];
+        messages.extend((1..=20).map(|n| UiMessage::user(format!("message {}", n))));
+        let mut app = app(MockLlmClient::new()).with_sessions(Sessions::new(messages)).with_layout_file(Some(path.clone()));
+        let mut state = TuiState { input_mode: InputMode::Normal, ..TuiState::default() };
+        let press = |code| KeyEvent::new(code, crossterm::event::KeyModifiers::NONE);
+        
+        handle_key(&mut app, &mut state, press(KeyCode::Char('z')))?;
+        assert_eq!(app.layout(), LayoutMode::Zen);
+        assert_eq!(Config::load_from(&path)?.layout, LayoutMode::Zen);
+        assert!(std::fs::read_to_string(&path)?.starts_with("word_counts = true\n"));
+        // The conversation takes every row but the two of the input line
+        let zen = rows(&app);
+        assert!(zen[17].0.starts_with("You: message 20"));
+        assert!(zen[18].0.starts_with("Input | mock | main"));
+        
+        // The selection is centered in the taller view, and its keys show in the input line
+        for _ in 0..3 {
+            handle_key(&mut app, &mut state, press(KeyCode::Up))?;
+        }
+        let zen = rows(&app);
+        let selected: Vec<&String> = zen.iter().filter(|(_, reversed)| *reversed).map(|(row, _)| row).collect();
+        assert_eq!(selected.len(), 1);
+        assert!(selected[0].starts_with("You: message 18"));
+        assert!(zen[9].1);
+        assert!(zen[18].0.starts_with("Conversation (message 18: y copy"));
+        
+        // The stats are an overlay, shown only when asked
+        assert!(!zen.iter().any(|(row, _)| row.contains("Input tokens")));
+        handle_key(&mut app, &mut state, press(KeyCode::Char('s')))?;
+        assert!(app.zen_stats_visible());
+        assert!(rows(&app).iter().any(|(row, _)| row.contains("Input tokens: 0")));
+        handle_key(&mut app, &mut state, press(KeyCode::Char('s')))?;
+        assert!(!app.zen_stats_visible());
+        
+        // Back to the full layout, saved again
+        handle_key(&mut app, &mut state, press(KeyCode::Char('z')))?;
+        assert_eq!(Config::load_from(&path)?.layout, LayoutMode::Full);
+        assert!(app.stats_visible());
+        assert!(rows(&app)[0].0.starts_with("┌Sentinel"));
+        assert_eq!(shown(&app, "message 18"), Some(true));
+        
+        // A config that can't be written leaves the layout switched, with a note
+        app.layout_file = Some(dir.path().to_path_buf());
+        handle_key(&mut app, &mut state, press(KeyCode::Char('z')))?;
+        assert_eq!(app.layout(), LayoutMode::Zen);
+        assert!(app.messages().last().unwrap().content.starts_with("The zen layout is not saved: "));
+        Ok(())
+    }
+    
+    #[test]
+    fn test_actions_on_the_selected_message() -> Result<()> {
+        let timings = vec![ToolTiming {
+            label: "bash".to_string(),
+            tool: "bash".to_string(),
+            duration: Duration::from_millis(1200),
+        }];
+        let reply = UiMessage::assistant_with_tools("Two files".to_string(), 10, 5, vec!["bash".to_string()])
+            .with_tool_timings(timings)
+            .with_reasoning(Some("Listing them first".to_string()));
+        let mut app = app(MockLlmClient::new()).with_sessions(Sessions::new(vec![
+            UiMessage::system("Be brief.".to_string()),
+            UiMessage::user("What is here?".to_string()),
+            reply,
+        ]));
+        let mut state = TuiState { input_mode: InputMode::Normal, ..TuiState::default() };
+        let press = |code| KeyEvent::new(code, crossterm::event::KeyModifiers::NONE);
+        
+        handle_key(&mut app, &mut state, press(KeyCode::Up))?;
+        assert_eq!(app.selected(), Some(2));
+        
+        // p pins and unpins it
+        handle_key(&mut app, &mut state, press(KeyCode::Char('p')))?;
+        assert!(app.messages()[2].pinned);
+        handle_key(&mut app, &mut state, press(KeyCode::Char('p')))?;
+        assert!(!app.messages()[2].pinned);
+        assert_eq!(app.messages()[4].content, "Unpinned message 2");
+        
+        // Enter shows its reasoning and each tool call on a line
+        assert_eq!(shown(&app, "Listing them first"), None);
+        handle_key(&mut app, &mut state, press(KeyCode::Enter))?;
+        assert!(app.messages()[2].expanded);
+        assert_eq!(shown(&app, "Listing them first"), Some(true));
+        assert!(rows(&app).iter().any(|(row, _)| row.starts_with("│    bash: 1.2s")));
+        
+        // y copies it, which in a test has no terminal to go to
+        handle_key(&mut app, &mut state, press(KeyCode::Char('y')))?;
+        assert!(app.messages().last().unwrap().content.contains("message 2"));
+        
+        // f forks a branch that ends with it, and selection starts over there
+        handle_key(&mut app, &mut state, press(KeyCode::Char('f')))?;
+        assert_eq!(app.selected(), None);
+        assert_eq!(app.sessions.active_name(), "fork-1");
+        assert_eq!(app.messages()[3].content, "Forked 'main' at message 2 into 'fork-1'");
+        
+        // Without a selection the keys say how to make one
+        handle_key(&mut app, &mut state, press(KeyCode::Char('p')))?;
+        assert_eq!(app.messages().last().unwrap().content, "No message is selected; press up to select one");
+        Ok(())
+    }
+    
+    #[test]
+    fn test_contents_moved_to_blobs_load_when_expanded() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let store = BlobStore::next_to(&dir.path().join("last-session.json"));
+        let moved = |content: &str| -> Result<UiMessage> {
+            let blob = store.put(content)?;
+            let mut message = UiMessage::assistant(crate::session_blobs::placeholder(&blob), 1, 1);
+            message.blob = Some(blob);
+            Ok(message)
+        };
+        let mut app = app(MockLlmClient::new()).with_sessions(Sessions::new(vec![moved("the whole report")?, moved("gone")?]));
+        app.blobs = Some(store.clone());
+        let mut state = TuiState { input_mode: InputMode::Normal, ..TuiState::default() };
+        let press = |code| KeyEvent::new(code, crossterm::event::KeyModifiers::NONE);
+        
+        // Nothing is read until the message is expanded
+        handle_key(&mut app, &mut state, press(KeyCode::Up))?;
+        handle_key(&mut app, &mut state, press(KeyCode::Char('k')))?;
+        assert!(app.messages()[0].content.contains("press Enter to load it"));
+        handle_key(&mut app, &mut state, press(KeyCode::Enter))?;
+        assert_eq!(app.messages()[0].content, "the whole report");
+        assert_eq!(app.messages()[0].blob, None);
+        
+        // A blob that was pruned leaves the placeholder and says why
+        let blob = app.messages()[1].blob.clone().unwrap();
+        std::fs::remove_file(store.path(&blob))?;
+        handle_key(&mut app, &mut state, press(KeyCode::Char('j')))?;
+        handle_key(&mut app, &mut state, press(KeyCode::Enter))?;
+        assert!(app.messages()[1].blob.is_some());
+        assert!(app.messages().last().unwrap().content.ends_with("the message's content could not be loaded"));
+        Ok(())
+    }
+    
+    #[test]
+    fn test_links_in_the_selected_message_are_listed_to_open() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("app"))?;
+        std::fs::write(dir.path().join("secret.txt"), "")?;
+        let mut app = app(MockLlmClient::new()).with_sessions(Sessions::new(vec![
+            UiMessage::user("Compare https://a.example/x with https://b.example/y, or https://a.example/x again".to_string()),
+            UiMessage::assistant(format!("It is in {}/secret.txt.", dir.path().display()), 1, 1),
+            UiMessage::assistant("Version 1.2.3 is out".to_string(), 1, 1),
+        ]));
+        app.agent.policy().set_sandbox_root(dir.path().join("app"));
+        let mut state = TuiState { input_mode: InputMode::Normal, ..TuiState::default() };
+        let press = |code| KeyEvent::new(code, crossterm::event::KeyModifiers::NONE);
+        
+        // Nothing here looks like a link
+        handle_key(&mut app, &mut state, press(KeyCode::Up))?;
+        handle_key(&mut app, &mut state, press(KeyCode::Char('o')))?;
+        assert_eq!(app.messages().last().unwrap().content, "Message 2 has no URLs or paths of existing files");
+        
+        // A path outside the sandbox root is refused without running the opener
+        handle_key(&mut app, &mut state, press(KeyCode::Char('k')))?;
+        handle_key(&mut app, &mut state, press(KeyCode::Char('o')))?;
+        let refused = &app.messages().last().unwrap().content;
+        assert!(refused.starts_with("Error: Could not open "), "{}", refused);
+        assert!(refused.contains("outside the sandbox root"));
+        
+        // Several links are listed once each to choose from
+        handle_key(&mut app, &mut state, press(KeyCode::Char('k')))?;
+        handle_key(&mut app, &mut state, press(KeyCode::Char('o')))?;
+        assert_eq!(key_mode(&app, &state), Mode::Picker);
+        let listed: Vec<&str> = app.link_picker().unwrap().links.iter().map(|(text, _)| text.as_str()).collect();
+        assert_eq!(listed, ["https://a.example/x", "https://b.example/y"]);
+        handle_key(&mut app, &mut state, press(KeyCode::Down))?;
+        assert_eq!(app.link_picker().unwrap().selected, 1);
+        assert!(rows(&app).iter().any(|(row, _)| row.contains("> https://b.example/y")));
+        handle_key(&mut app, &mut state, press(KeyCode::Esc))?;
+        assert!(app.link_picker().is_none());
+        assert_eq!(key_mode(&app, &state), Mode::Normal);
+        Ok(())
+    }
+    
+    #[test]
+    fn test_the_cursor_follows_wide_input() {
+        let mut app = app(MockLlmClient::new());
+        let cursor = |app: &SentinelApp| screen(app, 40, 20).get_cursor().unwrap();
+        let (_, row) = cursor(&app);
+        
+        // Each CJK character takes two columns
+        for c in "你好 hi".chars() {
+            app.handle_input(c);
+        }
+        assert_eq!(cursor(&app), (1 + 7, row));
+        
+        // Past the edge the input scrolls, keeping the cursor in the box
+        for _ in 0..20 {
+            app.handle_input('世');
+        }
+        assert_eq!(cursor(&app), (38, row));
+    }
+}
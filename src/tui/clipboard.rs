@@ -0,0 +1,38 @@
+//! Copying text to the system clipboard from the TUI.
+//!
+//! The text is sent to the terminal as an OSC 52 escape sequence, which
+//! terminals such as iTerm2, kitty, WezTerm, Alacritty and tmux (with
+//! `set-clipboard on`) put on the clipboard. It works over SSH as well, and
+//! needs no clipboard tool on the machine Sentinel runs on.
+
+use std::io::{self, IsTerminal, Write};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+/// The escape sequence that puts `text` on the clipboard
+pub fn osc52(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", STANDARD.encode(text))
+}
+
+/// Put `text` on the clipboard, returning whether the terminal was asked to.
+///
+/// Nothing is written when stdout is not a terminal, e.g. in tests.
+pub fn copy(text: &str) -> bool {
+    let mut stdout = io::stdout();
+    if !stdout.is_terminal() {
+        return false;
+    }
+    stdout.write_all(osc52(text).as_bytes()).is_ok() && stdout.flush().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_osc52_encodes_the_text() {
+        assert_eq!(osc52("hi"), "\x1b]52;c;aGk=\x07");
+        assert_eq!(osc52("你好"), "\x1b]52;c;5L2g5aW9\x07");
+    }
+}
@@ -0,0 +1,658 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// What the keyboard is driving at the moment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Normal,
+    Editing,
+    HistorySearch,
+    Search,
+    Picker,
+    Review,
+    ReviewReason,
+    Busy,
+}
+
+/// Every mode, in the order the help overlay lists them
+pub const MODES: [Mode; 8] = [
+    Mode::Normal,
+    Mode::Editing,
+    Mode::HistorySearch,
+    Mode::Search,
+    Mode::Picker,
+    Mode::Review,
+    Mode::ReviewReason,
+    Mode::Busy,
+];
+
+impl Mode {
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::Normal => "Normal",
+            Self::Editing => "Editing",
+            Self::HistorySearch => "History search",
+            Self::Search => "Conversation search",
+            Self::Picker => "Candidates, comparisons and links",
+            Self::Review => "Change review",
+            Self::ReviewReason => "Reason for declining a change",
+            Self::Busy => "Waiting for a reply",
+        }
+    }
+
+    // Modes where unbound keys are typed into an input
+    fn takes_text(self) -> bool {
+        matches!(
+            self,
+            Self::Editing | Self::HistorySearch | Self::Search | Self::ReviewReason
+        )
+    }
+}
+
+/// Something a key can do in the TUI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    Help,
+    StartEditing,
+    StopEditing,
+    Submit,
+    PreviousInput,
+    NextInput,
+    HistorySearch,
+    AcceptHistory,
+    CancelHistory,
+    Search,
+    ToggleReasoning,
+    ToggleSystem,
+    ToggleStats,
+    ToggleLayout,
+    NextMatch,
+    PreviousMatch,
+    ClearSearch,
+    FinishSearch,
+    CancelSearch,
+    ToggleRegex,
+    SelectPrevious,
+    SelectNext,
+    FollowLatest,
+    CopyMessage,
+    OpenLink,
+    TogglePin,
+    ForkHere,
+    ToggleDetails,
+    PreviousCandidate,
+    NextCandidate,
+    AcceptCandidate,
+    DiscardCandidates,
+    ApproveChange,
+    ApproveAllChanges,
+    RejectChange,
+    ScrollUp,
+    ScrollDown,
+    SendReason,
+    SkipReason,
+    Interrupt,
+}
+
+const ACTIONS: [Action; 41] = [
+    Action::Quit,
+    Action::Help,
+    Action::StartEditing,
+    Action::StopEditing,
+    Action::Submit,
+    Action::PreviousInput,
+    Action::NextInput,
+    Action::HistorySearch,
+    Action::AcceptHistory,
+    Action::CancelHistory,
+    Action::Search,
+    Action::ToggleReasoning,
+    Action::ToggleSystem,
+    Action::ToggleStats,
+    Action::ToggleLayout,
+    Action::NextMatch,
+    Action::PreviousMatch,
+    Action::ClearSearch,
+    Action::FinishSearch,
+    Action::CancelSearch,
+    Action::ToggleRegex,
+    Action::SelectPrevious,
+    Action::SelectNext,
+    Action::FollowLatest,
+    Action::CopyMessage,
+    Action::OpenLink,
+    Action::TogglePin,
+    Action::ForkHere,
+    Action::ToggleDetails,
+    Action::PreviousCandidate,
+    Action::NextCandidate,
+    Action::AcceptCandidate,
+    Action::DiscardCandidates,
+    Action::ApproveChange,
+    Action::ApproveAllChanges,
+    Action::RejectChange,
+    Action::ScrollUp,
+    Action::ScrollDown,
+    Action::SendReason,
+    Action::SkipReason,
+    Action::Interrupt,
+];
+
+impl Action {
+    /// The name used for the action in the `[keys]` config table
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Quit => "quit",
+            Self::Help => "help",
+            Self::StartEditing => "edit",
+            Self::StopEditing => "stop_editing",
+            Self::Submit => "submit",
+            Self::PreviousInput => "previous_input",
+            Self::NextInput => "next_input",
+            Self::HistorySearch => "history_search",
+            Self::AcceptHistory => "accept_history",
+            Self::CancelHistory => "cancel_history",
+            Self::Search => "search",
+            Self::ToggleReasoning => "toggle_reasoning",
+            Self::ToggleSystem => "toggle_system",
+            Self::ToggleStats => "toggle_stats",
+            Self::ToggleLayout => "toggle_layout",
+            Self::NextMatch => "next_match",
+            Self::PreviousMatch => "previous_match",
+            Self::ClearSearch => "clear_search",
+            Self::FinishSearch => "finish_search",
+            Self::CancelSearch => "cancel_search",
+            Self::ToggleRegex => "toggle_regex",
+            Self::SelectPrevious => "select_previous",
+            Self::SelectNext => "select_next",
+            Self::FollowLatest => "follow_latest",
+            Self::CopyMessage => "copy_message",
+            Self::OpenLink => "open_link",
+            Self::TogglePin => "toggle_pin",
+            Self::ForkHere => "fork_here",
+            Self::ToggleDetails => "toggle_details",
+            Self::PreviousCandidate => "previous_candidate",
+            Self::NextCandidate => "next_candidate",
+            Self::AcceptCandidate => "accept_candidate",
+            Self::DiscardCandidates => "discard_candidates",
+            Self::ApproveChange => "approve_change",
+            Self::ApproveAllChanges => "approve_all_changes",
+            Self::RejectChange => "reject_change",
+            Self::ScrollUp => "scroll_up",
+            Self::ScrollDown => "scroll_down",
+            Self::SendReason => "send_reason",
+            Self::SkipReason => "skip_reason",
+            Self::Interrupt => "interrupt",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Quit => "Quit",
+            Self::Help => "Show this help",
+            Self::StartEditing => "Start typing a message",
+            Self::StopEditing => "Stop typing (normal mode)",
+            Self::Submit => "Send the message",
+            Self::PreviousInput => "Previous prompt from history",
+            Self::NextInput => "Next prompt from history",
+            Self::HistorySearch => "Search prompt history (again for older matches)",
+            Self::AcceptHistory => "Use the matched prompt",
+            Self::CancelHistory => "Cancel the history search",
+            Self::Search => "Search the conversation",
+            Self::ToggleReasoning => "Show or hide the reasoning of reasoning models",
+            Self::ToggleSystem => "Expand or collapse long system messages",
+            Self::ToggleStats => {
+                "Show or hide the stats panel, its line on narrow terminals, or its overlay in zen"
+            }
+            Self::ToggleLayout => "Switch between the full and the zen layout, and save the choice",
+            Self::NextMatch => "Next search match",
+            Self::PreviousMatch => "Previous search match",
+            Self::ClearSearch => "Clear the search and the selection",
+            Self::FinishSearch => "Keep the search and browse matches",
+            Self::CancelSearch => "Cancel the search",
+            Self::ToggleRegex => "Toggle regex search",
+            Self::SelectPrevious => "Select the previous message",
+            Self::SelectNext => "Select the next message",
+            Self::FollowLatest => "Drop the selection and follow the latest message",
+            Self::CopyMessage => "Copy the selected message",
+            Self::OpenLink => "Open a URL or file path from the selected message",
+            Self::TogglePin => "Pin or unpin the selected message",
+            Self::ForkHere => "Fork a new branch from the selected message",
+            Self::ToggleDetails => "Show or hide the selected message's details",
+            Self::PreviousCandidate => "Previous candidate",
+            Self::NextCandidate => "Next candidate",
+            Self::AcceptCandidate => "Keep the selected candidate",
+            Self::DiscardCandidates => "Discard all candidates",
+            Self::ApproveChange => "Apply the change",
+            Self::ApproveAllChanges => "Apply this and every later change in the session",
+            Self::RejectChange => "Decline the change, then say why",
+            Self::ScrollUp => "Scroll up",
+            Self::ScrollDown => "Scroll down",
+            Self::SendReason => "Decline with this reason",
+            Self::SkipReason => "Decline without a reason",
+            Self::Interrupt => "Stop the reply, keeping what was said so far",
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        ACTIONS
+            .into_iter()
+            .find(|action| action.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<&str> = ACTIONS.iter().map(|action| action.name()).collect();
+                anyhow!(
+                    "Unknown key binding '{}', expected one of: {}",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// A key with its modifiers, e.g. `ctrl-r` or `esc`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chord {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Chord {
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        // Shift is already part of the character (or of BackTab)
+        let modifiers = match code {
+            KeyCode::Char(_) | KeyCode::BackTab => modifiers - KeyModifiers::SHIFT,
+            _ => modifiers,
+        };
+        Self { code, modifiers }
+    }
+
+    pub fn from_event(event: &KeyEvent) -> Self {
+        Self::new(event.code, event.modifiers)
+    }
+
+    // A printable key without modifiers, which text inputs need for typing
+    fn is_plain_char(&self) -> bool {
+        matches!(self.code, KeyCode::Char(_)) && self.modifiers.is_empty()
+    }
+}
+
+impl FromStr for Chord {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        // Split off the key, allowing '-' itself as in "ctrl--"
+        let (prefix, key) = match text.rfind('-') {
+            Some(i) if i + 1 < text.len() => (&text[..i], &text[i + 1..]),
+            _ if text.len() > 1 && text.ends_with("--") => (&text[..text.len() - 2], "-"),
+            _ => ("", text),
+        };
+
+        let mut modifiers = KeyModifiers::NONE;
+        for modifier in prefix.split('-').filter(|part| !part.is_empty()) {
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" | "meta" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => bail!("Unknown modifier '{}' in '{}'", modifier, text),
+            };
+        }
+
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) if modifiers.contains(KeyModifiers::SHIFT) => {
+                KeyCode::Char(c.to_ascii_uppercase())
+            }
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match key.to_lowercase().as_str() {
+                "esc" | "escape" => KeyCode::Esc,
+                "enter" | "return" => KeyCode::Enter,
+                "tab" if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+                "tab" => KeyCode::Tab,
+                "backtab" => KeyCode::BackTab,
+                "backspace" => KeyCode::Backspace,
+                "delete" | "del" => KeyCode::Delete,
+                "insert" => KeyCode::Insert,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "space" => KeyCode::Char(' '),
+                name => match name.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                    Some(n @ 1..=12) => KeyCode::F(n),
+                    _ => bail!("Unknown key '{}' in '{}'", key, text),
+                },
+            },
+        };
+
+        Ok(Self::new(code, modifiers))
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "ctrl-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "alt-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "shift-")?;
+        }
+
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "f{}", n),
+            KeyCode::Esc => write!(f, "esc"),
+            KeyCode::Enter => write!(f, "enter"),
+            KeyCode::Tab => write!(f, "tab"),
+            KeyCode::BackTab => write!(f, "shift-tab"),
+            KeyCode::Backspace => write!(f, "backspace"),
+            KeyCode::Delete => write!(f, "delete"),
+            KeyCode::Insert => write!(f, "insert"),
+            KeyCode::Home => write!(f, "home"),
+            KeyCode::End => write!(f, "end"),
+            KeyCode::PageUp => write!(f, "pageup"),
+            KeyCode::PageDown => write!(f, "pagedown"),
+            KeyCode::Up => write!(f, "up"),
+            KeyCode::Down => write!(f, "down"),
+            KeyCode::Left => write!(f, "left"),
+            KeyCode::Right => write!(f, "right"),
+            code => write!(f, "{:?}", code),
+        }
+    }
+}
+
+/// A key bound to an action in one mode
+#[derive(Debug, Clone, Copy)]
+pub struct Binding {
+    pub mode: Mode,
+    pub action: Action,
+    pub chord: Chord,
+}
+
+// The built-in bindings; an action listed in several modes is rebound in all of them
+const DEFAULTS: [(Mode, Action, &str); 47] = [
+    (Mode::Normal, Action::StartEditing, "e"),
+    (Mode::Normal, Action::Search, "/"),
+    (Mode::Normal, Action::NextMatch, "n"),
+    (Mode::Normal, Action::PreviousMatch, "N"),
+    (Mode::Normal, Action::ClearSearch, "esc"),
+    (Mode::Normal, Action::SelectPrevious, "up"),
+    (Mode::Normal, Action::SelectPrevious, "k"),
+    (Mode::Normal, Action::SelectNext, "down"),
+    (Mode::Normal, Action::SelectNext, "j"),
+    (Mode::Normal, Action::FollowLatest, "G"),
+    (Mode::Normal, Action::CopyMessage, "y"),
+    (Mode::Normal, Action::OpenLink, "o"),
+    (Mode::Normal, Action::TogglePin, "p"),
+    (Mode::Normal, Action::ForkHere, "f"),
+    (Mode::Normal, Action::ToggleDetails, "enter"),
+    (Mode::Normal, Action::ToggleReasoning, "t"),
+    (Mode::Normal, Action::ToggleSystem, "x"),
+    (Mode::Normal, Action::ToggleStats, "s"),
+    (Mode::Normal, Action::ToggleLayout, "z"),
+    (Mode::Normal, Action::Help, "?"),
+    (Mode::Normal, Action::Quit, "q"),
+    (Mode::Editing, Action::Submit, "enter"),
+    (Mode::Editing, Action::StopEditing, "esc"),
+    (Mode::Editing, Action::PreviousInput, "up"),
+    (Mode::Editing, Action::NextInput, "down"),
+    (Mode::Editing, Action::HistorySearch, "ctrl-r"),
+    (Mode::Editing, Action::Help, "f1"),
+    (Mode::HistorySearch, Action::HistorySearch, "ctrl-r"),
+    (Mode::HistorySearch, Action::AcceptHistory, "enter"),
+    (Mode::HistorySearch, Action::CancelHistory, "esc"),
+    (Mode::Search, Action::FinishSearch, "enter"),
+    (Mode::Search, Action::CancelSearch, "esc"),
+    (Mode::Search, Action::ToggleRegex, "tab"),
+    (Mode::Picker, Action::PreviousCandidate, "up"),
+    (Mode::Picker, Action::NextCandidate, "down"),
+    (Mode::Picker, Action::PreviousCandidate, "left"),
+    (Mode::Picker, Action::NextCandidate, "right"),
+    (Mode::Picker, Action::AcceptCandidate, "enter"),
+    (Mode::Picker, Action::DiscardCandidates, "esc"),
+    (Mode::Review, Action::ApproveChange, "y"),
+    (Mode::Review, Action::ApproveAllChanges, "a"),
+    (Mode::Review, Action::RejectChange, "n"),
+    (Mode::Review, Action::ScrollUp, "up"),
+    (Mode::Review, Action::ScrollDown, "down"),
+    (Mode::ReviewReason, Action::SendReason, "enter"),
+    (Mode::ReviewReason, Action::SkipReason, "esc"),
+    (Mode::Busy, Action::Interrupt, "esc"),
+];
+
+/// Which key does what in each mode of the TUI.
+///
+/// Both key handling and the help overlay are driven by this table, so
+/// bindings overridden in the config file show up correctly in the help.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: Vec<Binding>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bindings = DEFAULTS
+            .iter()
+            .map(|&(mode, action, chord)| Binding {
+                mode,
+                action,
+                chord: chord.parse().expect("default key bindings parse"),
+            })
+            .collect();
+        Self { bindings }
+    }
+}
+
+impl Keymap {
+    /// The default bindings with overrides from the `[keys]` config table applied
+    pub fn with_overrides(overrides: &BTreeMap<String, String>) -> Result<Self> {
+        let mut keymap = Self::default();
+        for (name, chord) in overrides {
+            let action: Action = name.parse()?;
+            let chord: Chord = chord
+                .parse()
+                .with_context(|| format!("Invalid key for '{}'", name))?;
+
+            for binding in keymap.bindings.iter_mut() {
+                if binding.action == action {
+                    binding.chord = chord;
+                }
+            }
+        }
+        // An action with several default keys is left with the one
+        keymap
+            .bindings
+            .dedup_by(|a, b| a.mode == b.mode && a.action == b.action && a.chord == b.chord);
+
+        keymap.validate()?;
+        Ok(keymap)
+    }
+
+    // Refuse bindings that would shadow typing or each other
+    fn validate(&self) -> Result<()> {
+        for (i, binding) in self.bindings.iter().enumerate() {
+            if binding.mode.takes_text() && binding.chord.is_plain_char() {
+                bail!(
+                    "Key '{}' for '{}' would stop it being typed in {} mode, add a modifier such as ctrl-",
+                    binding.chord,
+                    binding.action.name(),
+                    binding.mode.title().to_lowercase()
+                );
+            }
+
+            let conflict = self.bindings[i + 1..].iter().find(|other| {
+                other.mode == binding.mode
+                    && other.chord == binding.chord
+                    && other.action != binding.action
+            });
+            if let Some(other) = conflict {
+                bail!(
+                    "Key '{}' is bound to both '{}' and '{}' in {} mode",
+                    binding.chord,
+                    binding.action.name(),
+                    other.action.name(),
+                    binding.mode.title().to_lowercase()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The action a key press triggers in `mode`, if any
+    pub fn action(&self, mode: Mode, event: &KeyEvent) -> Option<Action> {
+        let chord = Chord::from_event(event);
+        self.bindings
+            .iter()
+            .find(|binding| binding.mode == mode && binding.chord == chord)
+            .map(|binding| binding.action)
+    }
+
+    /// The key for an action in `mode`, for hints in titles
+    pub fn key(&self, mode: Mode, action: Action) -> String {
+        self.bindings
+            .iter()
+            .find(|binding| binding.mode == mode && binding.action == action)
+            .map(|binding| binding.chord.to_string())
+            .unwrap_or_else(|| "unbound".to_string())
+    }
+
+    /// The bindings of one mode, in table order
+    pub fn bindings(&self, mode: Mode) -> impl Iterator<Item = &Binding> {
+        self.bindings
+            .iter()
+            .filter(move |binding| binding.mode == mode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, chord)| (name.to_string(), chord.to_string()))
+            .collect()
+    }
+
+    fn press(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn test_parse_chords() -> Result<()> {
+        let chord: Chord = "ctrl-q".parse()?;
+        assert_eq!(chord, Chord::new(KeyCode::Char('q'), KeyModifiers::CONTROL));
+        assert_eq!(chord.to_string(), "ctrl-q");
+
+        assert_eq!("Esc".parse::<Chord>()?.to_string(), "esc");
+        assert_eq!("shift-n".parse::<Chord>()?, "N".parse()?);
+        assert_eq!("ctrl--".parse::<Chord>()?.to_string(), "ctrl--");
+        assert_eq!("f5".parse::<Chord>()?.to_string(), "f5");
+        assert!("hyper-x".parse::<Chord>().is_err());
+        assert!("ctrl-escapee".parse::<Chord>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_actions() {
+        let keymap = Keymap::default();
+        assert_eq!(
+            keymap.action(Mode::Normal, &press(KeyCode::Char('q'), KeyModifiers::NONE)),
+            Some(Action::Quit)
+        );
+        assert_eq!(
+            keymap.action(
+                Mode::Normal,
+                &press(KeyCode::Char('N'), KeyModifiers::SHIFT)
+            ),
+            Some(Action::PreviousMatch)
+        );
+        assert_eq!(
+            keymap.action(
+                Mode::Editing,
+                &press(KeyCode::Char('q'), KeyModifiers::NONE)
+            ),
+            None
+        );
+        assert_eq!(keymap.key(Mode::Picker, Action::AcceptCandidate), "enter");
+
+        // Messages are selected with the arrow keys or j and k
+        for code in [KeyCode::Up, KeyCode::Char('k')] {
+            assert_eq!(
+                keymap.action(Mode::Normal, &press(code, KeyModifiers::NONE)),
+                Some(Action::SelectPrevious)
+            );
+        }
+    }
+
+    #[test]
+    fn test_overrides() -> Result<()> {
+        let keymap =
+            Keymap::with_overrides(&keys(&[("quit", "ctrl-q"), ("history_search", "ctrl-h")]))?;
+        assert_eq!(
+            keymap.action(
+                Mode::Normal,
+                &press(KeyCode::Char('q'), KeyModifiers::CONTROL)
+            ),
+            Some(Action::Quit)
+        );
+        assert_eq!(
+            keymap.action(Mode::Normal, &press(KeyCode::Char('q'), KeyModifiers::NONE)),
+            None
+        );
+        assert_eq!(keymap.key(Mode::Editing, Action::HistorySearch), "ctrl-h");
+        assert_eq!(
+            keymap.key(Mode::HistorySearch, Action::HistorySearch),
+            "ctrl-h"
+        );
+
+        // Overriding an action with two keys leaves it one
+        let keymap = Keymap::with_overrides(&keys(&[("select_next", "ctrl-n")]))?;
+        let chords: Vec<String> = keymap
+            .bindings(Mode::Normal)
+            .filter(|binding| binding.action == Action::SelectNext)
+            .map(|binding| binding.chord.to_string())
+            .collect();
+        assert_eq!(chords, ["ctrl-n"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_overrides() {
+        let error = Keymap::with_overrides(&keys(&[("teleport", "t")])).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Unknown key binding 'teleport'"));
+
+        let error = Keymap::with_overrides(&keys(&[("quit", "ctrl-")])).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid key for 'quit'");
+
+        let error = Keymap::with_overrides(&keys(&[("quit", "e")])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Key 'e' is bound to both 'edit' and 'quit' in normal mode"
+        );
+
+        let error = Keymap::with_overrides(&keys(&[("submit", "s")])).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("would stop it being typed in editing mode"));
+    }
+}
@@ -1,5 +1,12 @@
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize};
 
+use crate::images::ImageAttachment;
+use crate::llm::timings::ServerTimings;
+use crate::session_blobs::BlobRef;
+use crate::stats::ToolTiming;
+
 /// Represents the role of a message sender
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum MessageRole {
@@ -17,7 +24,7 @@ impl From<crate::Role> for MessageRole {
         match role {
             crate::Role::User => MessageRole::User,
             crate::Role::Assistant => MessageRole::Assistant,
-            crate::Role::System => MessageRole::System,
+            crate::Role::System | crate::Role::Tool => MessageRole::System,
         }
     }
 }
@@ -29,10 +36,57 @@ pub struct UiMessage {
     pub content: String,
     #[serde(skip, default)]
     pub input_tokens: usize,
+    /// Tokens of tool output sent to the model while producing this reply
+    #[serde(skip, default)]
+    pub tool_output_tokens: usize,
     #[serde(skip, default)]
     pub output_tokens: usize,
     #[serde(skip, default)]
     pub used_tools: Vec<String>,
+    /// How long each tool call made for this reply took
+    #[serde(skip, default)]
+    pub tool_timings: Vec<ToolTiming>,
+    /// The think blocks of a reasoning model, shown only when toggled on
+    #[serde(skip, default)]
+    pub reasoning: Option<String>,
+    /// The model that produced an assistant message
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub model: String,
+    /// Who served `model`, e.g. "ollama"
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub provider: String,
+    /// Images sent with the message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<ImageAttachment>,
+    /// Marked with /pin to be kept word for word
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    /// Cut off by the max_tokens limit, so most likely incomplete
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    /// Where content read from this machine came from, e.g. attached files
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub local_sources: Vec<String>,
+    /// Shown in the TUI but never sent back to the model, e.g. notes and errors
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ephemeral: bool,
+    /// Something that went wrong, shown in red
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub error: bool,
+    /// Shown with all its details in the TUI: the whole of a long system
+    /// message, the reasoning and each tool call on a line of its own
+    #[serde(skip, default)]
+    pub expanded: bool,
+    /// How long a reply of this session took to generate
+    #[serde(skip, default)]
+    pub latency: Option<Duration>,
+    /// Where the server's time went generating a reply of this session
+    #[serde(skip, default)]
+    pub server_timings: Option<ServerTimings>,
+    /// The blob file holding the content of a message saved in a session
+    /// that grew too large, until it is loaded back
+    #[serde(skip, default)]
+    pub blob: Option<BlobRef>,
 }
 
 impl UiMessage {
@@ -42,8 +96,23 @@ impl UiMessage {
             role,
             content,
             input_tokens: 0,
+            tool_output_tokens: 0,
             output_tokens: 0,
             used_tools: Vec::new(),
+            tool_timings: Vec::new(),
+            reasoning: None,
+            model: String::new(),
+            provider: String::new(),
+            images: Vec::new(),
+            pinned: false,
+            truncated: false,
+            local_sources: Vec::new(),
+            ephemeral: false,
+            error: false,
+            expanded: false,
+            latency: None,
+            server_timings: None,
+            blob: None,
         }
     }
 
@@ -72,10 +141,88 @@ impl UiMessage {
         msg
     }
 
+    /// Record the tool output sent to the model while producing this reply
+    pub fn with_tool_output_tokens(mut self, tokens: usize) -> Self {
+        self.tool_output_tokens = tokens;
+        self
+    }
+    
+    /// Record how long the tool calls made for this reply took
+    pub fn with_tool_timings(mut self, timings: Vec<ToolTiming>) -> Self {
+        self.tool_timings = timings;
+        self
+    }
+    
+    /// Keep the reasoning that led to this message
+    pub fn with_reasoning(mut self, reasoning: Option<String>) -> Self {
+        self.reasoning = reasoning;
+        self
+    }
+
+    /// Send images along with this message
+    pub fn with_images(mut self, images: Vec<ImageAttachment>) -> Self {
+        self.images = images;
+        self
+    }
+    
+    /// Record where the local content of this message came from
+    pub fn with_local_sources(mut self, sources: Vec<String>) -> Self {
+        self.local_sources = sources;
+        self
+    }
+
+    /// Record the model and provider that produced this message
+    pub fn with_source(mut self, model: impl Into<String>, provider: impl Into<String>) -> Self {
+        self.model = model.into();
+        self.provider = provider.into();
+        self
+    }
+    
+    /// Mark a reply cut off by the max_tokens limit
+    pub fn with_truncated(mut self, truncated: bool) -> Self {
+        self.truncated = truncated;
+        self
+    }
+    
+    /// Record how long the reply took to generate
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+    
+    /// Record the load, prompt and generation times the server reported
+    pub fn with_server_timings(mut self, timings: ServerTimings) -> Self {
+        self.server_timings = Some(timings);
+        self
+    }
+    
     /// Create a new system message
     pub fn system(content: String) -> Self {
         Self::new(MessageRole::System, content)
     }
+    
+    /// Create a note for the user, which the model never sees
+    pub fn note(content: String) -> Self {
+        let mut msg = Self::system(content);
+        msg.ephemeral = true;
+        msg
+    }
+    
+    /// Create an error note for the user, which the model never sees
+    pub fn error(content: String) -> Self {
+        let mut msg = Self::note(content);
+        msg.error = true;
+        msg
+    }
+}
+
+/// The messages to send back to the model, leaving out ephemeral ones
+pub fn history(messages: &[UiMessage]) -> Vec<crate::Message> {
+    messages
+        .iter()
+        .filter(|message| !message.ephemeral)
+        .map(crate::Message::from)
+        .collect()
 }
 
 // Implementation for converting from main::Message to tui::UiMessage
@@ -85,8 +232,43 @@ impl From<crate::Message> for UiMessage {
             role: MessageRole::from(message.role),
             content: message.content,
             input_tokens: message.input_tokens,
+            tool_output_tokens: message.tool_output_tokens,
             output_tokens: message.output_tokens,
             used_tools: message.used_tools,
+            tool_timings: message.tool_timings,
+            reasoning: message.reasoning,
+            model: message.model,
+            provider: message.provider,
+            images: message.images,
+            pinned: message.pinned,
+            truncated: message.truncated,
+            local_sources: message.local_sources,
+            ephemeral: false,
+            error: false,
+            expanded: false,
+            latency: message.latency,
+            server_timings: message.server_timings,
+            blob: None,
         }
     }
+}
+
+// Implementation for converting from tui::UiMessage to main::Message
+impl From<&UiMessage> for crate::Message {
+    fn from(message: &UiMessage) -> Self {
+        let role = match message.role {
+            MessageRole::User => crate::Role::User,
+            MessageRole::Assistant => crate::Role::Assistant,
+            MessageRole::System => crate::Role::System,
+        };
+        let mut converted = crate::Message::new(role, message.content.clone()).with_images(message.images.clone());
+        converted.model = message.model.clone();
+        converted.provider = message.provider.clone();
+        converted.pinned = message.pinned;
+        converted.truncated = message.truncated;
+        converted.local_sources = message.local_sources.clone();
+        converted.latency = message.latency;
+        converted.server_timings = message.server_timings;
+        converted
+    }
 }
\ No newline at end of file
@@ -1,5 +1,12 @@
 // Re-export the public API
 mod app;
-mod message;
+mod clipboard;
+mod keymap;
+pub(crate) mod message;
+mod progress;
+mod review;
+mod search;
+mod theme;
 mod ui;
-pub use app::run;
+mod wrap;
+pub use app::{last_conversation, run, TuiOptions};
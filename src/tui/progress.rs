@@ -0,0 +1,96 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use ratatui::{backend::CrosstermBackend, layout::Rect, Terminal, TerminalOptions, Viewport};
+
+use crate::activity::Activity;
+use crate::config::LayoutMode;
+use crate::tools::progress::Progress;
+use crate::tui::theme::Theme;
+use crate::tui::ui::{self, render_activity, render_progress, MIN_WIDTH, STATUS_HEIGHT};
+
+/// How often the live tail is redrawn at most
+const REDRAW_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Shows the latest output of a running tool in place of the status bar.
+///
+/// Like the review overlay, it draws while the event loop waits for the
+/// response, and flags that the screen needs a full redraw afterwards.
+pub struct TuiProgress {
+    shown: Arc<AtomicBool>,
+    activity: Arc<Activity>,
+    theme: Arc<Mutex<Theme>>,
+    // When the tail was last drawn, and how many lines were printed since the UI was
+    // last redrawn
+    state: Mutex<(Option<Instant>, usize)>,
+}
+
+impl TuiProgress {
+    pub fn new(shown: Arc<AtomicBool>, activity: Arc<Activity>, theme: Arc<Mutex<Theme>>) -> Self {
+        Self {
+            shown,
+            activity,
+            theme,
+            state: Mutex::new((None, 0)),
+        }
+    }
+
+    fn draw(&self, tool: &str, line: &str, count: usize) -> Result<()> {
+        let (width, _) = crossterm::terminal::size()?;
+        let area = Rect::new(0, 0, width, STATUS_HEIGHT);
+        let mut terminal = Terminal::with_options(
+            CrosstermBackend::new(io::stdout()),
+            TerminalOptions {
+                viewport: Viewport::Fixed(area),
+            },
+        )?;
+        let theme = self.theme.lock().unwrap().clone();
+        terminal.draw(|f| render_progress(f, area, tool, line, count, &theme))?;
+        self.shown.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Progress for TuiProgress {
+    fn line(&self, tool: &str, line: &str) {
+        self.activity.set_phase(Some(format!("running {}", tool)));
+        let mut state = self.state.lock().unwrap();
+        // Count again from the first line printed after the UI was redrawn
+        if !self.shown.load(Ordering::Relaxed) {
+            *state = (None, 0);
+        }
+        state.1 += 1;
+
+        let due = state.0.is_none_or(|last| last.elapsed() >= REDRAW_INTERVAL);
+        if due {
+            state.0 = Some(Instant::now());
+            // Progress is best effort; the full output still reaches the model
+            let _ = self.draw(tool, line, state.1);
+        }
+    }
+
+    fn finished(&self, _tool: &str) {
+        self.activity.set_phase(None);
+    }
+}
+
+/// Draw `title` over the top border of the input box of `layout`, for the
+/// status of a generation while the UI waits for it
+pub fn draw_activity(title: &str, theme: &Theme, layout: LayoutMode) -> Result<()> {
+    let (width, height) = crossterm::terminal::size()?;
+    if width < MIN_WIDTH || height < ui::min_height(layout) {
+        return Ok(());
+    }
+    let area = Rect::new(0, height - ui::input_height(layout), width, 1);
+    let mut terminal = Terminal::with_options(
+        CrosstermBackend::new(io::stdout()),
+        TerminalOptions {
+            viewport: Viewport::Fixed(area),
+        },
+    )?;
+    terminal.draw(|f| render_activity(f, area, title, theme, layout))?;
+    Ok(())
+}
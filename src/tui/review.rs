@@ -0,0 +1,208 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::{backend::CrosstermBackend, Terminal};
+
+use crate::tools::review::{Decision, DiffLine, ProposedChange, Reviewer};
+use crate::tui::keymap::{Action, Keymap, Mode};
+use crate::tui::theme::Theme;
+use crate::tui::ui::render_review;
+
+/// A line of the review overlay
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReviewLine {
+    /// A hunk header, e.g. "@@ -1,3 +1,4 @@"
+    Hunk(String),
+    Diff(DiffLine),
+}
+
+/// A proposed change shown in the review overlay
+#[derive(Debug)]
+pub struct ReviewView {
+    pub title: String,
+    pub lines: Vec<ReviewLine>,
+    /// Index of the first line shown
+    pub scroll: usize,
+    /// The reason being typed after declining, if any
+    pub reason: Option<String>,
+}
+
+impl ReviewView {
+    pub fn new(change: &ProposedChange) -> Self {
+        let mut lines = Vec::new();
+        for hunk in &change.hunks {
+            lines.push(ReviewLine::Hunk(hunk.header()));
+            lines.extend(hunk.lines.iter().cloned().map(ReviewLine::Diff));
+        }
+
+        Self {
+            title: change.summary(),
+            lines,
+            scroll: 0,
+            reason: None,
+        }
+    }
+
+    /// A command line strict mode asks about, with the reason it asks
+    pub fn command(command: &str, reason: &str) -> Self {
+        let mut lines = vec![ReviewLine::Hunk(reason.to_string())];
+        lines.extend(
+            command
+                .lines()
+                .map(|line| ReviewLine::Diff(DiffLine::Context(line.to_string()))),
+        );
+
+        Self {
+            title: "run command?".to_string(),
+            lines,
+            scroll: 0,
+            reason: None,
+        }
+    }
+
+    /// Which set of key bindings applies
+    pub fn mode(&self) -> Mode {
+        if self.reason.is_some() {
+            Mode::ReviewReason
+        } else {
+            Mode::Review
+        }
+    }
+
+    /// Handle a key press, returning the decision once the user made one
+    pub fn handle_key(&mut self, keymap: &Keymap, key: KeyEvent) -> Option<Decision> {
+        let action = keymap.action(self.mode(), &key);
+        if let Some(reason) = self.reason.as_mut() {
+            match (action, key.code) {
+                (Some(Action::SendReason), _) => {
+                    let reason = reason.trim().to_string();
+                    return Some(Decision::Reject {
+                        reason: (!reason.is_empty()).then_some(reason),
+                    });
+                }
+                (Some(Action::SkipReason), _) => return Some(Decision::Reject { reason: None }),
+                (None, KeyCode::Char(c)) => reason.push(c),
+                (None, KeyCode::Backspace) => {
+                    reason.pop();
+                }
+                _ => {}
+            }
+            return None;
+        }
+
+        match action? {
+            Action::ApproveChange => return Some(Decision::Approve),
+            Action::ApproveAllChanges => return Some(Decision::ApproveAll),
+            Action::RejectChange => self.reason = Some(String::new()),
+            Action::ScrollUp => self.scroll = self.scroll.saturating_sub(1),
+            Action::ScrollDown => {
+                self.scroll = (self.scroll + 1).min(self.lines.len().saturating_sub(1));
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+/// Reviews file changes and confirms commands in an overlay.
+///
+/// The event loop is waiting for the response that proposed the change, so
+/// the reviewer draws and reads keys itself, and flags that the screen needs
+/// a full redraw afterwards.
+pub struct TuiReviewer {
+    keymap: Keymap,
+    // Shared with the app, so /theme applies here too
+    theme: Arc<Mutex<Theme>>,
+    shown: Arc<AtomicBool>,
+}
+
+impl TuiReviewer {
+    pub fn new(keymap: Keymap, theme: Arc<Mutex<Theme>>, shown: Arc<AtomicBool>) -> Self {
+        Self {
+            keymap,
+            theme,
+            shown,
+        }
+    }
+
+    fn run(&self, mut view: ReviewView) -> Result<Decision> {
+        let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+        self.shown.store(true, Ordering::Relaxed);
+        let theme = self.theme.lock().unwrap().clone();
+
+        loop {
+            terminal.draw(|f| render_review(f, &view, &self.keymap, &theme))?;
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    if let Some(decision) = view.handle_key(&self.keymap, key) {
+                        return Ok(decision);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Reviewer for TuiReviewer {
+    fn review(&self, change: &ProposedChange) -> Decision {
+        // Nothing is written unless the user could see what it was
+        self.run(ReviewView::new(change))
+            .unwrap_or(Decision::Reject { reason: None })
+    }
+
+    fn confirm_command(&self, command: &str, reason: &str) -> Decision {
+        self.run(ReviewView::command(command, reason))
+            .unwrap_or(Decision::Reject { reason: None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyModifiers;
+    use std::path::PathBuf;
+
+    fn press(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_review_keys() {
+        let keymap = Keymap::default();
+        let change = ProposedChange::new("file", PathBuf::from("a.txt"), Some("a\n"), "b\n");
+        let mut view = ReviewView::new(&change);
+        assert_eq!(view.lines.len(), 3);
+        assert_eq!(
+            view.lines[0],
+            ReviewLine::Hunk("@@ -1,1 +1,1 @@".to_string())
+        );
+
+        assert_eq!(view.handle_key(&keymap, press(KeyCode::Down)), None);
+        assert_eq!(view.scroll, 1);
+        assert_eq!(
+            view.handle_key(&keymap, press(KeyCode::Char('y'))),
+            Some(Decision::Approve)
+        );
+        assert_eq!(
+            view.handle_key(&keymap, press(KeyCode::Char('a'))),
+            Some(Decision::ApproveAll)
+        );
+
+        // Declining asks for a reason, where keys are typed
+        assert_eq!(view.handle_key(&keymap, press(KeyCode::Char('n'))), None);
+        assert_eq!(view.mode(), Mode::ReviewReason);
+        for c in "no y".chars() {
+            assert_eq!(view.handle_key(&keymap, press(KeyCode::Char(c))), None);
+        }
+        view.handle_key(&keymap, press(KeyCode::Backspace));
+        assert_eq!(
+            view.handle_key(&keymap, press(KeyCode::Enter)),
+            Some(Decision::Reject {
+                reason: Some("no".to_string())
+            })
+        );
+    }
+}
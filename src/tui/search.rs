@@ -0,0 +1,239 @@
+use regex::{Regex, RegexBuilder};
+
+/// Incremental search over the conversation
+#[derive(Debug, Default)]
+pub struct Search {
+    query: String,
+    regex: bool,
+    pattern: Option<Regex>,
+    matches: Vec<usize>,
+    current: usize,
+}
+
+impl Search {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    /// Whether the query is interpreted as a regular expression
+    pub fn is_regex(&self) -> bool {
+        self.regex
+    }
+
+    /// Whether the query is a regex that does not compile
+    pub fn is_invalid(&self) -> bool {
+        self.regex && !self.query.is_empty() && self.pattern.is_none()
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.query.is_empty()
+    }
+
+    /// Index of the message the search is currently on
+    pub fn current_message(&self) -> Option<usize> {
+        self.matches.get(self.current).copied()
+    }
+
+    /// 1-based position of the current match, for display
+    pub fn position(&self) -> Option<(usize, usize)> {
+        if self.matches.is_empty() {
+            None
+        } else {
+            Some((self.current + 1, self.matches.len()))
+        }
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.query.push(c);
+        self.compile();
+    }
+
+    pub fn pop(&mut self) {
+        self.query.pop();
+        self.compile();
+    }
+
+    pub fn toggle_regex(&mut self) {
+        self.regex = !self.regex;
+        self.compile();
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Recompute which messages match, keeping the current match in range
+    pub fn update<'a>(&mut self, contents: impl Iterator<Item = &'a str>) {
+        self.matches = if self.is_active() {
+            contents
+                .enumerate()
+                .filter(|(_, content)| !self.ranges(content).is_empty())
+                .map(|(index, _)| index)
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        if self.current >= self.matches.len() {
+            self.current = self.matches.len().saturating_sub(1);
+        }
+    }
+
+    /// Move to the next match, wrapping around
+    pub fn next(&mut self) {
+        if !self.matches.is_empty() {
+            self.current = (self.current + 1) % self.matches.len();
+        }
+    }
+
+    /// Move to the previous match, wrapping around
+    pub fn previous(&mut self) {
+        if !self.matches.is_empty() {
+            self.current = (self.current + self.matches.len() - 1) % self.matches.len();
+        }
+    }
+
+    /// Byte ranges of every match of the query within `content`
+    pub fn ranges(&self, content: &str) -> Vec<(usize, usize)> {
+        if !self.is_active() {
+            return Vec::new();
+        }
+
+        if self.regex {
+            return match &self.pattern {
+                Some(pattern) => pattern
+                    .find_iter(content)
+                    .filter(|m| !m.is_empty())
+                    .map(|m| (m.start(), m.end()))
+                    .collect(),
+                None => Vec::new(),
+            };
+        }
+
+        find_case_insensitive(content, &self.query)
+    }
+
+    fn compile(&mut self) {
+        self.pattern = if self.regex {
+            RegexBuilder::new(&self.query)
+                .case_insensitive(true)
+                .build()
+                .ok()
+        } else {
+            None
+        };
+    }
+}
+
+// Case-insensitive substring search returning byte ranges into `content`
+fn find_case_insensitive(content: &str, query: &str) -> Vec<(usize, usize)> {
+    let query: Vec<char> = query.chars().flat_map(char::to_lowercase).collect();
+    if query.is_empty() {
+        return Vec::new();
+    }
+
+    let chars: Vec<(usize, char)> = content.char_indices().collect();
+    let mut ranges = Vec::new();
+    let mut start = 0;
+
+    while start < chars.len() {
+        let mut matched = 0;
+        let mut end = start;
+        while end < chars.len() && matched < query.len() {
+            let lowered: Vec<char> = chars[end].1.to_lowercase().collect();
+            if query[matched..].starts_with(&lowered) {
+                matched += lowered.len();
+                end += 1;
+            } else {
+                break;
+            }
+        }
+
+        if matched == query.len() {
+            let end_byte = chars.get(end).map(|(i, _)| *i).unwrap_or(content.len());
+            ranges.push((chars[start].0, end_byte));
+            start = end;
+        } else {
+            start += 1;
+        }
+    }
+
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search_for(query: &str) -> Search {
+        let mut search = Search::new();
+        for c in query.chars() {
+            search.push(c);
+        }
+        search
+    }
+
+    #[test]
+    fn test_case_insensitive_ranges() {
+        let search = search_for("regex");
+        let content = "Here is the Regex: ^a+$ and another REGEX";
+        let ranges = search.ranges(content);
+
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(&content[ranges[0].0..ranges[0].1], "Regex");
+        assert_eq!(&content[ranges[1].0..ranges[1].1], "REGEX");
+    }
+
+    #[test]
+    fn test_regex_mode() {
+        let mut search = search_for(r"\d+");
+        search.toggle_regex();
+
+        let content = "port 8080 and 443";
+        let found: Vec<&str> = search
+            .ranges(content)
+            .iter()
+            .map(|(start, end)| &content[*start..*end])
+            .collect();
+        assert_eq!(found, vec!["8080", "443"]);
+
+        let mut invalid = search_for("(");
+        invalid.toggle_regex();
+        assert!(invalid.is_invalid());
+        assert!(invalid.ranges("(").is_empty());
+    }
+
+    #[test]
+    fn test_navigation_wraps() {
+        let mut search = search_for("rust");
+        let messages = ["rust", "python", "Rust again", "more rust"];
+        search.update(messages.iter().copied());
+
+        assert_eq!(search.matches, vec![0, 2, 3]);
+        assert_eq!(search.current_message(), Some(0));
+
+        search.next();
+        search.next();
+        assert_eq!(search.current_message(), Some(3));
+        search.next();
+        assert_eq!(search.current_message(), Some(0));
+        search.previous();
+        assert_eq!(search.current_message(), Some(3));
+        assert_eq!(search.position(), Some((3, 3)));
+    }
+
+    #[test]
+    fn test_ranges_respect_char_boundaries() {
+        let search = search_for("é");
+        let content = "café CAFÉ";
+        let ranges = search.ranges(content);
+        assert_eq!(ranges.len(), 2);
+        for (start, end) in ranges {
+            assert_eq!(content[start..end].to_lowercase(), "é");
+        }
+    }
+}
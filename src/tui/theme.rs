@@ -0,0 +1,274 @@
+//! The colors of the TUI.
+//!
+//! The render functions take a [`Theme`] rather than naming colors, so the
+//! built-in dark and light themes, or colors from the config, apply to every
+//! part of the screen. Hex colors are shown as they are on terminals with
+//! truecolor, and as the nearest of the 16 ANSI colors on the others.
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+use ratatui::style::Color;
+
+use crate::config::ThemeConfig;
+
+/// The built-in themes
+pub const THEMES: [&str; 2] = ["dark", "light"];
+
+/// The colors a config table can set
+pub const COLORS: [&str; 10] = [
+    "user",
+    "assistant",
+    "system",
+    "text",
+    "dimmed",
+    "accent",
+    "highlight",
+    "success",
+    "error",
+    "border",
+];
+
+// The 16 ANSI colors and how xterm shows them, for terminals without truecolor
+const ANSI: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::Gray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+/// The colors the TUI is drawn in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    /// "dark", "light" or "custom"
+    pub name: String,
+    /// The messages of each role
+    pub user: Color,
+    pub assistant: Color,
+    pub system: Color,
+    /// Labels, e.g. in the status bar
+    pub text: Color,
+    /// Hints and whatever matters less, e.g. tools that were not used
+    pub dimmed: Color,
+    /// Names and headings, e.g. the branch and diff hunks
+    pub accent: Color,
+    /// Numbers and warnings, and the background of search matches
+    pub highlight: Color,
+    /// Tools that were used and added lines
+    pub success: Color,
+    pub error: Color,
+    pub border: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl Theme {
+    /// For terminals with a dark background
+    pub fn dark() -> Self {
+        Self {
+            name: "dark".to_string(),
+            user: Color::Cyan,
+            assistant: Color::Green,
+            system: Color::Yellow,
+            text: Color::Gray,
+            dimmed: Color::DarkGray,
+            accent: Color::Cyan,
+            highlight: Color::Yellow,
+            success: Color::Green,
+            error: Color::Red,
+            border: Color::White,
+        }
+    }
+
+    /// For terminals with a light background, where yellow and light gray
+    /// are hard to read
+    pub fn light() -> Self {
+        Self {
+            name: "light".to_string(),
+            user: Color::Blue,
+            assistant: Color::Green,
+            system: Color::Magenta,
+            text: Color::Black,
+            dimmed: Color::DarkGray,
+            accent: Color::Blue,
+            highlight: Color::Magenta,
+            success: Color::Green,
+            error: Color::Red,
+            border: Color::Black,
+        }
+    }
+
+    /// The built-in theme called `name`
+    pub fn named(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "dark" => Some(Self::dark()),
+            "light" => Some(Self::light()),
+            _ => None,
+        }
+    }
+
+    /// The theme a config asks for, with hex colors swapped for ANSI ones
+    /// unless the terminal has `truecolor`
+    pub fn from_config(config: &ThemeConfig, truecolor: bool) -> Result<Self> {
+        let theme = match config {
+            ThemeConfig::Named(name) => Self::named(name).ok_or_else(|| unknown(name))?,
+            ThemeConfig::Custom(colors) => {
+                let base = colors.get("base").map_or("dark", String::as_str);
+                let mut theme = Self::named(base).ok_or_else(|| unknown(base))?;
+                theme.name = "custom".to_string();
+                for (key, value) in colors.iter().filter(|(key, _)| *key != "base") {
+                    let Some(slot) = theme.color_mut(key) else {
+                        bail!(
+                            "Unknown theme color '{}'; the colors are {}",
+                            key,
+                            COLORS.join(", ")
+                        );
+                    };
+                    *slot = parse_color(value)?;
+                }
+                theme
+            }
+        };
+        Ok(if truecolor {
+            theme
+        } else {
+            theme.without_truecolor()
+        })
+    }
+
+    /// This theme with every hex color replaced by the nearest ANSI color
+    pub fn without_truecolor(mut self) -> Self {
+        for key in COLORS {
+            if let Some(color) = self.color_mut(key) {
+                *color = nearest_ansi(*color);
+            }
+        }
+        self
+    }
+
+    fn color_mut(&mut self, key: &str) -> Option<&mut Color> {
+        Some(match key {
+            "user" => &mut self.user,
+            "assistant" => &mut self.assistant,
+            "system" => &mut self.system,
+            "text" => &mut self.text,
+            "dimmed" => &mut self.dimmed,
+            "accent" => &mut self.accent,
+            "highlight" => &mut self.highlight,
+            "success" => &mut self.success,
+            "error" => &mut self.error,
+            "border" => &mut self.border,
+            _ => return None,
+        })
+    }
+}
+
+fn unknown(name: &str) -> anyhow::Error {
+    anyhow!("Unknown theme '{}'; use {}", name, THEMES.join(" or "))
+}
+
+// A color name such as "blue", a hex value such as "#005f87", or a number
+// of the 256-color palette
+fn parse_color(value: &str) -> Result<Color> {
+    Color::from_str(value.trim()).map_err(|_| {
+        anyhow!(
+            "Invalid color '{}': use a name such as \"blue\", a hex value such as \"#005f87\" or a number up to 255",
+            value
+        )
+    })
+}
+
+/// Whether the terminal says it shows 24-bit colors
+pub fn truecolor() -> bool {
+    std::env::var("COLORTERM")
+        .is_ok_and(|value| matches!(value.to_lowercase().as_str(), "truecolor" | "24bit"))
+}
+
+/// The ANSI color closest to `color` if it is a hex one, otherwise `color`
+pub fn nearest_ansi(color: Color) -> Color {
+    let Color::Rgb(r, g, b) = color else {
+        return color;
+    };
+    let distance = |(ar, ag, ab): (u8, u8, u8)| {
+        [(r, ar), (g, ag), (b, ab)]
+            .iter()
+            .map(|&(x, y)| (i32::from(x) - i32::from(y)).pow(2))
+            .sum::<i32>()
+    };
+    ANSI.iter()
+        .min_by_key(|(_, rgb)| distance(*rgb))
+        .map_or(color, |(ansi, _)| *ansi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn custom(colors: &[(&str, &str)]) -> ThemeConfig {
+        ThemeConfig::Custom(
+            colors
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<BTreeMap<_, _>>(),
+        )
+    }
+
+    #[test]
+    fn test_built_in_themes() -> Result<()> {
+        assert_eq!(
+            Theme::from_config(&ThemeConfig::default(), true)?,
+            Theme::dark()
+        );
+        let light = Theme::from_config(&ThemeConfig::Named("Light".to_string()), false)?;
+        assert_eq!(light, Theme::light());
+        assert_ne!(light.text, Theme::dark().text);
+
+        let error = Theme::from_config(&ThemeConfig::Named("solarized".to_string()), true);
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "Unknown theme 'solarized'; use dark or light"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_colors_fall_back_without_truecolor() -> Result<()> {
+        let config = custom(&[
+            ("base", "light"),
+            ("user", "#0000af"),
+            ("error", "lightred"),
+        ]);
+        let theme = Theme::from_config(&config, true)?;
+        assert_eq!(theme.name, "custom");
+        assert_eq!(theme.user, Color::Rgb(0, 0, 175));
+        assert_eq!(theme.error, Color::LightRed);
+        assert_eq!(theme.text, Color::Black);
+
+        let theme = Theme::from_config(&config, false)?;
+        assert_eq!(theme.user, Color::Blue);
+        assert_eq!(nearest_ansi(Color::Rgb(250, 250, 245)), Color::White);
+        assert_eq!(nearest_ansi(Color::Indexed(24)), Color::Indexed(24));
+
+        let error = Theme::from_config(&custom(&[("usr", "blue")]), true).unwrap_err();
+        assert!(error.to_string().starts_with("Unknown theme color 'usr'"));
+        assert!(Theme::from_config(&custom(&[("user", "#12345")]), true).is_err());
+        Ok(())
+    }
+}
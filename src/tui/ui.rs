@@ -1,67 +1,269 @@
 use ratatui::{
-    backend::Backend,
-    layout::{Constraint, Direction, Layout, Rect},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span, Text},
-    widgets::{Block, Borders, List, ListItem, Paragraph, Wrap},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap},
     Frame,
 };
 
-use crate::tui::{app::SentinelApp, message::MessageRole};
+use crate::config::LayoutMode;
+use crate::llm::limits;
+use crate::llm::timings;
+use crate::stats::{self, TokenBreakdown};
+use crate::tools::changes::LineKind;
+use crate::tools::review::DiffLine;
+use crate::tui::{
+    app::{CandidatePicker, ComparisonView, DiffView, LinkPicker, SentinelApp},
+    keymap::{self, Action, Keymap, Mode},
+    message::{MessageRole, UiMessage},
+    review::{ReviewLine, ReviewView},
+    search::Search,
+    theme::Theme,
+    wrap,
+};
+use crate::words::WordCount;
+
+/// Rows taken by the status bar: the status and the tools line
+pub const STATUS_HEIGHT: u16 = 4;
+
+/// Rows taken by the input box
+pub const INPUT_HEIGHT: u16 = 3;
+
+/// Rows taken by the input line of the zen layout: a rule with the status in
+/// its title, and the input under it
+pub const ZEN_INPUT_HEIGHT: u16 = 2;
+
+/// Below this many columns the stats panel folds into a line of the status bar
+pub const NARROW_WIDTH: u16 = 80;
+
+/// The smallest terminal the layout fits in; smaller ones get a placeholder
+pub const MIN_WIDTH: u16 = 30;
+pub const MIN_HEIGHT: u16 = 13;
+pub const ZEN_MIN_HEIGHT: u16 = 5;
+
+/// System messages longer than this, or of several lines, show as one line until expanded
+pub const COLLAPSED_CHARS: usize = 80;
+
+/// Rows taken by the input box of `layout`
+pub fn input_height(layout: LayoutMode) -> u16 {
+    match layout {
+        LayoutMode::Full => INPUT_HEIGHT,
+        LayoutMode::Zen => ZEN_INPUT_HEIGHT,
+    }
+}
+
+/// The fewest rows `layout` fits in
+pub fn min_height(layout: LayoutMode) -> u16 {
+    match layout {
+        LayoutMode::Full => MIN_HEIGHT,
+        LayoutMode::Zen => ZEN_MIN_HEIGHT,
+    }
+}
 
 /// Render the main UI
-pub fn render_ui<B: Backend>(f: &mut Frame, app: &SentinelApp) {
+pub fn render_ui(f: &mut Frame, app: &SentinelApp) {
+    let theme = app.theme();
+    let size = f.size();
+    let min_height = min_height(app.layout());
+    if size.width < MIN_WIDTH || size.height < min_height {
+        render_too_small(f, size, min_height, &theme);
+        return;
+    }
+
+    match app.layout() {
+        LayoutMode::Full => render_full(f, app, &theme, size),
+        LayoutMode::Zen => render_zen(f, app, &theme, size),
+    }
+
+    if let Some(picker) = app.candidate_picker() {
+        render_candidate_picker(f, picker, app.keymap(), &theme, f.size());
+    }
+
+    if let Some(view) = app.comparison() {
+        render_comparison(f, view, app.keymap(), &theme, f.size());
+    }
+
+    if let Some(picker) = app.link_picker() {
+        render_link_picker(f, picker, app.keymap(), &theme, f.size());
+    }
+
+    if app.help_visible() {
+        render_help(f, app.keymap(), &theme, f.size());
+    }
+
+    if let Some(view) = app.diff_view() {
+        render_diff(f, view, &theme, f.size());
+    }
+}
+
+/// Lay out the status bar, the conversation with the stats beside it, and
+/// the input box
+fn render_full(f: &mut Frame, app: &SentinelApp, theme: &Theme, size: Rect) {
+    // Narrow terminals show the stats as a line under the status instead of a panel
+    let narrow = size.width < NARROW_WIDTH;
+    let stats_line = narrow && app.stats_visible();
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
-            Constraint::Length(3), // Status bar
-            Constraint::Min(5),    // Messages
-            Constraint::Length(3), // Input box
+            Constraint::Length(STATUS_HEIGHT + u16::from(stats_line)), // Status bar
+            Constraint::Min(5),                                        // Messages
+            Constraint::Length(INPUT_HEIGHT),                          // Input box
         ])
-        .split(f.size());
+        .split(size);
 
-    render_status_bar::<B>(f, app, chunks[0]);
-    render_messages::<B>(f, app, chunks[1]);
-    render_input_box::<B>(f, app, chunks[2]);
+    let stats_panel = !narrow && app.stats_visible();
+    render_status_bar(f, app, theme, chunks[0], stats_line);
+    render_messages(f, app, theme, chunks[1], stats_panel, true);
+    render_input_box(f, app, theme, chunks[2], Borders::ALL);
 }
 
-/// Render the status bar
-fn render_status_bar<B: Backend>(f: &mut Frame, app: &SentinelApp, area: Rect) {
+/// Lay out the conversation alone over a line of input, with the status in
+/// the input's title and the stats over the conversation when asked
+fn render_zen(f: &mut Frame, app: &SentinelApp, theme: &Theme, size: Rect) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(1),                   // Messages
+            Constraint::Length(ZEN_INPUT_HEIGHT), // Input line
+        ])
+        .split(size);
+
+    render_messages(f, app, theme, chunks[0], false, false);
+    render_input_box(f, app, theme, chunks[1], Borders::TOP);
+
+    if app.zen_stats_visible() {
+        let area = centered_rect(60, 70, chunks[0]);
+        f.render_widget(Clear, area);
+        render_stats_panel(f, app, theme, area);
+    }
+}
+
+/// Say the terminal is too small instead of squeezing the layout into it
+fn render_too_small(f: &mut Frame, area: Rect, min_height: u16, theme: &Theme) {
+    let text = format!(
+        "Terminal too small: {}x{}, needs {}x{}",
+        area.width, area.height, MIN_WIDTH, min_height
+    );
+    let placeholder = Paragraph::new(text)
+        .style(Style::default().fg(theme.highlight))
+        .alignment(Alignment::Center)
+        .wrap(Wrap { trim: true });
+    f.render_widget(placeholder, area);
+}
+
+/// Render the status bar, with the stats on a line of their own if asked
+fn render_status_bar(
+    f: &mut Frame,
+    app: &SentinelApp,
+    theme: &Theme,
+    area: Rect,
+    stats_line: bool,
+) {
     // Create status text with model info
-    let status_text = Line::from(vec![
-        Span::styled("Model: ", Style::default().fg(Color::Gray)),
-        Span::styled(app.model_name(), Style::default().fg(Color::Green)),
-        Span::styled(" | Tools: ", Style::default().fg(Color::Gray)),
-        Span::styled("Enabled", Style::default().fg(Color::Green)),
-    ]);
+    let mut status_spans = vec![
+        Span::styled("Model: ", Style::default().fg(theme.text)),
+        Span::styled(app.model_name(), Style::default().fg(theme.assistant)),
+        Span::styled(" | Tools: ", Style::default().fg(theme.text)),
+        Span::styled("Enabled", Style::default().fg(theme.success)),
+        Span::styled(" | Branch: ", Style::default().fg(theme.text)),
+        Span::styled(app.branch_name(), Style::default().fg(theme.accent)),
+    ];
+
+    // Show which server answers, which changes if it fails over to another
+    if let Some(endpoint) = app.endpoint() {
+        status_spans.push(Span::styled(" | Host: ", Style::default().fg(theme.text)));
+        status_spans.push(Span::styled(endpoint, Style::default().fg(theme.accent)));
+        // Local content only goes there once the user agrees
+        if app.remote() {
+            status_spans.push(Span::styled(
+                " [remote]",
+                Style::default().fg(theme.highlight),
+            ));
+        }
+    }
+
+    // Show how much of the context the project primer takes
+    if let Some(primer) = app.primer() {
+        status_spans.push(Span::styled(" | Primer: ", Style::default().fg(theme.text)));
+        status_spans.push(Span::styled(
+            format!("~{} tokens", primer.tokens),
+            Style::default().fg(theme.highlight),
+        ));
+    }
+
+    // Show files staged with /attach
+    let staged = app.staged_attachments().len();
+    if staged > 0 {
+        status_spans.push(Span::styled(
+            " | Attached: ",
+            Style::default().fg(theme.text),
+        ));
+        status_spans.push(Span::styled(
+            format!("{} file{}", staged, if staged == 1 { "" } else { "s" }),
+            Style::default().fg(theme.highlight),
+        ));
+    }
+
+    // Show images staged with /image
+    let images = app.staged_images().len();
+    if images > 0 {
+        status_spans.push(Span::styled(" | Images: ", Style::default().fg(theme.text)));
+        status_spans.push(Span::styled(
+            images.to_string(),
+            Style::default().fg(theme.highlight),
+        ));
+    }
+
+    // Show messages waiting for the reply being generated
+    let queued = app.queued().len();
+    if queued > 0 {
+        status_spans.push(Span::styled(
+            format!(" | {} queued", queued),
+            Style::default().fg(theme.highlight),
+        ));
+    }
+
+    if app.tools_unsupported() {
+        status_spans.push(Span::styled(
+            " | no tools",
+            Style::default().fg(theme.error),
+        ));
+    }
+
+    if app.is_warming_up() {
+        status_spans.push(Span::styled(
+            " | warming up model…",
+            Style::default().fg(theme.highlight),
+        ));
+    }
+
+    // A read-only session comes first, so a narrow terminal never cuts it off
+    if app.read_only() {
+        status_spans.insert(
+            0,
+            Span::styled("🔒 read-only | ", Style::default().fg(theme.highlight)),
+        );
+    }
+
+    let status_text = Line::from(status_spans);
 
     // Create tools display line
     let tools_line = {
         let mut tool_spans = Vec::new();
 
-        let all_tools = [
-            "get_weather",
-            "Calculator",
-            "DDGSearcher",
-            "Scraper",
-            "StockScraper",
-            "Bash",
-            "LS",
-        ];
-
-        // Show tools and highlight used ones
+        // Show the tools the model is offered and highlight the ones the
+        // last reply used
+        let all_tools = app.available_tools();
         let current_tools = app.get_current_tools();
 
         // Create spans for each tool
         for (i, tool_name) in all_tools.iter().enumerate() {
-            let is_used = current_tools.contains(&tool_name.to_string());
+            let is_used = current_tools
+                .iter()
+                .any(|used| used.eq_ignore_ascii_case(tool_name));
 
             // Choose color based on if the tool was used
-            let color = if is_used {
-                Color::Green
-            } else {
-                Color::DarkGray
-            };
+            let color = if is_used { theme.success } else { theme.dimmed };
 
             // Add tool name with appropriate color
             if i > 0 {
@@ -76,38 +278,175 @@ fn render_status_bar<B: Backend>(f: &mut Frame, app: &SentinelApp, area: Rect) {
         Line::from(tool_spans)
     };
 
-    // Create the status box
-    let status_content = Text::from(vec![status_text, tools_line]);
+    // Create the status box, cutting lines too long for it short
+    let width = usize::from(area.width.saturating_sub(2));
+    let mut lines = vec![truncate_line(status_text, width, theme)];
+    if stats_line {
+        lines.push(truncate_line(compact_stats(app, theme), width, theme));
+    }
+    lines.push(truncate_line(tools_line, width, theme));
+    let status_content = Text::from(lines);
 
-    let status_bar = Paragraph::new(status_content)
-        .block(Block::default().borders(Borders::ALL).title("Sentinel"));
+    // Flash the title when a slow response has just finished
+    let title = if app.is_flashing() {
+        Span::styled(
+            " Sentinel - response ready ",
+            Style::default()
+                .fg(Color::Black)
+                .bg(theme.highlight)
+                .add_modifier(Modifier::BOLD),
+        )
+    } else if let Some(title) = app.title() {
+        Span::raw(format!("Sentinel - {}", title))
+    } else {
+        Span::raw("Sentinel")
+    };
+
+    let status_bar =
+        Paragraph::new(status_content).block(Block::default().borders(Borders::ALL).title(title));
 
     f.render_widget(status_bar, area);
 }
 
-/// Render the messages area
-fn render_messages<B: Backend>(f: &mut Frame, app: &SentinelApp, area: Rect) {
+/// Cut a line to `width` columns, ending it in an ellipsis if anything was cut
+fn truncate_line<'a>(line: Line<'a>, width: usize, theme: &Theme) -> Line<'a> {
+    let length: usize = line
+        .spans
+        .iter()
+        .map(|span| wrap::width(&span.content))
+        .sum();
+    if length <= width {
+        return line;
+    }
+
+    let mut room = width.saturating_sub(1);
+    let mut spans = Vec::new();
+    for span in line.spans {
+        if room == 0 {
+            break;
+        }
+        let mut content = String::new();
+        for c in span.content.chars() {
+            let columns = wrap::width(c.encode_utf8(&mut [0; 4]));
+            if columns > room {
+                room = 0;
+                break;
+            }
+            room -= columns;
+            content.push(c);
+        }
+        spans.push(Span::styled(content, span.style));
+    }
+    if width > 0 {
+        spans.push(Span::styled("…", Style::default().fg(theme.dimmed)));
+    }
+    Line::from(spans)
+}
+
+/// The last reply's tokens and the session's totals, for the status bar of
+/// narrow terminals
+fn compact_stats(app: &SentinelApp, theme: &Theme) -> Line<'static> {
+    let latest = latest_reply(app);
+    let session = app.stats();
+    let label = |text: &str| Span::styled(text.to_string(), Style::default().fg(theme.text));
+    let value = |text: String| Span::styled(text, Style::default().fg(theme.highlight));
+    Line::from(vec![
+        label("In: "),
+        value(latest.map_or(0, |msg| msg.input_tokens).to_string()),
+        label(" | Tool output: "),
+        value(latest.map_or(0, |msg| msg.tool_output_tokens).to_string()),
+        label(" | Out: "),
+        value(latest.map_or(0, |msg| msg.output_tokens).to_string()),
+        label(" | Requests: "),
+        value(session.requests.to_string()),
+        label(" | "),
+        value(format!("{:.1}s", session.generation_time.as_secs_f64())),
+    ])
+}
+
+/// The latest reply, whose tokens the stats show
+fn latest_reply(app: &SentinelApp) -> Option<&UiMessage> {
+    app.messages()
+        .iter()
+        .rev()
+        .find(|msg| msg.role == MessageRole::Assistant)
+}
+
+/// The first line of a long system message and how many lines it leaves
+/// out, or None if it is short enough to show in full or is an error
+fn collapse(msg: &UiMessage) -> Option<(String, String)> {
+    if msg.role != MessageRole::System || msg.error {
+        return None;
+    }
+    let first = msg.content.lines().next().unwrap_or_default();
+    let more_lines = msg.content.lines().count().saturating_sub(1);
+    if more_lines == 0 && first.chars().count() <= COLLAPSED_CHARS {
+        return None;
+    }
+
+    let line: String = first.chars().take(COLLAPSED_CHARS).collect();
+    let hidden = match more_lines {
+        0 => String::new(),
+        1 => "1 more line, ".to_string(),
+        lines => format!("{} more lines, ", lines),
+    };
+    Some((format!("{}…", line), hidden))
+}
+
+/// Render the messages area, with the stats panel beside it if asked, and
+/// in a box unless the layout is zen
+fn render_messages(
+    f: &mut Frame,
+    app: &SentinelApp,
+    theme: &Theme,
+    area: Rect,
+    stats_panel: bool,
+    bordered: bool,
+) {
     // Split the messages area for the chat and stats
-    let chunks = Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints([
+    let constraints = if stats_panel {
+        vec![
             Constraint::Percentage(75), // Chat messages
             Constraint::Percentage(25), // Stats panel
-        ])
+        ]
+    } else {
+        vec![Constraint::Percentage(100)]
+    };
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(constraints)
         .split(area);
 
+    let search = app.search();
+    let current_match = search.current_message();
+    let last = app.messages().len().saturating_sub(1);
+    // Lines are wrapped here, since a list would cut them off at the border
+    let border = if bordered { 2 } else { 0 };
+    let width = usize::from(chunks[0].width.saturating_sub(border));
+    let wrapped = |lines: Vec<Line>| -> Text<'static> {
+        Text::from(
+            lines
+                .into_iter()
+                .flat_map(|line| wrap::wrap(line, width))
+                .collect::<Vec<_>>(),
+        )
+    };
+
     // Create the message list items
-    let messages: Vec<ListItem> = app
+    let mut messages: Vec<ListItem> = app
         .messages()
         .iter()
-        .map(|msg| {
+        .enumerate()
+        .map(|(index, msg)| {
             let color = match msg.role {
-                MessageRole::User => Color::Cyan,
-                MessageRole::Assistant => Color::Green,
-                MessageRole::System => Color::Yellow,
+                _ if msg.error => theme.error,
+                MessageRole::User => theme.user,
+                MessageRole::Assistant => theme.assistant,
+                MessageRole::System => theme.system,
             };
 
             let role_name = match msg.role {
+                _ if msg.error => "✖ System",
                 MessageRole::User => "You",
                 MessageRole::Assistant => "Assistant",
                 MessageRole::System => "System",
@@ -119,54 +458,279 @@ fn render_messages<B: Backend>(f: &mut Frame, app: &SentinelApp, area: Rect) {
                 Style::default().fg(color).add_modifier(Modifier::BOLD),
             );
 
-            // Create content
-            let content_span = Span::raw(&msg.content);
+            // Create content, highlighting search matches
+            let mut spans = vec![role_span];
+            if msg.pinned {
+                spans.push(Span::styled(
+                    "[pinned] ",
+                    Style::default().fg(theme.highlight),
+                ));
+            }
+            if let Some(source) = crate::source_label(&msg.model, &msg.provider) {
+                spans.push(Span::styled(
+                    format!("[{}] ", source),
+                    Style::default().fg(theme.dimmed),
+                ));
+            }
+            if msg.role == MessageRole::System && !msg.ephemeral {
+                spans.push(Span::styled(
+                    "[system prompt] ",
+                    Style::default().fg(theme.dimmed),
+                ));
+            }
+
+            // Long system messages show one line until expanded; the latest
+            // note and the current search match are shown in full
+            let shown_in_full = app.system_expanded()
+                || msg.expanded
+                || current_match == Some(index)
+                || (index == last && msg.ephemeral);
+            let collapsed = (!shown_in_full).then(|| collapse(msg)).flatten();
+            match collapsed {
+                Some((line, hidden)) => {
+                    spans.push(Span::raw(line));
+                    let key = app.keymap().key(Mode::Normal, Action::ToggleSystem);
+                    spans.push(Span::styled(
+                        format!(" ({}{} to expand)", hidden, key),
+                        Style::default()
+                            .fg(theme.dimmed)
+                            .add_modifier(Modifier::ITALIC),
+                    ));
+                }
+                None if msg.error => spans.push(Span::styled(
+                    msg.content.as_str(),
+                    Style::default().fg(theme.error),
+                )),
+                None => spans.extend(highlight_matches(
+                    &msg.content,
+                    search,
+                    current_match == Some(index),
+                    theme,
+                )),
+            }
 
             // Create text with role and content
             let mut lines = Vec::new();
-            lines.push(Line::from(vec![role_span, content_span]));
+            lines.push(Line::from(spans));
+
+            // Note images by path rather than showing their data
+            for image in &msg.images {
+                lines.push(Line::from(vec![
+                    Span::raw("  "),
+                    Span::styled(image.to_string(), Style::default().fg(theme.accent)),
+                ]));
+            }
+
+            // Show the reasoning of reasoning models only when toggled on
+            if let Some(reasoning) = &msg.reasoning {
+                let dim = Style::default()
+                    .fg(theme.dimmed)
+                    .add_modifier(Modifier::ITALIC);
+                if app.reasoning_visible() || msg.expanded {
+                    for line in reasoning.lines() {
+                        lines.push(Line::from(vec![
+                            Span::raw("  "),
+                            Span::styled(line.to_string(), dim),
+                        ]));
+                    }
+                } else {
+                    let key = app.keymap().key(Mode::Normal, Action::ToggleReasoning);
+                    lines.push(Line::from(vec![
+                        Span::raw("  "),
+                        Span::styled(format!("Reasoning hidden ({} to show)", key), dim),
+                    ]));
+                }
+            }
+
+            // A reply cut off by the token limit is most likely incomplete
+            if msg.truncated {
+                lines.push(Line::from(vec![
+                    Span::raw("  "),
+                    Span::styled(limits::TRUNCATED_NOTE, Style::default().fg(theme.highlight)),
+                ]));
+            }
 
-            // Add tool usage info for assistant messages if tools were used
+            // Add tool usage info for assistant messages if tools were used,
+            // with how long each call took and the slow ones highlighted
             if msg.role == MessageRole::Assistant && !msg.used_tools.is_empty() {
-                let tools_used = format!("Tools: {}", msg.used_tools.join(", "));
-                let tools_span = Span::styled(
-                    tools_used,
-                    Style::default()
-                        .fg(Color::DarkGray)
-                        .add_modifier(Modifier::ITALIC),
-                );
-                lines.push(Line::from(vec![Span::raw("  "), tools_span]));
+                let dimmed = Style::default()
+                    .fg(theme.dimmed)
+                    .add_modifier(Modifier::ITALIC);
+                let calls: Vec<Span> = msg
+                    .tool_timings
+                    .iter()
+                    .map(|timing| {
+                        if timing.is_slow(app.slow_call()) {
+                            Span::styled(
+                                format!("{} (slow)", timing),
+                                Style::default().fg(theme.error),
+                            )
+                        } else {
+                            Span::styled(timing.to_string(), dimmed)
+                        }
+                    })
+                    .collect();
+                if msg.expanded && !calls.is_empty() {
+                    // Expanded, each call gets a line of its own
+                    lines.push(Line::from(vec![
+                        Span::raw("  "),
+                        Span::styled("Tools:", dimmed),
+                    ]));
+                    lines.extend(
+                        calls
+                            .into_iter()
+                            .map(|call| Line::from(vec![Span::raw("    "), call])),
+                    );
+                } else {
+                    let mut spans = vec![Span::raw("  "), Span::styled("Tools: ", dimmed)];
+                    if calls.is_empty() {
+                        spans.push(Span::styled(msg.used_tools.join(", "), dimmed));
+                    }
+                    for (i, call) in calls.into_iter().enumerate() {
+                        if i > 0 {
+                            spans.push(Span::styled(", ", dimmed));
+                        }
+                        spans.push(call);
+                    }
+                    lines.push(Line::from(spans));
+                }
             }
 
-            ListItem::new(Text::from(lines))
+            ListItem::new(wrapped(lines))
         })
         .collect();
 
+    // Messages typed during a reply wait after the conversation until sent
+    messages.extend(app.queued().iter().map(|queued| {
+        ListItem::new(wrapped(vec![Line::from(vec![
+            Span::styled(
+                "You: ",
+                Style::default().fg(theme.user).add_modifier(Modifier::BOLD),
+            ),
+            Span::styled("[queued] ", Style::default().fg(theme.dimmed)),
+            Span::styled(queued.content.as_str(), Style::default().fg(theme.dimmed)),
+        ])]))
+    }));
+
+    // Center the selected message, or else the current search match, in the
+    // view; with neither the view follows the latest message
+    let selected = app.selected();
+    let mut list_state = match selected.or(current_match) {
+        Some(focus) => {
+            let heights: Vec<usize> = messages.iter().map(ListItem::height).collect();
+            let visible = usize::from(chunks[0].height.saturating_sub(border));
+            ListState::default()
+                .with_selected(Some(focus))
+                .with_offset(centered_offset(&heights, focus, visible))
+        }
+        // The list scrolls down as far as it takes to show what it selects
+        None => ListState::default().with_selected(messages.len().checked_sub(1)),
+    };
+    let highlight = if selected.is_some() {
+        Style::default().add_modifier(Modifier::REVERSED)
+    } else if current_match.is_some() {
+        Style::default().add_modifier(Modifier::BOLD)
+    } else {
+        Style::default()
+    };
+
     // Create the messages list
-    let messages_list = List::new(messages)
-        .block(Block::default().borders(Borders::ALL).title("Conversation"))
-        .highlight_style(Style::default().add_modifier(Modifier::BOLD));
+    let mut messages_list = List::new(messages).highlight_style(highlight);
+    if bordered {
+        let title = conversation_title(app).unwrap_or_else(|| "Conversation".to_string());
+        messages_list = messages_list.block(Block::default().borders(Borders::ALL).title(title));
+    }
 
-    f.render_widget(messages_list, chunks[0]);
+    f.render_stateful_widget(messages_list, chunks[0], &mut list_state);
 
-    // Render the stats panel
-    render_stats_panel::<B>(f, app, chunks[1]);
+    if stats_panel {
+        render_stats_panel(f, app, theme, chunks[1]);
+    }
+}
+
+/// What the selected message or the search match offers, for the title of
+/// the conversation; None with neither
+fn conversation_title(app: &SentinelApp) -> Option<String> {
+    match (app.selected(), app.search().position()) {
+        (Some(index), _) => {
+            let key = |action| app.keymap().key(Mode::Normal, action);
+            Some(format!(
+                "Conversation (message {}: {} copy, {} pin, {} fork, {} details, {} open, {} follow)",
+                index,
+                key(Action::CopyMessage),
+                key(Action::TogglePin),
+                key(Action::ForkHere),
+                key(Action::ToggleDetails),
+                key(Action::OpenLink),
+                key(Action::FollowLatest)
+            ))
+        }
+        (None, Some((position, total))) => {
+            Some(format!("Conversation (match {}/{})", position, total))
+        }
+        (None, None) => None,
+    }
+}
+
+/// Split message content into spans with search matches highlighted
+fn highlight_matches<'a>(
+    content: &'a str,
+    search: &Search,
+    is_current: bool,
+    theme: &Theme,
+) -> Vec<Span<'a>> {
+    let ranges = search.ranges(content);
+    if ranges.is_empty() {
+        return vec![Span::raw(content)];
+    }
+
+    let match_style = if is_current {
+        Style::default().fg(Color::Black).bg(theme.error)
+    } else {
+        Style::default().fg(Color::Black).bg(theme.highlight)
+    };
+
+    let mut spans = Vec::new();
+    let mut last = 0;
+    for (start, end) in ranges {
+        if start > last {
+            spans.push(Span::raw(&content[last..start]));
+        }
+        spans.push(Span::styled(&content[start..end], match_style));
+        last = end;
+    }
+    if last < content.len() {
+        spans.push(Span::raw(&content[last..]));
+    }
+
+    spans
+}
+
+/// First item to show so that `selected` sits in the middle of `visible` rows
+fn centered_offset(heights: &[usize], selected: usize, visible: usize) -> usize {
+    let mut above = heights.get(selected).copied().unwrap_or(0) / 2;
+    let mut offset = selected;
+    while offset > 0 && above + heights[offset - 1] <= visible / 2 {
+        offset -= 1;
+        above += heights[offset];
+    }
+    offset
 }
 
 /// Render the stats panel
-fn render_stats_panel<B: Backend>(f: &mut Frame, app: &SentinelApp, area: Rect) {
+fn render_stats_panel(f: &mut Frame, app: &SentinelApp, theme: &Theme, area: Rect) {
     // Get the latest message for stats
-    let latest_message = app
-        .messages()
-        .iter()
-        .rev()
-        .find(|msg| msg.role == MessageRole::Assistant);
+    let latest_message = latest_reply(app);
 
     // Get token counts
     let input_tokens = latest_message
         .map(|msg| msg.input_tokens.to_string())
         .unwrap_or_else(|| "0".to_string());
 
+    let tool_output_tokens = latest_message
+        .map(|msg| msg.tool_output_tokens.to_string())
+        .unwrap_or_else(|| "0".to_string());
+
     let output_tokens = latest_message
         .map(|msg| msg.output_tokens.to_string())
         .unwrap_or_else(|| "0".to_string());
@@ -182,15 +746,21 @@ fn render_stats_panel<B: Backend>(f: &mut Frame, app: &SentinelApp, area: Rect)
         "None".to_string()
     };
 
+    let session = app.stats();
+
     // Create the stats text
-    let stats_text = vec![
+    let mut stats_text = vec![
         Line::from(vec![
             Span::raw("Input tokens: "),
-            Span::styled(input_tokens, Style::default().fg(Color::Yellow)),
+            Span::styled(input_tokens, Style::default().fg(theme.highlight)),
+        ]),
+        Line::from(vec![
+            Span::raw("Tool output tokens: "),
+            Span::styled(tool_output_tokens, Style::default().fg(theme.highlight)),
         ]),
         Line::from(vec![
             Span::raw("Output tokens: "),
-            Span::styled(output_tokens, Style::default().fg(Color::Yellow)),
+            Span::styled(output_tokens, Style::default().fg(theme.highlight)),
         ]),
         Line::from(""),
         Line::from(vec![Span::styled(
@@ -199,17 +769,180 @@ fn render_stats_panel<B: Backend>(f: &mut Frame, app: &SentinelApp, area: Rect)
         )]),
         {
             let color = if used_tools == "None" {
-                Color::DarkGray
+                theme.dimmed
             } else {
-                Color::Green
+                theme.success
             };
             Line::from(vec![Span::styled(
                 used_tools.clone(),
                 Style::default().fg(color),
             )])
         },
+        Line::from(""),
+        Line::from(vec![Span::styled(
+            "Session:",
+            Style::default().add_modifier(Modifier::UNDERLINED),
+        )]),
+        Line::from(vec![
+            Span::raw("Requests: "),
+            Span::styled(
+                session.requests.to_string(),
+                Style::default().fg(theme.highlight),
+            ),
+        ]),
+        Line::from(vec![
+            Span::raw("Tokens: "),
+            Span::styled(
+                TokenBreakdown::new(
+                    session.input_tokens,
+                    session.tool_output_tokens,
+                    session.output_tokens,
+                )
+                .to_string(),
+                Style::default().fg(theme.highlight),
+            ),
+        ]),
+        Line::from(vec![
+            Span::raw("Generation: "),
+            Span::styled(
+                format!("{:.1}s", session.generation_time.as_secs_f64()),
+                Style::default().fg(theme.highlight),
+            ),
+        ]),
     ];
 
+    // Where the server's time went on the last reply, loading the model apart
+    if let Some(server) = latest_message.and_then(|msg| msg.server_timings) {
+        let load_color = if server.loaded() {
+            theme.error
+        } else {
+            theme.highlight
+        };
+        let timing_lines = vec![
+            Line::from(vec![
+                Span::raw("Model load: "),
+                Span::styled(
+                    timings::seconds(server.load),
+                    Style::default().fg(load_color),
+                ),
+            ]),
+            Line::from(vec![
+                Span::raw("Prompt eval: "),
+                Span::styled(
+                    timings::seconds(server.prompt),
+                    Style::default().fg(theme.highlight),
+                ),
+            ]),
+            Line::from(vec![
+                Span::raw("Generation: "),
+                Span::styled(
+                    timings::seconds(server.generation),
+                    Style::default().fg(theme.highlight),
+                ),
+            ]),
+        ];
+        stats_text.splice(3..3, timing_lines);
+    }
+
+    // Words rather than tokens, for writing prose
+    if app.word_counts_visible() {
+        let reply = latest_message
+            .map(|msg| WordCount::of(&msg.content))
+            .unwrap_or_default();
+        let mut session_words = WordCount::default();
+        app.messages()
+            .iter()
+            .filter(|msg| msg.role == MessageRole::Assistant && !msg.ephemeral)
+            .for_each(|msg| session_words.add(WordCount::of(&msg.content)));
+        let words = |count: WordCount| match count.words {
+            0 => "0".to_string(),
+            words => format!("{} (~{} min)", words, count.reading_minutes()),
+        };
+        let reply_lines = vec![
+            Line::from(vec![
+                Span::raw("Words: "),
+                Span::styled(words(reply), Style::default().fg(theme.highlight)),
+            ]),
+            Line::from(vec![
+                Span::raw("Characters: "),
+                Span::styled(
+                    reply.characters.to_string(),
+                    Style::default().fg(theme.highlight),
+                ),
+            ]),
+            Line::from(vec![
+                Span::raw("Code lines: "),
+                Span::styled(
+                    reply.code_lines.to_string(),
+                    Style::default().fg(theme.highlight),
+                ),
+            ]),
+        ];
+        // Under the token counts of the last reply
+        stats_text.splice(3..3, reply_lines);
+        stats_text.push(Line::from(vec![
+            Span::raw("Words: "),
+            Span::styled(words(session_words), Style::default().fg(theme.highlight)),
+        ]));
+    }
+
+    // How much of the model's context window the last request took
+    if let Some(window) = app.context_window() {
+        let used = latest_message
+            .map(|msg| msg.input_tokens + msg.tool_output_tokens)
+            .unwrap_or(0);
+        stats_text.push(Line::from(vec![
+            Span::raw("Context: "),
+            Span::styled(
+                format!(
+                    "{} / {}",
+                    stats::compact(used),
+                    stats::compact(window.tokens)
+                ),
+                Style::default().fg(if used > window.budget() {
+                    theme.error
+                } else {
+                    theme.highlight
+                }),
+            ),
+        ]));
+    }
+
+    if session.estimated_cost > 0.0 {
+        stats_text.push(Line::from(vec![
+            Span::raw("Cost: "),
+            Span::styled(
+                format!("~${:.4}", session.estimated_cost),
+                Style::default().fg(theme.highlight),
+            ),
+        ]));
+    }
+
+    for (tool, count) in &session.tool_calls {
+        stats_text.push(Line::from(vec![
+            Span::raw(format!("  {}: ", tool)),
+            Span::styled(count.to_string(), Style::default().fg(theme.success)),
+        ]));
+    }
+
+    // Only worth the space once replies came from more than one model
+    if session.per_model.len() > 1 {
+        stats_text.push(Line::from(""));
+        stats_text.push(Line::from(vec![Span::styled(
+            "By model:",
+            Style::default().add_modifier(Modifier::UNDERLINED),
+        )]));
+        for (model, usage) in &session.per_model {
+            stats_text.push(Line::from(vec![
+                Span::raw(format!("  {}: ", model)),
+                Span::styled(
+                    format!("{} in / {} out", usage.input_tokens, usage.output_tokens),
+                    Style::default().fg(theme.highlight),
+                ),
+            ]));
+        }
+    }
+
     // Create the stats widget
     let stats_widget = Paragraph::new(Text::from(stats_text))
         .block(Block::default().borders(Borders::ALL).title("Stats"))
@@ -218,29 +951,952 @@ fn render_stats_panel<B: Backend>(f: &mut Frame, app: &SentinelApp, area: Rect)
     f.render_widget(stats_widget, area);
 }
 
-/// Render the input box
-fn render_input_box<B: Backend>(f: &mut Frame, app: &SentinelApp, area: Rect) {
-    // Create the input box
-    let input = Paragraph::new(app.input()).style(Style::default()).block(
-        Block::default()
-            .borders(Borders::ALL)
-            .title("Input")
-            .style(Style::default().fg(if app.is_loading() {
-                Color::DarkGray
-            } else {
-                Color::White
-            })),
-    );
+/// Render the input box, with only the top border in the zen layout
+fn render_input_box(f: &mut Frame, app: &SentinelApp, theme: &Theme, area: Rect, borders: Borders) {
+    if app.is_searching() {
+        render_search_box(f, app.search(), app.keymap(), theme, area, borders);
+        return;
+    }
+
+    if let Some(search) = app.history_search() {
+        render_history_search(
+            f,
+            &search.query,
+            app.history_search_match(),
+            app.keymap(),
+            theme,
+            area,
+            borders,
+        );
+        return;
+    }
+
+    // Create the input box, showing what the model is doing while it generates
+    let zen = !borders.contains(Borders::LEFT);
+    let left = u16::from(!zen);
+    let title = if app.is_loading() {
+        Line::from(busy_title(app.activity().status().as_deref(), app.keymap()))
+    } else if zen {
+        zen_title(app, theme, usize::from(area.width))
+    } else {
+        Line::from("Input")
+    };
+    // Scroll long input sideways so the cursor stays inside the box; wide
+    // characters such as CJK take two columns
+    let width = usize::from(area.width.saturating_sub(2 * left));
+    let cursor = wrap::width(app.input());
+    let scroll = (cursor + 1).saturating_sub(width);
+    let input = Paragraph::new(app.input())
+        .style(Style::default())
+        .scroll((0, u16::try_from(scroll).unwrap_or(u16::MAX)))
+        .block(
+            Block::default()
+                .borders(borders)
+                .title(title)
+                .style(Style::default().fg(if app.is_loading() {
+                    theme.dimmed
+                } else {
+                    theme.border
+                })),
+        );
 
     f.render_widget(input, area);
 
     // Show cursor if not loading
     if !app.is_loading() {
+        // Past the end of the input text, but never beyond the box
+        let column = cursor.saturating_sub(scroll).min(width.saturating_sub(1));
         f.set_cursor(
-            // Put cursor past the end of the input text
-            area.x + app.input().len() as u16 + 1,
+            area.x + left + u16::try_from(column).unwrap_or(0),
             // Position at the start of the input line
             area.y + 1,
         );
     }
 }
+
+/// The title of the zen layout's input line: what the selected message or
+/// the search match offers, or else the status bar folded into `width` columns
+fn zen_title(app: &SentinelApp, theme: &Theme, width: usize) -> Line<'static> {
+    // Flash when a slow response has just finished
+    if app.is_flashing() {
+        return Line::from(Span::styled(
+            " Sentinel - response ready ",
+            Style::default()
+                .fg(Color::Black)
+                .bg(theme.highlight)
+                .add_modifier(Modifier::BOLD),
+        ));
+    }
+    if let Some(title) = conversation_title(app) {
+        return truncate_line(Line::from(title), width, theme);
+    }
+
+    let dim = Style::default().fg(theme.dimmed);
+    let mut spans = vec![
+        Span::raw("Input"),
+        Span::styled(format!(" | {}", app.model_name()), dim),
+        Span::styled(format!(" | {}", app.branch_name()), dim),
+    ];
+    // What the status bar highlights
+    let mut flag = |text: String, color| spans.push(Span::styled(text, Style::default().fg(color)));
+    let staged = app.staged_attachments().len() + app.staged_images().len();
+    if staged > 0 {
+        flag(format!(" | {} attached", staged), theme.highlight);
+    }
+    let queued = app.queued().len();
+    if queued > 0 {
+        flag(format!(" | {} queued", queued), theme.highlight);
+    }
+    if app.tools_unsupported() {
+        flag(" | no tools".to_string(), theme.error);
+    }
+    if app.is_warming_up() {
+        flag(" | warming up model…".to_string(), theme.highlight);
+    }
+    if app.read_only() {
+        spans.insert(
+            0,
+            Span::styled("🔒 read-only | ", Style::default().fg(theme.highlight)),
+        );
+    }
+    truncate_line(Line::from(spans), width, theme)
+}
+
+/// The title of the input box while a reply is generated
+pub fn busy_title(status: Option<&str>, keymap: &Keymap) -> String {
+    format!(
+        "{} ({} to interrupt)",
+        status.unwrap_or("Input"),
+        keymap.key(Mode::Busy, Action::Interrupt)
+    )
+}
+
+/// Render the top border of the input box of `layout` with `title`, the
+/// status of a generation drawn while the UI waits for it
+pub fn render_activity(f: &mut Frame, area: Rect, title: &str, theme: &Theme, layout: LayoutMode) {
+    let borders = match layout {
+        LayoutMode::Full => Borders::TOP | Borders::LEFT | Borders::RIGHT,
+        LayoutMode::Zen => Borders::TOP,
+    };
+    let border = Block::default()
+        .borders(borders)
+        .title(title.to_string())
+        .style(Style::default().fg(theme.dimmed));
+    f.render_widget(Clear, area);
+    f.render_widget(border, area);
+}
+
+/// Render a readline-style reverse history search in place of the input box
+fn render_history_search(
+    f: &mut Frame,
+    query: &str,
+    matched: Option<&str>,
+    keymap: &Keymap,
+    theme: &Theme,
+    area: Rect,
+    borders: Borders,
+) {
+    let prompt = if query.is_empty() || matched.is_some() {
+        "reverse-i-search"
+    } else {
+        "failing reverse-i-search"
+    };
+    let prefix = format!("({})`{}': ", prompt, query);
+
+    // Show multi-line entries on one line
+    let matched = matched.unwrap_or("").replace('\n', " ⏎ ");
+    let line = Line::from(vec![
+        Span::styled(prefix.clone(), Style::default().fg(theme.highlight)),
+        Span::raw(matched),
+    ]);
+
+    let input = Paragraph::new(line).block(Block::default().borders(borders).title(format!(
+        "History - {}: older, {}: accept, {}: cancel",
+        keymap.key(Mode::HistorySearch, Action::HistorySearch),
+        keymap.key(Mode::HistorySearch, Action::AcceptHistory),
+        keymap.key(Mode::HistorySearch, Action::CancelHistory)
+    )));
+
+    f.render_widget(input, area);
+    // At the end of the query, before its closing "': "
+    let left = u16::from(borders.contains(Borders::LEFT));
+    let column = u16::try_from(prefix.chars().count() - 3).unwrap_or(u16::MAX) + left;
+    f.set_cursor(
+        area.x
+            .saturating_add(column)
+            .min(area.right().saturating_sub(1 + left)),
+        area.y + 1,
+    );
+}
+
+/// Render the /best candidates over the conversation, with a preview of the selected one
+fn render_candidate_picker(
+    f: &mut Frame,
+    picker: &CandidatePicker,
+    keymap: &Keymap,
+    theme: &Theme,
+    area: Rect,
+) {
+    let area = centered_rect(80, 70, area);
+    f.render_widget(Clear, area);
+
+    let block = Block::default().borders(Borders::ALL).title(format!(
+        "Candidates - {}/{}: select, {}: keep, {}: discard",
+        keymap.key(Mode::Picker, Action::PreviousCandidate),
+        keymap.key(Mode::Picker, Action::NextCandidate),
+        keymap.key(Mode::Picker, Action::AcceptCandidate),
+        keymap.key(Mode::Picker, Action::DiscardCandidates)
+    ));
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(28), Constraint::Min(10)])
+        .split(inner);
+
+    let items: Vec<ListItem> = picker
+        .candidates
+        .iter()
+        .map(|candidate| {
+            ListItem::new(Line::from(vec![
+                Span::styled(
+                    format!("{} ", candidate.label),
+                    Style::default()
+                        .fg(theme.accent)
+                        .add_modifier(Modifier::BOLD),
+                ),
+                Span::styled(
+                    format!(
+                        "{} tok, {:.1}s",
+                        candidate.input_tokens + candidate.output_tokens,
+                        candidate.latency.as_secs_f64()
+                    ),
+                    Style::default().fg(theme.highlight),
+                ),
+            ]))
+        })
+        .collect();
+
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::RIGHT))
+        .highlight_style(Style::default().bg(theme.dimmed))
+        .highlight_symbol("> ");
+    let mut state = ListState::default().with_selected(Some(picker.selected));
+    f.render_stateful_widget(list, chunks[0], &mut state);
+
+    let selected = &picker.candidates[picker.selected];
+    let mut preview = Vec::new();
+    if !selected.used_tools.is_empty() {
+        preview.push(Line::from(Span::styled(
+            format!("Tools: {}", selected.used_tools.join(", ")),
+            Style::default().fg(theme.success),
+        )));
+        preview.push(Line::from(""));
+    }
+    preview.extend(
+        selected
+            .text
+            .lines()
+            .map(|line| Line::from(line.to_string())),
+    );
+
+    let preview = Paragraph::new(Text::from(preview)).wrap(Wrap { trim: false });
+    f.render_widget(preview, chunks[1]);
+}
+
+/// Render the links of the selected message over the conversation, to pick one to open
+fn render_link_picker(
+    f: &mut Frame,
+    picker: &LinkPicker,
+    keymap: &Keymap,
+    theme: &Theme,
+    area: Rect,
+) {
+    let height = (picker.links.len() as u16 + 2).min(area.height);
+    let area = centered_rect(80, 100, area);
+    let area = Rect {
+        y: area.y + area.height.saturating_sub(height) / 2,
+        height,
+        ..area
+    };
+    f.render_widget(Clear, area);
+
+    let items: Vec<ListItem> = picker
+        .links
+        .iter()
+        .map(|(text, target)| {
+            let mut spans = vec![Span::styled(
+                text.clone(),
+                Style::default().fg(theme.accent),
+            )];
+            // Relative paths show where they lead
+            let shown = target.to_string();
+            if shown != *text {
+                spans.push(Span::styled(
+                    format!("  {}", shown),
+                    Style::default().fg(theme.dimmed),
+                ));
+            }
+            ListItem::new(Line::from(spans))
+        })
+        .collect();
+
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(format!(
+            "Links - {}/{}: select, {}: open, {}: close",
+            keymap.key(Mode::Picker, Action::PreviousCandidate),
+            keymap.key(Mode::Picker, Action::NextCandidate),
+            keymap.key(Mode::Picker, Action::AcceptCandidate),
+            keymap.key(Mode::Picker, Action::DiscardCandidates)
+        )))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .highlight_symbol("> ");
+    let mut state = ListState::default().with_selected(Some(picker.selected));
+    f.render_stateful_widget(list, area, &mut state);
+}
+
+/// Render the /compare replies beside each other over the conversation
+fn render_comparison(
+    f: &mut Frame,
+    view: &ComparisonView,
+    keymap: &Keymap,
+    theme: &Theme,
+    area: Rect,
+) {
+    let area = centered_rect(90, 80, area);
+    f.render_widget(Clear, area);
+
+    let block = Block::default().borders(Borders::ALL).title(format!(
+        "Compare - {}/{}: select, {}: promote, {}: discard",
+        keymap.key(Mode::Picker, Action::PreviousCandidate),
+        keymap.key(Mode::Picker, Action::NextCandidate),
+        keymap.key(Mode::Picker, Action::AcceptCandidate),
+        keymap.key(Mode::Picker, Action::DiscardCandidates)
+    ));
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let count = view.comparison.len() as u32;
+    let panes = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(vec![Constraint::Ratio(1, count); view.comparison.len()])
+        .split(inner);
+
+    for (index, (side, pane)) in view.comparison.iter().zip(panes.iter()).enumerate() {
+        let border = if index == view.selected {
+            Style::default().fg(theme.accent)
+        } else {
+            Style::default().fg(theme.dimmed)
+        };
+        let text_style = if side.reply.is_err() {
+            Style::default().fg(theme.error)
+        } else {
+            Style::default()
+        };
+
+        let mut lines = vec![
+            Line::from(Span::styled(
+                side.usage(),
+                Style::default().fg(theme.highlight),
+            )),
+            Line::from(""),
+        ];
+        lines.extend(
+            side.text()
+                .lines()
+                .map(|line| Line::from(Span::styled(line.to_string(), text_style))),
+        );
+
+        let paragraph = Paragraph::new(Text::from(lines))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(border)
+                    .title(side.heading()),
+            )
+            .wrap(Wrap { trim: false });
+        f.render_widget(paragraph, *pane);
+    }
+}
+
+/// Render every key binding, grouped by mode, over the conversation
+fn render_help(f: &mut Frame, keymap: &Keymap, theme: &Theme, area: Rect) {
+    let area = centered_rect(70, 80, area);
+    f.render_widget(Clear, area);
+
+    let mut lines = Vec::new();
+    for mode in keymap::MODES {
+        if !lines.is_empty() {
+            lines.push(Line::from(""));
+        }
+        lines.push(Line::from(Span::styled(
+            mode.title(),
+            Style::default()
+                .fg(theme.accent)
+                .add_modifier(Modifier::BOLD),
+        )));
+        // An action with several keys gets one line, e.g. "up/k"
+        let bindings: Vec<_> = keymap.bindings(mode).collect();
+        for (i, binding) in bindings.iter().enumerate() {
+            if bindings[..i]
+                .iter()
+                .any(|other| other.action == binding.action)
+            {
+                continue;
+            }
+            let chords: Vec<String> = bindings
+                .iter()
+                .filter(|other| other.action == binding.action)
+                .map(|other| other.chord.to_string())
+                .collect();
+            lines.push(Line::from(vec![
+                Span::styled(
+                    format!("  {:<12}", chords.join("/")),
+                    Style::default().fg(theme.highlight),
+                ),
+                Span::raw(binding.action.description()),
+            ]));
+        }
+    }
+
+    let help = Paragraph::new(Text::from(lines))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Key bindings - press any key to close"),
+        )
+        .wrap(Wrap { trim: false });
+    f.render_widget(help, area);
+}
+
+/// Render what the tools changed this session, colored like a review
+fn render_diff(f: &mut Frame, view: &DiffView, theme: &Theme, area: Rect) {
+    let area = centered_rect(90, 90, area);
+    f.render_widget(Clear, area);
+
+    let lines: Vec<Line> = view
+        .lines
+        .iter()
+        .map(|line| {
+            let style = match LineKind::of(line) {
+                LineKind::Title => Style::default().add_modifier(Modifier::BOLD),
+                LineKind::Header => Style::default().add_modifier(Modifier::DIM),
+                LineKind::Hunk => Style::default().fg(theme.accent),
+                LineKind::Added => Style::default().fg(theme.success),
+                LineKind::Removed => Style::default().fg(theme.error),
+                LineKind::Context => Style::default(),
+            };
+            Line::from(Span::styled(line.clone(), style))
+        })
+        .collect();
+
+    let diff =
+        Paragraph::new(Text::from(lines))
+            .block(Block::default().borders(Borders::ALL).title(
+                "Changes this session - Up/Down/PgUp/PgDn to scroll, any other key to close",
+            ))
+            .scroll((u16::try_from(view.scroll).unwrap_or(u16::MAX), 0));
+    f.render_widget(diff, area);
+}
+
+/// Render the latest line printed by a running tool in place of the status bar
+pub fn render_progress(
+    f: &mut Frame,
+    area: Rect,
+    tool: &str,
+    line: &str,
+    count: usize,
+    theme: &Theme,
+) {
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!(" Running {} ({} lines) ", tool, count))
+        .border_style(Style::default().fg(theme.highlight));
+    let tail = Paragraph::new(Span::styled(
+        line.to_string(),
+        Style::default().add_modifier(Modifier::DIM),
+    ))
+    .block(block);
+    f.render_widget(Clear, area);
+    f.render_widget(tail, area);
+}
+
+/// Render a proposed file change as a scrollable diff, with the decline reason being typed
+pub fn render_review(f: &mut Frame, view: &ReviewView, keymap: &Keymap, theme: &Theme) {
+    let area = centered_rect(90, 90, f.size());
+    f.render_widget(Clear, area);
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(3), Constraint::Length(3)])
+        .split(area);
+
+    let lines: Vec<Line> = view
+        .lines
+        .iter()
+        .skip(view.scroll)
+        .map(|line| match line {
+            ReviewLine::Hunk(header) => Line::from(Span::styled(
+                header.clone(),
+                Style::default().fg(theme.accent),
+            )),
+            ReviewLine::Diff(diff) => {
+                let color = match diff {
+                    DiffLine::Context(_) => Color::Reset,
+                    DiffLine::Added(_) => theme.success,
+                    DiffLine::Removed(_) => theme.error,
+                };
+                Line::from(Span::styled(diff.unified(), Style::default().fg(color)))
+            }
+        })
+        .collect();
+    let diff = Paragraph::new(Text::from(lines)).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(format!(" Review: {} ", view.title)),
+    );
+    f.render_widget(diff, chunks[0]);
+
+    let prompt = match &view.reason {
+        Some(reason) => Paragraph::new(reason.as_str()).block(
+            Block::default().borders(Borders::ALL).title(format!(
+                "Why not? (optional) - {}: decline, {}: decline without a reason",
+                keymap.key(Mode::ReviewReason, Action::SendReason),
+                keymap.key(Mode::ReviewReason, Action::SkipReason)
+            )),
+        ),
+        None => Paragraph::new(format!(
+            "{}: apply  {}: apply all for this session  {}: decline  {}/{}: scroll",
+            keymap.key(Mode::Review, Action::ApproveChange),
+            keymap.key(Mode::Review, Action::ApproveAllChanges),
+            keymap.key(Mode::Review, Action::RejectChange),
+            keymap.key(Mode::Review, Action::ScrollUp),
+            keymap.key(Mode::Review, Action::ScrollDown)
+        ))
+        .block(Block::default().borders(Borders::ALL)),
+    };
+    f.render_widget(prompt, chunks[1]);
+}
+
+/// A rectangle taking the given percentages of `area`, centered in it
+fn centered_rect(percent_x: u16, percent_y: u16, area: Rect) -> Rect {
+    let width = area.width * percent_x / 100;
+    let height = area.height * percent_y / 100;
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+/// Render the search query in place of the input box
+fn render_search_box(
+    f: &mut Frame,
+    search: &Search,
+    keymap: &Keymap,
+    theme: &Theme,
+    area: Rect,
+    borders: Borders,
+) {
+    let mode = if search.is_regex() { "Regex" } else { "Search" };
+    let toggle = keymap.key(Mode::Search, Action::ToggleRegex);
+    let title = match search.position() {
+        Some((position, total)) => format!(
+            "{} ({}/{}) - {}: toggle regex",
+            mode, position, total, toggle
+        ),
+        None if search.is_invalid() => {
+            format!("{} (invalid pattern) - {}: toggle regex", mode, toggle)
+        }
+        None if search.is_active() => format!("{} (no matches) - {}: toggle regex", mode, toggle),
+        None => format!("{} - {}: toggle regex", mode, toggle),
+    };
+
+    let input = Paragraph::new(format!("/{}", search.query())).block(
+        Block::default()
+            .borders(borders)
+            .title(title)
+            .style(Style::default().fg(theme.highlight)),
+    );
+
+    f.render_widget(input, area);
+    let left = u16::from(borders.contains(Borders::LEFT));
+    f.set_cursor(
+        area.x + left + search.query().chars().count() as u16 + 1,
+        area.y + 1,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::llm::mock::MockLlmClient;
+    use crate::llm::timings::ServerTimings;
+    use crate::session::Sessions;
+    use crate::stats::ToolTiming;
+    use ratatui::{backend::TestBackend, buffer::Buffer, Terminal};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    fn app() -> SentinelApp {
+        let client = MockLlmClient::new().with_tools(&["bash", "ls"]);
+        SentinelApp::with_client(Box::new(client), "mock").with_sessions(Sessions::new(vec![
+            UiMessage::user("What is the weather in Tbilisi?".to_string()),
+            UiMessage::assistant("Sunny and 25°C".to_string(), 120, 45),
+        ]))
+    }
+
+    // The cells `app` fills on a `width` x `height` terminal
+    fn buffer(app: &SentinelApp, width: u16, height: u16) -> Buffer {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|f| render_ui(f, app)).unwrap();
+        terminal.backend().buffer().clone()
+    }
+
+    // What `app` looks like on a `width` x `height` terminal, one string per row
+    fn draw(app: &SentinelApp, width: u16, height: u16) -> Vec<String> {
+        let buffer = buffer(app, width, height);
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| buffer.get(x, y).symbol.as_str())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_wide_terminals_show_the_stats_panel() {
+        let rows = draw(&app(), 120, 30);
+        assert!(rows[4].contains("Conversation") && rows[4].contains("Stats"));
+        assert!(rows.iter().any(|row| row.contains("Input tokens: 120")));
+    }
+
+    #[test]
+    fn test_the_stats_panel_lists_the_server_timings_of_the_last_reply() {
+        assert!(!draw(&app(), 120, 30)
+            .iter()
+            .any(|row| row.contains("Model load:")));
+
+        let cold = ServerTimings::from_nanos(12_100_000_000, 800_000_000, 4_200_000_000);
+        let app = app().with_sessions(Sessions::new(vec![
+            UiMessage::user("Hi".to_string()),
+            UiMessage::assistant("Hello".to_string(), 10, 2).with_server_timings(cold),
+        ]));
+        let rows = draw(&app, 120, 30);
+        assert!(rows.iter().any(|row| row.contains("Model load: 12.1s")));
+        assert!(rows.iter().any(|row| row.contains("Prompt eval: 0.8s")));
+        assert!(rows.iter().any(|row| row.contains("Generation: 4.2s")));
+    }
+
+    #[test]
+    fn test_word_counts_are_shown_when_the_config_asks() {
+        assert!(!draw(&app(), 120, 40).iter().any(|row| row.contains("Words:")));
+
+        let config = Config {
+            word_counts: true,
+            ..Config::default()
+        };
+        let rows = draw(&app().with_config(&config), 120, 40);
+        let words = rows.iter().filter(|row| row.contains("Words: 4 (~1 min)"));
+        // The last reply's, and the session's
+        assert_eq!(words.count(), 2);
+        assert!(rows.iter().any(|row| row.contains("Characters: 12")));
+        assert!(rows.iter().any(|row| row.contains("Code lines: 0")));
+    }
+
+    #[test]
+    fn test_narrow_terminals_fold_the_stats_into_the_status_bar() {
+        let rows = draw(&app(), 40, 20);
+        assert!(!rows.iter().any(|row| row.contains("Stats")));
+        assert!(rows[2].starts_with("│In: 120 | Tool output: 0 | Out: 45"));
+        assert!(rows[2].ends_with("…│"));
+        // The status line is cut short rather than running off the edge
+        assert!(rows[1].starts_with("│Model: mock | Tools: Enabled"));
+        assert!(rows[1].ends_with("…│"));
+        assert!(rows[3].starts_with("│bash ls"));
+        assert!(rows[5].contains("Conversation"));
+        assert!(rows[6].contains("You: What is the weather in Tbilisi?"));
+    }
+
+    #[test]
+    fn test_read_only_sessions_show_a_lock() {
+        let config = Config {
+            read_only: true,
+            ..Config::default()
+        };
+        let rows = draw(&app().with_config(&config), 40, 20);
+        assert!(rows[1].starts_with("│🔒"));
+        assert!(rows[1].contains("read-only | Model: mock"));
+        assert!(!draw(&app(), 120, 30)[1].contains("read-only"));
+    }
+
+    #[test]
+    fn test_the_full_layout_at_two_sizes() {
+        assert_eq!(
+            draw(&app(), 40, 14),
+            [
+                "┌Sentinel──────────────────────────────┐",
+                "│Model: mock | Tools: Enabled | Branch…│",
+                "│In: 120 | Tool output: 0 | Out: 45 | …│",
+                "│bash ls                               │",
+                "└──────────────────────────────────────┘",
+                "┌Conversation──────────────────────────┐",
+                "│You: What is the weather in Tbilisi?  │",
+                "│Assistant: Sunny and 25°C             │",
+                "│                                      │",
+                "│                                      │",
+                "└──────────────────────────────────────┘",
+                "┌Input─────────────────────────────────┐",
+                "│                                      │",
+                "└──────────────────────────────────────┘",
+            ]
+        );
+        let rows = draw(&app(), 100, 16);
+        assert_eq!(
+            rows[..6],
+            [
+                format!("┌Sentinel{}┐", "─".repeat(90)),
+                format!("│Model: mock | Tools: Enabled | Branch: main{}│", " ".repeat(55)),
+                format!("│bash ls{}│", " ".repeat(91)),
+                format!("└{}┘", "─".repeat(98)),
+                format!("┌Conversation{}┐┌Stats{}┐", "─".repeat(61), "─".repeat(18)),
+                format!(
+                    "│You: What is the weather in Tbilisi?{}││Input tokens: 120      │",
+                    " ".repeat(37)
+                ),
+            ]
+        );
+        assert_eq!(rows[13], format!("┌Input{}┐", "─".repeat(93)));
+    }
+
+    #[test]
+    fn test_zen_shows_the_conversation_over_a_line_of_input() {
+        let config = Config {
+            layout: LayoutMode::Zen,
+            ..Config::default()
+        };
+        let zen = app().with_config(&config);
+        let mut rows = vec![
+            "You: What is the weather in Tbilisi?    ",
+            "Assistant: Sunny and 25°C               ",
+            "                                        ",
+            "                                        ",
+            "                                        ",
+            "                                        ",
+            "Input | mock | main─────────────────────",
+            "                                        ",
+        ];
+        assert_eq!(draw(&zen, 40, 8), rows);
+
+        // A taller, wider terminal gives the conversation all the room
+        let wide: Vec<String> = draw(&zen, 60, 10);
+        assert_eq!(wide[0], format!("{:<60}", rows[0]));
+        assert_eq!(wide[1], format!("Assistant: Sunny and 25°C{}", " ".repeat(35)));
+        assert!(wide[2..8].iter().all(|row| row.trim().is_empty()));
+        assert_eq!(wide[8], format!("Input | mock | main{}", "─".repeat(41)));
+
+        // The smallest terminal zen fits in is far smaller than the full layout's
+        rows.drain(2..5);
+        assert_eq!(draw(&zen, MIN_WIDTH + 10, ZEN_MIN_HEIGHT), rows);
+        assert!(draw(&zen, 40, ZEN_MIN_HEIGHT - 1)[0].contains("Terminal too small"));
+    }
+
+    #[test]
+    fn test_zen_folds_the_status_into_the_input_title() {
+        let config = Config {
+            layout: LayoutMode::Zen,
+            read_only: true,
+            ..Config::default()
+        };
+        let rows = draw(&app().with_config(&config), 30, 6);
+        // The lock is two columns wide
+        assert!(rows[4].starts_with("🔒"));
+        assert!(rows[4].ends_with(" read-only | Input | mock |…"));
+        assert!(!rows.concat().contains("Stats"));
+    }
+
+    #[test]
+    fn test_tiny_terminals_get_a_placeholder() {
+        let rows = draw(&app(), 20, 10);
+        assert!(rows.concat().contains("Terminal too small"));
+        // However small it gets, nothing panics
+        for (width, height) in [(79, 5), (1, 1), (0, 0)] {
+            draw(&app(), width, height);
+        }
+        // The smallest terminal that fits shows the whole layout
+        let rows = draw(&app(), MIN_WIDTH, MIN_HEIGHT);
+        assert!(rows[0].starts_with("┌Sentinel"));
+        assert!(rows[MIN_HEIGHT as usize - 3].starts_with("┌Input"));
+    }
+
+    #[test]
+    fn test_long_system_messages_collapse() {
+        let prompt = UiMessage::system("Be brief.\nCite sources.\nUse metric units.".to_string());
+        assert_eq!(
+            collapse(&prompt),
+            Some(("Be brief.…".to_string(), "2 more lines, ".to_string()))
+        );
+        assert_eq!(collapse(&UiMessage::system("Be brief.".to_string())), None);
+        assert_eq!(collapse(&UiMessage::error("Error: a\nb".to_string())), None);
+
+        let mut messages = vec![prompt];
+        messages.extend(app().messages().iter().cloned());
+        messages.push(UiMessage::error("Error: connection refused".to_string()));
+        let app = app().with_sessions(Sessions::new(messages));
+        let rows = draw(&app, 120, 30).concat();
+        assert!(rows.contains("System: [system prompt] Be brief.… (2 more lines, x to expand)"));
+        assert!(!rows.contains("Cite sources."));
+        assert!(rows.contains("✖ System: Error: connection refused"));
+    }
+
+    #[test]
+    fn test_the_tools_line_shows_the_offered_tools() {
+        let app = app().with_sessions(Sessions::new(vec![UiMessage::assistant_with_tools(
+            "Two files".to_string(),
+            10,
+            5,
+            vec!["LS".to_string()],
+        )]));
+        let buffer = buffer(&app, 120, 30);
+        let row: String = (0..120).map(|x| buffer.get(x, 2).symbol.as_str()).collect();
+        assert!(row.starts_with("│bash ls "));
+        // Only the tool the last reply used stands out
+        assert_eq!(buffer.get(1, 2).fg, Color::DarkGray);
+        assert_eq!(buffer.get(6, 2).fg, Color::Green);
+    }
+
+    #[test]
+    fn test_slow_tool_calls_stand_out() {
+        let timing = |label: &str, millis| ToolTiming {
+            label: label.to_string(),
+            tool: label.split(' ').next().unwrap().to_string(),
+            duration: Duration::from_millis(millis),
+        };
+        let reply = UiMessage::assistant_with_tools(
+            "Built".to_string(),
+            10,
+            5,
+            vec!["file".to_string(), "bash".to_string()],
+        )
+        .with_tool_timings(vec![timing("file read", 12), timing("bash", 12_400)]);
+        let app = app().with_sessions(Sessions::new(vec![reply]));
+        let buffer = buffer(&app, 120, 30);
+
+        let (y, row) = (0..30)
+            .map(|y| {
+                (
+                    y,
+                    (0..120)
+                        .map(|x| buffer.get(x, y).symbol.as_str())
+                        .collect::<String>(),
+                )
+            })
+            .find(|(_, row)| row.contains("Tools: file"))
+            .unwrap();
+        assert!(row.contains("Tools: file read: 12ms, bash: 12.4s (slow)"));
+        let x = row.chars().position(|c| c == 'f').unwrap() as u16;
+        assert_eq!(buffer.get(x, y).fg, Color::DarkGray);
+        let x = row.chars().position(|c| c == 'b').unwrap() as u16;
+        assert_eq!(buffer.get(x, y).fg, Color::Red);
+    }
+
+    #[test]
+    fn test_themes_color_the_screen() {
+        let dark = buffer(&app(), 120, 30);
+        let light = app().with_theme(Arc::new(Mutex::new(Theme::light())));
+        let light = buffer(&light, 120, 30);
+
+        // "Model: " starts the status bar, and "You: " the first message
+        assert_eq!(dark.get(1, 1).fg, Color::Gray);
+        assert_eq!(light.get(1, 1).fg, Color::Black);
+        assert_eq!(dark.get(1, 5).symbol, "Y");
+        assert_eq!(dark.get(1, 5).fg, Color::Cyan);
+        assert_eq!(light.get(1, 5).fg, Color::Blue);
+    }
+
+    #[test]
+    fn test_busy_title_shows_the_activity() {
+        let keymap = Keymap::default();
+        assert_eq!(
+            busy_title(Some("⠙ running bash… 12s"), &keymap),
+            "⠙ running bash… 12s (esc to interrupt)"
+        );
+        assert_eq!(busy_title(None, &keymap), "Input (esc to interrupt)");
+    }
+
+    // The text of the conversation pane, rows joined without their borders
+    // and the blanks that wide characters cover
+    fn conversation(app: &SentinelApp, width: u16, height: u16) -> String {
+        let buffer = buffer(app, width, height);
+        let mut text = String::new();
+        for y in 0..height {
+            let mut x = 1;
+            while x + 1 < width {
+                let symbol = buffer.get(x, y).symbol.as_str();
+                if symbol == "│" {
+                    break;
+                }
+                text.push_str(symbol);
+                x += u16::try_from(wrap::width(symbol).max(1)).unwrap_or(1);
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    #[test]
+    fn test_wide_characters_wrap_without_being_lost() {
+        let url = "https://example.com/a/very/long/path/that/does/not/fit";
+        let reply = format!("你好世界！这是一个很长的回答 🎉🚀 done. See {}", url);
+        let app = app().with_sessions(Sessions::new(vec![
+            UiMessage::user("Say hi in Chinese 🇨🇳 please".to_string()),
+            UiMessage::assistant(reply.clone(), 10, 5),
+        ]));
+        let text = conversation(&app, 40, 30);
+        // Every word makes it onto the screen, however many rows it takes
+        let joined: String = text.split_whitespace().collect();
+        let expected: String = format!("Assistant: {}", reply).split_whitespace().collect();
+        assert!(joined.contains(&expected), "{}", text);
+        assert!(joined.contains("You:SayhiinChinese🇨🇳please"));
+        // The URL fills its row and goes on on the next
+        let rows: Vec<&str> = text.lines().collect();
+        assert!(rows.contains(&"done. See https://example.com/a/very/l"));
+        assert!(rows.contains(&"ong/path/that/does/not/fit            "));
+    }
+
+    #[test]
+    fn test_truncate_line() {
+        let line = Line::from(vec![Span::raw("Model: "), Span::raw("llama3.2")]);
+        let cut = truncate_line(line.clone(), 10, &Theme::default());
+        let text: String = cut.spans.iter().map(|span| span.content.as_ref()).collect();
+        assert_eq!(text, "Model: ll…");
+        assert_eq!(truncate_line(line, 15, &Theme::default()).spans.len(), 2);
+
+        // Wide characters are cut by the columns they take
+        let cut = truncate_line(Line::from("模型: 世界"), 6, &Theme::default());
+        let text: String = cut.spans.iter().map(|span| span.content.as_ref()).collect();
+        assert_eq!(text, "模型:…");
+    }
+
+    #[test]
+    fn test_centered_offset() {
+        // Ten single-line messages in a five row view
+        let heights = vec![1; 10];
+        assert_eq!(centered_offset(&heights, 0, 5), 0);
+        assert_eq!(centered_offset(&heights, 6, 5), 4);
+
+        // Taller messages above the selection take more room
+        let heights = vec![1, 3, 1, 1];
+        assert_eq!(centered_offset(&heights, 3, 6), 2);
+    }
+
+    #[test]
+    fn test_centered_rect() {
+        let area = Rect::new(0, 0, 100, 50);
+        assert_eq!(centered_rect(80, 70, area), Rect::new(10, 7, 80, 35));
+    }
+}
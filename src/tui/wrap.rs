@@ -0,0 +1,244 @@
+//! Wrapping the lines of the conversation to the width of its pane.
+//!
+//! Widths are display widths, so CJK characters and most emoji take two
+//! columns. Lines break between words where they can; a word wider than the
+//! pane, such as a URL or base64, is broken where it reaches the edge.
+//! Wrapped lines keep the indentation of the line they continue, so indented
+//! lines such as the tools of a reply stay indented.
+
+use ratatui::style::Style;
+use ratatui::text::{Line, Span};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+// What a tab is shown as, since terminals differ on how wide one is
+const TAB: &str = "    ";
+
+/// The number of columns `text` takes on a terminal
+pub fn width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+/// `line` split at its newlines and wrapped to `width` columns
+pub fn wrap(line: Line<'_>, width: usize) -> Vec<Line<'static>> {
+    let mut wrapped = Vec::new();
+    for segments in split_lines(line) {
+        wrap_segments(segments, width.max(1), &mut wrapped);
+    }
+    wrapped
+}
+
+// The styled text of `line`, one list for each line of its content
+fn split_lines(line: Line<'_>) -> Vec<Vec<(String, Style)>> {
+    let mut lines = vec![Vec::new()];
+    for span in line.spans {
+        for (index, part) in span.content.split('\n').enumerate() {
+            if index > 0 {
+                lines.push(Vec::new());
+            }
+            let part = part.trim_end_matches('\r').replace('\t', TAB);
+            if !part.is_empty() {
+                if let Some(last) = lines.last_mut() {
+                    last.push((part, span.style));
+                }
+            }
+        }
+    }
+    lines
+}
+
+// Wrap the styled text of one line of content into `wrapped`
+fn wrap_segments(segments: Vec<(String, Style)>, width: usize, wrapped: &mut Vec<Line<'static>>) {
+    // Words and the runs of whitespace between them; a word may change style
+    let mut pieces: Vec<(bool, Vec<(String, Style)>)> = Vec::new();
+    for (text, style) in segments {
+        let mut rest = text.as_str();
+        while let Some(first) = rest.chars().next() {
+            let space = first.is_whitespace();
+            let end = rest
+                .find(|c: char| c.is_whitespace() != space)
+                .unwrap_or(rest.len());
+            match pieces.last_mut() {
+                Some((last_space, parts)) if *last_space == space => {
+                    parts.push((rest[..end].to_string(), style))
+                }
+                _ => pieces.push((space, vec![(rest[..end].to_string(), style)])),
+            }
+            rest = &rest[end..];
+        }
+    }
+
+    // Continuation lines are indented like the first, if that leaves room
+    let indent: String = match pieces.first() {
+        Some((true, parts)) => parts.iter().map(|(text, _)| text.as_str()).collect(),
+        _ => String::new(),
+    };
+    let indent = if self::width(&indent) <= width / 2 {
+        indent
+    } else {
+        String::new()
+    };
+
+    let mut builder = LineBuilder::new(width, indent);
+    // Whitespace waits for the word after it, and is dropped where a line breaks
+    let mut pending = Vec::new();
+    for (space, parts) in pieces {
+        if space {
+            pending = parts;
+            continue;
+        }
+        let spaces: usize = pending.iter().map(|(text, _)| self::width(text)).sum();
+        let word: usize = parts.iter().map(|(text, _)| self::width(text)).sum();
+        // A word that fits on a line of its own starts one; a longer one
+        // goes on from here and breaks at the edge
+        if builder.has_text() && !builder.fits(spaces + word) && builder.fits_new_line(word) {
+            builder.break_line();
+            pending.clear();
+        }
+        for (text, style) in pending.drain(..) {
+            builder.push(&text, style, false);
+        }
+        for (text, style) in parts {
+            builder.push(&text, style, true);
+        }
+    }
+    wrapped.extend(builder.finish());
+}
+
+// Lays out the spans of one line of content over as many rows as it takes
+struct LineBuilder {
+    width: usize,
+    indent: String,
+    lines: Vec<Line<'static>>,
+    spans: Vec<Span<'static>>,
+    // Columns taken on the current row, and whether any are more than indentation
+    used: usize,
+    text: bool,
+}
+
+impl LineBuilder {
+    fn new(width: usize, indent: String) -> Self {
+        Self {
+            width,
+            indent,
+            lines: Vec::new(),
+            spans: Vec::new(),
+            used: 0,
+            text: false,
+        }
+    }
+
+    fn fits(&self, width: usize) -> bool {
+        self.used + width <= self.width
+    }
+
+    fn fits_new_line(&self, width: usize) -> bool {
+        self::width(&self.indent) + width <= self.width
+    }
+
+    fn has_text(&self) -> bool {
+        self.text
+    }
+
+    // Push `text`, starting a new row whenever the current one is full
+    fn push(&mut self, text: &str, style: Style, is_text: bool) {
+        let mut run = String::new();
+        let mut run_width = 0;
+        for c in text.chars() {
+            let width = c.width().unwrap_or(0);
+            if self.used + run_width + width > self.width && (self.text || !run.is_empty()) {
+                if !run.is_empty() {
+                    self.push_run(std::mem::take(&mut run), run_width, style, is_text);
+                    run_width = 0;
+                }
+                self.break_line();
+            }
+            run_width += width;
+            run.push(c);
+        }
+        if !run.is_empty() {
+            self.push_run(run, run_width, style, is_text);
+        }
+    }
+
+    fn push_run(&mut self, run: String, width: usize, style: Style, is_text: bool) {
+        self.used += width;
+        self.text |= is_text;
+        self.spans.push(Span::styled(run, style));
+    }
+
+    fn break_line(&mut self) {
+        self.lines.push(Line::from(std::mem::take(&mut self.spans)));
+        self.used = 0;
+        self.text = false;
+        if !self.indent.is_empty() {
+            let indent = self.indent.clone();
+            let width = self::width(&indent);
+            self.push_run(indent, width, Style::default(), false);
+        }
+    }
+
+    fn finish(mut self) -> Vec<Line<'static>> {
+        self.lines.push(Line::from(self.spans));
+        self.lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::style::Color;
+
+    // The text of each row
+    fn rows(lines: &[Line]) -> Vec<String> {
+        lines
+            .iter()
+            .map(|line| {
+                line.spans
+                    .iter()
+                    .map(|span| span.content.as_ref())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_words_wrap_at_display_width() {
+        let wrapped = wrap(Line::from("You: hello wide 世界世界 world"), 10);
+        assert_eq!(rows(&wrapped), ["You: hello", "wide", "世界世界", "world"]);
+        for row in rows(&wrapped) {
+            assert!(width(&row) <= 10);
+        }
+
+        // Newlines start rows of their own, blank ones included
+        let wrapped = wrap(Line::from("one\n\ntwo"), 10);
+        assert_eq!(rows(&wrapped), ["one", "", "two"]);
+    }
+
+    #[test]
+    fn test_long_tokens_break_at_the_edge() {
+        let url = "https://example.com/aaaaaaaaaaaaaaaa";
+        let wrapped = rows(&wrap(Line::from(format!("See {}", url)), 12));
+        assert_eq!(wrapped[0], "See https://");
+        assert_eq!(wrapped.concat(), format!("See {}", url));
+        assert!(wrapped.iter().all(|row| width(row) <= 12));
+
+        // A wide character never straddles the edge
+        let wrapped = wrap(Line::from("ab🎉🎉🎉"), 5);
+        assert_eq!(rows(&wrapped), ["ab🎉", "🎉🎉"]);
+    }
+
+    #[test]
+    fn test_continuation_lines_keep_the_indentation_and_styles() {
+        let dim = Style::default().fg(Color::DarkGray);
+        let line = Line::from(vec![
+            Span::raw("  "),
+            Span::styled("Tools: bash: 1.2s, file read: 3ms", dim),
+        ]);
+        let wrapped = wrap(line, 16);
+        assert_eq!(
+            rows(&wrapped),
+            ["  Tools: bash:", "  1.2s, file", "  read: 3ms"]
+        );
+        assert_eq!(wrapped[1].spans[1].style, dim);
+    }
+}
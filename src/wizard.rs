@@ -0,0 +1,795 @@
+//! The setup run the first time sentinel starts without a config file.
+//!
+//! It looks for an Ollama server, picks or pulls a model, asks which tools
+//! the model may use and writes the answers to the config file, then sends
+//! a test prompt to show everything works. Every question takes `b` to go
+//! back a step, `s` to skip it and `q` to stop without writing anything.
+//! Questions are plain lines on the terminal, so it runs the same before
+//! the CLI and the TUI. The Ollama checks go through a [`Probe`], and the
+//! questions through a [`Console`], so each step can be tested alone.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use toml::{Table, Value};
+
+use crate::config::DEFAULT_MODEL;
+use crate::llm::availability::{ModelRegistry, PullProgress};
+use crate::llm::endpoint::Endpoint;
+use crate::llm::ollama::{LlmClient, OllamaClient};
+use crate::tools::registry;
+use crate::Message;
+
+/// The model offered to pull when none is installed: small, and able to call tools
+pub const RECOMMENDED_MODEL: &str = "llama3.2:1b";
+
+/// Sent once the config is written, to show that the model answers
+pub const TEST_PROMPT: &str = "Reply with one short sentence saying you are ready.";
+
+/// Whether to run the setup: there is no config file, neither the user's
+/// at `global` nor a project's, and a person is there to answer
+pub fn needed(global: Option<&Path>, project: Option<&Path>, interactive: bool) -> bool {
+    interactive && global.is_some_and(|path| !path.exists()) && project.is_none()
+}
+
+/// What the setup asks an Ollama server
+#[async_trait]
+pub trait Probe: Send + Sync {
+    /// The models installed on the server, or an error if it can't be reached
+    async fn models(&self, endpoint: &Endpoint) -> Result<Vec<String>>;
+
+    /// Download `model` to the server, reporting progress as it goes
+    async fn pull(
+        &self,
+        endpoint: &Endpoint,
+        model: &str,
+        on_progress: &mut (dyn FnMut(PullProgress) + Send),
+    ) -> Result<()>;
+
+    /// `model`'s reply to `prompt`
+    async fn ask(&self, endpoint: &Endpoint, model: &str, prompt: &str) -> Result<String>;
+}
+
+/// Asks a real Ollama server
+pub struct OllamaProbe;
+
+fn client(endpoint: &Endpoint, model: &str) -> OllamaClient {
+    OllamaClient::new()
+        .with_endpoint(endpoint)
+        .with_model(model)
+}
+
+#[async_trait]
+impl Probe for OllamaProbe {
+    async fn models(&self, endpoint: &Endpoint) -> Result<Vec<String>> {
+        client(endpoint, DEFAULT_MODEL).installed_models().await
+    }
+
+    async fn pull(
+        &self,
+        endpoint: &Endpoint,
+        model: &str,
+        on_progress: &mut (dyn FnMut(PullProgress) + Send),
+    ) -> Result<()> {
+        client(endpoint, model).pull(model, on_progress).await
+    }
+
+    async fn ask(&self, endpoint: &Endpoint, model: &str, prompt: &str) -> Result<String> {
+        let (reply, _, _) = client(endpoint, model)
+            .generate_response(&[Message::user(prompt)])
+            .await?;
+        Ok(reply)
+    }
+}
+
+/// Where the setup shows its questions and reads the answers
+pub trait Console {
+    fn say(&mut self, text: &str);
+
+    /// The answer to `question`, trimmed, or None once input ends
+    fn ask(&mut self, question: &str) -> Option<String>;
+}
+
+/// Where a step leads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Nav {
+    Next,
+    Back,
+    Quit,
+}
+
+// An answer, with the words that move between steps taken out
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Answer {
+    Text(String),
+    Back,
+    Skip,
+    Quit,
+}
+
+fn answer(console: &mut dyn Console, question: &str) -> Answer {
+    match console.ask(question) {
+        None => Answer::Quit,
+        Some(text) => match text.to_lowercase().as_str() {
+            "b" | "back" => Answer::Back,
+            "s" | "skip" => Answer::Skip,
+            "q" | "quit" => Answer::Quit,
+            _ => Answer::Text(text),
+        },
+    }
+}
+
+/// The answers so far
+#[derive(Debug, Clone, PartialEq)]
+pub struct Setup {
+    pub endpoint: Endpoint,
+    /// Whether `endpoint` was typed in rather than the default, so it is written
+    pub custom_endpoint: bool,
+    /// Whether a server answered at `endpoint`
+    pub reachable: bool,
+    pub model: Option<String>,
+    /// Tools the model may use, by their config names; all of them if None
+    pub tools: Option<Vec<String>>,
+    /// Whether the model may run shell commands
+    pub bash: bool,
+}
+
+impl Setup {
+    /// A setup that starts from the server at `endpoint`
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self {
+            endpoint,
+            custom_endpoint: false,
+            reachable: false,
+            model: None,
+            tools: None,
+            bash: true,
+        }
+    }
+
+    /// The `enabled` list of the `[tools]` table, if not every tool is
+    pub fn enabled_tools(&self) -> Option<Vec<String>> {
+        if self.bash && self.tools.is_none() {
+            return None;
+        }
+        let mut tools = self.tools.clone().unwrap_or_else(choosable_tools);
+        if self.bash {
+            tools.push("bash".to_string());
+        }
+        Some(tools)
+    }
+
+    /// The config file the answers make
+    pub fn to_toml(&self) -> String {
+        let mut table = Table::new();
+        if let Some(model) = &self.model {
+            let mut section = Table::new();
+            section.insert("name".to_string(), Value::String(model.clone()));
+            table.insert("model".to_string(), Value::Table(section));
+        }
+        if let Some(enabled) = self.enabled_tools() {
+            let names = enabled.into_iter().map(Value::String).collect();
+            let mut section = Table::new();
+            section.insert("enabled".to_string(), Value::Array(names));
+            table.insert("tools".to_string(), Value::Table(section));
+        }
+        if self.custom_endpoint {
+            let mut server = Table::new();
+            server.insert(
+                "host".to_string(),
+                Value::String(format!("{}{}", self.endpoint.host, self.endpoint.path)),
+            );
+            server.insert(
+                "port".to_string(),
+                Value::Integer(self.endpoint.port.into()),
+            );
+            table.insert(
+                "endpoints".to_string(),
+                Value::Array(vec![Value::Table(server)]),
+            );
+        }
+        format!(
+            "# Written by the first-run setup; the README lists every setting\n\n{}",
+            toml::to_string(&table).unwrap_or_default()
+        )
+    }
+}
+
+// The built-in tools asked about one by one, by config name; bash has its own question
+fn choosable_tools() -> Vec<String> {
+    registry::builtin_definitions()
+        .iter()
+        .map(|tool| registry::config_name(&tool.name).to_string())
+        .filter(|name| name != "bash")
+        .collect()
+}
+
+/// The server from "host", "host:port" or a URL such as "https://host/ollama"
+pub fn parse_address(text: &str) -> Option<Endpoint> {
+    Endpoint::parse(text, None).ok()
+}
+
+/// Find a server: the default one, or another the user names
+pub async fn server(console: &mut dyn Console, probe: &dyn Probe, setup: &mut Setup) -> Nav {
+    loop {
+        let address = setup.endpoint.address();
+        let question = match probe.models(&setup.endpoint).await {
+            Ok(models) => {
+                setup.reachable = true;
+                console.say(&format!(
+                    "Ollama is running at {} with {} installed.",
+                    address,
+                    count(models.len(), "model", "models")
+                ));
+                "Press Enter to use it, or type another host[:port]: "
+            }
+            Err(_) => {
+                setup.reachable = false;
+                console.say(&format!(
+                    "No Ollama server answers at {}. Install Ollama from https://ollama.com and start it with `ollama serve`.",
+                    address
+                ));
+                "Press Enter to check again, type another host[:port], or s to go on without a server: "
+            }
+        };
+        match answer(console, question) {
+            Answer::Text(text) if text.is_empty() && setup.reachable => return Nav::Next,
+            Answer::Text(text) if text.is_empty() => {}
+            Answer::Text(text) => match parse_address(&text) {
+                Some(endpoint) => {
+                    setup.endpoint = endpoint;
+                    setup.custom_endpoint = true;
+                }
+                None => console.say(&format!(
+                    "'{}' is not a host[:port], e.g. 192.168.1.20:11434",
+                    text
+                )),
+            },
+            Answer::Skip => return Nav::Next,
+            Answer::Back => return Nav::Back,
+            Answer::Quit => return Nav::Quit,
+        }
+    }
+}
+
+/// Pick an installed model, or pull one
+pub async fn model(console: &mut dyn Console, probe: &dyn Probe, setup: &mut Setup) -> Nav {
+    if !setup.reachable {
+        console.say("Without a server the installed models can't be listed.");
+        return match answer(console, &format!("Model to use [{}]: ", DEFAULT_MODEL)) {
+            Answer::Text(name) => {
+                setup.model = (!name.is_empty()).then_some(name);
+                Nav::Next
+            }
+            Answer::Skip => Nav::Next,
+            Answer::Back => Nav::Back,
+            Answer::Quit => Nav::Quit,
+        };
+    }
+    loop {
+        let models = probe.models(&setup.endpoint).await.unwrap_or_default();
+        if models.is_empty() {
+            console.say("No models are installed yet.");
+        } else {
+            console.say("Installed models:");
+            for (index, name) in models.iter().enumerate() {
+                console.say(&format!("  {}) {}", index + 1, name));
+            }
+        }
+        console.say(&format!(
+            "  p) pull {}, a small model that can use tools",
+            RECOMMENDED_MODEL
+        ));
+        let question = if models.is_empty() {
+            "Type p or the name of a model to pull [p]: "
+        } else {
+            "Pick a model by number, p to pull the recommended one, or type a name to pull [1]: "
+        };
+        let choice = match answer(console, question) {
+            Answer::Text(text) => text,
+            Answer::Skip => return Nav::Next,
+            Answer::Back => return Nav::Back,
+            Answer::Quit => return Nav::Quit,
+        };
+        let picked = match choice.parse::<usize>() {
+            Ok(number) if (1..=models.len()).contains(&number) => Some(models[number - 1].clone()),
+            Ok(_) => {
+                console.say(&format!("There is no model {}", choice));
+                continue;
+            }
+            Err(_) if choice.is_empty() => models.first().cloned(),
+            Err(_) => models.iter().find(|name| **name == choice).cloned(),
+        };
+        if let Some(model) = picked {
+            setup.model = Some(model);
+            return Nav::Next;
+        }
+        let name = match choice.as_str() {
+            "" | "p" | "P" => RECOMMENDED_MODEL,
+            name => name,
+        };
+        if pull(console, probe, &setup.endpoint, name).await {
+            setup.model = Some(name.to_string());
+            return Nav::Next;
+        }
+    }
+}
+
+// Pull `model`, showing how far along it is; whether it was pulled
+async fn pull(
+    console: &mut dyn Console,
+    probe: &dyn Probe,
+    endpoint: &Endpoint,
+    model: &str,
+) -> bool {
+    console.say(&format!("Pulling {}...", model));
+    let mut shown = Vec::new();
+    let mut last = (String::new(), None);
+    let result = probe
+        .pull(endpoint, model, &mut |progress: PullProgress| {
+            // One line per status, and per tenth of a download
+            let tenth = progress.percent().map(|percent| (percent / 10.0) as u64);
+            if (progress.status.clone(), tenth) == last {
+                return;
+            }
+            shown.push(match progress.percent() {
+                Some(percent) => format!("  {} {:.0}%", progress.status, percent),
+                None => format!("  {}", progress.status),
+            });
+            last = (progress.status, tenth);
+        })
+        .await;
+    for line in shown {
+        console.say(&line);
+    }
+    match result {
+        Ok(()) => {
+            console.say(&format!("Pulled {}", model));
+            true
+        }
+        Err(e) => {
+            console.say(&format!("Couldn't pull {}: {:#}", model, e));
+            false
+        }
+    }
+}
+
+/// Choose which of the built-in tools, besides bash, the model may use
+pub fn tools(console: &mut dyn Console, setup: &mut Setup) -> Nav {
+    let names = choosable_tools();
+    console.say("Tools the model may use:");
+    for (index, name) in names.iter().enumerate() {
+        let tool = registry::builtin(name);
+        let line = tool
+            .as_ref()
+            .and_then(|tool| tool.description.lines().next())
+            .unwrap_or_default();
+        console.say(&format!("  {}) {}  - {}", index + 1, name, line.trim()));
+    }
+    loop {
+        let text = match answer(
+            console,
+            "Numbers of tools to turn off, e.g. 2 4, or Enter to keep them all: ",
+        ) {
+            Answer::Text(text) => text,
+            Answer::Skip => return Nav::Next,
+            Answer::Back => return Nav::Back,
+            Answer::Quit => return Nav::Quit,
+        };
+        let off: Option<Vec<usize>> = text
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|part| !part.is_empty())
+            .map(|part| part.parse().ok().filter(|n| (1..=names.len()).contains(n)))
+            .collect();
+        match off {
+            Some(off) if off.is_empty() => setup.tools = None,
+            Some(off) => {
+                let kept = names
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| !off.contains(&(index + 1)))
+                    .map(|(_, name)| name.clone());
+                setup.tools = Some(kept.collect());
+            }
+            None => {
+                console.say(&format!(
+                    "Type numbers from 1 to {}, separated by spaces",
+                    names.len()
+                ));
+                continue;
+            }
+        }
+        return Nav::Next;
+    }
+}
+
+/// Ask whether the model may run shell commands at all
+pub fn bash(console: &mut dyn Console, setup: &mut Setup) -> Nav {
+    loop {
+        match answer(
+            console,
+            "Let the model run shell commands with the bash tool? [Y/n]: ",
+        ) {
+            Answer::Text(text) => match text.to_lowercase().as_str() {
+                "" | "y" | "yes" => setup.bash = true,
+                "n" | "no" => setup.bash = false,
+                _ => continue,
+            },
+            Answer::Skip => {}
+            Answer::Back => return Nav::Back,
+            Answer::Quit => return Nav::Quit,
+        }
+        return Nav::Next;
+    }
+}
+
+/// Show the config the answers make and write it to `path` if the user agrees
+pub fn save(console: &mut dyn Console, setup: &Setup, path: &Path) -> Result<Nav> {
+    console.say(&format!(
+        "This config will be written to {}:\n\n{}",
+        path.display(),
+        setup.to_toml()
+    ));
+    loop {
+        match answer(console, "Write it? [Y/n]: ") {
+            Answer::Text(text) => match text.to_lowercase().as_str() {
+                "" | "y" | "yes" => break,
+                "n" | "no" => return Ok(Nav::Quit),
+                _ => continue,
+            },
+            Answer::Back => return Ok(Nav::Back),
+            Answer::Skip | Answer::Quit => return Ok(Nav::Quit),
+        }
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(path, setup.to_toml())
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    console.say(&format!("Wrote {}", path.display()));
+    Ok(Nav::Next)
+}
+
+/// Send the test prompt, saying whether the model answered
+pub async fn test_prompt(console: &mut dyn Console, probe: &dyn Probe, setup: &Setup) -> bool {
+    let model = setup.model.as_deref().unwrap_or(DEFAULT_MODEL);
+    if !setup.reachable {
+        console.say("Start Ollama, then run sentinel again to chat.");
+        return false;
+    }
+    console.say(&format!("Sending a test prompt to {}...", model));
+    match probe.ask(&setup.endpoint, model, TEST_PROMPT).await {
+        Ok(reply) => {
+            console.say(&format!("{}: {}", model, reply.trim()));
+            console.say("Everything works.");
+            true
+        }
+        Err(e) => {
+            console.say(&format!("The test prompt failed: {:#}", e));
+            false
+        }
+    }
+}
+
+// The steps in the order they are asked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Server,
+    Model,
+    Tools,
+    Bash,
+    Save,
+}
+
+const STEPS: [Step; 5] = [
+    Step::Server,
+    Step::Model,
+    Step::Tools,
+    Step::Bash,
+    Step::Save,
+];
+
+/// Ask every question, write the config to `path` and try it; whether the
+/// config was written
+pub async fn run(
+    console: &mut dyn Console,
+    probe: &dyn Probe,
+    path: &Path,
+    mut setup: Setup,
+) -> Result<bool> {
+    console.say(
+        "Welcome to Sentinel! There is no config file yet, so a few questions set one up.\n\
+         Answer b to go back, s to skip a question or q to stop; --no-wizard skips this setup.",
+    );
+    let mut index = 0;
+    while let Some(step) = STEPS.get(index) {
+        let nav = match step {
+            Step::Server => server(console, probe, &mut setup).await,
+            Step::Model => model(console, probe, &mut setup).await,
+            Step::Tools => tools(console, &mut setup),
+            Step::Bash => bash(console, &mut setup),
+            Step::Save => save(console, &setup, path)?,
+        };
+        match nav {
+            Nav::Next => index += 1,
+            Nav::Back => index = index.saturating_sub(1),
+            Nav::Quit => {
+                console.say(&format!(
+                    "Setup stopped and nothing was written; it runs again until {} exists.",
+                    path.display()
+                ));
+                return Ok(false);
+            }
+        }
+    }
+    test_prompt(console, probe, &setup).await;
+    Ok(true)
+}
+
+fn count(n: usize, one: &str, many: &str) -> String {
+    format!("{} {}", n, if n == 1 { one } else { many })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use anyhow::anyhow;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+    use tempfile::tempdir;
+
+    // A server that answers at some addresses, with these models installed
+    #[derive(Default)]
+    struct FakeProbe {
+        addresses: Vec<String>,
+        models: Mutex<Vec<String>>,
+        pullable: Vec<String>,
+    }
+
+    impl FakeProbe {
+        fn at(address: &str, models: &[&str]) -> Self {
+            Self {
+                addresses: vec![address.to_string()],
+                models: Mutex::new(models.iter().map(|m| m.to_string()).collect()),
+                pullable: vec![RECOMMENDED_MODEL.to_string()],
+            }
+        }
+
+        fn check(&self, endpoint: &Endpoint) -> Result<()> {
+            if self.addresses.contains(&endpoint.address()) {
+                Ok(())
+            } else {
+                Err(anyhow!("connection refused"))
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Probe for FakeProbe {
+        async fn models(&self, endpoint: &Endpoint) -> Result<Vec<String>> {
+            self.check(endpoint)?;
+            Ok(self.models.lock().unwrap().clone())
+        }
+
+        async fn pull(
+            &self,
+            endpoint: &Endpoint,
+            model: &str,
+            on_progress: &mut (dyn FnMut(PullProgress) + Send),
+        ) -> Result<()> {
+            self.check(endpoint)?;
+            if !self.pullable.iter().any(|name| name == model) {
+                return Err(anyhow!("file does not exist"));
+            }
+            for completed in [0, 1, 2, 5, 10] {
+                on_progress(PullProgress {
+                    status: "pulling layer".to_string(),
+                    completed: Some(completed),
+                    total: Some(10),
+                });
+            }
+            self.models.lock().unwrap().push(model.to_string());
+            Ok(())
+        }
+
+        async fn ask(&self, endpoint: &Endpoint, model: &str, _prompt: &str) -> Result<String> {
+            self.check(endpoint)?;
+            Ok(format!("{} is ready.", model))
+        }
+    }
+
+    // Answers given in order, and everything said
+    #[derive(Default)]
+    struct Script {
+        answers: VecDeque<&'static str>,
+        said: Vec<String>,
+    }
+
+    impl Script {
+        fn new(answers: &[&'static str]) -> Self {
+            Self {
+                answers: answers.iter().copied().collect(),
+                said: Vec::new(),
+            }
+        }
+
+        fn saw(&self, text: &str) -> bool {
+            self.said.iter().any(|line| line.contains(text))
+        }
+    }
+
+    impl Console for Script {
+        fn say(&mut self, text: &str) {
+            self.said.push(text.to_string());
+        }
+
+        fn ask(&mut self, question: &str) -> Option<String> {
+            self.said.push(question.to_string());
+            self.answers.pop_front().map(str::to_string)
+        }
+    }
+
+    fn local() -> Setup {
+        Setup::new(Endpoint::new("http://localhost", 11434))
+    }
+
+    #[test]
+    fn test_runs_only_without_any_config_file() {
+        let dir = tempdir().unwrap();
+        let missing = dir.path().join("config.toml");
+        assert!(needed(Some(&missing), None, true));
+        assert!(!needed(Some(&missing), None, false));
+        assert!(!needed(
+            Some(&missing),
+            Some(Path::new(".sentinel.toml")),
+            true
+        ));
+        assert!(!needed(None, None, true));
+        fs::write(&missing, "").unwrap();
+        assert!(!needed(Some(&missing), None, true));
+    }
+
+    #[test]
+    fn test_addresses() {
+        let endpoint = parse_address("192.168.1.20:11500").unwrap();
+        assert_eq!(endpoint.address(), "http://192.168.1.20:11500");
+        let endpoint = parse_address("https://gpu-box.lan/").unwrap();
+        assert_eq!(endpoint.host, "https://gpu-box.lan");
+        assert!(parse_address("gpu box").is_none());
+        assert!(parse_address("host:port").is_none());
+        assert!(parse_address("ftp://host").is_none());
+        assert!(parse_address("").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_another_server_is_tried_when_the_default_is_down() {
+        let probe = FakeProbe::at("http://10.0.0.5:11500", &["qwen2.5:7b"]);
+        let mut console = Script::new(&["", "nonsense here", "10.0.0.5:11500", ""]);
+        let mut setup = local();
+
+        assert_eq!(server(&mut console, &probe, &mut setup).await, Nav::Next);
+        assert!(setup.reachable && setup.custom_endpoint);
+        assert_eq!(setup.endpoint.address(), "http://10.0.0.5:11500");
+        assert!(console.saw("No Ollama server answers at http://localhost:11434"));
+        assert!(console.saw("'nonsense here' is not a host[:port]"));
+        assert!(console.saw("with 1 model installed"));
+
+        // Skipping goes on without a server, and asks for a model by name
+        let mut console = Script::new(&["s", "mistral"]);
+        let mut setup = local();
+        assert_eq!(server(&mut console, &probe, &mut setup).await, Nav::Next);
+        assert!(!setup.reachable);
+        assert_eq!(model(&mut console, &probe, &mut setup).await, Nav::Next);
+        assert_eq!(setup.model.as_deref(), Some("mistral"));
+    }
+
+    #[tokio::test]
+    async fn test_models_are_picked_or_pulled_with_progress() {
+        let probe = FakeProbe::at("http://localhost:11434", &["llama3.2:latest", "qwen2.5:7b"]);
+        let mut setup = Setup {
+            reachable: true,
+            ..local()
+        };
+        let mut console = Script::new(&["7", "2"]);
+        assert_eq!(model(&mut console, &probe, &mut setup).await, Nav::Next);
+        assert_eq!(setup.model.as_deref(), Some("qwen2.5:7b"));
+        assert!(console.saw("There is no model 7"));
+
+        // A failed pull is reported and the question asked again
+        let mut console = Script::new(&["phi-unknown", "p"]);
+        assert_eq!(model(&mut console, &probe, &mut setup).await, Nav::Next);
+        assert_eq!(setup.model.as_deref(), Some(RECOMMENDED_MODEL));
+        assert!(console.saw("Couldn't pull phi-unknown: file does not exist"));
+        assert!(console.saw("pulling layer 50%"));
+        assert!(console.saw(&format!("Pulled {}", RECOMMENDED_MODEL)));
+        assert!(console.saw("pulling layer 100%"));
+
+        let mut console = Script::new(&["b"]);
+        assert_eq!(model(&mut console, &probe, &mut setup).await, Nav::Back);
+    }
+
+    #[test]
+    fn test_tools_and_bash_make_the_enabled_list() {
+        let names = choosable_tools();
+        assert!(names.contains(&"file".to_string()));
+        assert!(names.contains(&"DDGSearcher".to_string()));
+        assert!(!names.contains(&"bash".to_string()));
+
+        let mut setup = local();
+        let mut console = Script::new(&["", "n"]);
+        assert_eq!(tools(&mut console, &mut setup), Nav::Next);
+        assert_eq!(setup.enabled_tools(), None);
+        assert_eq!(bash(&mut console, &mut setup), Nav::Next);
+        assert_eq!(setup.enabled_tools(), Some(names.clone()));
+
+        let mut console = Script::new(&["1, 99", "1 2", "yes"]);
+        assert_eq!(tools(&mut console, &mut setup), Nav::Next);
+        assert!(console.saw("Type numbers from 1 to"));
+        assert_eq!(bash(&mut console, &mut setup), Nav::Next);
+        let enabled = setup.enabled_tools().unwrap();
+        assert!(!enabled.contains(&names[0]) && !enabled.contains(&names[1]));
+        assert_eq!(enabled.last().map(String::as_str), Some("bash"));
+    }
+
+    #[tokio::test]
+    async fn test_the_answers_are_written_and_tried() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("sentinel").join("config.toml");
+        let probe = FakeProbe::at("http://localhost:11434", &["llama3.2:latest"]);
+        // Go back from the model to the server once, then turn bash off
+        let mut console = Script::new(&["", "b", "", "1", "", "n", ""]);
+
+        assert!(run(&mut console, &probe, &path, local()).await?);
+        let config = Config::load_from(&path)?;
+        assert_eq!(config.model.name.as_deref(), Some("llama3.2:latest"));
+        assert!(config.tools.allows("file") && !config.tools.allows("bash"));
+        assert!(config.endpoints.is_empty());
+        assert!(console.saw("llama3.2:latest: llama3.2:latest is ready."));
+        assert!(console.saw("Everything works."));
+        assert_eq!(
+            console
+                .said
+                .iter()
+                .filter(|line| line.starts_with("Ollama is running"))
+                .count(),
+            2
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stopping_writes_nothing() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("config.toml");
+        let probe = FakeProbe::at("http://localhost:11434", &["llama3.2:latest"]);
+
+        for answers in [&["", "1", "q"][..], &["", "1", "", "", "n"], &[""]] {
+            let mut console = Script::new(answers);
+            assert!(!run(&mut console, &probe, &path, local()).await?);
+            assert!(!path.exists());
+            assert!(console.saw("nothing was written"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_a_typed_server_is_written_with_the_model() -> Result<()> {
+        let setup = Setup {
+            model: Some("qwen2.5:7b".to_string()),
+            custom_endpoint: true,
+            ..Setup::new(Endpoint::new("http://10.0.0.5", 11500))
+        };
+        let dir = tempdir()?;
+        let path = dir.path().join("config.toml");
+        fs::write(&path, setup.to_toml())?;
+        let config = Config::load_from(&path)?;
+        assert!(config.warnings.is_empty());
+        assert_eq!(config.endpoints[0].address(), "http://10.0.0.5:11500");
+        assert_eq!(config.model.name(None), "qwen2.5:7b");
+        assert!(config.tools.enabled.is_none());
+        Ok(())
+    }
+}
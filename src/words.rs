@@ -0,0 +1,132 @@
+//! Counting the words of a reply, for those writing prose rather than code.
+//!
+//! Words are found by Unicode word segmentation, so punctuation and
+//! apostrophes inside words are handled as a reader would expect, and each
+//! Chinese ideograph or Japanese kana counts as a word of its own. Fenced code
+//! blocks are left out of the words and counted in lines instead, so a reply
+//! of mostly code doesn't claim a long reading time.
+
+use serde::{Deserialize, Serialize};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::truncate::Fences;
+
+/// Words an adult reads in a minute, for the reading time
+pub const WORDS_PER_MINUTE: usize = 230;
+
+/// The words and characters of a text, its code counted apart
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WordCount {
+    /// Words outside code blocks
+    pub words: usize,
+    /// Characters outside code blocks, leaving out whitespace
+    pub characters: usize,
+    /// Lines inside code blocks, leaving out the fences
+    pub code_lines: usize,
+}
+
+impl WordCount {
+    /// Count the words of `text`
+    pub fn of(text: &str) -> Self {
+        let mut count = Self::default();
+        let mut fences = Fences::default();
+        for line in text.split_inclusive('\n') {
+            let was_open = fences.opening().is_some();
+            fences.push(line);
+            let is_open = fences.opening().is_some();
+            if was_open && is_open {
+                count.code_lines += 1;
+            } else if !was_open && !is_open {
+                count.words += line.unicode_words().count();
+                count.characters += line.chars().filter(|c| !c.is_whitespace()).count();
+            }
+        }
+        count
+    }
+
+    /// Add the counts of another text, e.g. for a whole conversation
+    pub fn add(&mut self, other: WordCount) {
+        self.words += other.words;
+        self.characters += other.characters;
+        self.code_lines += other.code_lines;
+    }
+
+    /// Minutes it takes to read the words, rounded up; none for no words
+    pub fn reading_minutes(&self) -> usize {
+        self.words.div_ceil(WORDS_PER_MINUTE)
+    }
+}
+
+impl std::fmt::Display for WordCount {
+    /// e.g. "412 words, 2310 characters, ~2 min read, 14 lines of code"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} words, {} characters", self.words, self.characters)?;
+        if self.words > 0 {
+            write!(f, ", ~{} min read", self.reading_minutes())?;
+        }
+        match self.code_lines {
+            0 => Ok(()),
+            1 => write!(f, ", 1 line of code"),
+            lines => write!(f, ", {} lines of code", lines),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prose_and_code_are_counted_apart() {
+        let text = "Here's a well-known example:\n\n```rust\nfn main() {\n    println!(\"hi\");\n}\n```\n\nIt prints hi.\n";
+        let count = WordCount::of(text);
+        // "Here's", "a", "well", "known", "example", "It", "prints", "hi"
+        assert_eq!(count.words, 8);
+        assert_eq!(count.code_lines, 3);
+        assert_eq!(
+            count.characters,
+            "Here'sawell-knownexample:Itprintshi.".len()
+        );
+        assert_eq!(count.reading_minutes(), 1);
+        assert_eq!(
+            count.to_string(),
+            "8 words, 36 characters, ~1 min read, 3 lines of code"
+        );
+    }
+
+    #[test]
+    fn test_ideographs_are_words_of_their_own() {
+        // Each ideograph and kana is a word, with no spaces between them
+        let count = WordCount::of("東京は日本の首都です。Tokyo, Japan");
+        assert_eq!(count.words, 12);
+        assert_eq!(count.characters, 22);
+
+        // Korean separates words with spaces
+        assert_eq!(WordCount::of("안녕하세요 세계").words, 2);
+        // Numbers with separators stay whole
+        assert_eq!(WordCount::of("It costs 3.50 today").words, 4);
+    }
+
+    #[test]
+    fn test_empty_and_code_only_messages() {
+        assert_eq!(WordCount::of(""), WordCount::default());
+        assert_eq!(WordCount::of("").to_string(), "0 words, 0 characters");
+
+        let code = WordCount::of("```\nlet x = 1;\n```");
+        assert_eq!(code.words, 0);
+        assert_eq!(code.code_lines, 1);
+        assert_eq!(code.reading_minutes(), 0);
+        assert_eq!(code.to_string(), "0 words, 0 characters, 1 line of code");
+
+        // A block left open runs to the end of the text
+        assert_eq!(WordCount::of("Try:\n```\na b c\nd e\n").code_lines, 2);
+    }
+
+    #[test]
+    fn test_long_texts_take_minutes() {
+        let mut count = WordCount::of(&"word ".repeat(WORDS_PER_MINUTE + 1));
+        assert_eq!(count.reading_minutes(), 2);
+        count.add(WordCount::of("one more"));
+        assert_eq!(count.words, WORDS_PER_MINUTE + 3);
+    }
+}
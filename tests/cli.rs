@@ -0,0 +1,538 @@
+use assert_cmd::Command;
+use predicates::prelude::*;
+use sentinel::tools::journal::{self, Journal};
+use serde_json::Value;
+use std::fs;
+use tempfile::TempDir;
+
+// The binary, run in `home` with no config, history or server of the user's,
+// and nothing on stdin
+fn sentinel(home: &TempDir) -> Command {
+    let mut command = Command::cargo_bin("sentinel").unwrap();
+    command
+        .current_dir(home.path())
+        .env("HOME", home.path())
+        .env("XDG_CONFIG_HOME", home.path().join("config"))
+        .env("XDG_DATA_HOME", home.path().join("data"))
+        .env("NO_COLOR", "1")
+        .env("RUST_BACKTRACE", "0")
+        .env_remove("OLLAMA_HOST")
+        .env_remove("OLLAMA_PORT")
+        .env_remove("SENTINEL_FAKE_LLM")
+        .write_stdin("");
+    command
+}
+
+// The binary answering from the fake client instead of a server
+fn fake(home: &TempDir) -> Command {
+    failing(home, "1")
+}
+
+// The binary with a fake server that fails the way `mode` names
+fn failing(home: &TempDir, mode: &str) -> Command {
+    let mut command = sentinel(home);
+    command.env("SENTINEL_FAKE_LLM", mode);
+    command
+}
+
+#[test]
+fn test_ask_prints_the_response() {
+    let home = TempDir::new().unwrap();
+    // The answer is all there is on stdout, for scripts to capture
+    fake(&home)
+        .args(["ask", "hello", "there"])
+        .assert()
+        .success()
+        .stdout("You said: hello there\n")
+        .stderr(predicate::str::contains("[USER] hello there"))
+        .stderr(predicate::str::contains("[SESSION] 1 request"));
+}
+
+#[test]
+fn test_ask_exits_with_the_kind_of_failure() {
+    let home = TempDir::new().unwrap();
+    let cases = [
+        ("unreachable", 3, "Could not reach Ollama"),
+        (
+            "missing-model",
+            4,
+            "Model 'llama3.2:latest' is not installed",
+        ),
+        ("timeout", 5, "Generation timed out after 1s"),
+        ("error", 6, "The model failed to respond: the model crashed"),
+    ];
+    for (mode, code, message) in cases {
+        failing(&home, mode)
+            .args(["ask", "--timeout", "1s", "hello"])
+            .assert()
+            .code(code)
+            .stdout("")
+            .stderr(predicate::str::contains(format!("Error: {}", message)));
+
+        let output = failing(&home, mode)
+            .args(["ask", "--json", "--timeout", "1s", "hello"])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(code), "{}", mode);
+        let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+        assert_eq!(report["exit_code"], code);
+        assert_eq!(report["response"], "");
+    }
+
+    let output = failing(&home, "timeout")
+        .args(["ask", "--json", "--timeout", "1s", "hello"])
+        .output()
+        .unwrap();
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["error_kind"], "timeout");
+    assert_eq!(report["error"], "Generation timed out after 1s");
+
+    // Bad arguments fail before anything is sent
+    fake(&home)
+        .args(["ask", "--timeout", "soon", "hello"])
+        .assert()
+        .code(2)
+        .stdout("");
+    fake(&home)
+        .args(["ask", "--file", "missing.txt", "hello"])
+        .assert()
+        .code(2)
+        .stdout("")
+        .stderr(predicate::str::contains("missing.txt"));
+}
+
+#[test]
+fn test_ask_json_prints_one_json_object() {
+    let home = TempDir::new().unwrap();
+    let output = fake(&home)
+        .args(["ask", "--json", "--model", "qwen2.5", "hello"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let report: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["response"], "You said: hello");
+    assert_eq!(report["model"], "qwen2.5");
+    assert_eq!(report["tool_calls"], Value::Array(Vec::new()));
+    assert!(report.get("error").is_none());
+}
+
+#[test]
+fn test_ask_limits_the_response() {
+    let home = TempDir::new().unwrap();
+    let report = |args: &[&str]| -> Value {
+        let output = fake(&home).args(args).output().unwrap();
+        serde_json::from_slice(&output.stdout).unwrap()
+    };
+
+    let cut = report(&["ask", "--json", "--max-tokens", "2", "hello there"]);
+    assert_eq!(cut["response"], "You said");
+    assert_eq!(cut["truncated"], true);
+
+    // --stop replaces the configured sequences, and --stop "" clears them
+    fs::write(
+        home.path().join(".sentinel.toml"),
+        "[model]\nstop = [\":\"]\n",
+    )
+    .unwrap();
+    assert_eq!(report(&["ask", "--json", "hi"])["response"], "You said");
+    assert_eq!(
+        report(&["ask", "--json", "--stop", "a", "--stop", "i", "hi"])["response"],
+        "You s"
+    );
+    let full = report(&["ask", "--json", "--stop", "", "hi"]);
+    assert_eq!(full["response"], "You said: hi");
+    assert_eq!(full["truncated"], false);
+
+    fake(&home)
+        .args(["ask", "--max-tokens", "1", "hello"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "(cut off by the max_tokens limit)",
+        ));
+}
+
+#[test]
+fn test_ask_replays_a_recorded_session() {
+    let home = TempDir::new().unwrap();
+    let recorded = fake(&home)
+        .args(["--record", "session.jsonl", "ask", "hello there"])
+        .output()
+        .unwrap();
+    assert!(recorded.status.success());
+    let recording = fs::read_to_string(home.path().join("session.jsonl")).unwrap();
+    let kinds: Vec<String> = recording
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap()["kind"].to_string())
+        .collect();
+    assert_eq!(kinds, ["\"session\"", "\"exchange\""]);
+
+    // No server is needed to answer the same question again, word for word
+    let replayed = sentinel(&home)
+        .args(["--replay", "session.jsonl", "ask", "hello there"])
+        .output()
+        .unwrap();
+    assert!(replayed.status.success());
+    assert_eq!(replayed.stdout, recorded.stdout);
+    assert_eq!(replayed.stdout, b"You said: hello there\n");
+
+    sentinel(&home)
+        .args(["--replay", "session.jsonl", "ask", "goodbye"])
+        .assert()
+        .code(6)
+        .stdout("")
+        .stderr(predicate::str::contains(
+            "The replay diverged from the recording: this request was not recorded",
+        ))
+        .stderr(predicate::str::contains(
+            "-      \"content\": \"hello there\"",
+        ))
+        .stderr(predicate::str::contains("+      \"content\": \"goodbye\""));
+}
+
+#[test]
+fn test_batch_writes_one_ask_json_object_per_prompt() {
+    let home = TempDir::new().unwrap();
+    fs::write(
+        home.path().join("prompts.txt"),
+        "hello
+
+how are you
+",
+    )
+    .unwrap();
+    fake(&home)
+        .args([
+            "batch",
+            "prompts.txt",
+            "--out",
+            "results.jsonl",
+            "--jobs",
+            "2",
+        ])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("[BATCH] 2 prompts sent, 0 failed"));
+
+    let text = fs::read_to_string(home.path().join("results.jsonl")).unwrap();
+    let mut records: Vec<Value> = text
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    records.sort_by_key(|record| record["index"].as_u64());
+    assert_eq!(records[0]["prompt"], "hello");
+    assert_eq!(records[0]["response"], "You said: hello");
+    assert_eq!(records[1]["response"], "You said: how are you");
+    assert!(records[1]["duration_ms"].is_u64() && records[1]["output_tokens"].is_u64());
+
+    fake(&home)
+        .args(["batch", "prompts.txt", "--out", "results.jsonl", "--resume"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "0 prompts sent, 0 failed, 2 already done",
+        ));
+}
+
+#[test]
+fn test_invalid_flags_print_usage() {
+    let home = TempDir::new().unwrap();
+    sentinel(&home)
+        .arg("--bogus")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("unexpected argument '--bogus'"))
+        .stderr(predicate::str::contains("Usage: sentinel"));
+
+    sentinel(&home)
+        .args(["ask", "--json", "--candidates", "2", "hi"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn test_config_show_names_the_file_of_each_value() {
+    let home = TempDir::new().unwrap();
+    sentinel(&home)
+        .args(["config", "--show"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No configuration set"));
+
+    let path = home
+        .path()
+        .join("config")
+        .join("sentinel")
+        .join("config.toml");
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, "[model]\nname = \"qwen2.5\"\n").unwrap();
+    sentinel(&home)
+        .args(["config", "--show"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "model.name = \"qwen2.5\"  # from {}",
+            path.display()
+        )));
+}
+
+#[test]
+fn test_models_lists_the_fake_server() {
+    let home = TempDir::new().unwrap();
+    fake(&home)
+        .arg("models")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Models on fake:0:\n  llama3.2:latest",
+        ));
+}
+
+#[test]
+fn test_models_explains_an_unreachable_server() {
+    let home = TempDir::new().unwrap();
+    sentinel(&home)
+        .arg("models")
+        .env("OLLAMA_HOST", "http://127.0.0.1")
+        .env("OLLAMA_PORT", "9")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Could not reach the Ollama server at http://127.0.0.1:9",
+        ));
+}
+
+#[test]
+fn test_models_reads_a_full_url_in_ollama_host() {
+    let home = TempDir::new().unwrap();
+    // The port in the URL is used, not appended to
+    sentinel(&home)
+        .arg("models")
+        .env("OLLAMA_HOST", "http://127.0.0.1:9")
+        .env("OLLAMA_PORT", "11434")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Could not reach the Ollama server at http://127.0.0.1:9",
+        ));
+
+    sentinel(&home)
+        .arg("models")
+        .env("OLLAMA_HOST", "ftp://127.0.0.1")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "OLLAMA_HOST is not a server address",
+        ));
+}
+
+#[test]
+fn test_diff_shows_the_last_session() {
+    let home = TempDir::new().unwrap();
+    sentinel(&home)
+        .arg("diff")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No saved session changes at"));
+
+    // A session that rewrote one file and created another
+    let journal = Journal::new();
+    let notes = home.path().join("notes.txt");
+    fs::write(&notes, "milk\n").unwrap();
+    let before = journal::backup(&notes);
+    fs::write(&notes, "eggs\n").unwrap();
+    journal.record_write(&notes, before);
+    let todo = home.path().join("todo.txt");
+    let before = journal::backup(&todo);
+    fs::write(&todo, "call mum\n").unwrap();
+    journal.record_write(&todo, before);
+
+    let saved = home
+        .path()
+        .join("data")
+        .join("sentinel")
+        .join("last-session-journal.json");
+    fs::create_dir_all(saved.parent().unwrap()).unwrap();
+    journal.save(&saved).unwrap();
+
+    sentinel(&home)
+        .arg("diff")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "notes.txt (+1 -1)\n--- a/notes.txt\n+++ b/notes.txt\n@@ -1,1 +1,1 @@\n-milk\n+eggs\n",
+        ))
+        .stdout(predicate::str::contains("todo.txt (new file, +1)"))
+        .stdout(predicate::str::ends_with("2 files changed, +2 -1\n"));
+}
+
+#[test]
+fn test_sessions_prune_drops_large_blobs() {
+    let home = TempDir::new().unwrap();
+    let fixtures = std::path::Path::new("tests/fixtures/sessions");
+    let dir = home.path().join("data").join("sentinel");
+    fs::create_dir_all(dir.join("blobs")).unwrap();
+    fs::copy(fixtures.join("blobbed.json"), dir.join("last-session.json")).unwrap();
+    for entry in fs::read_dir(fixtures.join("blobs")).unwrap() {
+        let entry = entry.unwrap();
+        fs::copy(entry.path(), dir.join("blobs").join(entry.file_name())).unwrap();
+    }
+    let large = dir
+        .join("blobs")
+        .join("db1b855e88aac353ad23a0f49133d31303490af2cb17d3bedfa0ebfe80de0f30");
+
+    sentinel(&home)
+        .args(["sessions", "prune", "--larger-than", "1", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Would drop 1 blobs (1.2 KB)"));
+    assert!(large.exists());
+
+    sentinel(&home)
+        .args(["sessions", "prune", "--larger-than", "1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Dropped 1 blobs (1.2 KB), removing 1 files no other session uses",
+        ));
+    assert!(!large.exists());
+    assert!(fs::read_to_string(dir.join("last-session.json"))
+        .unwrap()
+        .contains("dropped from the session by `sentinel sessions prune`"));
+
+    // Pruning needs a rule to go by
+    sentinel(&home)
+        .args(["sessions", "prune"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--older-than <DAYS>"));
+}
+
+// Write `contents` to the config file of `home`
+fn write_config(home: &TempDir, contents: &str) {
+    let path = home
+        .path()
+        .join("config")
+        .join("sentinel")
+        .join("config.toml");
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+#[test]
+fn test_bare_invocation_runs_chat() {
+    let home = TempDir::new().unwrap();
+    write_config(&home, "[model]\nname = \"qwen2.5\"\nwarm_up = false\n");
+    let bare = fake(&home).output().unwrap();
+    let chat = fake(&home).arg("chat").output().unwrap();
+    assert!(bare.status.success() && chat.status.success());
+    assert_eq!(bare.stdout, chat.stdout);
+    assert!(String::from_utf8_lossy(&chat.stdout).contains("Model: qwen2.5"));
+}
+
+#[test]
+fn test_chat_flags_take_precedence_over_the_config() {
+    let home = TempDir::new().unwrap();
+    write_config(
+        &home,
+        "system_prompt = \"Be brief\"\n[model]\nname = \"qwen2.5\"\nwarm_up = false\n",
+    );
+    fake(&home)
+        .arg("chat")
+        .write_stdin("/history\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Model: qwen2.5"))
+        .stdout(predicate::str::contains("System: Be brief"));
+
+    fake(&home)
+        .args([
+            "chat",
+            "--model",
+            "llama3.2",
+            "--system",
+            "Answer in French",
+        ])
+        .write_stdin("/history\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Model: llama3.2"))
+        .stdout(predicate::str::contains("System: Answer in French"))
+        .stdout(predicate::str::contains("Be brief").not());
+}
+
+#[test]
+fn test_chat_continues_the_last_session() {
+    let home = TempDir::new().unwrap();
+    write_config(&home, "[model]\nwarm_up = false\n");
+    fake(&home)
+        .args(["chat", "--continue"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Cannot continue the last session"));
+
+    // Notes the TUI showed are not part of the conversation
+    let saved = home
+        .path()
+        .join("data")
+        .join("sentinel")
+        .join("last-session.json");
+    fs::create_dir_all(saved.parent().unwrap()).unwrap();
+    let session = serde_json::json!({
+        "meta": {},
+        "messages": [
+            {"role": "user", "content": "What is 6 times 7?"},
+            {"role": "assistant", "content": "42"},
+            {"role": "system", "content": "Theme: light", "ephemeral": true},
+        ],
+    });
+    fs::write(&saved, session.to_string()).unwrap();
+    fake(&home)
+        .args(["chat", "-c"])
+        .write_stdin("/history\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Continuing the last session (2 messages)",
+        ))
+        .stdout(predicate::str::contains("User: What is 6 times 7?"))
+        .stdout(predicate::str::contains("Sentinel: 42"))
+        .stdout(predicate::str::contains("Theme: light").not());
+}
+
+#[test]
+fn test_continued_sessions_can_undo_their_changes() {
+    let home = TempDir::new().unwrap();
+    write_config(&home, "[model]\nwarm_up = false\n");
+    let dir = home.path().join("data").join("sentinel");
+    fs::create_dir_all(&dir).unwrap();
+    let session = serde_json::json!({
+        "meta": {},
+        "messages": [
+            {"role": "user", "content": "Buy eggs instead"},
+            {"role": "assistant", "content": "Done"},
+        ],
+    });
+    fs::write(dir.join("last-session.json"), session.to_string()).unwrap();
+
+    // The last session rewrote notes.txt
+    let journal = Journal::new();
+    let notes = home.path().join("notes.txt");
+    fs::write(&notes, "milk\n").unwrap();
+    let before = journal::backup(&notes);
+    fs::write(&notes, "eggs\n").unwrap();
+    journal.record_write(&notes, before);
+    journal
+        .save(&dir.join("last-session-journal.json"))
+        .unwrap();
+
+    fake(&home)
+        .args(["chat", "--continue"])
+        .write_stdin("/undo\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Undone: wrote"));
+    assert_eq!(fs::read_to_string(&notes).unwrap(), "milk\n");
+}